@@ -10,6 +10,7 @@ use system::syscall::{Stat, SEEK_SET, SEEK_CUR, SEEK_END};
 
 extern crate system;
 
+#[derive(Clone)]
 struct ExampleFile {
     data: Vec<u8>,
     seek: usize,
@@ -118,7 +119,28 @@ impl Scheme for ExampleScheme {
         Err(Error::new(ENOENT))
     }
 
+    #[allow(unused_variables)]
+    fn rename(&mut self, old: &str, new: &str) -> Result<usize> {
+        println!("rename {}, {}", old, new);
+        Err(Error::new(ENOENT))
+    }
+
     /* Resource operations */
+    fn dup(&mut self, id: usize) -> Result<usize> {
+        println!("dup {}", id);
+        let file = match self.files.get(&id) {
+            Some(file) => file.clone(),
+            None => return Err(Error::new(EBADF)),
+        };
+        let new_id = self.next_id as usize;
+        self.next_id += 1;
+        if self.next_id < 0 {
+            self.next_id = 1;
+        }
+        self.files.insert(new_id, file);
+        Ok(new_id)
+    }
+
     #[allow(unused_variables)]
     fn read(&mut self, id: usize, buf: &mut [u8]) -> Result<usize> {
         println!("read {}, {:X}, {}", id, buf.as_mut_ptr() as usize, buf.len());
@@ -1,6 +1,57 @@
 use std::fs::File;
 use std::io::Read;
 use std::process::Command;
+use std::thread;
+use std::time::Duration;
+
+/// How many times a driver is restarted after failing, before it is given up on
+const DRIVER_RESTARTS: usize = 5;
+
+fn command(args: &[&str]) -> Command {
+    let mut command = Command::new(args[0]);
+    for i in 1..args.len() {
+        command.arg(args[i]);
+    }
+    command
+}
+
+/// Run a driver until it exits successfully, restarting it each time it fails
+///
+/// The kernel unregisters the schemes of a driver that exits, failing the requests it had not
+/// answered, so the restarted driver can register them again.
+fn supervise(line: String) {
+    let args: Vec<&str> = line.split(' ').collect();
+
+    let mut restarts = 0;
+    loop {
+        match command(&args).spawn() {
+            Ok(mut child) => match child.wait() {
+                Ok(status) => if status.success() {
+                    return;
+                } else {
+                    println!("init: driver '{}' failed with status {}", line, status.code().unwrap_or(0));
+                },
+                Err(err) => {
+                    println!("init: failed to wait: {}", err);
+                    return;
+                }
+            },
+            Err(err) => {
+                println!("init: failed to execute '{}': {}", line, err);
+                return;
+            }
+        }
+
+        if restarts >= DRIVER_RESTARTS {
+            println!("init: driver '{}' failed {} times, not restarting it", line, restarts + 1);
+            return;
+        }
+        restarts += 1;
+
+        thread::sleep(Duration::new(1, 0));
+        println!("init: restarting driver '{}'", line);
+    }
+}
 
 fn main() {
     let mut file = File::open("/etc/init.rc").unwrap();
@@ -9,17 +60,18 @@ fn main() {
     file.read_to_string(&mut string).unwrap();
 
     let mut children = Vec::new();
+    let mut drivers = Vec::new();
     for line_untrimmed in string.lines() {
         let line = line_untrimmed.trim();
         if ! line.is_empty() && ! line.starts_with('#') {
             let args: Vec<&str> = line.split(' ').collect();
-            if args.len() > 0 {
-                let mut command = Command::new(args[0]);
-                for i in 1..args.len() {
-                    command.arg(args[i]);
+            if args[0] == "driver" {
+                if args.len() > 1 {
+                    let line = args[1..].join(" ");
+                    drivers.push(thread::spawn(move || supervise(line)));
                 }
-
-                match command.spawn() {
+            } else {
+                match command(&args).spawn() {
                     Ok(child) => children.push(child),
                     Err(err) => println!("init: failed to execute '{}': {}", line, err),
                 }
@@ -32,4 +84,8 @@ fn main() {
             println!("init: failed to wait: {}", err)
         }
     }
+
+    for driver in drivers {
+        driver.join();
+    }
 }
@@ -28,12 +28,18 @@ impl Error {
     }
 
     pub fn text(&self) -> &str {
-        if let Some(description) = STR_ERROR.get(self.errno as usize) {
-            description
-        } else {
-            "Unknown Error"
+        strerror(self.errno)
+    }
+}
+
+/// Describe an error number, as `strerror` does in C
+pub fn strerror(errno: isize) -> &'static str {
+    if errno >= 0 {
+        if let Some(description) = STR_ERROR.get(errno as usize) {
+            return description;
         }
     }
+    "Unknown Error"
 }
 
 impl fmt::Debug for Error {
@@ -12,6 +12,7 @@ pub mod externs;
 pub mod graphics;
 pub mod scheme;
 pub mod syscall;
+pub mod vdso;
 
 /// Helper function for handling C strings, please do not copy it or make it pub or change it
 pub fn c_string_to_slice<'a>(ptr: *const u8) -> &'a [u8] {
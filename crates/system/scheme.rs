@@ -6,10 +6,16 @@ use super::error::*;
 use super::syscall::*;
 use super::c_string_to_str;
 
+/// A request from the kernel, answered by writing it back with the result in `a`
 #[derive(Copy, Clone, Debug, Default)]
 #[repr(packed)]
 pub struct Packet {
-    pub id: usize,
+    /// The ID of the request, unique for the life of the scheme, which the answer must keep
+    pub id: u64,
+    /// The effective user ID of the caller, for the scheme to check permissions with
+    pub uid: u32,
+    /// The effective group ID of the caller
+    pub gid: u32,
     pub a: usize,
     pub b: usize,
     pub c: usize,
@@ -39,13 +45,21 @@ pub trait Scheme {
             SYS_OPEN => self.open(c_string_to_str(packet.b as *const u8), packet.c, packet.d),
             SYS_MKDIR => self.mkdir(c_string_to_str(packet.b as *const u8), packet.c),
             SYS_RMDIR => self.rmdir(c_string_to_str(packet.b as *const u8)),
+            SYS_RENAME => self.rename(c_string_to_str(packet.b as *const u8), c_string_to_str(packet.c as *const u8)),
+            SYS_LINK => self.link(c_string_to_str(packet.b as *const u8), c_string_to_str(packet.c as *const u8)),
+            SYS_SYMLINK => self.symlink(c_string_to_str(packet.c as *const u8), c_string_to_str(packet.b as *const u8)),
+            SYS_READLINK => self.readlink(c_string_to_str(packet.b as *const u8), unsafe { slice::from_raw_parts_mut(packet.c as *mut u8, packet.d) }),
+            SYS_STAT => self.stat(c_string_to_str(packet.b as *const u8), unsafe { &mut *(packet.c as *mut Stat) }),
             SYS_UNLINK => self.unlink(c_string_to_str(packet.b as *const u8)),
 
+            SYS_DUP => self.dup(packet.b),
             SYS_READ => self.read(packet.b, unsafe { slice::from_raw_parts_mut(packet.c as *mut u8, packet.d) }),
             SYS_WRITE => self.write(packet.b, unsafe { slice::from_raw_parts(packet.c as *const u8, packet.d) }),
             SYS_LSEEK => self.seek(packet.b, packet.c, packet.d),
             SYS_FPATH => self.fpath(packet.b, unsafe { slice::from_raw_parts_mut(packet.c as *mut u8, packet.d) }),
+            SYS_FCNTL => self.fcntl(packet.b, packet.c, packet.d),
             SYS_FSTAT => self.fstat(packet.b, unsafe { &mut *(packet.c as *mut Stat) }),
+            SYS_GETDENTS => self.getdents(packet.b, unsafe { slice::from_raw_parts_mut(packet.c as *mut Dirent, packet.d / mem::size_of::<Dirent>()) }),
             SYS_FSYNC => self.fsync(packet.b),
             SYS_FTRUNCATE => self.ftruncate(packet.b, packet.c),
             SYS_CLOSE => self.close(packet.b),
@@ -71,12 +85,49 @@ pub trait Scheme {
         Err(Error::new(ENOENT))
     }
 
+    /// Rename `old` to `new`, both paths on this scheme
+    #[allow(unused_variables)]
+    fn rename(&mut self, old: &str, new: &str) -> Result<usize> {
+        Err(Error::new(ENOENT))
+    }
+
+    /// Create `new` as another name of the file `old`, both paths on this scheme
+    #[allow(unused_variables)]
+    fn link(&mut self, old: &str, new: &str) -> Result<usize> {
+        Err(Error::new(EPERM))
+    }
+
+    /// Create a symbolic link at `path` to `target`, which may be on any scheme
+    #[allow(unused_variables)]
+    fn symlink(&mut self, target: &str, path: &str) -> Result<usize> {
+        Err(Error::new(EPERM))
+    }
+
+    /// Read the target of the symbolic link at `path` into `buf`, failing with `EINVAL` if it is
+    /// not one
+    #[allow(unused_variables)]
+    fn readlink(&mut self, path: &str, buf: &mut [u8]) -> Result<usize> {
+        Err(Error::new(EINVAL))
+    }
+
+    /// Fill in `stat` for `path`, without opening it
+    #[allow(unused_variables)]
+    fn stat(&mut self, path: &str, stat: &mut Stat) -> Result<usize> {
+        Err(Error::new(ENOENT))
+    }
+
     #[allow(unused_variables)]
     fn unlink(&mut self, path: &str) -> Result<usize> {
         Err(Error::new(ENOENT))
     }
 
     /* Resource operations */
+    /// Duplicate the resource `id`, returning the new ID. Both are closed separately
+    #[allow(unused_variables)]
+    fn dup(&mut self, id: usize) -> Result<usize> {
+        Err(Error::new(EBADF))
+    }
+
     #[allow(unused_variables)]
     fn read(&mut self, id: usize, buf: &mut [u8]) -> Result<usize> {
         Err(Error::new(EBADF))
@@ -97,11 +148,24 @@ pub trait Scheme {
         Err(Error::new(EBADF))
     }
 
+    /// Change the flags of `id` with `F_SETFL`, so that it returns `EAGAIN` instead of blocking if
+    /// `O_NONBLOCK` is set, as it does if the file was opened with it
+    #[allow(unused_variables)]
+    fn fcntl(&mut self, id: usize, cmd: usize, arg: usize) -> Result<usize> {
+        Ok(0)
+    }
+
     #[allow(unused_variables)]
     fn fstat(&self, id: usize, stat: &mut Stat) -> Result<usize> {
         Err(Error::new(EBADF))
     }
 
+    /// Read the entries of the directory `id` into `buf`, returning the number read, 0 at the end
+    #[allow(unused_variables)]
+    fn getdents(&mut self, id: usize, buf: &mut [Dirent]) -> Result<usize> {
+        Err(Error::new(ENOTDIR))
+    }
+
     #[allow(unused_variables)]
     fn fsync(&mut self, id: usize) -> Result<usize> {
         Err(Error::new(EBADF))
@@ -1,4 +1,5 @@
-use syscall::arch::{syscall1, syscall2};
+use syscall::arch::{syscall1, syscall2, syscall3};
+use syscall::unix::TimeSpec;
 use error::Result;
 
 pub const SYS_DEBUG: usize = 0;
@@ -7,6 +8,19 @@ pub const SYS_ALLOC: usize = 1000;
 pub const SYS_REALLOC: usize = 1001;
 pub const SYS_REALLOC_INPLACE: usize = 1002;
 pub const SYS_UNALLOC: usize = 1003;
+pub const SYS_FMAP: usize = 1004;
+
+pub const SYS_DROP_PRIV: usize = 1010;
+/// Register userspace schemes
+pub const PRIV_SCHEME: usize = 1;
+/// Change user ID
+pub const PRIV_SETUID: usize = 2;
+pub const PRIV_ALL: usize = PRIV_SCHEME | PRIV_SETUID;
+pub const SYS_ENTER_NAMESPACE: usize = 1011;
+
+pub const SYS_ADJTIME: usize = 1020;
+
+pub const SYS_KEXEC: usize = 1030;
 
 pub fn sys_debug(buf: &[u8]) -> Result<usize> {
     unsafe { syscall2(SYS_DEBUG, buf.as_ptr() as usize, buf.len()) }
@@ -27,3 +41,39 @@ pub unsafe fn sys_realloc_inplace(ptr: usize, size: usize) -> Result<usize> {
 pub unsafe fn sys_unalloc(ptr: usize) -> Result<usize> {
     syscall1(SYS_UNALLOC, ptr)
 }
+
+/// Map the memory of a resource, such as the display backbuffer, returning its address
+///
+/// The mapping is removed with `sys_unalloc`.
+pub unsafe fn sys_fmap(fd: usize) -> Result<usize> {
+    syscall1(SYS_FMAP, fd)
+}
+
+/// Permanently drop the given privileges, returning the ones that remain
+pub fn sys_drop_priv(privs: usize) -> Result<usize> {
+    unsafe { syscall1(SYS_DROP_PRIV, privs) }
+}
+
+/// Restrict the current context and its future children to the schemes named in the null
+/// terminated array `schemes`, which must all be in its namespace already. Other schemes are
+/// then not found, as if they were not registered
+pub unsafe fn sys_enter_namespace(schemes: *const *const u8) -> Result<usize> {
+    syscall1(SYS_ENTER_NAMESPACE, schemes as usize)
+}
+
+/// Slew the realtime clock by `delta`, returning the adjustment that was still pending in `old`
+pub fn sys_adjtime(delta: &TimeSpec, old: Option<&mut TimeSpec>) -> Result<usize> {
+    let old_ptr = match old {
+        Some(old) => old as *mut TimeSpec as usize,
+        None => 0,
+    };
+    unsafe { syscall2(SYS_ADJTIME, delta as *const TimeSpec as usize, old_ptr) }
+}
+
+/// Replace the running kernel with the kernel file at `kernel`, without a firmware reboot. It is
+/// booted with the initial ramdisk at `initrd` and the command line `cmdline`, which may be null
+///
+/// Only root can do this. Filesystems are not synced first. This does not return on success.
+pub unsafe fn sys_kexec(kernel: *const u8, initrd: *const u8, cmdline: *const u8) -> Result<usize> {
+    syscall3(SYS_KEXEC, kernel as usize, initrd as usize, cmdline as usize)
+}
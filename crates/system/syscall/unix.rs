@@ -1,6 +1,8 @@
 use syscall::arch::{syscall0, syscall1, syscall2, syscall3};
 use error::Result;
+use vdso;
 
+pub const SYS_ALARM: usize = 27;
 pub const SYS_BRK: usize = 45;
 pub const SYS_CHDIR: usize = 12;
 pub const SYS_CLONE: usize = 120;
@@ -8,6 +10,11 @@ pub const SYS_CLONE: usize = 120;
     pub const CLONE_FS: usize = 0x200;
     pub const CLONE_FILES: usize = 0x400;
     pub const CLONE_VFORK: usize = 0x4000;
+    /// The child is a thread of the process of the parent, and is killed when its main thread
+    /// exits. It needs `CLONE_VM`
+    pub const CLONE_THREAD: usize = 0x10000;
+    /// Set the thread pointer of the child, on x86_64 only
+    pub const CLONE_SETTLS: usize = 0x80000;
 pub const SYS_CLOSE: usize = 6;
 pub const SYS_CLOCK_GETTIME: usize = 265;
     pub const CLOCK_REALTIME: usize = 1;
@@ -15,17 +22,84 @@ pub const SYS_CLOCK_GETTIME: usize = 265;
 pub const SYS_DUP: usize = 41;
 pub const SYS_EXECVE: usize = 11;
 pub const SYS_EXIT: usize = 1;
+pub const SYS_FCNTL: usize = 55;
+    /// Duplicate a file to the lowest free file descriptor at or above the argument
+    pub const F_DUPFD: usize = 0;
+    /// Get the file descriptor flags, which are `FD_CLOEXEC`
+    pub const F_GETFD: usize = 1;
+    /// Set the file descriptor flags
+    pub const F_SETFD: usize = 2;
+    /// Get the flags a file was opened with
+    pub const F_GETFL: usize = 3;
+    /// Set the flags of a file that can be changed, which are `O_NONBLOCK`
+    pub const F_SETFL: usize = 4;
+    /// Close the file descriptor when executing a new program
+    pub const FD_CLOEXEC: usize = 1;
+pub const SYS_FLOCK: usize = 143;
+    /// Take a shared lock, which other shared locks may be held with
+    pub const LOCK_SH: usize = 1;
+    /// Take an exclusive lock, which no other lock may be held with
+    pub const LOCK_EX: usize = 2;
+    /// Fail with `EWOULDBLOCK` instead of waiting for the lock
+    pub const LOCK_NB: usize = 4;
+    /// Release the lock
+    pub const LOCK_UN: usize = 8;
 pub const SYS_FPATH: usize = 928;
 pub const SYS_FSTAT: usize = 28;
 pub const SYS_FSYNC: usize = 118;
 pub const SYS_FTRUNCATE: usize = 93;
+pub const SYS_GETDENTS: usize = 141;
+    /// The longest name a `Dirent` holds
+    pub const DIRENT_NAME_MAX: usize = 256;
+pub const SYS_GETEGID: usize = 50;
+pub const SYS_GETEUID: usize = 49;
+pub const SYS_GETGID: usize = 47;
+pub const SYS_GETPGID: usize = 132;
 pub const SYS_GETPID: usize = 20;
+pub const SYS_GETRLIMIT: usize = 76;
+    /// The largest core file that is written when a context is killed, 0 disables core files
+    pub const RLIMIT_CORE: usize = 4;
+    /// The largest number of files a context can have open, above which `open` fails with `EMFILE`
+    pub const RLIMIT_NOFILE: usize = 7;
+    /// No limit
+    pub const RLIM_INFINITY: u64 = !0;
+pub const SYS_GETUID: usize = 24;
+pub const SYS_IOCTL: usize = 54;
+    /// Get the `Termios` of a terminal
+    pub const TCGETS: usize = 0x5401;
+    /// Set the `Termios` of a terminal
+    pub const TCSETS: usize = 0x5402;
+    /// Get the foreground process group of a terminal
+    pub const TIOCGPGRP: usize = 0x540F;
+    /// Set the foreground process group of a terminal
+    pub const TIOCSPGRP: usize = 0x5410;
+    /// Get the `Winsize` of a terminal
+    pub const TIOCGWINSZ: usize = 0x5413;
+    /// Set the `Winsize` of a terminal, sending `SIGWINCH` to its foreground process group
+    pub const TIOCSWINSZ: usize = 0x5414;
+pub const SYS_KILL: usize = 37;
 pub const SYS_LINK: usize = 9;
 pub const SYS_LSEEK: usize = 19;
     pub const SEEK_SET: usize = 0;
     pub const SEEK_CUR: usize = 1;
     pub const SEEK_END: usize = 2;
 pub const SYS_MKDIR: usize = 39;
+pub const SYS_MMAP: usize = 90;
+    /// The pages can be read, which they always can
+    pub const PROT_READ: usize = 0x1;
+    /// The pages can be written
+    pub const PROT_WRITE: usize = 0x2;
+    /// The pages can be executed, which they always can
+    pub const PROT_EXEC: usize = 0x4;
+    /// Map the memory of a file itself, so that writes are seen by every mapping of it
+    pub const MAP_SHARED: usize = 0x1;
+    /// Map a copy of a file
+    pub const MAP_PRIVATE: usize = 0x2;
+    /// Map at the address given, which must be page aligned and free
+    pub const MAP_FIXED: usize = 0x10;
+    /// Map zeroed memory instead of a file
+    pub const MAP_ANONYMOUS: usize = 0x20;
+pub const SYS_MUNMAP: usize = 91;
 pub const SYS_NANOSLEEP: usize = 162;
 pub const SYS_OPEN: usize = 5;
     pub const O_RDONLY: usize = 0;
@@ -33,36 +107,323 @@ pub const SYS_OPEN: usize = 5;
     pub const O_RDWR: usize = 2;
     pub const O_NONBLOCK: usize = 4;
     pub const O_APPEND: usize = 8;
+    /// Take a shared `flock` lock on the file when opening it
     pub const O_SHLOCK: usize = 0x10;
+    /// Take an exclusive `flock` lock on the file when opening it
     pub const O_EXLOCK: usize = 0x20;
     pub const O_ASYNC: usize = 0x40;
     pub const O_FSYNC: usize = 0x80;
     pub const O_CREAT: usize = 0x200;
     pub const O_TRUNC: usize = 0x400;
     pub const O_EXCL: usize = 0x800;
+    /// Set `FD_CLOEXEC` on the new file descriptor
+    pub const O_CLOEXEC: usize = 0x100000;
+    /// Fail with `ELOOP` if the last component of the path is a symbolic link, instead of
+    /// following it
+    pub const O_NOFOLLOW: usize = 0x200000;
 pub const SYS_PIPE2: usize = 331;
+    /// Writes to a pipe of at most this many bytes are not interleaved with other writes
+    pub const PIPE_BUF: usize = 4096;
+pub const SYS_POLL: usize = 168;
+    /// There is data to read
+    pub const POLLIN: usize = 0x1;
+    /// Writing would not block
+    pub const POLLOUT: usize = 0x4;
+    /// An error, such as the read end of a pipe being closed, reported even if not asked for
+    pub const POLLERR: usize = 0x8;
+    /// Hang up, such as the write end of a pipe being closed, reported even if not asked for
+    pub const POLLHUP: usize = 0x10;
+    /// The file descriptor is not open, reported even if not asked for
+    pub const POLLNVAL: usize = 0x20;
 pub const SYS_READ: usize = 3;
+pub const SYS_READLINK: usize = 85;
+    /// The most symbolic links followed while resolving a path, more fail with `ELOOP`
+    pub const SYMLINK_MAX: usize = 8;
+pub const SYS_RENAME: usize = 38;
 pub const SYS_RMDIR: usize = 84;
+pub const SYS_SETGID: usize = 46;
+pub const SYS_SETITIMER: usize = 104;
+pub const SYS_SETPGID: usize = 57;
+    pub const ITIMER_REAL: usize = 0;
+pub const SYS_SETPRIORITY: usize = 97;
+    /// Set the priority of a context, or the caller if `who` is 0
+    pub const PRIO_PROCESS: usize = 0;
+    /// Set the priority of a process group, or that of the caller if `who` is 0
+    pub const PRIO_PGRP: usize = 1;
+    /// Set the priority of the contexts of a user, or the caller's user if `who` is 0
+    pub const PRIO_USER: usize = 2;
+pub const SYS_SETRLIMIT: usize = 75;
+pub const SYS_SETUID: usize = 23;
+pub const SYS_SIGACTION: usize = 67;
+    /// Take the default action of the signal
+    pub const SIG_DFL: usize = 0;
+    /// Ignore the signal
+    pub const SIG_IGN: usize = 1;
+    /// Do not block the signal while its handler runs
+    pub const SA_NODEFER: usize = 0x40000000;
+    /// Reset the action to `SIG_DFL` when the handler is called
+    pub const SA_RESETHAND: usize = 0x80000000;
+pub const SYS_SIGPROCMASK: usize = 126;
+    /// Block the signals in the set, as well as those already blocked
+    pub const SIG_BLOCK: usize = 0;
+    /// Unblock the signals in the set
+    pub const SIG_UNBLOCK: usize = 1;
+    /// Block exactly the signals in the set
+    pub const SIG_SETMASK: usize = 2;
+pub const SYS_SIGRETURN: usize = 119;
 pub const SYS_STAT: usize = 18;
+    /// The bits of `st_mode` that hold the file type
+    pub const MODE_TYPE: u16 = 0xF000;
+    pub const MODE_FIFO: u16 = 0x1000;
     pub const MODE_DIR: u16 = 0x4000;
     pub const MODE_FILE: u16 = 0x8000;
+    pub const MODE_SYMLINK: u16 = 0xA000;
+    /// The bits of `st_mode` that hold the permissions, as in `0o644`
+    pub const MODE_PERM: u16 = 0x0FFF;
+    /// The permission to read a file, or list a directory, shifted left by 6 for the owner and 3
+    /// for the group
+    pub const MODE_READ: u16 = 0o4;
+    /// The permission to write a file, or create and remove entries in a directory
+    pub const MODE_WRITE: u16 = 0o2;
+    /// The permission to execute a file, or look up names in a directory
+    pub const MODE_EXEC: u16 = 0o1;
+pub const SYS_SYMLINK: usize = 83;
 pub const SYS_UNLINK: usize = 10;
 pub const SYS_WAITPID: usize = 7;
+    /// Return 0 instead of blocking if no child has exited
+    pub const WNOHANG: usize = 1;
 pub const SYS_WRITE: usize = 4;
 pub const SYS_YIELD: usize = 158;
 
+/// The number of signals, signal sets have one bit for each
+pub const NSIG: usize = 32;
+
+/// Hang up of the controlling terminal
+pub const SIGHUP: usize = 1;
+/// Interrupt from the terminal
+pub const SIGINT: usize = 2;
+/// Quit from the terminal
+pub const SIGQUIT: usize = 3;
+/// Illegal instruction
+pub const SIGILL: usize = 4;
+/// Breakpoint
+pub const SIGTRAP: usize = 5;
+/// Abort
+pub const SIGABRT: usize = 6;
+/// Bus error, such as a misaligned access
+pub const SIGBUS: usize = 7;
+/// Arithmetic error
+pub const SIGFPE: usize = 8;
+/// Kill, which cannot be caught, blocked or ignored
+pub const SIGKILL: usize = 9;
+/// User defined signal 1
+pub const SIGUSR1: usize = 10;
+/// Invalid memory reference
+pub const SIGSEGV: usize = 11;
+/// User defined signal 2
+pub const SIGUSR2: usize = 12;
+/// Write to a pipe with no readers
+pub const SIGPIPE: usize = 13;
+/// Alarm clock, sent when an `ITIMER_REAL` timer expires
+pub const SIGALRM: usize = 14;
+/// Termination request
+pub const SIGTERM: usize = 15;
+/// A child stopped or exited, ignored unless handled
+pub const SIGCHLD: usize = 17;
+/// Continue if stopped, ignored unless handled
+pub const SIGCONT: usize = 18;
+/// Stop, which cannot be caught, blocked or ignored
+pub const SIGSTOP: usize = 19;
+/// Stop from the terminal
+pub const SIGTSTP: usize = 20;
+/// Window size change, ignored unless handled
+pub const SIGWINCH: usize = 28;
+
+/// Terminal local mode: generate signals for the `VINTR` and `VSUSP` characters
+pub const ISIG: u32 = 0x1;
+/// Terminal local mode: canonical input, with line editing
+pub const ICANON: u32 = 0x2;
+/// Terminal local mode: echo input
+pub const ECHO: u32 = 0x8;
+
+/// Control characters, indices into `Termios::c_cc`
+pub const VINTR: usize = 0;
+pub const VERASE: usize = 2;
+pub const VEOF: usize = 4;
+pub const VSUSP: usize = 10;
+pub const NCCS: usize = 32;
+
+/// File information, filled in by `fstat` and `stat`, and by schemes for `SYS_FSTAT` and
+/// `SYS_STAT` packets
+#[derive(Copy, Clone, Default)]
 #[repr(packed)]
 pub struct Stat {
+    /// The type, one of the `MODE_TYPE` values, and the permissions
     pub st_mode: u16,
-    pub st_size: u64
+    /// The size in bytes
+    pub st_size: u64,
+    /// The user ID of the owner
+    pub st_uid: u32,
+    /// The group ID of the owner
+    pub st_gid: u32,
+    /// The number of hard links to the file
+    pub st_nlink: u32
+}
+
+/// A directory entry, read with `getdents`
+#[derive(Copy, Clone)]
+#[repr(packed)]
+pub struct Dirent {
+    /// The type and the permissions, as in `Stat`
+    pub d_mode: u16,
+    /// The size in bytes, as in `Stat`
+    pub d_size: u64,
+    /// The length of the name
+    pub d_namlen: u16,
+    /// The name, which is not terminated
+    pub d_name: [u8; DIRENT_NAME_MAX],
+}
+
+impl Dirent {
+    /// Create an entry, truncating the name if it is too long
+    pub fn new(name: &str, mode: u16, size: u64) -> Dirent {
+        let mut dirent = Dirent::default();
+        for (d, b) in dirent.d_name.iter_mut().zip(name.bytes()) {
+            *d = b;
+            dirent.d_namlen += 1;
+        }
+        dirent.d_mode = mode;
+        dirent.d_size = size;
+        dirent
+    }
+
+    pub fn name(&self) -> &[u8] {
+        let len = self.d_namlen as usize;
+        if len < DIRENT_NAME_MAX {
+            &self.d_name[.. len]
+        } else {
+            &self.d_name
+        }
+    }
+}
+
+impl Default for Dirent {
+    fn default() -> Dirent {
+        Dirent {
+            d_mode: 0,
+            d_size: 0,
+            d_namlen: 0,
+            d_name: [0; DIRENT_NAME_MAX],
+        }
+    }
 }
 
+#[derive(Copy, Clone, Default)]
 #[repr(packed)]
 pub struct TimeSpec {
     pub tv_sec: i64,
     pub tv_nsec: i32,
 }
 
+#[derive(Copy, Clone)]
+#[repr(packed)]
+pub struct Termios {
+    pub c_iflag: u32,
+    pub c_oflag: u32,
+    pub c_cflag: u32,
+    pub c_lflag: u32,
+    pub c_cc: [u8; NCCS],
+}
+
+impl Default for Termios {
+    fn default() -> Termios {
+        let mut c_cc = [0; NCCS];
+        c_cc[VINTR] = 0x03;
+        c_cc[VERASE] = 0x7F;
+        c_cc[VEOF] = 0x04;
+        c_cc[VSUSP] = 0x1A;
+
+        Termios {
+            c_iflag: 0,
+            c_oflag: 0,
+            c_cflag: 0,
+            c_lflag: ISIG | ICANON | ECHO,
+            c_cc: c_cc,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Default)]
+#[repr(packed)]
+pub struct Winsize {
+    pub ws_row: u16,
+    pub ws_col: u16,
+    pub ws_xpixel: u16,
+    pub ws_ypixel: u16,
+}
+
+/// A file descriptor to wait on with `poll`
+#[derive(Copy, Clone, Default)]
+#[repr(packed)]
+pub struct PollFd {
+    pub fd: usize,
+    /// The events to wait for, some of the `POLL` values
+    pub events: usize,
+    /// The events that are ready, filled in by `poll`
+    pub revents: usize,
+}
+
+/// The arguments of `mmap`, passed by pointer as there are more than fit in registers
+#[derive(Copy, Clone, Default)]
+#[repr(packed)]
+pub struct Mmap {
+    /// The address to map at with `MAP_FIXED`, otherwise ignored
+    pub addr: usize,
+    pub size: usize,
+    /// Some of the `PROT` values
+    pub prot: usize,
+    /// Some of the `MAP` values, one of `MAP_SHARED` or `MAP_PRIVATE` is required for a file
+    pub flags: usize,
+    /// The file to map, unless `MAP_ANONYMOUS` is set
+    pub fd: usize,
+    /// The page aligned offset in the file to map from
+    pub offset: usize,
+}
+
+/// The action taken for a signal
+#[derive(Copy, Clone, Default)]
+#[repr(packed)]
+pub struct SigAction {
+    /// `SIG_DFL`, `SIG_IGN`, or the address of a function taking the signal number
+    pub sa_handler: usize,
+    /// Signals blocked while the handler runs
+    pub sa_mask: usize,
+    /// Some of the `SA` values
+    pub sa_flags: usize,
+}
+
+#[derive(Copy, Clone)]
+#[repr(packed)]
+pub struct Rlimit {
+    /// The limit that is enforced
+    pub rlim_cur: u64,
+    /// The highest that `rlim_cur` can be raised to, which can only be lowered
+    pub rlim_max: u64,
+}
+
+#[derive(Copy, Clone, Default)]
+#[repr(packed)]
+pub struct ITimerSpec {
+    /// The period, or zero for a one-shot timer
+    pub it_interval: TimeSpec,
+    /// The time until the next expiry, or zero if the timer is disarmed
+    pub it_value: TimeSpec,
+}
+
+pub fn sys_alarm(seconds: usize) -> Result<usize> {
+    unsafe { syscall1(SYS_ALARM, seconds) }
+}
+
 pub unsafe fn sys_brk(addr: usize) -> Result<usize> {
     syscall1(SYS_BRK, addr)
 }
@@ -71,15 +432,24 @@ pub unsafe fn sys_chdir(path: *const u8) -> Result<usize> {
     syscall1(SYS_CHDIR, path as usize)
 }
 
-pub unsafe fn sys_clone(flags: usize) -> Result<usize> {
-    syscall1(SYS_CLONE, flags)
+/// Create a context like the current one, returning 0 in it and its PID in the current one
+///
+/// The child runs on the stack at `stack`, or on a copy of the stack of the current context if it
+/// is 0. With `CLONE_SETTLS`, its thread pointer is `tls`, otherwise it is the one of the current
+/// context.
+pub unsafe fn sys_clone(flags: usize, stack: usize, tls: usize) -> Result<usize> {
+    syscall3(SYS_CLONE, flags, stack, tls)
 }
 
 pub fn sys_close(fd: usize) -> Result<usize> {
     unsafe { syscall1(SYS_CLOSE, fd) }
 }
 
+/// Get the time of `clock`, from the time page of the kernel if it can be
 pub fn sys_clock_gettime(clock: usize, tp: &mut TimeSpec) -> Result<usize> {
+    if vdso::clock_gettime(clock, tp) {
+        return Ok(0);
+    }
     unsafe { syscall2(SYS_CLOCK_GETTIME, clock, tp as *mut TimeSpec as usize) }
 }
 
@@ -87,14 +457,24 @@ pub fn sys_dup(fd: usize) -> Result<usize> {
     unsafe { syscall1(SYS_DUP, fd) }
 }
 
-pub unsafe fn sys_execve(path: *const u8, args: *const *const u8) -> Result<usize> {
-    syscall2(SYS_EXECVE, path as usize, args as usize)
+pub unsafe fn sys_execve(path: *const u8, args: *const *const u8, envp: *const *const u8) -> Result<usize> {
+    syscall3(SYS_EXECVE, path as usize, args as usize, envp as usize)
 }
 
 pub fn sys_exit(status: usize) -> Result<usize> {
     unsafe { syscall1(SYS_EXIT, status) }
 }
 
+pub fn sys_fcntl(fd: usize, cmd: usize, arg: usize) -> Result<usize> {
+    unsafe { syscall3(SYS_FCNTL, fd, cmd, arg) }
+}
+
+/// Take or release an advisory lock on the file `fd`, as `operation` says, one of the `LOCK`
+/// values. The lock is shared by the duplicates of `fd`, and released when the last is closed
+pub fn sys_flock(fd: usize, operation: usize) -> Result<usize> {
+    unsafe { syscall2(SYS_FLOCK, fd, operation) }
+}
+
 pub fn sys_fpath(fd: usize, buf: &mut [u8]) -> Result<usize> {
     unsafe { syscall3(SYS_FPATH, fd, buf.as_mut_ptr() as usize, buf.len()) }
 }
@@ -111,10 +491,53 @@ pub fn sys_ftruncate(fd: usize, len: usize) -> Result<usize> {
     unsafe { syscall2(SYS_FTRUNCATE, fd, len) }
 }
 
+/// Read the entries of the directory `fd` into `buf`, returning the number read, 0 at the end
+pub fn sys_getdents(fd: usize, buf: &mut [Dirent]) -> Result<usize> {
+    unsafe { syscall3(SYS_GETDENTS, fd, buf.as_mut_ptr() as usize, buf.len()) }
+}
+
+/// Get the effective group ID, which permissions are checked against
+pub fn sys_getegid() -> Result<usize> {
+    unsafe { syscall0(SYS_GETEGID) }
+}
+
+/// Get the effective user ID, which permissions are checked against
+pub fn sys_geteuid() -> Result<usize> {
+    unsafe { syscall0(SYS_GETEUID) }
+}
+
+/// Get the real group ID
+pub fn sys_getgid() -> Result<usize> {
+    unsafe { syscall0(SYS_GETGID) }
+}
+
+pub fn sys_getpgid(pid: usize) -> Result<usize> {
+    unsafe { syscall1(SYS_GETPGID, pid) }
+}
+
 pub fn sys_getpid() -> Result<usize> {
     unsafe { syscall0(SYS_GETPID) }
 }
 
+pub fn sys_getrlimit(resource: usize, rlimit: &mut Rlimit) -> Result<usize> {
+    unsafe { syscall2(SYS_GETRLIMIT, resource, rlimit as *mut Rlimit as usize) }
+}
+
+/// Get the real user ID
+pub fn sys_getuid() -> Result<usize> {
+    unsafe { syscall0(SYS_GETUID) }
+}
+
+pub unsafe fn sys_ioctl(fd: usize, request: usize, arg: *mut u8) -> Result<usize> {
+    syscall3(SYS_IOCTL, fd, request, arg as usize)
+}
+
+/// Send `signal` to the context `pid`, to the process group `-pid` if it is negative, or to the
+/// process group of the caller if it is 0. A signal of 0 only checks that `pid` exists
+pub fn sys_kill(pid: isize, signal: usize) -> Result<usize> {
+    unsafe { syscall2(SYS_KILL, pid as usize, signal) }
+}
+
 pub unsafe fn sys_link(old: *const u8, new: *const u8) -> Result<usize> {
     syscall2(SYS_LINK, old as usize, new as usize)
 }
@@ -127,6 +550,24 @@ pub unsafe fn sys_mkdir(path: *const u8, mode: usize) -> Result<usize> {
     syscall2(SYS_MKDIR, path as usize, mode)
 }
 
+/// Map `size` bytes of zeroed memory, or of the file `fd`, into memory, returning the address
+pub unsafe fn sys_mmap(addr: usize, size: usize, prot: usize, flags: usize, fd: usize, offset: usize) -> Result<usize> {
+    let args = Mmap {
+        addr: addr,
+        size: size,
+        prot: prot,
+        flags: flags,
+        fd: fd,
+        offset: offset,
+    };
+    syscall1(SYS_MMAP, &args as *const Mmap as usize)
+}
+
+/// Unmap a whole mapping made by `mmap`, or loaded from an executable
+pub unsafe fn sys_munmap(addr: usize, size: usize) -> Result<usize> {
+    syscall2(SYS_MUNMAP, addr, size)
+}
+
 pub fn sys_nanosleep(req: &TimeSpec, rem: &mut TimeSpec) -> Result<usize> {
     unsafe { syscall2(SYS_NANOSLEEP, req as *const TimeSpec as usize, rem as *mut TimeSpec as usize) }
 }
@@ -139,18 +580,85 @@ pub unsafe fn sys_pipe2(fds: *mut usize, flags: usize) -> Result<usize> {
     syscall2(SYS_PIPE2, fds as usize, flags)
 }
 
+/// Wait up to `timeout` milliseconds, or forever if it is negative, until one of `fds` is ready,
+/// returning the number that are
+pub fn sys_poll(fds: &mut [PollFd], timeout: isize) -> Result<usize> {
+    unsafe { syscall3(SYS_POLL, fds.as_mut_ptr() as usize, fds.len(), timeout as usize) }
+}
+
 pub fn sys_read(fd: usize, buf: &mut [u8]) -> Result<usize> {
     unsafe { syscall3(SYS_READ, fd, buf.as_mut_ptr() as usize, buf.len()) }
 }
 
+/// Rename `old` to `new`, which must be on the same scheme
+/// Read the target of the symbolic link at `path` into `buf`, returning its length
+pub unsafe fn sys_readlink(path: *const u8, buf: &mut [u8]) -> Result<usize> {
+    syscall3(SYS_READLINK, path as usize, buf.as_mut_ptr() as usize, buf.len())
+}
+
+pub unsafe fn sys_rename(old: *const u8, new: *const u8) -> Result<usize> {
+    syscall2(SYS_RENAME, old as usize, new as usize)
+}
+
 pub unsafe fn sys_rmdir(path: *const u8) -> Result<usize> {
     syscall1(SYS_RMDIR, path as usize)
 }
 
+/// Set the real and effective group IDs with `PRIV_SETUID`, otherwise only set the effective
+/// group ID back to the real one
+pub fn sys_setgid(gid: usize) -> Result<usize> {
+    unsafe { syscall1(SYS_SETGID, gid) }
+}
+
+pub fn sys_setitimer(which: usize, new: &ITimerSpec, old: Option<&mut ITimerSpec>) -> Result<usize> {
+    let old_ptr = match old {
+        Some(old) => old as *mut ITimerSpec as usize,
+        None => 0,
+    };
+    unsafe { syscall3(SYS_SETITIMER, which, new as *const ITimerSpec as usize, old_ptr) }
+}
+
+pub fn sys_setpgid(pid: usize, pgid: usize) -> Result<usize> {
+    unsafe { syscall2(SYS_SETPGID, pid, pgid) }
+}
+
+/// Set the nice value of the contexts selected by `which` and `who`, from -20 for the highest
+/// priority to 19 for the lowest
+pub fn sys_setpriority(which: usize, who: usize, priority: isize) -> Result<usize> {
+    unsafe { syscall3(SYS_SETPRIORITY, which, who, priority as usize) }
+}
+
+pub fn sys_setrlimit(resource: usize, rlimit: &Rlimit) -> Result<usize> {
+    unsafe { syscall2(SYS_SETRLIMIT, resource, rlimit as *const Rlimit as usize) }
+}
+
+/// Set the real and effective user IDs with `PRIV_SETUID`, otherwise only set the effective user
+/// ID back to the real one
+pub fn sys_setuid(uid: usize) -> Result<usize> {
+    unsafe { syscall1(SYS_SETUID, uid) }
+}
+
+/// Set the action for `signal` if `act` is not null, and return the old one in `oldact` if it is
+/// not null
+pub unsafe fn sys_sigaction(signal: usize, act: *const SigAction, oldact: *mut SigAction) -> Result<usize> {
+    syscall3(SYS_SIGACTION, signal, act as usize, oldact as usize)
+}
+
+/// Change the blocked signals as `how` says if `set` is not null, and return the old set in
+/// `oldset` if it is not null
+pub unsafe fn sys_sigprocmask(how: usize, set: *const usize, oldset: *mut usize) -> Result<usize> {
+    syscall3(SYS_SIGPROCMASK, how, set as usize, oldset as usize)
+}
+
 pub unsafe fn sys_stat(path: *const u8, stat: &mut Stat) -> Result<usize> {
     syscall2(SYS_STAT, path as usize, stat as *mut Stat as usize)
 }
 
+/// Create a symbolic link at `path` to `target`, which is not checked
+pub unsafe fn sys_symlink(target: *const u8, path: *const u8) -> Result<usize> {
+    syscall2(SYS_SYMLINK, target as usize, path as usize)
+}
+
 pub unsafe fn sys_unlink(path: *const u8) -> Result<usize> {
     syscall1(SYS_UNLINK, path as usize)
 }
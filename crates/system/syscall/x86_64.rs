@@ -1,60 +1,64 @@
 use error::{Error, Result};
 
+// The SYSCALL instruction overwrites RCX with the return address and R11 with the flags, so the
+// second argument is passed in R10. 32-bit programs use `int 0x80`, with the arguments in EBX,
+// ECX and EDX.
+
 pub unsafe fn syscall0(mut a: usize) -> Result<usize> {
-    asm!("int 0x80"
+    asm!("syscall"
         : "={rax}"(a)
         : "{rax}"(a)
-        : "memory"
+        : "memory", "rcx", "r11"
         : "intel", "volatile");
 
     Error::demux(a)
 }
 
 pub unsafe fn syscall1(mut a: usize, b: usize) -> Result<usize> {
-    asm!("int 0x80"
+    asm!("syscall"
         : "={rax}"(a)
         : "{rax}"(a), "{rbx}"(b)
-        : "memory"
+        : "memory", "rcx", "r11"
         : "intel", "volatile");
 
     Error::demux(a)
 }
 
 pub unsafe fn syscall2(mut a: usize, b: usize, c: usize) -> Result<usize> {
-    asm!("int 0x80"
+    asm!("syscall"
         : "={rax}"(a)
-        : "{rax}"(a), "{rbx}"(b), "{rcx}"(c)
-        : "memory"
+        : "{rax}"(a), "{rbx}"(b), "{r10}"(c)
+        : "memory", "rcx", "r11"
         : "intel", "volatile");
 
     Error::demux(a)
 }
 
 pub unsafe fn syscall3(mut a: usize, b: usize, c: usize, d: usize) -> Result<usize> {
-    asm!("int 0x80"
+    asm!("syscall"
         : "={rax}"(a)
-        : "{rax}"(a), "{rbx}"(b), "{rcx}"(c), "{rdx}"(d)
-        : "memory"
+        : "{rax}"(a), "{rbx}"(b), "{r10}"(c), "{rdx}"(d)
+        : "memory", "rcx", "r11"
         : "intel", "volatile");
 
     Error::demux(a)
 }
 
 pub unsafe fn syscall4(mut a: usize, b: usize, c: usize, d: usize, e: usize) -> Result<usize> {
-    asm!("int 0x80"
+    asm!("syscall"
         : "={rax}"(a)
-        : "{rax}"(a), "{rbx}"(b), "{rcx}"(c), "{rdx}"(d), "{rsi}"(e)
-        : "memory"
+        : "{rax}"(a), "{rbx}"(b), "{r10}"(c), "{rdx}"(d), "{rsi}"(e)
+        : "memory", "rcx", "r11"
         : "intel", "volatile");
 
     Error::demux(a)
 }
 
 pub unsafe fn syscall5(mut a: usize, b: usize, c: usize, d: usize, e: usize, f: usize) -> Result<usize> {
-    asm!("int 0x80"
+    asm!("syscall"
         : "={rax}"(a)
-        : "{rax}"(a), "{rbx}"(b), "{rcx}"(c), "{rdx}"(d), "{rsi}"(e), "{rdi}"(f)
-        : "memory"
+        : "{rax}"(a), "{rbx}"(b), "{r10}"(c), "{rdx}"(d), "{rsi}"(e), "{rdi}"(f)
+        : "memory", "rcx", "r11"
         : "intel", "volatile");
 
     Error::demux(a)
@@ -0,0 +1,96 @@
+//! The time page, mapped read-only into every process by the kernel
+//!
+//! The kernel copies its clock here on every timer tick, so that `clock_gettime` can read the
+//! time without a syscall. The page is updated like a sequence lock: `seq` is odd while the
+//! kernel writes it, and a reader retries if `seq` was odd or changed while it read.
+
+use core::ptr;
+use core::sync::atomic::{fence, Ordering};
+
+use syscall::{CLOCK_MONOTONIC, CLOCK_REALTIME, TimeSpec};
+
+/// Where the page is mapped, below the stack of the process
+pub const VDSO_ADDR: usize = 0xAFFFF000;
+
+const NANOS_PER_SEC: i64 = 1000000000;
+
+/// The contents of the time page
+#[derive(Copy, Clone, Default)]
+#[repr(C)]
+pub struct VdsoData {
+    /// Odd while the kernel is writing the page
+    pub seq: u64,
+    /// Timer ticks since boot, zero until the clock is running
+    pub ticks: u64,
+    /// Monotonic time at the last tick
+    pub monotonic_secs: i64,
+    pub monotonic_nanos: i64,
+    /// Realtime minus monotonic time
+    pub offset_secs: i64,
+    pub offset_nanos: i64,
+    /// TSC at the last tick
+    pub tsc_tick: u64,
+    /// TSC frequency in Hz, zero until it has been measured
+    pub tsc_hz: u64,
+    /// Nanoseconds between ticks
+    pub tick_nanos: u64,
+}
+
+impl VdsoData {
+    /// Read a consistent copy of the page at `address`
+    pub unsafe fn read(address: usize) -> VdsoData {
+        let page = address as *const VdsoData;
+        loop {
+            let seq = ptr::read_volatile(&(*page).seq);
+            fence(Ordering::SeqCst);
+            let data = ptr::read_volatile(page);
+            fence(Ordering::SeqCst);
+            if seq % 2 == 0 && ptr::read_volatile(&(*page).seq) == seq {
+                return data;
+            }
+        }
+    }
+
+    /// Monotonic time in nanoseconds, interpolated with the TSC since the last tick like the
+    /// kernel clock
+    pub fn monotonic(&self, tsc: u64) -> i64 {
+        let mut nanos = 0;
+        if self.tsc_hz > 0 {
+            nanos = tsc.wrapping_sub(self.tsc_tick) * NANOS_PER_SEC as u64 / self.tsc_hz;
+            // Never run past the next tick, so the clock cannot go backwards
+            if nanos >= self.tick_nanos {
+                nanos = self.tick_nanos - 1;
+            }
+        }
+        self.monotonic_secs * NANOS_PER_SEC + self.monotonic_nanos + nanos as i64
+    }
+}
+
+fn rdtsc() -> u64 {
+    let low: u32;
+    let high: u32;
+    unsafe { asm!("rdtsc" : "={eax}"(low), "={edx}"(high) : : : "intel", "volatile") };
+    (high as u64) << 32 | low as u64
+}
+
+/// Read `clock` from the time page, returning false if it cannot be, so that the syscall is
+/// made instead
+pub fn clock_gettime(clock: usize, tp: &mut TimeSpec) -> bool {
+    if clock != CLOCK_MONOTONIC && clock != CLOCK_REALTIME {
+        return false;
+    }
+
+    let data = unsafe { VdsoData::read(VDSO_ADDR) };
+    if data.ticks == 0 {
+        return false;
+    }
+
+    let mut nanos = data.monotonic(rdtsc());
+    if clock == CLOCK_REALTIME {
+        nanos += data.offset_secs * NANOS_PER_SEC + data.offset_nanos;
+    }
+
+    tp.tv_sec = nanos / NANOS_PER_SEC;
+    tp.tv_nsec = (nanos % NANOS_PER_SEC) as i32;
+    true
+}
@@ -1,5 +1,7 @@
 use collections::string::String;
+use collections::vec::Vec;
 
+use core::cmp;
 use core::mem::size_of;
 use core::num::Zero;
 use core::ops::{BitOrAssign, ShlAssign};
@@ -236,6 +238,34 @@ pub fn parse_package(bytes: &[u8], i: &mut usize) {
     *i = end;
 }
 
+/// Find `Name (<name>, Package () { ... })` and parse up to `count` integers at the start of the
+/// package, without parsing the rest of the table. Used for the sleep state packages, like `_S3_`
+pub fn find_package(bytes: &[u8], name: &str, count: usize) -> Option<Vec<u64>> {
+    let name = name.as_bytes();
+    for start in 1..bytes.len().saturating_sub(name.len()) {
+        if &bytes[start..start + name.len()] != name {
+            continue;
+        }
+
+        let named = bytes[start - 1] == NAME_OP ||
+                    (start >= 2 && bytes[start - 1] == ROOT_PREFIX && bytes[start - 2] == NAME_OP);
+        let mut i = start + name.len();
+        if named && i < bytes.len() && bytes[i] == PACKAGE_OP {
+            i += 1;
+            let end = i + parse_length(bytes, &mut i);
+            let elements = parse_num::<u8>(bytes, &mut i) as usize;
+
+            let mut values = Vec::new();
+            while values.len() < cmp::min(count, elements) && i < cmp::min(end, bytes.len()) {
+                values.push(parse_int(bytes, &mut i));
+            }
+            return Some(values);
+        }
+    }
+
+    None
+}
+
 pub fn parse_device(bytes: &[u8], i: &mut usize) {
     let end = *i + parse_length(bytes, i);
     let name = parse_name(bytes, i);
@@ -0,0 +1,47 @@
+use core::ptr;
+
+/// The Firmware ACPI Control Structure, which holds where the firmware jumps on wake
+///
+/// It has no SDT header, and is found through the FADT.
+#[repr(packed)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FACS {
+    pub signature: [u8; 4],
+    pub length: u32,
+    pub hardware_signature: u32,
+    pub firmware_waking_vector: u32,
+    pub global_lock: u32,
+    pub flags: u32,
+    // Available on ACPI 2.0+
+    pub x_firmware_waking_vector: u64,
+    pub version: u8,
+    reserved: [u8; 3],
+    pub ospm_flags: u32,
+    reserved2: [u8; 24],
+}
+
+impl FACS {
+    /// The FACS at `address`, if it is valid
+    pub fn new(address: usize) -> Option<&'static mut Self> {
+        if address > 0 {
+            let facs = unsafe { &mut *(address as *mut FACS) };
+            if &facs.signature == b"FACS" {
+                return Some(facs);
+            }
+        }
+
+        None
+    }
+
+    /// Set the real mode address the firmware jumps to on wake
+    ///
+    /// The 64-bit vector would be used instead of it in protected mode, so it is cleared.
+    pub fn set_waking_vector(&mut self, vector: usize) {
+        unsafe {
+            ptr::write_volatile(&mut self.firmware_waking_vector, vector as u32);
+            if self.length as usize >= 32 {
+                ptr::write_volatile(&mut self.x_firmware_waking_vector, 0);
+            }
+        }
+    }
+}
@@ -1,8 +1,15 @@
 use alloc::boxed::Box;
-use fs::{KScheme, Resource, Url};
-use system::error::{Error, Result, ENOENT};
+use arch::smp;
+use arch::suspend;
+use collections::string::ToString;
+use collections::vec::Vec;
+use drivers::io::{Io, Pio};
+use env::log::LogLevel;
+use fs::{KScheme, Resource, Url, VecResource};
+use system::error::{Error, Result, EIO, ENODEV, ENOENT, EOPNOTSUPP, EPERM};
 use system::syscall::O_CREAT;
 pub use self::dsdt::DSDT;
+pub use self::facs::FACS;
 pub use self::fadt::FADT;
 pub use self::madt::MADT;
 pub use self::rsdt::RSDT;
@@ -11,12 +18,60 @@ pub use self::ssdt::SSDT;
 
 pub mod aml;
 pub mod dsdt;
+pub mod facs;
 pub mod fadt;
 pub mod madt;
 pub mod rsdt;
 pub mod sdt;
 pub mod ssdt;
 
+/// SLP_TYP in the PM1 control registers, the sleep state entered with SLP_EN
+const SLP_TYP_SHIFT: u16 = 10;
+const SLP_TYP_MASK: u16 = 0b111 << SLP_TYP_SHIFT;
+const SLP_EN: u16 = 1 << 13;
+/// WAK_STS in the PM1 status registers, set when the machine has slept and woken
+const WAK_STS: u16 = 1 << 15;
+/// How many times WAK_STS is read, waiting for the machine to sleep, before giving up
+const SLEEP_POLL: usize = 1000000;
+
+/// The PM1 registers and the values of SLP_TYP that enter a sleep state
+struct SleepControl {
+    pm1a_control: u16,
+    pm1b_control: u16,
+    pm1a_status: u16,
+    slp_typa: u16,
+    slp_typb: u16,
+}
+
+/// Enter the sleep state described by the `SleepControl` at `control`, called by
+/// `suspend::suspend` with every register saved. Returns if the machine does not sleep
+extern "C" fn enter_sleep(control: usize) {
+    let control = unsafe { &*(control as *const SleepControl) };
+
+    // The caches lose their contents
+    unsafe { asm!("wbinvd" : : : "memory" : "intel", "volatile") };
+
+    let mut status = Pio::<u16>::new(control.pm1a_status);
+    status.write(WAK_STS);
+
+    let mut pm1a = Pio::<u16>::new(control.pm1a_control);
+    let value = pm1a.read() & !(SLP_TYP_MASK | SLP_EN);
+    pm1a.write(value | control.slp_typa << SLP_TYP_SHIFT);
+    if control.pm1b_control > 0 {
+        let mut pm1b = Pio::<u16>::new(control.pm1b_control);
+        let value = pm1b.read() & !(SLP_TYP_MASK | SLP_EN);
+        pm1b.write(value | control.slp_typb << SLP_TYP_SHIFT);
+        pm1b.write(value | control.slp_typb << SLP_TYP_SHIFT | SLP_EN);
+    }
+    pm1a.write(value | control.slp_typa << SLP_TYP_SHIFT | SLP_EN);
+
+    for _ in 0..SLEEP_POLL {
+        if status.read() & WAK_STS == WAK_STS {
+            break;
+        }
+    }
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct Acpi {
     rsdt: RSDT,
@@ -74,6 +129,60 @@ impl Acpi {
             }
         }
     }
+
+    /// The table of interrupt controllers and processors
+    pub fn madt(&self) -> Option<&MADT> {
+        self.madt.as_ref()
+    }
+
+    /// Suspend to RAM, entering S3 until the machine is woken
+    ///
+    /// The scheduler is stopped, so that no user context runs, and the schemes are suspended
+    /// before the registers are saved. Everything is restarted in the reverse order on wake, and
+    /// the realtime clock is read again from the RTC.
+    fn suspend_to_ram(&mut self) -> Result<Box<Resource>> {
+        if try!(::env().contexts.read().current()).euid != 0 {
+            return Err(Error::new(EPERM));
+        }
+
+        let fadt = try!(self.fadt.ok_or(Error::new(ENODEV)));
+        let dsdt = try!(self.dsdt.ok_or(Error::new(ENODEV)));
+        let facs = try!(FACS::new(fadt.firmware_ctrl as usize).ok_or(Error::new(ENODEV)));
+
+        // SLP_TYPa and SLP_TYPb, if the machine supports S3
+        let slp_typ = try!(aml::find_package(dsdt.data, "_S3_", 2).ok_or(Error::new(EOPNOTSUPP)));
+        let control = SleepControl {
+            pm1a_control: fadt.pm1a_control_block as u16,
+            pm1b_control: fadt.pm1b_control_block as u16,
+            pm1a_status: fadt.pm1a_event_block as u16,
+            slp_typa: slp_typ.get(0).map_or(0, |&typ| typ as u16),
+            slp_typb: slp_typ.get(1).map_or(0, |&typ| typ as u16),
+        };
+
+        let env = ::env();
+
+        klogln!(LogLevel::Info, "acpi: suspending to RAM");
+        env.contexts.write().enabled = false;
+        env.suspend();
+
+        facs.set_waking_vector(suspend::waking_vector());
+        let resumed = unsafe { suspend::suspend(enter_sleep, &control as *const SleepControl as usize) };
+
+        if let Some(ref madt) = self.madt {
+            unsafe { smp::init(madt) };
+        }
+        env.clock.lock().init();
+        env.resume();
+        env.contexts.write().enabled = true;
+
+        if resumed {
+            klogln!(LogLevel::Info, "acpi: resumed");
+            Ok(box VecResource::new("acpi:suspend".to_string(), Vec::new()))
+        } else {
+            klogln!(LogLevel::Warning, "acpi: failed to enter S3");
+            Err(Error::new(EIO))
+        }
+    }
 }
 
 impl KScheme for Acpi {
@@ -82,6 +191,10 @@ impl KScheme for Acpi {
     }
 
     fn open(&mut self, url: Url, flags: usize) -> Result<Box<Resource>> {
+        if url.reference() == "suspend" && flags & O_CREAT == O_CREAT {
+            return self.suspend_to_ram();
+        }
+
         if url.reference() == "off" && flags & O_CREAT == O_CREAT {
             match self.fadt {
                 Some(fadt) => {
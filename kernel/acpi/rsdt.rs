@@ -1,3 +1,5 @@
+use arch::multiboot;
+
 use core::mem::size_of;
 
 use super::SDTHeader;
@@ -16,6 +18,14 @@ const SIGNATURE: &'static [u8] = b"RSD PTR ";
 
 impl RSDP {
     pub fn new() -> Result<Self, &'static str> {
+        // Use the RSDP passed by the boot loader, there may be no BIOS region on UEFI systems
+        if let Some(address) = multiboot::rsdp() {
+            let rsdp = address as *const RSDP;
+            if unsafe { (*rsdp).valid() } {
+                return Ok(unsafe { *rsdp });
+            }
+        }
+
         // Search top of bios region
         let mut search_ptr = 0xE0000;
         while search_ptr < 0xFFFFF {
@@ -1,16 +1,27 @@
+use arch::irq_pool;
 use arch::memory::*;
 
+use core::cmp;
+
 #[allocator]
 #[allow(unused_variables)]
 #[no_mangle]
 pub extern "C" fn __rust_allocate(size: usize, align: usize) -> *mut u8 {
-    unsafe { alloc(size) as *mut u8 }
+    if irq_pool::in_irq() {
+        irq_pool::alloc(size, align) as *mut u8
+    } else {
+        unsafe { alloc(size) as *mut u8 }
+    }
 }
 
 #[allow(unused_variables)]
 #[no_mangle]
 pub extern "C" fn __rust_deallocate(ptr: *mut u8, old_size: usize, align: usize) {
-    unsafe { unalloc(ptr as usize) }
+    if irq_pool::contains(ptr as usize) {
+        irq_pool::unalloc(ptr as usize)
+    } else {
+        unsafe { unalloc(ptr as usize) }
+    }
 }
 
 #[allow(unused_variables)]
@@ -20,7 +31,21 @@ pub extern "C" fn __rust_reallocate(ptr: *mut u8,
                                     size: usize,
                                     align: usize)
                                     -> *mut u8 {
-    unsafe { realloc(ptr as usize, size) as *mut u8 }
+    if irq_pool::contains(ptr as usize) || irq_pool::in_irq() {
+        // Memory moves between the pool and the heap by copying
+        if irq_pool::contains(ptr as usize) && size <= irq_pool::POOL_BLOCK {
+            return ptr;
+        }
+
+        let new = __rust_allocate(size, align);
+        if ! new.is_null() {
+            unsafe { ::memmove(new, ptr, cmp::min(old_size, size)) };
+            __rust_deallocate(ptr, old_size, align);
+        }
+        new
+    } else {
+        unsafe { realloc(ptr as usize, size) as *mut u8 }
+    }
 }
 
 #[allow(unused_variables)]
@@ -30,7 +55,15 @@ pub extern "C" fn __rust_reallocate_inplace(ptr: *mut u8,
                                             size: usize,
                                             align: usize)
                                             -> usize {
-    unsafe { realloc_inplace(ptr as usize, size) }
+    if irq_pool::contains(ptr as usize) {
+        if size <= irq_pool::POOL_BLOCK {
+            size
+        } else {
+            old_size
+        }
+    } else {
+        unsafe { realloc_inplace(ptr as usize, size) }
+    }
 }
 
 #[allow(unused_variables)]
@@ -5,34 +5,196 @@ use alloc::boxed::{Box, FnBox};
 
 use arch::memory;
 use arch::paging::Page;
+use arch::percpu;
 use arch::regs::Regs;
 
 use collections::string::{String, ToString};
 use collections::vec::Vec;
 
 use common::time::Duration;
+use common::trace::TracePoint;
 
-use core::cell::UnsafeCell;
+use core::cell::{Cell, UnsafeCell};
 use core::slice::{Iter, IterMut};
-use core::{mem, ptr};
+use core::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
+use core::{cmp, mem, ptr};
 use core::ops::DerefMut;
 
-use fs::Resource;
+use env::log::LogLevel;
+use env::vdso;
 
-use syscall::{do_sys_exit, CLONE_FILES, CLONE_FS, CLONE_VM, CLONE_VFORK};
+use fs::{FileLock, Resource};
+use schemes::strace::StraceQueue;
 
-use system::error::{Error, Result, EBADF, EFAULT, ENOMEM, ESRCH};
+use syscall::{do_sys_exit, signal_ignored, Rlimit, SigAction, CLONE_FILES, CLONE_FS, CLONE_SETTLS, CLONE_THREAD, CLONE_VM,
+              CLONE_VFORK, FD_CLOEXEC, NSIG, O_APPEND, O_CLOEXEC, O_NONBLOCK, O_RDWR, O_WRONLY, PRIV_ALL, RLIM_INFINITY, SIGALRM};
 
-use sync::WaitMap;
+use system::error::{Error, Result, EBADF, EFAULT, EINVAL, EMFILE, ENFILE, ENOMEM, ESRCH};
+
+use sync::Intex;
 
 pub const CONTEXT_STACK_SIZE: usize = 1024 * 1024;
 pub const CONTEXT_STACK_ADDR: usize = 0xB0000000;
+/// The lowest address user memory may be placed at by the context, above the kernel image, its
+/// heap and its page tables, which user pages must not replace
+pub const USER_START: usize = 0x40000000;
+/// The maximum number of open files per context
+pub const CONTEXT_MAX_FILES: usize = 256;
+/// The maximum number of open files in the system
+pub const SYSTEM_MAX_FILES: usize = 4096;
+/// The number of open files in a context at which a possible leak is first reported
+pub const FILES_WARN: usize = 64;
+/// The open flags kept for a file, the rest only change what `open` does
+pub const FILE_FLAGS: usize = O_WRONLY | O_RDWR | O_NONBLOCK | O_APPEND;
+
+/// The nice value of the highest priority
+pub const PRIORITY_MIN: isize = -20;
+/// The nice value of the lowest priority
+pub const PRIORITY_MAX: isize = 19;
+/// The number of run levels, level 0 runs first
+pub const RUN_LEVELS: usize = 4;
+/// The ticks in a time slice on each run level, shorter on the levels that run first
+const SLICE_TICKS: [usize; RUN_LEVELS] = [2, 4, 8, 16];
+/// The ticks after which every context is moved back to the level of its priority, so that
+/// contexts that were moved down cannot starve
+const BOOST_TICKS: usize = 256;
+
+/// The run level a context starts on, and returns to after a boost, for a nice value
+pub fn priority_level(priority: isize) -> usize {
+    let priority = cmp::max(PRIORITY_MIN, cmp::min(PRIORITY_MAX, priority));
+    ((priority - PRIORITY_MIN) as usize * RUN_LEVELS) / (PRIORITY_MAX - PRIORITY_MIN + 1) as usize
+}
+
+/// The number of open files in the system
+pub static OPEN_FILES: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Size of the unmapped guard region below each kernel stack
+pub const CONTEXT_STACK_GUARD: usize = 4096;
+/// Value written at the bottom of each kernel stack, checked on context switch
+pub const CONTEXT_STACK_CANARY: usize = 0xDEADBEEF;
+
+/// Allocate a kernel stack with room for the FX area, an unmapped guard page below it, and a
+/// canary at its lowest address. Returns the bottom of the usable stack, or 0 on failure
+pub unsafe fn kernel_stack_alloc() -> usize {
+    let base = memory::alloc(CONTEXT_STACK_GUARD + CONTEXT_STACK_SIZE + 512);
+    if base > 0 {
+        for page in 0..CONTEXT_STACK_GUARD / 4096 {
+            Page::new(base + page * 4096).unmap();
+        }
+
+        let kernel_stack = base + CONTEXT_STACK_GUARD;
+        ptr::write(kernel_stack as *mut usize, CONTEXT_STACK_CANARY);
+        kernel_stack
+    } else {
+        0
+    }
+}
+
+/// Remap the guard page and free a kernel stack allocated by `kernel_stack_alloc`
+pub unsafe fn kernel_stack_unalloc(kernel_stack: usize) {
+    if kernel_stack > 0 {
+        let base = kernel_stack - CONTEXT_STACK_GUARD;
+        for page in 0..CONTEXT_STACK_GUARD / 4096 {
+            Page::new(base + page * 4096).map_kernel_write(base + page * 4096);
+        }
+        memory::unalloc(base);
+    }
+}
+
+/// The FPU owner before the data of the bootstrap processor is set up
+static FPU_OWNER: AtomicUsize = ATOMIC_USIZE_INIT;
+/// The running context before the data of the bootstrap processor is set up
+static FPU_CURRENT: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// The context whose registers are loaded in the FPU of the running processor, 0 if none
+fn fpu_owner() -> &'static AtomicUsize {
+    percpu::get().map_or(&FPU_OWNER, |percpu| &percpu.fpu_owner)
+}
+
+/// The context running on the running processor, as last switched to
+fn fpu_current() -> &'static AtomicUsize {
+    percpu::get().map_or(&FPU_CURRENT, |percpu| &percpu.fpu_current)
+}
+
+/// The task switched bit of CR0, which makes the next FPU or SSE instruction trap
+const CR0_TS: usize = 1 << 3;
+
+#[inline(always)]
+unsafe fn read_cr0() -> usize {
+    let cr0: usize;
+    asm!("mov $0, cr0" : "=r"(cr0) : : "memory" : "intel", "volatile");
+    cr0
+}
+
+#[inline(always)]
+unsafe fn write_cr0(cr0: usize) {
+    asm!("mov cr0, $0" : : "r"(cr0) : "memory" : "intel", "volatile");
+}
+
+/// Save the FPU registers of their owner before a sleep state, in which the FPU loses them, and
+/// make the next FPU instruction trap to load them again
+pub unsafe fn fpu_suspend() {
+    let owner = fpu_owner().load(Ordering::SeqCst) as *mut Context;
+    if owner as usize > 0 {
+        (*owner).fpu_save();
+    }
+    fpu_owner().store(0, Ordering::SeqCst);
+}
+
+/// Trap on the next FPU instruction after waking, as the FPU was initialized by the trampoline
+pub unsafe fn fpu_resume() {
+    write_cr0(read_cr0() | CR0_TS);
+}
+
+/// Load the FPU registers of the running context, called on the device not available exception
+///
+/// The registers of the previous owner are saved first. Contexts that have never used the FPU
+/// start with it initialized.
+pub unsafe fn fpu_trap() {
+    asm!("clts" : : : "memory" : "intel", "volatile");
+
+    let owner = fpu_owner().load(Ordering::SeqCst) as *mut Context;
+    let current = fpu_current().load(Ordering::SeqCst) as *mut Context;
+    if owner != current {
+        if owner as usize > 0 {
+            asm!("fxsave [$0]" : : "r"((*owner).fx) : "memory" : "intel", "volatile");
+            (*owner).loadable = true;
+        }
+
+        if current as usize > 0 && (*current).loadable {
+            asm!("fxrstor [$0]" : : "r"((*current).fx) : "memory" : "intel", "volatile");
+        } else {
+            asm!("fninit" : : : "memory" : "intel", "volatile");
+        }
 
+        fpu_owner().store(current as usize, Ordering::SeqCst);
+    }
+}
+
+/// A real interval timer, set by `setitimer` or `alarm`
+#[derive(Copy, Clone)]
+pub struct ITimer {
+    /// The next expiry on the monotonic clock
+    pub deadline: Duration,
+    /// The period, or `None` for a one-shot timer
+    pub interval: Option<Duration>,
+}
+
+/// The contexts, shared by every processor
+///
+/// Each processor runs the contexts whose `cpu` is its number, its run queue, and takes a context
+/// from the queue of another processor when its own is empty. Only contexts without user memory
+/// can move, as user memory is mapped into the page tables shared by every processor when a
+/// context is switched to, so processes run on the bootstrap processor and the others run kernel
+/// threads.
 pub struct ContextManager {
     pub inner: Vec<Box<Context>>,
     pub enabled: bool,
-    pub i: usize,
     pub next_pid: usize,
+    /// The PID of init, which adopts orphaned contexts, or 0 before it is started
+    pub init_pid: usize,
+    /// The ticks left until every context is boosted back to the level of its priority
+    pub boost: usize,
 }
 
 impl ContextManager {
@@ -40,18 +202,25 @@ impl ContextManager {
         ContextManager {
             inner: Vec::new(),
             enabled: false,
-            i: 0,
             next_pid: 1,
+            init_pid: 0,
+            boost: BOOST_TICKS,
         }
     }
 
+    /// The index of the context running on this processor
+    fn current_i(&self) -> Option<usize> {
+        let current = percpu::get().map_or(ptr::null(), |percpu| percpu.current as *const Context);
+        self.inner.iter().position(|context| &**context as *const Context == current)
+    }
+
     pub fn current(&self) -> Result<&Box<Context>> {
-        let i = self.i;
+        let i = try!(self.current_i().ok_or(Error::new(ESRCH)));
         self.get(i)
     }
 
     pub fn current_mut(&mut self) -> Result<&mut Box<Context>> {
-        let i = self.i;
+        let i = try!(self.current_i().ok_or(Error::new(ESRCH)));
         self.get_mut(i)
     }
 
@@ -87,102 +256,350 @@ impl ContextManager {
         self.inner.push(context);
     }
 
+    /// Add the idle context of this processor, which is already running on it
+    pub unsafe fn push_idle(&mut self, mut context: Box<Context>) {
+        context.cpu = percpu::cpu_id();
+        context.pinned = true;
+        context.running = true;
+        if let Some(percpu) = percpu::get() {
+            percpu.current = context.deref_mut();
+            percpu.idle = context.deref_mut();
+        }
+        self.inner.push(context);
+    }
+
+    /// Forget the application processors after they were reset. Their idle contexts are removed,
+    /// and the contexts on their run queues move to the bootstrap processor. A context that was
+    /// running on one of them is lost, as its registers were not saved
+    pub unsafe fn reset_others(&mut self) {
+        for percpu in percpu::all().iter() {
+            if percpu.cpu_id != 0 && ! percpu.previous.is_null() {
+                (*percpu.previous).running = false;
+            }
+        }
+        self.inner.retain(|context| context.cpu == 0 || ! context.pinned);
+        for context in self.inner.iter_mut() {
+            context.cpu = 0;
+        }
+    }
+
+    /// Remove the contexts that exited and were reaped, except those still running on their
+    /// kernel stacks, which are removed once their processor has switched away from them
     pub unsafe fn clean(&mut self) {
-        loop {
-            if self.i >= self.len() {
-                self.i -= self.len();
+        self.inner.retain(|context| context.running || ! context.exited || context.status.is_some());
+    }
+
+    /// Let other processors run the context this processor last switched away from, as its
+    /// registers have been saved by now, and return the number and idle context of this processor
+    fn processor(&mut self) -> (usize, *const Context) {
+        match percpu::get() {
+            Some(percpu) => {
+                let previous = mem::replace(&mut percpu.previous, ptr::null_mut()) as *const Context;
+                if let Some(context) = self.inner.iter_mut().find(|context| &***context as *const Context == previous) {
+                    context.running = false;
+                }
+                (percpu.cpu_id, percpu.idle as *const Context)
+            },
+            None => (0, ptr::null()),
+        }
+    }
+
+    /// Find the runnable context on the best run level of the run queue of processor `cpu_id`,
+    /// other than its idle context, and its level. Contexts on a level take turns, the first
+    /// after `after` is chosen. If the queue has none, the best context that can move is taken
+    /// from another queue
+    fn pick(&mut self, cpu_id: usize, after: usize, idle: *const Context) -> Option<(usize, usize)> {
+        let now = Duration::monotonic();
+        let len = self.inner.len();
+        let fpu_owners: Vec<usize> = percpu::all().iter()
+                                                  .filter(|percpu| percpu.cpu_id != cpu_id)
+                                                  .map(|percpu| percpu.fpu_owner.load(Ordering::SeqCst))
+                                                  .collect();
+
+        let mut best: Option<(usize, usize)> = None;
+        let mut steal: Option<(usize, usize)> = None;
+        for offset in 1..len + 1 {
+            let i = (after + offset) % len;
+            let context = &mut self.inner[i];
+            let context_ptr = &**context as *const Context;
+            if context.exited || context_ptr == idle {
+                // A zombie, which only waits to be reaped, or the idle context
+                continue;
+            }
+            if context.running && i != after {
+                // Running on another processor, or still being switched away from
+                continue;
+            }
+            if context.blocked {
+                match context.wake {
+                    Some(wake) if wake <= now => {
+                        context.blocked = false;
+                        context.wake = None;
+                    },
+                    _ => continue,
+                }
             }
 
-            let mut remove = false;
-            if let Ok(next) = self.current() {
-                if next.exited {
-                    remove = true;
+            if context.cpu == cpu_id {
+                if best.map_or(true, |(_, level)| context.level < level) {
+                    best = Some((i, context.level));
+                }
+            } else if context.movable() && ! fpu_owners.contains(&(context_ptr as usize)) {
+                // The FPU registers of a context are saved only when another context uses the FPU
+                if steal.map_or(true, |(_, level)| context.level < level) {
+                    steal = Some((i, context.level));
                 }
             }
+        }
 
-            if remove {
-                let i = self.i;
-                drop(self.inner.remove(i));
-            } else {
-                break;
+        best.or(steal)
+    }
+
+    /// Is there a context for this processor to run other than its idle context
+    pub fn has_work(&mut self) -> bool {
+        let (cpu_id, idle) = self.processor();
+        let after = self.current_i().unwrap_or(0);
+        self.pick(cpu_id, after, idle).is_some()
+    }
+
+    /// Choose the index of the context to run next on this processor, which may be the current
+    /// one at `current_i`
+    ///
+    /// The runnable context on the best run level is chosen, the first after the current one so
+    /// that contexts on a level take turns. At the end of a time slice, which `preempted` counts
+    /// down, the current context is moved down a level, so that one that keeps spinning gives
+    /// way to those that block. Until then it is only preempted by a context on a better level.
+    /// The idle context of the processor is chosen when nothing else can run.
+    pub fn schedule(&mut self, current_i: usize, preempted: bool) -> usize {
+        let (cpu_id, idle) = self.processor();
+
+        if preempted {
+            // The bootstrap processor keeps time for the boost
+            if cpu_id == 0 {
+                if self.boost > 0 {
+                    self.boost -= 1;
+                } else {
+                    self.boost = BOOST_TICKS;
+                    for context in self.inner.iter_mut() {
+                        context.level = priority_level(context.priority);
+                    }
+                }
+            }
+
+            if let Some(current) = self.inner.get_mut(current_i) {
+                if current.slice > 0 {
+                    current.slice -= 1;
+                }
+                if current.slice == 0 && current.level + 1 < RUN_LEVELS {
+                    current.level += 1;
+                }
+            }
+        }
+
+        let best = self.pick(cpu_id, current_i, idle);
+
+        let next_i = match (self.inner.get(current_i), best) {
+            (Some(current), Some((i, level))) => {
+                let runnable = ! current.exited && ! current.blocked && &**current as *const Context != idle;
+                if preempted && runnable && current.slice > 0 && level >= current.level {
+                    current_i
+                } else {
+                    i
+                }
+            },
+            (_, Some((i, _))) => i,
+            (_, None) => self.inner.iter()
+                                   .position(|context| &**context as *const Context == idle)
+                                   .unwrap_or(current_i),
+        };
+
+        if let Some(next) = self.inner.get_mut(next_i) {
+            // A context taken from another run queue stays on this one
+            next.cpu = cpu_id;
+            if next_i != current_i || next.slice == 0 {
+                next.slice = SLICE_TICKS[next.level];
             }
         }
 
-        if self.i >= self.len() {
-            self.i -= self.len();
+        next_i
+    }
+
+    /// Send a signal to every context in a process group
+    pub fn signal_group(&mut self, pgid: usize, signal: usize) {
+        for context in self.inner.iter_mut() {
+            if context.pgid == pgid {
+                context.signal(signal);
+            }
+        }
+    }
+
+    /// Check if a context of the users `uid` and `euid` may use the process group `pgid`, for
+    /// `setpgid` and the foreground group of a terminal
+    ///
+    /// There are no sessions, so a group is limited to the users of its contexts, as for `kill`:
+    /// root may use any group that has contexts, and other users only groups whose contexts are
+    /// all theirs.
+    pub fn group_permitted(&self, pgid: usize, uid: usize, euid: usize) -> bool {
+        let mut found = false;
+        for context in self.inner.iter() {
+            if context.pgid == pgid && ! context.exited {
+                if euid != 0 && uid != context.uid && euid != context.uid {
+                    return false;
+                }
+                found = true;
+            }
+        }
+        found
+    }
+
+    /// Send `SIGALRM` to the contexts whose real interval timer expired, which the timer wheel
+    /// finds, called on each tick
+    pub fn check_timers(&mut self) {
+        let now = Duration::monotonic();
+        let mut timers = ::env().timers.lock();
+        for timer in timers.tick() {
+            let pid = match timer.pid {
+                Some(pid) => pid,
+                None => {
+                    // A timer resource expired, the contexts polling it check it again
+                    unsafe { ::env().pollers.notify(); }
+                    continue;
+                }
+            };
+            let context = match self.inner.iter_mut().find(|context| context.pid == pid) {
+                Some(context) => context,
+                None => continue,
+            };
+            let mut itimer = match context.itimer {
+                // The wheel only holds the timer of the current interval timer
+                Some(itimer) if itimer.deadline == timer.deadline => itimer,
+                _ => continue,
+            };
+
+            // The tick came early by the interpolation of the clock
+            if itimer.deadline > now {
+                timers.add(context.pid, itimer.deadline, now);
+                continue;
+            }
+
+            context.signal(SIGALRM);
+            context.itimer = match itimer.interval {
+                Some(interval) => {
+                    // Expiries that were missed are skipped, and signalled once
+                    let missed = (now - itimer.deadline).as_nanos() / interval.as_nanos();
+                    itimer.deadline = itimer.deadline + Duration::from_nanos(interval.as_nanos() * (missed + 1));
+                    timers.add(context.pid, itimer.deadline, now);
+                    Some(itimer)
+                },
+                None => None,
+            };
         }
     }
 }
 
 
-/// Switch context
+/// Switch context, because the current one blocked, slept or yielded
 ///
 /// Unsafe due to interrupt disabling, raw pointers, and unsafe Context functions
 pub unsafe fn context_switch() {
+    switch(false);
+}
+
+/// Switch context at the end of a time slice
+pub unsafe fn context_preempt() {
+    switch(true);
+}
+
+unsafe fn switch(preempted: bool) {
     let mut current_ptr: *mut Context = 0 as *mut Context;
     let mut next_ptr: *mut Context = 0 as *mut Context;
 
     {
-        let mut contexts = ::env().contexts.lock();
-        if contexts.enabled {
-            let current_i = contexts.i;
-            'searching: loop {
-                contexts.i += 1;
-                contexts.clean();
-                if let Ok(mut next) = contexts.current_mut() {
-                    if next.blocked {
-                        if let Some(wake) = next.wake {
-                            if wake <= Duration::monotonic() {
-                                next.blocked = false;
-                                next.wake = None;
-                                break 'searching;
-                            }
-                        }
-                    } else {
-                        break 'searching;
-                    }
+        let mut contexts = ::env().contexts.write();
+        contexts.clean();
+        let current_i = contexts.current_i();
+        if let (true, Some(current_i)) = (contexts.enabled, current_i) {
+            let next_i = contexts.schedule(current_i, preempted);
+
+            if next_i != current_i {
+                let mut percpu = percpu::get();
+                if let Some(ref mut percpu) = percpu {
+                    percpu.stats.switch(preempted);
                 }
-                if contexts.i == current_i {
-                    break 'searching;
-                }
-            }
 
-            if contexts.i != current_i {
+                let mut current_pid = 0;
                 if let Ok(mut current) = contexts.get_mut(current_i) {
+                    current_pid = current.pid;
+
+                    current.check_stack();
+
                     current.unmap();
 
                     current_ptr = current.deref_mut();
                 }
 
-                if let Ok(mut next) = contexts.current_mut() {
+                if let Ok(mut next) = contexts.get_mut(next_i) {
                     next.switch += 1;
+                    next.running = true;
+
+                    tracepoint!(TracePoint::Switch, current_pid, next.pid);
+                    if let Some(ref mut percpu) = percpu {
+                        percpu.pid = next.pid;
+                    }
+
+                    ::env().perf.lock().on_switch(current_pid, next.pid);
+
+                    next.map();
 
-                    if let Some(ref mut tss) = ::TSS_PTR {
+                    next_ptr = next.deref_mut();
+
+                    if let Some(percpu) = percpu {
+                        let tss = &mut *percpu.tss;
                         if next.kernel_stack > 0 {
                             tss.sp0 = next.kernel_stack + CONTEXT_STACK_SIZE - 128;
                         } else {
                             tss.sp0 = 0x200000 - 128;
                         }
-                    }
-
-                    next.map();
 
-                    next_ptr = next.deref_mut();
+                        percpu.current = next_ptr;
+                        percpu.tls = next.tls;
+                        // Other processors may run it once its registers are saved
+                        percpu.previous = current_ptr;
+                    }
                 }
             }
         }
     }
 
     if current_ptr as usize > 0 && next_ptr as usize > 0 {
+        // The guards are counted for the running context, one that holds any cannot move
+        if let Some(percpu) = percpu::get() {
+            (*current_ptr).locks = percpu.locks;
+            percpu.locks = (*next_ptr).locks;
+        }
+
         (*current_ptr).switch_to(&mut *next_ptr);
     }
 }
 
 pub unsafe fn context_clone(regs: &Regs) -> Result<usize> {
-    let mut contexts = ::env().contexts.lock();
+    let mut contexts = ::env().contexts.write();
     let flags = regs.bx;
+    // A stack for the child, which keeps the stack of the parent if it is zero
+    let stack = regs.cx;
+    let tls = regs.dx;
 
-    let kernel_stack = memory::alloc(CONTEXT_STACK_SIZE + 512);
+    // Threads share the memory they run in
+    if flags & CLONE_THREAD == CLONE_THREAD && flags & CLONE_VM != CLONE_VM {
+        return Err(Error::new(EINVAL));
+    }
+
+    // The thread pointer is only restored on return to user mode on x86_64
+    if flags & CLONE_SETTLS == CLONE_SETTLS && cfg!(target_arch = "x86") {
+        return Err(Error::new(EINVAL));
+    }
+
+    let kernel_stack = kernel_stack_alloc();
     if kernel_stack > 0 {
         let clone_pid = Context::next_pid();
 
@@ -201,19 +618,46 @@ pub unsafe fn context_clone(regs: &Regs) -> Result<usize> {
 
             let child_regs = &mut *(child_regs_addr as *mut Regs);
             child_regs.ax = 0;
+            if stack > 0 {
+                child_regs.sp = stack;
+            }
 
             let mut kernel_regs = parent.regs;
             kernel_regs.sp = child_regs_addr - extra_size;
 
+            parent.fpu_save();
+
             let fx = kernel_stack + CONTEXT_STACK_SIZE;
             ::memcpy(fx as *mut u8, parent.fx as *const u8, 512);
 
             box Context {
                 pid: clone_pid,
                 ppid: parent.pid,
+                pgid: parent.pgid,
+                tgid: if flags & CLONE_THREAD == CLONE_THREAD {
+                    parent.tgid
+                } else {
+                    clone_pid
+                },
                 name: parent.name.clone(),
+                uid: parent.uid,
+                gid: parent.gid,
+                euid: parent.euid,
+                egid: parent.egid,
+                priority: parent.priority,
+                level: priority_level(parent.priority),
+                slice: 0,
+                // A context with user memory stays on the bootstrap processor
+                cpu: 0,
+                pinned: false,
+                running: false,
+                locks: 0,
+                privs: parent.privs,
+                namespace: parent.namespace.clone(),
                 blocked: false,
                 exited: false,
+                status: None,
+                waiting: false,
                 switch: 0,
                 time: 0,
                 vfork: if flags & CLONE_VFORK == CLONE_VFORK {
@@ -223,11 +667,22 @@ pub unsafe fn context_clone(regs: &Regs) -> Result<usize> {
                     None
                 },
                 wake: None,
+                itimer: None,
+                signals: 0,
+                sigmask: parent.sigmask,
+                sigactions: parent.sigactions,
+                core_limit: parent.core_limit,
+                files_limit: parent.files_limit,
+                files_warn: Cell::new(FILES_WARN),
+                env: parent.env.clone(),
 
                 kernel_stack: kernel_stack,
                 regs: kernel_regs,
                 fx: fx,
-                stack: if let Some(ref entry) = parent.stack {
+                stack: if stack > 0 {
+                    // The thread runs on a stack it allocated in the shared memory
+                    None
+                } else if let Some(ref entry) = parent.stack {
                     let physical_address = memory::alloc(entry.virtual_size);
                     if physical_address > 0 {
                         ::memcpy(physical_address as *mut u8,
@@ -239,6 +694,8 @@ pub unsafe fn context_clone(regs: &Regs) -> Result<usize> {
                             virtual_size: entry.virtual_size,
                             writeable: entry.writeable,
                             allocated: true,
+                            shared: None,
+                            object: None,
                         })
                     } else {
                         None
@@ -246,6 +703,11 @@ pub unsafe fn context_clone(regs: &Regs) -> Result<usize> {
                 } else {
                     None
                 },
+                tls: if flags & CLONE_SETTLS == CLONE_SETTLS {
+                    tls
+                } else {
+                    parent.tls
+                },
                 loadable: parent.loadable,
 
                 cwd: if flags & CLONE_FS == CLONE_FS {
@@ -259,22 +721,11 @@ pub unsafe fn context_clone(regs: &Regs) -> Result<usize> {
                     parent.memory.clone()
                 } else {
                     let mut mem: Vec<ContextMemory> = Vec::new();
-                    for entry in (*parent.memory.get()).iter() {
-                        let physical_address = memory::alloc(entry.virtual_size);
-                        if physical_address > 0 {
-                            ::memcpy(physical_address as *mut u8,
-                                     entry.physical_address as *const u8,
-                                     entry.virtual_size);
-
+                    for entry in (*parent.memory.get()).iter_mut() {
+                        if let Some(child_entry) = entry.share() {
                             //debugln!("{}: {}: dup memory {:X}:{:X} for {}", parent.pid, parent.name, entry.virtual_address, entry.virtual_address + entry.virtual_size, clone_pid);
 
-                            mem.push(ContextMemory {
-                                physical_address: physical_address,
-                                virtual_address: entry.virtual_address,
-                                virtual_size: entry.virtual_size,
-                                writeable: entry.writeable,
-                                allocated: true,
-                            });
+                            mem.push(child_entry);
                         } else {
                             //debugln!("{}: {}: failed to dup memory {:X}:{:X} for {}", parent.pid, parent.name, entry.virtual_address, entry.virtual_address + entry.virtual_size, clone_pid);
                         }
@@ -288,14 +739,14 @@ pub unsafe fn context_clone(regs: &Regs) -> Result<usize> {
                 } else {
                     let mut files: Vec<ContextFile> = Vec::new();
                     for file in (*parent.files.get()).iter() {
-                        match file.resource.dup() {
+                        match file.resource.get().dup() {
                             Ok(resource) => {
                                 //debugln!("{}: {}: dup resource {} for {}", parent.pid, parent.name, file.fd, clone_pid);
 
-                                files.push(ContextFile {
-                                    fd: file.fd,
-                                    resource: resource,
-                                });
+                                let mut new_file = ContextFile::new(file.fd, resource, file.url.clone(), file.flags);
+                                new_file.fd_flags = file.fd_flags;
+                                new_file.lock = file.lock.clone();
+                                files.push(new_file);
                             },
                             Err(_err) => () //debugln!("{}: {}: failed to dup resource {} for {}: {}", parent.pid, parent.name, file.fd, clone_pid, err)
                         }
@@ -303,11 +754,13 @@ pub unsafe fn context_clone(regs: &Regs) -> Result<usize> {
                     Arc::new(UnsafeCell::new(files))
                 },
 
-                statuses: WaitMap::new(),
+                tracer: None,
             }
         };
 
         contexts.push(context);
+        // Switching while holding the lock would keep other processors from it
+        drop(contexts);
 
         if flags & CLONE_VFORK == CLONE_VFORK {
             context_switch();
@@ -375,18 +828,142 @@ unsafe extern "cdecl" fn context_box(/*Throw away extra params from ABI*/ _rdi:
     do_sys_exit(0);
 }
 
+/// Memory shared by the contexts made by `fork`, or a memory object, freed when the last one
+/// drops it
+pub struct SharedMemory {
+    physical_address: usize,
+    size: usize,
+}
+
+impl SharedMemory {
+    /// Allocate a zeroed memory object of `size` bytes, `None` if there is not enough memory
+    pub fn new(size: usize) -> Option<SharedMemory> {
+        let physical_address = unsafe { memory::alloc(size) };
+        if physical_address > 0 {
+            Some(SharedMemory {
+                physical_address: physical_address,
+                size: size,
+            })
+        } else {
+            None
+        }
+    }
+
+    pub fn physical_address(&self) -> usize {
+        self.physical_address
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+}
+
+impl Drop for SharedMemory {
+    fn drop(&mut self) {
+        if self.physical_address > 0 {
+            if unsafe { ::ENV_PTR.is_some() } {
+                ::env().grants.lock().revoke(self.physical_address, self.size);
+            }
+            unsafe { memory::unalloc(self.physical_address) };
+        }
+    }
+}
+
 pub struct ContextMemory {
     pub physical_address: usize,
     pub virtual_address: usize,
     pub virtual_size: usize,
     pub writeable: bool,
     pub allocated: bool,
+    /// Set instead of `allocated` while the memory is shared after `fork`. It is mapped read
+    /// only, and the first write copies it, see `unshare`
+    pub shared: Option<Arc<SharedMemory>>,
+    /// The memory object this maps, like those of `shm:`, which keeps it allocated. Writes are
+    /// seen by every mapping of it, and `fork` shares it instead of copying it
+    pub object: Option<Arc<SharedMemory>>,
 }
 
 impl ContextMemory {
+    /// Share allocated memory with a copy of this mapping for a new process, which is cheap as
+    /// nothing is copied until one of them writes. Other memory is copied now
+    pub unsafe fn share(&mut self) -> Option<ContextMemory> {
+        if let Some(ref object) = self.object {
+            return Some(ContextMemory {
+                physical_address: self.physical_address,
+                virtual_address: self.virtual_address,
+                virtual_size: self.virtual_size,
+                writeable: self.writeable,
+                allocated: false,
+                shared: None,
+                object: Some(object.clone()),
+            });
+        }
+
+        if self.allocated {
+            self.allocated = false;
+            self.shared = Some(Arc::new(SharedMemory {
+                physical_address: self.physical_address,
+                size: self.virtual_size,
+            }));
+            // Remap read only, so that the first write faults
+            self.map();
+        }
+
+        if let Some(ref shared) = self.shared {
+            return Some(ContextMemory {
+                physical_address: self.physical_address,
+                virtual_address: self.virtual_address,
+                virtual_size: self.virtual_size,
+                writeable: self.writeable,
+                allocated: false,
+                shared: Some(shared.clone()),
+                object: None,
+            });
+        }
+
+        let physical_address = memory::alloc(self.virtual_size);
+        if physical_address > 0 {
+            ::memcpy(physical_address as *mut u8, self.physical_address as *const u8, self.virtual_size);
+            Some(ContextMemory {
+                physical_address: physical_address,
+                virtual_address: self.virtual_address,
+                virtual_size: self.virtual_size,
+                writeable: self.writeable,
+                allocated: true,
+                shared: None,
+                object: None,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Stop sharing the memory, copying it unless this is the last mapping of it, before it is
+    /// written or reallocated. It must be mapped again after
+    pub unsafe fn unshare(&mut self) -> Result<()> {
+        if let Some(mut shared) = self.shared.take() {
+            if let Some(last) = Arc::get_mut(&mut shared) {
+                // Owned by this mapping now, so it must not be freed when shared is dropped
+                last.physical_address = 0;
+                self.allocated = true;
+                return Ok(());
+            }
+
+            let physical_address = memory::alloc(self.virtual_size);
+            if physical_address == 0 {
+                self.shared = Some(shared);
+                return Err(Error::new(ENOMEM));
+            }
+            ::memcpy(physical_address as *mut u8, self.physical_address as *const u8, self.virtual_size);
+            self.physical_address = physical_address;
+            self.allocated = true;
+        }
+        Ok(())
+    }
+
     pub unsafe fn map(&mut self) {
         for i in 0..(self.virtual_size + 4095) / 4096 {
-            if self.writeable {
+            if self.writeable && self.shared.is_none() {
                 Page::new(self.virtual_address + i * 4096)
                     .map_user_write(self.physical_address + i * 4096);
             } else {
@@ -407,14 +984,76 @@ impl ContextMemory {
 impl Drop for ContextMemory {
     fn drop(&mut self) {
         if self.allocated {
+            if unsafe { ::ENV_PTR.is_some() } {
+                ::env().grants.lock().revoke(self.physical_address, self.virtual_size);
+            }
             unsafe { memory::unalloc(self.physical_address) };
         }
     }
 }
 
+/// The resource of a file, shared with the calls using it
+///
+/// A call holds the resource while it uses it, without the contexts locked, so that closing the
+/// file or changing the file table, which a context sharing it with `CLONE_FILES` may do at the
+/// same time, does not free or move the resource under the call.
+pub struct FileResource {
+    inner: UnsafeCell<Box<Resource>>,
+}
+
+impl FileResource {
+    pub fn new(resource: Box<Resource>) -> Arc<FileResource> {
+        Arc::new(FileResource {
+            inner: UnsafeCell::new(resource),
+        })
+    }
+
+    /// The resource, which calls on the same file may use at once, as they could before
+    pub fn get<'a>(&self) -> &'a mut Box<Resource> {
+        unsafe { &mut *self.inner.get() }
+    }
+}
+
 pub struct ContextFile {
     pub fd: usize,
-    pub resource: Box<Resource>,
+    pub resource: Arc<FileResource>,
+    /// The URL the resource was opened with
+    pub url: String,
+    /// The flags it was opened with, see `FILE_FLAGS`
+    pub flags: usize,
+    /// The file descriptor flags, `FD_CLOEXEC` is set by `O_CLOEXEC` and not copied by `dup`
+    pub fd_flags: usize,
+    /// Monotonic time at which the file was opened, or inherited
+    pub time: Duration,
+    /// The `flock` lock, shared with the duplicates of the file and released when the last is
+    /// closed
+    pub lock: Arc<Intex<Option<FileLock>>>,
+}
+
+impl ContextFile {
+    pub fn new(fd: usize, resource: Box<Resource>, url: String, flags: usize) -> ContextFile {
+        OPEN_FILES.fetch_add(1, Ordering::SeqCst);
+
+        ContextFile {
+            fd: fd,
+            resource: FileResource::new(resource),
+            url: url,
+            flags: flags & FILE_FLAGS,
+            fd_flags: if flags & O_CLOEXEC == O_CLOEXEC {
+                FD_CLOEXEC
+            } else {
+                0
+            },
+            time: Duration::monotonic(),
+            lock: Arc::new(Intex::new(None)),
+        }
+    }
+}
+
+impl Drop for ContextFile {
+    fn drop(&mut self) {
+        OPEN_FILES.fetch_sub(1, Ordering::SeqCst);
+    }
 }
 
 pub struct Context {
@@ -423,12 +1062,52 @@ pub struct Context {
     pub pid: usize,
     /// The PID of the parent
     pub ppid: usize,
+    /// The process group, which receives terminal signals while it is in the foreground
+    pub pgid: usize,
+    /// The PID of the main thread of the process, its own PID unless it was made with
+    /// `CLONE_THREAD`. The other threads are killed when the main thread exits
+    pub tgid: usize,
     /// The name of the context
     pub name: String,
+    /// The real user ID of the context, who started it
+    pub uid: usize,
+    /// The real group ID
+    pub gid: usize,
+    /// The effective user ID, which permissions are checked against, 0 is root
+    pub euid: usize,
+    /// The effective group ID
+    pub egid: usize,
+    /// The nice value, from `PRIORITY_MIN` for the highest priority to `PRIORITY_MAX` for the
+    /// lowest, set with `setpriority`
+    pub priority: isize,
+    /// The run level, which starts at the level of the priority and is lowered each time the
+    /// context uses a whole time slice
+    pub level: usize,
+    /// The ticks left in the time slice
+    pub slice: usize,
+    /// The processor whose run queue holds the context
+    pub cpu: usize,
+    /// Indicates that the context stays on its processor, like an idle context
+    pub pinned: bool,
+    /// Indicates that a processor is running the context, or has not yet saved its registers
+    pub running: bool,
+    /// The number of `Intex` guards held when the context was switched away from, it stays on
+    /// its processor while it holds any
+    pub locks: usize,
+    /// The privileges the context still holds, these can be dropped but never regained
+    pub privs: usize,
+    /// The schemes the context may use, or `None` for every scheme. Entering a namespace can
+    /// only remove schemes, and children inherit it
+    pub namespace: Option<Vec<String>>,
     /// Indicates that the context is blocked, and should not be switched to
     pub blocked: bool,
     /// Indicates that the context exited
     pub exited: bool,
+    /// The exit status, kept while the context is a zombie until its parent collects it with
+    /// `waitpid`. An exited context without one is reaped
+    pub status: Option<usize>,
+    /// Indicates that the context is blocked in `waitpid` until one of its children exits
+    pub waiting: bool,
     /// How many times was the context switched to
     pub switch: usize,
     /// The number of time slices used
@@ -437,6 +1116,23 @@ pub struct Context {
     pub vfork: Option<*mut Context>,
     /// When to wake up
     pub wake: Option<Duration>,
+    /// The real interval timer
+    pub itimer: Option<ITimer>,
+    /// Pending signals, one bit per signal number
+    pub signals: usize,
+    /// Blocked signals, which stay pending until they are unblocked
+    pub sigmask: usize,
+    /// The action for each signal, set with `sigaction`
+    pub sigactions: [SigAction; NSIG],
+    /// The limit on the size of core files, `RLIMIT_CORE`
+    pub core_limit: Rlimit,
+    /// The limit on the number of open files, `RLIMIT_NOFILE`, which cannot exceed
+    /// `CONTEXT_MAX_FILES`
+    pub files_limit: Rlimit,
+    /// The number of open files at which a possible leak is next reported, doubled each time
+    pub files_warn: Cell<usize>,
+    /// The environment, as `KEY=VALUE` strings, copied for children and replaced by exec
+    pub env: Vec<String>,
     // }
 
     // These members control the stack and registers and are unique to each context {
@@ -448,6 +1144,8 @@ pub struct Context {
     pub fx: usize,
     /// The context stack
     pub stack: Option<ContextMemory>,
+    /// The thread pointer, which the FS base is set to on return to user mode on x86_64
+    pub tls: usize,
     /// Indicates that registers can be loaded (they must be saved first)
     pub loadable: bool,
     // }
@@ -461,13 +1159,13 @@ pub struct Context {
     pub files: Arc<UnsafeCell<Vec<ContextFile>>>,
     // }
 
-    /// Exit statuses of children
-    pub statuses: WaitMap<usize, usize>,
+    /// Receives decoded syscalls while a tracer is attached, and is told when the context exits
+    pub tracer: Option<Arc<StraceQueue>>,
 }
 
 impl Context {
     pub fn next_pid() -> usize {
-        let mut contexts = ::env().contexts.lock();
+        let mut contexts = ::env().contexts.write();
 
         let mut next_pid = contexts.next_pid;
 
@@ -497,62 +1195,129 @@ impl Context {
 
     pub unsafe fn root() -> Box<Self> {
         let fx = memory::alloc(512);
+        let pid = Context::next_pid();
 
         box Context {
-            pid: Context::next_pid(),
+            pid: pid,
             ppid: 0,
+            pgid: pid,
+            tgid: pid,
             name: "kidle".to_string(),
+            uid: 0,
+            gid: 0,
+            euid: 0,
+            egid: 0,
+            // Idle only runs when nothing else can
+            priority: PRIORITY_MAX,
+            level: RUN_LEVELS - 1,
+            slice: 0,
+            cpu: 0,
+            pinned: false,
+            running: false,
+            locks: 0,
+            privs: PRIV_ALL,
+            namespace: None,
             blocked: false,
             exited: false,
+            status: None,
+            waiting: false,
             switch: 0,
             time: 0,
             vfork: None,
             wake: None,
+            itimer: None,
+            signals: 0,
+            sigmask: 0,
+            sigactions: [SigAction::default(); NSIG],
+            core_limit: Rlimit {
+                rlim_cur: 0,
+                rlim_max: RLIM_INFINITY,
+            },
+            files_limit: Rlimit {
+                rlim_cur: CONTEXT_MAX_FILES as u64,
+                rlim_max: CONTEXT_MAX_FILES as u64,
+            },
+            files_warn: Cell::new(FILES_WARN),
+            env: Vec::new(),
 
             kernel_stack: 0,
             regs: Regs::default(),
             fx: fx,
             stack: None,
+            tls: 0,
             loadable: false,
 
             cwd: Arc::new(UnsafeCell::new(String::new())),
             memory: Arc::new(UnsafeCell::new(Vec::new())),
             files: Arc::new(UnsafeCell::new(Vec::new())),
 
-            statuses: WaitMap::new(),
+            tracer: None,
         }
     }
 
     pub unsafe fn new(name: String, call: usize, args: &Vec<usize>) -> Box<Self> {
-        let kernel_stack = memory::alloc(CONTEXT_STACK_SIZE + 512);
+        let kernel_stack = kernel_stack_alloc();
 
         let mut regs = Regs::default();
         regs.sp = kernel_stack + CONTEXT_STACK_SIZE - 128;
 
         let fx = kernel_stack + CONTEXT_STACK_SIZE;
+        let pid = Context::next_pid();
 
         let mut ret = box Context {
-            pid: Context::next_pid(),
+            pid: pid,
             ppid: 0,
+            pgid: pid,
+            tgid: pid,
             name: name,
+            uid: 0,
+            gid: 0,
+            euid: 0,
+            egid: 0,
+            priority: 0,
+            level: priority_level(0),
+            slice: 0,
+            cpu: 0,
+            pinned: false,
+            running: false,
+            locks: 0,
+            privs: PRIV_ALL,
+            namespace: None,
             blocked: false,
             exited: false,
+            status: None,
+            waiting: false,
             switch: 0,
             time: 0,
             vfork: None,
             wake: None,
+            itimer: None,
+            signals: 0,
+            sigmask: 0,
+            sigactions: [SigAction::default(); NSIG],
+            core_limit: Rlimit {
+                rlim_cur: 0,
+                rlim_max: RLIM_INFINITY,
+            },
+            files_limit: Rlimit {
+                rlim_cur: CONTEXT_MAX_FILES as u64,
+                rlim_max: CONTEXT_MAX_FILES as u64,
+            },
+            files_warn: Cell::new(FILES_WARN),
+            env: Vec::new(),
 
             kernel_stack: kernel_stack,
             regs: regs,
             fx: fx,
             stack: None,
+            tls: 0,
             loadable: false,
 
             cwd: Arc::new(UnsafeCell::new(String::new())),
             memory: Arc::new(UnsafeCell::new(Vec::new())),
             files: Arc::new(UnsafeCell::new(Vec::new())),
 
-            statuses: WaitMap::new(),
+            tracer: None,
         };
 
         for arg in args.iter() {
@@ -564,7 +1329,22 @@ impl Context {
         ret
     }
 
-    pub fn spawn(name: String, box_fn: Box<FnBox()>) -> usize {
+    /// Start a kernel thread, a context that runs `f` in kernel mode on its own kernel stack and
+    /// exits when it returns, and return its PID
+    ///
+    /// Kernel threads are scheduled like other contexts, so a thread that waits for work should
+    /// block on a `WaitQueue` or sleep rather than spin. They may run on any processor.
+    pub fn kspawn<F>(name: &str, f: F) -> usize where F: FnOnce() + 'static {
+        Context::kspawn_boxed(name, box f, false)
+    }
+
+    /// Start a kernel thread that stays on the bootstrap processor, for one that executes a
+    /// program or changes a context that has user memory
+    pub fn kspawn_pinned<F>(name: &str, f: F) -> usize where F: FnOnce() + 'static {
+        Context::kspawn_boxed(name, box f, true)
+    }
+
+    fn kspawn_boxed(name: &str, box_fn: Box<FnBox()>, pinned: bool) -> usize {
         let ret;
 
         unsafe {
@@ -575,16 +1355,22 @@ impl Context {
             context_box_args.push(box_fn_ptr as usize);
             context_box_args.push(0); //Return address, 0 catches bad code
 
-            let context = Context::new(name, context_box as usize, &context_box_args);
+            let mut context = Context::new(name.to_string(), context_box as usize, &context_box_args);
+            context.pinned = pinned;
 
             ret = context.pid;
 
-            ::env().contexts.lock().push(context);
+            ::env().contexts.write().push(context);
         }
 
         ret
     }
 
+    /// Can the context run on another processor, which needs it to be without user memory
+    pub fn movable(&self) -> bool {
+        ! self.pinned && self.locks == 0 && self.stack.is_none() && unsafe { (*self.memory.get()).is_empty() }
+    }
+
     pub fn canonicalize(&self, path: &str) -> String {
         if path.find(':').is_none() {
             let cwd = unsafe { &*self.cwd.get() };
@@ -621,15 +1407,19 @@ impl Context {
     }
 
     /// Translate to physical if a ptr is inside of the mapped memory
+    ///
+    /// The whole range must lie in a single mapping, so that it is physically contiguous
     pub fn translate(&self, ptr: usize, len: usize) -> Result<usize> {
+        let end = try!(ptr.checked_add(len).ok_or(Error::new(EFAULT)));
+
         if let Some(ref stack) = self.stack {
-            if ptr >= stack.virtual_address && ptr + len <= stack.virtual_address + stack.virtual_size {
+            if ptr >= stack.virtual_address && end <= stack.virtual_address + stack.virtual_size {
                 return Ok(ptr - stack.virtual_address + stack.physical_address);
             }
         }
 
         for mem in unsafe { (*self.memory.get()).iter() } {
-            if ptr >= mem.virtual_address && ptr < mem.virtual_address + mem.virtual_size {
+            if ptr >= mem.virtual_address && end <= mem.virtual_address + mem.virtual_size {
                 return Ok(ptr - mem.virtual_address + mem.physical_address);
             }
         }
@@ -637,10 +1427,68 @@ impl Context {
         Err(Error::new(EFAULT))
     }
 
+    /// Check that a range is covered by the mappings of this context, which may be adjacent. A
+    /// writeable range is copied if it is shared after fork, so it must be in the current context
+    pub fn validate(&self, ptr: usize, len: usize, writeable: bool) -> Result<()> {
+        let end = try!(ptr.checked_add(len).ok_or(Error::new(EFAULT)));
+
+        let mut addr = ptr;
+        while addr < end {
+            let mut next = addr;
+
+            if let Some(ref stack) = self.stack {
+                if addr >= stack.virtual_address && addr < stack.virtual_address + stack.virtual_size
+                   && (stack.writeable || ! writeable) {
+                    next = stack.virtual_address + stack.virtual_size;
+                }
+            }
+
+            for mem in unsafe { (*self.memory.get()).iter_mut() } {
+                if addr >= mem.virtual_address && addr < mem.virtual_address + mem.virtual_size
+                   && (mem.writeable || ! writeable) {
+                    // The kernel and servers write to it directly, so memory shared after fork is
+                    // copied now instead of on a fault
+                    if writeable && mem.shared.is_some() {
+                        unsafe {
+                            try!(mem.unshare());
+                            mem.map();
+                        }
+                    }
+                    next = mem.virtual_address + mem.virtual_size;
+                    break;
+                }
+            }
+
+            if next == addr {
+                return Err(Error::new(EFAULT));
+            }
+
+            addr = next;
+        }
+
+        Ok(())
+    }
+
+    /// Copy the memory shared after fork at `address` for a write fault, returning false if the
+    /// fault was not caused by sharing
+    pub unsafe fn copy_on_write(&mut self, address: usize) -> bool {
+        for mem in (*self.memory.get()).iter_mut() {
+            if address >= mem.virtual_address && address < mem.virtual_address + mem.virtual_size
+               && mem.writeable && mem.shared.is_some() {
+                if mem.unshare().is_err() {
+                    return false;
+                }
+                mem.map();
+                return true;
+            }
+        }
+        false
+    }
+
     /// Get a memory map from a pointer
     pub fn get_mem<'a>(&self, ptr: usize) -> Result<&'a ContextMemory> {
         for mem in unsafe { (*self.memory.get()).iter() } {
-            if mem.virtual_address == ptr {
+            if mem.virtual_address == ptr && mem.virtual_size > 0 {
                 return Ok(mem);
             }
         }
@@ -651,7 +1499,7 @@ impl Context {
     /// Get a mutable memory map from a pointer
     pub fn get_mem_mut<'a>(&mut self, ptr: usize) -> Result<&'a mut ContextMemory> {
         for mem in unsafe { (*self.memory.get()).iter_mut() } {
-            if mem.virtual_address == ptr {
+            if mem.virtual_address == ptr && mem.virtual_size > 0 {
                 return Ok(mem);
             }
         }
@@ -666,7 +1514,12 @@ impl Context {
 
     /// Get the next available file descriptor
     pub fn next_fd(&self) -> usize {
-        let mut next_fd = 0;
+        self.next_fd_from(0)
+    }
+
+    /// Get the lowest free file descriptor at or above `min`
+    pub fn next_fd_from(&self, min: usize) -> usize {
+        let mut next_fd = min;
 
         let mut collision = true;
         while collision {
@@ -683,11 +1536,48 @@ impl Context {
         return next_fd;
     }
 
+    /// Check that `count` more files can be opened, by this context and by the system
+    pub fn check_files(&self, count: usize) -> Result<()> {
+        let limit = cmp::min(self.files_limit.rlim_cur, CONTEXT_MAX_FILES as u64) as usize;
+        if unsafe { (*self.files.get()).len() } + count > limit {
+            Err(Error::new(EMFILE))
+        } else if OPEN_FILES.load(Ordering::SeqCst) + count > SYSTEM_MAX_FILES {
+            Err(Error::new(ENFILE))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Add a resource opened with `url` and `flags` to the file table, returning its file
+    /// descriptor
+    pub fn add_file(&self, resource: Box<Resource>, url: String, flags: usize) -> Result<usize> {
+        try!(self.check_files(1));
+
+        let fd = self.next_fd();
+        unsafe {
+            (*self.files.get()).push(ContextFile::new(fd, resource, url, flags));
+        }
+        self.check_leak();
+        Ok(fd)
+    }
+
+    /// Report a possible descriptor leak each time the number of open files doubles past
+    /// `FILES_WARN`, as leaked scheme resources also hold state in their server
+    pub fn check_leak(&self) {
+        let count = unsafe { (*self.files.get()).len() };
+        let warn = self.files_warn.get();
+        if count >= warn {
+            self.files_warn.set(warn * 2);
+            klogln!(LogLevel::Warning, "{}: {}: {} open files, possible descriptor leak, see proc:{}/fd",
+                    self.pid, self.name, count, self.pid);
+        }
+    }
+
     /// Get a resource from a file descriptor
     pub fn get_file<'a>(&self, fd: usize) -> Result<&'a Box<Resource>> {
         for file in unsafe { (*self.files.get()).iter() } {
             if file.fd == fd {
-                return Ok(&file.resource);
+                return Ok(file.resource.get());
             }
         }
 
@@ -696,11 +1586,25 @@ impl Context {
         Err(Error::new(EBADF))
     }
 
+    /// Get a file from a file descriptor, to change its flags
+    ///
+    /// The file is in the file table, so it must only be used with the contexts locked. A call
+    /// that may block clones the `resource` instead.
+    pub fn get_context_file_mut<'a>(&self, fd: usize) -> Result<&'a mut ContextFile> {
+        for file in unsafe { (*self.files.get()).iter_mut() } {
+            if file.fd == fd {
+                return Ok(file);
+            }
+        }
+
+        Err(Error::new(EBADF))
+    }
+
     /// Get a mutable resource from a file descriptor
-    pub fn get_file_mut<'a>(&mut self, fd: usize) -> Result<&'a mut Box<Resource>> {
+    pub fn get_file_mut<'a>(&self, fd: usize) -> Result<&'a mut Box<Resource>> {
         for file in unsafe { (*self.files.get()).iter_mut() } {
             if file.fd == fd {
-                return Ok(&mut file.resource);
+                return Ok(file.resource.get());
             }
         }
 
@@ -709,6 +1613,49 @@ impl Context {
         Err(Error::new(EBADF))
     }
 
+    /// Mark a signal pending, interrupting a sleep so that it is acted on unless it is blocked.
+    /// Ignored signals are discarded
+    pub fn signal(&mut self, signal: usize) {
+        if signal >= NSIG || signal_ignored(signal, &self.sigactions[signal]) {
+            return;
+        }
+
+        self.signals |= 1 << signal;
+        if self.sigmask & 1 << signal == 0 && self.blocked && self.wake.is_some() {
+            self.blocked = false;
+            self.wake = None;
+        }
+    }
+
+    /// The pending signals that are not blocked
+    pub fn pending_signals(&self) -> usize {
+        self.signals & ! self.sigmask
+    }
+
+    /// Check if the context holds all of the given privileges
+    pub fn has_priv(&self, privs: usize) -> bool {
+        self.privs & privs == privs
+    }
+
+    /// Check if the scheme called `name` is in the namespace of the context
+    pub fn in_namespace(&self, name: &str) -> bool {
+        match self.namespace {
+            Some(ref namespace) => namespace.iter().any(|scheme| scheme == name),
+            None => true,
+        }
+    }
+
+    /// Panic if the kernel stack canary was overwritten
+    pub fn check_stack(&self) {
+        if self.kernel_stack > 0 {
+            let canary = unsafe { ptr::read(self.kernel_stack as *const usize) };
+            if canary != CONTEXT_STACK_CANARY {
+                panic!("kernel stack overflow in PID {}: {}: canary {:X} at {:X}",
+                       self.pid, self.name, canary, self.kernel_stack);
+            }
+        }
+    }
+
     pub unsafe fn push(&mut self, data: usize) {
         self.regs.sp -= mem::size_of::<usize>();
         ptr::write(self.regs.sp as *mut usize, data);
@@ -717,6 +1664,7 @@ impl Context {
     pub unsafe fn map(&mut self) {
         if let Some(ref mut stack) = self.stack {
             stack.map();
+            vdso::map();
         }
         for entry in (*self.memory.get()).iter_mut() {
             entry.map();
@@ -729,6 +1677,56 @@ impl Context {
         }
         if let Some(ref mut stack) = self.stack {
             stack.unmap();
+            vdso::unmap();
+        }
+    }
+
+    /// Free the memory and files of an exiting context, and wake whatever waits for it, as it
+    /// may stay a zombie for a while
+    pub unsafe fn release(&mut self) {
+        self.unmap();
+        self.stack = None;
+        self.memory = Arc::new(UnsafeCell::new(Vec::new()));
+        self.files = Arc::new(UnsafeCell::new(Vec::new()));
+
+        if self.itimer.take().is_some() {
+            ::env().timers.lock().remove(self.pid);
+        }
+
+        if let Some(vfork) = self.vfork.take() {
+            (*vfork).blocked = false;
+        }
+        if let Some(tracer) = self.tracer.take() {
+            tracer.exit();
+        }
+    }
+
+    /// Save the FPU registers of this context to its FX area, if they are loaded
+    pub unsafe fn fpu_save(&mut self) {
+        if fpu_owner().load(Ordering::SeqCst) == self as *mut Context as usize {
+            let cr0 = read_cr0();
+            asm!("clts" : : : "memory" : "intel", "volatile");
+            asm!("fxsave [$0]" : : "r"(self.fx) : "memory" : "intel", "volatile");
+            self.loadable = true;
+            write_cr0(cr0);
+        }
+    }
+
+    /// Leave the FPU registers loaded, trapping on their first use unless they belong to `next`
+    #[inline(always)]
+    unsafe fn fpu_switch(&mut self, next: &mut Context) {
+        let cr0 = read_cr0();
+
+        // The first context switched away from owns whatever the FPU holds
+        if fpu_owner().load(Ordering::SeqCst) == 0 && cr0 & CR0_TS == 0 {
+            fpu_owner().store(self as *mut Context as usize, Ordering::SeqCst);
+        }
+
+        fpu_current().store(next as *mut Context as usize, Ordering::SeqCst);
+        if fpu_owner().load(Ordering::SeqCst) == next as *mut Context as usize {
+            write_cr0(cr0 & !CR0_TS);
+        } else {
+            write_cr0(cr0 | CR0_TS);
         }
     }
 
@@ -739,13 +1737,7 @@ impl Context {
     pub unsafe fn switch_to(&mut self, next: &mut Context) {
         //asm!("xchg bx, bx" : : : "memory" : "intel", "volatile");
 
-        asm!("fxsave [$0]" : : "r"(self.fx) : "memory" : "intel", "volatile");
-        self.loadable = true;
-        if next.loadable {
-            asm!("fxrstor [$0]" : : "r"(next.fx) : "memory" : "intel", "volatile");
-        }else{
-            asm!("fninit" : : : "memory" : "intel", "volatile");
-        }
+        self.fpu_switch(next);
 
         asm!("pushfd ; pop $0" : "=r"(self.regs.flags) : : "memory" : "intel", "volatile");
         asm!("push $0 ; popfd" : : "r"(next.regs.flags) : "memory" : "intel", "volatile");
@@ -773,13 +1765,7 @@ impl Context {
     pub unsafe fn switch_to(&mut self, next: &mut Context) {
         //asm!("xchg bx, bx" : : : "memory" : "intel", "volatile");
 
-        asm!("fxsave [$0]" : : "r"(self.fx) : "memory" : "intel", "volatile");
-        self.loadable = true;
-        if next.loadable {
-            asm!("fxrstor [$0]" : : "r"(next.fx) : "memory" : "intel", "volatile");
-        }else{
-            asm!("fninit" : : : "memory" : "intel", "volatile");
-        }
+        self.fpu_switch(next);
 
         asm!("pushfq ; pop $0" : "=r"(self.regs.flags) : : "memory" : "intel", "volatile");
         asm!("push $0 ; popfq" : : "r"(next.regs.flags) : "memory" : "intel", "volatile");
@@ -809,11 +1795,22 @@ impl Context {
 
 impl Drop for Context {
     fn drop(&mut self) {
+        // Forget the FPU registers on every processor, the FX area is freed with the context
+        let ptr = self as *mut Context as usize;
+        for percpu in percpu::all().iter() {
+            percpu.fpu_owner.compare_and_swap(ptr, 0, Ordering::SeqCst);
+            percpu.fpu_current.compare_and_swap(ptr, 0, Ordering::SeqCst);
+        }
+        FPU_OWNER.compare_and_swap(ptr, 0, Ordering::SeqCst);
+        FPU_CURRENT.compare_and_swap(ptr, 0, Ordering::SeqCst);
         if let Some(vfork) = self.vfork.take() {
             unsafe { (*vfork).blocked = false; }
         }
+        if let Some(tracer) = self.tracer.take() {
+            tracer.exit();
+        }
         if self.kernel_stack > 0 {
-            unsafe { memory::unalloc(self.kernel_stack); }
+            unsafe { kernel_stack_unalloc(self.kernel_stack); }
         }
     }
 }
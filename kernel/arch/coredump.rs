@@ -0,0 +1,194 @@
+//! Core files of user contexts killed by a signal
+//!
+//! A core file is an ELF file with a note holding the registers of the context, followed by a
+//! loadable segment for each of its memory mappings, so that it can be opened with a debugger.
+
+use arch::elf::{ElfAddr, ElfHalf, ElfHeader, ElfOff, ElfSegment, ElfWord, ELF_CLASS, ELF_MACHINE};
+use arch::regs::Regs;
+
+use collections::Vec;
+
+use core::{mem, slice};
+
+use env::log::LogLevel;
+
+use fs::Url;
+
+use self::arch::prstatus;
+
+#[cfg(target_arch = "x86")]
+#[path="x86/coredump.rs"]
+mod arch;
+
+#[cfg(target_arch = "x86_64")]
+#[path="x86_64/coredump.rs"]
+mod arch;
+
+/// The object type of core files
+const ET_CORE: ElfHalf = 4;
+/// The type of loadable segments
+const PT_LOAD: ElfWord = 1;
+/// The type of note segments
+const PT_NOTE: ElfWord = 4;
+/// Segment flags
+const PF_X: ElfWord = 1;
+const PF_W: ElfWord = 2;
+const PF_R: ElfWord = 4;
+/// The note type of the process status
+const NT_PRSTATUS: u32 = 1;
+
+/// A memory mapping of the context being dumped
+struct Mapping {
+    virtual_address: usize,
+    physical_address: usize,
+    size: usize,
+    writeable: bool,
+}
+
+/// View a structure as bytes
+unsafe fn bytes<T>(value: &T) -> &[u8] {
+    slice::from_raw_parts(value as *const T as *const u8, mem::size_of::<T>())
+}
+
+/// Round up to a multiple of `align`
+fn round_up(value: usize, align: usize) -> usize {
+    (value + align - 1) / align * align
+}
+
+/// Write a core file named `core.<pid>` to the working directory of the current context, which is
+/// being killed by `signal`. `regs` are its user registers
+///
+/// Nothing is written if the file would be larger than `RLIMIT_CORE`.
+pub fn dump(regs: &Regs, signal: usize) {
+    let (path, limit, note, mappings) = {
+        let contexts = ::env().contexts.read();
+        let current = match contexts.current() {
+            Ok(current) => current,
+            Err(_) => return,
+        };
+
+        let mut mappings = Vec::new();
+        if let Some(ref stack) = current.stack {
+            mappings.push(Mapping {
+                virtual_address: stack.virtual_address,
+                physical_address: stack.physical_address,
+                size: stack.virtual_size,
+                writeable: stack.writeable,
+            });
+        }
+        for memory in unsafe { (*current.memory.get()).iter() } {
+            mappings.push(Mapping {
+                virtual_address: memory.virtual_address,
+                physical_address: memory.physical_address,
+                size: memory.virtual_size,
+                writeable: memory.writeable,
+            });
+        }
+
+        (current.canonicalize(&format!("core.{}", current.pid)),
+         current.core_limit.rlim_cur,
+         prstatus(regs, signal, current.pid, current.ppid, current.pgid),
+         mappings)
+    };
+
+    if limit == 0 {
+        return;
+    }
+
+    // The note name is padded to four bytes, as is the description
+    let note_name = b"CORE\0\0\0\0";
+    let note_header = [5, note.len() as u32, NT_PRSTATUS];
+    let note_len = mem::size_of_val(&note_header) + note_name.len() + round_up(note.len(), 4);
+
+    let ph_len = 1 + mappings.len();
+    let ph_off = mem::size_of::<ElfHeader>();
+    let note_off = ph_off + ph_len * mem::size_of::<ElfSegment>();
+    let data_off = round_up(note_off + note_len, 4096);
+    let total = mappings.iter().fold(data_off as u64, |total, mapping| total + mapping.size as u64);
+    if total > limit {
+        klogln!(LogLevel::Warning, "core: {} is larger than the limit: {} > {}", path, total, limit);
+        return;
+    }
+
+    let header = ElfHeader {
+        magic: [0x7F, b'E', b'L', b'F'],
+        class: ELF_CLASS,
+        endian: 1,
+        ver: 1,
+        abi: [0; 2],
+        pad: [0; 7],
+        _type: ET_CORE,
+        machine: ELF_MACHINE,
+        ver_2: 1,
+        entry: 0,
+        ph_off: ph_off as ElfOff,
+        sh_off: 0,
+        flags: 0,
+        h_len: mem::size_of::<ElfHeader>() as ElfHalf,
+        ph_ent_len: mem::size_of::<ElfSegment>() as ElfHalf,
+        ph_len: ph_len as ElfHalf,
+        sh_ent_len: 0,
+        sh_len: 0,
+        sh_str_index: 0,
+    };
+
+    let mut segments = Vec::new();
+    segments.push(ElfSegment {
+        _type: PT_NOTE,
+        flags: 0,
+        off: note_off as ElfOff,
+        vaddr: 0,
+        paddr: 0,
+        file_len: note_len as ElfOff,
+        mem_len: 0,
+        align: 4,
+    });
+    let mut off = data_off;
+    for mapping in mappings.iter() {
+        segments.push(ElfSegment {
+            _type: PT_LOAD,
+            flags: PF_R | if mapping.writeable { PF_W } else { PF_X },
+            off: off as ElfOff,
+            vaddr: mapping.virtual_address as ElfAddr,
+            paddr: 0,
+            file_len: mapping.size as ElfOff,
+            mem_len: mapping.size as ElfOff,
+            align: 4096,
+        });
+        off += mapping.size;
+    }
+
+    let mut resource = match Url::from_str(&path).and_then(|url| url.create()) {
+        Ok(resource) => resource,
+        Err(err) => {
+            klogln!(LogLevel::Warning, "core: failed to create {}: {}", path, err);
+            return;
+        }
+    };
+
+    let mut data = Vec::new();
+    unsafe {
+        data.extend_from_slice(bytes(&header));
+        for segment in segments.iter() {
+            data.extend_from_slice(bytes(segment));
+        }
+        data.extend_from_slice(bytes(&note_header));
+    }
+    data.extend_from_slice(note_name);
+    data.extend_from_slice(&note);
+    data.resize(data_off, 0);
+
+    let mut result = resource.write(&data);
+    for mapping in mappings.iter() {
+        if result.is_err() {
+            break;
+        }
+        let memory = unsafe { slice::from_raw_parts(mapping.physical_address as *const u8, mapping.size) };
+        result = resource.write(memory);
+    }
+
+    match result.and_then(|_| resource.sync()) {
+        Ok(_) => klogln!(LogLevel::Info, "core: wrote {}", path),
+        Err(err) => klogln!(LogLevel::Warning, "core: failed to write {}: {}", path, err),
+    }
+}
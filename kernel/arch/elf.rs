@@ -17,6 +17,68 @@ mod arch;
 #[path="x86_64/elf.rs"]
 mod arch;
 
+/// 32-bit executables, which run in compatibility mode
+#[cfg(target_arch = "x86_64")]
+#[path="x86/elf.rs"]
+pub mod compat;
+
+/// 64-bit objects
+const ELF_CLASS64: u8 = 2;
+/// Little endian data
+const ELF_DATA_LSB: u8 = 1;
+/// The object type of executables
+const ET_EXEC: u16 = 2;
+/// The object type of shared objects, which includes position independent executables
+const ET_DYN: u16 = 3;
+/// The type of loadable segments
+const PT_LOAD: u32 = 1;
+/// The type of the dynamic section segment
+const PT_DYNAMIC: u32 = 2;
+/// The type of the segment naming the dynamic linker
+const PT_INTERP: u32 = 3;
+
+/// The end of the dynamic section
+const DT_NULL: u64 = 0;
+/// A needed shared library
+const DT_NEEDED: u64 = 1;
+/// The address of the relocations with addends
+const DT_RELA: u64 = 7;
+/// The size of the relocations with addends
+const DT_RELASZ: u64 = 8;
+/// The size of a relocation with addend
+const DT_RELAENT: u64 = 9;
+/// The address of the relocations without addends
+const DT_REL: u64 = 17;
+/// The size of the relocations without addends
+const DT_RELSZ: u64 = 18;
+/// The size of a relocation without addend
+const DT_RELENT: u64 = 19;
+
+/// No relocation, the same for R_386_NONE and R_X86_64_NONE
+const R_NONE: u64 = 0;
+/// Add the base, the same for R_386_RELATIVE and R_X86_64_RELATIVE
+const R_RELATIVE: u64 = 8;
+
+/// The header fields that locate the program headers, the same for 32 and 64-bit executables
+struct ElfInfo {
+    _type: u16,
+    machine: u16,
+    expected_machine: u16,
+    entry: u64,
+    ph_off: u64,
+    ph_ent_len: u64,
+    ph_len: u64,
+    segment_len: u64,
+}
+
+/// A relative relocation of a position independent executable
+pub struct ElfRelocation {
+    /// The address of the word to relocate, relative to the base
+    pub vaddr: usize,
+    /// The addend, or `None` if it is the word being relocated
+    pub addend: Option<usize>,
+}
+
 /// An ELF executable
 pub struct Elf<'a> {
     pub data: &'a [u8],
@@ -29,11 +91,124 @@ impl<'a> Elf<'a> {
             Err(format!("Elf: Not enough data: {} < {}", data.len(), mem::size_of::<ElfHeader>()))
         } else if data.get_slice(..4) != b"\x7FELF" {
             Err(format!("Elf: Invalid magic: {:?} != {:?}", data.get_slice(..4), b"\x7FELF"))
-        } else if data.get(4) != Some(&ELF_CLASS) {
+        } else if data.get(4) != Some(&ELF_CLASS) && ! Elf::compat_class(data.get(4)) {
             Err(format!("Elf: Invalid architecture: {:?} != {:?}", data.get(4), Some(&ELF_CLASS)))
         } else {
-            Ok(Elf { data: data })
+            let elf = Elf { data: data };
+            try!(elf.validate());
+            Ok(elf)
+        }
+    }
+
+    unsafe fn info(&self) -> ElfInfo {
+        if self.compat() {
+            return self.compat_info();
+        }
+
+        let header = &*(self.data.as_ptr() as usize as *const ElfHeader);
+        ElfInfo {
+            _type: header._type,
+            machine: header.machine,
+            expected_machine: ELF_MACHINE,
+            entry: header.entry as u64,
+            ph_off: header.ph_off as u64,
+            ph_ent_len: header.ph_ent_len as u64,
+            ph_len: header.ph_len as u64,
+            segment_len: mem::size_of::<ElfSegment>() as u64,
+        }
+    }
+
+    #[cfg(target_arch = "x86")]
+    unsafe fn compat_info(&self) -> ElfInfo {
+        unreachable!()
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    unsafe fn compat_info(&self) -> ElfInfo {
+        let header = &*(self.data.as_ptr() as usize as *const compat::ElfHeader);
+        ElfInfo {
+            _type: header._type,
+            machine: header.machine,
+            expected_machine: compat::ELF_MACHINE,
+            entry: header.entry as u64,
+            ph_off: header.ph_off as u64,
+            ph_ent_len: header.ph_ent_len as u64,
+            ph_len: header.ph_len as u64,
+            segment_len: mem::size_of::<compat::ElfSegment>() as u64,
+        }
+    }
+
+    /// Check that the program headers and the segments they describe lie within the data, and that
+    /// the entry is in a segment, so that loading cannot read past the data
+    fn validate(&self) -> Result<(), String> {
+        let info = unsafe { self.info() };
+        let len = self.data.len() as u64;
+
+        if self.data.get(5) != Some(&ELF_DATA_LSB) {
+            return Err(format!("Elf: Invalid endianness: {:?}", self.data.get(5)));
+        }
+        if info._type != ET_EXEC && info._type != ET_DYN {
+            return Err(format!("Elf: Not an executable: {}", info._type));
+        }
+        if info.machine != info.expected_machine {
+            return Err(format!("Elf: Invalid machine: {} != {}", info.machine, info.expected_machine));
+        }
+        if info.ph_len == 0 {
+            return Err(format!("Elf: No program headers"));
+        }
+        if info.ph_ent_len < info.segment_len {
+            return Err(format!("Elf: Invalid program header size: {} < {}", info.ph_ent_len, info.segment_len));
         }
+        if info.ph_off.checked_add(info.ph_ent_len * info.ph_len).map_or(true, |end| end > len) {
+            return Err(format!("Elf: Program headers past end: {:X}, {} * {} > {:X}", info.ph_off, info.ph_ent_len, info.ph_len, len));
+        }
+
+        let mut entry_found = false;
+        for segment in unsafe { self.load_segment() }.iter() {
+            let off = segment.off as u64;
+            let file_len = segment.file_len as u64;
+            let vaddr = segment.vaddr as u64;
+            let mem_len = segment.mem_len as u64;
+            let align = segment.align as u64;
+
+            if off.checked_add(file_len).map_or(true, |end| end > len) {
+                return Err(format!("Elf: Segment past end: {:X}, {:X} > {:X}", off, file_len, len));
+            }
+            if file_len > mem_len {
+                return Err(format!("Elf: Segment file size larger than memory size: {:X} > {:X}", file_len, mem_len));
+            }
+            if vaddr.checked_add(mem_len).map_or(true, |end| end > usize::max_value() as u64) {
+                return Err(format!("Elf: Segment past end of memory: {:X}, {:X}", vaddr, mem_len));
+            }
+            if align > 1 && (! align.is_power_of_two() || vaddr % align != off % align) {
+                return Err(format!("Elf: Segment misaligned: {:X}, {:X}, {:X}", vaddr, off, align));
+            }
+
+            if info.entry >= vaddr && info.entry < vaddr + mem_len {
+                entry_found = true;
+            }
+        }
+
+        if ! entry_found {
+            return Err(format!("Elf: Entry not in a segment: {:X}", info.entry));
+        }
+
+        Ok(())
+    }
+
+    #[cfg(target_arch = "x86")]
+    fn compat_class(_class: Option<&u8>) -> bool {
+        false
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn compat_class(class: Option<&u8>) -> bool {
+        class == Some(&compat::ELF_CLASS)
+    }
+
+    /// Is this a 32-bit executable that must run in compatibility mode
+    pub fn compat(&self) -> bool {
+        Elf::compat_class(self.data.get(4))
     }
 
     /// Debug
@@ -288,6 +463,15 @@ impl<'a> Elf<'a> {
     }
 
     pub unsafe fn load_segment(&self) -> Vec<ElfSegment> {
+        self.segments(PT_LOAD)
+    }
+
+    /// Get the program headers of type `_type`
+    unsafe fn segments(&self, _type: ElfWord) -> Vec<ElfSegment> {
+        if self.compat() {
+            return self.compat_segments(_type);
+        }
+
         let mut segments = Vec::new();
 
         let header = &*(self.data.as_ptr() as usize as *const ElfHeader);
@@ -295,7 +479,7 @@ impl<'a> Elf<'a> {
         for i in 0..header.ph_len {
             let segment = ptr::read((self.data.as_ptr() as usize + header.ph_off as usize + i as usize * header.ph_ent_len as usize) as *const ElfSegment);
 
-            if segment._type == 1 {
+            if segment._type == _type {
                 segments.push(segment);
             }
         }
@@ -303,12 +487,181 @@ impl<'a> Elf<'a> {
         segments
     }
 
+    #[cfg(target_arch = "x86")]
+    unsafe fn compat_segments(&self, _type: ElfWord) -> Vec<ElfSegment> {
+        Vec::new()
+    }
+
+    /// Get the program headers of a 32-bit executable, widened to the native segment
+    #[cfg(target_arch = "x86_64")]
+    unsafe fn compat_segments(&self, _type: ElfWord) -> Vec<ElfSegment> {
+        let mut segments = Vec::new();
+
+        let header = &*(self.data.as_ptr() as usize as *const compat::ElfHeader);
+
+        for i in 0..header.ph_len {
+            let segment = ptr::read((self.data.as_ptr() as usize + header.ph_off as usize + i as usize * header.ph_ent_len as usize) as *const compat::ElfSegment);
+
+            if segment._type == _type as compat::ElfWord {
+                segments.push(ElfSegment {
+                    _type: segment._type,
+                    flags: segment.flags,
+                    off: segment.off as ElfOff,
+                    vaddr: segment.vaddr as ElfAddr,
+                    paddr: segment.paddr as ElfAddr,
+                    file_len: segment.file_len as ElfXword,
+                    mem_len: segment.mem_len as ElfXword,
+                    align: segment.align as ElfXword,
+                });
+            }
+        }
+
+        segments
+    }
+
+    /// Is this a position independent executable, which must be relocated to its base
+    pub fn dynamic(&self) -> bool {
+        unsafe { self.info() }._type == ET_DYN
+    }
+
+    /// Get the path of the dynamic linker, if the executable names one
+    pub unsafe fn interpreter(&self) -> Option<&'a str> {
+        for segment in self.segments(PT_INTERP).iter() {
+            let start = segment.off as usize;
+            let end = start.saturating_add(segment.file_len as usize);
+            if end <= self.data.len() {
+                if let Ok(path) = str::from_utf8(&self.data[start..end]) {
+                    return Some(path.trim_matches('\0'));
+                }
+            }
+        }
+        None
+    }
+
+    /// Get the address of the program headers once loaded, relative to the base, with their
+    /// entry size and count, if they are in a loadable segment
+    pub unsafe fn program_headers(&self) -> Option<(usize, usize, usize)> {
+        let info = self.info();
+        let end = info.ph_off + info.ph_ent_len * info.ph_len;
+        for segment in self.load_segment().iter() {
+            if info.ph_off >= segment.off as u64 && end <= segment.off as u64 + segment.file_len as u64 {
+                let vaddr = info.ph_off - segment.off as u64 + segment.vaddr as u64;
+                return Some((vaddr as usize, info.ph_ent_len as usize, info.ph_len as usize));
+            }
+        }
+        None
+    }
+
+    /// Read a 32 or 64-bit word of the data, if it is in bounds
+    fn word(&self, off: u64, wide: bool) -> Result<u64, String> {
+        let size = if wide { 8 } else { 4 };
+        let mut value = 0;
+        for i in (0..size).rev() {
+            match self.data.get((off + i) as usize) {
+                Some(&b) => value = value << 8 | b as u64,
+                None => return Err(format!("Elf: Read past end: {:X}", off + i)),
+            }
+        }
+        Ok(value)
+    }
+
+    /// Translate a virtual address to an offset in the data
+    unsafe fn offset(&self, vaddr: u64) -> Result<u64, String> {
+        for segment in self.load_segment().iter() {
+            if vaddr >= segment.vaddr as u64 && vaddr < segment.vaddr as u64 + segment.file_len as u64 {
+                return Ok(vaddr - segment.vaddr as u64 + segment.off as u64);
+            }
+        }
+        Err(format!("Elf: Address not in a segment: {:X}", vaddr))
+    }
+
+    /// Get the relocations of a position independent executable from its dynamic section
+    ///
+    /// Only relative relocations are supported, so the executable must not need shared libraries.
+    pub unsafe fn relocations(&self) -> Result<Vec<ElfRelocation>, String> {
+        let wide = self.data.get(4) == Some(&ELF_CLASS64);
+        let word = if wide { 8 } else { 4 };
+
+        // Address, size, and entry size of each kind of relocation table
+        let mut rel = (0, 0, word * 2);
+        let mut rela = (0, 0, word * 3);
+
+        for segment in self.segments(PT_DYNAMIC).iter() {
+            let mut off = segment.off as u64;
+            let end = off.saturating_add(segment.file_len as u64);
+            while off + word * 2 <= end {
+                let tag = try!(self.word(off, wide));
+                let value = try!(self.word(off + word, wide));
+                match tag {
+                    DT_NULL => break,
+                    DT_NEEDED => return Err(format!("Elf: Shared libraries need a dynamic linker")),
+                    DT_REL => rel.0 = value,
+                    DT_RELSZ => rel.1 = value,
+                    DT_RELENT => rel.2 = value,
+                    DT_RELA => rela.0 = value,
+                    DT_RELASZ => rela.1 = value,
+                    DT_RELAENT => rela.2 = value,
+                    _ => (),
+                }
+                off += word * 2;
+            }
+        }
+
+        let mut relocations = Vec::new();
+        for &((vaddr, size, ent_len), addend) in [(rel, false), (rela, true)].iter() {
+            if size == 0 {
+                continue;
+            }
+            let min_len = if addend { word * 3 } else { word * 2 };
+            if ent_len < min_len {
+                return Err(format!("Elf: Invalid relocation size: {} < {}", ent_len, min_len));
+            }
+
+            let mut off = try!(self.offset(vaddr));
+            let end = off.saturating_add(size);
+            while off + ent_len <= end {
+                let info = try!(self.word(off + word, wide));
+                let _type = if wide { info & 0xFFFFFFFF } else { info & 0xFF };
+                match _type {
+                    R_NONE => (),
+                    R_RELATIVE => relocations.push(ElfRelocation {
+                        vaddr: try!(self.word(off, wide)) as usize,
+                        addend: if addend {
+                            Some(try!(self.word(off + word * 2, wide)) as usize)
+                        } else {
+                            None
+                        },
+                    }),
+                    _ => return Err(format!("Elf: Unsupported relocation: {}", _type)),
+                }
+                off += ent_len;
+            }
+        }
+
+        Ok(relocations)
+    }
+
     /// Get the entry field of the header
     pub unsafe fn entry(&self) -> usize {
+        if self.compat() {
+            return self.compat_entry();
+        }
+
         let header = &*(self.data.as_ptr() as usize as *const ElfHeader);
         header.entry as usize
     }
 
+    #[cfg(target_arch = "x86")]
+    unsafe fn compat_entry(&self) -> usize {
+        0
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    unsafe fn compat_entry(&self) -> usize {
+        let header = &*(self.data.as_ptr() as usize as *const compat::ElfHeader);
+        header.entry as usize
+    }
+
     /// ELF symbol
     pub unsafe fn symbol(&self, name: &str) -> usize {
         let header = &*(self.data.as_ptr() as usize as *const ElfHeader);
@@ -0,0 +1,190 @@
+//! Reports for CPU exceptions
+//!
+//! A fault in a user context prints and logs the faulting address, error code, registers and
+//! the memory map of the context, after which only that context is killed. A fault in kernel
+//! mode is a kernel bug and panics.
+
+use arch::paging::Page;
+use arch::regs::Regs;
+
+use collections::string::String;
+
+use common::backtrace;
+
+use core::ptr;
+
+use env::log::LogLevel;
+
+use system::syscall::{SIGBUS, SIGFPE, SIGILL, SIGSEGV, SIGTRAP};
+
+/// Page fault error code: the page was present, so this was a protection violation
+const PF_PRESENT: usize = 1;
+/// Page fault error code: the access was a write
+const PF_WRITE: usize = 1 << 1;
+/// Page fault error code: the access came from ring 3
+const PF_USER: usize = 1 << 2;
+/// Page fault error code: a reserved bit was set in a page table entry
+const PF_RESERVED: usize = 1 << 3;
+/// Page fault error code: the access was an instruction fetch
+const PF_FETCH: usize = 1 << 4;
+
+/// Page fault vector
+const INT_PAGE_FAULT: usize = 0xE;
+
+/// Was the interrupted code running in ring 3
+pub fn user_mode(regs: &Regs) -> bool {
+    regs.cs & 3 == 3
+}
+
+/// The signal that kills a user context for an exception, as on Linux
+pub fn signal(interrupt: usize) -> usize {
+    match interrupt {
+        0x0 | 0x10 | 0x13 => SIGFPE,
+        0x1 | 0x3 => SIGTRAP,
+        0x6 => SIGILL,
+        0xC | 0x11 => SIGBUS,
+        _ => SIGSEGV,
+    }
+}
+
+/// Handle a write fault on memory shared after fork by copying it, fixing up the frame so that
+/// the write is retried. Returns false for any other fault
+///
+/// Only faults from user mode are handled, as removing the error code from the frame relies on
+/// the CPU having pushed the stack pointer and segment after it.
+pub unsafe fn copy_on_write(regs: &mut Regs) -> bool {
+    // Before the frame is fixed up, the error code is in the place of ip
+    let error = regs.ip;
+    if error & (PF_PRESENT | PF_WRITE | PF_USER) != PF_PRESENT | PF_WRITE | PF_USER {
+        return false;
+    }
+
+    let cr2: usize;
+    asm!("mov $0, cr2" : "=r"(cr2) : : : "intel", "volatile");
+
+    let copied = match ::env().contexts.write().current_mut() {
+        Ok(mut current) => current.copy_on_write(cr2),
+        Err(_) => false,
+    };
+
+    if copied {
+        // The stack segment is just past the end of the frame
+        let ss = ptr::read((&*regs as *const Regs).offset(1) as *const usize);
+        regs.ip = regs.cs;
+        regs.cs = regs.flags;
+        regs.flags = regs.sp;
+        regs.sp = regs.ss;
+        regs.ss = ss;
+    }
+
+    copied
+}
+
+/// Describe a page fault error code
+fn page_fault_flags(error: usize) -> String {
+    let mut string = String::new();
+    string.push_str(if error & PF_USER == PF_USER { "user " } else { "kernel " });
+    string.push_str(if error & PF_FETCH == PF_FETCH {
+        "fetch"
+    } else if error & PF_WRITE == PF_WRITE {
+        "write"
+    } else {
+        "read"
+    });
+    string.push_str(if error & PF_PRESENT == PF_PRESENT { ", protection violation" } else { ", not present" });
+    if error & PF_RESERVED == PF_RESERVED {
+        string.push_str(", reserved bit set");
+    }
+    string
+}
+
+/// Dump the words around `sp`, skipping any that are not mapped
+unsafe fn dump_stack(sp: usize) {
+    let sp = sp as *const u32;
+    for y in -15..16 {
+        klog!(LogLevel::Error, "    {:>3}:", y * 8 * 4);
+        for x in 0..8 {
+            let word = sp.offset(-(x + y * 8));
+            if Page::new(word as usize).is_present() {
+                klog!(LogLevel::Error, " {:08X}", ptr::read(word));
+            } else {
+                klog!(LogLevel::Error, " ????????");
+            }
+        }
+        klog!(LogLevel::Error, "\n");
+    }
+}
+
+/// Print and log everything that is known about an exception
+///
+/// `error` is the error code pushed by the CPU, for the exceptions that have one.
+pub unsafe fn report(interrupt: usize, name: &str, regs: &Regs, error: Option<usize>) {
+    let user = user_mode(regs);
+
+    let cr0: usize;
+    let cr2: usize;
+    let cr3: usize;
+    let cr4: usize;
+    asm!("mov $0, cr0" : "=r"(cr0) : : : "intel", "volatile");
+    asm!("mov $0, cr2" : "=r"(cr2) : : : "intel", "volatile");
+    asm!("mov $0, cr3" : "=r"(cr3) : : : "intel", "volatile");
+    asm!("mov $0, cr4" : "=r"(cr4) : : : "intel", "volatile");
+
+    {
+        let contexts = ::env().contexts.read();
+        if let Ok(context) = contexts.current() {
+            klogln!(LogLevel::Error, "PID {}: {}", context.pid, context.name);
+        }
+    }
+
+    klogln!(LogLevel::Error, "  INT {:X}: {} in {} mode", interrupt, name, if user { "user" } else { "kernel" });
+    if let Some(error) = error {
+        if interrupt == INT_PAGE_FAULT {
+            klogln!(LogLevel::Error, "    ADDR: {:08X}    ERR: {:08X} ({})", cr2, error, page_fault_flags(error));
+        } else {
+            klogln!(LogLevel::Error, "    ERR: {:08X}", error);
+        }
+    }
+    klogln!(LogLevel::Error, "    CS:  {:08X}    IP:  {:08X}    FLG: {:08X}", regs.cs, regs.ip, regs.flags);
+    if user {
+        klogln!(LogLevel::Error, "    SS:  {:08X}    SP:  {:08X}    BP:  {:08X}", regs.ss, regs.sp, regs.bp);
+    } else {
+        // The CPU does not push SS and SP without a privilege change
+        klogln!(LogLevel::Error, "    BP:  {:08X}", regs.bp);
+    }
+    klogln!(LogLevel::Error, "    AX:  {:08X}    BX:  {:08X}    CX:  {:08X}    DX:  {:08X}", regs.ax, regs.bx, regs.cx, regs.dx);
+    klogln!(LogLevel::Error, "    DI:  {:08X}    SI:  {:08X}", regs.di, regs.si);
+    klogln!(LogLevel::Error, "    CR0: {:08X}    CR2: {:08X}    CR3: {:08X}    CR4: {:08X}", cr0, cr2, cr3, cr4);
+
+    let mut fsw: usize = 0;
+    let mut fcw: usize = 0;
+    asm!("fnstsw $0" : "=*m"(&mut fsw) : : : "intel", "volatile");
+    asm!("fnstcw $0" : "=*m"(&mut fcw) : : : "intel", "volatile");
+    klogln!(LogLevel::Error, "    FSW: {:08X}    FCW: {:08X}", fsw, fcw);
+
+    {
+        let contexts = ::env().contexts.read();
+        if let Ok(context) = contexts.current() {
+            klogln!(LogLevel::Error, "  Memory map:");
+            if let Some(ref stack) = context.stack {
+                klogln!(LogLevel::Error, "    {:08X}-{:08X} {} stack",
+                        stack.virtual_address,
+                        stack.virtual_address + stack.virtual_size,
+                        if stack.writeable { "rw" } else { "r-" });
+            }
+            for memory in (*context.memory.get()).iter() {
+                klogln!(LogLevel::Error, "    {:08X}-{:08X} {} -> {:08X}",
+                        memory.virtual_address,
+                        memory.virtual_address + memory.virtual_size,
+                        if memory.writeable { "rw" } else { "r-" },
+                        memory.physical_address);
+            }
+        }
+    }
+
+    backtrace::trace_from(regs.ip, regs.bp);
+
+    if user {
+        dump_stack(regs.sp);
+    }
+}
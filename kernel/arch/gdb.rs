@@ -0,0 +1,402 @@
+//! A GDB remote serial protocol stub on COM1
+//!
+//! The stub is entered on a breakpoint or single step in the kernel once a debugger has attached,
+//! on a panic if one has attached or `gdb` is on the command line, or on the next timer tick after
+//! Right Alt + F12 is pressed. While it runs, interrupts are disabled and the serial port is
+//! polled.
+
+use arch::paging::Page;
+use arch::regs::Regs;
+
+use collections::vec::Vec;
+
+use core::ptr;
+use core::sync::atomic::{AtomicBool, ATOMIC_BOOL_INIT, Ordering};
+
+use drivers::io::{Io, Pio};
+
+/// The serial port used by the stub
+const GDB_PORT: u16 = 0x3F8;
+
+/// The number of software breakpoints that can be set
+const GDB_BREAKPOINTS: usize = 32;
+
+/// The trap flag, enabling single step
+const FLAG_TF: usize = 1 << 8;
+
+/// Set once a debugger has attached, after which kernel breakpoints enter the stub
+pub static GDB_ACTIVE: AtomicBool = ATOMIC_BOOL_INIT;
+
+/// Set by the magic key, the stub is entered on the next timer tick
+pub static GDB_REQUEST: AtomicBool = ATOMIC_BOOL_INIT;
+
+/// Software breakpoints, as address and original byte. An address of 0 is unused
+static mut BREAKPOINTS: [(usize, u8); GDB_BREAKPOINTS] = [(0, 0); GDB_BREAKPOINTS];
+
+/// Why the stub was entered
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum GdbStop {
+    /// An int3 was hit
+    Breakpoint,
+    /// A single step completed
+    Step,
+    /// The magic key was pressed
+    Interrupt,
+    /// The kernel panicked
+    Panic,
+}
+
+impl GdbStop {
+    fn signal(&self) -> u8 {
+        match *self {
+            GdbStop::Breakpoint | GdbStop::Step => 5, // SIGTRAP
+            GdbStop::Interrupt => 2, // SIGINT
+            GdbStop::Panic => 6, // SIGABRT
+        }
+    }
+}
+
+/// Check if a trap from `regs` should be handled by the stub
+pub fn handles(regs: &Regs) -> bool {
+    GDB_ACTIVE.load(Ordering::SeqCst) && regs.cs & 3 == 0
+}
+
+fn reg(value: &mut usize, size: usize) -> (*mut usize, usize) {
+    (value as *mut usize, size)
+}
+
+/// The registers in the order of the GDB `g` packet, as a pointer and a size in bytes. Registers
+/// that are not saved have a null pointer
+#[cfg(target_arch = "x86")]
+fn registers(regs: &mut Regs) -> [(*mut usize, usize); 16] {
+    let none = 0 as *mut usize;
+    [reg(&mut regs.ax, 4), reg(&mut regs.cx, 4), reg(&mut regs.dx, 4), reg(&mut regs.bx, 4),
+     reg(&mut regs.sp, 4), reg(&mut regs.bp, 4), reg(&mut regs.si, 4), reg(&mut regs.di, 4),
+     reg(&mut regs.ip, 4), reg(&mut regs.flags, 4), reg(&mut regs.cs, 4), reg(&mut regs.ss, 4),
+     (none, 4), (none, 4), (none, 4), (none, 4)]
+}
+
+#[cfg(target_arch = "x86_64")]
+fn registers(regs: &mut Regs) -> [(*mut usize, usize); 24] {
+    let none = 0 as *mut usize;
+    [reg(&mut regs.ax, 8), reg(&mut regs.bx, 8), reg(&mut regs.cx, 8), reg(&mut regs.dx, 8),
+     reg(&mut regs.si, 8), reg(&mut regs.di, 8), reg(&mut regs.bp, 8), reg(&mut regs.sp, 8),
+     reg(&mut regs.r8, 8), reg(&mut regs.r9, 8), reg(&mut regs.r10, 8), reg(&mut regs.r11, 8),
+     reg(&mut regs.r12, 8), reg(&mut regs.r13, 8), reg(&mut regs.r14, 8), reg(&mut regs.r15, 8),
+     reg(&mut regs.ip, 8), reg(&mut regs.flags, 4), reg(&mut regs.cs, 4), reg(&mut regs.ss, 4),
+     (none, 4), (none, 4), (none, 4), (none, 4)]
+}
+
+fn getc() -> u8 {
+    let status = Pio::<u8>::new(GDB_PORT + 5);
+    while ! status.readf(1) {}
+    Pio::<u8>::new(GDB_PORT).read()
+}
+
+fn putc(byte: u8) {
+    let status = Pio::<u8>::new(GDB_PORT + 5);
+    while ! status.readf(0x20) {}
+    Pio::<u8>::new(GDB_PORT).write(byte);
+}
+
+fn hex_digit(value: u8) -> u8 {
+    b"0123456789abcdef"[(value & 0xF) as usize]
+}
+
+fn hex_value(digit: u8) -> Option<u8> {
+    match digit {
+        b'0' ... b'9' => Some(digit - b'0'),
+        b'a' ... b'f' => Some(digit - b'a' + 10),
+        b'A' ... b'F' => Some(digit - b'A' + 10),
+        _ => None,
+    }
+}
+
+fn push_hex(packet: &mut Vec<u8>, byte: u8) {
+    packet.push(hex_digit(byte >> 4));
+    packet.push(hex_digit(byte));
+}
+
+/// Parse a hexadecimal number, returning it and the rest of the input
+fn parse_hex(data: &[u8]) -> (usize, &[u8]) {
+    let mut value = 0;
+    let mut i = 0;
+    while i < data.len() {
+        if let Some(digit) = hex_value(data[i]) {
+            value = value << 4 | digit as usize;
+            i += 1;
+        } else {
+            break;
+        }
+    }
+    (value, &data[i..])
+}
+
+/// The input after the first `n` bytes, or nothing
+fn after(data: &[u8], n: usize) -> &[u8] {
+    if data.len() > n {
+        &data[n..]
+    } else {
+        &[]
+    }
+}
+
+/// Parse hex encoded bytes
+fn parse_bytes(data: &[u8]) -> Vec<u8> {
+    data.chunks(2).filter_map(|pair| {
+        if pair.len() == 2 {
+            hex_value(pair[0]).and_then(|high| hex_value(pair[1]).map(|low| high << 4 | low))
+        } else {
+            None
+        }
+    }).collect()
+}
+
+/// Receive a packet, acknowledging it if the checksum matches
+fn receive() -> Vec<u8> {
+    loop {
+        while getc() != b'$' {}
+
+        let mut data = Vec::new();
+        let mut sum: u8 = 0;
+        loop {
+            let byte = getc();
+            if byte == b'#' {
+                break;
+            }
+            sum = sum.wrapping_add(byte);
+            data.push(byte);
+        }
+
+        let high = hex_value(getc()).unwrap_or(0);
+        let low = hex_value(getc()).unwrap_or(0);
+        if high << 4 | low == sum {
+            putc(b'+');
+            return data;
+        } else {
+            putc(b'-');
+        }
+    }
+}
+
+/// Send a packet, retrying until it is acknowledged
+fn send(data: &[u8]) {
+    let sum = data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    loop {
+        putc(b'$');
+        for byte in data.iter() {
+            putc(*byte);
+        }
+        putc(b'#');
+        putc(hex_digit(sum >> 4));
+        putc(hex_digit(sum));
+
+        match getc() {
+            b'+' => return,
+            // A new packet instead of an acknowledgement, the debugger has given up on this one
+            b'$' => return,
+            _ => (),
+        }
+    }
+}
+
+/// Check that a range of memory is mapped
+fn mapped(address: usize, len: usize) -> bool {
+    if len == 0 {
+        return true;
+    }
+
+    match address.checked_add(len - 1) {
+        Some(end) => {
+            let mut page = address & !0xFFF;
+            while page <= end {
+                if ! Page::new(page).is_present() {
+                    return false;
+                }
+                match page.checked_add(4096) {
+                    Some(next) => page = next,
+                    None => break,
+                }
+            }
+            true
+        },
+        None => false,
+    }
+}
+
+unsafe fn insert_breakpoint(address: usize) -> bool {
+    if address == 0 {
+        return false;
+    }
+
+    for breakpoint in BREAKPOINTS.iter() {
+        if breakpoint.0 == address {
+            return true;
+        }
+    }
+
+    if ! mapped(address, 1) {
+        return false;
+    }
+
+    for breakpoint in BREAKPOINTS.iter_mut() {
+        if breakpoint.0 == 0 {
+            *breakpoint = (address, ptr::read(address as *const u8));
+            ptr::write(address as *mut u8, 0xCC);
+            return true;
+        }
+    }
+
+    false
+}
+
+unsafe fn remove_breakpoint(address: usize) -> bool {
+    for breakpoint in BREAKPOINTS.iter_mut() {
+        if breakpoint.0 == address && address != 0 {
+            ptr::write(address as *mut u8, breakpoint.1);
+            *breakpoint = (0, 0);
+            return true;
+        }
+    }
+
+    false
+}
+
+/// Run the stub until the debugger continues, steps or detaches
+pub unsafe fn enter(regs: &mut Regs, stop: GdbStop) {
+    GDB_ACTIVE.store(true, Ordering::SeqCst);
+    GDB_REQUEST.store(false, Ordering::SeqCst);
+
+    regs.flags &= !FLAG_TF;
+
+    // The trap leaves the instruction pointer after the int3
+    if stop == GdbStop::Breakpoint {
+        for breakpoint in BREAKPOINTS.iter() {
+            if breakpoint.0 != 0 && breakpoint.0 == regs.ip.wrapping_sub(1) {
+                regs.ip -= 1;
+                break;
+            }
+        }
+    }
+
+    let mut reply = Vec::new();
+    reply.push(b'S');
+    push_hex(&mut reply, stop.signal());
+    send(&reply);
+
+    loop {
+        let packet = receive();
+        let mut reply = Vec::new();
+
+        match packet.first().cloned() {
+            Some(b'?') => {
+                reply.push(b'S');
+                push_hex(&mut reply, stop.signal());
+            },
+            Some(b'g') => {
+                for &(reg, size) in registers(regs).iter() {
+                    let value = if reg.is_null() {
+                        None
+                    } else {
+                        Some(ptr::read(reg))
+                    };
+
+                    for i in 0..size {
+                        match value {
+                            Some(value) => push_hex(&mut reply, (value >> (i * 8)) as u8),
+                            None => reply.extend_from_slice(b"xx"),
+                        }
+                    }
+                }
+            },
+            Some(b'G') => {
+                let bytes = parse_bytes(&packet[1..]);
+                let mut offset = 0;
+                for &(reg, size) in registers(regs).iter() {
+                    if offset + size > bytes.len() {
+                        break;
+                    }
+
+                    if ! reg.is_null() {
+                        let mut value = 0;
+                        for i in 0..size {
+                            value |= (bytes[offset + i] as usize) << (i * 8);
+                        }
+                        ptr::write(reg, value);
+                    }
+                    offset += size;
+                }
+                reply.extend_from_slice(b"OK");
+            },
+            Some(b'm') => {
+                let (address, rest) = parse_hex(&packet[1..]);
+                let (len, _) = parse_hex(after(rest, 1));
+                if mapped(address, len) {
+                    for i in 0..len {
+                        push_hex(&mut reply, ptr::read((address + i) as *const u8));
+                    }
+                } else {
+                    reply.extend_from_slice(b"E01");
+                }
+            },
+            Some(b'M') => {
+                let (address, rest) = parse_hex(&packet[1..]);
+                let (len, rest) = parse_hex(after(rest, 1));
+                let bytes = parse_bytes(after(rest, 1));
+                if bytes.len() == len && mapped(address, len) {
+                    for (i, byte) in bytes.iter().enumerate() {
+                        ptr::write((address + i) as *mut u8, *byte);
+                    }
+                    reply.extend_from_slice(b"OK");
+                } else {
+                    reply.extend_from_slice(b"E01");
+                }
+            },
+            Some(b'Z') | Some(b'z') => {
+                // Only software breakpoints, Z0,addr,kind
+                if packet.get(1) == Some(&b'0') {
+                    let (address, _) = parse_hex(after(&packet, 3));
+                    let ok = if packet[0] == b'Z' {
+                        insert_breakpoint(address)
+                    } else {
+                        remove_breakpoint(address)
+                    };
+                    if ok {
+                        reply.extend_from_slice(b"OK");
+                    } else {
+                        reply.extend_from_slice(b"E01");
+                    }
+                }
+            },
+            Some(b'c') => {
+                if packet.len() > 1 {
+                    regs.ip = parse_hex(&packet[1..]).0;
+                }
+                return;
+            },
+            Some(b's') => {
+                if packet.len() > 1 {
+                    regs.ip = parse_hex(&packet[1..]).0;
+                }
+                regs.flags |= FLAG_TF;
+                return;
+            },
+            Some(b'D') | Some(b'k') => {
+                for breakpoint in BREAKPOINTS.iter_mut() {
+                    if breakpoint.0 != 0 {
+                        ptr::write(breakpoint.0 as *mut u8, breakpoint.1);
+                        *breakpoint = (0, 0);
+                    }
+                }
+                GDB_ACTIVE.store(false, Ordering::SeqCst);
+                if packet[0] == b'D' {
+                    send(b"OK");
+                }
+                return;
+            },
+            _ => (),
+        }
+
+        send(&reply);
+    }
+}
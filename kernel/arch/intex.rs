@@ -1,10 +1,28 @@
+#[cfg(debug)]
+use arch::lockdep;
+
+use arch::cpu;
+use arch::percpu::{self, MAX_CPUS};
+
+use collections::vec::Vec;
+
 use core::cell::UnsafeCell;
 use core::ops::{Deref, DerefMut, Drop};
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 pub static mut intex_count: usize = 0;
 
 /// An Intex, interrupt exclusion during value usage
+///
+/// Interrupts are disabled while it is locked, and other processors spin until it is unlocked.
+/// The processor holding it may lock it again, as an interrupt handler or a context switched to
+/// while a guard is held may, so it excludes processors rather than contexts. A guard must be
+/// dropped on the processor that took it.
 pub struct Intex<T: ?Sized> {
+    /// The number of the processor holding the lock plus one, or 0 if it is free
+    owner: AtomicUsize,
+    /// The number of guards the owner holds, only changed by the owner
+    depth: UnsafeCell<usize>,
     value: UnsafeCell<T>,
 }
 
@@ -17,14 +35,19 @@ impl Intex<()> {
 impl<T> Intex<T> {
     /// Create a new Intex with value `value`.
     pub fn new(value: T) -> Self {
-        Intex { value: UnsafeCell::new(value) }
+        Intex {
+            owner: AtomicUsize::new(0),
+            depth: UnsafeCell::new(0),
+            value: UnsafeCell::new(value),
+        }
     }
 }
 
 impl<T: ?Sized> Intex<T> {
-    /// Lock the Intex
+    /// Lock the Intex, spinning while another processor holds it
+    #[cfg_attr(debug, inline(never))]
     pub fn lock(&self) -> IntexGuard<T> {
-        IntexGuard::new(&self.value)
+        IntexGuard::new(&self.owner, &self.depth, &self.value)
     }
 }
 
@@ -35,14 +58,60 @@ unsafe impl<T: ?Sized + Send> Sync for Intex<T> { }
 /// A Intex guard (returned by .lock())
 pub struct IntexGuard<'a, T: ?Sized + 'a> {
     inner: StaticIntexGuard,
+    owner: &'a AtomicUsize,
+    depth: &'a UnsafeCell<usize>,
     data: &'a UnsafeCell<T>,
 }
 
 impl<'intex, T: ?Sized> IntexGuard<'intex, T> {
-    fn new(data: &'intex UnsafeCell<T>) -> Self {
-        IntexGuard {
-            inner: StaticIntexGuard::new(),
+    #[cfg_attr(debug, inline(always))]
+    fn new(owner: &'intex AtomicUsize, depth: &'intex UnsafeCell<usize>, data: &'intex UnsafeCell<T>) -> Self {
+        let inner = StaticIntexGuard::new();
+
+        let percpu = percpu::get();
+        let cpu = percpu.as_ref().map_or(0, |percpu| percpu.cpu_id) + 1;
+        if owner.load(Ordering::SeqCst) != cpu {
+            while owner.compare_and_swap(0, cpu, Ordering::SeqCst) != 0 {
+                unsafe { cpu::pause(); }
+            }
+        }
+        unsafe { *depth.get() += 1; }
+        if let Some(percpu) = percpu {
+            percpu.locks += 1;
+        }
+
+        let guard = IntexGuard {
+            inner: inner,
+            owner: owner,
+            depth: depth,
             data: data,
+        };
+
+        #[cfg(debug)]
+        unsafe { lockdep::acquire(guard.id()) };
+
+        guard
+    }
+
+    /// An identifier for the locked Intex
+    fn id(&self) -> usize {
+        self.data as *const UnsafeCell<T> as *const u8 as usize
+    }
+}
+
+impl<'intex, T: ?Sized> Drop for IntexGuard<'intex, T> {
+    fn drop(&mut self) {
+        #[cfg(debug)]
+        unsafe { lockdep::release(self.id()) };
+
+        if let Some(percpu) = percpu::get() {
+            percpu.locks -= 1;
+        }
+        unsafe {
+            *self.depth.get() -= 1;
+            if *self.depth.get() == 0 {
+                self.owner.store(0, Ordering::SeqCst);
+            }
         }
     }
 }
@@ -61,13 +130,195 @@ impl<'intex, T: ?Sized> DerefMut for IntexGuard<'intex, T> {
     }
 }
 
+/// An IntexRw, interrupt exclusion during writes with shared reads
+///
+/// Interrupts are disabled while it is locked, as with an `Intex`. Any number of processors may
+/// read at once, and a processor that writes spins until the others have stopped reading. The
+/// processor that writes may read or write again, and one that reads may read again, but must
+/// drop its read guards before it writes: two processors that both read and then write would each
+/// wait for the other to stop reading. Neither guard should be held while the context blocks, as
+/// other processors spin until it is dropped.
+pub struct IntexRw<T: ?Sized> {
+    /// The number of the processor writing plus one, or 0 if none is
+    writer: AtomicUsize,
+    /// The number of write guards the writer holds, only changed by the writer
+    depth: UnsafeCell<usize>,
+    /// The number of read guards held on each processor
+    readers: Vec<AtomicUsize>,
+    value: UnsafeCell<T>,
+}
+
+impl<T> IntexRw<T> {
+    /// Create a new IntexRw with value `value`.
+    pub fn new(value: T) -> Self {
+        IntexRw {
+            writer: AtomicUsize::new(0),
+            depth: UnsafeCell::new(0),
+            readers: (0..MAX_CPUS).map(|_| AtomicUsize::new(0)).collect(),
+            value: UnsafeCell::new(value),
+        }
+    }
+}
+
+impl<T: ?Sized> IntexRw<T> {
+    /// Lock the IntexRw to read, spinning while another processor writes
+    #[cfg_attr(debug, inline(never))]
+    pub fn read(&self) -> IntexReadGuard<T> {
+        let inner = StaticIntexGuard::new();
+
+        let percpu = percpu::get();
+        let cpu = percpu.as_ref().map_or(0, |percpu| percpu.cpu_id);
+        let readers = &self.readers[cpu];
+        // A processor that already holds a guard is not kept waiting, the writer waits for it
+        if self.writer.load(Ordering::SeqCst) == cpu + 1 || readers.load(Ordering::SeqCst) > 0 {
+            readers.fetch_add(1, Ordering::SeqCst);
+        } else {
+            loop {
+                while self.writer.load(Ordering::SeqCst) != 0 {
+                    unsafe { cpu::pause(); }
+                }
+                readers.fetch_add(1, Ordering::SeqCst);
+                if self.writer.load(Ordering::SeqCst) == 0 {
+                    break;
+                }
+                readers.fetch_sub(1, Ordering::SeqCst);
+            }
+        }
+        if let Some(percpu) = percpu {
+            percpu.locks += 1;
+        }
+
+        let guard = IntexReadGuard {
+            inner: inner,
+            readers: readers,
+            data: &self.value,
+        };
+
+        #[cfg(debug)]
+        unsafe { lockdep::acquire(guard.data as *const UnsafeCell<T> as *const u8 as usize) };
+
+        guard
+    }
+
+    /// Lock the IntexRw to write, spinning while another processor reads or writes
+    #[cfg_attr(debug, inline(never))]
+    pub fn write(&self) -> IntexWriteGuard<T> {
+        let inner = StaticIntexGuard::new();
+
+        let percpu = percpu::get();
+        let cpu = percpu.as_ref().map_or(0, |percpu| percpu.cpu_id);
+        if self.writer.load(Ordering::SeqCst) != cpu + 1 {
+            debug_assert!(self.readers[cpu].load(Ordering::SeqCst) == 0, "IntexRw: write while reading");
+            loop {
+                while self.writer.compare_and_swap(0, cpu + 1, Ordering::SeqCst) != 0 {
+                    unsafe { cpu::pause(); }
+                }
+                let reading = self.readers.iter().enumerate().any(|(i, readers)| {
+                    i != cpu && readers.load(Ordering::SeqCst) != 0
+                });
+                if ! reading {
+                    break;
+                }
+                self.writer.store(0, Ordering::SeqCst);
+                unsafe { cpu::pause(); }
+            }
+        }
+        unsafe { *self.depth.get() += 1; }
+        if let Some(percpu) = percpu {
+            percpu.locks += 1;
+        }
+
+        let guard = IntexWriteGuard {
+            inner: inner,
+            writer: &self.writer,
+            depth: &self.depth,
+            data: &self.value,
+        };
+
+        #[cfg(debug)]
+        unsafe { lockdep::acquire(guard.data as *const UnsafeCell<T> as *const u8 as usize) };
+
+        guard
+    }
+}
+
+unsafe impl<T: ?Sized + Send> Send for IntexRw<T> { }
+
+unsafe impl<T: ?Sized + Send> Sync for IntexRw<T> { }
+
+/// An IntexRw guard for reading (returned by .read())
+pub struct IntexReadGuard<'a, T: ?Sized + 'a> {
+    inner: StaticIntexGuard,
+    readers: &'a AtomicUsize,
+    data: &'a UnsafeCell<T>,
+}
+
+impl<'intex, T: ?Sized> Drop for IntexReadGuard<'intex, T> {
+    fn drop(&mut self) {
+        #[cfg(debug)]
+        unsafe { lockdep::release(self.data as *const UnsafeCell<T> as *const u8 as usize) };
+
+        if let Some(percpu) = percpu::get() {
+            percpu.locks -= 1;
+        }
+        self.readers.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl<'intex, T: ?Sized> Deref for IntexReadGuard<'intex, T> {
+    type Target = T;
+
+    fn deref<'a>(&'a self) -> &'a T {
+        unsafe { &*self.data.get() }
+    }
+}
+
+/// An IntexRw guard for writing (returned by .write())
+pub struct IntexWriteGuard<'a, T: ?Sized + 'a> {
+    inner: StaticIntexGuard,
+    writer: &'a AtomicUsize,
+    depth: &'a UnsafeCell<usize>,
+    data: &'a UnsafeCell<T>,
+}
+
+impl<'intex, T: ?Sized> Drop for IntexWriteGuard<'intex, T> {
+    fn drop(&mut self) {
+        #[cfg(debug)]
+        unsafe { lockdep::release(self.data as *const UnsafeCell<T> as *const u8 as usize) };
+
+        if let Some(percpu) = percpu::get() {
+            percpu.locks -= 1;
+        }
+        unsafe {
+            *self.depth.get() -= 1;
+            if *self.depth.get() == 0 {
+                self.writer.store(0, Ordering::SeqCst);
+            }
+        }
+    }
+}
+
+impl<'intex, T: ?Sized> Deref for IntexWriteGuard<'intex, T> {
+    type Target = T;
+
+    fn deref<'a>(&'a self) -> &'a T {
+        unsafe { &*self.data.get() }
+    }
+}
+
+impl<'intex, T: ?Sized> DerefMut for IntexWriteGuard<'intex, T> {
+    fn deref_mut<'a>(&'a mut self) -> &'a mut T {
+        unsafe { &mut *self.data.get() }
+    }
+}
+
 /// A Static Intex guard (returned by .static_lock())
 pub struct StaticIntexGuard;
 
 impl StaticIntexGuard {
     fn new() -> Self {
         unsafe {
-            asm!("cli");
+            cpu::interrupts_disable();
             intex_count += 1;
         }
         StaticIntexGuard
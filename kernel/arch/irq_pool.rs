@@ -0,0 +1,91 @@
+//! Allocation in interrupt context
+//!
+//! The heap in `arch::memory` is not reentrant: an IRQ arriving while a context allocates would
+//! see the cluster table half updated. IRQ handlers therefore must not use the heap. While
+//! `IrqManager::handle` runs the handlers, every allocation is served from a small pool reserved
+//! at build time instead, which is claimed and released with atomic operations only. Handlers are
+//! expected to allocate rarely and only small values, such as the closure passed to `defer`, and
+//! to move anything larger out of the interrupt.
+//!
+//! An allocation in interrupt context that the pool cannot serve fails. With `--cfg debug` it
+//! panics instead, naming the size, as does any use of the heap in interrupt context that
+//! bypasses the allocator.
+
+use core::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
+
+/// The size of a block of the pool, the largest allocation it serves
+pub const POOL_BLOCK: usize = 256;
+/// The number of blocks, 32 in each word of `USED`
+const POOL_BLOCKS: usize = 128;
+
+/// The memory of the pool, as `u64` so that blocks are 8 byte aligned
+static mut POOL: [u64; POOL_BLOCK / 8 * POOL_BLOCKS] = [0; POOL_BLOCK / 8 * POOL_BLOCKS];
+
+/// A bit for each block that is allocated, only the low 32 bits of each word are used
+static USED: [AtomicUsize; POOL_BLOCKS / 32] = [ATOMIC_USIZE_INIT, ATOMIC_USIZE_INIT,
+                                                ATOMIC_USIZE_INIT, ATOMIC_USIZE_INIT];
+
+/// How deep IRQ handling is nested, IRQs are only routed to the bootstrap processor
+static DEPTH: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Interrupt context, from `enter` until dropped
+pub struct IrqContext;
+
+impl IrqContext {
+    pub fn enter() -> IrqContext {
+        DEPTH.fetch_add(1, Ordering::SeqCst);
+        IrqContext
+    }
+}
+
+impl Drop for IrqContext {
+    fn drop(&mut self) {
+        DEPTH.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Is an IRQ handler running
+pub fn in_irq() -> bool {
+    DEPTH.load(Ordering::SeqCst) > 0
+}
+
+fn start() -> usize {
+    unsafe { POOL.as_ptr() as usize }
+}
+
+/// Was `address` allocated from the pool
+pub fn contains(address: usize) -> bool {
+    address >= start() && address < start() + POOL_BLOCK * POOL_BLOCKS
+}
+
+/// Allocate a block, returning 0 if `size` does not fit or the pool is empty
+pub fn alloc(size: usize, align: usize) -> usize {
+    if size > 0 && size <= POOL_BLOCK && align <= 8 {
+        for (word, used) in USED.iter().enumerate() {
+            loop {
+                let bits = used.load(Ordering::SeqCst);
+                let free = (! bits & 0xFFFFFFFF).trailing_zeros() as usize;
+                if free >= 32 {
+                    break;
+                }
+                if used.compare_and_swap(bits, bits | 1 << free, Ordering::SeqCst) == bits {
+                    return start() + (word * 32 + free) * POOL_BLOCK;
+                }
+            }
+        }
+    }
+
+    if cfg!(debug) {
+        panic!("irq_pool: {} byte allocation in interrupt context cannot be served", size);
+    }
+
+    0
+}
+
+/// Return a block to the pool
+pub fn unalloc(address: usize) {
+    if contains(address) {
+        let block = (address - start()) / POOL_BLOCK;
+        USED[block / 32].fetch_and(! (1 << (block % 32)), Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,5 @@
+pub use self::arch::*;
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[path="x86/kexec.rs"]
+mod arch;
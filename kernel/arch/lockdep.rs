@@ -0,0 +1,303 @@
+//! Lock diagnostics for `Intex`, only built with `--cfg debug`
+//!
+//! Every acquisition records the call site. Taking a lock that closes a cycle in the order graph,
+//! of which an inversion of two locks is the shortest, is reported with both call sites, as it
+//! would deadlock once an `Intex` spins on another CPU. Taking an `Intex` that is already held is
+//! allowed, as it only disables interrupts. The order graph and the hold-time statistics live in
+//! fixed arrays under a spinlock of their own, so that recording never allocates or takes an
+//! `Intex`, and reports are printed after it is released.
+//!
+//! The held stack is kept in the `PerCpu` of each processor, not per context, so locks held across
+//! a context switch are attributed to whichever context releases them.
+
+use arch::cpu;
+use arch::percpu;
+
+use collections::string::String;
+use collections::vec::Vec;
+
+use common::backtrace;
+use common::random::rdtsc;
+
+use core::{mem, ptr};
+use core::sync::atomic::{AtomicBool, ATOMIC_BOOL_INIT, Ordering};
+
+/// The maximum lock nesting that is tracked
+const LOCKDEP_HELD: usize = 32;
+/// The number of lock order edges that are remembered
+const LOCKDEP_EDGES: usize = 512;
+/// The number of locks with hold-time statistics
+const LOCKDEP_LOCKS: usize = 128;
+
+/// Set while a report is printed, so that the locks it takes are not tracked
+static BUSY: AtomicBool = ATOMIC_BOOL_INIT;
+
+/// Held while the order graph or the statistics are used
+static GRAPH: AtomicBool = ATOMIC_BOOL_INIT;
+
+#[derive(Copy, Clone)]
+struct Held {
+    lock: usize,
+    site: usize,
+    tsc: u64,
+}
+
+/// The locks held by a processor, innermost last
+pub struct HeldLocks {
+    held: [Held; LOCKDEP_HELD],
+    len: usize,
+}
+
+impl Default for HeldLocks {
+    fn default() -> HeldLocks {
+        HeldLocks {
+            held: [Held { lock: 0, site: 0, tsc: 0 }; LOCKDEP_HELD],
+            len: 0,
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Edge {
+    /// The lock that was held
+    from: usize,
+    /// The lock that was taken
+    to: usize,
+    /// Where `to` was taken
+    site: usize,
+}
+
+/// Hold-time statistics for a lock
+#[derive(Copy, Clone)]
+pub struct LockStat {
+    pub lock: usize,
+    pub count: u64,
+    pub total: u64,
+    pub max: u64,
+    /// Where the longest hold was taken
+    pub max_site: usize,
+}
+
+/// The locks held by the bootstrap processor before its `PerCpu` is set up
+static mut BOOT_HELD: HeldLocks = HeldLocks {
+    held: [Held { lock: 0, site: 0, tsc: 0 }; LOCKDEP_HELD],
+    len: 0,
+};
+static mut EDGES: [Edge; LOCKDEP_EDGES] = [Edge { from: 0, to: 0, site: 0 }; LOCKDEP_EDGES];
+static mut EDGES_LEN: usize = 0;
+static mut STATS: [LockStat; LOCKDEP_LOCKS] = [LockStat { lock: 0, count: 0, total: 0, max: 0, max_site: 0 }; LOCKDEP_LOCKS];
+/// The edges visited and still to visit by `find_path`, kept here as they are too large for the
+/// kernel stack
+static mut VISITED: [bool; LOCKDEP_EDGES] = [false; LOCKDEP_EDGES];
+static mut PENDING: [usize; LOCKDEP_EDGES] = [0; LOCKDEP_EDGES];
+
+/// The locks held by the running processor
+unsafe fn held() -> &'static mut HeldLocks {
+    match percpu::get() {
+        Some(percpu) => &mut percpu.held_locks,
+        None => &mut BOOT_HELD,
+    }
+}
+
+/// Lock the order graph and the statistics. Interrupts are already disabled by the `Intex`
+unsafe fn graph_lock() {
+    while GRAPH.compare_and_swap(false, true, Ordering::SeqCst) {
+        cpu::pause();
+    }
+}
+
+unsafe fn graph_unlock() {
+    GRAPH.store(false, Ordering::SeqCst);
+}
+
+/// Find a path of edges from `from` to `to`, with the graph locked, returning the site of its
+/// last edge
+unsafe fn find_path(from: usize, to: usize) -> Option<usize> {
+    for visited in VISITED[.. EDGES_LEN].iter_mut() {
+        *visited = false;
+    }
+
+    let mut pending = 0;
+    for i in 0..EDGES_LEN {
+        if EDGES[i].from == from {
+            VISITED[i] = true;
+            PENDING[pending] = i;
+            pending += 1;
+        }
+    }
+
+    while pending > 0 {
+        pending -= 1;
+        let edge = EDGES[PENDING[pending]];
+        if edge.to == to {
+            return Some(edge.site);
+        }
+
+        for i in 0..EDGES_LEN {
+            if ! VISITED[i] && EDGES[i].from == edge.to {
+                VISITED[i] = true;
+                PENDING[pending] = i;
+                pending += 1;
+            }
+        }
+    }
+
+    None
+}
+
+/// The return address of the function that called our caller
+#[inline(always)]
+unsafe fn call_site() -> usize {
+    let bp: usize;
+    #[cfg(target_arch = "x86")]
+    asm!("mov $0, ebp" : "=r"(bp) : : : "intel", "volatile");
+    #[cfg(target_arch = "x86_64")]
+    asm!("mov $0, rbp" : "=r"(bp) : : : "intel", "volatile");
+
+    // Our frame, then the frame of `Intex::lock`
+    let lock_bp = ptr::read(bp as *const usize);
+    if lock_bp == 0 {
+        0
+    } else {
+        ptr::read((lock_bp + mem::size_of::<usize>()) as *const usize)
+    }
+}
+
+fn site_name(site: usize) -> String {
+    match backtrace::resolve(site) {
+        Some((name, offset)) => format!("{:08X} {}+{:#X}", site, name, offset),
+        None => format!("{:08X}", site),
+    }
+}
+
+unsafe fn report(message: &str, first: usize, second: usize) {
+    if ! BUSY.compare_and_swap(false, true, Ordering::SeqCst) {
+        debugln!("LOCKDEP: {}", message);
+        debugln!("  first:  {}", site_name(first));
+        debugln!("  second: {}", site_name(second));
+        backtrace::trace();
+        BUSY.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Record that `lock` is about to be taken
+#[inline(never)]
+pub unsafe fn acquire(lock: usize) {
+    if BUSY.load(Ordering::SeqCst) {
+        return;
+    }
+
+    let site = call_site();
+    let held = held();
+    let mut cycle = None;
+
+    graph_lock();
+    for i in 0..held.len {
+        let outer = held.held[i].lock;
+        if outer == lock {
+            continue;
+        }
+
+        let mut known = false;
+        for j in 0..EDGES_LEN {
+            let edge = EDGES[j];
+            if edge.from == outer && edge.to == lock {
+                known = true;
+                break;
+            }
+        }
+
+        // Each edge is checked once, when it is new, so a cycle is reported once
+        if ! known && EDGES_LEN < LOCKDEP_EDGES {
+            if cycle.is_none() {
+                cycle = find_path(lock, outer);
+            }
+
+            EDGES[EDGES_LEN] = Edge {
+                from: outer,
+                to: lock,
+                site: site,
+            };
+            EDGES_LEN += 1;
+        }
+    }
+    graph_unlock();
+
+    if held.len < LOCKDEP_HELD {
+        held.held[held.len] = Held {
+            lock: lock,
+            site: site,
+            tsc: rdtsc(),
+        };
+        held.len += 1;
+    }
+
+    if let Some(first) = cycle {
+        report("lock order cycle", first, site);
+    }
+}
+
+/// Record that `lock` was released
+pub unsafe fn release(lock: usize) {
+    let held = held();
+    let mut i = held.len;
+    while i > 0 {
+        i -= 1;
+        if held.held[i].lock == lock {
+            let entry = held.held[i];
+            let time = rdtsc().wrapping_sub(entry.tsc);
+
+            for j in i..held.len - 1 {
+                held.held[j] = held.held[j + 1];
+            }
+            held.len -= 1;
+
+            graph_lock();
+            for stat in STATS.iter_mut() {
+                if stat.lock == lock || stat.lock == 0 {
+                    stat.lock = lock;
+                    stat.count += 1;
+                    stat.total += time;
+                    if time > stat.max {
+                        stat.max = time;
+                        stat.max_site = entry.site;
+                    }
+                    break;
+                }
+            }
+            graph_unlock();
+
+            return;
+        }
+    }
+}
+
+/// Format the hold-time statistics, one lock per line, in cycles
+pub fn stats() -> String {
+    let mut string = String::new();
+
+    // Copied with the graph locked, and formatted after, as formatting allocates
+    let mut stats = Vec::with_capacity(LOCKDEP_LOCKS);
+    unsafe {
+        graph_lock();
+        for stat in STATS.iter() {
+            if stat.lock != 0 {
+                stats.push(*stat);
+            }
+        }
+        graph_unlock();
+    }
+
+    BUSY.store(true, Ordering::SeqCst);
+    for stat in stats.iter() {
+        string.push_str(&format!("{:08X} count {} avg {} max {} at {}\n",
+                                 stat.lock,
+                                 stat.count,
+                                 stat.total / stat.count,
+                                 stat.max,
+                                 site_name(stat.max_site)));
+    }
+    BUSY.store(false, Ordering::SeqCst);
+
+    string
+}
@@ -1,9 +1,13 @@
 // TODO: Doc the rest
 
+use common::cmdline;
+use common::trace::TracePoint;
+
 use core::{cmp, intrinsics, mem};
 use core::ops::{Index, IndexMut};
-use core::ptr;
+use core::{ptr, slice};
 
+use super::irq_pool;
 use super::paging::PAGE_END;
 
 pub const CLUSTER_ADDRESS: usize = PAGE_END;
@@ -113,17 +117,59 @@ impl<T> IndexMut<usize> for Memory<T> {
     }
 }
 
-/// A memory map entry
+/// A memory map entry, in the format of the BIOS E820 call
+#[derive(Copy, Clone, Default)]
 #[repr(packed)]
-struct MemoryMapEntry {
-    base: u64,
-    len: u64,
-    class: u32,
-    acpi: u32,
+pub struct MemoryMapEntry {
+    pub base: u64,
+    pub len: u64,
+    /// 1 is usable memory, 3 is ACPI reclaimable, and 4 is ACPI NVS
+    pub class: u32,
+    pub acpi: u32,
 }
 
 const MEMORY_MAP: *const MemoryMapEntry = 0x500 as *const MemoryMapEntry;
 
+/// The number of entries that fit in the memory map
+pub const MEMORY_MAP_COUNT: usize = (0x5000 - 0x500) / mem::size_of::<MemoryMapEntry>();
+
+/// Replace the memory map left by the bootloader, for boot protocols that pass it differently
+///
+/// Entries past `MEMORY_MAP_COUNT` are dropped. This must be called before `cluster_init`.
+pub unsafe fn memory_map_set<I: Iterator<Item = MemoryMapEntry>>(entries: I) {
+    let mut i = 0;
+    for entry in entries.take(MEMORY_MAP_COUNT) {
+        ptr::write(MEMORY_MAP.offset(i as isize) as *mut MemoryMapEntry, entry);
+        i += 1;
+    }
+
+    while i < MEMORY_MAP_COUNT {
+        ptr::write(MEMORY_MAP.offset(i as isize) as *mut MemoryMapEntry, MemoryMapEntry::default());
+        i += 1;
+    }
+}
+
+/// The memory map, with unused entries at the end having a length of zero
+///
+/// The first page is unmapped once the kernel is initialized, and must be mapped again first.
+pub unsafe fn memory_map() -> &'static [MemoryMapEntry] {
+    slice::from_raw_parts(MEMORY_MAP, MEMORY_MAP_COUNT)
+}
+
+/// Mark the clusters covering `address` to `address + size` as not present, so they are never
+/// allocated
+pub unsafe fn reserve(address: usize, size: usize) {
+    let start = cluster_to_address(0);
+    let end = address + size;
+    if end > start {
+        let first = address.saturating_sub(start) / CLUSTER_SIZE;
+        let last = cmp::min(CLUSTER_COUNT, (end - start + CLUSTER_SIZE - 1) / CLUSTER_SIZE);
+        for cluster in first..last {
+            set_cluster(cluster, 0xFFFFFFFF);
+        }
+    }
+}
+
 /// Get the data (address) of a given cluster
 pub unsafe fn cluster(number: usize) -> usize {
     if number < CLUSTER_COUNT {
@@ -161,15 +207,17 @@ pub unsafe fn cluster_init() {
         set_cluster(cluster, 0xFFFFFFFF);
     }
 
-    // Next, set all valid clusters to the free value
+    // Next, set all valid clusters to the free value, below the limit from the command line
     // TODO: Optimize this function
-    for i in 0..((0x5000 - 0x500) / mem::size_of::<MemoryMapEntry>()) {
+    let limit = cmdline::config().mem.unwrap_or(u64::max_value());
+    for i in 0..MEMORY_MAP_COUNT {
         let entry = &*MEMORY_MAP.offset(i as isize);
         if entry.len > 0 && entry.class == 1 {
             for cluster in 0..CLUSTER_COUNT {
                 let address = cluster_to_address(cluster);
                 if address as u64 >= entry.base &&
-                   (address as u64 + CLUSTER_SIZE as u64) <= (entry.base + entry.len) {
+                   (address as u64 + CLUSTER_SIZE as u64) <= (entry.base + entry.len) &&
+                   (address as u64 + CLUSTER_SIZE as u64) <= limit {
                     set_cluster(cluster, 0);
                 }
             }
@@ -177,8 +225,18 @@ pub unsafe fn cluster_init() {
     }
 }
 
+/// Catch the heap being used by an IRQ handler, see `arch::irq_pool`
+#[inline(always)]
+fn heap_check(size: usize) {
+    if cfg!(debug) && irq_pool::in_irq() {
+        panic!("memory: {} byte heap allocation in interrupt context", size);
+    }
+}
+
 /// Allocate memory
 pub unsafe fn alloc(size: usize) -> usize {
+    heap_check(size);
+
     if size > 0 {
         let mut number = 0;
         let mut count = 0;
@@ -204,6 +262,9 @@ pub unsafe fn alloc(size: usize) -> usize {
             for i in number..number + count {
                 set_cluster(i, address);
             }
+
+            tracepoint!(TracePoint::Alloc, size, address);
+
             return address;
         }
     }
@@ -212,6 +273,8 @@ pub unsafe fn alloc(size: usize) -> usize {
 }
 
 pub unsafe fn alloc_aligned(size: usize, align: usize) -> usize {
+    heap_check(size);
+
     if size > 0 {
         let mut number = 0;
         let mut count = 0;
@@ -293,12 +356,11 @@ pub unsafe fn realloc(ptr: usize, size: usize) -> usize {
             ret = ptr;
         } else {
             ret = alloc(size);
-            if ptr > 0 {
-                if ret > 0 {
-                    let copy_size = cmp::min(old_size, size);
+            // On failure the old allocation is kept, as it still is in use
+            if ptr > 0 && ret > 0 {
+                let copy_size = cmp::min(old_size, size);
 
-                    ::memmove(ret as *mut u8, ptr as *const u8, copy_size);
-                }
+                ::memmove(ret as *mut u8, ptr as *const u8, copy_size);
                 unalloc(ptr);
             }
         }
@@ -1,7 +1,24 @@
+pub mod apic;
 pub mod context;
+pub mod coredump;
+pub mod cpu;
 pub mod elf;
+pub mod fault;
+pub mod gdb;
+pub mod interrupt;
 pub mod intex;
+pub mod irq_pool;
+pub mod kexec;
+#[cfg(debug)]
+pub mod lockdep;
 pub mod memory;
+pub mod multiboot;
 pub mod paging;
+pub mod percpu;
+pub mod pstore;
 pub mod regs;
+pub mod serial;
+pub mod smp;
+pub mod suspend;
 pub mod tss;
+pub mod usercopy;
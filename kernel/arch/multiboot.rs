@@ -0,0 +1,408 @@
+//! Multiboot2 boot information
+//!
+//! The legacy bootloader leaves the memory map at 0x500 and the VBE mode at 0x5200, and the ACPI
+//! tables are found by scanning the BIOS area. A Multiboot2 loader, including one running on UEFI
+//! firmware, passes all of these as tags instead. They are converted here to what the rest of the
+//! kernel expects, before the memory allocator is initialized. The command line of the kernel is
+//! only passed by a Multiboot2 loader. The legacy bootloader loads the initfs archive after the
+//! kernel and leaves where it is at 0x5600, which is kept as a module like one passed by a
+//! Multiboot2 loader.
+
+use arch::memory::{self, MemoryMapEntry};
+
+use collections::Vec;
+
+use core::{cmp, mem, ptr, slice, str};
+
+use graphics::display;
+
+/// The value a Multiboot2 loader passes to the kernel
+pub const MULTIBOOT2_MAGIC: usize = 0x36D76289;
+
+const TAG_END: u32 = 0;
+const TAG_CMDLINE: u32 = 1;
+const TAG_MODULE: u32 = 3;
+const TAG_MEMORY_MAP: u32 = 6;
+const TAG_FRAMEBUFFER: u32 = 8;
+const TAG_ACPI_OLD: u32 = 14;
+const TAG_ACPI_NEW: u32 = 15;
+const TAG_EFI_MEMORY_MAP: u32 = 17;
+
+/// The maximum number of modules that are kept
+pub const MODULE_COUNT: usize = 16;
+/// The maximum length of the name of a module
+pub const MODULE_NAME_SIZE: usize = 64;
+
+/// The maximum length of the command line
+pub const CMDLINE_SIZE: usize = 256;
+
+/// Where the legacy bootloader leaves the address and size of the initfs archive, after a
+/// signature, see `asm/startup-common.asm`
+const LEGACY_INITFS: usize = 0x5600;
+/// The signature of `LegacyInitFs`, which is not there if an older bootloader booted the kernel
+const LEGACY_INITFS_SIGNATURE: &'static [u8; 8] = b"INITFS\0\0";
+
+/// A file loaded into memory by the boot loader
+#[derive(Copy, Clone)]
+pub struct Module {
+    pub start: usize,
+    pub end: usize,
+    name: [u8; MODULE_NAME_SIZE],
+    name_len: usize,
+}
+
+impl Module {
+    /// The name of the module, its command line in the boot loader configuration
+    pub fn name(&self) -> &str {
+        unsafe { str::from_utf8_unchecked(&self.name[..self.name_len]) }
+    }
+
+    /// The contents of the module
+    pub unsafe fn data(&self) -> &'static [u8] {
+        slice::from_raw_parts(self.start as *const u8, self.end - self.start)
+    }
+}
+
+#[repr(packed)]
+struct Tag {
+    _type: u32,
+    size: u32,
+}
+
+#[repr(packed)]
+struct LegacyInitFs {
+    signature: [u8; 8],
+    start: u32,
+    size: u32,
+}
+
+#[repr(packed)]
+struct ModuleTag {
+    tag: Tag,
+    start: u32,
+    end: u32,
+}
+
+#[repr(packed)]
+struct MemoryMapTag {
+    tag: Tag,
+    entry_size: u32,
+    entry_version: u32,
+}
+
+#[repr(packed)]
+struct FramebufferTag {
+    tag: Tag,
+    address: u64,
+    pitch: u32,
+    width: u32,
+    height: u32,
+    bpp: u8,
+    _type: u8,
+}
+
+#[repr(packed)]
+struct EfiMemoryMapTag {
+    tag: Tag,
+    descriptor_size: u32,
+    descriptor_version: u32,
+}
+
+#[repr(packed)]
+struct EfiMemoryDescriptor {
+    _type: u32,
+    _pad: u32,
+    physical_start: u64,
+    virtual_start: u64,
+    pages: u64,
+    attribute: u64,
+}
+
+/// The size of an ACPI 2.0 RSDP, the ACPI 1.0 RSDP is the first 20 bytes of it
+const RSDP_SIZE: usize = 36;
+/// The size of an ACPI 1.0 RSDP
+const RSDP_OLD_SIZE: usize = 20;
+
+static mut MODULES: [Module; MODULE_COUNT] = [Module {
+    start: 0,
+    end: 0,
+    name: [0; MODULE_NAME_SIZE],
+    name_len: 0,
+}; MODULE_COUNT];
+static mut MODULES_LEN: usize = 0;
+
+static mut CMDLINE: [u8; CMDLINE_SIZE] = [0; CMDLINE_SIZE];
+static mut CMDLINE_LEN: usize = 0;
+
+static mut RSDP: [u8; RSDP_SIZE] = [0; RSDP_SIZE];
+static mut RSDP_FOUND: bool = false;
+
+/// Convert the type of an EFI memory descriptor to the E820 class
+fn efi_class(_type: u32) -> u32 {
+    match _type {
+        // Loader code and data, boot services code and data, and conventional memory are free
+        // once boot services have exited
+        1 ... 4 | 7 => 1,
+        9 => 3,
+        10 => 4,
+        _ => 2,
+    }
+}
+
+/// Iterate over the tags of the boot information at `info`
+unsafe fn tags(info: usize) -> TagIter {
+    let total_size = ptr::read(info as *const u32) as usize;
+    TagIter {
+        address: info + 8,
+        end: info + total_size,
+    }
+}
+
+struct TagIter {
+    address: usize,
+    end: usize,
+}
+
+impl Iterator for TagIter {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        if self.address + mem::size_of::<Tag>() > self.end {
+            return None;
+        }
+
+        let tag = unsafe { &*(self.address as *const Tag) };
+        if tag._type == TAG_END || tag.size < mem::size_of::<Tag>() as u32 {
+            return None;
+        }
+
+        let address = self.address;
+        // Tags are aligned to 8 bytes
+        self.address += (tag.size as usize + 7) & !7;
+        Some(address)
+    }
+}
+
+/// Read the boot information passed in `info`, if `magic` shows a Multiboot2 loader passed it
+///
+/// Returns false on the legacy boot path, where the boot information is already in place.
+pub unsafe fn init(magic: usize, info: usize) -> bool {
+    if magic != MULTIBOOT2_MAGIC || info == 0 {
+        legacy_init();
+        return false;
+    }
+
+    let mut memory_map = false;
+    for address in tags(info) {
+        let tag = &*(address as *const Tag);
+        match tag._type {
+            // The BIOS memory map is preferred over the EFI memory map when both are present
+            TAG_MEMORY_MAP => {
+                let map = &*(address as *const MemoryMapTag);
+                let entry_size = map.entry_size as usize;
+                if entry_size >= mem::size_of::<MemoryMapEntry>() {
+                    let first = address + mem::size_of::<MemoryMapTag>();
+                    let count = (tag.size as usize - mem::size_of::<MemoryMapTag>()) / entry_size;
+                    memory::memory_map_set((0..count).map(|i| {
+                        ptr::read((first + i * entry_size) as *const MemoryMapEntry)
+                    }));
+                    memory_map = true;
+                }
+            },
+            TAG_EFI_MEMORY_MAP if ! memory_map => {
+                let map = &*(address as *const EfiMemoryMapTag);
+                let descriptor_size = map.descriptor_size as usize;
+                if descriptor_size >= mem::size_of::<EfiMemoryDescriptor>() {
+                    let first = address + mem::size_of::<EfiMemoryMapTag>();
+                    let count = (tag.size as usize - mem::size_of::<EfiMemoryMapTag>()) / descriptor_size;
+                    memory::memory_map_set((0..count).map(|i| {
+                        let descriptor = &*((first + i * descriptor_size) as *const EfiMemoryDescriptor);
+                        MemoryMapEntry {
+                            base: descriptor.physical_start,
+                            len: descriptor.pages * 4096,
+                            class: efi_class(descriptor._type),
+                            acpi: 0,
+                        }
+                    }));
+                }
+            },
+            TAG_FRAMEBUFFER => {
+                let framebuffer = &*(address as *const FramebufferTag);
+                // Only direct color framebuffers without padding can be used by the display
+                if framebuffer._type == 1 && framebuffer.bpp == 32 &&
+                   framebuffer.pitch == framebuffer.width * 4 &&
+                   framebuffer.address < 0x100000000 &&
+                   framebuffer.width <= 0xFFFF && framebuffer.height <= 0xFFFF {
+                    display::vbe_framebuffer(framebuffer.address as u32,
+                                             framebuffer.width as u16,
+                                             framebuffer.height as u16);
+                }
+            },
+            TAG_ACPI_OLD | TAG_ACPI_NEW => {
+                // Prefer the ACPI 2.0 RSDP, which starts with the ACPI 1.0 one
+                if tag._type == TAG_ACPI_NEW || ! RSDP_FOUND {
+                    let len = cmp::min(RSDP_SIZE, tag.size as usize - mem::size_of::<Tag>());
+                    ptr::copy((address + mem::size_of::<Tag>()) as *const u8, RSDP.as_mut_ptr(), len);
+                    RSDP_FOUND = true;
+                }
+            },
+            TAG_CMDLINE => {
+                // The command line is a null terminated string following the tag
+                let cmdline = (address + mem::size_of::<Tag>()) as *const u8;
+                let max = cmp::min(CMDLINE_SIZE, tag.size as usize - mem::size_of::<Tag>());
+                CMDLINE_LEN = 0;
+                while CMDLINE_LEN < max && *cmdline.offset(CMDLINE_LEN as isize) != 0 {
+                    CMDLINE[CMDLINE_LEN] = *cmdline.offset(CMDLINE_LEN as isize);
+                    CMDLINE_LEN += 1;
+                }
+            },
+            TAG_MODULE => if MODULES_LEN < MODULE_COUNT {
+                let module_tag = &*(address as *const ModuleTag);
+                let module = &mut MODULES[MODULES_LEN];
+                module.start = module_tag.start as usize;
+                module.end = module_tag.end as usize;
+
+                // The name is a null terminated string following the module tag
+                let name = (address + mem::size_of::<ModuleTag>()) as *const u8;
+                let max = cmp::min(MODULE_NAME_SIZE, tag.size as usize - mem::size_of::<ModuleTag>());
+                module.name_len = 0;
+                while module.name_len < max && *name.offset(module.name_len as isize) != 0 {
+                    module.name[module.name_len] = *name.offset(module.name_len as isize);
+                    module.name_len += 1;
+                }
+
+                MODULES_LEN += 1;
+            },
+            _ => (),
+        }
+    }
+
+    true
+}
+
+/// Keep the initfs archive loaded by the legacy bootloader as a module named `initfs`
+unsafe fn legacy_init() {
+    let initfs = &*(LEGACY_INITFS as *const LegacyInitFs);
+    if &initfs.signature != LEGACY_INITFS_SIGNATURE || initfs.size == 0 {
+        return;
+    }
+
+    let module = &mut MODULES[0];
+    module.start = initfs.start as usize;
+    module.end = initfs.start as usize + initfs.size as usize;
+    module.name[..6].copy_from_slice(b"initfs");
+    module.name_len = 6;
+    MODULES_LEN = 1;
+}
+
+/// Keep the memory allocator away from the modules, called after `memory::cluster_init`
+pub unsafe fn reserve() {
+    for module in modules() {
+        memory::reserve(module.start, module.end - module.start);
+    }
+}
+
+/// The modules loaded by the boot loader
+pub fn modules() -> &'static [Module] {
+    unsafe { &MODULES[..MODULES_LEN] }
+}
+
+/// The command line passed by the boot loader, empty if there is none or it is not UTF-8
+pub fn cmdline() -> &'static str {
+    unsafe { str::from_utf8(&CMDLINE[..CMDLINE_LEN]).unwrap_or("") }
+}
+
+/// The address of the RSDP passed by the boot loader, if any
+pub fn rsdp() -> Option<usize> {
+    unsafe {
+        if RSDP_FOUND {
+            Some(RSDP.as_ptr() as usize)
+        } else {
+            None
+        }
+    }
+}
+
+/// Append a little endian `u32` to `info`
+fn push_u32(info: &mut Vec<u8>, value: u32) {
+    for i in 0..4 {
+        info.push((value >> (i * 8)) as u8);
+    }
+}
+
+/// Append a tag of type `_type` holding `data` to `info`, padded to 8 bytes
+fn push_tag(info: &mut Vec<u8>, _type: u32, data: &[u8]) {
+    push_u32(info, _type);
+    push_u32(info, (mem::size_of::<Tag>() + data.len()) as u32);
+    info.extend_from_slice(data);
+    while info.len() % 8 != 0 {
+        info.push(0);
+    }
+}
+
+/// Build Multiboot2 boot information describing this machine, to boot another kernel with kexec
+///
+/// It holds `cmdline`, a module for each start, end and name in `modules`, the memory map, the
+/// framebuffer and the RSDP, when the RSDP was passed by the boot loader. The first page must be
+/// mapped, for the memory map.
+pub unsafe fn build_info(cmdline: &str, modules: &[(usize, usize, &str)]) -> Vec<u8> {
+    let mut info = Vec::new();
+    // The total size is filled in at the end
+    push_u32(&mut info, 0);
+    push_u32(&mut info, 0);
+
+    let mut data = Vec::new();
+    data.extend_from_slice(cmdline.as_bytes());
+    data.push(0);
+    push_tag(&mut info, TAG_CMDLINE, &data);
+
+    for &(start, end, name) in modules.iter() {
+        let mut data = Vec::new();
+        push_u32(&mut data, start as u32);
+        push_u32(&mut data, end as u32);
+        data.extend_from_slice(name.as_bytes());
+        data.push(0);
+        push_tag(&mut info, TAG_MODULE, &data);
+    }
+
+    let mut data = Vec::new();
+    push_u32(&mut data, mem::size_of::<MemoryMapEntry>() as u32);
+    push_u32(&mut data, 0);
+    for entry in memory::memory_map().iter().filter(|entry| entry.len > 0) {
+        data.extend_from_slice(slice::from_raw_parts(entry as *const MemoryMapEntry as *const u8,
+                                                     mem::size_of::<MemoryMapEntry>()));
+    }
+    push_tag(&mut info, TAG_MEMORY_MAP, &data);
+
+    if let Some((address, width, height)) = display::vbe_framebuffer_info() {
+        let mut data = Vec::new();
+        push_u32(&mut data, address);
+        push_u32(&mut data, 0);
+        push_u32(&mut data, width as u32 * 4);
+        push_u32(&mut data, width as u32);
+        push_u32(&mut data, height as u32);
+        // 32 bits per pixel, direct color, then the reserved field
+        data.extend_from_slice(&[32, 1, 0, 0]);
+        // The position and size of red, green and blue
+        data.extend_from_slice(&[16, 8, 8, 8, 0, 8]);
+        push_tag(&mut info, TAG_FRAMEBUFFER, &data);
+    }
+
+    if RSDP_FOUND {
+        // The revision is 2 or more for the ACPI 2.0 RSDP
+        if RSDP[15] >= 2 {
+            push_tag(&mut info, TAG_ACPI_NEW, &RSDP);
+        } else {
+            push_tag(&mut info, TAG_ACPI_OLD, &RSDP[..RSDP_OLD_SIZE]);
+        }
+    }
+
+    push_tag(&mut info, TAG_END, &[]);
+
+    let size = info.len() as u32;
+    for i in 0..4 {
+        info[i] = (size >> (i * 8)) as u8;
+    }
+
+    info
+}
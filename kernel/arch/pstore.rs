@@ -0,0 +1,144 @@
+//! Persistent crash log
+//!
+//! On panic, the end of the kernel log, which holds the panic message and backtrace, is written to
+//! a region at the top of usable memory below 4 GiB. Memory is kept over a warm reboot, so the next
+//! boot finds the log there, checks it, and exposes it as `sys:/lastcrash`. The region is chosen
+//! from the memory map, so it is the same on every boot of the same machine with the same `mem`.
+
+use common::cmdline;
+
+use core::{cmp, fmt, mem, slice};
+use core::fmt::Write;
+
+use super::memory;
+
+/// The size of the region, including the header
+pub const PSTORE_SIZE: usize = 64 * 1024;
+
+/// "PSTORE01"
+const PSTORE_MAGIC: u64 = 0x3130_4552_4F54_5350;
+
+/// The most a log entry adds to its message when formatted
+const ENTRY_OVERHEAD: usize = 32;
+
+/// The start of the region, followed by the log
+#[repr(packed)]
+struct PstoreHeader {
+    magic: u64,
+    len: u32,
+    checksum: u32,
+}
+
+/// The address of the region, 0 if there is none
+static mut REGION: usize = 0;
+/// The length of the log left by the previous boot, 0 if it did not panic
+static mut LAST_LEN: usize = 0;
+
+/// FNV-1a, to reject a region that was not kept over the reboot
+fn checksum(data: &[u8]) -> u32 {
+    let mut hash: u32 = 0x811C9DC5;
+    for &byte in data {
+        hash ^= byte as u32;
+        hash = hash.wrapping_mul(0x01000193);
+    }
+    hash
+}
+
+unsafe fn data() -> &'static mut [u8] {
+    let header = mem::size_of::<PstoreHeader>();
+    slice::from_raw_parts_mut((REGION + header) as *mut u8, PSTORE_SIZE - header)
+}
+
+/// Reserve the region and read the log left by the previous boot, which is then invalidated so
+/// that it is only reported once. Must be called after `memory::cluster_init`
+pub unsafe fn init() {
+    let limit = cmp::min((memory::CLUSTER_COUNT * memory::CLUSTER_SIZE) as u64,
+                         cmdline::config().mem.unwrap_or(u64::max_value()));
+
+    let mut top = 0;
+    for entry in memory::memory_map().iter().filter(|entry| entry.len > 0 && entry.class == 1) {
+        let end = cmp::min(entry.base + entry.len, limit) & ! (memory::CLUSTER_SIZE as u64 - 1);
+        if end >= entry.base + PSTORE_SIZE as u64 && end > top {
+            top = end;
+        }
+    }
+    if top == 0 {
+        return;
+    }
+
+    REGION = (top - PSTORE_SIZE as u64) as usize;
+    memory::reserve(REGION, PSTORE_SIZE);
+
+    let header = &mut *(REGION as *mut PstoreHeader);
+    let len = header.len as usize;
+    if header.magic == PSTORE_MAGIC && len <= data().len() && checksum(&data()[.. len]) == header.checksum {
+        LAST_LEN = len;
+    }
+    header.magic = 0;
+}
+
+/// The log left by the previous boot, if it panicked
+pub fn last() -> Option<&'static [u8]> {
+    unsafe {
+        if LAST_LEN > 0 {
+            Some(&data()[.. LAST_LEN])
+        } else {
+            None
+        }
+    }
+}
+
+/// Writes to the region, dropping what does not fit, as the heap may not be usable in a panic
+struct RegionWriter {
+    data: &'static mut [u8],
+    len: usize,
+}
+
+impl fmt::Write for RegionWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let count = cmp::min(s.len(), self.data.len() - self.len);
+        self.data[self.len .. self.len + count].copy_from_slice(&s.as_bytes()[.. count]);
+        self.len += count;
+        Ok(())
+    }
+}
+
+/// Write the end of the kernel log to the region, called on panic
+pub unsafe fn save() {
+    if REGION == 0 || ::ENV_PTR.is_none() {
+        return;
+    }
+
+    let mut writer = RegionWriter {
+        data: data(),
+        len: 0,
+    };
+
+    // Intex only disables interrupts, so this cannot deadlock if the panic happened while writing
+    let log = ::env().log.lock();
+
+    // Keep the newest entries that fit
+    let mut first = log.entries.len();
+    let mut size = log.line.as_ref().map_or(0, |line| line.message.len() + ENTRY_OVERHEAD);
+    while first > 0 {
+        size += log.entries[first - 1].message.len() + ENTRY_OVERHEAD;
+        if size > writer.data.len() {
+            break;
+        }
+        first -= 1;
+    }
+
+    for entry in log.entries.iter().skip(first).chain(log.line.iter()) {
+        let _ = write!(writer, "[{:>5}.{:09}] {:<6}{}\n",
+                       entry.time.secs,
+                       entry.time.nanos,
+                       entry.level.name(),
+                       entry.text());
+    }
+
+    let len = writer.len;
+    let header = &mut *(REGION as *mut PstoreHeader);
+    header.len = len as u32;
+    header.checksum = checksum(&data()[.. len]);
+    header.magic = PSTORE_MAGIC;
+}
@@ -0,0 +1,111 @@
+//! The local APIC of each processor, used to start the others and for their timers
+
+use core::intrinsics::{volatile_load, volatile_store};
+
+/// The interrupt of the local APIC timer
+pub const TIMER_VECTOR: usize = 0x40;
+/// The interrupt delivered when an interrupt disappears before it is handled, which needs no EOI
+pub const SPURIOUS_VECTOR: usize = 0x4F;
+
+const REG_ID: usize = 0x20;
+const REG_EOI: usize = 0xB0;
+const REG_SPURIOUS: usize = 0xF0;
+const REG_ICR_LOW: usize = 0x300;
+const REG_ICR_HIGH: usize = 0x310;
+const REG_TIMER: usize = 0x320;
+const REG_TIMER_INITIAL: usize = 0x380;
+const REG_TIMER_CURRENT: usize = 0x390;
+const REG_TIMER_DIVIDE: usize = 0x3E0;
+
+const SPURIOUS_ENABLE: u32 = 1 << 8;
+const ICR_PENDING: u32 = 1 << 12;
+const ICR_INIT: u32 = 0x4500;
+const ICR_STARTUP: u32 = 0x4600;
+const ICR_ALL_EXCLUDING_SELF: u32 = 3 << 18;
+const TIMER_MASKED: u32 = 1 << 16;
+const TIMER_PERIODIC: u32 = 1 << 17;
+/// Divide the bus clock by 16
+const TIMER_DIVIDE_16: u32 = 0x3;
+
+/// A local APIC, mapped at the address given by the MADT
+pub struct LocalApic {
+    address: usize,
+}
+
+impl LocalApic {
+    pub fn new(address: usize) -> LocalApic {
+        LocalApic {
+            address: address,
+        }
+    }
+
+    unsafe fn read(&self, reg: usize) -> u32 {
+        volatile_load((self.address + reg) as *const u32)
+    }
+
+    unsafe fn write(&self, reg: usize, value: u32) {
+        volatile_store((self.address + reg) as *mut u32, value);
+    }
+
+    /// The APIC ID of the running processor
+    pub fn id(&self) -> u8 {
+        unsafe { (self.read(REG_ID) >> 24) as u8 }
+    }
+
+    /// Enable the local APIC of the running processor
+    pub unsafe fn init(&self) {
+        let spurious = self.read(REG_SPURIOUS);
+        self.write(REG_SPURIOUS, (spurious & !0xFF) | SPURIOUS_ENABLE | SPURIOUS_VECTOR as u32);
+    }
+
+    /// Signal the end of an interrupt from the local APIC
+    pub unsafe fn eoi(&self) {
+        self.write(REG_EOI, 0);
+    }
+
+    unsafe fn ipi(&self, apic_id: u8, command: u32) {
+        self.write(REG_ICR_HIGH, (apic_id as u32) << 24);
+        self.write(REG_ICR_LOW, command);
+        while self.read(REG_ICR_LOW) & ICR_PENDING == ICR_PENDING {}
+    }
+
+    /// Reset the processor `apic_id`, so that it waits for a startup IPI
+    pub unsafe fn ipi_init(&self, apic_id: u8) {
+        self.ipi(apic_id, ICR_INIT);
+    }
+
+    /// Reset every other processor, so that they wait for a startup IPI
+    pub unsafe fn ipi_init_others(&self) {
+        self.ipi(0, ICR_INIT | ICR_ALL_EXCLUDING_SELF);
+    }
+
+    /// Start the processor `apic_id` in real mode at `page * 4096`
+    pub unsafe fn ipi_startup(&self, apic_id: u8, page: u8) {
+        self.ipi(apic_id, ICR_STARTUP | page as u32);
+    }
+
+    /// Run the timer as a one-shot count down from `count`, with no interrupt
+    pub unsafe fn timer_start(&self, count: u32) {
+        self.write(REG_TIMER_DIVIDE, TIMER_DIVIDE_16);
+        self.write(REG_TIMER, TIMER_MASKED | TIMER_VECTOR as u32);
+        self.write(REG_TIMER_INITIAL, count);
+    }
+
+    /// The remaining count of the timer
+    pub unsafe fn timer_current(&self) -> u32 {
+        self.read(REG_TIMER_CURRENT)
+    }
+
+    /// Set up the timer to interrupt every `count` ticks, masked until `timer_unmask`
+    pub unsafe fn timer_periodic(&self, count: u32) {
+        self.write(REG_TIMER_DIVIDE, TIMER_DIVIDE_16);
+        self.write(REG_TIMER, TIMER_MASKED | TIMER_PERIODIC | TIMER_VECTOR as u32);
+        self.write(REG_TIMER_INITIAL, count);
+    }
+
+    /// Let the timer interrupt
+    pub unsafe fn timer_unmask(&self) {
+        let timer = self.read(REG_TIMER);
+        self.write(REG_TIMER, timer & !TIMER_MASKED);
+    }
+}
@@ -0,0 +1,51 @@
+use arch::regs::Regs;
+
+use collections::Vec;
+
+use core::{mem, slice};
+
+/// The process status note of a core file, laid out as `elf_prstatus` on i386
+#[repr(packed)]
+struct PrStatus {
+    si_signo: u32,
+    si_code: u32,
+    si_errno: u32,
+    cursig: u16,
+    _pad: u16,
+    sigpend: u32,
+    sighold: u32,
+    pid: u32,
+    ppid: u32,
+    pgrp: u32,
+    sid: u32,
+    /// User, system, and children's user and system time
+    times: [u32; 8],
+    /// The registers, in the order of `user_regs_struct`
+    regs: [u32; 17],
+    fpvalid: u32,
+}
+
+/// Describe the registers of a context killed by `signal`
+pub fn prstatus(regs: &Regs, signal: usize, pid: usize, ppid: usize, pgid: usize) -> Vec<u8> {
+    let ds = regs.ss as u32;
+    let status = PrStatus {
+        si_signo: signal as u32,
+        si_code: 0,
+        si_errno: 0,
+        cursig: signal as u16,
+        _pad: 0,
+        sigpend: 0,
+        sighold: 0,
+        pid: pid as u32,
+        ppid: ppid as u32,
+        pgrp: pgid as u32,
+        sid: 0,
+        times: [0; 8],
+        regs: [regs.bx as u32, regs.cx as u32, regs.dx as u32, regs.si as u32, regs.di as u32, regs.bp as u32,
+               regs.ax as u32, ds, ds, 0, 0, !0, regs.ip as u32, regs.cs as u32, regs.flags as u32,
+               regs.sp as u32, regs.ss as u32],
+        fpvalid: 0,
+    };
+
+    unsafe { slice::from_raw_parts(&status as *const PrStatus as *const u8, mem::size_of::<PrStatus>()) }.to_vec()
+}
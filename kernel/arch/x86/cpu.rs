@@ -0,0 +1,36 @@
+//! Processor control, shared by x86 and x86_64
+
+/// Disable interrupts
+#[inline(always)]
+pub unsafe fn interrupts_disable() {
+    asm!("cli" : : : : "intel", "volatile");
+}
+
+/// Enable interrupts, and wait for the next one
+#[inline(always)]
+pub unsafe fn halt() {
+    asm!("sti ; hlt" : : : : "intel", "volatile");
+}
+
+/// Enable interrupts, letting any pending ones run
+#[inline(always)]
+pub unsafe fn interrupts_poll() {
+    asm!("sti ; nop" : : : : "intel", "volatile");
+}
+
+/// Hint that this is a spin loop, waiting for another processor
+#[inline(always)]
+pub unsafe fn pause() {
+    asm!("pause" : : : "memory" : "intel", "volatile");
+}
+
+/// Read the cycle counter, the TSC
+#[inline(always)]
+pub fn timestamp() -> u64 {
+    let low: u32;
+    let high: u32;
+    unsafe {
+        asm!("rdtsc" : "={eax}"(low), "={edx}"(high) : : : "intel", "volatile");
+    }
+    (high as u64) << 32 | low as u64
+}
@@ -1,4 +1,6 @@
 pub const ELF_CLASS: u8 = 1;
+/// EM_386
+pub const ELF_MACHINE: u16 = 3;
 pub type ElfAddr = u32;
 pub type ElfHalf = u16;
 pub type ElfOff = u32;
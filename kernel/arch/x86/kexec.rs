@@ -0,0 +1,165 @@
+//! Booting another kernel without going through the firmware
+//!
+//! The new kernel is booted like the boot loader boots the first one: its file is copied to
+//! `KERNEL_BASE`, the interrupt stubs of the boot loader are pointed at its entry, and interrupt
+//! 0xFF enters it with Multiboot2 boot information holding the command line, the initial ramdisk
+//! as a module and what is known about the machine. The copy is done by `asm/kexec.asm`, run from
+//! low memory, as it overwrites the running kernel.
+
+use arch::cpu;
+use arch::elf::Elf;
+use arch::multiboot;
+use arch::paging::Page;
+use arch::smp;
+
+use collections::string::{String, ToString};
+use collections::vec::Vec;
+
+use core::{mem, ptr};
+
+use drivers::pci;
+
+/// Where the boot loader loads the kernel file
+const KERNEL_BASE: usize = 0x100000;
+/// Where the page tables start, on x86 and x86_64
+const KERNEL_END: usize = 0x200000;
+/// The stack of the new kernel, where the boot loader puts it, below the page tables
+const KERNEL_STACK: usize = KERNEL_END - 128;
+/// Room kept for the stack of the new kernel, which the kernel must end below
+const KERNEL_STACK_SIZE: usize = 64 * 1024;
+
+/// Where the handoff code is copied, below the boot loader and above the SMP trampoline
+const KEXEC_TRAMPOLINE: usize = 0x7000;
+
+static KEXEC_DATA: &'static [u8] = include_bytes!("../../../build/kexec.bin");
+
+/// The offsets of the arguments of the handoff code
+const ARG_SOURCE: usize = 8;
+const ARG_SIZE: usize = 16;
+const ARG_DEST: usize = 24;
+const ARG_HANDLER: usize = 32;
+const ARG_STACK: usize = 40;
+const ARG_TSS: usize = 48;
+const ARG_INFO: usize = 56;
+
+/// The interrupt that enters the kernel for the first time
+const INIT_VECTOR: usize = 0xFF;
+/// How far after the stub of an interrupt its handler pointer is searched for
+const HANDLER_SEARCH: usize = 256;
+
+/// The operand of `sidt`, with the base truncated to 32 bits on x86
+#[repr(packed)]
+#[derive(Copy, Clone, Default)]
+struct DescriptorTablePointer {
+    limit: u16,
+    base: u64,
+}
+
+/// A kernel loaded into memory, ready to be booted
+pub struct Kexec {
+    image: Vec<u8>,
+    initrd: Option<Vec<u8>>,
+    cmdline: String,
+}
+
+impl Kexec {
+    /// Check that `image` is a kernel for this architecture that fits where the boot loader puts
+    /// the kernel
+    pub fn new(image: Vec<u8>, initrd: Option<Vec<u8>>, cmdline: String) -> Result<Kexec, String> {
+        {
+            let elf = try!(Elf::from(&image));
+            if elf.compat() || elf.dynamic() {
+                return Err("not a kernel for this architecture".to_string());
+            }
+
+            let end = KERNEL_END - KERNEL_STACK_SIZE;
+            if image.len() > end - KERNEL_BASE {
+                return Err(format!("kernel file is too large: {} bytes", image.len()));
+            }
+            for segment in unsafe { elf.load_segment() }.iter() {
+                let vaddr = segment.vaddr as usize;
+                if vaddr < KERNEL_BASE || vaddr + segment.mem_len as usize > end {
+                    return Err(format!("segment at {:X} is outside of {:X} to {:X}", vaddr, KERNEL_BASE, end));
+                }
+            }
+        }
+
+        Ok(Kexec {
+            image: image,
+            initrd: initrd,
+            cmdline: cmdline,
+        })
+    }
+
+    /// Stop the other processors and devices, and boot the new kernel
+    ///
+    /// Filesystems are not synced, that is left to the caller.
+    pub unsafe fn boot(self) -> ! {
+        cpu::interrupts_disable();
+
+        smp::stop();
+        pci::pci_quiesce();
+
+        // The memory map and VBE information of the boot loader are in the first page, which the
+        // new kernel reads before setting up paging
+        Page::new(0).map_kernel_write(0);
+
+        let mut modules = Vec::new();
+        if let Some(ref initrd) = self.initrd {
+            let start = initrd.as_ptr() as usize;
+            modules.push((start, start + initrd.len(), "initrd"));
+        }
+        let info = multiboot::build_info(&self.cmdline, &modules);
+
+        let handler = match handler_slot() {
+            Some(handler) => handler,
+            None => panic!("kexec: interrupt handler not found"),
+        };
+
+        let tss = match ::TSS_PTR {
+            Some(ref tss) => &**tss as *const _ as usize,
+            None => 0,
+        };
+
+        ptr::copy(KEXEC_DATA.as_ptr(), KEXEC_TRAMPOLINE as *mut u8, KEXEC_DATA.len());
+        trampoline_write(ARG_SOURCE, self.image.as_ptr() as u64);
+        trampoline_write(ARG_SIZE, self.image.len() as u64);
+        trampoline_write(ARG_DEST, KERNEL_BASE as u64);
+        trampoline_write(ARG_HANDLER, handler as u64);
+        trampoline_write(ARG_STACK, KERNEL_STACK as u64);
+        trampoline_write(ARG_TSS, tss as u64);
+        trampoline_write(ARG_INFO, info.as_ptr() as u64);
+
+        // The image, the initial ramdisk and the boot information are read by the new kernel
+        mem::forget(self);
+        mem::forget(info);
+
+        asm!("jmp $0" : : "r"(KEXEC_TRAMPOLINE) : "memory" : "intel", "volatile");
+        loop {
+            cpu::halt();
+        }
+    }
+}
+
+unsafe fn trampoline_write<T>(offset: usize, value: T) {
+    ptr::write_volatile((KEXEC_TRAMPOLINE + offset) as *mut T, value);
+}
+
+/// Find the pointer that the interrupt stubs of the boot loader call the kernel through
+///
+/// The stubs in `asm/interrupts-*.asm` are followed by the handling code and then the pointer. It
+/// is found by looking for the entry of this kernel after the stub of the last interrupt, as it is
+/// not at a fixed address.
+unsafe fn handler_slot() -> Option<usize> {
+    let mut idtr = DescriptorTablePointer::default();
+    asm!("sidt [$0]" : : "r"(&mut idtr as *mut DescriptorTablePointer) : "memory" : "intel", "volatile");
+
+    // IDT entries are 8 bytes on x86 and 16 bytes on x86_64, with the low 16 bits of the offset
+    // first and the next 16 bits at byte 6. The stubs are below 4 GiB
+    let base = idtr.base as usize;
+    let entry = base + INIT_VECTOR * mem::size_of::<usize>() * 2;
+    let stub = ptr::read(entry as *const u16) as usize | (ptr::read((entry + 6) as *const u16) as usize) << 16;
+
+    let kernel = ::kernel as usize;
+    (stub..stub + HANDLER_SEARCH).find(|&address| ptr::read(address as *const usize) == kernel)
+}
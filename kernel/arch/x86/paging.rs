@@ -264,6 +264,11 @@ impl Page {
         unsafe { (ptr::read(self.entry_address() as *mut usize) & PF_NONE) as usize }
     }
 
+    /// Check if the memory page is mapped
+    pub fn is_present(&self) -> bool {
+        unsafe { ptr::read(self.entry_address() as *mut usize) & PF_PRESENT == PF_PRESENT }
+    }
+
     /// Get the current virtual address
     pub fn virt_addr(&self) -> usize {
         self.virtual_address & PF_NONE
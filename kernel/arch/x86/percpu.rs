@@ -0,0 +1,298 @@
+//! Data of each processor
+//!
+//! Every processor has its own `PerCpu`, found through the GS segment. The GDT of each processor
+//! has a descriptor at `GDT_PERCPU` whose base is the `PerCpu` of that processor, and the
+//! interrupt stubs load GS with it on entry to the kernel, so that a program changing GS does not
+//! affect the kernel. The first field of `PerCpu` is its own address, which `get` reads with
+//! `gs:[0]`. On x86_64 the base of a descriptor is 32 bits, so the data is allocated below 4 GiB.
+//!
+//! State that belongs to the running processor, like the context it runs and the TSS holding its
+//! kernel stack, is kept here instead of in `Environment`, which is shared by all processors. On
+//! x86_64 the kernel GS base is also set to the data, so that the SYSCALL entry can find the
+//! kernel stack with `swapgs` before any segment is loaded.
+
+use arch::context::Context;
+use arch::intex::Intex;
+#[cfg(debug)]
+use arch::lockdep::HeldLocks;
+use arch::memory;
+use arch::paging::Page;
+use arch::tss::Tss;
+
+use collections::vec::Vec;
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, ATOMIC_BOOL_INIT, Ordering};
+use core::{mem, ptr};
+
+/// Nothing is tracked without lockdep
+#[cfg(not(debug))]
+pub type HeldLocks = ();
+
+/// The selector of the per-CPU descriptor
+#[cfg(target_arch = "x86")]
+pub const GDT_PERCPU: u16 = 0x30;
+/// The selector of the per-CPU descriptor, after the 16 byte TSS descriptor and 32-bit user code
+#[cfg(target_arch = "x86_64")]
+pub const GDT_PERCPU: u16 = 0x40;
+
+/// The largest number of processors that are used
+pub const MAX_CPUS: usize = 64;
+
+/// Where the temporary mapping slots start, `TEMP_SLOTS` pages for each processor
+pub const TEMP_ADDR: usize = 0xAFE00000;
+/// The number of temporary mapping slots of each processor
+pub const TEMP_SLOTS: usize = 4;
+
+/// The operand of `sgdt`, with the base truncated to 32 bits on x86
+#[repr(packed)]
+#[derive(Copy, Clone, Default)]
+struct DescriptorTablePointer {
+    limit: u16,
+    base: u64,
+}
+
+/// Counters of a processor, summed over processors by `sys:stats` and `interrupt:`
+pub struct CpuStats {
+    /// Switches away from a context that blocked, slept or yielded
+    pub voluntary: u64,
+    /// Switches away from a context at the end of its time slice
+    pub preempted: u64,
+    /// Interrupts by vector
+    pub interrupts: [u64; 256],
+}
+
+impl CpuStats {
+    /// Count a context switch
+    pub fn switch(&mut self, preempted: bool) {
+        if preempted {
+            self.preempted += 1;
+        } else {
+            self.voluntary += 1;
+        }
+    }
+}
+
+/// The data of one processor, `repr(C)` so that `this` is first, and the fields used by the
+/// SYSCALL entry and the interrupt return in `asm/interrupts-x86_64.asm` stay at the offsets
+/// they use
+#[repr(C)]
+pub struct PerCpu {
+    /// The address of this structure, read through GS
+    pub this: usize,
+    /// The number of the processor, 0 for the bootstrap processor
+    pub cpu_id: usize,
+    /// The context running on this processor, its idle context until the first switch
+    pub current: *mut Context,
+    /// The TSS of this processor, which holds the kernel stack of the running context
+    pub tss: *mut Tss,
+    /// The user stack pointer, saved by the SYSCALL entry while it switches stacks
+    pub syscall_sp: usize,
+    /// The thread pointer of the running context, which the FS base is set to on return to user
+    /// mode
+    pub tls: usize,
+    /// The idle context of this processor, which runs when no other context can
+    pub idle: *mut Context,
+    /// The context last switched away from, which other processors may not run until this one
+    /// has finished saving its registers, at its next switch
+    pub previous: *mut Context,
+    /// The PID of the running context, as last switched to, so that tracepoints do not lock the
+    /// contexts
+    pub pid: usize,
+    /// The number of `Intex` guards held by the running context, saved in the context when it is
+    /// switched away from
+    pub locks: usize,
+    /// The locks held by this processor, for lockdep
+    pub held_locks: HeldLocks,
+    /// The context whose registers are loaded in the FPU, 0 if none
+    pub fpu_owner: AtomicUsize,
+    /// The context running, as last switched to, for the FPU trap
+    pub fpu_current: AtomicUsize,
+    /// Counters
+    pub stats: CpuStats,
+    /// A bit for each temporary mapping slot in use
+    temp_used: usize,
+}
+
+/// The `PerCpu` of each processor, by number
+static mut CPUS: [usize; MAX_CPUS] = [0; MAX_CPUS];
+
+/// Has the bootstrap processor set up its data, before then GS does not point to a `PerCpu`
+static READY: AtomicBool = ATOMIC_BOOL_INIT;
+
+/// Load GS with the per-CPU descriptor of the running processor, and list its data in `all`
+pub unsafe fn load() {
+    asm!("mov gs, $0" : : "r"(GDT_PERCPU as usize) : "memory" : "intel", "volatile");
+
+    let percpu: usize;
+    asm!("mov $0, gs:[0]" : "=r"(percpu) : : "memory" : "intel", "volatile");
+    CPUS[(*(percpu as *const PerCpu)).cpu_id] = percpu;
+
+    kernel_gs_set(percpu);
+}
+
+/// The kernel GS base, which `swapgs` exchanges with the GS base
+#[cfg(target_arch = "x86_64")]
+const MSR_KERNEL_GS_BASE: u32 = 0xC0000102;
+
+#[cfg(target_arch = "x86")]
+unsafe fn kernel_gs_set(_percpu: usize) {}
+
+/// Set the kernel GS base to the data at `percpu`, for the SYSCALL entry
+#[cfg(target_arch = "x86_64")]
+unsafe fn kernel_gs_set(percpu: usize) {
+    asm!("wrmsr" : : "{ecx}"(MSR_KERNEL_GS_BASE), "{eax}"(percpu as u32), "{edx}"((percpu as u64 >> 32) as u32)
+         : "memory" : "intel", "volatile");
+}
+
+/// Allocate the data of processor `cpu_id`, which uses the TSS at `tss`. Returns 0 if it could not
+/// be allocated
+pub unsafe fn alloc(cpu_id: usize, tss: usize) -> usize {
+    if cpu_id >= MAX_CPUS {
+        return 0;
+    }
+
+    let percpu = memory::alloc_type::<PerCpu>();
+    if percpu.is_null() || percpu as u64 + mem::size_of::<PerCpu>() as u64 > 0x100000000 {
+        if ! percpu.is_null() {
+            memory::unalloc_type(percpu);
+        }
+        return 0;
+    }
+    ptr::write(percpu, PerCpu {
+        this: percpu as usize,
+        cpu_id: cpu_id,
+        current: ptr::null_mut(),
+        tss: tss as *mut Tss,
+        syscall_sp: 0,
+        tls: 0,
+        idle: ptr::null_mut(),
+        previous: ptr::null_mut(),
+        pid: 0,
+        locks: 0,
+        held_locks: Default::default(),
+        fpu_owner: AtomicUsize::new(0),
+        fpu_current: AtomicUsize::new(0),
+        stats: CpuStats {
+            voluntary: 0,
+            preempted: 0,
+            interrupts: [0; 256],
+        },
+        temp_used: 0,
+    });
+    percpu as usize
+}
+
+/// Point the per-CPU descriptor of the GDT at `gdt` to the data at `percpu`
+pub unsafe fn descriptor_set(gdt: usize, percpu: usize) {
+    let descriptor = (gdt + GDT_PERCPU as usize) as *mut u8;
+    *descriptor.offset(2) = percpu as u8;
+    *descriptor.offset(3) = (percpu >> 8) as u8;
+    *descriptor.offset(4) = (percpu >> 16) as u8;
+    *descriptor.offset(7) = (percpu >> 24) as u8;
+}
+
+/// Set up the data of the bootstrap processor, which uses the TSS at `tss`
+pub unsafe fn init(tss: usize) {
+    let percpu = alloc(0, tss);
+    if percpu == 0 {
+        panic!("percpu: failed to allocate the data of the bootstrap processor");
+    }
+
+    let mut gdtr = DescriptorTablePointer::default();
+    asm!("sgdt [$0]" : : "r"(&mut gdtr as *mut DescriptorTablePointer) : "memory" : "intel", "volatile");
+    descriptor_set(gdtr.base as usize, percpu);
+    load();
+
+    // The temporary mapping slots hide the memory identity mapped there
+    memory::reserve(TEMP_ADDR, MAX_CPUS * TEMP_SLOTS * 4096);
+
+    READY.store(true, Ordering::SeqCst);
+}
+
+/// The data of the running processor, `None` until the bootstrap processor has called `init`
+pub fn get() -> Option<&'static mut PerCpu> {
+    if READY.load(Ordering::SeqCst) {
+        let percpu: usize;
+        unsafe {
+            asm!("mov $0, gs:[0]" : "=r"(percpu) : : "memory" : "intel", "volatile");
+            Some(&mut *(percpu as *mut PerCpu))
+        }
+    } else {
+        None
+    }
+}
+
+/// The number of the running processor, 0 until the bootstrap processor has called `init`
+pub fn cpu_id() -> usize {
+    get().map_or(0, |percpu| percpu.cpu_id)
+}
+
+/// The data of every processor that has called `load`
+pub fn all() -> Vec<&'static PerCpu> {
+    let mut cpus = Vec::new();
+    for &percpu in unsafe { CPUS.iter() } {
+        if percpu > 0 {
+            cpus.push(unsafe { &*(percpu as *const PerCpu) });
+        }
+    }
+    cpus
+}
+
+/// The interrupts by vector, summed over processors
+pub fn interrupts() -> [u64; 256] {
+    let mut interrupts = [0; 256];
+    for percpu in all().iter() {
+        for (total, count) in interrupts.iter_mut().zip(percpu.stats.interrupts.iter()) {
+            *total += *count;
+        }
+    }
+    interrupts
+}
+
+/// A page mapped into a temporary mapping slot of the running processor
+///
+/// The slot belongs to the processor, so the mapping must be dropped before the context switches,
+/// and is only valid on the processor that made it.
+pub struct TempMapping {
+    address: usize,
+    slot: usize,
+}
+
+impl TempMapping {
+    /// Map the page at `physical_address` into a free slot, returning `None` if all are in use
+    pub unsafe fn new(physical_address: usize) -> Option<TempMapping> {
+        let _intex = Intex::static_lock();
+
+        if let Some(percpu) = get() {
+            let slot = (! percpu.temp_used).trailing_zeros() as usize;
+            if slot < TEMP_SLOTS {
+                percpu.temp_used |= 1 << slot;
+
+                let address = TEMP_ADDR + (percpu.cpu_id * TEMP_SLOTS + slot) * 4096;
+                Page::new(address).map_kernel_write(physical_address);
+                return Some(TempMapping {
+                    address: address,
+                    slot: slot,
+                });
+            }
+        }
+
+        None
+    }
+
+    /// The virtual address of the page
+    pub fn address(&self) -> usize {
+        self.address
+    }
+}
+
+impl Drop for TempMapping {
+    fn drop(&mut self) {
+        let _intex = Intex::static_lock();
+
+        unsafe { Page::new(self.address).map_kernel_write(self.address) };
+        if let Some(percpu) = get() {
+            percpu.temp_used &= ! (1 << self.slot);
+        }
+    }
+}
@@ -0,0 +1,54 @@
+//! The interrupt controller, a pair of cascaded 8259 PICs
+
+use drivers::io::{Io, Pio};
+
+/// The interrupt that IRQ 0 is delivered on
+pub const IRQ_BASE: usize = 0x20;
+/// The number of IRQs
+pub const IRQ_COUNT: usize = 16;
+/// The IRQ of the timer, which drives the clock and preemption
+pub const TIMER_IRQ: usize = 0;
+
+/// Get the IRQ delivered on `interrupt`, if it is one
+pub fn irq(interrupt: usize) -> Option<usize> {
+    if interrupt >= IRQ_BASE && interrupt < IRQ_BASE + IRQ_COUNT {
+        Some(interrupt - IRQ_BASE)
+    } else {
+        None
+    }
+}
+
+/// Acknowledge `irq`, so that the controller delivers it again
+pub unsafe fn eoi(irq: usize) {
+    if irq >= 8 {
+        Pio::<u8>::new(0xA0).write(0x20);
+    }
+
+    Pio::<u8>::new(0x20).write(0x20);
+}
+
+/// Set up the controllers like `initialize.asm`, with IRQs at `IRQ_BASE` and none masked
+///
+/// The boot loader does this at boot, it is done again when waking from a sleep state.
+pub unsafe fn init() {
+    let mut master_cmd = Pio::<u8>::new(0x20);
+    let mut master_data = Pio::<u8>::new(0x21);
+    let mut slave_cmd = Pio::<u8>::new(0xA0);
+    let mut slave_data = Pio::<u8>::new(0xA1);
+
+    master_cmd.write(0x11);
+    slave_cmd.write(0x11);
+    master_data.write(IRQ_BASE as u8);
+    slave_data.write(IRQ_BASE as u8 + 8);
+    // The slave is cascaded on IRQ 2
+    master_data.write(4);
+    slave_data.write(2);
+    master_data.write(1);
+    slave_data.write(1);
+
+    master_data.write(0);
+    slave_data.write(0);
+
+    slave_cmd.write(0x20);
+    master_cmd.write(0x20);
+}
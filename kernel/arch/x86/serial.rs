@@ -0,0 +1,20 @@
+//! Debug output on the first serial port
+
+use drivers::io::{Io, Pio};
+
+/// Write a byte to COM1, erasing the previous character on backspace
+pub fn write(byte: u8) {
+    let serial_status = Pio::<u8>::new(0x3F8 + 5);
+    let mut serial_data = Pio::<u8>::new(0x3F8);
+
+    while !serial_status.readf(0x20) {}
+    serial_data.write(byte);
+
+    if byte == 8 {
+        while !serial_status.readf(0x20) {}
+        serial_data.write(0x20);
+
+        while !serial_status.readf(0x20) {}
+        serial_data.write(8);
+    }
+}
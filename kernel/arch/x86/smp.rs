@@ -0,0 +1,347 @@
+//! Starting the application processors
+//!
+//! Each enabled processor in the MADT is started with an INIT and startup IPIs, running
+//! `asm/trampoline.asm` from `TRAMPOLINE`. It gets its own GDT, TSS, kernel stack and per-CPU
+//! data, shares the IDT and page tables of the bootstrap processor, and enters the idle loop as
+//! its own idle context, with its local APIC timer ending time slices.
+//!
+//! The application processors run the contexts they take from the run queues of the others, which
+//! are only those without user memory, see `ContextManager`. User memory is mapped into the shared
+//! page tables each time a context is switched to, so processes stay on the bootstrap processor.
+
+use acpi::MADT;
+
+use arch::apic::LocalApic;
+use arch::context::{kernel_stack_alloc, Context, CONTEXT_STACK_SIZE};
+use arch::memory;
+use arch::percpu::{self, MAX_CPUS};
+use arch::tss::Tss;
+
+use common::trace;
+
+use core::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
+use core::{cmp, mem, ptr};
+
+use drivers::io::{Io, Pio};
+
+use env::clock::PIT_DURATION;
+
+/// Where the trampoline is copied, page aligned and below 1 MiB
+pub const TRAMPOLINE: usize = 0x6000;
+
+static TRAMPOLINE_DATA: &'static [u8] = include_bytes!("../../../build/trampoline.bin");
+
+/// The offsets of the arguments in the trampoline
+const ARG_READY: usize = 8;
+const ARG_CPU_ID: usize = 16;
+const ARG_PAGE_TABLE: usize = 24;
+const ARG_STACK_END: usize = 32;
+const ARG_CODE: usize = 40;
+const ARG_GDTR: usize = 48;
+const ARG_IDTR: usize = 58;
+
+/// The selector of the TSS, the same in the GDT of x86 and x86_64
+const GDT_TSS: u16 = 0x28;
+
+/// The input frequency of the PIT
+const PIT_HZ: u64 = 1193182;
+
+/// The operand of `lgdt`, `lidt`, `sgdt` and `sidt`, with the base truncated to 32 bits on x86
+#[repr(packed)]
+#[derive(Copy, Clone, Default)]
+struct DescriptorTablePointer {
+    limit: u16,
+    base: u64,
+}
+
+/// The number of processors running, including the bootstrap processor
+static CPU_COUNT: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// The address of the local APIC, the same for every processor
+static mut LOCAL_APIC: usize = 0;
+
+/// The count of the local APIC timer for one PIT tick
+static mut TIMER_COUNT: u32 = 0;
+
+/// The number of processors running
+pub fn cpu_count() -> usize {
+    cmp::max(1, CPU_COUNT.load(Ordering::SeqCst))
+}
+
+/// The local APIC of the running processor
+pub unsafe fn local_apic() -> LocalApic {
+    LocalApic::new(LOCAL_APIC)
+}
+
+/// Reset the application processors, before the kernel is replaced by kexec or the machine
+/// enters a sleep state
+pub unsafe fn stop() {
+    if LOCAL_APIC > 0 && cpu_count() > 1 {
+        local_apic().ipi_init_others();
+        CPU_COUNT.store(1, Ordering::SeqCst);
+        ::env().contexts.write().reset_others();
+    }
+}
+
+/// Wait for `micros` microseconds, up to 54 milliseconds, with channel 2 of the PIT
+unsafe fn delay(micros: u64) {
+    let count = cmp::min(0xFFFF, micros * PIT_HZ / 1000000) as u16;
+
+    // Enable the gate of channel 2, with the speaker off
+    let mut gate = Pio::<u8>::new(0x61);
+    let value = gate.read();
+    gate.write((value & !0x02) | 0x01);
+
+    // Channel 2, low and high byte, interrupt on terminal count
+    Pio::<u8>::new(0x43).write(0xB0);
+    let mut channel = Pio::<u8>::new(0x42);
+    channel.write(count as u8);
+    channel.write((count >> 8) as u8);
+
+    while gate.read() & 0x20 == 0 {}
+}
+
+unsafe fn trampoline_write<T>(offset: usize, value: T) {
+    ptr::write_volatile((TRAMPOLINE + offset) as *mut T, value);
+}
+
+unsafe fn trampoline_read<T>(offset: usize) -> T {
+    ptr::read_volatile((TRAMPOLINE + offset) as *const T)
+}
+
+#[cfg(target_arch = "x86")]
+unsafe fn syscall_save() {}
+
+#[cfg(target_arch = "x86")]
+unsafe fn syscall_init() {}
+
+/// STAR, LSTAR and FMASK, which set up the SYSCALL instruction
+#[cfg(target_arch = "x86_64")]
+const SYSCALL_MSRS: [u32; 3] = [0xC0000081, 0xC0000082, 0xC0000084];
+
+#[cfg(target_arch = "x86_64")]
+static mut SYSCALL_VALUES: [u64; 3] = [0; 3];
+
+/// Save the SYSCALL setup of the bootstrap processor
+#[cfg(target_arch = "x86_64")]
+unsafe fn syscall_save() {
+    for (msr, value) in SYSCALL_MSRS.iter().zip(SYSCALL_VALUES.iter_mut()) {
+        let low: u32;
+        let high: u32;
+        asm!("rdmsr" : "={eax}"(low), "={edx}"(high) : "{ecx}"(*msr) : "memory" : "intel", "volatile");
+        *value = (high as u64) << 32 | low as u64;
+    }
+}
+
+/// Copy the SYSCALL setup of the bootstrap processor, the trampoline has enabled the instruction
+#[cfg(target_arch = "x86_64")]
+unsafe fn syscall_init() {
+    for (msr, value) in SYSCALL_MSRS.iter().zip(SYSCALL_VALUES.iter()) {
+        asm!("wrmsr" : : "{ecx}"(*msr), "{eax}"(*value as u32), "{edx}"((*value >> 32) as u32) : "memory" : "intel", "volatile");
+    }
+}
+
+/// Copy the trampoline, to call `code` with the page tables and IDT of the bootstrap processor
+unsafe fn trampoline_init(code: usize) {
+    syscall_save();
+
+    ptr::copy(TRAMPOLINE_DATA.as_ptr(), TRAMPOLINE as *mut u8, TRAMPOLINE_DATA.len());
+
+    let page_table: usize;
+    asm!("mov $0, cr3" : "=r"(page_table) : : "memory" : "intel", "volatile");
+    trampoline_write(ARG_PAGE_TABLE, page_table as u64);
+    trampoline_write(ARG_CODE, code as u64);
+
+    let mut idtr = DescriptorTablePointer::default();
+    asm!("sidt [$0]" : : "r"(&mut idtr as *mut DescriptorTablePointer) : "memory" : "intel", "volatile");
+    trampoline_write(ARG_IDTR, idtr);
+}
+
+/// The GDT of the running processor
+unsafe fn gdtr() -> DescriptorTablePointer {
+    let mut gdtr = DescriptorTablePointer::default();
+    asm!("sgdt [$0]" : : "r"(&mut gdtr as *mut DescriptorTablePointer) : "memory" : "intel", "volatile");
+    gdtr
+}
+
+/// Set up the trampoline to wake the bootstrap processor from a sleep state, calling `code` on
+/// `stack_end` with the GDT it has now. The firmware must jump to `TRAMPOLINE` on wake
+pub unsafe fn wake_prepare(code: usize, stack_end: usize) {
+    trampoline_init(code);
+
+    trampoline_write(ARG_READY, 0u64);
+    trampoline_write(ARG_CPU_ID, 0u64);
+    trampoline_write(ARG_STACK_END, stack_end as u64);
+    trampoline_write(ARG_GDTR, gdtr());
+}
+
+/// Restore the TSS and SYSCALL setup of the bootstrap processor, called by the code passed to
+/// `wake_prepare`
+pub unsafe fn wake_init() {
+    // The TSS is still marked busy in the GDT, from before the sleep state
+    let descriptor = (gdtr().base as usize + GDT_TSS as usize) as *mut u8;
+    *descriptor.offset(5) &= !0x02;
+    asm!("ltr $0" : : "r"(GDT_TSS) : "memory" : "intel", "volatile");
+
+    percpu::load();
+    syscall_init();
+}
+
+/// Allocate a TSS for a processor, a copy of the bootstrap TSS with its own kernel stack
+unsafe fn tss_alloc(stack_end: usize) -> usize {
+    let size = mem::size_of::<Tss>();
+    let tss = memory::alloc(size);
+    if tss > 0 {
+        ptr::write_bytes(tss as *mut u8, 0, size);
+        if let Some(ref bsp_tss) = ::TSS_PTR {
+            ptr::copy(&**bsp_tss as *const Tss as *const u8, tss as *mut u8, size);
+        }
+        (*(tss as *mut Tss)).sp0 = stack_end;
+    }
+    tss
+}
+
+/// Allocate a GDT for a processor, a copy of the bootstrap GDT with the TSS descriptor pointing
+/// to `tss`
+unsafe fn gdt_alloc(tss: usize) -> Option<DescriptorTablePointer> {
+    let bsp_gdtr = gdtr();
+
+    let size = bsp_gdtr.limit as usize + 1;
+    let gdt = memory::alloc(size);
+    if gdt == 0 {
+        return None;
+    }
+    ptr::copy(bsp_gdtr.base as usize as *const u8, gdt as *mut u8, size);
+
+    let descriptor = (gdt + GDT_TSS as usize) as *mut u8;
+    *descriptor.offset(2) = tss as u8;
+    *descriptor.offset(3) = (tss >> 8) as u8;
+    *descriptor.offset(4) = (tss >> 16) as u8;
+    // The bootstrap TSS is busy since it was loaded, the copy must be available to be loaded
+    *descriptor.offset(5) &= !0x02;
+    *descriptor.offset(7) = (tss >> 24) as u8;
+    // The TSS descriptor is 16 bytes on x86_64, with the high half of the base after it
+    if mem::size_of::<usize>() == 8 {
+        ptr::write(descriptor.offset(8) as *mut u32, (tss as u64 >> 32) as u32);
+    }
+
+    Some(DescriptorTablePointer {
+        limit: bsp_gdtr.limit,
+        base: gdt as u64,
+    })
+}
+
+/// Start the processor `apic_id` as number `cpu_id`, returning false if it did not start
+unsafe fn start(apic: &LocalApic, cpu_id: usize, apic_id: u8) -> bool {
+    let stack = kernel_stack_alloc();
+    if stack == 0 {
+        return false;
+    }
+    let stack_end = stack + CONTEXT_STACK_SIZE - 128;
+
+    let tss = tss_alloc(stack_end);
+    if tss == 0 {
+        return false;
+    }
+
+    let percpu = percpu::alloc(cpu_id, tss);
+    if percpu == 0 {
+        return false;
+    }
+
+    let gdtr = match gdt_alloc(tss) {
+        Some(gdtr) => gdtr,
+        None => return false,
+    };
+    percpu::descriptor_set(gdtr.base as usize, percpu);
+
+    trampoline_write(ARG_READY, 0u64);
+    trampoline_write(ARG_CPU_ID, cpu_id as u64);
+    trampoline_write(ARG_STACK_END, stack_end as u64);
+    trampoline_write(ARG_GDTR, gdtr);
+
+    let count = CPU_COUNT.load(Ordering::SeqCst);
+
+    apic.ipi_init(apic_id);
+    delay(10000);
+
+    // A second startup IPI is sent if the first is missed, as the MP specification recommends
+    for _ in 0..2 {
+        apic.ipi_startup(apic_id, (TRAMPOLINE / 4096) as u8);
+        delay(200);
+        if trampoline_read::<u64>(ARG_READY) != 0 {
+            break;
+        }
+    }
+
+    // Wait up to 100 ms for it to reach the idle loop
+    for _ in 0..100 {
+        if CPU_COUNT.load(Ordering::SeqCst) > count {
+            return true;
+        }
+        delay(1000);
+    }
+
+    false
+}
+
+/// The entry of each application processor, called by the trampoline on its own stack
+extern "C" fn ap_main(_cpu_id: usize) -> ! {
+    unsafe {
+        asm!("ltr $0" : : "r"(GDT_TSS) : "memory" : "intel", "volatile");
+
+        percpu::load();
+        syscall_init();
+
+        let apic = local_apic();
+        apic.init();
+        apic.timer_periodic(TIMER_COUNT);
+
+        let mut idle = Context::root();
+        idle.name = format!("kidle{}", percpu::cpu_id());
+        ::env().contexts.write().push_idle(idle);
+        trace::start_cpu(percpu::cpu_id());
+
+        CPU_COUNT.fetch_add(1, Ordering::SeqCst);
+
+        apic.timer_unmask();
+        ::idle_loop();
+    }
+}
+
+/// Start the application processors listed in `madt`
+///
+/// This is done again after waking from a sleep state, which resets them. The stacks, TSS and GDT
+/// they had before are not freed.
+pub unsafe fn init(madt: &MADT) {
+    CPU_COUNT.store(1, Ordering::SeqCst);
+
+    LOCAL_APIC = madt.local_apic_address as usize;
+    let apic = local_apic();
+    apic.init();
+
+    // Measure the timer against the PIT, all local APIC timers run from the same bus clock
+    apic.timer_start(0xFFFFFFFF);
+    delay(10000);
+    let ticks = 0xFFFFFFFF - apic.timer_current();
+    apic.timer_start(0);
+    TIMER_COUNT = (ticks as u64 * PIT_DURATION.nanos as u64 / 10000000) as u32;
+
+    trampoline_init(ap_main as usize);
+
+    let bsp_id = apic.id();
+    let mut cpu_id = 1;
+    for local_apic in madt.local_apics.iter() {
+        let id = local_apic.id;
+        // Processors that are not enabled cannot be started
+        if local_apic.flags & 1 == 1 && id != bsp_id && cpu_id < MAX_CPUS {
+            if start(&apic, cpu_id, id) {
+                cpu_id += 1;
+            } else {
+                debugln!("SMP: Processor with APIC ID {} did not start", id);
+            }
+        }
+    }
+
+    debugln!("SMP: {} processors", cpu_count());
+}
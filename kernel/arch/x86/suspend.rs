@@ -0,0 +1,137 @@
+//! Saving and restoring the processor around a sleep state
+//!
+//! In a sleep state like ACPI S3 memory is kept, but the processors, the interrupt controller and
+//! the timer lose their state. Every register is pushed on the stack before the sleep state is
+//! entered. On wake the firmware jumps to `asm/trampoline.asm` in real mode, which returns to
+//! protected or long mode and calls `resume_main`. That restores what the trampoline does not
+//! and pops the registers, returning from `suspend` as if the sleep state had been left
+//! immediately.
+
+use arch::context::{self, kernel_stack_alloc, kernel_stack_unalloc, CONTEXT_STACK_SIZE};
+use arch::cpu;
+use arch::interrupt;
+use arch::smp;
+
+use drivers::io::{Io, Pio};
+
+/// The divider of the PIT, the same as `initialize.asm`
+const PIT_DIVIDER: u16 = 5370;
+
+/// The stack pointer after the registers were pushed by `save`, read by `resume_main`
+static mut SAVED_SP: usize = 0;
+
+/// The address the firmware must jump to on wake, in real mode
+pub fn waking_vector() -> usize {
+    smp::TRAMPOLINE
+}
+
+/// Set up channel 0 of the PIT like `initialize.asm`
+unsafe fn pit_init() {
+    // Channel 0, low and high byte, square wave
+    Pio::<u8>::new(0x43).write(0x36);
+    let mut channel = Pio::<u8>::new(0x40);
+    channel.write(PIT_DIVIDER as u8);
+    channel.write((PIT_DIVIDER >> 8) as u8);
+}
+
+/// Push every register and call `sleep(arg)`, returning true when woken by `resume_main`, or
+/// false if `sleep` returned
+#[cfg(target_arch = "x86")]
+#[inline(never)]
+unsafe fn save(sleep: extern "C" fn(usize), arg: usize) -> bool {
+    let resumed: usize;
+    asm!("pushad
+        call 1f
+        popad
+        mov eax, 1
+        jmp 2f
+    1:
+        mov [ecx], esp
+        push edx
+        call eax
+        add esp, 8
+        popad
+        xor eax, eax
+    2:"
+        : "={eax}"(resumed)
+        : "{eax}"(sleep as usize), "{ecx}"(&mut SAVED_SP as *mut usize), "{edx}"(arg)
+        : "memory", "cc" : "intel", "volatile");
+    resumed == 1
+}
+
+/// Push every register and call `sleep(arg)`, returning true when woken by `resume_main`, or
+/// false if `sleep` returned
+#[cfg(target_arch = "x86_64")]
+#[inline(never)]
+unsafe fn save(sleep: extern "C" fn(usize), arg: usize) -> bool {
+    let resumed: usize;
+    asm!("push rax ; push rbx ; push rcx ; push rdx ; push rsi ; push rdi ; push rbp
+        push r8 ; push r9 ; push r10 ; push r11 ; push r12 ; push r13 ; push r14 ; push r15
+        call 1f
+        pop r15 ; pop r14 ; pop r13 ; pop r12 ; pop r11 ; pop r10 ; pop r9 ; pop r8
+        pop rbp ; pop rdi ; pop rsi ; pop rdx ; pop rcx ; pop rbx ; pop rax
+        mov rax, 1
+        jmp 2f
+    1:
+        mov [rcx], rsp
+        mov rbx, rsp
+        and rsp, -16
+        call rax
+        mov rsp, rbx
+        add rsp, 8
+        pop r15 ; pop r14 ; pop r13 ; pop r12 ; pop r11 ; pop r10 ; pop r9 ; pop r8
+        pop rbp ; pop rdi ; pop rsi ; pop rdx ; pop rcx ; pop rbx ; pop rax
+        xor rax, rax
+    2:"
+        : "={rax}"(resumed)
+        : "{rax}"(sleep as usize), "{rcx}"(&mut SAVED_SP as *mut usize), "{rdi}"(arg)
+        : "memory", "cc" : "intel", "volatile");
+    resumed == 1
+}
+
+/// Called by the trampoline on wake, on the stack allocated by `suspend`
+extern "C" fn resume_main(_cpu_id: usize) -> ! {
+    unsafe {
+        smp::wake_init();
+        context::fpu_resume();
+
+        // Return into `save`, after the call that pushed the return address at `SAVED_SP`
+        #[cfg(target_arch = "x86")]
+        asm!("mov esp, $0 ; ret" : : "r"(SAVED_SP) : "memory" : "intel", "volatile");
+        #[cfg(target_arch = "x86_64")]
+        asm!("mov rsp, $0 ; ret" : : "r"(SAVED_SP) : "memory" : "intel", "volatile");
+
+        loop {
+            cpu::halt();
+        }
+    }
+}
+
+/// Stop the application processors, save the registers and call `sleep(arg)`, which must enter a
+/// sleep state that jumps to `waking_vector` on wake
+///
+/// Interrupts are disabled, and left disabled. Returns true after waking, with the interrupt
+/// controller and the timer set up again, or false if `sleep` returned. Either way the
+/// application processors are left stopped.
+pub unsafe fn suspend(sleep: extern "C" fn(usize), arg: usize) -> bool {
+    cpu::interrupts_disable();
+
+    let stack = kernel_stack_alloc();
+    if stack == 0 {
+        return false;
+    }
+
+    smp::stop();
+    context::fpu_suspend();
+    smp::wake_prepare(resume_main as usize, stack + CONTEXT_STACK_SIZE - 128);
+
+    let resumed = save(sleep, arg);
+    if resumed {
+        interrupt::init();
+        pit_init();
+    }
+
+    kernel_stack_unalloc(stack);
+
+    resumed
+}
@@ -0,0 +1,131 @@
+//! Copying to and from user memory
+//!
+//! Syscalls check user pointers against the memory of the context, but a page can still fault
+//! by the time it is used, if another thread of the context unmapped it for example. `copy` is
+//! the only code that should touch user memory from a syscall. It pushes every register and keeps
+//! the stack pointer in `RECOVER_SP`, like `suspend` does, so that a fault while copying is
+//! returned from by `recover` with `EFAULT` instead of panicking. The copy runs with interrupts
+//! disabled, so nothing else runs on this processor while `RECOVER_SP` is set.
+//!
+//! When the processor supports SMAP, the AC flag is set with `stac` for the copy alone.
+
+use core::sync::atomic::{AtomicBool, ATOMIC_BOOL_INIT, Ordering};
+
+use system::error::{Error, Result, EFAULT};
+
+/// The stack pointer in `copy` after the registers were pushed, 0 when not copying
+static mut RECOVER_SP: usize = 0;
+
+/// Does the processor support SMAP
+static SMAP: AtomicBool = ATOMIC_BOOL_INIT;
+
+/// Detect SMAP, with CPUID leaf 7
+pub unsafe fn init() {
+    let max: u32;
+    asm!("cpuid" : "={eax}"(max) : "{eax}"(0) : "ebx", "ecx", "edx" : "intel", "volatile");
+    if max >= 7 {
+        let ebx: u32;
+        asm!("cpuid" : "={ebx}"(ebx) : "{eax}"(7), "{ecx}"(0) : "eax", "edx" : "intel", "volatile");
+        SMAP.store(ebx & 1 << 20 == 1 << 20, Ordering::SeqCst);
+    }
+}
+
+/// Copy `len` bytes from `src` to `dst`, returning true if a fault stopped the copy
+#[cfg(target_arch = "x86")]
+#[inline(never)]
+unsafe fn copy_inner(dst: usize, src: usize, len: usize, smap: bool) -> bool {
+    let faulted: usize;
+    asm!("pushfd
+        pushad
+        cli
+        call 1f
+        popad
+        popfd
+        mov eax, 1
+        jmp 4f
+    1:
+        mov [edx], esp
+        test eax, eax
+        jz 2f
+        stac
+    2:
+        rep movsb
+        test eax, eax
+        jz 3f
+        clac
+    3:
+        mov dword ptr [edx], 0
+        add esp, 4
+        popad
+        popfd
+        xor eax, eax
+    4:"
+        : "={eax}"(faulted)
+        : "{edi}"(dst), "{esi}"(src), "{ecx}"(len), "{edx}"(&mut RECOVER_SP as *mut usize), "{eax}"(smap as usize)
+        : "memory", "cc" : "intel", "volatile");
+    faulted == 1
+}
+
+/// Copy `len` bytes from `src` to `dst`, returning true if a fault stopped the copy
+#[cfg(target_arch = "x86_64")]
+#[inline(never)]
+unsafe fn copy_inner(dst: usize, src: usize, len: usize, smap: bool) -> bool {
+    let faulted: usize;
+    asm!("pushfq
+        push rax ; push rbx ; push rcx ; push rdx ; push rsi ; push rdi ; push rbp
+        cli
+        call 1f
+        pop rbp ; pop rdi ; pop rsi ; pop rdx ; pop rcx ; pop rbx ; pop rax
+        popfq
+        mov rax, 1
+        jmp 4f
+    1:
+        mov [rdx], rsp
+        test rax, rax
+        jz 2f
+        stac
+    2:
+        rep movsb
+        test rax, rax
+        jz 3f
+        clac
+    3:
+        mov qword ptr [rdx], 0
+        add rsp, 8
+        pop rbp ; pop rdi ; pop rsi ; pop rdx ; pop rcx ; pop rbx ; pop rax
+        popfq
+        xor rax, rax
+    4:"
+        : "={rax}"(faulted)
+        : "{rdi}"(dst), "{rsi}"(src), "{rcx}"(len), "{rdx}"(&mut RECOVER_SP as *mut usize), "{rax}"(smap as usize)
+        : "memory", "cc" : "intel", "volatile");
+    faulted == 1
+}
+
+/// Copy `len` bytes from `src` to `dst`, failing with `EFAULT` if either faults
+///
+/// The caller must have checked that the user side lies in the memory of the current context.
+pub unsafe fn copy(dst: usize, src: usize, len: usize) -> Result<()> {
+    if len == 0 || ! copy_inner(dst, src, len, SMAP.load(Ordering::SeqCst)) {
+        Ok(())
+    } else {
+        Err(Error::new(EFAULT))
+    }
+}
+
+/// Called on an exception in kernel mode, returning into `copy` if it caused the exception
+///
+/// The registers and flags pushed by `copy` are restored, which turns interrupts back on if they
+/// were on, and clears the AC flag.
+pub unsafe fn recover() {
+    if RECOVER_SP != 0 {
+        let sp = RECOVER_SP;
+        RECOVER_SP = 0;
+
+        // Return after the call in `copy`, which pushed the return address at `sp`
+        #[cfg(target_arch = "x86")]
+        asm!("mov esp, $0 ; ret" : : "r"(sp) : "memory" : "intel", "volatile");
+        #[cfg(target_arch = "x86_64")]
+        asm!("mov rsp, $0 ; ret" : : "r"(sp) : "memory" : "intel", "volatile");
+    }
+}
@@ -0,0 +1,54 @@
+use arch::regs::Regs;
+
+use collections::Vec;
+
+use core::{mem, slice};
+
+/// The process status note of a core file, laid out as `elf_prstatus` on x86_64
+#[repr(packed)]
+struct PrStatus {
+    si_signo: u32,
+    si_code: u32,
+    si_errno: u32,
+    cursig: u16,
+    _pad: u16,
+    sigpend: u64,
+    sighold: u64,
+    pid: u32,
+    ppid: u32,
+    pgrp: u32,
+    sid: u32,
+    /// User, system, and children's user and system time
+    times: [u64; 8],
+    /// The registers, in the order of `user_regs_struct`
+    regs: [u64; 27],
+    fpvalid: u32,
+    _pad_end: u32,
+}
+
+/// Describe the registers of a context killed by `signal`
+pub fn prstatus(regs: &Regs, signal: usize, pid: usize, ppid: usize, pgid: usize) -> Vec<u8> {
+    let ds = regs.ss as u64;
+    let status = PrStatus {
+        si_signo: signal as u32,
+        si_code: 0,
+        si_errno: 0,
+        cursig: signal as u16,
+        _pad: 0,
+        sigpend: 0,
+        sighold: 0,
+        pid: pid as u32,
+        ppid: ppid as u32,
+        pgrp: pgid as u32,
+        sid: 0,
+        times: [0; 8],
+        regs: [regs.r15 as u64, regs.r14 as u64, regs.r13 as u64, regs.r12 as u64, regs.bp as u64, regs.bx as u64,
+               regs.r11 as u64, regs.r10 as u64, regs.r9 as u64, regs.r8 as u64, regs.ax as u64, regs.cx as u64,
+               regs.dx as u64, regs.si as u64, regs.di as u64, !0, regs.ip as u64, regs.cs as u64,
+               regs.flags as u64, regs.sp as u64, regs.ss as u64, 0, 0, ds, ds, 0, 0],
+        fpvalid: 0,
+        _pad_end: 0,
+    };
+
+    unsafe { slice::from_raw_parts(&status as *const PrStatus as *const u8, mem::size_of::<PrStatus>()) }.to_vec()
+}
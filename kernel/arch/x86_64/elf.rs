@@ -1,4 +1,6 @@
 pub const ELF_CLASS: u8 = 2;
+/// EM_X86_64
+pub const ELF_MACHINE: u16 = 62;
 pub type ElfAddr = u64;
 pub type ElfOff = u64;
 pub type ElfHalf = u16;
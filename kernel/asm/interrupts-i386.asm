@@ -9,19 +9,25 @@ endstruc
 SECTION .text
 USE32
 
+; Each entry pushes its vector, which is sign extended from a byte to keep the entries the same size
 interrupts:
 .first:
-    mov [.entry], byte 0
+    push byte 0
     jmp dword .handle
 .second:
 %assign i 1
 %rep 255
-    mov [.entry], byte i
+%if i < 128
+    push byte i
+%else
+    push byte i - 256
+%endif
     jmp dword .handle
 %assign i i+1
 %endrep
 .handle:
-    push ebp
+    ; swap the vector for EBP, the first register saved, so that the registers are above the frame
+    xchg ebp, [esp]
     push esi
     push edi
     push edx
@@ -30,12 +36,14 @@ interrupts:
     push eax
 
     push esp
-    push dword [.entry]
+    and ebp, 0xFF
+    push ebp
 
     mov eax, gdt.kernel_data
     mov ds, eax
     mov es, eax
     mov fs, eax
+    mov eax, gdt.kernel_percpu
     mov gs, eax
 
     	call dword [.handler]
@@ -59,7 +67,6 @@ interrupts:
     iretd
 
 .handler: dd 0
-.entry: dd 0
 
 idtr:
     dw (idt.end - idt) + 1
@@ -10,19 +10,25 @@ endstruc
 
 SECTION .text
 USE64
+; Each entry pushes its vector, which is sign extended from a byte to keep the entries the same size
 interrupts:
 .first:
-	mov [.entry], byte 0
+	push byte 0
     jmp qword .handle
 .second:
 %assign i 1
 %rep 255
-	mov [.entry], byte i
+%if i < 128
+	push byte i
+%else
+	push byte i - 256
+%endif
     jmp qword .handle
 %assign i i+1
 %endrep
 .handle:
-	push rbp
+	; swap the vector for RBP, the first register saved, so that the registers are above the frame
+	xchg rbp, [rsp]
 	push r15
 	push r14
 	push r13
@@ -40,13 +46,15 @@ interrupts:
 
 	mov rsi, rsp
 	push rsi
-	mov rdi, qword [.entry]
+	and rbp, 0xFF
+	mov rdi, rbp
 	push rdi
 
     mov rax, gdt.kernel_data
     mov ds, rax
     mov es, rax
     mov fs, rax
+    mov rax, gdt.kernel_percpu
     mov gs, rax
 
 		call qword [.handler]
@@ -55,6 +63,13 @@ interrupts:
     mov ds, rax
     mov es, rax
     mov fs, rax
+    ; loading FS cleared its base, set it to the thread pointer of the context while GS still
+    ; points to the PerCpu. RAX, RCX and RDX are restored from the frame below
+    mov ecx, 0xC0000100
+    mov eax, [gs:PERCPU_TLS]
+    mov edx, [gs:PERCPU_TLS + 4]
+    wrmsr
+    mov rax, gdt.user_data | 3
     mov gs, rax
 
 	add rsp, 16 ; Skip interrupt code and reg pointer
@@ -78,7 +93,6 @@ interrupts:
     iretq
 
 .handler: dq 0
-.entry: dq 0
 
 idtr:
     dw (idt.end - idt) + 1
@@ -127,3 +141,29 @@ iend
 %assign i i+1
 %endrep
 .end:
+
+; Entry for SYSCALL from 64-bit programs
+; SYSCALL leaves the return address in RCX and the flags in R11, and does not switch stacks, so
+; an interrupt frame is built on the kernel stack from the TSS and the call is handled like
+; int 0x80. The second argument comes in R10 and is moved to RCX, where the handler expects it.
+; Interrupts are masked on entry by the FMASK MSR, so the saved user stack pointer cannot be
+; overwritten before it is pushed. The TSS and the place to save the user stack pointer are found
+; in the PerCpu of the processor, which the kernel GS base points to.
+PERCPU_TSS equ 24
+PERCPU_SYSCALL_SP equ 32
+PERCPU_TLS equ 40
+
+syscall_entry:
+    swapgs
+    mov [gs:PERCPU_SYSCALL_SP], rsp
+    mov rsp, [gs:PERCPU_TSS]
+    mov rsp, [rsp + TSS.rsp0]
+    push qword gdt.user_data | 3
+    push qword [gs:PERCPU_SYSCALL_SP]
+    swapgs
+    push r11
+    push qword gdt.user_code | 3
+    push rcx
+    mov rcx, r10
+    push qword 0x80
+    jmp interrupts.handle
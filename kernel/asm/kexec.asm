@@ -0,0 +1,87 @@
+; Handoff to a new kernel, for kexec
+;
+; This is copied to 0x7000 and jumped to by the running kernel, with interrupts disabled, the
+; other processors stopped and the arguments below filled in. It copies the new kernel over the
+; running one and enters it the way startup-i386.asm and startup-x86_64.asm do, with Multiboot2
+; boot information built by the old kernel.
+ORG 0x7000
+SECTION .text
+
+%ifdef ARCH_i386
+USE32
+%endif
+%ifdef ARCH_x86_64
+USE64
+%endif
+
+kexec:
+    jmp short start
+    align 8, db 0
+.source: dq 0
+.size: dq 0
+.dest: dq 0
+.handler: dq 0
+.stack: dq 0
+.tss: dq 0
+.info: dq 0
+
+start:
+%ifdef ARCH_i386
+    ; turn off paging, everything used from here on is identity mapped
+    mov eax, cr0
+    and eax, 0x7FFFFFFF
+    mov cr0, eax
+
+    mov esi, [kexec.source]
+    mov edi, [kexec.dest]
+    mov ecx, [kexec.size]
+    cld
+    rep movsb
+
+    ; the stubs in interrupts-i386.asm call the entry of the new kernel from now on
+    mov edx, [kexec.dest]
+    mov eax, [edx + 0x18]
+    mov edx, [kexec.handler]
+    mov [edx], eax
+
+    mov esp, [kexec.stack]
+
+    ;rust init, with Multiboot2 information
+    mov eax, [kexec.tss]
+    mov ebx, [kexec.info]
+    mov ecx, 0x36D76289
+    int 0xFF
+%endif
+
+%ifdef ARCH_x86_64
+    ; paging cannot be turned off in long mode, so allow writes to the read only pages of the old
+    ; kernel instead. Page::init of the new kernel turns write protection back on
+    mov rax, cr0
+    and rax, ~(1 << 16)
+    mov cr0, rax
+
+    mov rsi, [kexec.source]
+    mov rdi, [kexec.dest]
+    mov rcx, [kexec.size]
+    cld
+    rep movsb
+
+    ; the stubs in interrupts-x86_64.asm call the entry of the new kernel from now on
+    mov rdx, [kexec.dest]
+    mov eax, [rdx + 0x18]
+    mov rdx, [kexec.handler]
+    mov [rdx], rax
+
+    mov rsp, [kexec.stack]
+
+    ;rust init, with Multiboot2 information
+    mov rax, [kexec.tss]
+    mov rbx, [kexec.info]
+    mov rcx, 0x36D76289
+    int 0xFF
+%endif
+
+.lp:
+    cli
+    hlt
+    jmp .lp
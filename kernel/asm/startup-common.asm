@@ -20,53 +20,83 @@ buffer_size_bytes equ buffer_size_sectors * 512
 
 kernel_base equ 0x100000
 
-    ; how often do we need to call load and move memory
-    mov ecx, kernel_file.length_sectors / buffer_size_sectors
+; the initfs archive is loaded above the page tables and the cluster table of the kernel, which
+; keeps the memory allocator away from it
+initfs_base equ 0x2000000
+
+; where the address and size of the initfs archive are left for the kernel, after a signature,
+; see arch/multiboot.rs
+initfs_info equ 0x5600
+initfs_info.base equ initfs_info + 8
+initfs_info.size equ initfs_info + 12
+
+    cld
 
     mov ax, (kernel_file - boot) / 512
+    mov ecx, kernel_file.length_sectors
     mov edi, kernel_base
-    cld
-.lp:
+    call load_high
+
+    mov dword [initfs_info], 'INIT'
+    mov dword [initfs_info + 4], 'FS'
+    mov dword [initfs_info.base], initfs_base
+    mov dword [initfs_info.size], initfs_file.size
+
+    mov ax, (initfs_file - boot) / 512
+    mov ecx, initfs_file.length_sectors
+    mov edi, initfs_base
+    call load_high
+
+    jmp finished_loading
+
+; load sectors from disk to memory above 1MiB, a buffer at a time
+; IN
+;   ax: start sector
+;   ecx: number of sectors
+;   edi: destination
+; CLOBBER
+;   eax, ebx, ecx, edx, esi, edi
+load_high:
+    test ecx, ecx
+    jz .done
+
+    ; the sectors of this iteration, at most a buffer
+    mov ebx, buffer_size_sectors
+    cmp ecx, ebx
+    jae .full
+    mov ebx, ecx
+.full:
+    sub ecx, ebx
+
     ; saving counter
-    push cx
+    push ecx
+    push ax
+    push bx
 
         ; populating buffer
-        mov cx, buffer_size_sectors
+        mov cx, bx
         mov bx, startup_end
         mov dx, 0x0
-
-        push ax
         call load
 
         ; moving buffer
         call unreal
-        pop ax
-
-        mov esi, startup_end
-        mov ecx, buffer_size_bytes / 4
-        a32 rep movsd
 
-        ; preparing next iteration
-        add ax, buffer_size_sectors
-
-    pop cx
-    loop .lp
-
-    ; load the part of the kernel that does not fill the buffer completely
-    mov cx, kernel_file.length_sectors % buffer_size_sectors
-    test cx, cx
-    jz finished_loading ; if cx = 0 => skip
-
-    mov bx, startup_end
-    mov dx, 0x0
-    call load
-
-    ; moving remnants of kernel
-    call unreal
+    pop bx
+    pop ax
 
     mov esi, startup_end
-    mov ecx, (kernel_file.length_sectors % buffer_size_bytes) / 4
+    movzx ecx, bx
+    shl ecx, 7 ; 512 / 4 moves of 4 Bytes per sector
     a32 rep movsd
+
+    ; preparing next iteration
+    add ax, bx
+    pop ecx
+    jmp load_high
+.done:
+    ret
+
 finished_loading:
 
 
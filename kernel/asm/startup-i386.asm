@@ -29,10 +29,12 @@ protected_mode:
     mov eax, gdt.tss
     ltr ax
 
-    ;rust init
+    ;rust init, with no Multiboot2 information
     mov eax, [kernel_base + 0x18]
     mov [interrupts.handler], eax
     mov eax, tss
+    xor ebx, ebx
+    xor ecx, ecx
     int 255
 .lp:
     sti
@@ -96,6 +98,17 @@ gdt:
         at GDTEntry.flags__limith, db ((tss.end - tss) >> 16) & 0xF
         at GDTEntry.baseh, db ((tss-$$+0x7C00) >> 24) & 0xFF
     iend
+
+; Kernel data based at the data of the processor, set by the kernel in the GDT of each processor
+.kernel_percpu equ $ - gdt
+    istruc GDTEntry
+        at GDTEntry.limitl, dw 0xFFFF
+        at GDTEntry.basel, dw 0
+        at GDTEntry.basem, db 0
+        at GDTEntry.attribute, db attrib.present | attrib.user | attrib.writable
+        at GDTEntry.flags__limith, db 0xFF | flags.granularity | flags.default_operand_size
+        at GDTEntry.baseh, db 0
+    iend
 .end equ $ - gdt
 
 struc TSS
@@ -75,10 +75,35 @@ long_mode:
     mov rax, gdt.tss
     ltr ax
 
-    ;rust init
+    ; enable SYSCALL
+    mov ecx, 0xC0000080               ; EFER
+    rdmsr
+    or eax, 1                         ; System Call Extensions
+    wrmsr
+
+    ; SYSCALL loads CS from STAR bits 32 to 47 and SS from the descriptor after it
+    mov ecx, 0xC0000081               ; STAR
+    xor eax, eax
+    mov edx, gdt.kernel_code
+    wrmsr
+
+    mov ecx, 0xC0000082               ; LSTAR, the SYSCALL entry point
+    mov rax, syscall_entry
+    mov rdx, rax
+    shr rdx, 32
+    wrmsr
+
+    mov ecx, 0xC0000084               ; FMASK, flags cleared by SYSCALL
+    mov eax, 1 << 8 | 1 << 9 | 1 << 10 ; trap, interrupt and direction
+    xor edx, edx
+    wrmsr
+
+    ;rust init, with no Multiboot2 information
     mov eax, [kernel_base + 0x18]
     mov [interrupts.handler], rax
     mov rax, tss
+    xor rbx, rbx
+    xor rcx, rcx
     int 0xFF
 .lp:
     sti
@@ -146,6 +171,28 @@ long_mode:
     iend
     dq 0 ;tss descriptors are extended to 16 Bytes
 
+    ; 32-bit code for programs running in compatibility mode
+    .user_code32 equ $ - gdt
+    istruc GDTEntry
+        at GDTEntry.limitl, dw 0xFFFF
+        at GDTEntry.basel, dw 0
+        at GDTEntry.basem, db 0
+        at GDTEntry.attribute, db attrib.present | attrib.ring3 | attrib.user | attrib.code | attrib.readable
+        at GDTEntry.flags__limith, db flags.granularity | flags.default_operand_size | 0xF
+        at GDTEntry.baseh, db 0
+    iend
+
+    ; Kernel data based at the data of the processor, set by the kernel in the GDT of each processor
+    .kernel_percpu equ $ - gdt
+    istruc GDTEntry
+        at GDTEntry.limitl, dw 0
+        at GDTEntry.basel, dw 0
+        at GDTEntry.basem, db 0
+        at GDTEntry.attribute, db attrib.present | attrib.user | attrib.writable
+        at GDTEntry.flags__limith, db 0
+        at GDTEntry.baseh, db 0
+    iend
+
     .end equ $ - gdt
 
     struc TSS
@@ -0,0 +1,125 @@
+; Startup code for the application processors
+;
+; This is copied to 0x6000 and run in real mode by each processor that receives a startup IPI. The
+; bootstrap processor fills in the arguments below before starting each one, and waits for .ready
+; to be set before reusing them.
+ORG 0x6000
+SECTION .text
+USE16
+
+trampoline:
+    jmp short startup_ap
+    align 8, db 0
+.ready: dq 0
+.cpu_id: dq 0
+.page_table: dq 0
+.stack_end: dq 0
+.code: dq 0
+.gdtr:
+    dw 0
+    dq 0
+.idtr:
+    dw 0
+    dq 0
+
+startup_ap:
+    cli
+
+    xor ax, ax
+    mov ds, ax
+    mov es, ax
+    mov ss, ax
+
+    ; enable FPU and SSE, like initialize.fpu and initialize.sse on the bootstrap processor
+    mov eax, cr0
+    and al, 11110011b
+    or al, 00100010b
+    mov cr0, eax
+    mov eax, cr4
+    or eax, 0000011000000000b
+    mov cr4, eax
+    fninit
+
+    ; the GDT of this processor, which may be above 16 MiB
+    o32 lgdt [trampoline.gdtr]
+
+%ifdef ARCH_i386
+    mov eax, cr0
+    or eax, 1
+    mov cr0, eax
+
+    jmp 0x08:protected_mode_ap
+
+USE32
+protected_mode_ap:
+    mov eax, 0x10
+    mov ds, eax
+    mov es, eax
+    mov fs, eax
+    mov gs, eax
+    mov ss, eax
+
+    ; use the page tables of the bootstrap processor, with write protection like Page::init
+    mov eax, [trampoline.page_table]
+    mov cr3, eax
+    mov eax, cr0
+    or eax, 1 << 31 | 1 << 16
+    mov cr0, eax
+
+    lidt [trampoline.idtr]
+
+    mov esp, [trampoline.stack_end]
+    mov eax, [trampoline.cpu_id]
+    mov ebx, [trampoline.code]
+    mov dword [trampoline.ready], 1
+
+    push eax
+    call ebx
+%endif
+
+%ifdef ARCH_x86_64
+    ; enable Page Address Extension and Page Size Extension
+    mov eax, cr4
+    or eax, 1 << 5 | 1 << 4
+    mov cr4, eax
+
+    mov eax, [trampoline.page_table]
+    mov cr3, eax
+
+    mov ecx, 0xC0000080               ; EFER
+    rdmsr
+    or eax, 1 << 8 | 1                ; Long Mode Enable and System Call Extensions
+    wrmsr
+
+    ; enable paging and protection simultaneously, with write protection like Page::init
+    mov ebx, cr0
+    or ebx, 1 << 31 | 1 << 16 | 1
+    mov cr0, ebx
+
+    jmp 0x08:long_mode_ap
+
+USE64
+long_mode_ap:
+    mov rax, 0x10
+    mov ds, rax
+    mov es, rax
+    mov fs, rax
+    mov gs, rax
+    mov ss, rax
+
+    ; reload the GDT with its full base
+    lgdt [trampoline.gdtr]
+    lidt [trampoline.idtr]
+
+    mov rsp, [trampoline.stack_end]
+    mov rdi, [trampoline.cpu_id]
+    mov rax, [trampoline.code]
+    mov qword [trampoline.ready], 1
+
+    call rax
+%endif
+
+.halt:
+    cli
+    hlt
+    jmp .halt
@@ -1,2 +1,30 @@
+use drivers::pci::common::deviceid::*;
+use drivers::pci::common::vendorid::*;
+use drivers::pci::config::PciConfig;
+use drivers::registry::{self, PciId, Subsystem};
+
+use env::Environment;
+
+use self::ac97::Ac97;
+use self::intelhda::IntelHda;
+
 pub mod ac97;
 pub mod intelhda;
+
+/// The audio subsystem, enabled by the `audio` feature
+pub const SUBSYSTEM: Subsystem = Subsystem {
+    name: "audio",
+    init: registry::no_init,
+    pci_device: pci_device,
+};
+
+/// Start a driver for a sound card
+unsafe fn pci_device(env: &mut Environment, mut pci: PciConfig, id: &PciId) -> bool {
+    let irq = pci.read(0x3C) as u8 & 0xF;
+    match (id.vendor, id.device) {
+        (INTEL, AC97_82801AA) | (INTEL, AC97_ICH4) => env.add_driver(Ac97::new(pci), &[irq]),
+        (INTEL, INTELHDA_ICH6) => env.add_driver(IntelHda::new(pci), &[irq]),
+        _ => return false,
+    }
+    true
+}
@@ -0,0 +1,104 @@
+use arch::paging::Page;
+
+use core::{mem, ptr};
+
+use env::log::LogLevel;
+
+use super::debug;
+
+/// The symbol table, generated from a first link of the kernel
+#[cfg(not(no_symbols))]
+#[path="../../build/symbols.gen"]
+mod symbols;
+
+#[cfg(no_symbols)]
+mod symbols {
+    pub static SYMBOLS: &'static [(usize, &'static str)] = &[];
+}
+
+/// The maximum number of frames to print
+pub const BACKTRACE_MAX_FRAMES: usize = 64;
+
+/// Find the symbol containing `address`, returning the symbol and the offset into it
+#[inline(never)]
+pub fn resolve(address: usize) -> Option<(&'static str, usize)> {
+    // Read through a volatile load so the table length does not get folded into the code, which
+    // would change the text layout between the first and second link
+    let symbols: &'static [(usize, &'static str)] = unsafe { ptr::read_volatile(&symbols::SYMBOLS) };
+
+    let mut min = 0;
+    let mut max = symbols.len();
+    while min < max {
+        let mid = min + (max - min) / 2;
+        if symbols[mid].0 <= address {
+            min = mid + 1;
+        } else {
+            max = mid;
+        }
+    }
+
+    if min > 0 {
+        let (start, name) = symbols[min - 1];
+        Some((name, address - start))
+    } else {
+        None
+    }
+}
+
+/// Print one frame of a backtrace
+fn frame(i: usize, address: usize) {
+    if let Some((name, offset)) = resolve(address) {
+        klogln!(LogLevel::Critical, "  {:>2}: {:08X} - {}+{:#X}", i, address, name, offset);
+    } else {
+        klogln!(LogLevel::Critical, "  {:>2}: {:08X} - ???", i, address);
+    }
+}
+
+/// Check that a frame pointer can be followed without faulting
+fn readable(bp: usize) -> bool {
+    let size = mem::size_of::<usize>();
+    bp != 0 && bp % size == 0 && bp.checked_add(2 * size).is_some() &&
+    Page::new(bp).is_present() && Page::new(bp + 2 * size - 1).is_present()
+}
+
+/// Walk the frame pointer chain starting at `bp`, printing a symbolized backtrace
+pub unsafe fn trace_from(ip: usize, mut bp: usize) {
+    klogln!(LogLevel::Critical, "Backtrace:");
+    frame(0, ip);
+
+    let size = mem::size_of::<usize>();
+    for i in 1..BACKTRACE_MAX_FRAMES {
+        if ! readable(bp) {
+            return;
+        }
+
+        let next_bp = ptr::read(bp as *const usize);
+        let ret = ptr::read((bp + size) as *const usize);
+        if ret == 0 {
+            return;
+        }
+
+        frame(i, ret);
+
+        // Frames grow down, so the chain must move up the stack
+        if next_bp <= bp {
+            return;
+        }
+        bp = next_bp;
+    }
+
+    debug::d("  ...\n");
+}
+
+/// Print a backtrace of the caller
+#[inline(never)]
+pub fn trace() {
+    let bp: usize;
+    unsafe {
+        #[cfg(target_arch = "x86")]
+        asm!("mov $0, ebp" : "=r"(bp) : : : "intel", "volatile");
+        #[cfg(target_arch = "x86_64")]
+        asm!("mov $0, rbp" : "=r"(bp) : : : "intel", "volatile");
+        trace_from(trace as usize, bp);
+    }
+}
@@ -0,0 +1,98 @@
+//! The kernel command line
+//!
+//! The command line is a list of options separated by spaces, either `key=value` or a flag. It is
+//! parsed once at boot, before the memory allocator is initialized, so the options refer to the
+//! command line in place instead of being copied.
+
+use env::log::LogLevel;
+
+/// The options of the kernel command line
+pub struct Config {
+    /// The working directory of init, `root=`
+    pub root: &'static str,
+    /// The resource opened as the standard input, output and error of init, `console=`
+    pub console: &'static str,
+    /// The program run as init, `init=`
+    pub init: &'static str,
+    /// The program run as init instead when it is in the initfs archive, `rdinit=`. It runs
+    /// before anything is read from a disk, so it can start the drivers of the root file system,
+    /// then run the init on it
+    pub rdinit: &'static str,
+    /// The most physical memory that is used, in bytes, `mem=` with an optional `K`, `M` or `G`
+    pub mem: Option<u64>,
+    /// The least severe messages that are written to the console, `loglevel=`, or `debug` and
+    /// `quiet`. Messages are recorded in the kernel log regardless
+    pub log_level: LogLevel,
+    /// Run the kernel tests instead of init, `test`, on by default in kernels built with `TEST=1`
+    pub test: bool,
+    /// Wait for a debugger on COM1 when the kernel panics, `gdb`, instead of halting
+    pub gdb: bool,
+}
+
+static mut CONFIG: Config = Config {
+    root: "file:/",
+    console: "debug:",
+    init: "init",
+    rdinit: "initfs:/bin/init",
+    mem: None,
+    log_level: LogLevel::Info,
+    test: cfg!(ktest),
+    gdb: false,
+};
+
+/// Parse a size, with an optional binary suffix
+fn parse_size(value: &str) -> Option<u64> {
+    let (number, shift) = match value.chars().last() {
+        Some('K') | Some('k') => (&value[..value.len() - 1], 10),
+        Some('M') | Some('m') => (&value[..value.len() - 1], 20),
+        Some('G') | Some('g') => (&value[..value.len() - 1], 30),
+        _ => (value, 0),
+    };
+    number.parse::<u64>().ok().and_then(|number| number.checked_mul(1 << shift))
+}
+
+/// Parse a log level by its name in the kernel log
+fn parse_log_level(value: &str) -> Option<LogLevel> {
+    match value {
+        "crit" => Some(LogLevel::Critical),
+        "err" => Some(LogLevel::Error),
+        "warn" => Some(LogLevel::Warning),
+        "info" => Some(LogLevel::Info),
+        "debug" => Some(LogLevel::Debug),
+        _ => None,
+    }
+}
+
+/// Parse the command line passed by the boot loader
+///
+/// Unknown options are ignored, as are options with invalid values, which keep their defaults.
+pub unsafe fn init(cmdline: &'static str) {
+    for option in cmdline.split(' ').filter(|option| ! option.is_empty()) {
+        let mut parts = option.splitn(2, '=');
+        let key = parts.next().unwrap_or("");
+        let value = parts.next();
+
+        match (key, value) {
+            ("root", Some(value)) if ! value.is_empty() => CONFIG.root = value,
+            ("console", Some(value)) if ! value.is_empty() => CONFIG.console = value,
+            ("init", Some(value)) if ! value.is_empty() => CONFIG.init = value,
+            ("rdinit", Some(value)) if ! value.is_empty() => CONFIG.rdinit = value,
+            ("mem", Some(value)) => if let Some(mem) = parse_size(value) {
+                CONFIG.mem = Some(mem);
+            },
+            ("loglevel", Some(value)) => if let Some(level) = parse_log_level(value) {
+                CONFIG.log_level = level;
+            },
+            ("debug", None) => CONFIG.log_level = LogLevel::Debug,
+            ("quiet", None) => CONFIG.log_level = LogLevel::Warning,
+            ("test", None) => CONFIG.test = true,
+            ("gdb", None) => CONFIG.gdb = true,
+            _ => (),
+        }
+    }
+}
+
+/// The options of the kernel command line
+pub fn config() -> &'static Config {
+    unsafe { &CONFIG }
+}
@@ -1,5 +1,9 @@
 use core::str::StrExt;
 
+use common::cmdline;
+
+use env::log::LogLevel;
+
 use syscall::do_sys_debug;
 
 /// Debug to console
@@ -17,12 +21,41 @@ macro_rules! debugln {
     ($fmt:expr, $($arg:tt)*) => (debug!(concat!($fmt, "\n"), $($arg)*));
 }
 
+/// Log to console with a severity level
+#[macro_export]
+macro_rules! klog {
+    ($level:expr, $($arg:tt)*) => ({
+        $crate::common::debug::log($level, &format!($($arg)*));
+    });
+}
+
+/// Log new line to console with a severity level
+#[macro_export]
+macro_rules! klogln {
+    ($level:expr, $fmt:expr) => (klog!($level, concat!($fmt, "\n")));
+    ($level:expr, $fmt:expr, $($arg:tt)*) => (klog!($level, concat!($fmt, "\n"), $($arg)*));
+}
+
+/// Write to the console, unless `level` is filtered out by the command line, and record in the
+/// kernel log
+pub fn log(level: LogLevel, msg: &str) {
+    if level <= cmdline::config().log_level {
+        let _ = do_sys_debug(msg.as_ptr(), msg.len());
+    }
+    if unsafe { ::ENV_PTR.is_some() } {
+        ::env().log.lock().write(level, msg.as_bytes());
+    }
+}
+
 pub fn d(msg: &str) {
-    let _ = do_sys_debug(msg.as_ptr(), msg.len());
+    log(LogLevel::Info, msg);
 }
 
 pub fn db(byte: u8) {
     let _ = do_sys_debug(&byte, 1);
+    if unsafe { ::ENV_PTR.is_some() } {
+        ::env().log.lock().write(LogLevel::Info, &[byte]);
+    }
 }
 
 pub fn dbh(byte: u8) {
@@ -1,6 +1,10 @@
 /// Debug
 #[macro_use]
 pub mod debug;
+/// Stack backtraces
+pub mod backtrace;
+/// The kernel command line
+pub mod cmdline;
 /// Event input
 pub mod event;
 /// Slice-related traits
@@ -9,9 +13,12 @@ pub mod slice;
 pub mod parse_path;
 /// A module for parsing IP related string
 pub mod parse_ip;
-/// A module for pseudorandom generator
+/// A module for the ChaCha20 random generator
 pub mod random;
 /// A module for time
 pub mod time;
+/// Static tracepoints
+#[macro_use]
+pub mod trace;
 /// String to number
 pub mod to_num;
@@ -1,19 +1,202 @@
-static mut seed: u64 = 19940046431; //259261034506304368955239; //1706322144714608529217229883707268827757977089;
+use arch::cpu;
+use arch::intex::Intex;
 
-/// Generate pseudo random number
+use core::ptr;
+use core::sync::atomic::{AtomicBool, ATOMIC_BOOL_INIT, Ordering};
+
+use drivers::rtc::Rtc;
+
+/// The number of 64-bit lanes of the entropy pool, one ChaCha20 key
+const POOL_LANES: usize = 4;
+
+/// Entropy pool, mixed into the key by `reseed`
+static mut pool: [u64; POOL_LANES] = [0; POOL_LANES];
+/// The lane the next entropy is mixed into
+static mut pool_lane: usize = 0;
+
+/// The ChaCha20 key of the generator, replaced by each block it generates, so that earlier output
+/// cannot be recovered from it
+static mut key: [u32; 8] = [0; 8];
+/// Output of the last block that was not used yet
+static mut output: [u8; 32] = [0; 32];
+static mut output_used: usize = 32;
+
+/// Held while the generator is used, as every processor shares it
+static LOCKED: AtomicBool = ATOMIC_BOOL_INIT;
+
+/// Run `f` with the generator locked, and interrupts disabled so that a handler on this processor
+/// cannot spin on it
+fn locked<T, F: FnOnce() -> T>(f: F) -> T {
+    let _guard = Intex::static_lock();
+    while LOCKED.compare_and_swap(false, true, Ordering::SeqCst) {
+        unsafe { cpu::pause(); }
+    }
+    let value = f();
+    LOCKED.store(false, Ordering::SeqCst);
+    value
+}
+
+fn quarter_round(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(12);
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(7);
+}
+
+/// The ChaCha20 block of `key` with a zero counter and nonce
+fn chacha20(key_words: &[u32; 8]) -> [u32; 16] {
+    let mut state = [0x61707865, 0x3320646E, 0x79622D32, 0x6B206574,
+                     key_words[0], key_words[1], key_words[2], key_words[3],
+                     key_words[4], key_words[5], key_words[6], key_words[7],
+                     0, 0, 0, 0];
+    let initial = state;
+
+    for _ in 0..10 {
+        quarter_round(&mut state, 0, 4, 8, 12);
+        quarter_round(&mut state, 1, 5, 9, 13);
+        quarter_round(&mut state, 2, 6, 10, 14);
+        quarter_round(&mut state, 3, 7, 11, 15);
+        quarter_round(&mut state, 0, 5, 10, 15);
+        quarter_round(&mut state, 1, 6, 11, 12);
+        quarter_round(&mut state, 2, 7, 8, 13);
+        quarter_round(&mut state, 3, 4, 9, 14);
+    }
+
+    for (word, initial) in state.iter_mut().zip(initial.iter()) {
+        *word = word.wrapping_add(*initial);
+    }
+    state
+}
+
+/// Generate the next block, whose first half becomes the key and second half the output
+unsafe fn refill() {
+    let block = chacha20(&key);
+    for i in 0..8 {
+        key[i] = block[i];
+    }
+    for i in 0..8 {
+        for j in 0..4 {
+            output[i * 4 + j] = (block[8 + i] >> (j * 8)) as u8;
+        }
+    }
+    output_used = 0;
+}
+
+/// Fill `buf` with random bytes from the ChaCha20 generator
+pub fn fill(buf: &mut [u8]) {
+    locked(|| unsafe {
+        for b in buf.iter_mut() {
+            if output_used >= output.len() {
+                refill();
+            }
+            *b = output[output_used];
+            output[output_used] = 0;
+            output_used += 1;
+        }
+    });
+}
+
+/// Generate a random number
 pub fn rand() -> usize {
-    unsafe {
-        seed ^= seed << 12;
-        seed ^= seed << 25;
-        seed ^= seed << 27;
-        seed = seed * 82724793451 + 12345;
-        seed as usize % ::core::usize::MAX
+    let mut bytes = [0; 8];
+    fill(&mut bytes);
+    let mut value = 0;
+    for &b in bytes.iter() {
+        value = value << 8 | b as u64;
     }
+    value as usize
+}
+
+/// Mix a value into the entropy pool
+pub fn add_entropy(data: u64) {
+    locked(|| unsafe {
+        let lane = &mut pool[pool_lane];
+        *lane ^= data;
+        *lane = lane.rotate_left(13).wrapping_mul(0x9E3779B97F4A7C15);
+        *lane ^= *lane >> 29;
+        pool_lane = (pool_lane + 1) % POOL_LANES;
+    });
+}
+
+/// Mix the entropy pool into the key, and discard the output generated with the old one
+pub fn reseed() {
+    locked(|| unsafe {
+        for (i, &lane) in pool.iter().enumerate() {
+            key[i * 2] ^= lane as u32;
+            key[i * 2 + 1] ^= (lane >> 32) as u32;
+        }
+        refill();
+    });
 }
 
-/// Set the seed
-pub fn srand(s: usize) {
+/// Read the time stamp counter
+pub fn rdtsc() -> u64 {
+    cpu::timestamp()
+}
+
+/// Read a hardware random number, if the CPU supports RDRAND
+pub fn rdrand() -> Option<usize> {
+    let features: u32;
     unsafe {
-        seed = s as u64;
+        asm!("cpuid" : "={ecx}"(features) : "{eax}"(1) : "eax", "ebx", "edx" : "intel", "volatile");
     }
+
+    if features & 1 << 30 == 1 << 30 {
+        for _ in 0..10 {
+            let value: usize;
+            let ok: u8;
+            unsafe {
+                asm!("rdrand $0
+                    setc $1"
+                    : "=r"(value), "=r"(ok)
+                    :
+                    : "cc"
+                    : "intel", "volatile");
+            }
+            if ok == 1 {
+                return Some(value);
+            }
+        }
+    }
+
+    None
+}
+
+/// Gather entropy from TSC jitter, RDRAND, the RTC, and the BIOS memory map, then key the
+/// generator with it. Must run before the first page is unmapped, as the memory map lives there
+pub unsafe fn entropy_init() {
+    // TSC jitter from timing a short, variable-length loop
+    let mut last = rdtsc();
+    for i in 0..256 {
+        for _ in 0..(last as usize & 0xF) + i % 7 {
+            ptr::read_volatile(&last);
+        }
+        let now = rdtsc();
+        add_entropy(now.wrapping_sub(last));
+        last = now;
+    }
+
+    for _ in 0..4 {
+        if let Some(value) = rdrand() {
+            add_entropy(value as u64);
+        }
+    }
+
+    add_entropy(Rtc::new().sample());
+
+    // BIOS memory map, located at 0x500 by the bootloader
+    for i in 0..((0x5000 - 0x500) / 8) {
+        let value = ptr::read((0x500 + i * 8) as *const u64);
+        if value != 0 {
+            add_entropy(value ^ i as u64);
+        }
+    }
+
+    add_entropy(rdtsc());
+
+    reseed();
 }
@@ -35,12 +35,22 @@ impl Duration {
 
     /// Get the current duration
     pub fn monotonic() -> Self {
-        ::env().clock_monotonic.lock().clone()
+        ::env().clock.lock().monotonic()
     }
 
     /// Get the realtime
     pub fn realtime() -> Self {
-        ::env().clock_realtime.lock().clone()
+        ::env().clock.lock().realtime()
+    }
+
+    /// The duration in nanoseconds, saturating at the limits of `i64`
+    pub fn as_nanos(&self) -> i64 {
+        self.secs.saturating_mul(NANOS_PER_SEC as i64).saturating_add(self.nanos as i64)
+    }
+
+    /// Create a duration of `nanos` nanoseconds
+    pub fn from_nanos(nanos: i64) -> Self {
+        Duration::new(nanos / NANOS_PER_SEC as i64, (nanos % NANOS_PER_SEC as i64) as i32)
     }
 }
 
@@ -0,0 +1,213 @@
+use collections::string::String;
+use collections::vec::Vec;
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, ATOMIC_BOOL_INIT, ATOMIC_USIZE_INIT, Ordering};
+
+use arch::percpu;
+
+use super::random::rdtsc;
+
+/// The number of events kept in each CPU's buffer before the oldest are overwritten
+pub const TRACE_CAPACITY: usize = 4096;
+
+/// Bit mask of enabled tracepoints, checked before anything else is done
+pub static TRACE_ENABLED: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Set while the buffers are being written or read. Hits while it is set are dropped, so that
+/// tracepoints in the allocator cannot recurse into the buffers
+pub static TRACE_BUSY: AtomicBool = ATOMIC_BOOL_INIT;
+
+/// Record a tracepoint with two arguments, if it is enabled
+#[macro_export]
+macro_rules! tracepoint {
+    ($point:expr, $a:expr, $b:expr) => ({
+        if $crate::common::trace::enabled($point) {
+            $crate::common::trace::record($point, $a as usize, $b as usize);
+        }
+    });
+}
+
+/// A static tracepoint
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TracePoint {
+    /// A context switch, with the previous and next PID
+    Switch,
+    /// Syscall entry, with the number and first argument
+    SyscallEnter,
+    /// Syscall exit, with the number and muxed result
+    SyscallExit,
+    /// IRQ entry, with the IRQ number
+    Irq,
+    /// Kernel allocation, with the size and address
+    Alloc,
+}
+
+/// All tracepoints, in bit order
+pub const TRACE_POINTS: [TracePoint; 5] = [TracePoint::Switch,
+                                           TracePoint::SyscallEnter,
+                                           TracePoint::SyscallExit,
+                                           TracePoint::Irq,
+                                           TracePoint::Alloc];
+
+impl TracePoint {
+    pub fn bit(&self) -> usize {
+        1 << (*self as usize)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            TracePoint::Switch => "switch",
+            TracePoint::SyscallEnter => "sys_enter",
+            TracePoint::SyscallExit => "sys_exit",
+            TracePoint::Irq => "irq",
+            TracePoint::Alloc => "alloc",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<TracePoint> {
+        for point in TRACE_POINTS.iter() {
+            if point.name() == name {
+                return Some(*point);
+            }
+        }
+        None
+    }
+}
+
+/// A recorded tracepoint hit
+#[derive(Copy, Clone, Debug)]
+pub struct TraceEvent {
+    /// Time stamp counter at the hit
+    pub tsc: u64,
+    pub pid: usize,
+    pub point: TracePoint,
+    pub a: usize,
+    pub b: usize,
+}
+
+/// A ring buffer of events for a single CPU
+pub struct TraceBuffer {
+    /// Preallocated so that recording never allocates
+    pub events: Vec<TraceEvent>,
+    /// The sequence number of the next event
+    pub head: u64,
+}
+
+impl TraceBuffer {
+    pub fn new() -> TraceBuffer {
+        let empty = TraceEvent {
+            tsc: 0,
+            pid: 0,
+            point: TracePoint::Switch,
+            a: 0,
+            b: 0,
+        };
+
+        TraceBuffer {
+            events: vec![empty; TRACE_CAPACITY],
+            head: 0,
+        }
+    }
+
+    pub fn push(&mut self, event: TraceEvent) {
+        let i = (self.head % TRACE_CAPACITY as u64) as usize;
+        self.events[i] = event;
+        self.head += 1;
+    }
+
+    /// Get the event with sequence number `seq`, if it has not been overwritten
+    pub fn get(&self, seq: u64) -> Option<&TraceEvent> {
+        if seq < self.head && self.head - seq <= TRACE_CAPACITY as u64 {
+            Some(&self.events[(seq % TRACE_CAPACITY as u64) as usize])
+        } else {
+            None
+        }
+    }
+
+    /// The oldest sequence number still in the buffer
+    pub fn tail(&self) -> u64 {
+        if self.head > TRACE_CAPACITY as u64 {
+            self.head - TRACE_CAPACITY as u64
+        } else {
+            0
+        }
+    }
+}
+
+/// The trace buffers of all CPUs, by number
+pub struct Tracer {
+    pub buffers: Vec<TraceBuffer>,
+}
+
+impl Tracer {
+    /// Create the tracer with the buffer of the bootstrap processor
+    pub fn new() -> Tracer {
+        Tracer { buffers: vec![TraceBuffer::new()] }
+    }
+}
+
+/// Add the buffers of the processors up to `cpu_id`, which is starting. The buffers are
+/// allocated before they are added, so that recording never sees them half made
+pub fn start_cpu(cpu_id: usize) {
+    let count = ::env().trace.lock().buffers.len();
+    let mut buffers: Vec<TraceBuffer> = (count..cpu_id + 1).map(|_| TraceBuffer::new()).collect();
+
+    while TRACE_BUSY.compare_and_swap(false, true, Ordering::SeqCst) {}
+    {
+        let mut tracer = ::env().trace.lock();
+        // Another processor may have added some meanwhile
+        let skip = tracer.buffers.len() - count;
+        tracer.buffers.extend(buffers.drain(..).skip(skip));
+    }
+    TRACE_BUSY.store(false, Ordering::SeqCst);
+}
+
+/// Check if a tracepoint is enabled
+#[inline(always)]
+pub fn enabled(point: TracePoint) -> bool {
+    TRACE_ENABLED.load(Ordering::Relaxed) & point.bit() == point.bit()
+}
+
+/// Enable or disable a tracepoint
+pub fn set_enabled(point: TracePoint, enable: bool) {
+    if enable {
+        TRACE_ENABLED.fetch_or(point.bit(), Ordering::SeqCst);
+    } else {
+        TRACE_ENABLED.fetch_and(!point.bit(), Ordering::SeqCst);
+    }
+}
+
+/// Record a tracepoint hit on the current CPU
+pub fn record(point: TracePoint, a: usize, b: usize) {
+    if unsafe { ::ENV_PTR.is_none() } {
+        return;
+    }
+
+    let (cpu, pid) = percpu::get().map_or((0, 0), |percpu| (percpu.cpu_id, percpu.pid));
+
+    let event = TraceEvent {
+        tsc: rdtsc(),
+        pid: pid,
+        point: point,
+        a: a,
+        b: b,
+    };
+
+    if ! TRACE_BUSY.compare_and_swap(false, true, Ordering::SeqCst) {
+        if let Some(buffer) = ::env().trace.lock().buffers.get_mut(cpu) {
+            buffer.push(event);
+        }
+        TRACE_BUSY.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Format an event as a line
+pub fn format_event(cpu: usize, event: &TraceEvent) -> String {
+    format!("{:>20} CPU {} PID {:<6}{:<10}{:X} {:X}\n",
+            event.tsc,
+            cpu,
+            event.pid,
+            event.point.name(),
+            event.a,
+            event.b)
+}
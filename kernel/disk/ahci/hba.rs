@@ -1,9 +1,7 @@
 use arch::memory;
 
 use core::mem::size_of;
-use core::u32;
-
-use disk::Disk;
+use core::{cmp, u32};
 
 use drivers::io::{Io, Mmio};
 
@@ -11,22 +9,36 @@ use system::error::{Error, Result, EIO};
 
 use super::fis::{FIS_TYPE_REG_H2D, FisRegH2D};
 
-const ATA_CMD_READ_DMA_EXT: u8 = 0x25;
-const ATA_CMD_WRITE_DMA_EXT: u8 = 0x35;
+pub const ATA_CMD_READ_DMA_EXT: u8 = 0x25;
+pub const ATA_CMD_WRITE_DMA_EXT: u8 = 0x35;
+pub const ATA_CMD_FLUSH_CACHE_EXT: u8 = 0xEA;
+pub const ATA_CMD_IDENTIFY: u8 = 0xEC;
 const ATA_DEV_BUSY: u8 = 0x80;
 const ATA_DEV_DRQ: u8 = 0x08;
+const ATA_DEV_ERR: u8 = 0x01;
 
+pub const HBA_GHC_AE: u32 = 1 << 31;
+pub const HBA_GHC_IE: u32 = 1 << 1;
 const HBA_PORT_CMD_CR: u32 = 1 << 15;
 const HBA_PORT_CMD_FR: u32 = 1 << 14;
 const HBA_PORT_CMD_FRE: u32 = 1 << 4;
 const HBA_PORT_CMD_ST: u32 = 1;
 const HBA_PORT_IS_TFES: u32 = 1 << 30;
+const HBA_PORT_IS_PSS: u32 = 1 << 1;
+const HBA_PORT_IS_DHRS: u32 = 1;
 const HBA_SSTS_PRESENT: u32 = 0x3;
 const HBA_SIG_ATA: u32 = 0x00000101;
 const HBA_SIG_ATAPI: u32 = 0xEB140101;
 const HBA_SIG_PM: u32 = 0x96690101;
 const HBA_SIG_SEMB: u32 = 0xC33C0101;
 
+/// The most sectors a command transfers, as the count register has 16 bits
+pub const MAX_SECTORS: usize = 65535;
+/// The most bytes a PRDT entry transfers
+const PRD_MAX: usize = 4 * 1024 * 1024;
+/// The PRDT entries of a command table, enough for `MAX_SECTORS`
+const PRDT_ENTRIES: usize = (MAX_SECTORS * 512 + PRD_MAX - 1) / PRD_MAX;
+
 #[derive(Debug)]
 pub enum HbaPortType {
     None,
@@ -74,31 +86,34 @@ impl HbaPort {
         }
     }
 
+    /// Allocate the command list, the received FIS area and the command tables, and start the
+    /// port, which interrupts when a command completes or fails
     pub fn init(&mut self) {
         self.stop();
 
-        // debugln!("Port Command List");
-        let clb = unsafe { memory::alloc_aligned(size_of::<HbaCmdHeader>(), 1024) };
+        let clb = unsafe { memory::alloc_aligned(size_of::<HbaCmdHeader>() * 32, 1024) };
+        unsafe { ::memset(clb as *mut u8, 0, size_of::<HbaCmdHeader>() * 32) };
         self.clb.write(clb as u64);
 
-        // debugln!("Port FIS");
         let fb = unsafe { memory::alloc_aligned(256, 256) };
+        unsafe { ::memset(fb as *mut u8, 0, 256) };
         self.fb.write(fb as u64);
 
         for i in 0..32 {
-            // debugln!("Port Command Table {}", i);
             let cmdheader = unsafe { &mut *(clb as *mut HbaCmdHeader).offset(i) };
             let ctba = unsafe { memory::alloc_aligned(size_of::<HbaCmdTable>(), 256) };
             cmdheader.ctba.write(ctba as u64);
             cmdheader.prdtl.write(0);
         }
 
+        self.serr.write(u32::MAX);
+        self.is.write(u32::MAX);
+        self.ie.write(HBA_PORT_IS_DHRS | HBA_PORT_IS_PSS | HBA_PORT_IS_TFES);
+
         self.start();
     }
 
     pub fn start(&mut self) {
-        // debugln!("Starting port");
-
         while self.cmd.readf(HBA_PORT_CMD_CR) {}
 
         self.cmd.writef(HBA_PORT_CMD_FRE, true);
@@ -106,8 +121,6 @@ impl HbaPort {
     }
 
     pub fn stop(&mut self) {
-        // debugln!("Stopping port");
-
         self.cmd.writef(HBA_PORT_CMD_ST, false);
 
         while self.cmd.readf(HBA_PORT_CMD_FR | HBA_PORT_CMD_CR) {}
@@ -115,6 +128,14 @@ impl HbaPort {
         self.cmd.writef(HBA_PORT_CMD_FRE, false);
     }
 
+    /// Restart the port after a command failed, as it stops processing commands until then
+    pub fn recover(&mut self) {
+        self.stop();
+        self.serr.write(u32::MAX);
+        self.is.write(u32::MAX);
+        self.start();
+    }
+
     pub fn slot(&self) -> Option<u32> {
         let slots = self.sact.read() | self.ci.read();
         for i in 0..32 {
@@ -125,87 +146,88 @@ impl HbaPort {
         None
     }
 
-    pub fn ata_dma(&mut self,
-                   block: u64,
-                   sectors: usize,
-                   buf: usize,
-                   write: bool)
-                   -> Result<usize> {
-        // debugln!("AHCI {:X} DMA BLOCK: {:X} SECTORS: {} BUF: {:X} WRITE: {}", (self as *mut HbaPort) as usize, block, sectors, buf, write);
-
-        // TODO: PRDTL for files larger than 4MB
-        let entries = 1;
-
-        if buf > 0 && sectors > 0 {
-            self.is.write(u32::MAX);
+    /// Issue `cmd` for `sectors` from `block` in a free slot, returning the slot
+    ///
+    /// `buf` is the address of the data, which is split in PRDT entries, or 0 for a command
+    /// without data. The command completes when `done` returns true for its slot.
+    pub fn ata_start(&mut self,
+                     cmd: u8,
+                     block: u64,
+                     sectors: usize,
+                     buf: usize,
+                     write: bool)
+                     -> Result<u32> {
+        if sectors > MAX_SECTORS {
+            return Err(Error::new(EIO));
+        }
 
-            if let Some(slot) = self.slot() {
-                // debugln!("Slot {}", slot);
+        let slot = match self.slot() {
+            Some(slot) => slot,
+            None => {
+                debugln!("No Command Slots");
+                return Err(Error::new(EIO));
+            }
+        };
 
-                let clb = self.clb.read() as usize;
-                let cmdheader = unsafe { &mut *(clb as *mut HbaCmdHeader).offset(slot as isize) };
+        self.is.write(u32::MAX);
 
-                cmdheader.cfl.write(((size_of::<FisRegH2D>() / size_of::<u32>()) as u8));
-                cmdheader.cfl.writef(1 << 6, write);
+        let clb = self.clb.read() as usize;
+        let cmdheader = unsafe { &mut *(clb as *mut HbaCmdHeader).offset(slot as isize) };
 
-                cmdheader.prdtl.write(entries);
+        cmdheader.cfl.write(((size_of::<FisRegH2D>() / size_of::<u32>()) as u8));
+        cmdheader.cfl.writef(1 << 6, write);
 
-                let ctba = cmdheader.ctba.read() as usize;
-                unsafe { ::memset(ctba as *mut u8, 0, size_of::<HbaCmdTable>()) };
-                let cmdtbl = unsafe { &mut *(ctba as *mut HbaCmdTable) };
+        let ctba = cmdheader.ctba.read() as usize;
+        unsafe { ::memset(ctba as *mut u8, 0, size_of::<HbaCmdTable>()) };
+        let cmdtbl = unsafe { &mut *(ctba as *mut HbaCmdTable) };
 
-                let prdt_entry = &mut cmdtbl.prdt_entry[0];
-                prdt_entry.dba.write(buf as u64);
-                prdt_entry.dbc.write(((sectors * 512) as u32) | 1);
+        let bytes = if buf > 0 { sectors * 512 } else { 0 };
+        let entries = (bytes + PRD_MAX - 1) / PRD_MAX;
+        for i in 0..entries {
+            let offset = i * PRD_MAX;
+            let prdt_entry = &mut cmdtbl.prdt_entry[i];
+            prdt_entry.dba.write((buf + offset) as u64);
+            // The byte count is stored minus one
+            prdt_entry.dbc.write((cmp::min(bytes - offset, PRD_MAX) - 1) as u32);
+        }
+        cmdheader.prdtl.write(entries as u16);
+        cmdheader.prdbc.write(0);
 
-                let cmdfis = unsafe { &mut *(cmdtbl.cfis.as_ptr() as *mut FisRegH2D) };
+        let cmdfis = unsafe { &mut *(cmdtbl.cfis.as_ptr() as *mut FisRegH2D) };
 
-                cmdfis.fis_type.write(FIS_TYPE_REG_H2D);
-                cmdfis.pm.write(1 << 7);
-                if write {
-                    cmdfis.command.write(ATA_CMD_WRITE_DMA_EXT);
-                } else {
-                    cmdfis.command.write(ATA_CMD_READ_DMA_EXT);
-                }
+        cmdfis.fis_type.write(FIS_TYPE_REG_H2D);
+        cmdfis.pm.write(1 << 7);
+        cmdfis.command.write(cmd);
 
-                cmdfis.lba0.write(block as u8);
-                cmdfis.lba1.write((block >> 8) as u8);
-                cmdfis.lba2.write((block >> 16) as u8);
+        cmdfis.lba0.write(block as u8);
+        cmdfis.lba1.write((block >> 8) as u8);
+        cmdfis.lba2.write((block >> 16) as u8);
 
-                cmdfis.device.write(1 << 6);
+        cmdfis.device.write(1 << 6);
 
-                cmdfis.lba3.write((block >> 24) as u8);
-                cmdfis.lba4.write((block >> 32) as u8);
-                cmdfis.lba5.write((block >> 40) as u8);
+        cmdfis.lba3.write((block >> 24) as u8);
+        cmdfis.lba4.write((block >> 32) as u8);
+        cmdfis.lba5.write((block >> 40) as u8);
 
-                cmdfis.countl.write(sectors as u8);
-                cmdfis.counth.write((sectors >> 8) as u8);
+        cmdfis.countl.write(sectors as u8);
+        cmdfis.counth.write((sectors >> 8) as u8);
 
-                // debugln!("Busy Wait");
-                while self.tfd.readf((ATA_DEV_BUSY | ATA_DEV_DRQ) as u32) {}
+        while self.tfd.readf((ATA_DEV_BUSY | ATA_DEV_DRQ) as u32) {}
 
-                self.ci.writef(1 << slot, true);
+        self.ci.writef(1 << slot, true);
 
-                // debugln!("Completion Wait");
-                while self.ci.readf(1 << slot) {
-                    if self.is.readf(HBA_PORT_IS_TFES) {
-                        return Err(Error::new(EIO));
-                    }
-                }
+        Ok(slot)
+    }
 
-                if self.is.readf(HBA_PORT_IS_TFES) {
-                    return Err(Error::new(EIO));
-                }
+    /// Has the command in `slot` completed
+    pub fn done(&self, slot: u32) -> bool {
+        ! self.ci.readf(1 << slot)
+    }
 
-                Ok(sectors * 512)
-            } else {
-                debugln!("No Command Slots");
-                Err(Error::new(EIO))
-            }
-        } else {
-            debugln!("Empty request");
-            Err(Error::new(EIO))
-        }
+    /// Has the last command failed, the status register keeps the error after the interrupt
+    /// status is cleared
+    pub fn failed(&self) -> bool {
+        self.is.readf(HBA_PORT_IS_TFES) || self.tfd.readf(ATA_DEV_ERR as u32)
     }
 }
 
@@ -231,7 +253,7 @@ pub struct HbaMem {
 struct HbaPrdtEntry {
     dba: Mmio<u64>, // Data base address
     rsv0: Mmio<u32>, // Reserved
-    dbc: Mmio<u32>, // Byte count, 4M max, interrupt = 1
+    dbc: Mmio<u32>, // Byte count minus one, 4M max, interrupt on completion: 1 << 31
 }
 
 #[repr(packed)]
@@ -246,7 +268,7 @@ struct HbaCmdTable {
     rsv: [Mmio<u8>; 48], // Reserved
 
     // 0x80
-    prdt_entry: [HbaPrdtEntry; 65536], // Physical region descriptor table entries, 0 ~ 65535
+    prdt_entry: [HbaPrdtEntry; PRDT_ENTRIES], // Physical region descriptor table entries
 }
 
 #[repr(packed)]
@@ -1,74 +1,209 @@
+use alloc::arc::Arc;
 use alloc::boxed::Box;
 
-use collections::string::String;
+use collections::string::{String, ToString};
 use collections::vec::Vec;
 
-use disk::Disk;
+use core::{cmp, u32};
+
+use disk::BlockDevice;
 
 use drivers::io::Io;
 use drivers::pci::config::PciConfig;
 
-use system::error::Result;
+use fs::KScheme;
+
+use sync::WaitQueue;
 
-use self::hba::{HbaMem, HbaPort, HbaPortType};
+use system::error::{Error, Result, EIO};
+
+use self::hba::{HbaMem, HbaPort, HbaPortType, ATA_CMD_FLUSH_CACHE_EXT, ATA_CMD_IDENTIFY,
+                ATA_CMD_READ_DMA_EXT, ATA_CMD_WRITE_DMA_EXT, HBA_GHC_AE, HBA_GHC_IE, MAX_SECTORS};
 
 pub mod fis;
 pub mod hba;
 
-pub struct Ahci;
+/// The interrupt statuses a port keeps for its disk, which has one command outstanding at a time
+const STATUS_QUEUE: usize = 4;
+
+/// An AHCI controller, which passes the interrupts of its ports to their disks
+///
+/// A disk issues one command at a time and waits for the interrupt of its port, or polls for the
+/// completion before contexts are switched, as the file system is read at boot.
+pub struct Ahci {
+    hba: &'static mut HbaMem,
+    pub irq: u8,
+    /// The ports with a disk, and the queue of interrupt statuses of each
+    ports: Vec<(usize, Arc<WaitQueue<u32>>)>,
+}
 
 impl Ahci {
-    pub fn disks(mut pci: PciConfig) -> Vec<Box<Disk>> {
-        let base = unsafe { (pci.read(0x24) & 0xFFFFFFF0) as usize };
-        let irq = unsafe { (pci.read(0x3C) & 0xF) as u8 };
-
-        debugln!("AHCI on: {:X} IRQ: {:X}", base as usize, irq);
-
-        let pi = unsafe { &mut *(base as *mut HbaMem) }.pi.read();
-        let ret: Vec<Box<Disk>> = (0..32)
-                                      .filter(|&i| pi & 1 << i as i32 == 1 << i as i32)
-                                      .filter_map(|i| {
-                                          let mut disk = box AhciDisk::new(base, i);
-                                          let port_type = disk.port.probe();
-                                          debugln!("Port {}: {:?}", i, port_type);
-                                          match port_type {
-                                              HbaPortType::SATA => {
-                                                  disk.port.init();
-                                                  Some(disk as Box<Disk>)
-                                              }
-                                              _ => None,
-                                          }
-                                      })
-                                      .collect();
-
-        ret
+    /// Enable the controller and the SATA disks on its ports
+    ///
+    /// The controller must be registered for its IRQ, so that the disks are told when commands
+    /// complete.
+    pub unsafe fn new(mut pci: PciConfig) -> (Box<Ahci>, Vec<Box<BlockDevice>>) {
+        pci.flag(4, 4, true); // Bus mastering
+
+        let base = (pci.read(0x24) & 0xFFFFFFF0) as usize;
+        let irq = (pci.read(0x3C) & 0xF) as u8;
+
+        debugln!("AHCI on: {:X} IRQ: {:X}", base, irq);
+
+        let hba = &mut *(base as *mut HbaMem);
+        hba.ghc.writef(HBA_GHC_AE, true);
+
+        let mut ports = Vec::new();
+        let mut disks: Vec<Box<BlockDevice>> = Vec::new();
+        let pi = hba.pi.read();
+        for i in (0..32).filter(|&i| pi & 1 << i == 1 << i) {
+            let port = &mut *(&mut hba.ports[i] as *mut HbaPort);
+            let port_type = port.probe();
+            debugln!("Port {}: {:?}", i, port_type);
+            if let HbaPortType::SATA = port_type {
+                port.init();
+
+                let statuses = Arc::new(WaitQueue::with_capacity(STATUS_QUEUE));
+                let mut disk = box AhciDisk {
+                    port: port,
+                    port_index: i,
+                    statuses: statuses.clone(),
+                    sectors: 0,
+                };
+                match disk.identify() {
+                    Ok(model) => {
+                        debugln!("Port {}: {} {} MB", i, model, disk.sectors / 2048);
+                        ports.push((i, statuses));
+                        disks.push(disk);
+                    },
+                    Err(err) => debugln!("Port {}: failed to identify: {}", i, err),
+                }
+            }
+        }
+
+        hba.is.write(u32::MAX);
+        hba.ghc.writef(HBA_GHC_IE, true);
+
+        (box Ahci {
+            hba: hba,
+            irq: irq,
+            ports: ports,
+        }, disks)
+    }
+}
+
+impl KScheme for Ahci {
+    fn on_irq(&mut self, irq: u8) {
+        if irq == self.irq {
+            let is = self.hba.is.read();
+            for &(i, ref statuses) in self.ports.iter() {
+                if is & 1 << i == 1 << i {
+                    let port = &mut self.hba.ports[i];
+                    let status = port.is.read();
+                    port.is.write(status);
+                    statuses.try_send(status);
+                }
+            }
+            // The port statuses are cleared first, or the controller raises the IRQ again
+            self.hba.is.write(is);
+        }
     }
 }
 
 pub struct AhciDisk {
     port: &'static mut HbaPort,
     port_index: usize,
+    /// The interrupt statuses of the port, sent by the controller
+    statuses: Arc<WaitQueue<u32>>,
+    /// The size of the disk, from IDENTIFY DEVICE
+    sectors: u64,
 }
 
 impl AhciDisk {
-    fn new(base: usize, port_index: usize) -> Self {
-        AhciDisk {
-            port: &mut unsafe { &mut *(base as *mut HbaMem) }.ports[port_index],
-            port_index: port_index
+    /// Run a command and wait for it, restarting the port if it fails
+    fn command(&mut self, cmd: u8, block: u64, sectors: usize, buf: usize, write: bool) -> Result<()> {
+        // Statuses left by earlier commands would end the wait early
+        self.statuses.inner.lock().clear();
+
+        let slot = try!(self.port.ata_start(cmd, block, sectors, buf, write));
+
+        let switching = ::env().contexts.read().enabled;
+        while ! self.port.done(slot) && ! self.port.failed() {
+            if switching {
+                self.statuses.receive();
+            }
+        }
+
+        if self.port.failed() {
+            debugln!("AHCI Port {}: command {:X} at {} failed", self.port_index, cmd, block);
+            self.port.recover();
+            return Err(Error::new(EIO));
         }
+
+        Ok(())
+    }
+
+    /// Read the size of the disk with IDENTIFY DEVICE, returning its model
+    fn identify(&mut self) -> Result<String> {
+        let data = vec![0u16; 256];
+        try!(self.command(ATA_CMD_IDENTIFY, 0, 1, data.as_ptr() as usize, false));
+
+        let mut model = String::new();
+        for word in 27..47 {
+            model.push((data[word] >> 8) as u8 as char);
+            model.push(data[word] as u8 as char);
+        }
+
+        self.sectors = (data[100] as u64) | ((data[101] as u64) << 16) |
+                       ((data[102] as u64) << 32) | ((data[103] as u64) << 48);
+        if self.sectors == 0 {
+            self.sectors = (data[60] as u64) | ((data[61] as u64) << 16);
+        }
+
+        Ok(model.trim().to_string())
+    }
+
+    fn ata_dma(&mut self, block: u64, sectors: usize, buf: usize, write: bool) -> Result<usize> {
+        if buf == 0 || sectors == 0 {
+            debugln!("Empty request");
+            return Err(Error::new(EIO));
+        }
+
+        let cmd = if write {
+            ATA_CMD_WRITE_DMA_EXT
+        } else {
+            ATA_CMD_READ_DMA_EXT
+        };
+
+        let mut sector = 0;
+        while sector < sectors {
+            let count = cmp::min(sectors - sector, MAX_SECTORS);
+            try!(self.command(cmd, block + sector as u64, count, buf + sector * 512, write));
+            sector += count;
+        }
+
+        Ok(sectors * 512)
     }
 }
 
-impl Disk for AhciDisk {
+impl BlockDevice for AhciDisk {
     fn name(&self) -> String {
         format!("AHCI Port {}", self.port_index)
     }
 
-    fn read(&mut self, block: u64, buffer: &mut [u8]) -> Result<usize> {
-        self.port.ata_dma(block, buffer.len() / 512, buffer.as_ptr() as usize, false)
+    fn blocks(&self) -> Option<u64> {
+        Some(self.sectors)
+    }
+
+    fn read_blocks(&mut self, block: u64, buffer: &mut [u8]) -> Result<usize> {
+        self.ata_dma(block, buffer.len() / 512, buffer.as_ptr() as usize, false)
+    }
+
+    fn write_blocks(&mut self, block: u64, buffer: &[u8]) -> Result<usize> {
+        self.ata_dma(block, buffer.len() / 512, buffer.as_ptr() as usize, true)
     }
 
-    fn write(&mut self, block: u64, buffer: &[u8]) -> Result<usize> {
-        self.port.ata_dma(block, buffer.len() / 512, buffer.as_ptr() as usize, true)
+    fn flush(&mut self) -> Result<()> {
+        self.command(ATA_CMD_FLUSH_CACHE_EXT, 0, 0, 0, false)
     }
 }
@@ -0,0 +1,318 @@
+use alloc::arc::Arc;
+use alloc::boxed::Box;
+
+use arch::context::{context_switch, Context};
+
+use collections::{BTreeMap, Vec};
+use collections::string::String;
+
+use common::time::Duration;
+
+use sync::Intex;
+
+use system::error::{Error, Result, EINVAL};
+
+use super::BlockDevice;
+
+/// The blocks kept in memory
+const CACHE_BLOCKS: usize = 2048;
+/// The largest write of adjacent dirty blocks
+const MAX_WRITE_BACK: usize = 128 * 1024;
+/// How often `kflushd` writes dirty blocks back
+const FLUSH_SECS: i64 = 5;
+
+/// A block in memory
+struct CachedBlock {
+    data: Vec<u8>,
+    /// Changed since it was written to the device
+    dirty: bool,
+    /// Counted up on every write, so that a block changed while it is written back stays dirty
+    version: u64,
+    /// When the block was last used, its key in the LRU list
+    used: u64,
+}
+
+/// The blocks in memory, and the order they were used in
+struct Blocks {
+    blocks: BTreeMap<u64, CachedBlock>,
+    /// The blocks by last use, the least recently used first
+    lru: BTreeMap<u64, u64>,
+    /// Counted up on every use
+    clock: u64,
+}
+
+impl Blocks {
+    /// Put `data` in memory as `block`, a dirty block is only replaced by a write
+    fn insert(&mut self, block: u64, data: &[u8], write: bool) {
+        self.clock += 1;
+        let clock = self.clock;
+
+        if let Some(cached) = self.blocks.get_mut(&block) {
+            if write || ! cached.dirty {
+                cached.data.clear();
+                cached.data.extend_from_slice(data);
+            }
+            if write {
+                cached.dirty = true;
+                cached.version += 1;
+            }
+
+            self.lru.remove(&cached.used);
+            cached.used = clock;
+            self.lru.insert(clock, block);
+            return;
+        }
+
+        self.blocks.insert(block, CachedBlock {
+            data: data.to_vec(),
+            dirty: write,
+            version: 0,
+            used: clock,
+        });
+        self.lru.insert(clock, block);
+    }
+
+    /// Copy `block` into `data` if it is in memory
+    fn read(&mut self, block: u64, data: &mut [u8]) -> bool {
+        self.clock += 1;
+        let clock = self.clock;
+
+        if let Some(cached) = self.blocks.get_mut(&block) {
+            data.copy_from_slice(&cached.data);
+
+            self.lru.remove(&cached.used);
+            cached.used = clock;
+            self.lru.insert(clock, block);
+            return true;
+        }
+
+        false
+    }
+
+    /// Drop clean blocks, the least recently used first, until `CACHE_BLOCKS` are left, returning
+    /// false if there are more dirty blocks than that
+    fn evict(&mut self) -> bool {
+        let mut evicted = Vec::new();
+        for (&used, &block) in self.lru.iter() {
+            if self.blocks.len() - evicted.len() <= CACHE_BLOCKS {
+                break;
+            }
+            if self.blocks.get(&block).map_or(false, |cached| ! cached.dirty) {
+                evicted.push((used, block));
+            }
+        }
+
+        for &(used, block) in evicted.iter() {
+            self.lru.remove(&used);
+            self.blocks.remove(&block);
+        }
+
+        self.blocks.len() <= CACHE_BLOCKS
+    }
+}
+
+/// The state shared by a `BlockCache` and its `kflushd`
+struct CacheInner {
+    /// The device, owned by the cache
+    device: *mut BlockDevice,
+    block_size: usize,
+    blocks: Intex<Blocks>,
+    /// Set while the dirty blocks are written back, so that an older copy of a block is never
+    /// written after a newer one
+    writing: Intex<bool>,
+}
+
+impl CacheInner {
+    /// Write the dirty blocks to the device, adjacent blocks in one write
+    ///
+    /// The blocks are copied, so that they can be used while they are written. A block is clean
+    /// when the write succeeds, unless it was changed in the meantime.
+    fn write_back(&self) -> Result<()> {
+        loop {
+            {
+                let mut writing = self.writing.lock();
+                if ! *writing {
+                    *writing = true;
+                    break;
+                }
+            }
+            unsafe { context_switch(); }
+        }
+
+        let dirty: Vec<(u64, u64, Vec<u8>)> = self.blocks.lock().blocks.iter()
+                                                  .filter(|&(_, cached)| cached.dirty)
+                                                  .map(|(&block, cached)| (block, cached.version, cached.data.clone()))
+                                                  .collect();
+
+        let mut result = Ok(());
+        let mut i = 0;
+        while i < dirty.len() {
+            let mut j = i + 1;
+            while j < dirty.len() && dirty[j].0 == dirty[j - 1].0 + 1 &&
+                  (j - i + 1) * self.block_size <= MAX_WRITE_BACK {
+                j += 1;
+            }
+
+            let mut data = Vec::with_capacity((j - i) * self.block_size);
+            for &(_, _, ref block_data) in dirty[i..j].iter() {
+                data.extend_from_slice(block_data);
+            }
+
+            match unsafe { (*self.device).write_blocks(dirty[i].0, &data) } {
+                Ok(_) => {
+                    let mut blocks = self.blocks.lock();
+                    for &(block, version, _) in dirty[i..j].iter() {
+                        if let Some(cached) = blocks.blocks.get_mut(&block) {
+                            if cached.version == version {
+                                cached.dirty = false;
+                            }
+                        }
+                    }
+                },
+                Err(err) => {
+                    debugln!("{}: failed to write back block {}: {}", unsafe { (*self.device).name() }, dirty[i].0, err);
+                    result = Err(err);
+                }
+            }
+
+            i = j;
+        }
+
+        *self.writing.lock() = false;
+
+        result
+    }
+}
+
+impl Drop for CacheInner {
+    fn drop(&mut self) {
+        drop(unsafe { Box::from_raw(self.device) });
+    }
+}
+
+/// A write-back cache of the blocks of a device
+///
+/// A read is served from memory if every block is there, otherwise the blocks are read from the
+/// device and kept. The least recently used clean blocks are dropped when there are more than
+/// `CACHE_BLOCKS`. Writes only change the blocks in memory, which `kflushd` writes back every
+/// `FLUSH_SECS` seconds, and `flush` at once, as a file does when it is synced. A write that
+/// leaves more than `CACHE_BLOCKS` dirty blocks writes them back itself.
+pub struct BlockCache {
+    inner: Arc<CacheInner>,
+}
+
+impl BlockCache {
+    pub fn new(device: Box<BlockDevice>) -> Box<BlockCache> {
+        let inner = Arc::new(CacheInner {
+            block_size: device.block_size(),
+            device: Box::into_raw(device),
+            blocks: Intex::new(Blocks {
+                blocks: BTreeMap::new(),
+                lru: BTreeMap::new(),
+                clock: 0,
+            }),
+            writing: Intex::new(false),
+        });
+
+        let flusher = Arc::downgrade(&inner);
+        Context::kspawn("kflushd", move || {
+            loop {
+                {
+                    let mut contexts = ::env().contexts.write();
+                    if let Ok(mut current) = contexts.current_mut() {
+                        current.blocked = true;
+                        current.wake = Some(Duration::monotonic() + Duration::new(FLUSH_SECS, 0));
+                    }
+                }
+                unsafe { context_switch(); }
+
+                match flusher.upgrade() {
+                    Some(inner) => {
+                        let _ = inner.write_back();
+                    },
+                    None => break,
+                }
+            }
+        });
+
+        box BlockCache {
+            inner: inner,
+        }
+    }
+}
+
+impl BlockDevice for BlockCache {
+    fn name(&self) -> String {
+        unsafe { (*self.inner.device).name() }
+    }
+
+    fn block_size(&self) -> usize {
+        self.inner.block_size
+    }
+
+    fn blocks(&self) -> Option<u64> {
+        unsafe { (*self.inner.device).blocks() }
+    }
+
+    fn read_blocks(&mut self, block: u64, buffer: &mut [u8]) -> Result<usize> {
+        let size = self.inner.block_size;
+        if buffer.len() % size != 0 {
+            return Err(Error::new(EINVAL));
+        }
+
+        {
+            let mut blocks = self.inner.blocks.lock();
+            if (0..buffer.len() / size).all(|i| blocks.blocks.contains_key(&(block + i as u64))) {
+                for (i, data) in buffer.chunks_mut(size).enumerate() {
+                    blocks.read(block + i as u64, data);
+                }
+                return Ok(buffer.len());
+            }
+        }
+
+        let count = try!(unsafe { (*self.inner.device).read_blocks(block, buffer) });
+
+        let mut blocks = self.inner.blocks.lock();
+        for (i, data) in buffer[.. count - count % size].chunks_mut(size).enumerate() {
+            // A dirty block in memory is newer than the one on the device
+            blocks.insert(block + i as u64, data, false);
+            blocks.read(block + i as u64, data);
+        }
+        blocks.evict();
+
+        Ok(count)
+    }
+
+    fn write_blocks(&mut self, block: u64, buffer: &[u8]) -> Result<usize> {
+        let size = self.inner.block_size;
+        if buffer.len() % size != 0 {
+            return Err(Error::new(EINVAL));
+        }
+
+        let full = {
+            let mut blocks = self.inner.blocks.lock();
+            for (i, data) in buffer.chunks(size).enumerate() {
+                blocks.insert(block + i as u64, data, true);
+            }
+            ! blocks.evict()
+        };
+
+        if full {
+            try!(self.inner.write_back());
+            self.inner.blocks.lock().evict();
+        }
+
+        Ok(buffer.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        try!(self.inner.write_back());
+        unsafe { (*self.inner.device).flush() }
+    }
+}
+
+impl Drop for BlockCache {
+    fn drop(&mut self) {
+        let _ = self.inner.write_back();
+    }
+}
@@ -0,0 +1,212 @@
+use alloc::arc::Arc;
+use alloc::boxed::Box;
+
+use collections::string::String;
+use collections::vec::Vec;
+
+use core::cell::Cell;
+use core::{cmp, slice};
+
+use sync::Intex;
+
+use system::error::{Error, Result, EINVAL};
+
+use super::{BlockCompletion, BlockDevice};
+
+/// The largest transfer that requests are merged into
+const MAX_MERGE: usize = 128 * 1024;
+/// How many transfers a request can be passed over by before it is served first
+const STARVATION_LIMIT: u64 = 16;
+
+/// A request waiting in the queue of an `Elevator`
+struct Request {
+    block: u64,
+    buffer: *mut u8,
+    len: usize,
+    write: bool,
+    /// The number of transfers done when the request was queued
+    queued: u64,
+    completion: Arc<BlockCompletion>,
+}
+
+/// An I/O scheduler for a block device
+///
+/// Requests are queued instead of being sent to the device in arrival order. The context that
+/// finds the device idle sends requests until the queue is empty, in increasing block order from
+/// the last block transferred, wrapping around to the lowest block like C-LOOK. Requests for
+/// adjacent blocks in the same direction are merged into one transfer. A request that has been
+/// passed over by `STARVATION_LIMIT` transfers is served next, so that requests far from the
+/// others are not delayed forever.
+pub struct Elevator {
+    device: Box<BlockDevice>,
+    queue: Intex<Vec<Request>>,
+    /// Set while a context is sending requests to the device
+    busy: Cell<bool>,
+    /// The block after the last transfer
+    position: Cell<u64>,
+    /// The number of transfers done
+    transfers: Cell<u64>,
+}
+
+impl Elevator {
+    pub fn new(device: Box<BlockDevice>) -> Box<Elevator> {
+        box Elevator {
+            device: device,
+            queue: Intex::new(Vec::new()),
+            busy: Cell::new(false),
+            position: Cell::new(0),
+            transfers: Cell::new(0),
+        }
+    }
+
+    /// Queue a request, and send the queue to the device unless another context is
+    fn submit(&mut self, block: u64, buffer: *mut u8, len: usize, write: bool, completion: Arc<BlockCompletion>) {
+        if len % self.device.block_size() != 0 {
+            completion.complete(Err(Error::new(EINVAL)));
+            return;
+        }
+
+        self.queue.lock().push(Request {
+            block: block,
+            buffer: buffer,
+            len: len,
+            write: write,
+            queued: self.transfers.get(),
+            completion: completion,
+        });
+
+        if ! self.busy.get() {
+            self.busy.set(true);
+            while let Some(requests) = self.next() {
+                self.transfer(requests);
+            }
+            self.busy.set(false);
+        }
+    }
+
+    /// Take the next request out of the queue, with the requests that follow it on the device
+    fn next(&self) -> Option<Vec<Request>> {
+        let mut queue = self.queue.lock();
+        if queue.is_empty() {
+            return None;
+        }
+
+        let transfers = self.transfers.get();
+        let position = self.position.get();
+        let starved = queue.iter().position(|request| transfers - request.queued >= STARVATION_LIMIT);
+        let index = starved.unwrap_or_else(|| {
+            // The closest request after the last transfer, or the lowest if there is none
+            let mut index = 0;
+            for (i, request) in queue.iter().enumerate() {
+                let best = &queue[index];
+                let ahead = request.block >= position;
+                let best_ahead = best.block >= position;
+                if (ahead && ! best_ahead) || (ahead == best_ahead && request.block < best.block) {
+                    index = i;
+                }
+            }
+            index
+        });
+
+        let mut requests = vec![queue.remove(index)];
+        let block_size = self.device.block_size() as u64;
+        loop {
+            let (end, write, len) = {
+                let last = &requests[requests.len() - 1];
+                (last.block + last.len as u64 / block_size, last.write, requests.iter().fold(0, |len, request| len + request.len))
+            };
+            match queue.iter().position(|request| request.block == end && request.write == write && len + request.len <= MAX_MERGE) {
+                Some(i) => requests.push(queue.remove(i)),
+                None => break,
+            }
+        }
+
+        Some(requests)
+    }
+
+    /// Send adjacent requests to the device as one transfer, and complete them
+    fn transfer(&mut self, requests: Vec<Request>) {
+        let block = requests[0].block;
+        let write = requests[0].write;
+        let len = requests.iter().fold(0, |len, request| len + request.len);
+
+        let result = if requests.len() == 1 {
+            let request = &requests[0];
+            unsafe {
+                if write {
+                    self.device.write_blocks(block, slice::from_raw_parts(request.buffer, len))
+                } else {
+                    self.device.read_blocks(block, slice::from_raw_parts_mut(request.buffer, len))
+                }
+            }
+        } else {
+            let mut data = Vec::with_capacity(len);
+            if write {
+                for request in requests.iter() {
+                    data.extend_from_slice(unsafe { slice::from_raw_parts(request.buffer, request.len) });
+                }
+                self.device.write_blocks(block, &data)
+            } else {
+                data.resize(len, 0);
+                let result = self.device.read_blocks(block, &mut data);
+                let mut offset = 0;
+                for request in requests.iter() {
+                    unsafe { slice::from_raw_parts_mut(request.buffer, request.len) }.copy_from_slice(&data[offset..offset + request.len]);
+                    offset += request.len;
+                }
+                result
+            }
+        };
+
+        self.position.set(block + (len / self.device.block_size()) as u64);
+        self.transfers.set(self.transfers.get() + 1);
+
+        // Each request gets the part of the transfer that covered it
+        let mut offset = 0;
+        for request in requests.iter() {
+            request.completion.complete(match result {
+                Ok(count) => Ok(cmp::min(count.saturating_sub(offset), request.len)),
+                Err(ref err) => Err(Error::new(err.errno)),
+            });
+            offset += request.len;
+        }
+    }
+}
+
+impl BlockDevice for Elevator {
+    fn name(&self) -> String {
+        self.device.name()
+    }
+
+    fn block_size(&self) -> usize {
+        self.device.block_size()
+    }
+
+    fn blocks(&self) -> Option<u64> {
+        self.device.blocks()
+    }
+
+    fn read_blocks(&mut self, block: u64, buffer: &mut [u8]) -> Result<usize> {
+        let completion = BlockCompletion::new();
+        self.submit(block, buffer.as_mut_ptr(), buffer.len(), false, completion.clone());
+        completion.wait()
+    }
+
+    fn write_blocks(&mut self, block: u64, buffer: &[u8]) -> Result<usize> {
+        let completion = BlockCompletion::new();
+        self.submit(block, buffer.as_ptr() as *mut u8, buffer.len(), true, completion.clone());
+        completion.wait()
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.device.flush()
+    }
+
+    unsafe fn read_blocks_async(&mut self, block: u64, buffer: &mut [u8], completion: Arc<BlockCompletion>) {
+        self.submit(block, buffer.as_mut_ptr(), buffer.len(), false, completion);
+    }
+
+    unsafe fn write_blocks_async(&mut self, block: u64, buffer: &[u8], completion: Arc<BlockCompletion>) {
+        self.submit(block, buffer.as_ptr() as *mut u8, buffer.len(), true, completion);
+    }
+}
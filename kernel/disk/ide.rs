@@ -7,7 +7,7 @@ use core::ptr;
 
 use arch::memory::Memory;
 
-use disk::Disk;
+use disk::BlockDevice;
 
 use drivers::pci::config::PciConfig;
 use drivers::io::{Io, Pio};
@@ -133,8 +133,8 @@ const IDE_ATAPI: u8 = 0x01;
 pub struct Ide;
 
 impl Ide {
-    pub fn disks(mut pci: PciConfig) -> Vec<Box<Disk>> {
-        let mut ret: Vec<Box<Disk>> = Vec::new();
+    pub fn disks(mut pci: PciConfig) -> Vec<Box<BlockDevice>> {
+        let mut ret: Vec<Box<BlockDevice>> = Vec::new();
 
         unsafe { pci.flag(4, 4, true) }; // Bus mastering
 
@@ -529,7 +529,7 @@ impl IdeDisk {
     }
 }
 
-impl Disk for IdeDisk {
+impl BlockDevice for IdeDisk {
     fn name(&self) -> String {
         format!("IDE {} {}", if self.irq == 0xE {
             "Primary"
@@ -542,11 +542,21 @@ impl Disk for IdeDisk {
         })
     }
 
-    fn read(&mut self, block: u64, buffer: &mut [u8]) -> Result<usize> {
+    fn read_blocks(&mut self, block: u64, buffer: &mut [u8]) -> Result<usize> {
         self.ata_dma(block, buffer.len() / 512, buffer.as_ptr() as usize, false)
     }
 
-    fn write(&mut self, block: u64, buffer: &[u8]) -> Result<usize> {
+    fn write_blocks(&mut self, block: u64, buffer: &[u8]) -> Result<usize> {
         self.ata_dma(block, buffer.len() / 512, buffer.as_ptr() as usize, true)
     }
+
+    fn flush(&mut self) -> Result<()> {
+        unsafe {
+            self.ata(ATA_CMD_CACHE_FLUSH_EXT, 0, 0);
+            if self.ide_poll(false) != 0 || self.cmdsts.read() & ATA_SR_ERR == ATA_SR_ERR {
+                return Err(Error::new(EIO));
+            }
+        }
+        Ok(())
+    }
 }
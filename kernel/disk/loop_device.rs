@@ -0,0 +1,68 @@
+use alloc::boxed::Box;
+
+use collections::borrow::ToOwned;
+use collections::string::String;
+
+use fs::{Resource, ResourceSeek, Url};
+
+use system::error::{Error, Result, EINVAL};
+
+use super::BlockDevice;
+
+/// The block size of a loop device
+const BLOCK_SIZE: usize = 512;
+
+/// A block device backed by a file, to use a disk image as a disk
+pub struct LoopDevice {
+    path: String,
+    resource: Box<Resource>,
+    size: u64,
+}
+
+impl LoopDevice {
+    /// Open the file at `path` as a block device
+    pub fn new(path: &str) -> Result<LoopDevice> {
+        let mut resource = try!(Url::from_str(path).and_then(|url| url.open()));
+        let size = try!(resource.seek(ResourceSeek::End(0))) as u64;
+
+        Ok(LoopDevice {
+            path: path.to_owned(),
+            resource: resource,
+            size: size,
+        })
+    }
+
+    /// Seek to `block`, checking that transfers of `len` bytes are whole blocks
+    fn seek(&mut self, block: u64, len: usize) -> Result<()> {
+        if len % BLOCK_SIZE != 0 {
+            return Err(Error::new(EINVAL));
+        }
+
+        try!(self.resource.seek(ResourceSeek::Start(block as usize * BLOCK_SIZE)));
+        Ok(())
+    }
+}
+
+impl BlockDevice for LoopDevice {
+    fn name(&self) -> String {
+        format!("Loop {}", self.path)
+    }
+
+    fn blocks(&self) -> Option<u64> {
+        Some(self.size / BLOCK_SIZE as u64)
+    }
+
+    fn read_blocks(&mut self, block: u64, buffer: &mut [u8]) -> Result<usize> {
+        try!(self.seek(block, buffer.len()));
+        self.resource.read(buffer)
+    }
+
+    fn write_blocks(&mut self, block: u64, buffer: &[u8]) -> Result<usize> {
+        try!(self.seek(block, buffer.len()));
+        self.resource.write(buffer)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.resource.sync()
+    }
+}
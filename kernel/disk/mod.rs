@@ -1,12 +1,126 @@
+use alloc::arc::Arc;
+use alloc::boxed::Box;
+
 use collections::string::String;
+use collections::vec::Vec;
+
+use env::Environment;
+
+use schemes::disk::DiskScheme;
+use schemes::file::FileScheme;
+
+use sync::WaitQueue;
 
 use system::error::Result;
 
+use self::elevator::Elevator;
+use self::partition::{Disk, PartitionDevice};
+
 pub mod ahci;
+pub mod cache;
+pub mod elevator;
 pub mod ide;
+pub mod loop_device;
+pub mod partition;
+pub mod ramdisk;
+
+/// Read the partitions of the disks found at boot, and add `disk:` and the `file:` scheme of the
+/// first file system on them
+///
+/// The partitions of a disk are tried in order, and the whole disk only if it has none. Requests
+/// to each disk go through an `Elevator`, which orders and merges those of all its partitions.
+pub fn init(env: &mut Environment, devices: Vec<Box<BlockDevice>>) {
+    let disks: Vec<Arc<Disk>> = devices.into_iter()
+                                       .enumerate()
+                                       .map(|(number, device)| Disk::new(number, Elevator::new(device)))
+                                       .collect();
 
-pub trait Disk {
+    let mut volumes: Vec<Box<BlockDevice>> = Vec::new();
+    for disk in disks.iter() {
+        if disk.partitions.is_empty() {
+            if let Some(device) = PartitionDevice::new(disk.clone(), 0) {
+                volumes.push(box device);
+            }
+        }
+        for partition in disk.partitions.iter() {
+            if let Some(device) = PartitionDevice::new(disk.clone(), partition.number) {
+                volumes.push(box device);
+            }
+        }
+    }
+
+    if let Some(module) = FileScheme::new(volumes) {
+        env.schemes.push(module);
+    }
+    env.schemes.push(DiskScheme::new(disks));
+}
+
+/// A device of fixed size blocks, like a disk
+///
+/// Filesystems and the partition scanner are written against this trait, so that every driver
+/// can be used by them. Buffers passed to `read_blocks` and `write_blocks` must be a multiple of
+/// the block size, and the number of bytes transferred is returned.
+pub trait BlockDevice {
+    /// The name of the device, for messages
     fn name(&self) -> String;
-    fn read(&mut self, block: u64, buffer: &mut [u8]) -> Result<usize>;
-    fn write(&mut self, block: u64, buffer: &[u8]) -> Result<usize>;
+
+    /// The size of a block in bytes
+    fn block_size(&self) -> usize {
+        512
+    }
+
+    /// The number of blocks, if the device knows it
+    fn blocks(&self) -> Option<u64> {
+        None
+    }
+
+    /// Read the blocks starting at `block`
+    fn read_blocks(&mut self, block: u64, buffer: &mut [u8]) -> Result<usize>;
+
+    /// Write the blocks starting at `block`
+    fn write_blocks(&mut self, block: u64, buffer: &[u8]) -> Result<usize>;
+
+    /// Write any blocks cached by the device to the medium
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Start reading the blocks starting at `block`, completing `completion` when done
+    ///
+    /// The buffer must stay valid until the request completes. By default the read is done
+    /// before returning, drivers that can complete requests from their interrupt handler override
+    /// this.
+    unsafe fn read_blocks_async(&mut self, block: u64, buffer: &mut [u8], completion: Arc<BlockCompletion>) {
+        completion.complete(self.read_blocks(block, buffer));
+    }
+
+    /// Start writing the blocks starting at `block`, completing `completion` when done
+    ///
+    /// The buffer must stay valid until the request completes.
+    unsafe fn write_blocks_async(&mut self, block: u64, buffer: &[u8], completion: Arc<BlockCompletion>) {
+        completion.complete(self.write_blocks(block, buffer));
+    }
+}
+
+/// The completion of a request started with `read_blocks_async` or `write_blocks_async`
+pub struct BlockCompletion {
+    result: WaitQueue<Result<usize>>,
+}
+
+impl BlockCompletion {
+    pub fn new() -> Arc<BlockCompletion> {
+        Arc::new(BlockCompletion {
+            result: WaitQueue::new(),
+        })
+    }
+
+    /// Complete the request with `result`, called by the driver
+    pub fn complete(&self, result: Result<usize>) {
+        self.result.send(result);
+    }
+
+    /// Wait for the request to complete
+    pub fn wait(&self) -> Result<usize> {
+        self.result.receive()
+    }
 }
@@ -0,0 +1,295 @@
+use alloc::arc::Arc;
+use alloc::boxed::Box;
+
+use collections::string::String;
+use collections::vec::Vec;
+
+use core::{cmp, u64};
+
+use system::error::Result;
+
+use super::BlockDevice;
+
+/// The MBR partition type of a protective entry, which covers a disk with a GPT
+const MBR_TYPE_GPT: u8 = 0xEE;
+/// The MBR partition types of extended partitions, whose logical partitions are not scanned
+const MBR_TYPE_EXTENDED: [u8; 3] = [0x05, 0x0F, 0x85];
+
+/// The signature of a GPT header
+const GPT_SIGNATURE: &'static [u8] = b"EFI PART";
+/// The most GPT entries read, 128 is the usual count
+const GPT_ENTRIES_MAX: usize = 256;
+
+/// A partition, in blocks of its disk
+#[derive(Copy, Clone, Debug)]
+pub struct Partition {
+    /// The number of the partition, from 1, in the order of the partition table
+    pub number: usize,
+    pub start: u64,
+    pub blocks: u64,
+}
+
+/// A disk, used by `disk:` and by the devices of its partitions
+///
+/// The device is the `Elevator` of the disk, which queues the transfers of several contexts,
+/// so every user calls it through the same pointer.
+pub struct Disk {
+    /// The number of the disk, from 0, in the order the disks were found
+    pub number: usize,
+    device: *mut BlockDevice,
+    pub partitions: Vec<Partition>,
+}
+
+impl Disk {
+    /// Read the partition table of `device`, if it has one
+    pub fn new(number: usize, mut device: Box<BlockDevice>) -> Arc<Disk> {
+        let partitions = scan(&mut *device);
+        for partition in partitions.iter() {
+            debugln!("{}: partition {} at {}, {} blocks",
+                     device.name(),
+                     partition.number,
+                     partition.start,
+                     partition.blocks);
+        }
+
+        Arc::new(Disk {
+            number: number,
+            device: Box::into_raw(device),
+            partitions: partitions,
+        })
+    }
+
+    pub fn device(&self) -> &mut BlockDevice {
+        unsafe { &mut *self.device }
+    }
+}
+
+impl Drop for Disk {
+    fn drop(&mut self) {
+        drop(unsafe { Box::from_raw(self.device) });
+    }
+}
+
+/// A range of the blocks of a disk, a partition or the whole disk
+///
+/// Transfers are clamped to the range, so that a file system in a partition does not write over
+/// the others or the boot loader.
+pub struct PartitionDevice {
+    disk: Arc<Disk>,
+    /// The number of the partition, 0 for the whole disk
+    number: usize,
+    start: u64,
+    blocks: u64,
+}
+
+impl PartitionDevice {
+    /// The partition `number` of `disk`, or the whole disk if it is 0
+    pub fn new(disk: Arc<Disk>, number: usize) -> Option<PartitionDevice> {
+        let (start, blocks) = if number == 0 {
+            (0, disk.device().blocks().unwrap_or(u64::MAX))
+        } else {
+            match disk.partitions.iter().find(|partition| partition.number == number) {
+                Some(partition) => (partition.start, partition.blocks),
+                None => return None,
+            }
+        };
+
+        Some(PartitionDevice {
+            disk: disk,
+            number: number,
+            start: start,
+            blocks: blocks,
+        })
+    }
+
+    /// The bytes of a transfer of `len` bytes from `block` that are inside the range
+    fn clamp(&self, block: u64, len: usize) -> usize {
+        let block_size = self.disk.device().block_size() as u64;
+        let left = self.blocks.saturating_sub(block).saturating_mul(block_size);
+        cmp::min(len as u64, left) as usize
+    }
+}
+
+impl BlockDevice for PartitionDevice {
+    fn name(&self) -> String {
+        if self.number == 0 {
+            self.disk.device().name()
+        } else {
+            format!("{} Partition {}", self.disk.device().name(), self.number)
+        }
+    }
+
+    fn block_size(&self) -> usize {
+        self.disk.device().block_size()
+    }
+
+    fn blocks(&self) -> Option<u64> {
+        if self.blocks == u64::MAX {
+            None
+        } else {
+            Some(self.blocks)
+        }
+    }
+
+    fn read_blocks(&mut self, block: u64, buffer: &mut [u8]) -> Result<usize> {
+        let len = self.clamp(block, buffer.len());
+        if len == 0 {
+            return Ok(0);
+        }
+        self.disk.device().read_blocks(self.start + block, &mut buffer[..len])
+    }
+
+    fn write_blocks(&mut self, block: u64, buffer: &[u8]) -> Result<usize> {
+        let len = self.clamp(block, buffer.len());
+        if len == 0 {
+            return Ok(0);
+        }
+        self.disk.device().write_blocks(self.start + block, &buffer[..len])
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.disk.device().flush()
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    (data[offset] as u32) | (data[offset + 1] as u32) << 8 | (data[offset + 2] as u32) << 16 |
+    (data[offset + 3] as u32) << 24
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    read_u32(data, offset) as u64 | (read_u32(data, offset + 4) as u64) << 32
+}
+
+/// The CRC-32 of the GPT header and entries
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFF;
+    for &b in data.iter() {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB88320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    ! crc
+}
+
+/// Read the blocks holding `len` bytes from `block`
+fn read(device: &mut BlockDevice, block: u64, len: usize) -> Option<Vec<u8>> {
+    let block_size = device.block_size();
+    let mut data = vec![0; (len + block_size - 1) / block_size * block_size];
+    match device.read_blocks(block, &mut data) {
+        Ok(count) if count >= len => Some(data),
+        _ => None,
+    }
+}
+
+/// Read the partitions of `device`, from its GPT or else its MBR
+///
+/// The boot sector of a disk without partitions has the MBR signature too, so a table is only
+/// used if every entry is empty or a partition inside the disk, and at least one is a partition.
+pub fn scan(device: &mut BlockDevice) -> Vec<Partition> {
+    let mbr = match read(device, 0, 512) {
+        Some(mbr) => mbr,
+        None => return Vec::new(),
+    };
+    if mbr[510] != 0x55 || mbr[511] != 0xAA {
+        return Vec::new();
+    }
+
+    let size = device.blocks().unwrap_or(u64::MAX);
+    let mut partitions = Vec::new();
+    for i in 0..4 {
+        let entry = &mbr[446 + i * 16 .. 446 + (i + 1) * 16];
+        let kind = entry[4];
+        let start = read_u32(entry, 8) as u64;
+        let blocks = read_u32(entry, 12) as u64;
+
+        if kind == 0 {
+            continue;
+        }
+        if (entry[0] != 0 && entry[0] != 0x80) || start == 0 || blocks == 0 ||
+           start.saturating_add(blocks) > size {
+            return Vec::new();
+        }
+
+        if kind == MBR_TYPE_GPT {
+            return gpt(device, size);
+        }
+        if ! MBR_TYPE_EXTENDED.contains(&kind) {
+            partitions.push(Partition {
+                number: i + 1,
+                start: start,
+                blocks: blocks,
+            });
+        }
+    }
+
+    partitions
+}
+
+/// Read the partitions of the GPT, checking the CRC of its header and entries
+fn gpt(device: &mut BlockDevice, size: u64) -> Vec<Partition> {
+    let header = match read(device, 1, 92) {
+        Some(header) => header,
+        None => return Vec::new(),
+    };
+    if &header[..8] != GPT_SIGNATURE {
+        debugln!("{}: protective MBR without a GPT", device.name());
+        return Vec::new();
+    }
+
+    let header_size = read_u32(&header, 12) as usize;
+    if header_size < 92 || header_size > header.len() {
+        return Vec::new();
+    }
+    let mut checked = header[..header_size].to_vec();
+    for b in checked[16..20].iter_mut() {
+        *b = 0;
+    }
+    if crc32(&checked) != read_u32(&header, 16) {
+        debugln!("{}: GPT header CRC mismatch", device.name());
+        return Vec::new();
+    }
+
+    let entries_block = read_u64(&header, 72);
+    let count = read_u32(&header, 80) as usize;
+    let entry_size = read_u32(&header, 84) as usize;
+    if count > GPT_ENTRIES_MAX || entry_size < 128 {
+        return Vec::new();
+    }
+
+    let entries = match read(device, entries_block, count * entry_size) {
+        Some(entries) => entries,
+        None => return Vec::new(),
+    };
+    if crc32(&entries[..count * entry_size]) != read_u32(&header, 88) {
+        debugln!("{}: GPT entries CRC mismatch", device.name());
+        return Vec::new();
+    }
+
+    let mut partitions = Vec::new();
+    for i in 0..count {
+        let entry = &entries[i * entry_size .. (i + 1) * entry_size];
+        if entry[..16].iter().all(|&b| b == 0) {
+            continue;
+        }
+
+        let first = read_u64(entry, 32);
+        let last = read_u64(entry, 40);
+        if first == 0 || last < first || last >= size {
+            continue;
+        }
+
+        partitions.push(Partition {
+            number: i + 1,
+            start: first,
+            blocks: last - first + 1,
+        });
+    }
+
+    partitions
+}
@@ -0,0 +1,63 @@
+use collections::string::String;
+use collections::vec::Vec;
+
+use core::cmp;
+
+use system::error::{Error, Result, EINVAL};
+
+use super::BlockDevice;
+
+/// The block size of a RAM disk
+const BLOCK_SIZE: usize = 512;
+
+/// A block device in memory, like an initial ramdisk
+pub struct RamDisk {
+    name: String,
+    data: Vec<u8>,
+}
+
+impl RamDisk {
+    /// Create a RAM disk holding `data`, which is padded to a whole number of blocks
+    pub fn new(name: String, mut data: Vec<u8>) -> RamDisk {
+        let len = (data.len() + BLOCK_SIZE - 1) / BLOCK_SIZE * BLOCK_SIZE;
+        data.resize(len, 0);
+
+        RamDisk {
+            name: name,
+            data: data,
+        }
+    }
+
+    /// The range of `data` for a transfer of `len` bytes starting at `block`
+    fn range(&self, block: u64, len: usize) -> Result<(usize, usize)> {
+        if len % BLOCK_SIZE != 0 {
+            return Err(Error::new(EINVAL));
+        }
+
+        let start = cmp::min(block.saturating_mul(BLOCK_SIZE as u64), self.data.len() as u64) as usize;
+        let end = cmp::min(start + len, self.data.len());
+        Ok((start, end))
+    }
+}
+
+impl BlockDevice for RamDisk {
+    fn name(&self) -> String {
+        format!("RAM disk {}", self.name)
+    }
+
+    fn blocks(&self) -> Option<u64> {
+        Some((self.data.len() / BLOCK_SIZE) as u64)
+    }
+
+    fn read_blocks(&mut self, block: u64, buffer: &mut [u8]) -> Result<usize> {
+        let (start, end) = try!(self.range(block, buffer.len()));
+        buffer[..end - start].copy_from_slice(&self.data[start..end]);
+        Ok(end - start)
+    }
+
+    fn write_blocks(&mut self, block: u64, buffer: &[u8]) -> Result<usize> {
+        let (start, end) = try!(self.range(block, buffer.len()));
+        self.data[start..end].copy_from_slice(&buffer[..end - start]);
+        Ok(end - start)
+    }
+}
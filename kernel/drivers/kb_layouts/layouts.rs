@@ -1,12 +1,124 @@
+use alloc::boxed::Box;
+
+use collections::Vec;
+
+use core::char;
+
+use system::error::{Error, Result, EINVAL};
+
 /// Public keyboard layouts
 /// The layout can be:
 /// *   English
 /// *   French
 /// *   German
+/// *   Custom, a keymap loaded at runtime
 pub enum Layout {
     English,
     French,
     German,
+    Custom(Box<Keymap>),
+}
+
+impl Layout {
+    /// Find a builtin layout by name
+    pub fn from_name(name: &str) -> Option<Layout> {
+        match name {
+            "english" => Some(Layout::English),
+            "french" => Some(Layout::French),
+            "german" => Some(Layout::German),
+            _ => None,
+        }
+    }
+
+    /// The name of the layout
+    pub fn name(&self) -> &str {
+        match *self {
+            Layout::English => "english",
+            Layout::French => "french",
+            Layout::German => "german",
+            Layout::Custom(_) => "custom",
+        }
+    }
+}
+
+/// The number of scancodes in a keymap
+pub const KEYMAP_SIZE: usize = 128;
+
+/// A keymap loaded at runtime
+///
+/// The text format has one key per line, `<scancode> <normal> [shift] [altgr]`, and one dead key
+/// composition per line, `dead <dead key> <key> <result>`. Scancodes are decimal or `0x`
+/// hexadecimal, characters are written as themselves, as `U+<hex>`, or as `none`. Empty lines
+/// and lines starting with `#` are ignored.
+pub struct Keymap {
+    /// The normal, shift and AltGr characters of each scancode
+    pub keys: Vec<[char; 3]>,
+    /// Dead key compositions, as the dead key, the following key and the result
+    pub dead: Vec<(char, char, char)>,
+}
+
+impl Keymap {
+    fn parse_char(arg: &str) -> Result<char> {
+        if arg == "none" {
+            Ok('\0')
+        } else if arg.starts_with("U+") && arg.len() > 2 {
+            u32::from_str_radix(&arg[2..], 16).ok().and_then(char::from_u32).ok_or(Error::new(EINVAL))
+        } else {
+            let mut chars = arg.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => Ok(c),
+                _ => Err(Error::new(EINVAL)),
+            }
+        }
+    }
+
+    /// Parse a keymap from its text format
+    pub fn parse(text: &str) -> Result<Keymap> {
+        let mut keymap = Keymap {
+            keys: vec![['\0'; 3]; KEYMAP_SIZE],
+            dead: Vec::new(),
+        };
+
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let args: Vec<&str> = line.split_whitespace().collect();
+            if args[0] == "dead" {
+                if args.len() != 4 {
+                    return Err(Error::new(EINVAL));
+                }
+                keymap.dead.push((try!(Keymap::parse_char(args[1])),
+                                  try!(Keymap::parse_char(args[2])),
+                                  try!(Keymap::parse_char(args[3]))));
+            } else {
+                if args.len() < 2 || args.len() > 4 {
+                    return Err(Error::new(EINVAL));
+                }
+
+                let scancode = try!(if args[0].starts_with("0x") {
+                    usize::from_str_radix(&args[0][2..], 16)
+                } else {
+                    args[0].parse::<usize>()
+                }.or(Err(Error::new(EINVAL))));
+                if scancode >= KEYMAP_SIZE {
+                    return Err(Error::new(EINVAL));
+                }
+
+                // Shift and AltGr default to the normal character
+                let normal = try!(Keymap::parse_char(args[1]));
+                let mut key = [normal; 3];
+                for (i, arg) in args.iter().enumerate().skip(2) {
+                    key[i - 1] = try!(Keymap::parse_char(arg));
+                }
+                keymap.keys[scancode] = key;
+            }
+        }
+
+        Ok(keymap)
+    }
 }
 
 /// Function to get the scancode from the current layout
@@ -23,6 +135,7 @@ pub fn get_scancode_from_layout(layout: &Layout, scancode: u8) -> [char; 3] {
         Layout::English => SCANCODES_EN[scancode as usize],
         Layout::French => SCANCODES_FR[scancode as usize],
         Layout::German => SCANCODES_DE[scancode as usize],
+        Layout::Custom(ref keymap) => keymap.keys[scancode as usize],
     }
 }
 
@@ -31,6 +144,7 @@ fn get_special_keys_from_layout(layout: &Layout, scancode: u8) -> [char; 3] {
         Layout::English => SCANCODES_EXTRA_EN,
         Layout::French => SCANCODES_EXTRA_FR,
         Layout::German => SCANCODES_EXTRA_DE,
+        Layout::Custom(ref keymap) => return keymap.keys.get(scancode as usize).map_or(['\0', '\0', '\0'], |&keys| keys),
     };
     match keys.iter().filter(|&&(code, _)| code == scancode).next() {
         Some(&(_, keys)) => keys,
@@ -38,6 +152,35 @@ fn get_special_keys_from_layout(layout: &Layout, scancode: u8) -> [char; 3] {
     }
 }
 
+fn get_dead_keys_from_layout(layout: &Layout) -> &[(char, char, char)] {
+    match *layout {
+        Layout::English => DEAD_KEYS_EN,
+        Layout::French => DEAD_KEYS_FR,
+        Layout::German => DEAD_KEYS_DE,
+        Layout::Custom(ref keymap) => &keymap.dead[..],
+    }
+}
+
+/// Is `character` a dead key in the layout
+pub fn is_dead_key(character: char, layout: &Layout) -> bool {
+    character != '\0' && get_dead_keys_from_layout(layout).iter().any(|&(dead, _, _)| dead == character)
+}
+
+/// Compose a dead key with the following character
+///
+/// A space gives the dead key itself, and a character that does not compose with the dead key
+/// is returned unchanged.
+pub fn compose(dead: char, character: char, layout: &Layout) -> char {
+    if character == ' ' {
+        return dead;
+    }
+
+    match get_dead_keys_from_layout(layout).iter().find(|&&(d, c, _)| d == dead && c == character) {
+        Some(&(_, _, result)) => result,
+        None => character,
+    }
+}
+
 
 /// Function to return the character associated with the scancode, and the layout
 pub fn char_for_scancode(scancode: u8, shift: bool, altgr: bool, layout: &Layout) -> char {
@@ -250,3 +393,23 @@ static SCANCODES_DE: [[char; 3]; 58] = [['\0', '\0', '\0'],
 
 /// Special keys, not present on every keyboard
 static SCANCODES_EXTRA_DE: &'static [(u8, [char; 3])] = &[(0x56, ['<', '>', '|'])];
+
+// DEAD KEYS
+
+/// Dead keys for English keyboards
+static DEAD_KEYS_EN: &'static [(char, char, char)] = &[];
+
+/// Dead keys for French keyboards, circumflex and diaeresis
+static DEAD_KEYS_FR: &'static [(char, char, char)] = &[('^', 'a', 'â'), ('^', 'e', 'ê'), ('^', 'i', 'î'),
+                                                        ('^', 'o', 'ô'), ('^', 'u', 'û'), ('^', 'A', 'Â'),
+                                                        ('^', 'E', 'Ê'), ('^', 'I', 'Î'), ('^', 'O', 'Ô'),
+                                                        ('^', 'U', 'Û'), ('¨', 'a', 'ä'), ('¨', 'e', 'ë'),
+                                                        ('¨', 'i', 'ï'), ('¨', 'o', 'ö'), ('¨', 'u', 'ü'),
+                                                        ('¨', 'y', 'ÿ'), ('¨', 'A', 'Ä'), ('¨', 'E', 'Ë'),
+                                                        ('¨', 'I', 'Ï'), ('¨', 'O', 'Ö'), ('¨', 'U', 'Ü')];
+
+/// Dead keys for German keyboards, circumflex
+static DEAD_KEYS_DE: &'static [(char, char, char)] = &[('^', 'a', 'â'), ('^', 'e', 'ê'), ('^', 'i', 'î'),
+                                                        ('^', 'o', 'ô'), ('^', 'u', 'û'), ('^', 'A', 'Â'),
+                                                        ('^', 'E', 'Ê'), ('^', 'I', 'Î'), ('^', 'O', 'Ô'),
+                                                        ('^', 'U', 'Û')];
@@ -2,8 +2,12 @@ pub extern crate io;
 
 /// PCI
 pub mod pci;
+/// Performance monitoring unit
+pub mod pmu;
 /// PS2
 pub mod ps2;
+/// Optional subsystems
+pub mod registry;
 /// RTC
 pub mod rtc;
 /// Serial
@@ -1,34 +1,23 @@
+use alloc::boxed::Box;
+
+use collections::vec::Vec;
+
+use disk::{self, BlockDevice};
 use disk::ahci::Ahci;
 use disk::ide::Ide;
 
-use env::Environment;
+use drivers::registry::{self, PciId};
 
-use schemes::file::FileScheme;
+use env::Environment;
 
 use super::config::PciConfig;
 use super::common::class::*;
 use super::common::subclass::*;
 use super::common::programming_interface::*;
 
-/*
-use super::common::vendorid::*;
-use super::common::deviceid::*;
-
-use audio::ac97::Ac97;
-use audio::intelhda::IntelHda;
-
-use network::rtl8139::Rtl8139;
-use network::intel8254x::Intel8254x;
-
-use usb::uhci::Uhci;
-use usb::ohci::Ohci;
-use usb::ehci::Ehci;
-use usb::xhci::Xhci;
-*/
-
-
-/// PCI device
+/// PCI device, the disks of storage controllers are added to `disks`
 pub unsafe fn pci_device(env: &mut Environment,
+                         disks: &mut Vec<Box<BlockDevice>>,
                          pci: PciConfig,
                          class_id: u8,
                          subclass_id: u8,
@@ -37,34 +26,31 @@ pub unsafe fn pci_device(env: &mut Environment,
                          device_code: u16) {
     match (class_id, subclass_id, interface_id) {
         (MASS_STORAGE, IDE, _) => {
-            if let Some(module) = FileScheme::new(Ide::disks(pci)) {
-                env.schemes.lock().push(module);
-            }
+            disks.extend(Ide::disks(pci));
         }
         (MASS_STORAGE, SATA, AHCI) => {
-            if let Some(module) = FileScheme::new(Ahci::disks(pci)) {
-                env.schemes.lock().push(module);
-            }
+            let (controller, controller_disks) = Ahci::new(pci);
+            let irq = controller.irq;
+            env.add_driver(controller, &[irq]);
+            disks.extend(controller_disks);
         }
-        /*
-        (SERIAL_BUS, USB, UHCI) => env.schemes.lock().push(Uhci::new(pci)),
-        (SERIAL_BUS, USB, OHCI) => env.schemes.lock().push(Ohci::new(pci)),
-        (SERIAL_BUS, USB, EHCI) => env.schemes.lock().push(Ehci::new(pci)),
-        (SERIAL_BUS, USB, XHCI) => env.schemes.lock().push(Xhci::new(pci)),
-        */
-        _ => match (vendor_code, device_code) {
-            //(REALTEK, RTL8139) => env.schemes.lock().push(Rtl8139::new(pci)),
-            //(INTEL, GBE_82540EM) => env.schemes.lock().push(Intel8254x::new(pci)),
-            //(INTEL, AC97_82801AA) => env.schemes.lock().push(Ac97::new(pci)),
-            //(INTEL, AC97_ICH4) => env.schemes.lock().push(Ac97::new(pci)),
-            //(INTEL, INTELHDA_ICH6) => env.schemes.lock().push(IntelHda::new(pci)),
-            _ => (),
+        _ => {
+            // Other devices are driven by optional subsystems
+            registry::pci_device(env, pci, &PciId {
+                class: class_id,
+                subclass: subclass_id,
+                interface: interface_id,
+                vendor: vendor_code,
+                device: device_code,
+            });
         }
     }
 }
 
-/// Initialize PCI session
+/// Initialize PCI session, then the disks found on it
 pub unsafe fn pci_init(env: &mut Environment) {
+    let mut disks = Vec::new();
+
     for bus in 0..256 {
         for slot in 0..32 {
             for func in 0..8 {
@@ -101,6 +87,7 @@ pub unsafe fn pci_init(env: &mut Environment) {
                     */
 
                     pci_device(env,
+                               &mut disks,
                                pci,
                                ((class_id >> 24) & 0xFF) as u8,
                                ((class_id >> 16) & 0xFF) as u8,
@@ -111,4 +98,28 @@ pub unsafe fn pci_init(env: &mut Environment) {
             }
         }
     }
+
+    disk::init(env, disks);
+}
+
+/// Stop PCI devices from writing to memory, before the kernel is replaced by kexec
+///
+/// Bus mastering is turned off for every device but disk controllers and bridges, which the new
+/// kernel uses as they are. The other drivers turn it back on when they start.
+pub unsafe fn pci_quiesce() {
+    for bus in 0..256 {
+        for slot in 0..32 {
+            for func in 0..8 {
+                let mut pci = PciConfig::new(bus as u8, slot as u8, func as u8);
+                let id = pci.read(0);
+
+                if (id & 0xFFFF) != 0xFFFF {
+                    let class_id = ((pci.read(8) >> 24) & 0xFF) as u8;
+                    if class_id != MASS_STORAGE && class_id != BRIDGE_DEVICE {
+                        pci.flag(4, 4, false);
+                    }
+                }
+            }
+        }
+    }
 }
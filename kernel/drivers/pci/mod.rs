@@ -2,4 +2,4 @@ pub mod config;
 pub mod common;
 mod init;
 
-pub use drivers::pci::init::pci_init;
+pub use drivers::pci::init::{pci_init, pci_quiesce};
@@ -0,0 +1,248 @@
+use collections::vec::Vec;
+
+/// Performance event select registers, one per programmable counter
+const IA32_PERFEVTSEL0: u32 = 0x186;
+/// Programmable counters
+const IA32_PMC0: u32 = 0xC1;
+/// Fixed counters, instructions retired, core cycles and reference cycles
+const IA32_FIXED_CTR0: u32 = 0x309;
+/// Fixed counter control, four bits per counter
+const IA32_FIXED_CTR_CTRL: u32 = 0x38D;
+/// Global enable, programmable counters in the low bits and fixed counters from bit 32
+const IA32_PERF_GLOBAL_CTRL: u32 = 0x38F;
+
+/// Count in ring 0
+const EVTSEL_OS: u64 = 1 << 17;
+/// Count in ring 3
+const EVTSEL_USR: u64 = 1 << 16;
+/// Enable the counter
+const EVTSEL_EN: u64 = 1 << 22;
+
+pub unsafe fn rdmsr(msr: u32) -> u64 {
+    let low: u32;
+    let high: u32;
+    asm!("rdmsr" : "={eax}"(low), "={edx}"(high) : "{ecx}"(msr) : : "intel", "volatile");
+    (high as u64) << 32 | low as u64
+}
+
+pub unsafe fn wrmsr(msr: u32, value: u64) {
+    let low = value as u32;
+    let high = (value >> 32) as u32;
+    asm!("wrmsr" : : "{ecx}"(msr), "{eax}"(low), "{edx}"(high) : : "intel", "volatile");
+}
+
+/// An event that can be counted
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PerfEvent {
+    /// Unhalted core cycles
+    Cycles,
+    /// Instructions retired
+    Instructions,
+    /// Last level cache misses
+    CacheMisses,
+    /// Mispredicted branches retired
+    BranchMisses,
+}
+
+pub const PERF_EVENTS: [PerfEvent; 4] = [PerfEvent::Cycles,
+                                         PerfEvent::Instructions,
+                                         PerfEvent::CacheMisses,
+                                         PerfEvent::BranchMisses];
+
+impl PerfEvent {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            PerfEvent::Cycles => "cycles",
+            PerfEvent::Instructions => "instructions",
+            PerfEvent::CacheMisses => "cache-misses",
+            PerfEvent::BranchMisses => "branch-misses",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<PerfEvent> {
+        for event in PERF_EVENTS.iter() {
+            if event.name() == name {
+                return Some(*event);
+            }
+        }
+        None
+    }
+
+    /// The architectural event select and unit mask
+    fn select(&self) -> u64 {
+        match *self {
+            PerfEvent::Cycles => 0x003C,
+            PerfEvent::Instructions => 0x00C0,
+            PerfEvent::CacheMisses => 0x412E,
+            PerfEvent::BranchMisses => 0x00C5,
+        }
+    }
+
+    /// The fixed counter for this event, if there is one
+    fn fixed(&self) -> Option<usize> {
+        match *self {
+            PerfEvent::Instructions => Some(0),
+            PerfEvent::Cycles => Some(1),
+            _ => None,
+        }
+    }
+}
+
+/// A hardware counter
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PerfSlot {
+    Fixed(usize),
+    General(usize),
+}
+
+/// The architectural performance monitoring unit
+pub struct Pmu {
+    /// Architectural performance monitoring version
+    pub version: u8,
+    /// Number of programmable counters
+    pub general: usize,
+    /// Width of the programmable counters in bits
+    pub general_width: usize,
+    /// Number of fixed counters
+    pub fixed: usize,
+    /// Width of the fixed counters in bits
+    pub fixed_width: usize,
+    /// Counters in use
+    pub used: Vec<PerfSlot>,
+}
+
+impl Pmu {
+    /// Detect the PMU with CPUID leaf 0xA
+    pub fn new() -> Option<Pmu> {
+        let max: u32;
+        unsafe {
+            asm!("cpuid" : "={eax}"(max) : "{eax}"(0) : "ebx", "ecx", "edx" : "intel", "volatile");
+        }
+        if max < 0xA {
+            return None;
+        }
+
+        let eax: u32;
+        let edx: u32;
+        unsafe {
+            asm!("cpuid" : "={eax}"(eax), "={edx}"(edx) : "{eax}"(0xA), "{ecx}"(0) : "ebx" : "intel", "volatile");
+        }
+
+        let version = eax as u8;
+        if version == 0 {
+            return None;
+        }
+
+        let mut pmu = Pmu {
+            version: version,
+            general: (eax >> 8) as u8 as usize,
+            general_width: (eax >> 16) as u8 as usize,
+            fixed: 0,
+            fixed_width: 0,
+            used: Vec::new(),
+        };
+
+        // Fixed counters are only enumerated from version 2
+        if version > 1 {
+            pmu.fixed = (edx & 0x1F) as usize;
+            pmu.fixed_width = (edx >> 5) as u8 as usize;
+        }
+
+        Some(pmu)
+    }
+
+    /// Mask a raw counter value to the counter width
+    pub fn mask(&self, slot: PerfSlot) -> u64 {
+        let width = match slot {
+            PerfSlot::Fixed(_) => self.fixed_width,
+            PerfSlot::General(_) => self.general_width,
+        };
+
+        if width >= 64 {
+            !0
+        } else {
+            (1u64 << width) - 1
+        }
+    }
+
+    /// Allocate and start a counter for `event`, preferring a fixed counter
+    pub fn start(&mut self, event: PerfEvent) -> Option<PerfSlot> {
+        let mut slot = None;
+        if let Some(i) = event.fixed() {
+            if i < self.fixed && ! self.used.contains(&PerfSlot::Fixed(i)) {
+                slot = Some(PerfSlot::Fixed(i));
+            }
+        }
+        if slot.is_none() {
+            for i in 0..self.general {
+                if ! self.used.contains(&PerfSlot::General(i)) {
+                    slot = Some(PerfSlot::General(i));
+                    break;
+                }
+            }
+        }
+
+        if let Some(slot) = slot {
+            unsafe {
+                match slot {
+                    PerfSlot::Fixed(i) => {
+                        wrmsr(IA32_FIXED_CTR0 + i as u32, 0);
+                        let ctrl = rdmsr(IA32_FIXED_CTR_CTRL) & !(0xF << (i * 4));
+                        // Count in ring 0 and ring 3
+                        wrmsr(IA32_FIXED_CTR_CTRL, ctrl | 0x3 << (i * 4));
+                    },
+                    PerfSlot::General(i) => {
+                        wrmsr(IA32_PMC0 + i as u32, 0);
+                        wrmsr(IA32_PERFEVTSEL0 + i as u32, event.select() | EVTSEL_OS | EVTSEL_USR | EVTSEL_EN);
+                    }
+                }
+
+                if self.version > 1 {
+                    let bit = self.global_bit(slot);
+                    wrmsr(IA32_PERF_GLOBAL_CTRL, rdmsr(IA32_PERF_GLOBAL_CTRL) | bit);
+                }
+            }
+
+            self.used.push(slot);
+        }
+
+        slot
+    }
+
+    /// Stop and free a counter
+    pub fn stop(&mut self, slot: PerfSlot) {
+        unsafe {
+            if self.version > 1 {
+                let bit = self.global_bit(slot);
+                wrmsr(IA32_PERF_GLOBAL_CTRL, rdmsr(IA32_PERF_GLOBAL_CTRL) & !bit);
+            }
+
+            match slot {
+                PerfSlot::Fixed(i) => {
+                    let ctrl = rdmsr(IA32_FIXED_CTR_CTRL) & !(0xF << (i * 4));
+                    wrmsr(IA32_FIXED_CTR_CTRL, ctrl);
+                },
+                PerfSlot::General(i) => wrmsr(IA32_PERFEVTSEL0 + i as u32, 0),
+            }
+        }
+
+        self.used.retain(|used| *used != slot);
+    }
+
+    /// Read the raw value of a counter
+    pub fn read(&self, slot: PerfSlot) -> u64 {
+        unsafe {
+            match slot {
+                PerfSlot::Fixed(i) => rdmsr(IA32_FIXED_CTR0 + i as u32),
+                PerfSlot::General(i) => rdmsr(IA32_PMC0 + i as u32),
+            }
+        }
+    }
+
+    fn global_bit(&self, slot: PerfSlot) -> u64 {
+        match slot {
+            PerfSlot::Fixed(i) => 1u64 << (32 + i),
+            PerfSlot::General(i) => 1u64 << i,
+        }
+    }
+}
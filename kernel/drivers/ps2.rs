@@ -1,8 +1,14 @@
 use alloc::boxed::Box;
 
+use arch::gdb::GDB_REQUEST;
+use arch::intex::Intex;
+
+use collections::vec_deque::VecDeque;
+
 use core::cmp;
+use core::sync::atomic::{AtomicBool, Ordering};
 
-use common::event::{KeyEvent, MouseEvent};
+use common::event::{KeyEvent, MouseEvent, K_F1, K_F6, K_PGDN, K_PGUP};
 
 use drivers::io::{Io, Pio};
 
@@ -26,8 +32,12 @@ pub struct Ps2 {
     caps_lock: bool,
     /// Caps lock toggle
     caps_lock_toggle: bool,
+    /// Left Alt?
+    alt: bool,
     /// AltGr?
     altgr: bool,
+    /// Control?
+    ctrl: bool,
     /// The mouse packet
     mouse_packet: [u8; 4],
     /// Mouse packet index
@@ -36,11 +46,18 @@ pub struct Ps2 {
     mouse_x: i32,
     /// Mouse point y
     mouse_y: i32,
-    /// Layout for keyboard
-    /// Default: English
-    layout: layouts::Layout,
+    /// The pending dead key
+    dead: Option<char>,
+    /// Key events for the console, with whether shift and alt were held, handled outside of the
+    /// interrupt
+    keys: Intex<VecDeque<(KeyEvent, bool, bool)>>,
+    /// Set while handling `keys` is deferred and has not run yet
+    deferred: AtomicBool,
 }
 
+/// The number of key events reserved for in `Ps2::keys`
+const KEYS_CAPACITY: usize = 64;
+
 impl Ps2 {
     /// Create new PS2 data
     pub fn new() -> Box<Self> {
@@ -51,12 +68,16 @@ impl Ps2 {
             rshift: false,
             caps_lock: false,
             caps_lock_toggle: false,
+            alt: false,
             altgr: false,
+            ctrl: false,
             mouse_packet: [0; 4],
             mouse_i: 0,
             mouse_x: 0,
             mouse_y: 0,
-            layout: layouts::Layout::English,
+            dead: None,
+            keys: Intex::new(VecDeque::with_capacity(KEYS_CAPACITY)),
+            deferred: AtomicBool::new(false),
         };
 
         unsafe {
@@ -167,15 +188,81 @@ impl Ps2 {
             }
         }
 
+        if scancode == 0x38 {
+            self.alt = true;
+        } else if scancode == 0xB8 {
+            self.alt = false;
+        }
+
+        // Left and right control share a scancode
+        if scancode == 0x1D {
+            self.ctrl = true;
+        } else if scancode == 0x9D {
+            self.ctrl = false;
+        }
+
+        // Right Alt + F12 breaks into the debugger
+        if scancode == 0x58 && self.altgr {
+            GDB_REQUEST.store(true, Ordering::SeqCst);
+        }
+
         let shift = self.caps_lock != (self.lshift || self.rshift);
 
+        let mut character = {
+            let layout = ::env().layout.lock();
+            let character = layouts::char_for_scancode(scancode & 0x7F, shift, self.altgr, &layout);
+            // A dead key only gives a character when combined with the next key press
+            if scancode >= 0x80 || character == '\0' {
+                character
+            } else if let Some(dead) = self.dead.take() {
+                layouts::compose(dead, character, &layout)
+            } else if layouts::is_dead_key(character, &layout) {
+                self.dead = Some(character);
+                '\0'
+            } else {
+                character
+            }
+        };
+        // Control with a letter gives the control character, so that Ctrl+C is 0x03
+        if self.ctrl {
+            if let 'a' ... 'z' | 'A' ... 'Z' = character {
+                character = ((character as u8) & 0x1F) as char;
+            }
+        }
+
         return Some(KeyEvent {
-            character: layouts::char_for_scancode(scancode & 0x7F, shift, self.altgr, &self.layout),
+            character: character,
             scancode: scancode & 0x7F,
             pressed: scancode < 0x80,
         });
     }
 
+    /// Send the queued key events to the active virtual terminal, or to the display server
+    fn handle_keys(&mut self) {
+        self.deferred.store(false, Ordering::SeqCst);
+
+        loop {
+            let (key_event, shift, alt) = match self.keys.lock().pop_front() {
+                Some(key) => key,
+                None => break,
+            };
+
+            let mut vts = ::env().vts.lock();
+            if alt && key_event.pressed && key_event.scancode >= K_F1 && key_event.scancode <= K_F6 {
+                vts.switch((key_event.scancode - K_F1) as usize);
+            } else if shift && key_event.pressed && (key_event.scancode == K_PGUP || key_event.scancode == K_PGDN) && vts.active().draw {
+                // Scroll back by half a screen
+                let console = vts.active_mut();
+                let rows = (console.cells.len() / 2) as isize;
+                console.scrollback(if key_event.scancode == K_PGUP { rows } else { -rows });
+            } else if vts.active().draw {
+                vts.active_mut().event(key_event.to_event());
+            } else {
+                ::env().events.send(key_event.to_event());
+            }
+        }
+    }
+
     unsafe fn mouse_cmd(&mut self, byte: u8) -> u8 {
         self.wait1();
         self.cmd.write(0xD4);
@@ -261,43 +348,54 @@ impl Ps2 {
 
         return None;
     }
+}
 
-    /// Function to change the layout of the keyboard
-    pub fn change_layout(&mut self, layout: usize) {
-        self.layout = match layout {
-            0 => layouts::Layout::English,
-            1 => layouts::Layout::French,
-            2 => layouts::Layout::German,
-            _ => layouts::Layout::English,
+impl KScheme for Ps2 {
+    /// The controller is reset by the firmware on wake, with the keyboard and mouse disabled
+    fn resume(&mut self) {
+        self.mouse_packet = [0; 4];
+        self.mouse_i = 0;
+        unsafe {
+            self.keyboard_init();
+            self.mouse_init();
         }
     }
-}
 
-impl KScheme for Ps2 {
     fn on_irq(&mut self, irq: u8) {
         if irq == 0x1 || irq == 0xC {
             loop {
                 let status = self.cmd.read();
                 if status & 0x21 == 0x21 {
                     if let Some(mouse_event) = self.mouse_interrupt() {
-                        if ::env().console.lock().draw {
+                        if ::env().vts.lock().active().draw {
                             //Ignore mouse event
                         } else {
-                            ::env().events.send(mouse_event.to_event());
+                            ::env().events.try_send(mouse_event.to_event());
                         }
                     }
                 } else if status & 0x21 == 1 {
                     if let Some(key_event) = self.keyboard_interrupt() {
-                        if ::env().console.lock().draw {
-                            ::env().console.lock().event(key_event.to_event());
-                        } else {
-                            ::env().events.send(key_event.to_event());
+                        let shift = self.lshift || self.rshift;
+                        let mut keys = self.keys.lock();
+                        if keys.len() < keys.capacity() {
+                            keys.push_back((key_event, shift, self.alt));
                         }
                     }
                 } else {
                     break;
                 }
             }
+
+            // The console allocates as it handles keys, so it is done outside of the interrupt
+            if ! self.keys.lock().is_empty() && ! self.deferred.swap(true, Ordering::SeqCst) {
+                let ps2 = self as *mut Ps2 as usize;
+                let deferred = ::env().irqs.defer(box move || {
+                    unsafe { (*(ps2 as *mut Ps2)).handle_keys() };
+                });
+                if ! deferred {
+                    self.deferred.store(false, Ordering::SeqCst);
+                }
+            }
         }
     }
 }
@@ -0,0 +1,66 @@
+//! Optional subsystems
+//!
+//! Subsystems are enabled with features, passed to rustc as `--cfg feature="<name>"` from
+//! `KERNEL_FEATURES` in the Makefile. Each one is described by a `Subsystem` listed here behind its
+//! feature, so enabling the feature is enough for its schemes to be registered at boot and for its
+//! drivers to be given PCI devices.
+
+use drivers::pci::config::PciConfig;
+
+use env::Environment;
+
+/// The identity of a PCI device
+pub struct PciId {
+    pub class: u8,
+    pub subclass: u8,
+    pub interface: u8,
+    pub vendor: u16,
+    pub device: u16,
+}
+
+/// An optional subsystem
+pub struct Subsystem {
+    /// The feature that enables it
+    pub name: &'static str,
+    /// Register the schemes that do not belong to a device
+    pub init: unsafe fn(&mut Environment),
+    /// Start a driver for a PCI device, returning false if the device is not supported
+    pub pci_device: unsafe fn(&mut Environment, PciConfig, &PciId) -> bool,
+}
+
+/// For subsystems without schemes of their own
+pub unsafe fn no_init(_: &mut Environment) {}
+
+/// For subsystems without PCI drivers
+pub unsafe fn no_pci_device(_: &mut Environment, _: PciConfig, _: &PciId) -> bool {
+    false
+}
+
+/// The subsystems that are compiled in
+pub static SUBSYSTEMS: &'static [Subsystem] = &[
+    #[cfg(feature = "audio")]
+    ::audio::SUBSYSTEM,
+    #[cfg(feature = "network")]
+    ::network::SUBSYSTEM,
+    #[cfg(feature = "trace")]
+    ::schemes::trace::SUBSYSTEM,
+    #[cfg(feature = "usb")]
+    ::usb::SUBSYSTEM,
+];
+
+/// Register the schemes of every subsystem
+pub unsafe fn init(env: &mut Environment) {
+    for subsystem in SUBSYSTEMS.iter() {
+        (subsystem.init)(env);
+    }
+}
+
+/// Give a PCI device to the first subsystem with a driver for it
+pub unsafe fn pci_device(env: &mut Environment, pci: PciConfig, id: &PciId) -> bool {
+    for subsystem in SUBSYSTEMS.iter() {
+        if (subsystem.pci_device)(env, pci, id) {
+            return true;
+        }
+    }
+    false
+}
@@ -33,6 +33,15 @@ impl Rtc {
         while self.read(0xA) & 0x80 == 0x80 {}
     }
 
+    /// Sample the raw clock registers without waiting for an update, for use as entropy
+    pub fn sample(&mut self) -> u64 {
+        let mut value = 0;
+        for reg in [0, 2, 4, 7, 8, 9].iter() {
+            value = value << 8 | unsafe { self.read(*reg) } as u64;
+        }
+        value
+    }
+
     /// Get time
     pub fn time(&mut self) -> Duration {
         let mut second;
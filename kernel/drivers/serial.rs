@@ -1,7 +1,5 @@
 use alloc::boxed::Box;
 
-use collections::string::String;
-
 use common::event;
 
 use drivers::io::{Io, Pio};
@@ -76,12 +74,6 @@ impl KScheme for Serial {
                 } else if c == 'D' {
                     sc = event::K_LEFT;
                 }
-            } else if c == '\x03' {
-                ::env().console.lock().write(b"^C\n");
-                ::env().console.lock().commands.send(String::new());
-
-                c = '\0';
-                sc = 0;
             } else if c == '\x1B' {
                 self.escape = true;
                 c = '\0';
@@ -99,7 +91,7 @@ impl KScheme for Serial {
                     pressed: true,
                 };
 
-                ::env().console.lock().event(key_event.to_event());
+                ::env().vts.lock().consoles[0].event(key_event.to_event());
             }
         }
     }
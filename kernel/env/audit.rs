@@ -0,0 +1,91 @@
+use collections::string::String;
+use collections::vec_deque::VecDeque;
+
+use common::time::Duration;
+
+/// The number of audit events kept before the oldest are overwritten
+pub const AUDIT_CAPACITY: usize = 256;
+
+/// The kind of a security-relevant event
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AuditKind {
+    /// A userspace scheme was registered
+    SchemeRegister,
+    /// A context changed its privileges
+    PrivilegeChange,
+    /// An open was refused for lack of permission
+    OpenDenied,
+    /// A context was killed by another context
+    Kill,
+}
+
+impl AuditKind {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            AuditKind::SchemeRegister => "SCHEME",
+            AuditKind::PrivilegeChange => "PRIV",
+            AuditKind::OpenDenied => "DENIED",
+            AuditKind::Kill => "KILL",
+        }
+    }
+}
+
+/// A single audit record
+pub struct AuditEvent {
+    /// Monotonic time of the event
+    pub time: Duration,
+    /// The PID of the context that caused the event
+    pub pid: usize,
+    /// The user ID of the context that caused the event
+    pub uid: usize,
+    /// The kind of event
+    pub kind: AuditKind,
+    /// A description of the event
+    pub message: String,
+}
+
+/// An append-only ring buffer of audit events
+pub struct AuditLog {
+    pub events: VecDeque<AuditEvent>,
+    /// The number of events that were overwritten
+    pub dropped: usize,
+}
+
+impl AuditLog {
+    pub fn new() -> AuditLog {
+        AuditLog {
+            events: VecDeque::new(),
+            dropped: 0,
+        }
+    }
+
+    /// Append an event, overwriting the oldest if the log is full
+    pub fn push(&mut self, event: AuditEvent) {
+        while self.events.len() >= AUDIT_CAPACITY {
+            self.events.pop_front();
+            self.dropped += 1;
+        }
+        self.events.push_back(event);
+    }
+
+    /// Format the log, one event per line
+    pub fn to_string(&self) -> String {
+        let mut string = String::new();
+
+        if self.dropped > 0 {
+            string.push_str(&format!("{} events dropped\n", self.dropped));
+        }
+
+        for event in self.events.iter() {
+            string.push_str(&format!("[{:>5}.{:09}] {:<8}PID {:<6}UID {:<6}{}\n",
+                                     event.time.secs,
+                                     event.time.nanos,
+                                     event.kind.name(),
+                                     event.pid,
+                                     event.uid,
+                                     event.message));
+        }
+
+        string
+    }
+}
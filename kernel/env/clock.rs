@@ -0,0 +1,151 @@
+use common::random::rdtsc;
+use common::time::{Duration, NANOS_PER_SEC};
+
+use drivers::rtc::Rtc;
+
+use system::vdso::VdsoData;
+
+use super::vdso;
+
+/// The PIT (programmable interval timer) duration.
+///
+/// This duration defines the PIT interval, which is added to the monotonic clock when interrupt
+/// 0x20 is received.
+pub const PIT_DURATION: Duration = Duration {
+    secs: 0,
+    nanos: 4500572,
+};
+
+/// The number of ticks used to measure the TSC frequency
+const TSC_CALIBRATE_TICKS: u64 = 64;
+
+/// The maximum slew in nanoseconds per tick, 500 parts per million of `PIT_DURATION` like BSD
+/// `adjtime`
+const SLEW_PER_TICK: i64 = 2250;
+
+/// Timekeeping
+///
+/// The PIT tick is the monotonic clock source, interpolated between ticks with the TSC once its
+/// frequency has been measured against the PIT. The realtime clock is an offset from the
+/// monotonic clock, read from the RTC at boot, which can be stepped with `set_realtime` or slewed
+/// with `adjust`. Each change is copied to the time page of `env::vdso`, from which user
+/// contexts read the clocks without a syscall.
+pub struct Clock {
+    /// PIT ticks since boot
+    pub ticks: u64,
+    /// Monotonic time at the last tick
+    monotonic: Duration,
+    /// Realtime minus monotonic time
+    offset: Duration,
+    /// Nanoseconds still to be slewed into `offset`
+    adjustment: i64,
+    /// TSC at the last tick
+    tsc_tick: u64,
+    /// TSC at the first tick, while calibrating
+    tsc_start: u64,
+    /// TSC frequency in Hz, once calibrated
+    pub tsc_hz: Option<u64>,
+}
+
+impl Clock {
+    pub fn new() -> Clock {
+        Clock {
+            ticks: 0,
+            monotonic: Duration::new(0, 0),
+            offset: Duration::new(0, 0),
+            adjustment: 0,
+            tsc_tick: 0,
+            tsc_start: 0,
+            tsc_hz: None,
+        }
+    }
+
+    /// Set the realtime clock from the RTC
+    pub fn init(&mut self) {
+        let time = Rtc::new().time();
+        self.set_realtime(time);
+    }
+
+    /// Advance the clocks, called on each PIT interrupt
+    pub fn tick(&mut self) {
+        let tsc = rdtsc();
+
+        self.ticks += 1;
+        self.monotonic = self.monotonic + PIT_DURATION;
+        self.tsc_tick = tsc;
+
+        if self.ticks == 1 {
+            self.tsc_start = tsc;
+        } else if self.ticks == TSC_CALIBRATE_TICKS + 1 {
+            let nanos = (PIT_DURATION.secs as u64 * NANOS_PER_SEC as u64 + PIT_DURATION.nanos as u64) * TSC_CALIBRATE_TICKS;
+            let hz = tsc.wrapping_sub(self.tsc_start) * NANOS_PER_SEC as u64 / nanos;
+            // A TSC slower than 1 MHz is too coarse to interpolate with
+            if hz >= 1000000 {
+                self.tsc_hz = Some(hz);
+            }
+        }
+
+        if self.adjustment != 0 {
+            let slew = if self.adjustment > SLEW_PER_TICK {
+                SLEW_PER_TICK
+            } else if self.adjustment < -SLEW_PER_TICK {
+                -SLEW_PER_TICK
+            } else {
+                self.adjustment
+            };
+            self.offset = self.offset + Duration::new(0, slew as i32);
+            self.adjustment -= slew;
+        }
+
+        self.publish();
+    }
+
+    /// Copy the clock to the time page
+    fn publish(&self) {
+        vdso::publish(VdsoData {
+            seq: 0,
+            ticks: self.ticks,
+            monotonic_secs: self.monotonic.secs,
+            monotonic_nanos: self.monotonic.nanos as i64,
+            offset_secs: self.offset.secs,
+            offset_nanos: self.offset.nanos as i64,
+            tsc_tick: self.tsc_tick,
+            tsc_hz: self.tsc_hz.unwrap_or(0),
+            tick_nanos: PIT_DURATION.nanos as u64,
+        });
+    }
+
+    /// Time since boot
+    pub fn monotonic(&self) -> Duration {
+        let mut nanos = 0;
+        if let Some(hz) = self.tsc_hz {
+            nanos = rdtsc().wrapping_sub(self.tsc_tick) * NANOS_PER_SEC as u64 / hz;
+            // Never run past the next tick, so the clock cannot go backwards
+            if nanos >= PIT_DURATION.nanos as u64 {
+                nanos = PIT_DURATION.nanos as u64 - 1;
+            }
+        }
+        self.monotonic + Duration::new(0, nanos as i32)
+    }
+
+    /// Time since the epoch
+    pub fn realtime(&self) -> Duration {
+        self.monotonic() + self.offset
+    }
+
+    /// Step the realtime clock, cancelling any adjustment in progress
+    pub fn set_realtime(&mut self, time: Duration) {
+        self.offset = time - self.monotonic();
+        self.adjustment = 0;
+        self.publish();
+    }
+
+    /// Slew the realtime clock by `delta` nanoseconds, replacing any adjustment in progress
+    ///
+    /// Returns the adjustment that had not been applied yet.
+    pub fn adjust(&mut self, delta: i64) -> i64 {
+        let remaining = self.adjustment;
+        self.adjustment = delta;
+        remaining
+    }
+}
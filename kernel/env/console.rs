@@ -1,19 +1,25 @@
 use alloc::boxed::Box;
 
+use arch::serial;
+
 use collections::String;
 use collections::Vec;
+use collections::vec_deque::VecDeque;
 
 use common::event::{self, Event, EventOption};
 
-use core::mem;
-
-use drivers::io::{Io, Pio};
+use core::cmp;
 
 use graphics::color::Color;
 use graphics::display::Display;
 
 use sync::WaitQueue;
 
+use system::error::Result;
+use system::syscall::{Winsize, ECHO, ICANON, VERASE};
+
+use super::tty::{self, LineDiscipline};
+
 const BLACK: Color = Color::new(0, 0, 0);
 const RED: Color = Color::new(194, 54, 33);
 const GREEN: Color = Color::new(37, 188, 36);
@@ -22,6 +28,36 @@ const BLUE: Color = Color::new(73, 46, 225);
 const MAGENTA: Color = Color::new(211, 56, 211);
 const CYAN: Color = Color::new(51, 187, 200);
 const WHITE: Color = Color::new(203, 204, 205);
+const BRIGHT_BLACK: Color = Color::new(129, 131, 131);
+const BRIGHT_RED: Color = Color::new(252, 57, 31);
+const BRIGHT_GREEN: Color = Color::new(49, 231, 34);
+const BRIGHT_YELLOW: Color = Color::new(234, 236, 35);
+const BRIGHT_BLUE: Color = Color::new(88, 51, 255);
+const BRIGHT_MAGENTA: Color = Color::new(249, 53, 248);
+const BRIGHT_CYAN: Color = Color::new(20, 240, 240);
+const BRIGHT_WHITE: Color = Color::new(233, 235, 235);
+
+/// The colors selected by SGR 30-37 and 40-47, followed by the bright colors of SGR 90-97 and
+/// 100-107
+const PALETTE: [Color; 16] = [BLACK, RED, GREEN, YELLOW, BLUE, MAGENTA, CYAN, WHITE,
+                              BRIGHT_BLACK, BRIGHT_RED, BRIGHT_GREEN, BRIGHT_YELLOW,
+                              BRIGHT_BLUE, BRIGHT_MAGENTA, BRIGHT_CYAN, BRIGHT_WHITE];
+
+/// The number of screens of history kept for scrollback
+const SCROLLBACK_SCREENS: usize = 4;
+
+/// A character cell, kept so that the screen can be drawn again after showing the scrollback
+#[derive(Copy, Clone)]
+pub struct Cell {
+    pub c: char,
+    pub foreground: Color,
+    pub background: Color,
+}
+
+/// The default foreground palette index
+const DEFAULT_FOREGROUND: usize = 7;
+/// The default background palette index
+const DEFAULT_BACKGROUND: usize = 0;
 
 pub struct Console {
     pub display: Option<Box<Display>>,
@@ -29,95 +65,404 @@ pub struct Console {
     pub point_y: usize,
     pub foreground: Color,
     pub background: Color,
+    /// The foreground palette index
+    pub fg: usize,
+    /// The background palette index
+    pub bg: usize,
+    /// Bold text is drawn with the bright colors
+    pub bold: bool,
+    /// Swap the foreground and background colors
+    pub reverse: bool,
+    /// The cursor position saved by `ESC 7` or `CSI s`
+    pub saved: (usize, usize),
+    /// The top and bottom rows of the scrolling region, the whole display if none
+    pub region: Option<(usize, usize)>,
+    /// The cell under the cursor is currently inverted
+    pub cursor: bool,
+    /// The contents of the screen, by row
+    pub cells: Vec<Vec<Cell>>,
+    /// Rows that have scrolled off the top of the screen, oldest first
+    pub history: VecDeque<Vec<Cell>>,
+    /// The number of rows of history shown, the screen is live if 0
+    pub offset: usize,
     pub draw: bool,
     pub redraw: bool,
-    pub command: String,
+    /// This is the active virtual terminal, so it is shown on the screen
+    pub visible: bool,
+    /// Output is copied to the serial port
+    pub serial: bool,
+    /// Input ready to be read, lines in canonical mode, an empty string is end of file
     pub commands: WaitQueue<String>,
     pub escape: bool,
     pub escape_sequence: bool,
     pub sequence: Vec<String>,
-    pub raw_mode: bool,
+    /// The line discipline
+    pub tty: LineDiscipline,
 }
 
 impl Console {
     pub fn new() -> Console {
-        Console {
+        let mut console = Console {
             display: Display::root(),
             point_x: 0,
             point_y: 0,
-            foreground: WHITE,
-            background: BLACK,
+            foreground: PALETTE[DEFAULT_FOREGROUND],
+            background: PALETTE[DEFAULT_BACKGROUND],
+            fg: DEFAULT_FOREGROUND,
+            bg: DEFAULT_BACKGROUND,
+            bold: false,
+            reverse: false,
+            saved: (0, 0),
+            region: None,
+            cursor: false,
+            cells: Vec::new(),
+            history: VecDeque::new(),
+            offset: 0,
             draw: false,
             redraw: true,
-            command: String::new(),
+            visible: false,
+            serial: false,
             commands: WaitQueue::new(),
             escape: false,
             escape_sequence: false,
             sequence: Vec::new(),
-            raw_mode: false,
+            tty: LineDiscipline::new(),
+        };
+
+        let (cols, rows) = console.size();
+        let blank = console.blank();
+        console.cells = vec![vec![blank; cols]; rows];
+        console.tty.winsize = Winsize {
+            ws_row: rows as u16,
+            ws_col: cols as u16,
+            ws_xpixel: (cols * 8) as u16,
+            ws_ypixel: (rows * 16) as u16,
+        };
+
+        console
+    }
+
+    /// The size of the display in character cells
+    fn size(&self) -> (usize, usize) {
+        if let Some(ref display) = self.display {
+            (cmp::max(1, display.width / 8), cmp::max(1, display.height / 16))
+        } else {
+            (80, 25)
         }
     }
 
-    pub fn code(&mut self, c: char) {
-        if self.escape_sequence {
-            match c {
-                '0' ... '9' => {
-                    // Add a number to the sequence list
-                    if let Some(mut value) = self.sequence.last_mut() {
-                        value.push(c);
-                    }
-                },
-                ';' => {
-                    // Split sequence into list
-                    self.sequence.push(String::new());
-                },
-                'm' => {
-                    // Display attributes
-                    for value in self.sequence.iter() {
-                        match value.as_str() {
-                            "0" => {
-                                self.foreground = WHITE;
-                                self.background = BLACK;
-                            },
-                            "30" => self.foreground = BLACK,
-                            "31" => self.foreground = RED,
-                            "32" => self.foreground = GREEN,
-                            "33" => self.foreground = YELLOW,
-                            "34" => self.foreground = BLUE,
-                            "35" => self.foreground = MAGENTA,
-                            "36" => self.foreground = CYAN,
-                            "37" => self.foreground = WHITE,
-                            "40" => self.background = BLACK,
-                            "41" => self.background = RED,
-                            "42" => self.background = GREEN,
-                            "43" => self.background = YELLOW,
-                            "44" => self.background = BLUE,
-                            "45" => self.background = MAGENTA,
-                            "46" => self.background = CYAN,
-                            "47" => self.background = WHITE,
-                            _ => {},
-                        }
-                    }
-                    self.escape_sequence = false;
-                },
-                'H' | 'f' => {
-                    if let Some(ref mut display) = self.display {
-                        display.rect(self.point_x, self.point_y, 8, 16, self.background);
-                    }
+    /// The column and row of the cursor
+    fn position(&self) -> (usize, usize) {
+        let (cols, rows) = self.size();
+        (cmp::min(self.point_x / 8, cols - 1), cmp::min(self.point_y / 16, rows - 1))
+    }
+
+    /// Move the cursor, clamped to the display
+    fn goto(&mut self, col: usize, row: usize) {
+        let (cols, rows) = self.size();
+        self.point_x = cmp::min(col, cols - 1) * 8;
+        self.point_y = cmp::min(row, rows - 1) * 16;
+    }
+
+    /// The top and bottom rows of the scrolling region
+    fn margins(&self) -> (usize, usize) {
+        let (_, rows) = self.size();
+        self.region.unwrap_or((0, rows - 1))
+    }
 
-                    let row = self.sequence.get(0).map_or("", |p| &p).parse::<usize>().unwrap_or(0);
-                    self.point_y = row * 16;
+    /// An empty cell in the current colors
+    fn blank(&self) -> Cell {
+        Cell {
+            c: ' ',
+            foreground: self.foreground,
+            background: self.background,
+        }
+    }
 
-                    let col = self.sequence.get(1).map_or("", |p| &p).parse::<usize>().unwrap_or(0);
-                    self.point_x = col * 8;
+    /// Scroll the rows from `top` to `bottom` up by `rows`, or down if negative
+    ///
+    /// With `save`, the rows scrolled off the top are added to the history.
+    fn scroll_rows(&mut self, top: usize, bottom: usize, rows: isize, save: bool) {
+        if let Some(ref display) = self.display {
+            display.scroll_area(top * 16, (bottom + 1 - top) * 16, rows * 16, self.background);
+        }
 
-                    if let Some(ref mut display) = self.display {
-                        display.rect(self.point_x, self.point_y, 8, 16, self.foreground);
+        let (cols, screen_rows) = self.size();
+        let blank = self.blank();
+        for _ in 0..cmp::min(rows.abs() as usize, bottom + 1 - top) {
+            if rows > 0 {
+                let row = self.cells.remove(top);
+                self.cells.insert(bottom, vec![blank; cols]);
+                if save {
+                    while self.history.len() >= SCROLLBACK_SCREENS * screen_rows {
+                        self.history.pop_front();
                     }
-                    self.redraw = true;
+                    self.history.push_back(row);
+                }
+            } else {
+                self.cells.remove(bottom);
+                self.cells.insert(top, vec![blank; cols]);
+            }
+        }
 
-                    self.escape_sequence = false;
+        self.redraw = true;
+    }
+
+    /// Move down a row, scrolling the region if the cursor is on its bottom row
+    fn line_feed(&mut self) {
+        let (top, bottom) = self.margins();
+        let (col, row) = self.position();
+        if row == bottom {
+            // Only rows leaving the whole screen are kept, not those of a scrolling region
+            let save = top == 0 && self.region.is_none();
+            self.scroll_rows(top, bottom, 1, save);
+        } else {
+            self.goto(col, row + 1);
+        }
+    }
+
+    /// Move up a row, scrolling the region if the cursor is on its top row
+    fn reverse_line_feed(&mut self) {
+        let (top, bottom) = self.margins();
+        let (col, row) = self.position();
+        if row == top {
+            self.scroll_rows(top, bottom, -1, false);
+        } else if row > 0 {
+            self.goto(col, row - 1);
+        }
+    }
+
+    /// Erase the cells from `col` to the end of the row, up to `count` cells
+    fn erase(&mut self, col: usize, row: usize, count: usize) {
+        if let Some(ref display) = self.display {
+            display.rect_exact(col * 8, row * 16, count * 8, 16, self.background);
+        }
+
+        let blank = self.blank();
+        if let Some(cells) = self.cells.get_mut(row) {
+            for cell in cells.iter_mut().skip(col).take(count) {
+                *cell = blank;
+            }
+        }
+
+        self.redraw = true;
+    }
+
+    /// Draw the screen again from the cells, with `offset` rows of history above it
+    fn render(&mut self) {
+        if let Some(ref display) = self.display {
+            let rows = self.cells.len();
+            let history = self.history.len();
+            for y in 0..rows {
+                let row = if y < self.offset {
+                    &self.history[history - self.offset + y]
+                } else {
+                    &self.cells[y - self.offset]
+                };
+
+                for (x, cell) in row.iter().enumerate() {
+                    display.rect_exact(x * 8, y * 16, 8, 16, cell.background);
+                    display.char(x * 8, y * 16, cell.c, cell.foreground);
+                }
+            }
+        }
+
+        // Drawing the cells removed the cursor, which is only shown on the live screen
+        self.cursor = false;
+        if self.offset == 0 {
+            self.show_cursor();
+        }
+        self.redraw = true;
+    }
+
+    /// Show the screen `offset` rows back in the history, or the live screen if 0
+    pub fn view(&mut self, offset: usize) {
+        let offset = cmp::min(offset, cmp::min(self.history.len(), self.cells.len()));
+        if offset != self.offset {
+            self.offset = offset;
+            self.render();
+        }
+    }
+
+    /// Scroll the view back by `rows`, or forward if negative, as with Shift+PageUp and
+    /// Shift+PageDown
+    pub fn scrollback(&mut self, rows: isize) {
+        let offset = if rows < 0 {
+            self.offset.saturating_sub(rows.abs() as usize)
+        } else {
+            self.offset + rows as usize
+        };
+        self.view(offset);
+        self.present();
+    }
+
+    /// Invert the cell under the cursor
+    fn toggle_cursor(&mut self) {
+        if let Some(ref display) = self.display {
+            if self.point_x + 8 <= display.width && self.point_y + 16 <= display.height {
+                display.invert(self.point_x, self.point_y, 8, 16);
+            }
+        }
+        self.redraw = true;
+    }
+
+    fn hide_cursor(&mut self) {
+        if self.cursor {
+            self.toggle_cursor();
+            self.cursor = false;
+        }
+    }
+
+    fn show_cursor(&mut self) {
+        if ! self.cursor {
+            self.toggle_cursor();
+            self.cursor = true;
+        }
+    }
+
+    /// A numeric parameter of the current control sequence, `default` if it is missing or empty
+    fn param(&self, i: usize, default: usize) -> usize {
+        self.sequence.get(i).and_then(|p| p.parse::<usize>().ok()).unwrap_or(default)
+    }
+
+    /// A count parameter, where zero means one
+    fn count(&self, i: usize) -> usize {
+        cmp::max(1, self.param(i, 1))
+    }
+
+    /// Update the colors from the palette indexes and attributes
+    fn update_colors(&mut self) {
+        let fg = if self.bold && self.fg < 8 {
+            self.fg + 8
+        } else {
+            self.fg
+        };
+
+        if self.reverse {
+            self.foreground = PALETTE[self.bg];
+            self.background = PALETTE[fg];
+        } else {
+            self.foreground = PALETTE[fg];
+            self.background = PALETTE[self.bg];
+        }
+    }
+
+    /// Select graphic rendition
+    fn sgr(&mut self) {
+        for i in 0..self.sequence.len() {
+            match self.param(i, 0) {
+                0 => {
+                    self.fg = DEFAULT_FOREGROUND;
+                    self.bg = DEFAULT_BACKGROUND;
+                    self.bold = false;
+                    self.reverse = false;
                 },
+                1 => self.bold = true,
+                7 => self.reverse = true,
+                22 => self.bold = false,
+                27 => self.reverse = false,
+                n @ 30 ... 37 => self.fg = n - 30,
+                39 => self.fg = DEFAULT_FOREGROUND,
+                n @ 40 ... 47 => self.bg = n - 40,
+                49 => self.bg = DEFAULT_BACKGROUND,
+                n @ 90 ... 97 => self.fg = n - 90 + 8,
+                n @ 100 ... 107 => self.bg = n - 100 + 8,
+                _ => {},
+            }
+        }
+        self.update_colors();
+    }
+
+    /// Run the final character of a control sequence
+    fn control(&mut self, c: char) {
+        let (cols, rows) = self.size();
+        let (col, row) = self.position();
+
+        match c {
+            // Cursor movement
+            'A' => {
+                let n = self.count(0);
+                self.goto(col, row.saturating_sub(n));
+            },
+            'B' => {
+                let n = self.count(0);
+                self.goto(col, row + n);
+            },
+            'C' => {
+                let n = self.count(0);
+                self.goto(col + n, row);
+            },
+            'D' => {
+                let n = self.count(0);
+                self.goto(col.saturating_sub(n), row);
+            },
+            'E' => {
+                let n = self.count(0);
+                self.goto(0, row + n);
+            },
+            'F' => {
+                let n = self.count(0);
+                self.goto(0, row.saturating_sub(n));
+            },
+            'G' => {
+                let n = self.count(0);
+                self.goto(n - 1, row);
+            },
+            'd' => {
+                let n = self.count(0);
+                self.goto(col, n - 1);
+            },
+            'H' | 'f' => {
+                let row = self.count(0);
+                let col = self.count(1);
+                self.goto(col - 1, row - 1);
+            },
+            // Erase in display
+            'J' => {
+                match self.param(0, 0) {
+                    0 => {
+                        self.erase(col, row, cols - col);
+                        for row in row + 1..rows {
+                            self.erase(0, row, cols);
+                        }
+                    },
+                    1 => {
+                        for row in 0..row {
+                            self.erase(0, row, cols);
+                        }
+                        self.erase(0, row, col + 1);
+                    },
+                    2 | 3 => for row in 0..rows {
+                        self.erase(0, row, cols);
+                    },
+                    _ => {},
+                }
+            },
+            // Erase in line
+            'K' => {
+                match self.param(0, 0) {
+                    0 => self.erase(col, row, cols - col),
+                    1 => self.erase(0, row, col + 1),
+                    2 => self.erase(0, row, cols),
+                    _ => {},
+                }
+            },
+            // Insert and delete lines inside the scrolling region
+            'L' | 'M' => {
+                let (top, bottom) = self.margins();
+                if row >= top && row <= bottom {
+                    let n = self.count(0) as isize;
+                    self.scroll_rows(row, bottom, if c == 'L' { -n } else { n }, false);
+                    self.goto(0, row);
+                }
+            },
+            // Scroll the region up or down
+            'S' | 'T' => {
+                let (top, bottom) = self.margins();
+                let n = self.count(0) as isize;
+                self.scroll_rows(top, bottom, if c == 'T' { -n } else { n }, false);
+            },
+            'm' => self.sgr(),
 /*
 @MANSTART{terminal-raw-mode}
 INTRODUCTION
@@ -125,6 +470,9 @@ INTRODUCTION
 
 ENTERING AND EXITING RAW MODE
     Entering raw mode is done using CSI-r (^[r). Unsetting raw mode is done by CSI-R (^[R).
+    These clear and set ICANON and ECHO, which can also be changed with the TCSETS ioctl.
+    CSI-r with parameters, such as ^[[1;20r, sets the scrolling region as on a VT100 and does not
+    change the mode.
 
 RAW MODE
     Raw mode means that the stdin must be handled solely by the program itself. It will not automatically be printed nor will it be modified in any way (modulo escape codes).
@@ -135,10 +483,96 @@ RAW MODE
         - stdin is not buffered, meaning that the stream of bytes goes directly to the program, without the user having to press enter.
 @MANEND
 */
-                'r' => self.raw_mode = true,
-                'R' => self.raw_mode = false,
-                _ => self.escape_sequence = false,
+            'r' => {
+                if self.sequence.iter().all(|p| p.is_empty()) {
+                    let mut termios = self.tty.termios;
+                    termios.c_lflag &= !(ICANON | ECHO);
+                    self.tty.set_termios(termios, &self.commands);
+                } else {
+                    // Set the scrolling region, which also homes the cursor
+                    let top = self.count(0) - 1;
+                    let bottom = cmp::min(self.param(1, rows), rows) - 1;
+                    if top < bottom {
+                        self.region = if top == 0 && bottom == rows - 1 {
+                            None
+                        } else {
+                            Some((top, bottom))
+                        };
+                    }
+                    self.goto(0, 0);
+                }
+            },
+            'R' => {
+                let mut termios = self.tty.termios;
+                termios.c_lflag |= ICANON | ECHO;
+                self.tty.set_termios(termios, &self.commands);
+            },
+            // Show the screen scrolled back by a number of rows, 0 returns to the live screen
+            'y' => {
+                let offset = self.param(0, 0);
+                self.view(offset);
+            },
+            's' => self.saved = (col, row),
+            'u' => {
+                let (col, row) = self.saved;
+                self.goto(col, row);
+            },
+            _ => {},
+        }
+    }
 
+    /// Reset the terminal state and clear the display
+    fn reset(&mut self) {
+        self.point_x = 0;
+        self.point_y = 0;
+        self.fg = DEFAULT_FOREGROUND;
+        self.bg = DEFAULT_BACKGROUND;
+        self.bold = false;
+        self.reverse = false;
+        self.update_colors();
+        self.saved = (0, 0);
+        self.region = None;
+        if let Some(ref mut display) = self.display {
+            display.set(self.background);
+        }
+        let blank = self.blank();
+        for row in self.cells.iter_mut() {
+            for cell in row.iter_mut() {
+                *cell = blank;
+            }
+        }
+        self.offset = 0;
+        // Clearing the display removed the cursor
+        self.cursor = false;
+        self.redraw = true;
+    }
+
+    pub fn code(&mut self, c: char) {
+        if self.escape_sequence {
+            match c {
+                '0' ... '9' => {
+                    // Add a number to the sequence list
+                    if let Some(mut value) = self.sequence.last_mut() {
+                        value.push(c);
+                    }
+                },
+                ';' => {
+                    // Split sequence into list
+                    self.sequence.push(String::new());
+                },
+                // Private modes, such as cursor visibility, are not supported
+                '?' | '>' | '=' => {},
+                'y' => {
+                    self.control(c);
+                    self.escape_sequence = false;
+                },
+                _ => {
+                    self.view(0);
+                    self.hide_cursor();
+                    self.control(c);
+                    self.show_cursor();
+                    self.escape_sequence = false;
+                },
             }
 
             if !self.escape_sequence {
@@ -146,70 +580,102 @@ RAW MODE
                 self.escape = false;
             }
         } else {
+            if c != '[' {
+                self.view(0);
+            }
+            self.hide_cursor();
             match c {
                 '[' => {
                     // Control sequence initiator
-
                     self.escape_sequence = true;
                     self.sequence.push(String::new());
                 },
-                'c' => {
-                    // Reset
+                'c' => self.reset(),
+                '7' => self.saved = self.position(),
+                '8' => {
+                    let (col, row) = self.saved;
+                    self.goto(col, row);
+                },
+                // Index, next line and reverse index
+                'D' => self.line_feed(),
+                'E' => {
                     self.point_x = 0;
-                    self.point_y = 0;
-                    self.foreground = WHITE;
-                    self.background = BLACK;
-                    if let Some(ref mut display) = self.display {
-                        display.set(self.background);
-                    }
-                    self.redraw = true;
+                    self.line_feed();
+                },
+                'M' => self.reverse_line_feed(),
+                _ => {},
+            }
+            self.show_cursor();
 
-                    self.escape = false;
-                }
-                _ => self.escape = false,
+            if !self.escape_sequence {
+                self.escape = false;
             }
         }
     }
 
     pub fn character(&mut self, c: char) {
-        if let Some(ref mut display) = self.display {
-            display.rect(self.point_x, self.point_y, 8, 16, self.background);
+        let raw_mode = self.tty.termios.c_lflag & ICANON != ICANON;
+        let width = match self.display {
+            Some(ref display) => display.width,
+            None => return,
+        };
+
+        // Any output returns to the live screen
+        if c != '\0' && c != '\x1B' {
+            self.view(0);
+        }
+        self.hide_cursor();
 
-            match c {
-                '\0' => {},
-                '\x1B' => self.escape = true,
-                '\n' if self.raw_mode => self.point_x = 0,
-                '\n' => {
+        match c {
+            '\0' => {},
+            '\x1B' => self.escape = true,
+            '\n' if raw_mode => self.point_x = 0,
+            '\n' => {
+                self.point_x = 0;
+                self.line_feed();
+            },
+            '\t' => self.point_x = cmp::min(((self.point_x / 64) + 1) * 64, width - 8),
+            '\r' => self.point_x = 0,
+            '\x07' => {},
+            '\x08' if raw_mode => {},
+            '\x08' => {
+                if self.point_x >= 8 {
+                    self.point_x -= 8;
+                }
+                let (col, row) = self.position();
+                self.erase(col, row, 1);
+            },
+            _ => {
+                // The wrap is deferred until the next character, so that the last column can be
+                // written without scrolling
+                if self.point_x + 8 > width {
                     self.point_x = 0;
-                    self.point_y += 16;
-                },
-                '\t' => self.point_x = ((self.point_x / 64) + 1) * 64,
-                '\r' => self.point_x = 0,
-                '\x08' if self.raw_mode => {},
-                '\x08' => {
-                    if self.point_x >= 8 {
-                        self.point_x -= 8;
-                    }
-                    display.rect(self.point_x, self.point_y, 8, 16, self.background);
-                },
-                _ => {
+                    self.line_feed();
+                }
+
+                if let Some(ref display) = self.display {
+                    display.rect_exact(self.point_x, self.point_y, 8, 16, self.background);
                     display.char(self.point_x, self.point_y, c, self.foreground);
-                    self.point_x += 8;
                 }
-            }
 
-            if self.point_x >= display.width {
-                self.point_x = 0;
-                self.point_y += 16;
-            }
+                let (col, row) = self.position();
+                let cell = Cell {
+                    c: c,
+                    foreground: self.foreground,
+                    background: self.background,
+                };
+                if let Some(cells) = self.cells.get_mut(row) {
+                    if let Some(old) = cells.get_mut(col) {
+                        *old = cell;
+                    }
+                }
 
-            while self.point_y + 16 > display.height {
-                display.scroll(16);
-                self.point_y -= 16;
+                self.point_x += 8;
             }
-            display.rect(self.point_x, self.point_y, 8, 16, self.foreground);
-            self.redraw = true;
         }
+
+        self.show_cursor();
+        self.redraw = true;
     }
 
     pub fn event(&mut self, event: Event) {
@@ -217,24 +683,13 @@ RAW MODE
             EventOption::Key(key_event) => {
                 if key_event.pressed {
                     match key_event.scancode {
-                        event::K_BKSP => if ! self.command.is_empty() {
-                            self.write(&[8]);
-                            self.command.pop();
+                        event::K_BKSP => {
+                            let erase = self.tty.termios.c_cc[VERASE] as char;
+                            self.input(erase);
                         },
                         _ => match key_event.character {
                             '\0' => (),
-                            c => {
-                                self.command.push(c);
-                                if ! self.raw_mode {
-                                    self.write(&[c as u8]);
-                                }
-
-                                if c == '\n' {
-                                    let mut command = String::new();
-                                    mem::swap(&mut self.command, &mut command);
-                                    self.commands.send(command);
-                                }
-                            }
+                            c => self.input(c),
                         },
                     }
                 }
@@ -243,10 +698,18 @@ RAW MODE
         }
     }
 
-    pub fn write(&mut self, bytes: &[u8]) {
-        let serial_status = Pio::<u8>::new(0x3F8 + 5);
-        let mut serial_data = Pio::<u8>::new(0x3F8);
+    /// Run a character through the line discipline, echoing to the console
+    pub fn input(&mut self, c: char) {
+        let echo = self.tty.input(c, &self.commands);
+        self.write(&echo);
+    }
+
+    /// Wait for input, failing with `EINTR` if the current context has a signal pending
+    pub fn receive(&self) -> Result<String> {
+        tty::receive(&self.commands)
+    }
 
+    pub fn write(&mut self, bytes: &[u8]) {
         for byte in bytes.iter() {
             let c = *byte as char;
 
@@ -256,19 +719,17 @@ RAW MODE
                 self.character(c);
             }
 
-            while !serial_status.readf(0x20) {}
-            serial_data.write(*byte);
-
-            if *byte == 8 {
-                while !serial_status.readf(0x20) {}
-                serial_data.write(0x20);
-
-                while !serial_status.readf(0x20) {}
-                serial_data.write(8);
+            if self.serial {
+                serial::write(*byte);
             }
         }
 
-        if self.draw && self.redraw {
+        self.present();
+    }
+
+    /// Copy the console to the screen, if it is visible and changed
+    fn present(&mut self) {
+        if self.visible && self.draw && self.redraw {
             self.redraw = false;
             if let Some(ref mut display) = self.display {
                 display.flip();
@@ -0,0 +1,70 @@
+use collections::BTreeMap;
+
+use system::error::Error;
+use system::syscall::{SYS_EXECVE, SYS_EXIT, SYS_KEXEC};
+
+/// Syscalls refused while fuzzing, as they would end the fuzzer or replace the kernel
+pub const FUZZ_REFUSED: [usize; 3] = [SYS_EXIT, SYS_EXECVE, SYS_KEXEC];
+
+/// Syscall fuzzing state
+///
+/// Contexts holding `fuzz:` open have their syscalls tallied by number and outcome, the outcome
+/// being 0 for success or the error number. A new pair means the fuzzer reached a new branch of a
+/// handler.
+pub struct Fuzz {
+    /// The number of times each fuzzing context has `fuzz:` open, by PID
+    pub pids: BTreeMap<usize, usize>,
+    /// Calls by syscall number and outcome
+    pub coverage: BTreeMap<(usize, usize), u64>,
+    /// Calls refused with `EPERM`, see `FUZZ_REFUSED`
+    pub refused: u64,
+}
+
+impl Fuzz {
+    pub fn new() -> Fuzz {
+        Fuzz {
+            pids: BTreeMap::new(),
+            coverage: BTreeMap::new(),
+            refused: 0,
+        }
+    }
+
+    /// Is the context `pid` fuzzing
+    pub fn fuzzing(&self, pid: usize) -> bool {
+        self.pids.contains_key(&pid)
+    }
+
+    /// Start fuzzing from the context `pid`
+    pub fn open(&mut self, pid: usize) {
+        *self.pids.entry(pid).or_insert(0) += 1;
+    }
+
+    /// Stop fuzzing from the context `pid`, once it has closed every `fuzz:`
+    pub fn close(&mut self, pid: usize) {
+        let remove = match self.pids.get_mut(&pid) {
+            Some(count) => {
+                *count -= 1;
+                *count == 0
+            },
+            None => false,
+        };
+        if remove {
+            self.pids.remove(&pid);
+        }
+    }
+
+    /// Record the muxed `result` of syscall `number`
+    pub fn record(&mut self, number: usize, result: usize) {
+        let outcome = match Error::demux(result) {
+            Ok(_) => 0,
+            Err(err) => err.errno as usize,
+        };
+        *self.coverage.entry((number, outcome)).or_insert(0) += 1;
+    }
+
+    /// Forget the tallies
+    pub fn reset(&mut self) {
+        self.coverage.clear();
+        self.refused = 0;
+    }
+}
@@ -0,0 +1,86 @@
+use alloc::boxed::{Box, FnBox};
+
+use arch::interrupt::IRQ_COUNT;
+use arch::intex::Intex;
+use arch::irq_pool::IrqContext;
+
+use collections::Vec;
+
+use fs::KScheme;
+
+use sync::WaitQueue;
+
+/// The deferred work reserved for, so that `defer` does not grow the queue in an IRQ handler
+const DEFER_CAPACITY: usize = 64;
+
+/// Dispatch of IRQs to the drivers that registered for them
+///
+/// Several drivers may share an IRQ, as PCI devices do, and each one must check whether its
+/// device raised it. Handlers run with interrupts disabled, so work that can wait is deferred to
+/// the `kirqd` kernel thread with `defer`. Handlers may only allocate from the pool in
+/// `arch::irq_pool`, which is enough for a few deferred closures at a time.
+pub struct IrqManager {
+    /// The drivers registered for each IRQ
+    handlers: Intex<Vec<Vec<*mut KScheme>>>,
+    /// Deferred work, run in order by `kirqd`
+    work: WaitQueue<Box<FnBox()>>,
+}
+
+impl IrqManager {
+    pub fn new() -> IrqManager {
+        IrqManager {
+            handlers: Intex::new((0..IRQ_COUNT).map(|_| Vec::new()).collect()),
+            work: WaitQueue::with_capacity(DEFER_CAPACITY),
+        }
+    }
+
+    /// Call `on_irq` of `scheme` when `irq` is raised
+    ///
+    /// The scheme must stay at the same address until it is unregistered, as the schemes boxed
+    /// in the environment do.
+    pub unsafe fn register(&self, irq: u8, scheme: *mut KScheme) {
+        if let Some(handlers) = self.handlers.lock().get_mut(irq as usize) {
+            handlers.push(scheme);
+        }
+    }
+
+    /// Remove `scheme` from every IRQ
+    pub fn unregister(&self, scheme: *mut KScheme) {
+        for handlers in self.handlers.lock().iter_mut() {
+            handlers.retain(|&handler| handler as *mut u8 != scheme as *mut u8);
+        }
+    }
+
+    /// Call the handlers of `irq` in interrupt context, returning how many there were
+    pub fn handle(&self, irq: u8) -> usize {
+        let _context = IrqContext::enter();
+
+        // The lock is not held while a handler runs, so that it can register or defer
+        let mut i = 0;
+        loop {
+            let handler = match self.handlers.lock().get(irq as usize).and_then(|handlers| handlers.get(i)) {
+                Some(&handler) => handler,
+                None => return i,
+            };
+
+            unsafe { (*handler).on_irq(irq) };
+            i += 1;
+        }
+    }
+
+    /// Run `work` later in the `kirqd` kernel thread, with interrupts enabled
+    ///
+    /// Returns false and drops `work` when `DEFER_CAPACITY` items are already waiting, as the
+    /// queue cannot grow in interrupt context. Handlers should defer once until the work has run.
+    pub fn defer(&self, work: Box<FnBox()>) -> bool {
+        self.work.try_send(work)
+    }
+
+    /// Run deferred work forever, the body of `kirqd`
+    pub fn run(&self) -> ! {
+        loop {
+            let work = self.work.receive();
+            work();
+        }
+    }
+}
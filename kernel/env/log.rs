@@ -0,0 +1,149 @@
+use collections::borrow::Cow;
+use collections::string::String;
+use collections::vec::Vec;
+use collections::vec_deque::VecDeque;
+
+use common::time::Duration;
+
+/// The number of message bytes kept before the oldest entries are overwritten
+pub const LOG_CAPACITY: usize = 64 * 1024;
+
+/// The severity of a kernel log entry
+#[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
+pub enum LogLevel {
+    /// The kernel cannot continue
+    Critical,
+    /// An operation failed
+    Error,
+    /// Something unexpected that was recovered from
+    Warning,
+    /// Normal output
+    Info,
+    /// Verbose output for debugging
+    Debug,
+}
+
+impl LogLevel {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            LogLevel::Critical => "CRIT",
+            LogLevel::Error => "ERR",
+            LogLevel::Warning => "WARN",
+            LogLevel::Info => "INFO",
+            LogLevel::Debug => "DEBUG",
+        }
+    }
+}
+
+/// A single line of kernel output
+pub struct LogEntry {
+    /// Monotonic time at which the line was started
+    pub time: Duration,
+    /// The severity of the line
+    pub level: LogLevel,
+    /// The bytes of the line, without the trailing newline. They are kept as written, as a
+    /// character may be split across writes
+    pub message: Vec<u8>,
+}
+
+impl LogEntry {
+    /// The line as text, with bytes that are not UTF-8 replaced
+    pub fn text(&self) -> Cow<str> {
+        String::from_utf8_lossy(&self.message)
+    }
+}
+
+/// A fixed-size ring buffer of kernel output, split into timestamped lines
+pub struct KernelLog {
+    pub entries: VecDeque<LogEntry>,
+    /// The line currently being written
+    pub line: Option<LogEntry>,
+    /// The number of message bytes in `entries`
+    pub size: usize,
+    /// The number of entries that were overwritten
+    pub dropped: usize,
+}
+
+impl KernelLog {
+    pub fn new() -> KernelLog {
+        KernelLog {
+            entries: VecDeque::new(),
+            line: None,
+            size: 0,
+            dropped: 0,
+        }
+    }
+
+    /// Append output at `level`, starting a new entry after every newline, and after
+    /// `LOG_CAPACITY` bytes without one
+    pub fn write(&mut self, level: LogLevel, bytes: &[u8]) {
+        for &b in bytes.iter() {
+            if self.line.is_none() {
+                self.line = Some(LogEntry {
+                    time: Duration::monotonic(),
+                    level: level,
+                    message: Vec::new(),
+                });
+            }
+
+            let full = if b == b'\n' {
+                true
+            } else if let Some(ref mut entry) = self.line {
+                if level < entry.level {
+                    entry.level = level;
+                }
+                entry.message.push(b);
+                // A line that never ends is split, so that it cannot grow past the log
+                entry.message.len() >= LOG_CAPACITY
+            } else {
+                false
+            };
+
+            if full {
+                if let Some(entry) = self.line.take() {
+                    self.push(entry);
+                }
+            }
+        }
+    }
+
+    /// Append a complete entry, overwriting the oldest entries if the log is full
+    fn push(&mut self, entry: LogEntry) {
+        self.size += entry.message.len();
+        self.entries.push_back(entry);
+        while self.size > LOG_CAPACITY {
+            if let Some(old) = self.entries.pop_front() {
+                self.size -= old.message.len();
+                self.dropped += 1;
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Remove all entries
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.size = 0;
+        self.dropped = 0;
+    }
+
+    /// Format the log, one entry per line
+    pub fn to_string(&self) -> String {
+        let mut string = String::new();
+
+        if self.dropped > 0 {
+            string.push_str(&format!("{} entries dropped\n", self.dropped));
+        }
+
+        for entry in self.entries.iter().chain(self.line.iter()) {
+            string.push_str(&format!("[{:>5}.{:09}] {:<6}{}\n",
+                                     entry.time.secs,
+                                     entry.time.nanos,
+                                     entry.level.name(),
+                                     entry.text()));
+        }
+
+        string
+    }
+}
@@ -1,67 +1,197 @@
+use alloc::arc::Arc;
 use alloc::boxed::Box;
 
-use arch::intex::Intex;
+use arch::intex::{Intex, IntexRw};
 
+use collections::{BTreeMap, Vec};
 use collections::string::{String, ToString};
-use collections::vec::Vec;
+
+use core::str;
 
 use common::event::Event;
 use common::time::Duration;
+use common::trace::Tracer;
 
 use arch::context::ContextManager;
 
-use fs::{KScheme, Resource, Scheme, VecResource, Url};
+use drivers::kb_layouts::layouts::Layout;
+
+use fs::{GrantTable, KScheme, LockTable, Resource, Scheme, SchemeEntry, SchemeRegistry, VecResource, Url};
+
+use sync::{PollWaiters, WaitQueue};
 
-use sync::WaitQueue;
+use system::error::{Error, Result, EACCES, EDQUOT, EINVAL, ELOOP, ENOENT, ENOSYS, EPERM, EXDEV};
+use system::syscall::{O_CREAT, O_NOFOLLOW, PRIV_SCHEME, SYMLINK_MAX, Stat};
 
-use system::error::{Error, Result, ENOENT, EEXIST};
-use system::syscall::{O_CREAT, Stat};
+use self::audit::{AuditEvent, AuditKind, AuditLog};
+use self::clock::Clock;
+use self::fuzz::Fuzz;
+use self::irq::IrqManager;
+use self::log::KernelLog;
+use self::perf::Perf;
+use self::stats::Stats;
+use self::timer::TimerWheel;
+use self::vt::VirtualTerminals;
 
-use self::console::Console;
+/// The maximum number of userspace schemes a non-root user can register
+pub const UID_MAX_SCHEMES: usize = 64;
+/// The number of pending events reserved for, as input drivers queue them from IRQ handlers
+pub const EVENT_CAPACITY: usize = 256;
 
+/// Security audit log
+pub mod audit;
+/// Timekeeping
+pub mod clock;
 /// The Kernel Console
 pub mod console;
+/// Syscall fuzzing
+pub mod fuzz;
+/// IRQ handler registration
+pub mod irq;
+/// Kernel log
+pub mod log;
+/// Performance counters
+pub mod perf;
+/// Syscall statistics
+pub mod stats;
+/// The timer wheel of the interval timers and timer resources
+pub mod timer;
+/// Terminal line discipline
+pub mod tty;
+/// The time page mapped into user contexts
+pub mod vdso;
+/// Virtual terminals
+pub mod vt;
 
 /// The kernel environment
 pub struct Environment {
-    /// Contexts
-    pub contexts: Intex<ContextManager>,
+    /// Contexts, read by most syscalls and written by the scheduler
+    pub contexts: IntexRw<ContextManager>,
 
-    /// Clock realtime (default)
-    pub clock_realtime: Intex<Duration>,
-    /// Monotonic clock
-    pub clock_monotonic: Intex<Duration>,
+    /// Monotonic and realtime clocks
+    pub clock: Intex<Clock>,
+    /// Real interval timers and polled timer resources, by the tick they expire on
+    pub timers: Intex<TimerWheel>,
 
-    /// Default console
-    pub console: Intex<Console>,
-    /// Pending events
+    /// Virtual terminals
+    pub vts: Intex<VirtualTerminals>,
+    /// Kernel log
+    pub log: Intex<KernelLog>,
+    /// Pending events, sent from IRQ handlers with `try_send`
     pub events: WaitQueue<Event>,
+    /// Contexts blocked in `poll`
+    pub pollers: PollWaiters,
+    /// Keyboard layout
+    pub layout: Intex<Layout>,
     /// Schemes
-    pub schemes: Intex<Vec<Box<KScheme>>>,
+    pub schemes: SchemeRegistry,
+    /// Number of userspace schemes registered by each user ID
+    pub scheme_counts: Intex<BTreeMap<usize, usize>>,
+    /// Caller memory mapped into userspace schemes
+    pub grants: Intex<GrantTable>,
+    /// Advisory locks taken with `flock`
+    pub locks: LockTable,
 
-    /// Interrupt stats
-    pub interrupts: Intex<[u64; 256]>,
+    /// Drivers registered for each IRQ
+    pub irqs: IrqManager,
+    /// Syscall stats, interrupts and context switches are counted by each processor
+    pub stats: Intex<Stats>,
+
+    /// Security audit log
+    pub audit: Intex<AuditLog>,
+    /// Tracepoint buffers
+    pub trace: Intex<Tracer>,
+    /// Performance counters
+    pub perf: Intex<Perf>,
+    /// Syscall fuzzing
+    pub fuzz: Intex<Fuzz>,
 }
 
 impl Environment {
     pub fn new() -> Box<Environment> {
         box Environment {
-            contexts: Intex::new(ContextManager::new()),
+            contexts: IntexRw::new(ContextManager::new()),
+
+            clock: Intex::new(Clock::new()),
+            timers: Intex::new(TimerWheel::new()),
+
+            vts: Intex::new(VirtualTerminals::new()),
+            log: Intex::new(KernelLog::new()),
+            events: WaitQueue::with_capacity(EVENT_CAPACITY),
+            pollers: PollWaiters::new(),
+            layout: Intex::new(Layout::English),
+            schemes: SchemeRegistry::new(),
+            scheme_counts: Intex::new(BTreeMap::new()),
+            grants: Intex::new(GrantTable::new()),
+            locks: LockTable::new(),
 
-            clock_realtime: Intex::new(Duration::new(0, 0)),
-            clock_monotonic: Intex::new(Duration::new(0, 0)),
+            irqs: IrqManager::new(),
+            stats: Intex::new(Stats::new()),
 
-            console: Intex::new(Console::new()),
-            events: WaitQueue::new(),
-            schemes: Intex::new(Vec::new()),
+            audit: Intex::new(AuditLog::new()),
+            trace: Intex::new(Tracer::new()),
+            perf: Intex::new(Perf::new()),
+            fuzz: Intex::new(Fuzz::new()),
+        }
+    }
+
+    /// Record a security-relevant event in the audit log
+    pub fn audit(&self, kind: AuditKind, message: String) {
+        let (pid, uid) = if let Ok(current) = self.contexts.read().current() {
+            (current.pid, current.uid)
+        } else {
+            (0, 0)
+        };
+
+        self.audit.lock().push(AuditEvent {
+            time: Duration::monotonic(),
+            pid: pid,
+            uid: uid,
+            kind: kind,
+            message: message,
+        });
+    }
 
-            interrupts: Intex::new([0; 256]),
+    /// Add a driver, which handles `irqs`
+    pub fn add_driver(&self, mut scheme: Box<KScheme>, irqs: &[u8]) {
+        let scheme_ptr: *mut KScheme = &mut *scheme;
+        for &irq in irqs.iter() {
+            unsafe { self.irqs.register(irq, scheme_ptr) };
         }
+        self.schemes.push(scheme);
     }
 
     pub fn on_irq(&self, irq: u8) {
-        for mut scheme in self.schemes.lock().iter_mut() {
-            scheme.on_irq(irq);
+        self.irqs.handle(irq);
+    }
+
+    /// Stop every scheme before a sleep state, in the reverse order they were added
+    pub fn suspend(&self) {
+        for entry in self.schemes.list().iter().rev() {
+            entry.call(|scheme| scheme.suspend());
+        }
+    }
+
+    /// Restart every scheme after waking, in the order they were added
+    pub fn resume(&self) {
+        for entry in self.schemes.list().iter() {
+            entry.call(|scheme| scheme.resume());
+        }
+    }
+
+    /// Check if the scheme called `name` is in the namespace of the current context, kernel code
+    /// that runs before the first context may use every scheme
+    fn in_namespace(&self, name: &str) -> bool {
+        self.contexts.read().current().map_or(true, |current| current.in_namespace(name))
+    }
+
+    /// Find the scheme called `name`, which is not found if it is outside the namespace of the
+    /// current context
+    fn scheme(&self, name: &str) -> Option<Arc<SchemeEntry>> {
+        if self.in_namespace(name) {
+            self.schemes.get(name)
+        } else {
+            None
         }
     }
 
@@ -73,9 +203,9 @@ impl Environment {
             if url_path.trim_matches('/').is_empty() {
                 let mut list = String::new();
 
-                for scheme in self.schemes.lock().iter() {
-                    let scheme_str = scheme.scheme();
-                    if !scheme_str.is_empty() {
+                for entry in self.schemes.list().iter() {
+                    let scheme_str = entry.name();
+                    if !scheme_str.is_empty() && self.in_namespace(scheme_str) {
                         if !list.is_empty() {
                             list = list + "\n" + scheme_str;
                         } else {
@@ -86,15 +216,25 @@ impl Environment {
 
                 Ok(box VecResource::new(":".to_string(), list.into_bytes()))
             } else if flags & O_CREAT == O_CREAT {
-                for scheme in self.schemes.lock().iter_mut() {
-                    if scheme.scheme() == url_path {
-                        return Err(Error::new(EEXIST));
-                    }
+                let (uid, privileged) = {
+                    let contexts = self.contexts.read();
+                    let current = try!(contexts.current());
+                    // A context in a namespace is sandboxed, and may not offer schemes to others
+                    (current.uid, current.has_priv(PRIV_SCHEME) && current.namespace.is_none())
+                };
+
+                if ! privileged {
+                    self.audit(AuditKind::OpenDenied, format!("registering {}: without privilege", url_path));
+                    return Err(Error::new(EPERM));
+                }
+
+                if uid != 0 && *self.scheme_counts.lock().get(&uid).unwrap_or(&0) >= UID_MAX_SCHEMES {
+                    return Err(Error::new(EDQUOT));
                 }
 
-                match Scheme::new(url_path) {
-                    Ok((scheme, server)) => {
-                        self.schemes.lock().push(scheme);
+                match Scheme::register(url_path) {
+                    Ok(server) => {
+                        self.audit(AuditKind::SchemeRegister, format!("registered {}:", url_path));
                         Ok(server)
                     },
                     Err(err) => Err(err)
@@ -103,23 +243,36 @@ impl Environment {
                 Err(Error::new(ENOENT))
             }
         } else {
-            for mut scheme in self.schemes.lock().iter_mut() {
-                if scheme.scheme() == url_scheme {
-                    return scheme.open(url, flags);
+            let follow = flags & O_NOFOLLOW != O_NOFOLLOW;
+            let path = try!(self.resolve(url, follow));
+            let url = try!(Url::from_str(&path));
+            if ! follow && self.is_link(url) {
+                return Err(Error::new(ELOOP));
+            }
+
+            let result = match self.scheme(url.scheme()) {
+                Some(entry) => entry.call(move |scheme| scheme.open(url, flags)),
+                None => Err(Error::new(ENOENT)),
+            };
+
+            if let Err(ref err) = result {
+                if err.errno == EACCES || err.errno == EPERM {
+                    self.audit(AuditKind::OpenDenied, format!("{}: {}", url.to_string(), err));
                 }
             }
-            Err(Error::new(ENOENT))
+
+            result
         }
     }
 
     /// Makes a directory
     pub fn mkdir(&self, url: Url, flags: usize) -> Result<()> {
+        let path = try!(self.resolve(url, false));
+        let url = try!(Url::from_str(&path));
         let url_scheme = url.scheme();
         if !url_scheme.is_empty() {
-            for mut scheme in self.schemes.lock().iter_mut() {
-                if scheme.scheme() == url_scheme {
-                    return scheme.mkdir(url, flags);
-                }
+            if let Some(entry) = self.scheme(url_scheme) {
+                return entry.call(move |scheme| scheme.mkdir(url, flags));
             }
         }
         Err(Error::new(ENOENT))
@@ -127,40 +280,226 @@ impl Environment {
 
     /// Remove a directory
     pub fn rmdir(&self, url: Url) -> Result<()> {
+        let path = try!(self.resolve(url, false));
+        let url = try!(Url::from_str(&path));
         let url_scheme = url.scheme();
         if !url_scheme.is_empty() {
-            for mut scheme in self.schemes.lock().iter_mut() {
-                if scheme.scheme() == url_scheme {
-                    return scheme.rmdir(url);
-                }
+            if let Some(entry) = self.scheme(url_scheme) {
+                return entry.call(move |scheme| scheme.rmdir(url));
+            }
+        }
+        Err(Error::new(ENOENT))
+    }
+
+    /// Rename a path, only within a scheme
+    pub fn rename(&self, old: Url, new: Url) -> Result<()> {
+        let old_path = try!(self.resolve(old, false));
+        let old = try!(Url::from_str(&old_path));
+        let new_path = try!(self.resolve(new, false));
+        let new = try!(Url::from_str(&new_path));
+        let url_scheme = old.scheme();
+        if !url_scheme.is_empty() {
+            if url_scheme != new.scheme() {
+                return Err(Error::new(EXDEV));
+            }
+            if let Some(entry) = self.scheme(url_scheme) {
+                return entry.call(move |scheme| scheme.rename(old, new));
             }
         }
         Err(Error::new(ENOENT))
     }
 
-    /// Stat a path
+    /// Stat a path, following a symbolic link at its end
     pub fn stat(&self, url: Url, stat: &mut Stat) -> Result<()> {
+        let path = try!(self.resolve(url, true));
+        let url = try!(Url::from_str(&path));
         let url_scheme = url.scheme();
         if !url_scheme.is_empty() {
-            for mut scheme in self.schemes.lock().iter_mut() {
-                if scheme.scheme() == url_scheme {
-                    return scheme.stat(url, stat);
-                }
+            if let Some(entry) = self.scheme(url_scheme) {
+                return entry.call(move |scheme| scheme.stat(url, stat));
             }
         }
         Err(Error::new(ENOENT))
     }
 
-    /// Unlink a resource
+    /// Unlink a resource, or a symbolic link rather than its target
     pub fn unlink(&self, url: Url) -> Result<()> {
+        let path = try!(self.resolve(url, false));
+        let url = try!(Url::from_str(&path));
         let url_scheme = url.scheme();
         if !url_scheme.is_empty() {
-            for mut scheme in self.schemes.lock().iter_mut() {
-                if scheme.scheme() == url_scheme {
-                    return scheme.unlink(url);
-                }
+            if let Some(entry) = self.scheme(url_scheme) {
+                return entry.call(move |scheme| scheme.unlink(url));
             }
         }
         Err(Error::new(ENOENT))
     }
+
+    /// Create `new` as another name of `old`, only within a scheme
+    pub fn link(&self, old: Url, new: Url) -> Result<()> {
+        let old_path = try!(self.resolve(old, false));
+        let old = try!(Url::from_str(&old_path));
+        let new_path = try!(self.resolve(new, false));
+        let new = try!(Url::from_str(&new_path));
+        let url_scheme = old.scheme();
+        if !url_scheme.is_empty() {
+            if url_scheme != new.scheme() {
+                return Err(Error::new(EXDEV));
+            }
+            if let Some(entry) = self.scheme(url_scheme) {
+                return entry.call(move |scheme| scheme.link(old, new));
+            }
+        }
+        Err(Error::new(ENOENT))
+    }
+
+    /// Create a symbolic link at `url` to `target`, which is kept as it is given
+    pub fn symlink(&self, target: &str, url: Url) -> Result<()> {
+        let path = try!(self.resolve(url, false));
+        let url = try!(Url::from_str(&path));
+        let url_scheme = url.scheme();
+        if !url_scheme.is_empty() {
+            if let Some(entry) = self.scheme(url_scheme) {
+                return entry.call(move |scheme| scheme.symlink(target, url));
+            }
+        }
+        Err(Error::new(ENOENT))
+    }
+
+    /// Read the target of the symbolic link at `url`
+    pub fn readlink(&self, url: Url, buf: &mut [u8]) -> Result<usize> {
+        let path = try!(self.resolve(url, false));
+        self.read_link(try!(Url::from_str(&path)), buf).map_err(|err| if err.errno == ENOSYS {
+            // Nothing on a scheme without symbolic links is one
+            Error::new(EINVAL)
+        } else {
+            err
+        })
+    }
+
+    /// Read the target of the symbolic link at `url`, without resolving the links before it
+    fn read_link(&self, url: Url, buf: &mut [u8]) -> Result<usize> {
+        let url_scheme = url.scheme();
+        if !url_scheme.is_empty() {
+            if let Some(entry) = self.scheme(url_scheme) {
+                return entry.call(move |scheme| scheme.readlink(url, buf));
+            }
+        }
+        Err(Error::new(ENOENT))
+    }
+
+    /// Check if `url` is a symbolic link
+    fn is_link(&self, url: Url) -> bool {
+        let mut buf = [0; 1];
+        self.read_link(url, &mut buf).is_ok()
+    }
+
+    /// Follow the symbolic links in the path of `url`, and the one at its end too if `follow` is
+    /// set, returning the path they lead to
+    ///
+    /// Following more than `SYMLINK_MAX` links fails with `ELOOP`, as they may form a cycle.
+    pub fn resolve(&self, url: Url, follow: bool) -> Result<String> {
+        let mut path = url.to_string();
+        let mut links = 0;
+        // The targets are read into one buffer, for every prefix and link
+        let mut buf = vec![0; 4096];
+        loop {
+            let next = match try!(self.follow_link(&path, follow, &mut buf)) {
+                Some(next) => next,
+                None => return Ok(path),
+            };
+
+            links += 1;
+            if links > SYMLINK_MAX {
+                return Err(Error::new(ELOOP));
+            }
+            path = next;
+        }
+    }
+
+    /// Replace the first symbolic link in `path` with its target, returning `None` if it has none
+    ///
+    /// Every prefix of the path that ends before a `/` is a directory that may be a link, and so
+    /// is the whole path if `follow` is set. A target is relative to the directory of its link,
+    /// unless it starts with `/`, the root of the scheme of the link, or names a scheme itself.
+    /// The path is not searched if its scheme has no symbolic links. Targets are read into `buf`.
+    fn follow_link(&self, path: &str, follow: bool, buf: &mut [u8]) -> Result<Option<String>> {
+        let start = match path.find(':') {
+            Some(i) => i + 1,
+            None => return Ok(None),
+        };
+        if path[..start - 1].is_empty() {
+            return Ok(None);
+        }
+
+        let bytes = path.as_bytes();
+        for end in start..path.len() + 1 {
+            if end < path.len() && bytes[end] != b'/' || end == path.len() && ! follow {
+                continue;
+            }
+            // An empty component, or a `.` or `..`, is not a link
+            let name = &path[path[start..end].rfind('/').map_or(start, |i| start + i + 1)..end];
+            if name.is_empty() || name == "." || name == ".." {
+                continue;
+            }
+
+            let count = match self.read_link(try!(Url::from_str(&path[..end])), buf) {
+                Ok(count) => count,
+                // Every prefix is on the same scheme
+                Err(ref err) if err.errno == ENOSYS => return Ok(None),
+                Err(_) => continue,
+            };
+            let target = try!(str::from_utf8(&buf[..count]).or(Err(Error::new(EINVAL))));
+            if target.is_empty() {
+                return Err(Error::new(ENOENT));
+            }
+
+            let mut next = if target.contains(':') {
+                target.to_string()
+            } else if target.starts_with('/') {
+                path[..start].to_string() + target
+            } else {
+                path[..end - name.len()].to_string() + target
+            };
+            next.push_str(&path[end..]);
+            return Ok(Some(normalize(&next)));
+        }
+
+        Ok(None)
+    }
+}
+
+/// Remove the `.` and `..` components of the reference of `path`, which a link target may have
+fn normalize(path: &str) -> String {
+    let start = path.find(':').map_or(0, |i| i + 1);
+
+    let mut parts: Vec<&str> = Vec::new();
+    for part in path[start..].split('/') {
+        match part {
+            "." => (),
+            // `..` stays at the root, and is kept at the start of a relative reference
+            ".." => match parts.last().map(|last| *last) {
+                Some("") => (),
+                Some("..") | None => parts.push(part),
+                Some(_) => {
+                    parts.pop();
+                },
+            },
+            _ => parts.push(part),
+        }
+    }
+
+    // The root alone is `/`
+    if parts.len() == 1 && parts[0].is_empty() {
+        parts.push("");
+    }
+
+    let mut normal = path[..start].to_string();
+    for (i, part) in parts.iter().enumerate() {
+        if i > 0 {
+            normal.push('/');
+        }
+        normal.push_str(part);
+    }
+    normal
 }
@@ -0,0 +1,128 @@
+use collections::BTreeMap;
+use collections::vec_deque::VecDeque;
+
+use common::random::rdtsc;
+
+use drivers::pmu::{PerfEvent, PerfSlot, Pmu};
+
+/// The number of samples kept for each counter before the oldest are overwritten
+pub const PERF_SAMPLES: usize = 1024;
+
+/// A counter opened through the perf scheme
+pub struct PerfCounter {
+    pub event: PerfEvent,
+    pub slot: PerfSlot,
+    /// Only count while this context is running, or system-wide if `None`
+    pub pid: Option<usize>,
+    /// Events counted in previous runs of `pid`
+    pub total: u64,
+    /// Raw counter value when `pid` was last switched to, if it is running
+    pub start: Option<u64>,
+    /// Time stamp counter and value, taken on each timer tick
+    pub samples: VecDeque<(u64, u64)>,
+}
+
+/// The number of events counted by `counter`
+fn count(pmu: &Pmu, counter: &PerfCounter) -> u64 {
+    let mut value = counter.total;
+    if let Some(start) = counter.start {
+        value += pmu.read(counter.slot).wrapping_sub(start) & pmu.mask(counter.slot);
+    }
+    value
+}
+
+/// Performance counter state
+pub struct Perf {
+    /// The PMU, if the CPU has architectural performance monitoring
+    pub pmu: Option<Pmu>,
+    pub counters: BTreeMap<usize, PerfCounter>,
+    pub next_id: usize,
+}
+
+impl Perf {
+    pub fn new() -> Perf {
+        Perf {
+            pmu: Pmu::new(),
+            counters: BTreeMap::new(),
+            next_id: 1,
+        }
+    }
+
+    /// Start counting `event`, returning the counter ID
+    pub fn open(&mut self, event: PerfEvent, pid: Option<usize>, current_pid: usize) -> Option<usize> {
+        let slot = match self.pmu {
+            Some(ref mut pmu) => match pmu.start(event) {
+                Some(slot) => slot,
+                None => return None,
+            },
+            None => return None,
+        };
+
+        let start = match pid {
+            Some(pid) if pid != current_pid => None,
+            _ => Some(0),
+        };
+
+        let id = self.next_id;
+        self.next_id += 1;
+        self.counters.insert(id, PerfCounter {
+            event: event,
+            slot: slot,
+            pid: pid,
+            total: 0,
+            start: start,
+            samples: VecDeque::new(),
+        });
+
+        Some(id)
+    }
+
+    /// Stop counting and free the hardware counter
+    pub fn close(&mut self, id: usize) {
+        if let Some(counter) = self.counters.remove(&id) {
+            if let Some(ref mut pmu) = self.pmu {
+                pmu.stop(counter.slot);
+            }
+        }
+    }
+
+    /// The number of events counted
+    pub fn value(&self, id: usize) -> u64 {
+        if let (Some(pmu), Some(counter)) = (self.pmu.as_ref(), self.counters.get(&id)) {
+            count(pmu, counter)
+        } else {
+            0
+        }
+    }
+
+    /// Stop counting for the previous context and start for the next
+    pub fn on_switch(&mut self, prev_pid: usize, next_pid: usize) {
+        if let Some(ref pmu) = self.pmu {
+            for counter in self.counters.values_mut() {
+                if let Some(pid) = counter.pid {
+                    if pid == prev_pid {
+                        if let Some(start) = counter.start.take() {
+                            counter.total += pmu.read(counter.slot).wrapping_sub(start) & pmu.mask(counter.slot);
+                        }
+                    }
+                    if pid == next_pid {
+                        counter.start = Some(pmu.read(counter.slot));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Sample every counter
+    pub fn on_tick(&mut self) {
+        if let Some(ref pmu) = self.pmu {
+            for counter in self.counters.values_mut() {
+                let sample = (rdtsc(), count(pmu, counter));
+                while counter.samples.len() >= PERF_SAMPLES {
+                    counter.samples.pop_front();
+                }
+                counter.samples.push_back(sample);
+            }
+        }
+    }
+}
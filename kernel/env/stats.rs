@@ -0,0 +1,103 @@
+use collections::string::String;
+use collections::vec::Vec;
+
+use syscall::strace;
+
+/// Above the largest syscall number, the numbers below it that `strace` knows are counted
+const SYSCALL_NUMBERS: usize = 2048;
+
+/// Latency buckets, bucket `i` counts calls that took less than `2^i` cycles
+pub const LATENCY_BUCKETS: usize = 48;
+
+/// A log-scale latency histogram
+#[derive(Copy, Clone)]
+pub struct Histogram {
+    pub count: u64,
+    pub total: u64,
+    pub buckets: [u64; LATENCY_BUCKETS],
+}
+
+impl Histogram {
+    pub fn new() -> Histogram {
+        Histogram {
+            count: 0,
+            total: 0,
+            buckets: [0; LATENCY_BUCKETS],
+        }
+    }
+
+    /// Count a call that took `cycles`
+    pub fn add(&mut self, cycles: u64) {
+        let bucket = (64 - cycles.leading_zeros()) as usize;
+        if bucket < LATENCY_BUCKETS {
+            self.buckets[bucket] += 1;
+        } else {
+            self.buckets[LATENCY_BUCKETS - 1] += 1;
+        }
+        self.count += 1;
+        self.total += cycles;
+    }
+
+    /// Format the non-empty buckets, one per line
+    pub fn to_string(&self) -> String {
+        let mut string = String::new();
+        for (i, count) in self.buckets.iter().enumerate() {
+            if *count > 0 {
+                string.push_str(&format!("  < 2^{:<4}{}\n", i, count));
+            }
+        }
+        string
+    }
+}
+
+/// The counters of one syscall
+pub struct SyscallStats {
+    pub number: usize,
+    pub count: u64,
+    /// Latency in cycles
+    pub latency: Histogram,
+}
+
+/// Syscall counters, interrupts and context switches are counted by each processor in
+/// `percpu::CpuStats`
+pub struct Stats {
+    /// Calls of the known syscalls, sorted by number. The table is made once, so that calling
+    /// unknown numbers does not grow it
+    pub syscalls: Vec<SyscallStats>,
+}
+
+impl Stats {
+    pub fn new() -> Stats {
+        Stats {
+            syscalls: (0..SYSCALL_NUMBERS).filter(|&number| strace::name(number).is_some()).map(|number| {
+                SyscallStats {
+                    number: number,
+                    count: 0,
+                    latency: Histogram::new(),
+                }
+            }).collect(),
+        }
+    }
+
+    /// The counters of a known syscall
+    fn get(&mut self, number: usize) -> Option<&mut SyscallStats> {
+        match self.syscalls.binary_search_by(|syscall| syscall.number.cmp(&number)) {
+            Ok(i) => Some(&mut self.syscalls[i]),
+            Err(_) => None,
+        }
+    }
+
+    /// Count a syscall, unknown numbers are not counted
+    pub fn syscall(&mut self, number: usize) {
+        if let Some(syscall) = self.get(number) {
+            syscall.count += 1;
+        }
+    }
+
+    /// Record the latency of a syscall, unknown numbers are not recorded
+    pub fn syscall_latency(&mut self, number: usize, cycles: u64) {
+        if let Some(syscall) = self.get(number) {
+            syscall.latency.add(cycles);
+        }
+    }
+}
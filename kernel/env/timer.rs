@@ -0,0 +1,117 @@
+use collections::{BTreeMap, Vec};
+
+use common::time::Duration;
+
+use super::clock::PIT_DURATION;
+
+/// The number of slots of the wheel, each one PIT tick wide
+const WHEEL_SLOTS: usize = 256;
+
+/// A timer in the wheel, for the real interval timer of a context or for a timer resource
+#[derive(Copy, Clone)]
+pub struct WheelTimer {
+    /// The context of the interval timer, or `None` to wake the polling contexts when a timer
+    /// resource expires
+    pub pid: Option<usize>,
+    /// The deadline it was added for, which for an interval timer no longer matches once the
+    /// interval timer is set again
+    pub deadline: Duration,
+    /// The tick of the wheel it expires on
+    tick: u64,
+}
+
+/// The timer wheel, which finds the real interval timers and timer resources that expire on a
+/// tick without looking at every context
+///
+/// A timer is kept in the slot of the tick it expires on, modulo the number of slots, so that
+/// each tick looks at one slot. Timers more than a turn of the wheel away stay in their slot until
+/// their tick comes. A context has at most one timer, which is replaced when its interval timer is
+/// set again and removed when it is cancelled or the context exits.
+pub struct TimerWheel {
+    slots: Vec<Vec<WheelTimer>>,
+    /// The tick of the timer of each context, to find its slot
+    pids: BTreeMap<usize, u64>,
+    /// The ticks the wheel has turned
+    tick: u64,
+}
+
+impl TimerWheel {
+    pub fn new() -> TimerWheel {
+        TimerWheel {
+            slots: (0..WHEEL_SLOTS).map(|_| Vec::new()).collect(),
+            pids: BTreeMap::new(),
+            tick: 0,
+        }
+    }
+
+    /// The tick that `deadline` on the monotonic clock, which is at `now`, falls on
+    fn tick_of(&self, deadline: Duration, now: Duration) -> u64 {
+        // Rounded up, so that the timer is not found before its deadline, and at least on the
+        // next tick
+        let tick_nanos = PIT_DURATION.as_nanos();
+        let ticks = if deadline > now {
+            ((deadline - now).as_nanos() + tick_nanos - 1) / tick_nanos
+        } else {
+            1
+        };
+        self.tick + ticks as u64
+    }
+
+    /// Add a timer for the context `pid` that expires at `deadline` on the monotonic clock, which
+    /// is at `now`, replacing its previous timer
+    pub fn add(&mut self, pid: usize, deadline: Duration, now: Duration) {
+        self.remove(pid);
+
+        let tick = self.tick_of(deadline, now);
+        self.slots[(tick % WHEEL_SLOTS as u64) as usize].push(WheelTimer {
+            pid: Some(pid),
+            deadline: deadline,
+            tick: tick,
+        });
+        self.pids.insert(pid, tick);
+    }
+
+    /// Remove the timer of the context `pid`, if it has one
+    pub fn remove(&mut self, pid: usize) {
+        if let Some(tick) = self.pids.remove(&pid) {
+            self.slots[(tick % WHEEL_SLOTS as u64) as usize].retain(|timer| timer.pid != Some(pid));
+        }
+    }
+
+    /// Wake the polling contexts at `deadline` on the monotonic clock, which is at `now`
+    ///
+    /// One timer is kept for each tick, however many resources poll for it.
+    pub fn add_pollers(&mut self, deadline: Duration, now: Duration) {
+        let tick = self.tick_of(deadline, now);
+        let slot = &mut self.slots[(tick % WHEEL_SLOTS as u64) as usize];
+        if ! slot.iter().any(|timer| timer.pid.is_none() && timer.tick == tick) {
+            slot.push(WheelTimer {
+                pid: None,
+                deadline: deadline,
+                tick: tick,
+            });
+        }
+    }
+
+    /// Turn the wheel by one tick, removing the timers that expire on it
+    pub fn tick(&mut self) -> Vec<WheelTimer> {
+        self.tick += 1;
+
+        let tick = self.tick;
+        let slot = &mut self.slots[(tick % WHEEL_SLOTS as u64) as usize];
+        let mut expired = Vec::new();
+        let mut i = 0;
+        while i < slot.len() {
+            if slot[i].tick <= tick {
+                let timer = slot.swap_remove(i);
+                if let Some(pid) = timer.pid {
+                    self.pids.remove(&pid);
+                }
+                expired.push(timer);
+            } else {
+                i += 1;
+            }
+        }
+        expired
+    }
+}
@@ -0,0 +1,159 @@
+use collections::String;
+use collections::Vec;
+
+use core::{mem, ptr};
+
+use sync::WaitQueue;
+
+use system::error::{Error, Result, EINTR, ENOTTY, EPERM};
+use system::syscall::{Termios, Winsize, ECHO, ICANON, ISIG, SIGINT, SIGTSTP, SIGWINCH, TCGETS, TCSETS,
+                      TIOCGPGRP, TIOCGWINSZ, TIOCSPGRP, TIOCSWINSZ, VEOF, VERASE, VINTR, VSUSP};
+
+/// Terminal line discipline, shared by the console and pseudo-terminals
+///
+/// Input is sent to a queue of commands, lines in canonical mode, where an empty string is end of
+/// file. Echoed characters are returned to the caller, which writes them to the terminal.
+pub struct LineDiscipline {
+    /// The line being edited in canonical mode
+    pub line: String,
+    /// The line discipline settings
+    pub termios: Termios,
+    /// The process group that receives signals from the keyboard, none if 0
+    pub pgrp: usize,
+    /// The size of the terminal
+    pub winsize: Winsize,
+}
+
+impl LineDiscipline {
+    pub fn new() -> LineDiscipline {
+        LineDiscipline {
+            line: String::new(),
+            termios: Termios::default(),
+            pgrp: 0,
+            winsize: Winsize::default(),
+        }
+    }
+
+    /// Send `signal` to the foreground process group
+    fn signal(&self, signal: usize) {
+        if self.pgrp > 0 {
+            ::env().contexts.write().signal_group(self.pgrp, signal);
+        }
+    }
+
+    /// Change the settings, passing on a partial line when leaving canonical mode
+    pub fn set_termios(&mut self, termios: Termios, commands: &WaitQueue<String>) {
+        if termios.c_lflag & ICANON != ICANON && ! self.line.is_empty() {
+            let line = mem::replace(&mut self.line, String::new());
+            commands.send(line);
+        }
+        self.termios = termios;
+    }
+
+    /// Run a character through the line discipline, returning the characters to echo
+    pub fn input(&mut self, c: char, commands: &WaitQueue<String>) -> Vec<u8> {
+        let lflag = self.termios.c_lflag;
+        let cc = self.termios.c_cc;
+        let echo = lflag & ECHO == ECHO;
+
+        let mut output = Vec::new();
+
+        if lflag & ISIG == ISIG && (c == cc[VINTR] as char || c == cc[VSUSP] as char) {
+            let (signal, name) = if c == cc[VINTR] as char {
+                (SIGINT, b"^C\n")
+            } else {
+                (SIGTSTP, b"^Z\n")
+            };
+
+            if echo {
+                output.extend_from_slice(name);
+            }
+            self.line.clear();
+
+            self.signal(signal);
+            // Wake readers, so that they notice the signal
+            unsafe { commands.condition.notify(); }
+        } else if lflag & ICANON == ICANON {
+            if c == cc[VERASE] as char || c == '\x08' {
+                if self.line.pop().is_some() && echo {
+                    output.push(8);
+                }
+            } else if c == cc[VEOF] as char {
+                // Pass on the line without a newline, so an empty line is end of file
+                let line = mem::replace(&mut self.line, String::new());
+                commands.send(line);
+            } else {
+                self.line.push(c);
+                if echo {
+                    output.push(c as u8);
+                }
+
+                if c == '\n' {
+                    let line = mem::replace(&mut self.line, String::new());
+                    commands.send(line);
+                }
+            }
+        } else {
+            if echo {
+                output.push(c as u8);
+            }
+
+            let mut string = String::new();
+            string.push(c);
+            commands.send(string);
+        }
+
+        output
+    }
+
+    /// Handle the terminal ioctls, `arg` is sized for the request by the caller. The foreground
+    /// group can only be set to a group the current context may use, see
+    /// `ContextManager::group_permitted`
+    pub fn ioctl(&mut self, request: usize, arg: &mut [u8], commands: &WaitQueue<String>) -> Result<usize> {
+        unsafe {
+            match request {
+                TCGETS => ptr::write(arg.as_mut_ptr() as *mut Termios, self.termios),
+                TCSETS => self.set_termios(ptr::read(arg.as_ptr() as *const Termios), commands),
+                TIOCGPGRP => ptr::write(arg.as_mut_ptr() as *mut usize, self.pgrp),
+                TIOCSPGRP => {
+                    let pgrp = ptr::read(arg.as_ptr() as *const usize);
+                    {
+                        let contexts = ::env().contexts.read();
+                        let current = try!(contexts.current());
+                        if ! contexts.group_permitted(pgrp, current.uid, current.euid) {
+                            return Err(Error::new(EPERM));
+                        }
+                    }
+                    self.pgrp = pgrp;
+                },
+                TIOCGWINSZ => ptr::write(arg.as_mut_ptr() as *mut Winsize, self.winsize),
+                TIOCSWINSZ => {
+                    self.winsize = ptr::read(arg.as_ptr() as *const Winsize);
+                    self.signal(SIGWINCH);
+                },
+                _ => return Err(Error::new(ENOTTY)),
+            }
+        }
+        Ok(0)
+    }
+}
+
+/// Wait for input, failing with `EINTR` if the current context has a signal pending
+pub fn receive(commands: &WaitQueue<String>) -> Result<String> {
+    loop {
+        if let Some(command) = commands.inner.lock().pop_front() {
+            return Ok(command);
+        }
+
+        {
+            let contexts = ::env().contexts.read();
+            if let Ok(current) = contexts.current() {
+                if current.pending_signals() != 0 {
+                    return Err(Error::new(EINTR));
+                }
+            }
+        }
+
+        unsafe { commands.condition.wait(); }
+    }
+}
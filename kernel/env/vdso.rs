@@ -0,0 +1,55 @@
+use arch::memory;
+use arch::paging::Page;
+
+use core::ptr;
+use core::sync::atomic::{fence, AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
+
+use system::vdso::{VdsoData, VDSO_ADDR};
+
+/// The physical address of the time page, 0 until `init`
+static PAGE: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Allocate the time page, which `Clock` writes on each tick
+pub fn init() {
+    // The memory identity mapped at `VDSO_ADDR` is hidden while a user context runs
+    unsafe { memory::reserve(VDSO_ADDR, 4096) };
+
+    let page = unsafe { memory::alloc_aligned(4096, 4096) };
+    if page > 0 {
+        unsafe { ptr::write(page as *mut VdsoData, VdsoData::default()) };
+        PAGE.store(page, Ordering::SeqCst);
+    }
+}
+
+/// Map the time page read-only at `VDSO_ADDR`, done with the memory of each user context
+pub unsafe fn map() {
+    let page = PAGE.load(Ordering::SeqCst);
+    if page > 0 {
+        Page::new(VDSO_ADDR).map_user_read(page);
+    }
+}
+
+/// Unmap the time page, restoring the identity mapping of the kernel
+pub unsafe fn unmap() {
+    if PAGE.load(Ordering::SeqCst) > 0 {
+        Page::new(VDSO_ADDR).map_kernel_write(VDSO_ADDR);
+    }
+}
+
+/// Replace the contents of the time page, so that readers never see a partial update
+pub fn publish(data: VdsoData) {
+    let page = PAGE.load(Ordering::SeqCst) as *mut VdsoData;
+    if ! page.is_null() {
+        unsafe {
+            let seq = ptr::read_volatile(&(*page).seq);
+            ptr::write_volatile(&mut (*page).seq, seq + 1);
+            fence(Ordering::SeqCst);
+            ptr::write_volatile(page, VdsoData {
+                seq: seq + 1,
+                .. data
+            });
+            fence(Ordering::SeqCst);
+            ptr::write_volatile(&mut (*page).seq, seq + 2);
+        }
+    }
+}
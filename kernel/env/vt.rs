@@ -0,0 +1,59 @@
+use collections::Vec;
+
+use super::console::Console;
+
+/// The number of virtual terminals, switched with Alt+F1 to Alt+F6
+pub const VT_COUNT: usize = 6;
+
+/// Virtual terminals
+///
+/// Each terminal has its own screen contents, input queue, line discipline and foreground
+/// process group. Only the active one is shown and receives keyboard input. Kernel messages and
+/// the serial port use the first one.
+pub struct VirtualTerminals {
+    pub consoles: Vec<Console>,
+    pub active: usize,
+}
+
+impl VirtualTerminals {
+    pub fn new() -> VirtualTerminals {
+        let mut consoles = Vec::new();
+        for i in 0..VT_COUNT {
+            let mut console = Console::new();
+            console.visible = i == 0;
+            console.serial = i == 0;
+            consoles.push(console);
+        }
+
+        VirtualTerminals {
+            consoles: consoles,
+            active: 0,
+        }
+    }
+
+    pub fn active(&self) -> &Console {
+        &self.consoles[self.active]
+    }
+
+    pub fn active_mut(&mut self) -> &mut Console {
+        let active = self.active;
+        &mut self.consoles[active]
+    }
+
+    /// Show terminal `i` and send it keyboard input
+    pub fn switch(&mut self, i: usize) {
+        if i < self.consoles.len() && i != self.active {
+            self.consoles[self.active].visible = false;
+            self.active = i;
+
+            let console = &mut self.consoles[i];
+            console.visible = true;
+            if console.draw {
+                console.redraw = false;
+                if let Some(ref display) = console.display {
+                    display.flip();
+                }
+            }
+        }
+    }
+}
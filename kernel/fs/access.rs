@@ -0,0 +1,77 @@
+use system::error::{Error, Result, EACCES};
+use system::syscall::{Stat, MODE_EXEC, MODE_READ, MODE_WRITE, O_RDWR, O_TRUNC, O_WRONLY};
+
+/// The effective user and group IDs that permissions are checked against
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Creds {
+    pub uid: usize,
+    pub gid: usize,
+}
+
+impl Creds {
+    /// The credentials of root, which kernel threads and boot code run with
+    pub fn root() -> Creds {
+        Creds {
+            uid: 0,
+            gid: 0,
+        }
+    }
+
+    /// The effective IDs of the current context, root if there is none yet
+    pub fn current() -> Creds {
+        match ::env().contexts.read().current() {
+            Ok(current) => Creds {
+                uid: current.euid,
+                gid: current.egid,
+            },
+            Err(_) => Creds::root(),
+        }
+    }
+
+    /// Check if these credentials allow `access`, a mask of `MODE_READ`, `MODE_WRITE` and
+    /// `MODE_EXEC`, to a file with the owner and mode in `stat`
+    ///
+    /// The bits of the owner apply to the owner, those of the group to members of the group, and
+    /// the rest to everyone else. Root may do anything, except execute a file nobody may execute.
+    pub fn permits(&self, stat: &Stat, access: u16) -> bool {
+        let mode = stat.st_mode;
+        if self.uid == 0 {
+            return access & MODE_EXEC == 0 || mode & (MODE_EXEC << 6 | MODE_EXEC << 3 | MODE_EXEC) != 0;
+        }
+
+        let bits = if self.uid == stat.st_uid as usize {
+            mode >> 6
+        } else if self.gid == stat.st_gid as usize {
+            mode >> 3
+        } else {
+            mode
+        } & 0o7;
+        bits & access == access
+    }
+
+    /// Fail with `EACCES` unless these credentials allow `access` to the file in `stat`
+    pub fn check(&self, stat: &Stat, access: u16) -> Result<()> {
+        if self.permits(stat, access) {
+            Ok(())
+        } else {
+            Err(Error::new(EACCES))
+        }
+    }
+}
+
+/// The access that opening a file with `flags` needs, truncating it needs write access
+pub fn open_access(flags: usize) -> u16 {
+    let access = if flags & O_RDWR == O_RDWR {
+        MODE_READ | MODE_WRITE
+    } else if flags & O_WRONLY == O_WRONLY {
+        MODE_WRITE
+    } else {
+        MODE_READ
+    };
+
+    if flags & O_TRUNC == O_TRUNC {
+        access | MODE_WRITE
+    } else {
+        access
+    }
+}
@@ -0,0 +1,108 @@
+use super::{Resource, ResourceSeek};
+
+use alloc::boxed::Box;
+
+use collections::{String, Vec};
+
+use core::cmp::{max, min};
+
+use system::error::Result;
+use system::syscall::{Dirent, MODE_DIR, MODE_TYPE};
+
+/// A directory listing
+///
+/// Reading returns the names, one per line, with `/` after the names of directories. `getdents`
+/// returns the entries with their types and sizes. Seeking to the start restarts both.
+pub struct DirResource {
+    path: String,
+    entries: Vec<Dirent>,
+    data: Vec<u8>,
+    seek: usize,
+    /// The next entry for `getdents`
+    next: usize,
+}
+
+impl DirResource {
+    pub fn new(path: String, entries: Vec<Dirent>) -> Self {
+        let mut data = Vec::new();
+        for entry in entries.iter() {
+            if ! data.is_empty() {
+                data.push(b'\n');
+            }
+            data.extend_from_slice(entry.name());
+            if entry.d_mode & MODE_TYPE == MODE_DIR {
+                data.push(b'/');
+            }
+        }
+
+        DirResource {
+            path: path,
+            entries: entries,
+            data: data,
+            seek: 0,
+            next: 0,
+        }
+    }
+}
+
+impl Resource for DirResource {
+    fn dup(&self) -> Result<Box<Resource>> {
+        Ok(box DirResource {
+            path: self.path.clone(),
+            entries: self.entries.clone(),
+            data: self.data.clone(),
+            seek: self.seek,
+            next: self.next,
+        })
+    }
+
+    fn path(&self, buf: &mut [u8]) -> Result<usize> {
+        let path = self.path.as_bytes();
+
+        let mut i = 0;
+        while i < buf.len() && i < path.len() {
+            buf[i] = path[i];
+            i += 1;
+        }
+
+        Ok(i)
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let mut i = 0;
+        while i < buf.len() && self.seek < self.data.len() {
+            buf[i] = self.data[self.seek];
+            self.seek += 1;
+            i += 1;
+        }
+        Ok(i)
+    }
+
+    fn getdents(&mut self, buf: &mut [Dirent]) -> Result<usize> {
+        let mut i = 0;
+        while i < buf.len() && self.next < self.entries.len() {
+            buf[i] = self.entries[self.next];
+            self.next += 1;
+            i += 1;
+        }
+        Ok(i)
+    }
+
+    fn seek(&mut self, pos: ResourceSeek) -> Result<usize> {
+        match pos {
+            ResourceSeek::Start(offset) => self.seek = min(self.data.len(), offset),
+            ResourceSeek::Current(offset) =>
+                self.seek = max(0, min(self.data.len() as isize, self.seek as isize + offset)) as usize,
+            ResourceSeek::End(offset) =>
+                self.seek = max(0, min(self.data.len() as isize, self.data.len() as isize + offset)) as usize,
+        }
+        if self.seek == 0 {
+            self.next = 0;
+        }
+        Ok(self.seek)
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,185 @@
+//! Grants of caller memory to scheme servers
+//!
+//! A buffer passed to a userspace scheme is mapped into the server for the call. The mapping is
+//! kept as a grant when the call returns, so that a caller reading or writing the same buffer
+//! again, as large I/O does, reuses it instead of mapping it again. Each server keeps at most
+//! `GRANT_IDLE_MAX` grants that no call is using, dropping the least recently used first.
+//!
+//! A grant must not outlive the memory it maps, so grants are revoked when that memory is freed
+//! or moved, when the server closes its scheme, and when the server calls `fsync` on its scheme
+//! handle.
+
+use arch::context::ContextMemory;
+
+use collections::vec::Vec;
+
+use system::error::{Error, Result, EBADF, EFAULT};
+
+/// The most grants a server keeps that no call is using
+pub const GRANT_IDLE_MAX: usize = 16;
+
+/// Memory of a caller mapped into a server
+pub struct ContextGrant {
+    /// The PID of the server
+    pub server: usize,
+    /// The first page of the memory
+    pub physical_address: usize,
+    /// Where the memory is mapped in the server
+    pub virtual_address: usize,
+    /// The size, in whole pages
+    pub size: usize,
+    /// If the server may write to the memory, only for buffers the caller may write to, so that
+    /// memory shared read-only or copied on write is never written through a grant
+    pub writeable: bool,
+    /// The number of calls using the grant
+    pub uses: usize,
+    /// When the grant was last used, by `GrantTable::clock`
+    pub last_use: u64,
+}
+
+/// The grants to all servers
+pub struct GrantTable {
+    pub grants: Vec<ContextGrant>,
+    /// Counts uses, to find the least recently used grant
+    clock: u64,
+    /// Calls that reused a grant
+    pub hits: u64,
+    /// Calls that mapped memory
+    pub misses: u64,
+}
+
+impl GrantTable {
+    pub fn new() -> GrantTable {
+        GrantTable {
+            grants: Vec::new(),
+            clock: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// Grant `len` bytes at `physical_address` to the server `server` for a call, returning the
+    /// address in the server. The server may write to them if `writeable` is set. `release` must
+    /// be called when the call returns
+    pub fn grant(&mut self, server: usize, physical_address: usize, len: usize, writeable: bool) -> Result<usize> {
+        let start = physical_address - physical_address % 4096;
+        let end = try!(physical_address.checked_add(len).and_then(|end| end.checked_add(4095))
+                                       .ok_or(Error::new(EFAULT))) / 4096 * 4096;
+        let end = if end > start { end } else { start + 4096 };
+
+        self.clock += 1;
+
+        for grant in self.grants.iter_mut() {
+            if grant.server == server && grant.writeable == writeable && grant.physical_address <= start && end <= grant.physical_address + grant.size {
+                grant.uses += 1;
+                grant.last_use = self.clock;
+                self.hits += 1;
+                return Ok(grant.virtual_address + physical_address - grant.physical_address);
+            }
+        }
+
+        let virtual_address = {
+            let mut contexts = ::env().contexts.write();
+            let context = try!(contexts.iter_mut().find(|context| context.pid == server && ! context.exited)
+                                      .ok_or(Error::new(EBADF)));
+            unsafe {
+                context.clean_mem();
+                let virtual_address = context.next_mem();
+                (*context.memory.get()).push(ContextMemory {
+                    physical_address: start,
+                    virtual_address: virtual_address,
+                    virtual_size: end - start,
+                    writeable: writeable,
+                    allocated: false,
+                    shared: None,
+                    object: None,
+                });
+                virtual_address
+            }
+        };
+
+        self.misses += 1;
+        self.grants.push(ContextGrant {
+            server: server,
+            physical_address: start,
+            virtual_address: virtual_address,
+            size: end - start,
+            writeable: writeable,
+            uses: 1,
+            last_use: self.clock,
+        });
+
+        Ok(virtual_address + physical_address - start)
+    }
+
+    /// End a call using the grant at `virtual_address` in `server`, dropping the least recently
+    /// used grants of the server over `GRANT_IDLE_MAX`
+    pub fn release(&mut self, server: usize, virtual_address: usize) {
+        for grant in self.grants.iter_mut() {
+            if grant.server == server && grant.virtual_address <= virtual_address
+               && virtual_address < grant.virtual_address + grant.size {
+                grant.uses -= 1;
+                break;
+            }
+        }
+
+        loop {
+            let mut idle = 0;
+            let mut oldest: Option<usize> = None;
+            for (i, grant) in self.grants.iter().enumerate() {
+                if grant.server == server && grant.uses == 0 {
+                    idle += 1;
+                    if oldest.map_or(true, |j| grant.last_use < self.grants[j].last_use) {
+                        oldest = Some(i);
+                    }
+                }
+            }
+
+            match oldest {
+                Some(i) if idle > GRANT_IDLE_MAX => unmap(&self.grants.remove(i)),
+                _ => break,
+            }
+        }
+    }
+
+    /// Revoke the grants of memory overlapping `size` bytes at `physical_address`, which is being
+    /// freed or moved
+    pub fn revoke(&mut self, physical_address: usize, size: usize) {
+        let end = physical_address.saturating_add(size);
+        self.remove(|grant| grant.physical_address < end && physical_address < grant.physical_address + grant.size);
+    }
+
+    /// Revoke every grant to `server`
+    pub fn revoke_server(&mut self, server: usize) {
+        self.remove(|grant| grant.server == server);
+    }
+
+    fn remove<F: Fn(&ContextGrant) -> bool>(&mut self, f: F) {
+        let mut i = 0;
+        while i < self.grants.len() {
+            if f(&self.grants[i]) {
+                unmap(&self.grants.remove(i));
+            } else {
+                i += 1;
+            }
+        }
+    }
+}
+
+/// Remove a grant from the memory of its server, if it still runs
+///
+/// The entry is emptied rather than removed, as this can be called while the memory of a context
+/// is being changed. It is removed by the next `clean_mem` of the server.
+fn unmap(grant: &ContextGrant) {
+    let mut contexts = ::env().contexts.write();
+    let current = contexts.current().map(|context| context.pid).ok();
+    if let Some(context) = contexts.iter_mut().find(|context| context.pid == grant.server) {
+        if let Ok(mem) = context.get_mem_mut(grant.virtual_address) {
+            // The pages of the running context are in the page tables
+            if current == Some(grant.server) {
+                unsafe { mem.unmap(); }
+            }
+            mem.virtual_size = 0;
+        }
+    }
+}
@@ -2,15 +2,26 @@ use super::{Resource, Url};
 
 use alloc::boxed::Box;
 
-use system::error::{Error, Result, ENOENT};
+use system::error::{Error, Result, ENOENT, ENOSYS, EPERM};
 use system::syscall::Stat;
 
 #[allow(unused_variables)]
 pub trait KScheme {
+    /// Handle an IRQ this scheme was registered for with `Environment::add_driver`
     fn on_irq(&mut self, irq: u8) {
 
     }
 
+    /// Stop the device before the machine enters a sleep state, in which it loses power
+    fn suspend(&mut self) {
+
+    }
+
+    /// Restart the device after the machine wakes from a sleep state
+    fn resume(&mut self) {
+
+    }
+
     fn scheme(&self) -> &str {
         ""
     }
@@ -27,6 +38,28 @@ pub trait KScheme {
         Err(Error::new(ENOENT))
     }
 
+    /// Rename `old` to `new`, which is on this scheme too
+    fn rename(&mut self, old: Url, new: Url) -> Result<()> {
+        Err(Error::new(ENOENT))
+    }
+
+    /// Create `new` as another name of the file `old`, which is on this scheme too
+    fn link(&mut self, old: Url, new: Url) -> Result<()> {
+        Err(Error::new(EPERM))
+    }
+
+    /// Create a symbolic link at `path` to `target`, which may be on any scheme
+    fn symlink(&mut self, target: &str, path: Url) -> Result<()> {
+        Err(Error::new(EPERM))
+    }
+
+    /// Read the target of the symbolic link at `path`, failing with `EINVAL` if it is not one.
+    /// Schemes without symbolic links fail with `ENOSYS`, so that paths on them are not searched
+    /// for links
+    fn readlink(&mut self, path: Url, buf: &mut [u8]) -> Result<usize> {
+        Err(Error::new(ENOSYS))
+    }
+
     fn stat(&mut self, path: Url, stat: &mut Stat) -> Result<()> {
         Err(Error::new(ENOENT))
     }
@@ -0,0 +1,123 @@
+use arch::context::{context_switch, Context};
+
+use collections::BTreeMap;
+use collections::string::String;
+
+use core::ops::DerefMut;
+
+use sync::{Intex, PollWaiters};
+
+use system::error::{Error, Result, EINTR, EWOULDBLOCK};
+
+/// The `flock` locks held on a node
+struct NodeLock {
+    /// The shared locks
+    shared: usize,
+    exclusive: bool,
+}
+
+/// A lock taken with `flock`, released when it is dropped
+///
+/// The files of a context keep it in a slot shared by the duplicates of the file they were
+/// opened as, so that it is released when the last of them is closed.
+pub struct FileLock {
+    node: String,
+    exclusive: bool,
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        ::env().locks.unlock(&self.node, self.exclusive);
+    }
+}
+
+/// The advisory locks of every node, by the node path of the resources opened on it
+///
+/// The locks are only checked by `flock`, a file may be read and written without one.
+pub struct LockTable {
+    locks: Intex<BTreeMap<String, NodeLock>>,
+    /// Contexts waiting for a lock to be released, which check again when any is
+    waiters: PollWaiters,
+}
+
+impl LockTable {
+    pub fn new() -> LockTable {
+        LockTable {
+            locks: Intex::new(BTreeMap::new()),
+            waiters: PollWaiters::new(),
+        }
+    }
+
+    /// Lock `node`, waiting until it can be locked, unless `block` is not set
+    pub fn lock(&self, node: String, exclusive: bool, block: bool) -> Result<FileLock> {
+        loop {
+            {
+                let mut contexts = ::env().contexts.write();
+                let mut current = try!(contexts.current_mut());
+                let context = current.deref_mut() as *mut Context;
+
+                // Woken by an unlock, or by a signal
+                self.waiters.remove(context);
+
+                {
+                    let mut locks = self.locks.lock();
+                    let lock = locks.entry(node.clone()).or_insert(NodeLock {
+                        shared: 0,
+                        exclusive: false,
+                    });
+                    if exclusive && lock.shared == 0 && ! lock.exclusive {
+                        lock.exclusive = true;
+                        return Ok(FileLock {
+                            node: node,
+                            exclusive: true,
+                        });
+                    }
+                    if ! exclusive && ! lock.exclusive {
+                        lock.shared += 1;
+                        return Ok(FileLock {
+                            node: node,
+                            exclusive: false,
+                        });
+                    }
+                }
+
+                if ! block {
+                    return Err(Error::new(EWOULDBLOCK));
+                }
+                if current.pending_signals() != 0 {
+                    return Err(Error::new(EINTR));
+                }
+
+                // Interrupts are disabled from the check until here, so the lock cannot be
+                // released before this context waits for it
+                self.waiters.add(context);
+                current.blocked = true;
+            }
+
+            unsafe { context_switch(); }
+        }
+    }
+
+    /// Release a lock of `node`, and wake the contexts waiting for one
+    fn unlock(&self, node: &str, exclusive: bool) {
+        {
+            let mut locks = self.locks.lock();
+            let unused = match locks.get_mut(node) {
+                Some(lock) => {
+                    if exclusive {
+                        lock.exclusive = false;
+                    } else {
+                        lock.shared = lock.shared.saturating_sub(1);
+                    }
+                    lock.shared == 0 && ! lock.exclusive
+                },
+                None => false,
+            };
+            if unused {
+                locks.remove(node);
+            }
+        }
+
+        unsafe { self.waiters.notify(); }
+    }
+}
@@ -1,13 +1,28 @@
 pub mod redoxfs;
 
+pub use self::access::Creds;
+pub use self::dir_resource::DirResource;
+pub use self::grant::{ContextGrant, GrantTable};
 pub use self::kscheme::KScheme;
+pub use self::lock::{FileLock, LockTable};
+pub use self::registry::{SchemeEntry, SchemeRegistry};
 pub use self::resource::{Resource, ResourceSeek};
 pub use self::scheme::Scheme;
 pub use self::url::{Url, OwnedUrl};
 pub use self::vec_resource::VecResource;
 
+/// Permission checks
+pub mod access;
+/// Directory listings
+pub mod dir_resource;
+/// Grants of caller memory to scheme servers
+pub mod grant;
 /// Kernel schemes
 pub mod kscheme;
+/// Advisory file locks
+pub mod lock;
+/// Registered schemes
+pub mod registry;
 /// Internal resource representation
 pub mod resource;
 /// Userspace scheme
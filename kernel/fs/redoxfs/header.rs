@@ -6,7 +6,9 @@ pub struct Header {
     pub signature: [u8; 8],
     pub version: u64,
     pub free_space: Extent,
-    pub padding: [u8; 224],
+    /// The metadata journal, empty on file systems made before it, which get one when mounted
+    pub journal: Extent,
+    pub padding: [u8; 208],
     pub extents: [Extent; 16],
 }
 
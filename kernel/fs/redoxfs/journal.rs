@@ -0,0 +1,171 @@
+use collections::vec::Vec;
+
+use core::{mem, ptr};
+
+use disk::BlockDevice;
+use disk::ide::Extent;
+
+use system::error::{Error, Result, EIO};
+
+/// The blocks of a journal, a commit block followed by the blocks of a transaction
+pub const JOURNAL_BLOCKS: u64 = 64;
+
+/// The signature of a commit block
+const JOURNAL_SIGNATURE: &'static [u8; 8] = b"RFSJRNL\0";
+
+/// The most blocks in a transaction, as many as the commit block lists
+const TRANSACTION_MAX: usize = 61;
+
+/// The first block of a journal, listing where the blocks of the committed transaction go
+#[repr(packed)]
+pub struct JournalHeader {
+    pub signature: [u8; 8],
+    /// Counted up on every commit
+    pub sequence: u64,
+    /// The blocks in the transaction, 0 if there is none to replay
+    pub count: u64,
+    pub blocks: [u64; TRANSACTION_MAX],
+}
+
+impl JournalHeader {
+    fn new(sequence: u64) -> JournalHeader {
+        JournalHeader {
+            signature: *JOURNAL_SIGNATURE,
+            sequence: sequence,
+            count: 0,
+            blocks: [0; TRANSACTION_MAX],
+        }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![0; 512];
+        unsafe { ptr::write(bytes.as_mut_ptr() as *mut JournalHeader, ptr::read(self)) };
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> JournalHeader {
+        assert!(bytes.len() >= mem::size_of::<JournalHeader>());
+        unsafe { ptr::read(bytes.as_ptr() as *const JournalHeader) }
+    }
+}
+
+/// A metadata journal, an intent log of the node and header blocks about to be written
+///
+/// Blocks are logged in memory until `commit`, which writes them to the journal, then the commit
+/// block, and only then the blocks where they belong, flushing the disk between each step. If
+/// the system stops before the commit block is written, the blocks are lost, and if it stops
+/// after, `replay` finishes writing them when the file system is mounted, so a transaction is
+/// either done or not at all. A transaction that would have more than `TRANSACTION_MAX` blocks
+/// is committed in parts.
+pub struct Journal {
+    /// The commit block
+    block: u64,
+    sequence: u64,
+    /// The logged blocks, not written yet
+    pending: Vec<(u64, Vec<u8>)>,
+}
+
+impl Journal {
+    /// The journal in `extent`, created by the file system
+    pub fn new(extent: &Extent) -> Journal {
+        Journal {
+            block: extent.block,
+            sequence: 0,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Write an empty commit block, for a new journal
+    pub fn format(&mut self, disk: &mut BlockDevice) -> Result<()> {
+        try!(disk.write_blocks(self.block, &JournalHeader::new(0).to_bytes()));
+        disk.flush()
+    }
+
+    /// Finish writing the committed transaction, if there is one, before the file system is read
+    pub fn replay(&mut self, disk: &mut BlockDevice) -> Result<()> {
+        let mut data = vec![0; 512];
+        try!(disk.read_blocks(self.block, &mut data));
+        let header = JournalHeader::from_bytes(&data);
+        if &header.signature != JOURNAL_SIGNATURE {
+            debugln!("{}: journal missing, formatting it", disk.name());
+            return self.format(disk);
+        }
+
+        self.sequence = header.sequence;
+
+        let count = header.count as usize;
+        if count == 0 {
+            return Ok(());
+        }
+        if count > TRANSACTION_MAX || count as u64 >= JOURNAL_BLOCKS {
+            debugln!("{}: journal transaction {} has {} blocks, dropping it", disk.name(), header.sequence, count);
+            return self.clear(disk);
+        }
+
+        debugln!("{}: replaying journal transaction {} of {} blocks", disk.name(), header.sequence, count);
+        let mut blocks = vec![0; count * 512];
+        try!(disk.read_blocks(self.block + 1, &mut blocks));
+        for i in 0..count {
+            try!(disk.write_blocks(header.blocks[i], &blocks[i * 512 .. (i + 1) * 512]));
+        }
+        try!(disk.flush());
+
+        self.clear(disk)
+    }
+
+    /// Log `data` to be written to `block` by the next commit, replacing an earlier copy
+    pub fn log(&mut self, disk: &mut BlockDevice, block: u64, data: Vec<u8>) -> Result<()> {
+        if let Some(i) = self.pending.iter().position(|&(pending, _)| pending == block) {
+            self.pending[i].1 = data;
+            return Ok(());
+        }
+
+        if self.pending.len() >= TRANSACTION_MAX {
+            try!(self.commit(disk));
+        }
+        self.pending.push((block, data));
+        Ok(())
+    }
+
+    /// Write the logged blocks atomically
+    ///
+    /// The disk is flushed first, so that the data of files reaches it before the nodes that
+    /// point to it.
+    pub fn commit(&mut self, disk: &mut BlockDevice) -> Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+
+        let mut data = Vec::with_capacity(self.pending.len() * 512);
+        for &(_, ref block_data) in self.pending.iter() {
+            data.extend_from_slice(block_data);
+        }
+        try!(disk.write_blocks(self.block + 1, &data));
+        try!(disk.flush());
+
+        self.sequence += 1;
+        let mut header = JournalHeader::new(self.sequence);
+        header.count = self.pending.len() as u64;
+        for (i, &(block, _)) in self.pending.iter().enumerate() {
+            header.blocks[i] = block;
+        }
+        try!(disk.write_blocks(self.block, &header.to_bytes()));
+        try!(disk.flush());
+
+        for &(block, ref block_data) in self.pending.iter() {
+            if try!(disk.write_blocks(block, block_data)) != block_data.len() {
+                return Err(Error::new(EIO));
+            }
+        }
+        try!(disk.flush());
+        self.pending.clear();
+
+        self.clear(disk)
+    }
+
+    /// Mark the transaction as written, so that it is not replayed
+    fn clear(&mut self, disk: &mut BlockDevice) -> Result<()> {
+        try!(disk.write_blocks(self.block, &JournalHeader::new(self.sequence).to_bytes()));
+        disk.flush()
+    }
+}
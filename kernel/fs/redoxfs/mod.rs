@@ -8,33 +8,54 @@ use arch::memory::Memory;
 
 use core::{cmp, ptr, slice};
 
-use disk::Disk;
+use disk::BlockDevice;
 
 use system::error::{Error, Result, ENOMEM, EINVAL};
 
 pub use self::header::Header;
-pub use self::node::{Node, NodeData};
+pub use self::journal::{Journal, JOURNAL_BLOCKS};
+pub use self::node::{Node, NodeData, NODE_EXTENTS};
 
 pub mod header;
+pub mod journal;
 pub mod node;
 
+/// The block of the header
+const HEADER_BLOCK: u64 = 1;
+
 /// A file system
 pub struct FileSystem {
-    pub disk: Box<Disk>,
+    pub disk: Box<BlockDevice>,
     pub header: Header,
     pub nodes: Vec<Node>,
+    /// The journal of the changes to the header and the nodes, if there was room for it
+    pub journal: Option<Journal>,
 }
 
 impl FileSystem {
     /// Create a file system from a disk
-    pub fn from_disk(mut disk: Box<Disk>) -> Result<Self> {
+    ///
+    /// The journal is replayed before the header and the nodes are read, or made from free space
+    /// if the file system has none.
+    pub fn from_disk(mut disk: Box<BlockDevice>) -> Result<Self> {
         if let Some(data) = Memory::<u8>::new(512) {
-            try!(disk.read(1, unsafe { slice::from_raw_parts_mut(data.ptr, 512) }));
-            
-            let header = unsafe { ptr::read(data.ptr as *const Header) };
+            try!(disk.read_blocks(HEADER_BLOCK, unsafe { slice::from_raw_parts_mut(data.ptr, 512) }));
+
+            let mut header = unsafe { ptr::read(data.ptr as *const Header) };
             if header.valid() {
                 debugln!("{}: Redox Filesystem", disk.name());
 
+                let journal = if header.journal.empty() {
+                    try!(FileSystem::create_journal(&mut *disk, &mut header))
+                } else {
+                    let mut journal = Journal::new(&header.journal);
+                    try!(journal.replay(&mut *disk));
+
+                    try!(disk.read_blocks(HEADER_BLOCK, unsafe { slice::from_raw_parts_mut(data.ptr, 512) }));
+                    header = unsafe { ptr::read(data.ptr as *const Header) };
+                    Some(journal)
+                };
+
                 let mut nodes = Vec::new();
                 for extent in &header.extents {
                     if extent.block > 0 && extent.length > 0 {
@@ -47,12 +68,16 @@ impl FileSystem {
                             let mut buffer = unsafe {
                                 slice::from_raw_parts_mut(data.ptr, max_size)
                             };
-                            try!(disk.read(extent.block, &mut buffer));
+                            try!(disk.read_blocks(extent.block, &mut buffer));
 
                             for i in 0..size / 512 {
-                                nodes.push(Node::new(extent.block + i as u64, unsafe {
+                                let node = Node::new(extent.block + i as u64, unsafe {
                                     &*(data.ptr.offset(i as isize * 512) as *const NodeData)
-                                }));
+                                });
+                                // A removed node has no name
+                                if ! node.name.is_empty() {
+                                    nodes.push(node);
+                                }
                             }
                         }
                     }
@@ -62,6 +87,7 @@ impl FileSystem {
                     disk: disk,
                     header: header,
                     nodes: nodes,
+                    journal: journal,
                 })
             } else {
                 debugln!("{}: Unknown Filesystem", disk.name());
@@ -72,6 +98,61 @@ impl FileSystem {
         }
     }
 
+    /// Take the blocks of a journal from the free space, and write the header pointing to it
+    fn create_journal(disk: &mut BlockDevice, header: &mut Header) -> Result<Option<Journal>> {
+        if header.free_space.length < JOURNAL_BLOCKS * 512 {
+            debugln!("{}: no space for a journal", disk.name());
+            return Ok(None);
+        }
+
+        header.journal.block = header.free_space.block;
+        header.journal.length = JOURNAL_BLOCKS * 512;
+        header.free_space.block += JOURNAL_BLOCKS;
+        header.free_space.length -= JOURNAL_BLOCKS * 512;
+
+        let mut journal = Journal::new(&header.journal);
+        try!(journal.format(disk));
+        try!(disk.write_blocks(HEADER_BLOCK, unsafe { slice::from_raw_parts(header as *const Header as *const u8, 512) }));
+        try!(disk.flush());
+
+        debugln!("{}: journal created at {}", disk.name(), header.journal.block);
+        Ok(Some(journal))
+    }
+
+    /// Log the header, to be written by `commit`
+    pub fn write_header(&mut self) -> Result<()> {
+        let data = unsafe { slice::from_raw_parts(&self.header as *const Header as *const u8, 512) }.to_vec();
+        self.write_metadata(HEADER_BLOCK, data)
+    }
+
+    /// Log `node`, to be written by `commit`
+    pub fn write_node(&mut self, node: &Node) -> Result<()> {
+        let node_data = node.data();
+        let data = unsafe { slice::from_raw_parts(&node_data as *const NodeData as *const u8, 512) }.to_vec();
+        self.write_metadata(node.block, data)
+    }
+
+    /// Log the removal of the node at `block`, which is cleared
+    pub fn remove_node(&mut self, block: u64) -> Result<()> {
+        self.write_node(&Node::create(block, String::new(), 0, 0))
+    }
+
+    /// Log a block of metadata, or write it at once without a journal
+    fn write_metadata(&mut self, block: u64, data: Vec<u8>) -> Result<()> {
+        match self.journal {
+            Some(ref mut journal) => journal.log(&mut *self.disk, block, data),
+            None => self.disk.write_blocks(block, &data).and(Ok(())),
+        }
+    }
+
+    /// Write the logged metadata as one transaction, and flush the disk
+    pub fn commit(&mut self) -> Result<()> {
+        if let Some(ref mut journal) = self.journal {
+            try!(journal.commit(&mut *self.disk));
+        }
+        self.disk.flush()
+    }
+
     /// Get node with a given filename
     pub fn node(&self, filename: &str) -> Option<Node> {
         for node in self.nodes.iter() {
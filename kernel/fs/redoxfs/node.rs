@@ -3,21 +3,53 @@ use collections::vec::Vec;
 
 use disk::ide::Extent;
 
+use syscall::{MODE_FILE, MODE_SYMLINK, MODE_TYPE};
+
+/// The extents of a node
+pub const NODE_EXTENTS: usize = 15;
+
 /// Data for a node
+///
+/// The type and the link were added in place of the last of 16 extents, so a node written before
+/// them has a type of 0, which is a file.
 #[repr(packed)]
 pub struct NodeData {
     pub name: [u8; 256],
-    pub extents: [Extent; 16],
+    pub extents: [Extent; NODE_EXTENTS],
+    /// `MODE_FILE` or `MODE_SYMLINK`
+    pub mode: u16,
+    pub padding: [u8; 6],
+    /// The block of the node whose data this hard link shares, or 0
+    pub link: u64,
 }
 
 /// A file node
+///
+/// A symbolic link is a node whose data is its target. A hard link has no extents of its own,
+/// but the `link` of the node that has them.
 pub struct Node {
     pub block: u64,
     pub name: String,
-    pub extents: [Extent; 16],
+    pub extents: [Extent; NODE_EXTENTS],
+    pub mode: u16,
+    pub link: u64,
 }
 
 impl Node {
+    /// Create an empty node at `block`
+    pub fn create(block: u64, name: String, mode: u16, link: u64) -> Self {
+        Node {
+            block: block,
+            name: name,
+            extents: [Extent {
+                block: 0,
+                length: 0,
+            }; NODE_EXTENTS],
+            mode: mode,
+            link: link,
+        }
+    }
+
     /// Create a new file node from an address and some data
     pub fn new(block: u64, data: &NodeData) -> Self {
         let mut bytes = Vec::new();
@@ -33,6 +65,12 @@ impl Node {
             block: block,
             name: unsafe { String::from_utf8_unchecked(bytes) },
             extents: data.extents,
+            mode: if data.mode & MODE_TYPE == MODE_SYMLINK {
+                MODE_SYMLINK
+            } else {
+                MODE_FILE
+            },
+            link: data.link,
         }
     }
 
@@ -50,8 +88,18 @@ impl Node {
         NodeData {
             name: name,
             extents: self.extents,
+            mode: self.mode,
+            padding: [0; 6],
+            link: self.link,
         }
     }
+
+    /// The size of the data in the extents of the node
+    pub fn size(&self) -> u64 {
+        self.extents.iter()
+                    .filter(|extent| extent.block > 0 && extent.length > 0)
+                    .fold(0, |size, extent| size + extent.length)
+    }
 }
 
 impl Clone for Node {
@@ -60,6 +108,8 @@ impl Clone for Node {
             block: self.block,
             name: self.name.clone(),
             extents: self.extents,
+            mode: self.mode,
+            link: self.link,
         }
     }
 }
@@ -0,0 +1,165 @@
+use alloc::arc::Arc;
+use alloc::boxed::Box;
+
+use collections::string::{String, ToString};
+use collections::vec::Vec;
+
+use core::cell::UnsafeCell;
+
+use sync::{Intex, Rcu};
+
+use system::error::{Error, Result, EEXIST};
+
+use super::KScheme;
+
+/// A registered scheme
+///
+/// Callers get their own reference, so a scheme that is unregistered while one of its calls
+/// blocks is freed when the call returns.
+pub struct SchemeEntry {
+    name: String,
+    scheme: UnsafeCell<Box<KScheme>>,
+    lock: Intex<()>,
+    /// Whether calls skip the lock, set for user schemes, which block until the server replies
+    /// and keep their state behind their own locks
+    shared: bool,
+}
+
+impl SchemeEntry {
+    fn new(scheme: Box<KScheme>, shared: bool) -> SchemeEntry {
+        SchemeEntry {
+            name: scheme.scheme().to_string(),
+            scheme: UnsafeCell::new(scheme),
+            lock: Intex::new(()),
+            shared: shared,
+        }
+    }
+
+    /// The name of the scheme
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Call the scheme
+    ///
+    /// Calls to a kernel scheme are serialized by the entry's lock, as they were by the lock on
+    /// the whole list, so they may mutate its state. Only user schemes are called without it.
+    pub fn call<T, F: FnOnce(&mut KScheme) -> T>(&self, f: F) -> T {
+        let scheme = unsafe { &mut **self.scheme.get() };
+        if self.shared {
+            f(scheme)
+        } else {
+            let _guard = self.lock.lock();
+            f(scheme)
+        }
+    }
+}
+
+/// The number of buckets a table starts with, doubled when there are more schemes than buckets
+const INITIAL_BUCKETS: usize = 64;
+
+/// FNV-1a, schemes are looked up by name on every URL resolution
+fn hash(name: &str) -> usize {
+    let mut hash: u32 = 0x811C9DC5;
+    for &byte in name.as_bytes() {
+        hash ^= byte as u32;
+        hash = hash.wrapping_mul(0x01000193);
+    }
+    hash as usize
+}
+
+/// The schemes indexed by name, and in the order they were registered
+#[derive(Clone)]
+struct SchemeTable {
+    buckets: Vec<Vec<Arc<SchemeEntry>>>,
+    order: Vec<Arc<SchemeEntry>>,
+}
+
+impl SchemeTable {
+    fn new() -> SchemeTable {
+        SchemeTable {
+            buckets: (0..INITIAL_BUCKETS).map(|_| Vec::new()).collect(),
+            order: Vec::new(),
+        }
+    }
+
+    fn bucket(&self, name: &str) -> usize {
+        hash(name) % self.buckets.len()
+    }
+
+    fn get(&self, name: &str) -> Option<&Arc<SchemeEntry>> {
+        self.buckets[self.bucket(name)].iter().find(|entry| entry.name() == name)
+    }
+
+    fn push(&mut self, entry: Arc<SchemeEntry>) {
+        if self.order.len() >= self.buckets.len() {
+            let count = self.buckets.len() * 2;
+            self.buckets = (0..count).map(|_| Vec::new()).collect();
+            for entry in self.order.iter() {
+                let bucket = self.bucket(entry.name());
+                self.buckets[bucket].push(entry.clone());
+            }
+        }
+
+        let bucket = self.bucket(entry.name());
+        self.buckets[bucket].push(entry.clone());
+        self.order.push(entry);
+    }
+
+    fn remove(&mut self, name: &str) {
+        let bucket = self.bucket(name);
+        self.buckets[bucket].retain(|entry| entry.name() != name);
+        self.order.retain(|entry| entry.name() != name);
+    }
+}
+
+/// The registered schemes
+///
+/// URL resolution reads the table without locking, finding a scheme by the hash of its name.
+/// Registering and unregistering publish a new table, and the old one is freed once no resolution
+/// is using it.
+pub struct SchemeRegistry {
+    table: Rcu<SchemeTable>,
+}
+
+impl SchemeRegistry {
+    pub fn new() -> SchemeRegistry {
+        SchemeRegistry {
+            table: Rcu::new(SchemeTable::new()),
+        }
+    }
+
+    /// Register a kernel scheme
+    pub fn push(&self, scheme: Box<KScheme>) {
+        let entry = Arc::new(SchemeEntry::new(scheme, false));
+        self.table.update(move |table| table.push(entry));
+    }
+
+    /// Register a user scheme, failing with `EEXIST` if its name is taken
+    pub fn insert(&self, scheme: Box<KScheme>) -> Result<()> {
+        let entry = Arc::new(SchemeEntry::new(scheme, true));
+        self.table.update(move |table| {
+            if table.get(entry.name()).is_some() {
+                Err(Error::new(EEXIST))
+            } else {
+                table.push(entry);
+                Ok(())
+            }
+        })
+    }
+
+    /// Unregister the scheme called `name`
+    pub fn remove(&self, name: &str) {
+        self.table.update(|table| table.remove(name));
+    }
+
+    /// Find the scheme called `name`
+    pub fn get(&self, name: &str) -> Option<Arc<SchemeEntry>> {
+        self.table.read().get(name).map(|entry| entry.clone())
+    }
+
+    /// Every registered scheme, in the order they were registered
+    pub fn list(&self) -> Vec<Arc<SchemeEntry>> {
+        self.table.read().order.clone()
+    }
+}
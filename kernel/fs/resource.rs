@@ -1,7 +1,10 @@
+use alloc::arc::Arc;
 use alloc::boxed::Box;
 
-use system::error::{Error, Result, EBADF};
-use system::syscall::Stat;
+use arch::context::SharedMemory;
+
+use system::error::{Error, Result, EBADF, EINVAL, ENODEV, ENOTDIR, ENOTTY, ESPIPE};
+use system::syscall::{Dirent, POLLIN, POLLOUT, Stat};
 
 /// Resource seek
 #[derive(Copy, Clone, Debug)]
@@ -27,6 +30,12 @@ pub trait Resource {
         Err(Error::new(EBADF))
     }
 
+    /// Return the path of the node under this resource, which `flock` locks. A resource that may
+    /// be opened by several paths, like a file with hard links, returns the same for each
+    fn node_path(&self, buf: &mut [u8]) -> Result<usize> {
+        self.path(buf)
+    }
+
     /// Read data to buffer
     fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
         Err(Error::new(EBADF))
@@ -37,9 +46,14 @@ pub trait Resource {
         Err(Error::new(EBADF))
     }
 
+    /// Read the entries of a directory, returning the number read, 0 at the end
+    fn getdents(&mut self, buf: &mut [Dirent]) -> Result<usize> {
+        Err(Error::new(ENOTDIR))
+    }
+
     /// Seek to the given offset
     fn seek(&mut self, pos: ResourceSeek) -> Result<usize> {
-        Err(Error::new(EBADF))
+        Err(Error::new(ESPIPE))
     }
 
     fn stat(&self, stat: &mut Stat) -> Result<usize> {
@@ -48,11 +62,43 @@ pub trait Resource {
 
     /// Sync all buffers
     fn sync(&mut self) -> Result<()> {
-        Err(Error::new(EBADF))
+        Err(Error::new(EINVAL))
     }
 
     /// Truncate to the given length
     fn truncate(&mut self, len: usize) -> Result<()> {
-        Err(Error::new(EBADF))
+        Err(Error::new(EINVAL))
+    }
+
+    /// Change the flags of the file with `F_SETFL`. Files that are not ready fail with `EAGAIN`
+    /// if `O_NONBLOCK` is set, resources that can block report if they are ready with `poll`
+    fn fcntl(&mut self, cmd: usize, arg: usize) -> Result<usize> {
+        Ok(0)
+    }
+
+    /// Physical memory to map into the caller with `fmap`, as an address and a size
+    ///
+    /// The memory must stay allocated after the resource is closed, as the mapping may outlive it.
+    fn map(&mut self) -> Result<(usize, usize)> {
+        Err(Error::new(ENODEV))
+    }
+
+    /// A memory object to map instead of the memory from `map`, which the mappings keep allocated
+    /// so that it can be freed when the last resource is closed
+    fn map_object(&mut self) -> Option<Arc<SharedMemory>> {
+        None
+    }
+
+    /// The events that are ready, some of the `POLL` values
+    ///
+    /// Resources that can block must override this, and notify a `WaitCondition` when they become
+    /// ready, so that `poll` checks them again. Others never block, so they are always ready.
+    fn poll(&self) -> Result<usize> {
+        Ok(POLLIN | POLLOUT)
+    }
+
+    /// Device specific control, `arg` is sized for the request by the caller
+    fn ioctl(&mut self, request: usize, arg: &mut [u8]) -> Result<usize> {
+        Err(Error::new(ENOTTY))
     }
 }
@@ -1,73 +1,277 @@
 use alloc::arc::{Arc, Weak};
 use alloc::boxed::Box;
 
-use collections::String;
+use collections::{BTreeMap, String};
 use collections::borrow::ToOwned;
 
 use core::cell::Cell;
 use core::mem::size_of;
-use core::ops::DerefMut;
 use core::ptr;
 
-use arch::context::{Context, ContextMemory};
+use arch::context::ContextMemory;
+use arch::memory;
 
-use sync::{WaitMap, WaitQueue};
+use env::log::LogLevel;
 
-use system::error::{Error, Result, EBADF, EFAULT, EINVAL, ENOENT, ESPIPE};
+use sync::{Intex, WaitMap, WaitQueue};
+
+use system::error::{Error, Result, EBADF, EFAULT, EINVAL, EIO, ENODEV, ENOMEM, ESPIPE};
 use system::scheme::Packet;
-use system::syscall::{SYS_CLOSE, SYS_FPATH, SYS_FSYNC, SYS_FTRUNCATE,
-                    SYS_LSEEK, SEEK_SET, SEEK_CUR, SEEK_END, SYS_MKDIR,
-                    SYS_OPEN, SYS_READ, SYS_WRITE, SYS_RMDIR, SYS_UNLINK};
+use system::syscall::{Dirent, POLLIN, POLLOUT, Stat, SYS_CLOSE, SYS_DUP, SYS_FCNTL, SYS_FPATH, SYS_FSTAT, SYS_FSYNC,
+                    SYS_FTRUNCATE, SYS_GETDENTS, SYS_LSEEK, SEEK_SET, SEEK_CUR, SEEK_END, SYS_MKDIR,
+                    SYS_OPEN, SYS_READ, SYS_WRITE, SYS_RENAME, SYS_RMDIR, SYS_STAT, SYS_UNLINK, SYS_LINK,
+                    SYS_SYMLINK, SYS_READLINK};
 
 use super::{Resource, ResourceSeek, KScheme, Url};
 
+/// A request sent to the server that it has not answered
+struct Request {
+    /// The PID of the caller
+    pid: usize,
+    /// The call, `a` of the packet
+    call: usize,
+}
+
+/// A userspace scheme
+///
+/// Every call is a request with an ID of its own, so a server can answer requests in any order,
+/// and several contexts, like the threads of a process, can have calls to one scheme in flight.
+/// Each caller is woken when the answer to its own request is written.
 struct SchemeInner {
     name: String,
-    context: *mut Context,
-    next_id: Cell<usize>,
+    uid: usize,
+    /// The PID of the server, which is looked up to map memory into it, as it may have exited
+    pid: usize,
+    /// The ID of the next request, IDs are never reused, as a `u64` does not wrap
+    next_id: Cell<u64>,
     todo: WaitQueue<Packet>,
-    done: WaitMap<usize, (usize, usize, usize, usize)>,
+    done: WaitMap<u64, (usize, usize, usize, usize)>,
+    /// The requests sent that have not been answered, by ID
+    pending: Intex<BTreeMap<u64, Request>>,
+    /// The number of handles the server has open
+    servers: Cell<usize>,
+    /// Set when the server has closed its last handle
+    closed: Cell<bool>,
+    /// Set once the scheme is in the registry, so that only a registered scheme is removed
+    registered: Cell<bool>,
 }
 
 impl SchemeInner {
-    fn new(name: &str, context: *mut Context) -> SchemeInner {
+    fn new(name: &str, uid: usize, pid: usize) -> SchemeInner {
+        *::env().scheme_counts.lock().entry(uid).or_insert(0) += 1;
+
         SchemeInner {
             name: name.to_owned(),
-            context: context,
+            uid: uid,
+            pid: pid,
             next_id: Cell::new(1),
             todo: WaitQueue::new(),
             done: WaitMap::new(),
+            pending: Intex::new(BTreeMap::new()),
+            servers: Cell::new(1),
+            closed: Cell::new(false),
+            registered: Cell::new(false),
         }
     }
 
-    fn call(inner: &Weak<SchemeInner>, a: usize, b: usize, c: usize, d: usize) -> Result<usize> {
-        if let Some(scheme) = inner.upgrade() {
-            let id = scheme.next_id.get();
+    /// The scheme, if its server has not closed its last handle
+    fn upgrade(inner: &Weak<SchemeInner>) -> Option<Arc<SchemeInner>> {
+        inner.upgrade().and_then(|scheme| if scheme.closed.get() {
+            None
+        } else {
+            Some(scheme)
+        })
+    }
+
+    /// Unregister the scheme when the server has closed its last handle, usually because it
+    /// exited, so that it can be registered again
+    ///
+    /// Requests that the server has not answered fail with `EIO`, later ones with `ENODEV`. Grants
+    /// to the server are revoked.
+    fn close(&self) {
+        self.closed.set(true);
+        ::env().grants.lock().revoke_server(self.pid);
+
+        // Scheme names are unique, so this is the scheme of this server. Resolutions that found
+        // it before it was removed hold their own reference, and fail with `ENODEV`
+        if self.registered.get() {
+            ::env().schemes.remove(&self.name);
+        }
+
+        let mut pending = self.pending.lock();
+        if ! pending.is_empty() {
+            klogln!(LogLevel::Warning, "scheme: {}: server closed with {} requests pending", self.name, pending.len());
+        }
 
-            //TODO: What should be done about collisions in self.todo or self.done?
-            let mut next_id = id + 1;
-            if next_id <= 0 {
-                next_id = 1;
+        // Answers that were written before the server closed are kept
+        self.todo.inner.lock().clear();
+        for (id, request) in pending.iter() {
+            klogln!(LogLevel::Debug, "scheme: {}: request {} for call {} of PID {} failed", self.name, id, request.call, request.pid);
+            self.done.send(*id, (Error::mux(Err(Error::new(EIO))), 0, 0, 0));
+        }
+        pending.clear();
+    }
+
+    /// Map `size` bytes of kernel memory at `address` into the server, returning the address in
+    /// the server. Fails with `EBADF` if the server has exited, as a handle it left open in a
+    /// child keeps the scheme
+    fn map(&self, address: usize, size: usize, writeable: bool) -> Result<usize> {
+        let mut contexts = ::env().contexts.write();
+        let context = try!(contexts.iter_mut().find(|context| context.pid == self.pid && ! context.exited)
+                                   .ok_or(Error::new(EBADF)));
+        let virtual_address = context.next_mem();
+        unsafe {
+            (*context.memory.get()).push(ContextMemory {
+                physical_address: address,
+                virtual_address: virtual_address,
+                virtual_size: size,
+                writeable: writeable,
+                allocated: false,
+                shared: None,
+                object: None,
+            });
+        }
+        Ok(virtual_address)
+    }
+
+    /// Remove a mapping made with `map`, if the server has not exited
+    fn unmap(&self, virtual_address: usize) {
+        let mut contexts = ::env().contexts.write();
+        if let Some(context) = contexts.iter_mut().find(|context| context.pid == self.pid) {
+            if let Ok(mut mem) = context.get_mem_mut(virtual_address) {
+                mem.virtual_size = 0;
             }
-            scheme.next_id.set(next_id);
-
-            scheme.todo.send(Packet {
-                id: id,
-                a: a,
-                b: b,
-                c: c,
-                d: d
+            unsafe { context.clean_mem(); }
+        }
+    }
+
+    /// Call the server with the path of `url` mapped into it, as `b`
+    fn call_path(inner: &Weak<SchemeInner>, a: usize, url: &Url, c: usize) -> Result<usize> {
+        let c_str = url.to_string() + "\0";
+
+        let virtual_address = match SchemeInner::upgrade(inner) {
+            Some(scheme) => try!(scheme.map(c_str.as_ptr() as usize, c_str.len(), false)),
+            None => return Err(Error::new(ENODEV)),
+        };
+
+        let result = SchemeInner::call(inner, a, virtual_address, c, 0);
+
+        // The mapping is removed even if the server closed in the meantime
+        if let Some(scheme) = inner.upgrade() {
+            scheme.unmap(virtual_address);
+        }
+
+        result
+    }
+
+    /// Call the server with the path of `url` mapped into it, as `b`, and the string `other`,
+    /// which must be terminated, as `c`
+    fn call_paths(inner: &Weak<SchemeInner>, a: usize, url: &Url, other: &str) -> Result<usize> {
+        let virtual_address = match SchemeInner::upgrade(inner) {
+            Some(scheme) => try!(scheme.map(other.as_ptr() as usize, other.len(), false)),
+            None => return Err(Error::new(ENODEV)),
+        };
+
+        let result = SchemeInner::call_path(inner, a, url, virtual_address);
+
+        if let Some(scheme) = inner.upgrade() {
+            scheme.unmap(virtual_address);
+        }
+
+        result
+    }
+
+    /// Call the server with a `Stat` it writes to, as `c`. The `Stat` is in a page of its own, so
+    /// that the server cannot see the kernel memory around it
+    fn call_stat(inner: &Weak<SchemeInner>, a: usize, b: usize, stat: &mut Stat) -> Result<usize> {
+        let buffer = unsafe { memory::alloc(size_of::<Stat>()) };
+        if buffer == 0 {
+            return Err(Error::new(ENOMEM));
+        }
+
+        let result = match SchemeInner::upgrade(inner).map(|scheme| scheme.map(buffer, size_of::<Stat>(), true)) {
+            Some(Ok(virtual_address)) => {
+                let result = SchemeInner::call(inner, a, b, virtual_address, 0);
+
+                if let Some(scheme) = inner.upgrade() {
+                    scheme.unmap(virtual_address);
+                }
+
+                result
+            },
+            Some(Err(err)) => Err(err),
+            None => Err(Error::new(ENODEV)),
+        };
+
+        if result.is_ok() {
+            *stat = unsafe { ptr::read(buffer as *const Stat) };
+        }
+        unsafe { memory::unalloc(buffer) };
+
+        result
+    }
+
+    /// Queue a request for the server, returning its ID, which `wait` takes. The packet carries
+    /// the effective IDs of the caller, for the server to check permissions with
+    fn send(&self, a: usize, b: usize, c: usize, d: usize) -> u64 {
+        let (pid, uid, gid) = match ::env().contexts.read().current() {
+            Ok(context) => (context.pid, context.euid, context.egid),
+            Err(_) => (0, 0, 0),
+        };
+
+        let id = {
+            let mut pending = self.pending.lock();
+
+            let id = self.next_id.get();
+            self.next_id.set(id + 1);
+
+            pending.insert(id, Request {
+                pid: pid,
+                call: a,
             });
-            Error::demux(scheme.done.receive(&id).0)
+            id
+        };
+
+        self.todo.send(Packet {
+            id: id,
+            uid: uid as u32,
+            gid: gid as u32,
+            a: a,
+            b: b,
+            c: c,
+            d: d
+        });
+
+        id
+    }
+
+    /// Block until the server answers the request `id`
+    fn wait(&self, id: u64) -> Result<usize> {
+        Error::demux(self.done.receive(&id).0)
+    }
+
+    fn call(inner: &Weak<SchemeInner>, a: usize, b: usize, c: usize, d: usize) -> Result<usize> {
+        if let Some(scheme) = SchemeInner::upgrade(inner) {
+            let id = scheme.send(a, b, c, d);
+            scheme.wait(id)
         } else {
-            Err(Error::new(EBADF))
+            Err(Error::new(ENODEV))
         }
     }
 }
 
 impl Drop for SchemeInner {
     fn drop(&mut self) {
-        ::env().schemes.lock().retain(|scheme| scheme.scheme() != self.name);
+        let mut scheme_counts = ::env().scheme_counts.lock();
+        let remove = if let Some(mut count) = scheme_counts.get_mut(&self.uid) {
+            *count -= 1;
+            *count == 0
+        } else {
+            false
+        };
+        if remove {
+            scheme_counts.remove(&self.uid);
+        }
     }
 }
 
@@ -80,147 +284,66 @@ impl SchemeResource {
     fn call(&self, a: usize, b: usize, c: usize, d: usize) -> Result<usize> {
         SchemeInner::call(&self.inner, a, b, c, d)
     }
+
+    /// Call the server with `len` bytes of the current context at `address` granted to it, passing
+    /// the address in the server as `c` and `len` as `d`. A buffer the server writes to must be
+    /// `writeable` by the caller, other buffers are mapped read-only into the server
+    fn call_buffer(&self, a: usize, address: usize, len: usize, writeable: bool) -> Result<usize> {
+        let physical_address = {
+            // Validating a writeable buffer copies memory shared after fork, which takes the
+            // contexts to write
+            if writeable {
+                let contexts = ::env().contexts.write();
+                let current = try!(contexts.current());
+                try!(current.validate(address, len, true).or(Err(Error::new(EFAULT))));
+            }
+            let contexts = ::env().contexts.read();
+            let current = try!(contexts.current());
+            try!(current.translate(address, len).or(Err(Error::new(EFAULT))))
+        };
+
+        let server = match SchemeInner::upgrade(&self.inner) {
+            Some(scheme) => scheme.pid,
+            None => return Err(Error::new(ENODEV)),
+        };
+        let virtual_address = try!(::env().grants.lock().grant(server, physical_address, len, writeable));
+
+        let result = self.call(a, self.file_id, virtual_address, len);
+
+        ::env().grants.lock().release(server, virtual_address);
+
+        result
+    }
 }
 
 impl Resource for SchemeResource {
-    /// Duplicate the resource
+    /// Duplicate the resource, the server returns the ID of the copy
     fn dup(&self) -> Result<Box<Resource>> {
-        Err(Error::new(EBADF))
+        let file_id = try!(self.call(SYS_DUP, self.file_id, 0, 0));
+        Ok(box SchemeResource {
+            inner: self.inner.clone(),
+            file_id: file_id,
+        })
     }
 
     /// Return the url of this resource
     fn path(&self, buf: &mut [u8]) -> Result <usize> {
-        let contexts = ::env().contexts.lock();
-        let current = try!(contexts.current());
-        if let Ok(physical_address) = current.translate(buf.as_mut_ptr() as usize, buf.len()) {
-            let offset = physical_address % 4096;
-
-            let mut virtual_address = 0;
-            let virtual_size = (buf.len() + offset + 4095)/4096 * 4096;
-            if let Some(scheme) = self.inner.upgrade() {
-                unsafe {
-                    virtual_address = (*scheme.context).next_mem();
-                    (*(*scheme.context).memory.get()).push(ContextMemory {
-                        physical_address: physical_address - offset,
-                        virtual_address: virtual_address,
-                        virtual_size: virtual_size,
-                        writeable: true,
-                        allocated: false,
-                    });
-                }
-            }
-
-            if virtual_address > 0 {
-                let result = self.call(SYS_FPATH, self.file_id, virtual_address + offset, buf.len());
-
-                //debugln!("Read {:X} mapped from {:X} to {:X} offset {} length {} size {} result {:?}", physical_address, buf.as_ptr() as usize, virtual_address + offset, offset, buf.len(), virtual_size, result);
-
-                if let Some(scheme) = self.inner.upgrade() {
-                    unsafe {
-                        if let Ok(mut mem) = (*scheme.context).get_mem_mut(virtual_address) {
-                            mem.virtual_size = 0;
-                        }
-                        (*scheme.context).clean_mem();
-                    }
-                }
+        self.call_buffer(SYS_FPATH, buf.as_mut_ptr() as usize, buf.len(), true)
+    }
 
-                result
-            } else {
-                Err(Error::new(EBADF))
-            }
-        } else {
-            Err(Error::new(EFAULT))
-        }
+    /// Read directory entries, the server returns the number it wrote
+    fn getdents(&mut self, buf: &mut [Dirent]) -> Result<usize> {
+        self.call_buffer(SYS_GETDENTS, buf.as_mut_ptr() as usize, buf.len() * size_of::<Dirent>(), true)
     }
 
     /// Read data to buffer
     fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
-        let contexts = ::env().contexts.lock();
-        let current = try!(contexts.current());
-        if let Ok(physical_address) = current.translate(buf.as_mut_ptr() as usize, buf.len()) {
-            let offset = physical_address % 4096;
-
-            let mut virtual_address = 0;
-            let virtual_size = (buf.len() + offset + 4095)/4096 * 4096;
-            if let Some(scheme) = self.inner.upgrade() {
-                unsafe {
-                    virtual_address = (*scheme.context).next_mem();
-                    (*(*scheme.context).memory.get()).push(ContextMemory {
-                        physical_address: physical_address - offset,
-                        virtual_address: virtual_address,
-                        virtual_size: virtual_size,
-                        writeable: true,
-                        allocated: false,
-                    });
-                }
-            }
-
-            if virtual_address > 0 {
-                let result = self.call(SYS_READ, self.file_id, virtual_address + offset, buf.len());
-
-                //debugln!("Read {:X} mapped from {:X} to {:X} offset {} length {} size {} result {:?}", physical_address, buf.as_ptr() as usize, virtual_address + offset, offset, buf.len(), virtual_size, result);
-
-                if let Some(scheme) = self.inner.upgrade() {
-                    unsafe {
-                        if let Ok(mut mem) = (*scheme.context).get_mem_mut(virtual_address) {
-                            mem.virtual_size = 0;
-                        }
-                        (*scheme.context).clean_mem();
-                    }
-                }
-
-                result
-            } else {
-                Err(Error::new(EBADF))
-            }
-        } else {
-            Err(Error::new(EFAULT))
-        }
+        self.call_buffer(SYS_READ, buf.as_mut_ptr() as usize, buf.len(), true)
     }
 
     /// Write to resource
     fn write(&mut self, buf: &[u8]) -> Result<usize> {
-        let contexts = ::env().contexts.lock();
-        let current = try!(contexts.current());
-        if let Ok(physical_address) = current.translate(buf.as_ptr() as usize, buf.len()) {
-            let offset = physical_address % 4096;
-
-            let mut virtual_address = 0;
-            let virtual_size = (buf.len() + offset + 4095)/4096 * 4096;
-            if let Some(scheme) = self.inner.upgrade() {
-                unsafe {
-                    virtual_address = (*scheme.context).next_mem();
-                    (*(*scheme.context).memory.get()).push(ContextMemory {
-                        physical_address: physical_address - offset,
-                        virtual_address: virtual_address,
-                        virtual_size: virtual_size,
-                        writeable: true,
-                        allocated: false,
-                    });
-                }
-            }
-
-            if virtual_address > 0 {
-                let result = self.call(SYS_WRITE, self.file_id, virtual_address + offset, buf.len());
-
-                //debugln!("Write {:X} mapped from {:X} to {:X} offset {} length {} size {} result {:?}", physical_address, buf.as_ptr() as usize, virtual_address + offset, offset, buf.len(), virtual_size, result);
-
-                if let Some(scheme) = self.inner.upgrade() {
-                    unsafe {
-                        if let Ok(mut mem) = (*scheme.context).get_mem_mut(virtual_address) {
-                            mem.virtual_size = 0;
-                        }
-                        (*scheme.context).clean_mem();
-                    }
-                }
-
-                result
-            } else {
-                Err(Error::new(EBADF))
-            }
-        } else {
-            Err(Error::new(EFAULT))
-        }
+        self.call_buffer(SYS_WRITE, buf.as_ptr() as usize, buf.len(), false)
     }
 
     /// Seek
@@ -239,9 +362,19 @@ impl Resource for SchemeResource {
         self.call(SYS_FSYNC, self.file_id, 0, 0).and(Ok(()))
     }
 
+    /// Tell the server the flags changed, it returns `EAGAIN` itself for a non-blocking file
+    fn fcntl(&mut self, cmd: usize, arg: usize) -> Result<usize> {
+        self.call(SYS_FCNTL, self.file_id, cmd, arg)
+    }
+
     fn truncate(&mut self, len: usize) -> Result<()> {
         self.call(SYS_FTRUNCATE, self.file_id, len, 0).and(Ok(()))
     }
+
+    /// Stat the resource, the server fills in the `Stat`
+    fn stat(&self, stat: &mut Stat) -> Result<usize> {
+        SchemeInner::call_stat(&self.inner, SYS_FSTAT, self.file_id, stat)
+    }
 }
 
 impl Drop for SchemeResource {
@@ -257,6 +390,7 @@ pub struct SchemeServerResource {
 impl Resource for SchemeServerResource {
     /// Duplicate the resource
     fn dup(&self) -> Result<Box<Resource>> {
+        self.inner.servers.set(self.inner.servers.get() + 1);
         Ok(box SchemeServerResource {
             inner: self.inner.clone()
         })
@@ -313,7 +447,14 @@ impl Resource for SchemeServerResource {
 
             while i <= buf.len() - size_of::<Packet>() {
                 let packet = unsafe { & *(buf.as_ptr().offset(i as isize) as *const Packet) };
-                self.inner.done.send(packet.id, (packet.a, packet.b, packet.c, packet.d));
+                let id = packet.id;
+                // Only requests in flight are answered, once, so a duplicate or stray answer is
+                // dropped instead of being received by another request
+                if self.inner.pending.lock().remove(&id).is_some() {
+                    self.inner.done.send(id, (packet.a, packet.b, packet.c, packet.d));
+                } else {
+                    klogln!(LogLevel::Debug, "scheme: {}: answer for request {} dropped, it is not pending", self.inner.name, id);
+                }
                 i += size_of::<Packet>();
             }
 
@@ -328,9 +469,19 @@ impl Resource for SchemeServerResource {
         Err(Error::new(ESPIPE))
     }
 
-    /// Sync the resource
+    /// Revoke the grants to the server, so that no caller memory stays mapped into it
     fn sync(&mut self) -> Result<()> {
-        Err(Error::new(EINVAL))
+        ::env().grants.lock().revoke_server(self.inner.pid);
+        Ok(())
+    }
+
+    /// Readable when there are requests to read, answers can always be written
+    fn poll(&self) -> Result<usize> {
+        if self.inner.todo.inner.lock().is_empty() {
+            Ok(POLLOUT)
+        } else {
+            Ok(POLLIN | POLLOUT)
+        }
     }
 
     fn truncate(&mut self, _len: usize) -> Result<()> {
@@ -338,6 +489,16 @@ impl Resource for SchemeServerResource {
     }
 }
 
+impl Drop for SchemeServerResource {
+    fn drop(&mut self) {
+        let servers = self.inner.servers.get() - 1;
+        self.inner.servers.set(servers);
+        if servers == 0 {
+            self.inner.close();
+        }
+    }
+}
+
 /// Scheme has to be wrapped
 pub struct Scheme {
     name: String,
@@ -345,21 +506,25 @@ pub struct Scheme {
 }
 
 impl Scheme {
-    pub fn new(name: &str) -> Result<(Box<Scheme>, Box<Resource>)> {
-        let mut contexts = ::env().contexts.lock();
-        let mut current = try!(contexts.current_mut());
-        let server = box SchemeServerResource {
-            inner: Arc::new(SchemeInner::new(name, current.deref_mut()))
+    /// Register the scheme `name`, served by the current context, returning the resource the
+    /// server reads requests from. Fails with `EEXIST` if the name is taken
+    pub fn register(name: &str) -> Result<Box<Resource>> {
+        let server = {
+            let contexts = ::env().contexts.read();
+            let current = try!(contexts.current());
+            box SchemeServerResource {
+                inner: Arc::new(SchemeInner::new(name, current.uid, current.pid))
+            }
         };
         let scheme = box Scheme {
             name: name.to_owned(),
             inner: Arc::downgrade(&server.inner)
         };
-        Ok((scheme, server))
-    }
 
-    fn call(&self, a: usize, b: usize, c: usize, d: usize) -> Result<usize> {
-        SchemeInner::call(&self.inner, a, b, c, d)
+        try!(::env().schemes.insert(scheme));
+        server.inner.registered.set(true);
+
+        Ok(server)
     }
 }
 
@@ -373,156 +538,102 @@ impl KScheme for Scheme {
     }
 
     fn open(&mut self, url: Url, flags: usize) -> Result<Box<Resource>> {
-        let c_str = url.to_string() + "\0";
-
-        let physical_address = c_str.as_ptr() as usize;
-
-        let mut virtual_address = 0;
-        if let Some(scheme) = self.inner.upgrade() {
-            unsafe {
-                virtual_address = (*scheme.context).next_mem();
-                (*(*scheme.context).memory.get()).push(ContextMemory {
-                    physical_address: physical_address,
-                    virtual_address: virtual_address,
-                    virtual_size: c_str.len(),
-                    writeable: false,
-                    allocated: false,
-                });
-            }
-        }
-
-        if virtual_address > 0 {
-            let result = self.call(SYS_OPEN, virtual_address, flags, 0);
-
-            if let Some(scheme) = self.inner.upgrade() {
-                unsafe {
-                    if let Ok(mut mem) = (*scheme.context).get_mem_mut(virtual_address) {
-                        mem.virtual_size = 0;
-                    }
-                    (*scheme.context).clean_mem();
-                }
-            }
-
-            match result {
-                Ok(file_id) => Ok(box SchemeResource {
-                    inner: self.inner.clone(),
-                    file_id: file_id,
-                }),
-                Err(err) => Err(err)
-            }
-        } else {
-            Err(Error::new(ENOENT))
-        }
+        let file_id = try!(SchemeInner::call_path(&self.inner, SYS_OPEN, &url, flags));
+        Ok(box SchemeResource {
+            inner: self.inner.clone(),
+            file_id: file_id,
+        })
     }
 
     fn mkdir(&mut self, url: Url, flags: usize) -> Result<()> {
-        let c_str = url.to_string() + "\0";
-
-        let physical_address = c_str.as_ptr() as usize;
+        SchemeInner::call_path(&self.inner, SYS_MKDIR, &url, flags).and(Ok(()))
+    }
 
-        let mut virtual_address = 0;
-        if let Some(scheme) = self.inner.upgrade() {
-            unsafe {
-                virtual_address = (*scheme.context).next_mem();
-                (*(*scheme.context).memory.get()).push(ContextMemory {
-                    physical_address: physical_address,
-                    virtual_address: virtual_address,
-                    virtual_size: c_str.len(),
-                    writeable: false,
-                    allocated: false,
-                });
-            }
-        }
+    fn rmdir(&mut self, url: Url) -> Result<()> {
+        SchemeInner::call_path(&self.inner, SYS_RMDIR, &url, 0).and(Ok(()))
+    }
 
-        if virtual_address > 0 {
-            let result = self.call(SYS_MKDIR, virtual_address, flags, 0);
+    /// Rename, with `new` mapped into the server too, as `c`
+    fn rename(&mut self, old: Url, new: Url) -> Result<()> {
+        SchemeInner::call_paths(&self.inner, SYS_RENAME, &old, &(new.to_string() + "\0")).and(Ok(()))
+    }
 
-            if let Some(scheme) = self.inner.upgrade() {
-                unsafe {
-                    if let Ok(mut mem) = (*scheme.context).get_mem_mut(virtual_address) {
-                        mem.virtual_size = 0;
-                    }
-                    (*scheme.context).clean_mem();
-                }
-            }
+    /// Link, with `new` mapped into the server too, as `c`
+    fn link(&mut self, old: Url, new: Url) -> Result<()> {
+        SchemeInner::call_paths(&self.inner, SYS_LINK, &old, &(new.to_string() + "\0")).and(Ok(()))
+    }
 
-            result.and(Ok(()))
-        } else {
-            Err(Error::new(ENOENT))
-        }
+    /// Create a symbolic link, with `target` mapped into the server too, as `c`
+    fn symlink(&mut self, target: &str, url: Url) -> Result<()> {
+        SchemeInner::call_paths(&self.inner, SYS_SYMLINK, &url, &(target.to_string() + "\0")).and(Ok(()))
     }
 
-    fn rmdir(&mut self, url: Url) -> Result<()> {
-        let c_str = url.to_string() + "\0";
+    /// Read a symbolic link, with `url` mapped into the server as `b`, and a buffer of the kernel
+    /// it writes to as `c`
+    fn readlink(&mut self, url: Url, buf: &mut [u8]) -> Result<usize> {
+        let buffer = unsafe { memory::alloc(buf.len()) };
+        if buffer == 0 {
+            return Err(Error::new(ENOMEM));
+        }
 
-        let physical_address = c_str.as_ptr() as usize;
+        let result = match SchemeInner::upgrade(&self.inner).map(|scheme| scheme.map(buffer, buf.len(), true)) {
+            Some(Ok(virtual_address)) => {
+                let c_str = url.to_string() + "\0";
+                let result = match SchemeInner::upgrade(&self.inner).map(|scheme| scheme.map(c_str.as_ptr() as usize, c_str.len(), false)) {
+                    Some(Ok(path_address)) => {
+                        let result = SchemeInner::call(&self.inner, SYS_READLINK, path_address, virtual_address, buf.len());
 
-        let mut virtual_address = 0;
-        if let Some(scheme) = self.inner.upgrade() {
-            unsafe {
-                virtual_address = (*scheme.context).next_mem();
-                (*(*scheme.context).memory.get()).push(ContextMemory {
-                    physical_address: physical_address,
-                    virtual_address: virtual_address,
-                    virtual_size: c_str.len(),
-                    writeable: false,
-                    allocated: false,
-                });
-            }
-        }
+                        if let Some(scheme) = self.inner.upgrade() {
+                            scheme.unmap(path_address);
+                        }
 
-        if virtual_address > 0 {
-            let result = self.call(SYS_RMDIR, virtual_address, 0, 0);
+                        result
+                    },
+                    Some(Err(err)) => Err(err),
+                    None => Err(Error::new(ENODEV)),
+                };
 
-            if let Some(scheme) = self.inner.upgrade() {
-                unsafe {
-                    if let Ok(mut mem) = (*scheme.context).get_mem_mut(virtual_address) {
-                        mem.virtual_size = 0;
-                    }
-                    (*scheme.context).clean_mem();
+                if let Some(scheme) = self.inner.upgrade() {
+                    scheme.unmap(virtual_address);
                 }
-            }
 
-            result.and(Ok(()))
-        } else {
-            Err(Error::new(ENOENT))
-        }
+                result
+            },
+            Some(Err(err)) => Err(err),
+            None => Err(Error::new(ENODEV)),
+        };
+
+        let result = match result {
+            Ok(count) if count <= buf.len() => {
+                unsafe { ptr::copy_nonoverlapping(buffer as *const u8, buf.as_mut_ptr(), count) };
+                Ok(count)
+            },
+            Ok(_) => Err(Error::new(EIO)),
+            Err(err) => Err(err),
+        };
+        unsafe { memory::unalloc(buffer) };
+
+        result
     }
 
-    fn unlink(&mut self, url: Url) -> Result<()> {
+    fn stat(&mut self, url: Url, stat: &mut Stat) -> Result<()> {
         let c_str = url.to_string() + "\0";
 
-        let physical_address = c_str.as_ptr() as usize;
+        let virtual_address = match SchemeInner::upgrade(&self.inner) {
+            Some(scheme) => try!(scheme.map(c_str.as_ptr() as usize, c_str.len(), false)),
+            None => return Err(Error::new(ENODEV)),
+        };
+
+        let result = SchemeInner::call_stat(&self.inner, SYS_STAT, virtual_address, stat);
 
-        let mut virtual_address = 0;
         if let Some(scheme) = self.inner.upgrade() {
-            unsafe {
-                virtual_address = (*scheme.context).next_mem();
-                (*(*scheme.context).memory.get()).push(ContextMemory {
-                    physical_address: physical_address,
-                    virtual_address: virtual_address,
-                    virtual_size: c_str.len(),
-                    writeable: false,
-                    allocated: false,
-                });
-            }
+            scheme.unmap(virtual_address);
         }
 
-        if virtual_address > 0 {
-            let result = self.call(SYS_UNLINK, virtual_address, 0, 0);
-
-            if let Some(scheme) = self.inner.upgrade() {
-                unsafe {
-                    if let Ok(mut mem) = (*scheme.context).get_mem_mut(virtual_address) {
-                        mem.virtual_size = 0;
-                    }
-                    (*scheme.context).clean_mem();
-                }
-            }
+        result.and(Ok(()))
+    }
 
-            result.and(Ok(()))
-        } else {
-            Err(Error::new(ENOENT))
-        }
+    fn unlink(&mut self, url: Url) -> Result<()> {
+        SchemeInner::call_path(&self.inner, SYS_UNLINK, &url, 0).and(Ok(()))
     }
 }
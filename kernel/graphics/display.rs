@@ -58,6 +58,22 @@ pub unsafe fn vbe_init(){
     }
 }
 
+/// Use a linear framebuffer set up by the boot loader, with 32 bits per pixel and no padding
+pub unsafe fn vbe_framebuffer(physbaseptr: u32, width: u16, height: u16) {
+    let mut mode_info = VBEModeInfo::default();
+    mode_info.bitsperpixel = 32;
+    mode_info.bytesperscanline = width * 4;
+    mode_info.xresolution = width;
+    mode_info.yresolution = height;
+    mode_info.physbaseptr = physbaseptr;
+    VBEMODEINFO = Some(mode_info);
+}
+
+/// The address, width and height of the linear framebuffer used by the display, if any
+pub fn vbe_framebuffer_info() -> Option<(u32, u16, u16)> {
+    unsafe { VBEMODEINFO }.map(|mode_info| (mode_info.physbaseptr, mode_info.xresolution, mode_info.yresolution))
+}
+
 /// A display
 pub struct Display {
     pub offscreen: *mut u32,
@@ -103,6 +119,71 @@ impl Display {
         }
     }
 
+    /// Scroll the `h` rows starting at `y` up by `rows`, or down if `rows` is negative, filling the
+    /// rows that are uncovered with `color`
+    pub fn scroll_area(&self, y: usize, h: usize, rows: isize, color: Color) {
+        let end_y = cmp::min(self.height, y + h);
+        if y >= end_y {
+            return;
+        }
+        let h = end_y - y;
+
+        let distance = rows.abs() as usize;
+        if distance >= h {
+            self.rect_exact(0, y, self.width, h, color);
+            return;
+        }
+
+        if rows > 0 {
+            for row in y..end_y - distance {
+                unsafe {
+                    fast_copy(self.offscreen.offset((row * self.width) as isize),
+                              self.offscreen.offset(((row + distance) * self.width) as isize),
+                              self.width);
+                }
+            }
+            self.rect_exact(0, end_y - distance, self.width, distance, color);
+        } else if rows < 0 {
+            // Copy backwards, so that rows are not overwritten before they are moved
+            for row in (y + distance..end_y).rev() {
+                unsafe {
+                    fast_copy(self.offscreen.offset((row * self.width) as isize),
+                              self.offscreen.offset(((row - distance) * self.width) as isize),
+                              self.width);
+                }
+            }
+            self.rect_exact(0, y, self.width, distance, color);
+        }
+    }
+
+    /// Fill a rectangle, clipped to the display
+    pub fn rect_exact(&self, x: usize, y: usize, w: usize, h: usize, color: Color) {
+        let start_x = cmp::min(self.width, x);
+        let end_x = cmp::min(self.width, x + w);
+        let end_y = cmp::min(self.height, y + h);
+
+        for y in cmp::min(self.height, y)..end_y {
+            unsafe {
+                fast_set(self.offscreen.offset((y * self.width + start_x) as isize), color.data, end_x - start_x);
+            }
+        }
+    }
+
+    /// Invert the colors of a rectangle, clipped to the display
+    pub fn invert(&self, x: usize, y: usize, w: usize, h: usize) {
+        let end_x = cmp::min(self.width, x + w);
+        let end_y = cmp::min(self.height, y + h);
+
+        for y in cmp::min(self.height, y)..end_y {
+            for x in cmp::min(self.width, x)..end_x {
+                unsafe {
+                    let pixel = self.offscreen.offset((y * self.width + x) as isize);
+                    *pixel ^= 0xFFFFFF;
+                }
+            }
+        }
+    }
+
     /// Flip the display
     pub fn flip(&self) {
         unsafe {
@@ -36,6 +36,14 @@ kernel_file:
 .length equ kernel_file.end - kernel_file
 .length_sectors equ .length / 512
 
+initfs_file:
+  incbin "initfs.bin"
+.size equ $ - initfs_file
+  align 512, db 0
+.end:
+.length equ initfs_file.end - initfs_file
+.length_sectors equ .length / 512
+
 fs_root_node_list:
 %macro file 2+
     fs_node.%1:
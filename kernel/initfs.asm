@@ -0,0 +1,24 @@
+; The initfs archive, loaded by the boot loader next to the kernel and read by `initfs:`
+;
+; It starts with a signature, followed by the files, each the length of its name and of its data
+; as 32 bit little endian numbers, then the name and the data. A file with an empty name ends it.
+
+db "RDXINITF"
+
+%macro file 2+
+    dd initfs_name.%1.end - initfs_name.%1
+    dd initfs_data.%1.end - initfs_data.%1
+initfs_name.%1:
+    db %2
+.end:
+initfs_data.%1:
+    incbin %2
+.end:
+%endmacro
+
+%include "initfs.gen"
+
+%unmacro file 2+
+
+dd 0
+dd 0
@@ -13,6 +13,7 @@
 #![feature(fnbox)]
 #![feature(fundamental)]
 #![feature(lang_items)]
+#![feature(stmt_expr_attributes)]
 #![feature(unboxed_closures)]
 #![feature(unsafe_no_drop_flag)]
 #![feature(unwind_attributes)]
@@ -37,39 +38,67 @@ use acpi::Acpi;
 
 use alloc::boxed::Box;
 
-use arch::context::{context_switch, Context};
+use arch::apic;
+use arch::context::{context_preempt, context_switch, fpu_trap, Context};
+use arch::cpu;
+use arch::fault;
+use arch::gdb::{self, GdbStop};
+use arch::interrupt;
 use arch::memory;
+use arch::multiboot;
 use arch::paging::Page;
+use arch::percpu;
+use arch::pstore;
 use arch::regs::Regs;
+use arch::smp;
 use arch::tss::Tss;
+use arch::usercopy;
 
 use collections::string::ToString;
 
-use core::{ptr, mem, usize};
+use core::{mem, usize};
 use core::slice::SliceExt;
+use core::sync::atomic::Ordering;
 
-use common::time::Duration;
+use common::cmdline;
+use common::random;
+use common::trace::TracePoint;
 
 use drivers::pci;
-use drivers::io::{Io, Pio};
+use drivers::registry;
 use drivers::ps2::*;
-use drivers::rtc::*;
 use drivers::serial::*;
 
 use env::Environment;
 
+use fs::Url;
+
 use graphics::display;
 
+use schemes::audit::*;
+use schemes::chan::ChanScheme;
 use schemes::context::*;
 use schemes::debug::*;
 use schemes::display::*;
+use schemes::fuzz::*;
 use schemes::initfs::*;
 use schemes::interrupt::*;
+use schemes::keymap::*;
+use schemes::klog::*;
 use schemes::memory::*;
-use schemes::test::*;
+use schemes::perf::*;
+use schemes::pipe::PipeScheme;
+use schemes::proc::*;
+use schemes::pty::*;
+use schemes::rand::*;
+use schemes::shm::ShmScheme;
+use schemes::sys::*;
+use schemes::test::TestScheme;
+use schemes::time::*;
+use schemes::tmp::TmpScheme;
 
 use syscall::execute::execute;
-use syscall::{do_sys_chdir, do_sys_exit, do_sys_open, syscall_handle};
+use syscall::{do_sys_chdir, do_sys_open, handle_signals, signal_exit, syscall_handle};
 
 pub use system::externs::*;
 
@@ -105,12 +134,13 @@ pub mod arch;
 ///
 /// This module contains `ac97` and `intelhda` audio drivers. These are likely to be moved to
 /// userspace in the future.
+#[cfg(feature = "audio")]
 pub mod audio;
 /// Disk drivers.
 ///
-/// Drivers for reading and writing disks. Currently includes drivers for following interfaces:
-/// AHCI (Advanced Host Controller Interface), IDE (Integrated Drive Electronics), and ATA-1 (AT
-/// Attachment Interface for Disk Drives).
+/// Block devices, behind the `BlockDevice` trait. Currently includes drivers for following
+/// interfaces: AHCI (Advanced Host Controller Interface), IDE (Integrated Drive Electronics), and
+/// ATA-1 (AT Attachment Interface for Disk Drives), as well as RAM disks and loop devices.
 pub mod disk;
 /// Miscellaneous drivers.
 ///
@@ -136,6 +166,7 @@ pub mod graphics;
 ///
 /// This module contains drivers (e.g, intel8254x and rtl8139), primitives, schemes, and data
 /// structures related to networking, providing Redox's networking stack.
+#[cfg(feature = "network")]
 pub mod network;
 /// Kernel panic handling.
 ///
@@ -165,6 +196,7 @@ pub mod syscall;
 ///
 /// USB (Universal Serial Bus) is a standardized serial bus interface, used for many peripherals.
 /// This modules contains drivers and other tools for USB.
+#[cfg(feature = "usb")]
 pub mod usb;
 
 /// The TTS pointer.
@@ -190,35 +222,19 @@ pub fn env() -> &'static Environment {
     }
 }
 
-/// The PIT (programmable interval timer) duration.
-///
-/// This duration defines the PIT interval, which is added to the monotonic clock and the real time
-/// clock, when interrupt 0x20 is received.
-static PIT_DURATION: Duration = Duration {
-    secs: 0,
-    nanos: 4500572,
-};
-
 /// The idle loop.
 ///
-/// This loop runs while the system is idle.
-fn idle_loop() {
+/// This loop runs while the system is idle, as the idle context of each processor.
+fn idle_loop() -> ! {
     loop {
-        unsafe { asm!("cli" : : : : "intel", "volatile"); }
+        unsafe { cpu::interrupts_disable(); }
 
-        let mut halt = true;
-
-        for context in env().contexts.lock().iter().skip(1) {
-            if !context.blocked {
-                halt = false;
-                break;
-            }
-        }
+        let halt = ! env().contexts.write().has_work();
 
         if halt {
-            unsafe { asm!("sti ; hlt" : : : : "intel", "volatile"); }
+            unsafe { cpu::halt(); }
         } else {
-            unsafe { asm!("sti ; nop" : : : : "intel", "volatile"); }
+            unsafe { cpu::interrupts_poll(); }
             unsafe { context_switch(); }
         }
     }
@@ -254,7 +270,10 @@ static BSS_TEST_NONZERO: usize = !0;
 /// on.
 ///
 /// Note that this will not start the even loop.
-unsafe fn init(tss_data: usize) {
+///
+/// `boot_magic` and `boot_info` are passed by a Multiboot2 loader, and are 0 on the legacy boot
+/// path.
+unsafe fn init(tss_data: usize, boot_magic: usize, boot_info: usize) {
 
     // Test
     assume!(true);
@@ -273,12 +292,27 @@ unsafe fn init(tss_data: usize) {
         debug_assert_eq!(BSS_TEST_NONZERO, usize::MAX);
     }
 
+    // Read the boot information of a Multiboot2 loader, before the memory map is used
+    let from_multiboot = multiboot::init(boot_magic, boot_info);
+    cmdline::init(multiboot::cmdline());
+
     // Setup paging, this allows for memory allocation
     Page::init();
     memory::cluster_init();
+    multiboot::reserve();
+    pstore::init();
+
+    percpu::init(tss_data);
+
+    // Gather entropy before any driver loads
+    random::entropy_init();
+
+    usercopy::init();
 
     // Get the VBE information before unmapping the first megabyte
-    display::vbe_init();
+    if ! from_multiboot {
+        display::vbe_init();
+    }
 
     // Unmap first page (TODO: Unmap more)
     {
@@ -324,49 +358,87 @@ unsafe fn init(tss_data: usize) {
 
     match ENV_PTR {
         Some(ref mut env) => {
-            env.contexts.lock().push(Context::root());
+            env.contexts.write().push_idle(Context::root());
 
-            env.console.lock().draw = true;
+            for console in env.vts.lock().consoles.iter_mut() {
+                console.draw = true;
+            }
 
             debugln!("Redox {} bits", mem::size_of::<usize>() * 8);
 
             if let Some(acpi) = Acpi::new() {
-                env.schemes.lock().push(acpi);
+                if let Some(madt) = acpi.madt() {
+                    smp::init(madt);
+                }
+                env.schemes.push(acpi);
             }
 
-            *(env.clock_realtime.lock()) = Rtc::new().time();
+            env::vdso::init();
+            env.clock.lock().init();
 
-            env.schemes.lock().push(Ps2::new());
-            env.schemes.lock().push(Serial::new(0x3F8, 0x4));
+            env.add_driver(Ps2::new(), &[0x1, 0xC]);
+            env.add_driver(Serial::new(0x3F8, 0x4), &[0x4]);
 
             pci::pci_init(env);
 
-            env.schemes.lock().push(DebugScheme::new());
-            env.schemes.lock().push(InitFsScheme::new());
-            env.schemes.lock().push(box ContextScheme);
-            env.schemes.lock().push(box DisplayScheme);
-            env.schemes.lock().push(box InterruptScheme);
-            env.schemes.lock().push(box MemoryScheme);
-            env.schemes.lock().push(box TestScheme);
+            env.schemes.push(box AuditScheme);
+            env.schemes.push(ChanScheme::new());
+            env.schemes.push(DebugScheme::new());
+            env.schemes.push(InitFsScheme::new());
+            env.schemes.push(box ContextScheme);
+            env.schemes.push(DisplayScheme::new());
+            env.schemes.push(box FuzzScheme);
+            env.schemes.push(box InterruptScheme);
+            env.schemes.push(box KeymapScheme);
+            env.schemes.push(box KlogScheme);
+            env.schemes.push(box MemoryScheme);
+            env.schemes.push(box PerfScheme);
+            env.schemes.push(PipeScheme::new());
+            env.schemes.push(box ProcScheme);
+            env.schemes.push(PtyScheme::new());
+            env.schemes.push(box RandScheme);
+            env.schemes.push(ShmScheme::new());
+            env.schemes.push(box SysScheme);
+            env.schemes.push(box TestScheme);
+            env.schemes.push(box TimeScheme);
+            env.schemes.push(TmpScheme::new());
+
+            registry::init(env);
+
+            env.contexts.write().enabled = true;
+
+            Context::kspawn("kirqd", move || {
+                ::env().irqs.run();
+            });
 
-            env.contexts.lock().enabled = true;
+            let init_pid = Context::kspawn_pinned("kinit", move || {
+                let config = cmdline::config();
+                if config.test {
+                    schemes::test::run_boot();
+                }
 
-            Context::spawn("kinit".to_string(),
-            box move || {
                 {
-                    let wd_c = "file:/\0";
+                    let wd_c = format!("{}\0", config.root);
                     do_sys_chdir(wd_c.as_ptr()).unwrap();
 
-                    let stdio_c = "debug:\0";
+                    let stdio_c = format!("{}\0", config.console);
                     do_sys_open(stdio_c.as_ptr(), 0).unwrap();
                     do_sys_open(stdio_c.as_ptr(), 0).unwrap();
                     do_sys_open(stdio_c.as_ptr(), 0).unwrap();
                 }
 
-                if let Err(err) = execute(vec!["init".to_string()]) {
+                // An init in the initfs archive runs first, and starts what the root file system
+                // needs itself
+                let init = match Url::from_str(config.rdinit).and_then(|url| url.open()) {
+                    Ok(_) => config.rdinit,
+                    Err(_) => config.init,
+                };
+
+                if let Err(err) = execute(vec![init.to_string()], None) {
                     debugln!("INIT: Failed to execute: {}", err);
                 }
             });
+            env.contexts.write().init_pid = init_pid;
         },
         None => unreachable!(),
     }
@@ -378,58 +450,27 @@ unsafe fn init(tss_data: usize) {
 /// Interrupt and exception handling.
 pub extern "cdecl" fn kernel(interrupt: usize, mut regs: &mut Regs) {
     macro_rules! exception_inner {
-        ($name:expr) => ({
-            {
-                let contexts = ::env().contexts.lock();
-                if let Ok(context) = contexts.current() {
-                    debugln!("PID {}: {}", context.pid, context.name);
-                }
+        ($name:expr, $error:expr) => ({
+            // A fault while copying user memory fails the copy instead of the kernel
+            if ! fault::user_mode(regs) {
+                unsafe { usercopy::recover() };
             }
 
-            debugln!("  INT {:X}: {}", interrupt, $name);
-            debugln!("    CS:  {:08X}    IP:  {:08X}    FLG: {:08X}", regs.cs, regs.ip, regs.flags);
-            debugln!("    SS:  {:08X}    SP:  {:08X}    BP:  {:08X}", regs.ss, regs.sp, regs.bp);
-            debugln!("    AX:  {:08X}    BX:  {:08X}    CX:  {:08X}    DX:  {:08X}", regs.ax, regs.bx, regs.cx, regs.dx);
-            debugln!("    DI:  {:08X}    SI:  {:08X}", regs.di, regs.di);
+            unsafe { fault::report(interrupt, $name, regs, $error) };
 
-            let cr0: usize;
-            let cr2: usize;
-            let cr3: usize;
-            let cr4: usize;
-            unsafe {
-                asm!("mov $0, cr0" : "=r"(cr0) : : : "intel", "volatile");
-                asm!("mov $0, cr2" : "=r"(cr2) : : : "intel", "volatile");
-                asm!("mov $0, cr3" : "=r"(cr3) : : : "intel", "volatile");
-                asm!("mov $0, cr4" : "=r"(cr4) : : : "intel", "volatile");
+            if ! fault::user_mode(regs) {
+                panic!("{} in kernel mode", $name);
             }
-            debugln!("    CR0: {:08X}    CR2: {:08X}    CR3: {:08X}    CR4: {:08X}", cr0, cr2, cr3, cr4);
 
-            let mut fsw: usize = 0;
-            let mut fcw: usize = 0;
-            unsafe {
-                asm!("fnstsw $0" : "=*m"(&mut fsw) : : : "intel", "volatile");
-                asm!("fnstcw $0" : "=*m"(&mut fcw) : : : "intel", "volatile");
-            }
-            debugln!("    FSW: {:08X}    FCW: {:08X}", fsw, fcw);
-
-            let sp = regs.sp as *const u32;
-            for y in -15..16 {
-                debug!("    {:>3}:", y * 8 * 4);
-                for x in 0..8 {
-                    debug!(" {:08X}", unsafe { ptr::read(sp.offset(-(x + y * 8))) });
-                }
-                debug!("\n");
-            }
+            // A handler could not return to the faulting instruction, so the default action of
+            // the signal kills the context, with a core dump
+            signal_exit(fault::signal(interrupt), regs);
         })
     };
 
     macro_rules! exception {
         ($name:expr) => ({
-            exception_inner!($name);
-
-            loop {
-                do_sys_exit(usize::MAX);
-            }
+            exception_inner!($name, None);
         })
     };
 
@@ -443,36 +484,56 @@ pub extern "cdecl" fn kernel(interrupt: usize, mut regs: &mut Regs) {
             regs.ss = 0;
             //regs.ss = regs.error;
 
-            exception_inner!($name);
-            debugln!("    ERR: {:08X}", error);
-
-            loop {
-                do_sys_exit(usize::MAX);
-            }
+            exception_inner!($name, Some(error));
         })
     };
 
+    // Local APIC interrupts arrive on the application processors, so they are handled before
+    // anything only the bootstrap processor does. The timer ends their time slices
+    if interrupt == apic::SPURIOUS_VECTOR {
+        return;
+    }
+    if interrupt == apic::TIMER_VECTOR {
+        unsafe {
+            smp::local_apic().eoi();
+            context_preempt();
+        }
+        return;
+    }
+
+    // Load the FPU registers before anything else can use them, the kernel may use SSE on x86_64
+    if interrupt == 0x7 {
+        unsafe { fpu_trap() };
+        return;
+    }
+
     //Do not catch init interrupt
     if interrupt < 0xFF {
-        env().interrupts.lock()[interrupt as usize] += 1;
+        if let Some(percpu) = percpu::get() {
+            percpu.stats.interrupts[interrupt as usize] += 1;
+        }
+    }
+
+    if let Some(irq) = interrupt::irq(interrupt) {
+        tracepoint!(TracePoint::Irq, irq, 0);
     }
 
     match interrupt {
         0x20 => {
-            {
-                let mut clock_monotonic = env().clock_monotonic.lock();
-                *clock_monotonic = *clock_monotonic + PIT_DURATION;
-            }
-            {
-                let mut clock_realtime = env().clock_realtime.lock();
-                *clock_realtime = *clock_realtime + PIT_DURATION;
-            }
+            env().clock.lock().tick();
 
-            if let Ok(mut current) = env().contexts.lock().current_mut() {
+            if let Ok(mut current) = env().contexts.write().current_mut() {
                 current.time += 1;
             }
 
-            unsafe { context_switch(); }
+            env().perf.lock().on_tick();
+            env().contexts.write().check_timers();
+
+            if gdb::GDB_REQUEST.load(Ordering::SeqCst) {
+                unsafe { gdb::enter(regs, GdbStop::Interrupt) };
+            }
+
+            unsafe { context_preempt(); }
         }
         i @ 0x21 ... 0x2F => {
             env().on_irq(i as u8 - 0x20);
@@ -480,25 +541,37 @@ pub extern "cdecl" fn kernel(interrupt: usize, mut regs: &mut Regs) {
         0x80 => syscall_handle(regs),
         0xFF => {
             unsafe {
-                init(regs.ax);
+                init(regs.ax, regs.cx, regs.bx);
                 idle_loop();
             }
         },
         0x0 => exception!("Divide by zero exception"),
-        0x1 => exception!("Debug exception"),
+        0x1 => if gdb::handles(regs) {
+            unsafe { gdb::enter(regs, GdbStop::Step) };
+        } else {
+            exception!("Debug exception");
+        },
         0x2 => exception!("Non-maskable interrupt"),
-        0x3 => exception!("Breakpoint exception"),
+        0x3 => if gdb::handles(regs) {
+            unsafe { gdb::enter(regs, GdbStop::Breakpoint) };
+        } else {
+            exception!("Breakpoint exception");
+        },
         0x4 => exception!("Overflow exception"),
         0x5 => exception!("Bound range exceeded exception"),
         0x6 => exception!("Invalid opcode exception"),
-        0x7 => exception!("Device not available exception"),
         0x8 => exception_error!("Double fault"),
         0x9 => exception!("Coprocessor Segment Overrun"), // legacy
         0xA => exception_error!("Invalid TSS exception"),
         0xB => exception_error!("Segment not present exception"),
         0xC => exception_error!("Stack-segment fault"),
         0xD => exception_error!("General protection fault"),
-        0xE => exception_error!("Page fault"),
+        0xE => {
+            // A write to memory shared after fork copies it and is retried
+            if ! unsafe { fault::copy_on_write(regs) } {
+                exception_error!("Page fault");
+            }
+        },
         0x10 => exception!("x87 floating-point exception"),
         0x11 => exception_error!("Alignment check exception"),
         0x12 => exception!("Machine check exception"),
@@ -508,11 +581,12 @@ pub extern "cdecl" fn kernel(interrupt: usize, mut regs: &mut Regs) {
         _ => exception!("Unknown Interrupt"),
     }
 
-    if interrupt >= 0x20 && interrupt < 0x30 {
-        if interrupt >= 0x28 {
-            Pio::<u8>::new(0xA0).write(0x20);
-        }
+    if let Some(irq) = interrupt::irq(interrupt) {
+        unsafe { interrupt::eoi(irq) };
+    }
 
-        Pio::<u8>::new(0x20).write(0x20);
+    // Signals for a context that does not make syscalls are acted on when its time slice ends
+    if interrupt == 0x20 && fault::user_mode(regs) {
+        handle_signals(regs);
     }
 }
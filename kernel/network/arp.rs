@@ -0,0 +1,78 @@
+use common::slice::GetSlice;
+
+use collections::slice;
+use collections::vec::Vec;
+
+use core::mem;
+
+use network::common::*;
+
+/// A request for the hardware address of `dst_ip`
+pub const ARP_REQUEST: u16 = 1;
+/// The answer to a request, from the host with the address
+pub const ARP_REPLY: u16 = 2;
+
+#[derive(Copy, Clone)]
+#[repr(packed)]
+pub struct ArpHeader {
+    pub htype: n16,
+    pub ptype: n16,
+    pub hlen: u8,
+    pub plen: u8,
+    pub oper: n16,
+    pub src_mac: MacAddr,
+    pub src_ip: Ipv4Addr,
+    pub dst_mac: MacAddr,
+    pub dst_ip: Ipv4Addr,
+}
+
+pub struct Arp {
+    pub header: ArpHeader,
+    pub data: Vec<u8>,
+}
+
+impl Arp {
+    /// An Ethernet and IPv4 message of the operation `oper`
+    pub fn new(oper: u16, src_mac: MacAddr, src_ip: Ipv4Addr, dst_mac: MacAddr, dst_ip: Ipv4Addr) -> Arp {
+        Arp {
+            header: ArpHeader {
+                htype: n16::new(1),
+                ptype: n16::new(0x800),
+                hlen: 6,
+                plen: 4,
+                oper: n16::new(oper),
+                src_mac: src_mac,
+                src_ip: src_ip,
+                dst_mac: dst_mac,
+                dst_ip: dst_ip,
+            },
+            data: Vec::new(),
+        }
+    }
+}
+
+impl FromBytes for Arp {
+    fn from_bytes(bytes: Vec<u8>) -> Option<Self> {
+        if bytes.len() >= mem::size_of::<ArpHeader>() {
+            unsafe {
+                return Some(Arp {
+                    header: *(bytes.as_ptr() as *const ArpHeader),
+                    data: bytes.get_slice(mem::size_of::<ArpHeader>() ..).to_vec(),
+                });
+            }
+        }
+        None
+    }
+}
+
+impl ToBytes for Arp {
+    fn to_bytes(&self) -> Vec<u8> {
+        unsafe {
+            let header_ptr: *const ArpHeader = &self.header;
+            let mut ret = Vec::from(slice::from_raw_parts(header_ptr as *const u8,
+                                                          mem::size_of::<ArpHeader>()));
+            ret.push_all(&self.data);
+            ret
+        }
+    }
+}
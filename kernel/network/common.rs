@@ -108,12 +108,51 @@ pub static BROADCAST_MAC_ADDR: MacAddr = MacAddr { bytes: [0xFF, 0xFF, 0xFF, 0xF
 
 pub static mut MAC_ADDR: MacAddr = MacAddr { bytes: [0x00, 0x00, 0x00, 0x00, 0x00, 0x00] };
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Ipv4Addr {
     pub bytes: [u8; 4],
 }
 
 impl Ipv4Addr {
+    /// Parse four decimal octets separated by dots, returning `None` for anything else
+    pub fn parse(string: &str) -> Option<Self> {
+        let mut addr = Ipv4Addr { bytes: [0; 4] };
+
+        let mut i = 0;
+        for part in string.split('.') {
+            if i >= 4 || part.is_empty() || part.len() > 3 || ! part.bytes().all(|b| b >= b'0' && b <= b'9') {
+                return None;
+            }
+            let octet = part.to_num();
+            if octet > 255 {
+                return None;
+            }
+            addr.bytes[i] = octet as u8;
+            i += 1;
+        }
+
+        if i == 4 {
+            Some(addr)
+        } else {
+            None
+        }
+    }
+
+    /// Is this an address of 127.0.0.0/8, which is never sent on the network
+    pub fn is_loopback(&self) -> bool {
+        self.bytes[0] == 127
+    }
+
+    /// The address as a number, for masking
+    pub fn to_u32(&self) -> u32 {
+        n32 { bytes: self.bytes }.get()
+    }
+
+    /// The address of a number
+    pub fn from_u32(value: u32) -> Self {
+        Ipv4Addr { bytes: n32::new(value).bytes }
+    }
+
     pub fn equals(&self, other: Self) -> bool {
         for i in 0..4 {
             if self.bytes[i] != other.bytes[i] {
@@ -180,6 +219,15 @@ pub static BROADCAST_IP_ADDR: Ipv4Addr = Ipv4Addr { bytes: [10, 85, 85, 255] };
 
 pub static IP_ADDR: Ipv4Addr = Ipv4Addr { bytes: [10, 85, 85, 2] };
 
+/// The mask of the local network, whose hosts are reached directly
+pub static NETMASK_ADDR: Ipv4Addr = Ipv4Addr { bytes: [255, 255, 255, 0] };
+
+/// The router that other hosts are reached through, the host end of `tap_redox`
+pub static GATEWAY_ADDR: Ipv4Addr = Ipv4Addr { bytes: [10, 85, 85, 1] };
+
+/// The address that reaches every host of any network
+pub static LIMITED_BROADCAST_ADDR: Ipv4Addr = Ipv4Addr { bytes: [255, 255, 255, 255] };
+
 #[derive(Copy, Clone)]
 pub struct Checksum {
     pub data: u16,
@@ -229,6 +277,15 @@ impl Checksum {
         sum
     }
 
+    /// The checksum of `parts` one after another, each but the last of an even length
+    pub fn of(parts: &[&[u8]]) -> u16 {
+        let mut sum = 0;
+        for part in parts.iter() {
+            sum += unsafe { Checksum::sum(part.as_ptr() as usize, part.len()) };
+        }
+        unsafe { Checksum::compile(sum) }
+    }
+
     pub unsafe fn compile(mut sum: usize) -> u16 {
         while (sum >> 16) > 0 {
             sum = (sum & 0xFFFF) + (sum >> 16);
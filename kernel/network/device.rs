@@ -0,0 +1,42 @@
+use collections::string::String;
+use collections::vec::Vec;
+use collections::vec_deque::VecDeque;
+
+use network::common::MacAddr;
+
+use system::error::Result;
+
+/// A network card, or anything else that sends and receives Ethernet frames
+///
+/// Drivers implement this and are registered with `network::register`, which gives each device a
+/// `network:` scheme of raw frames. The protocol layers are written against that scheme, so they
+/// work with any device.
+pub trait NetworkDevice {
+    /// The name of the device, for messages
+    fn name(&self) -> String;
+
+    /// The hardware address of the device
+    fn mac(&self) -> MacAddr;
+
+    /// The largest payload of a frame, without the Ethernet header
+    fn mtu(&self) -> usize {
+        1500
+    }
+
+    /// Whether the device is connected to a network
+    fn link_up(&self) -> bool {
+        true
+    }
+
+    /// Send a frame, including its Ethernet header
+    fn transmit(&mut self, frame: &[u8]) -> Result<usize>;
+
+    /// Move the frames the device has received to the back of `inbound`
+    fn receive(&mut self, inbound: &mut VecDeque<Vec<u8>>);
+
+    /// Acknowledge an interrupt of the device, before its frames are moved outside of the
+    /// interrupt handler
+    fn interrupt(&mut self) {
+
+    }
+}
@@ -0,0 +1,200 @@
+use common::slice::GetSlice;
+
+use collections::slice;
+use collections::vec::Vec;
+
+use core::mem;
+
+use network::common::*;
+
+/// A message from a client, in `op`
+pub const BOOTP_REQUEST: u8 = 1;
+/// A message from a server, in `op`
+pub const BOOTP_REPLY: u8 = 2;
+
+/// Ask the server to broadcast its replies, for a client without an address, in `flags`
+pub const DHCP_BROADCAST: u16 = 0x8000;
+
+/// The value of `magic`, which marks the options as DHCP options
+pub const DHCP_MAGIC: u32 = 0x63825363;
+
+pub const DHCP_SERVER_PORT: u16 = 67;
+pub const DHCP_CLIENT_PORT: u16 = 68;
+
+/// Option codes
+pub const DHCP_OPT_PAD: u8 = 0;
+pub const DHCP_OPT_SUBNET_MASK: u8 = 1;
+pub const DHCP_OPT_ROUTER: u8 = 3;
+pub const DHCP_OPT_DNS: u8 = 6;
+pub const DHCP_OPT_REQUESTED_IP: u8 = 50;
+pub const DHCP_OPT_LEASE_TIME: u8 = 51;
+pub const DHCP_OPT_MESSAGE_TYPE: u8 = 53;
+pub const DHCP_OPT_SERVER_ID: u8 = 54;
+pub const DHCP_OPT_PARAMETERS: u8 = 55;
+pub const DHCP_OPT_RENEWAL_TIME: u8 = 58;
+pub const DHCP_OPT_REBINDING_TIME: u8 = 59;
+pub const DHCP_OPT_END: u8 = 255;
+
+/// Message types, in the `DHCP_OPT_MESSAGE_TYPE` option
+pub const DHCP_DISCOVER: u8 = 1;
+pub const DHCP_OFFER: u8 = 2;
+pub const DHCP_REQUEST: u8 = 3;
+pub const DHCP_DECLINE: u8 = 4;
+pub const DHCP_ACK: u8 = 5;
+pub const DHCP_NAK: u8 = 6;
+pub const DHCP_RELEASE: u8 = 7;
+
+#[derive(Copy)]
+#[repr(packed)]
+pub struct DhcpHeader {
+    pub op: u8,
+    pub htype: u8,
+    pub hlen: u8,
+    pub hops: u8,
+    pub xid: n32,
+    pub secs: n16,
+    pub flags: n16,
+    pub ciaddr: Ipv4Addr,
+    pub yiaddr: Ipv4Addr,
+    pub siaddr: Ipv4Addr,
+    pub giaddr: Ipv4Addr,
+    pub chaddr: [u8; 16],
+    pub sname: [u8; 64],
+    pub file: [u8; 128],
+    pub magic: n32,
+}
+
+// Arrays longer than 32 do not implement `Clone`, so it cannot be derived
+impl Clone for DhcpHeader {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+pub struct Dhcp {
+    pub header: DhcpHeader,
+    pub options: Vec<u8>,
+}
+
+impl Dhcp {
+    /// A request of `message_type` from the client with the hardware address `mac`, with `options`
+    /// after the message type
+    pub fn request(message_type: u8, xid: u32, mac: MacAddr, ciaddr: Ipv4Addr, options: &[u8]) -> Dhcp {
+        let mut chaddr = [0; 16];
+        chaddr[.. 6].copy_from_slice(&mac.bytes);
+
+        let mut dhcp = Dhcp {
+            header: DhcpHeader {
+                op: BOOTP_REQUEST,
+                htype: 1,
+                hlen: 6,
+                hops: 0,
+                xid: n32::new(xid),
+                secs: n16::new(0),
+                flags: n16::new(if ciaddr == Ipv4Addr::from_u32(0) { DHCP_BROADCAST } else { 0 }),
+                ciaddr: ciaddr,
+                yiaddr: Ipv4Addr::from_u32(0),
+                siaddr: Ipv4Addr::from_u32(0),
+                giaddr: Ipv4Addr::from_u32(0),
+                chaddr: chaddr,
+                sname: [0; 64],
+                file: [0; 128],
+                magic: n32::new(DHCP_MAGIC),
+            },
+            options: Vec::new(),
+        };
+
+        dhcp.push_option(DHCP_OPT_MESSAGE_TYPE, &[message_type]);
+        dhcp.options.push_all(options);
+        dhcp.options.push(DHCP_OPT_END);
+        dhcp
+    }
+
+    /// Append an option of `code`
+    pub fn push_option(&mut self, code: u8, data: &[u8]) {
+        self.options.push(code);
+        self.options.push(data.len() as u8);
+        self.options.push_all(data);
+    }
+
+    /// The data of the first option of `code`
+    pub fn option(&self, code: u8) -> Option<&[u8]> {
+        let mut i = 0;
+        while i < self.options.len() {
+            match self.options[i] {
+                DHCP_OPT_PAD => i += 1,
+                DHCP_OPT_END => break,
+                option => {
+                    if i + 1 >= self.options.len() {
+                        break;
+                    }
+                    let start = i + 2;
+                    let end = start + self.options[i + 1] as usize;
+                    if end > self.options.len() {
+                        break;
+                    }
+                    if option == code {
+                        return Some(&self.options[start .. end]);
+                    }
+                    i = end;
+                }
+            }
+        }
+        None
+    }
+
+    /// The first four bytes of the option of `code`
+    fn option_bytes(&self, code: u8) -> Option<[u8; 4]> {
+        self.option(code).and_then(|data| if data.len() >= 4 {
+            Some([data[0], data[1], data[2], data[3]])
+        } else {
+            None
+        })
+    }
+
+    /// The first address in the option of `code`
+    pub fn option_addr(&self, code: u8) -> Option<Ipv4Addr> {
+        self.option_bytes(code).map(|bytes| Ipv4Addr { bytes: bytes })
+    }
+
+    /// The number in the option of `code`, for times
+    pub fn option_u32(&self, code: u8) -> Option<u32> {
+        self.option_bytes(code).map(|bytes| n32 { bytes: bytes }.get())
+    }
+
+    /// The message type
+    pub fn message_type(&self) -> Option<u8> {
+        self.option(DHCP_OPT_MESSAGE_TYPE).and_then(|data| data.get(0).cloned())
+    }
+}
+
+impl FromBytes for Dhcp {
+    fn from_bytes(bytes: Vec<u8>) -> Option<Self> {
+        if bytes.len() >= mem::size_of::<DhcpHeader>() {
+            unsafe {
+                let header = *(bytes.as_ptr() as *const DhcpHeader);
+                if header.magic.get() != DHCP_MAGIC {
+                    return None;
+                }
+
+                return Some(Dhcp {
+                    header: header,
+                    options: bytes.get_slice(mem::size_of::<DhcpHeader>() ..).to_vec(),
+                });
+            }
+        }
+        None
+    }
+}
+
+impl ToBytes for Dhcp {
+    fn to_bytes(&self) -> Vec<u8> {
+        unsafe {
+            let header_ptr: *const DhcpHeader = &self.header;
+            let mut ret = Vec::from(slice::from_raw_parts(header_ptr as *const u8,
+                                                          mem::size_of::<DhcpHeader>()));
+            ret.push_all(&self.options);
+            ret
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use collections::vec::Vec;
+
+use network::common::*;
+
+/// The port DNS servers answer on
+pub const DNS_PORT: u16 = 53;
+
+/// The message is a response, in `flags`
+pub const DNS_RESPONSE: u16 = 0x8000;
+/// Ask the server to resolve the name fully, in `flags`
+pub const DNS_RECURSION_DESIRED: u16 = 0x100;
+/// The result of a query, in `flags`
+pub const DNS_RCODE: u16 = 0xF;
+/// The name does not exist, in `DNS_RCODE`
+pub const DNS_NXDOMAIN: u16 = 3;
+
+/// A host address record
+pub const DNS_TYPE_A: u16 = 1;
+/// The Internet class
+pub const DNS_CLASS_IN: u16 = 1;
+
+/// The answer to a query
+pub enum DnsAnswer {
+    /// Addresses, with the seconds they may be cached for
+    Addresses(Vec<(Ipv4Addr, u32)>),
+    /// The name does not exist, or has no address
+    NotFound,
+    /// The server failed to resolve the name
+    Failed,
+}
+
+/// A query with the ID `id` for the addresses of `name`, which must be a valid name
+pub fn query(id: u16, name: &str) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytes.push_all(&n16::new(id).bytes);
+    bytes.push_all(&n16::new(DNS_RECURSION_DESIRED).bytes);
+    bytes.push_all(&n16::new(1).bytes);
+    bytes.push_all(&[0; 6]);
+
+    for label in name.split('.') {
+        bytes.push(label.len() as u8);
+        bytes.push_all(label.as_bytes());
+    }
+    bytes.push(0);
+
+    bytes.push_all(&n16::new(DNS_TYPE_A).bytes);
+    bytes.push_all(&n16::new(DNS_CLASS_IN).bytes);
+    bytes
+}
+
+/// Is `name` a name that can be queried, labels of 1 to 63 letters, digits and hyphens
+pub fn valid_name(name: &str) -> bool {
+    ! name.is_empty() && name.len() <= 253 && name.split('.').all(|label| {
+        ! label.is_empty() && label.len() <= 63 &&
+        label.bytes().all(|b| (b >= b'a' && b <= b'z') || (b >= b'A' && b <= b'Z') ||
+                              (b >= b'0' && b <= b'9') || b == b'-')
+    })
+}
+
+/// Read the `n16` at `i`
+fn get_n16(bytes: &[u8], i: usize) -> Result<u16, ()> {
+    if i + 2 <= bytes.len() {
+        Ok(n16 { bytes: [bytes[i], bytes[i + 1]] }.get())
+    } else {
+        Err(())
+    }
+}
+
+/// The index after the name at `i`, which may end with a pointer to another name
+fn skip_name(bytes: &[u8], mut i: usize) -> Result<usize, ()> {
+    loop {
+        let len = *try!(bytes.get(i).ok_or(())) as usize;
+        if len & 0xC0 == 0xC0 {
+            return Ok(i + 2);
+        } else if len == 0 {
+            return Ok(i + 1);
+        }
+        i += 1 + len;
+    }
+}
+
+/// Parse the response to the query with the ID `id`, returning `None` if it is not one
+///
+/// Any address record in the answers is used, which includes those that the server followed
+/// aliases to.
+pub fn parse_response(bytes: &[u8], id: u16) -> Option<DnsAnswer> {
+    parse(bytes, id).ok()
+}
+
+fn parse(bytes: &[u8], id: u16) -> Result<DnsAnswer, ()> {
+    let flags = try!(get_n16(bytes, 2));
+    if try!(get_n16(bytes, 0)) != id || flags & DNS_RESPONSE != DNS_RESPONSE {
+        return Err(());
+    }
+
+    match flags & DNS_RCODE {
+        0 => (),
+        DNS_NXDOMAIN => return Ok(DnsAnswer::NotFound),
+        _ => return Ok(DnsAnswer::Failed),
+    }
+
+    let questions = try!(get_n16(bytes, 4));
+    let answers = try!(get_n16(bytes, 6));
+
+    let mut i = 12;
+    for _ in 0..questions {
+        i = try!(skip_name(bytes, i)) + 4;
+    }
+
+    let mut addresses = Vec::new();
+    for _ in 0..answers {
+        i = try!(skip_name(bytes, i));
+        let record_type = try!(get_n16(bytes, i));
+        let class = try!(get_n16(bytes, i + 2));
+        let ttl = (try!(get_n16(bytes, i + 4)) as u32) << 16 | try!(get_n16(bytes, i + 6)) as u32;
+        let len = try!(get_n16(bytes, i + 8)) as usize;
+        i += 10;
+        if i + len > bytes.len() {
+            return Err(());
+        }
+
+        if record_type == DNS_TYPE_A && class == DNS_CLASS_IN && len == 4 {
+            addresses.push((Ipv4Addr { bytes: [bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]] }, ttl));
+        }
+        i += len;
+    }
+
+    if addresses.is_empty() {
+        Ok(DnsAnswer::NotFound)
+    } else {
+        Ok(DnsAnswer::Addresses(addresses))
+    }
+}
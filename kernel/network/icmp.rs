@@ -0,0 +1,79 @@
+use common::slice::GetSlice;
+
+use collections::slice;
+use collections::vec::Vec;
+
+use core::mem;
+
+use network::common::*;
+
+/// The answer to an echo request
+pub const ICMP_ECHO_REPLY: u8 = 0;
+/// A request for the host to send the data back
+pub const ICMP_ECHO_REQUEST: u8 = 8;
+
+#[derive(Copy, Clone)]
+#[repr(packed)]
+pub struct IcmpHeader {
+    pub _type: u8,
+    pub code: u8,
+    pub checksum: Checksum,
+    /// The identifier and sequence number of an echo message
+    pub id: n16,
+    pub sequence: n16,
+}
+
+pub struct Icmp {
+    pub header: IcmpHeader,
+    pub data: Vec<u8>,
+}
+
+impl Icmp {
+    /// An echo request or reply of `data`, with its checksum
+    pub fn echo(_type: u8, id: u16, sequence: u16, data: Vec<u8>) -> Icmp {
+        let mut icmp = Icmp {
+            header: IcmpHeader {
+                _type: _type,
+                code: 0,
+                checksum: Checksum { data: 0 },
+                id: n16::new(id),
+                sequence: n16::new(sequence),
+            },
+            data: data,
+        };
+
+        icmp.header.checksum.data = Checksum::of(&[&icmp.to_bytes()]);
+        icmp
+    }
+
+    /// Check the checksum
+    pub fn valid(&self) -> bool {
+        Checksum::of(&[&self.to_bytes()]) == 0
+    }
+}
+
+impl FromBytes for Icmp {
+    fn from_bytes(bytes: Vec<u8>) -> Option<Self> {
+        if bytes.len() >= mem::size_of::<IcmpHeader>() {
+            unsafe {
+                return Some(Icmp {
+                    header: *(bytes.as_ptr() as *const IcmpHeader),
+                    data: bytes.get_slice(mem::size_of::<IcmpHeader>() ..).to_vec(),
+                });
+            }
+        }
+        None
+    }
+}
+
+impl ToBytes for Icmp {
+    fn to_bytes(&self) -> Vec<u8> {
+        unsafe {
+            let header_ptr: *const IcmpHeader = &self.header;
+            let mut ret = Vec::from(slice::from_raw_parts(header_ptr as *const u8,
+                                                          mem::size_of::<IcmpHeader>()));
+            ret.push_all(&self.data);
+            ret
+        }
+    }
+}
@@ -3,6 +3,7 @@ use alloc::boxed::Box;
 use arch::memory;
 
 use collections::slice;
+use collections::string::{String, ToString};
 use collections::vec::Vec;
 use collections::vec_deque::VecDeque;
 
@@ -13,13 +14,9 @@ use common::debug;
 use drivers::pci::config::PciConfig;
 
 use network::common::*;
-use network::scheme::*;
+use network::device::NetworkDevice;
 
-use fs::{KScheme, Resource, Url};
-
-use system::error::Result;
-
-use sync::Intex;
+use system::error::{Error, Result, EMSGSIZE};
 
 const CTRL: u32 = 0x00;
 const CTRL_LRST: u32 = 1 << 3;
@@ -30,6 +27,7 @@ const CTRL_VME: u32 = 1 << 30;
 const CTRL_PHY_RST: u32 = 1 << 31;
 
 const STATUS: u32 = 0x08;
+const STATUS_LU: u32 = 1 << 1;
 
 const FCAL: u32 = 0x28;
 const FCAH: u32 = 0x2C;
@@ -110,81 +108,38 @@ pub struct Intel8254x {
     pub base: usize,
     pub memory_mapped: bool,
     pub irq: u8,
-    pub resources: Intex<Vec<*mut NetworkResource>>,
-    pub inbound: VecDeque<Vec<u8>>,
-    pub outbound: VecDeque<Vec<u8>>,
+    pub mac: MacAddr,
+    /// The next receive descriptor the device writes a frame to
+    rx_i: usize,
+    /// The next transmit descriptor, which may still be in use by the device
+    tx_i: usize,
 }
 
-impl KScheme for Intel8254x {
-    fn scheme(&self) -> &str {
-        "network"
+impl NetworkDevice for Intel8254x {
+    fn name(&self) -> String {
+        "Intel 8254x".to_string()
     }
 
-    fn open(&mut self, _: Url, _: usize) -> Result<Box<Resource>> {
-        Ok(NetworkResource::new(self))
+    fn mac(&self) -> MacAddr {
+        self.mac
     }
 
-    fn on_irq(&mut self, irq: u8) {
-        if irq == self.irq {
-            unsafe { self.read(ICR) };
-
-            self.sync();
-        }
+    fn link_up(&self) -> bool {
+        unsafe { self.read(STATUS) & STATUS_LU == STATUS_LU }
     }
-}
 
-impl NetworkScheme for Intel8254x {
-    fn add(&mut self, resource: *mut NetworkResource) {
-        self.resources.lock().push(resource);
+    fn transmit(&mut self, frame: &[u8]) -> Result<usize> {
+        unsafe { self.send(frame) }
     }
 
-    fn remove(&mut self, resource: *mut NetworkResource) {
-        let mut resources = self.resources.lock();
-
-        let mut i = 0;
-        while i < resources.len() {
-            let mut remove = false;
-
-            match resources.get(i) {
-                Some(ptr) => if *ptr == resource {
-                    remove = true;
-                } else {
-                    i += 1;
-                },
-                None => break,
-            }
-
-            if remove {
-                resources.remove(i);
-            }
-        }
+    fn receive(&mut self, inbound: &mut VecDeque<Vec<u8>>) {
+        unsafe { self.receive_inbound(inbound) };
     }
 
-    fn sync(&mut self) {
-        unsafe {
-            {
-                let resources = self.resources.lock();
-
-                for resource in resources.iter() {
-                    while let Some(bytes) = (**resource).outbound.lock().pop_front() {
-                        self.outbound.push_back(bytes);
-                    }
-                }
-            }
-
-            self.send_outbound();
-
-            self.receive_inbound();
-
-            {
-                let resources = self.resources.lock();
-
-                while let Some(bytes) = self.inbound.pop_front() {
-                    for resource in resources.iter() {
-                        (**resource).inbound.lock().push_back(bytes.clone());
-                    }
-                }
-            }
+    fn interrupt(&mut self) {
+        let icr = unsafe { self.read(ICR) };
+        if icr & IMS_LSC == IMS_LSC {
+            debugln!("Intel 8254x: link {}", if self.link_up() { "up" } else { "down" });
         }
     }
 }
@@ -198,9 +153,9 @@ impl Intel8254x {
             base: base & 0xFFFFFFF0,
             memory_mapped: base & 1 == 0,
             irq: pci.read(0x3C) as u8 & 0xF,
-            resources: Intex::new(Vec::new()),
-            inbound: VecDeque::new(),
-            outbound: VecDeque::new(),
+            mac: MacAddr { bytes: [0; 6] },
+            rx_i: 0,
+            tx_i: 0,
         };
 
         module.init();
@@ -208,81 +163,58 @@ impl Intel8254x {
         module
     }
 
-    pub unsafe fn receive_inbound(&mut self) {
+    /// Move the frames in the descriptors the device is done with, in the order it wrote them,
+    /// and give the descriptors back to it
+    pub unsafe fn receive_inbound(&mut self, inbound: &mut VecDeque<Vec<u8>>) {
         let receive_ring = self.read(RDBAL) as *mut Rd;
-        let length = self.read(RDLEN);
-
-        for tail in 0..length / 16 {
-            let rd = &mut *receive_ring.offset(tail as isize);
-            if rd.status & RD_DD == RD_DD {
-                debug::d("Recv ");
-                debug::dh(rd as *mut Rd as usize);
-                debug::d(" ");
-                debug::dh(rd.status as usize);
-                debug::d(" ");
-                debug::dh(rd.buffer as usize);
-                debug::d(" ");
-                debug::dh(rd.length as usize);
-                debug::dl();
-
-                self.inbound.push_back(Vec::from(slice::from_raw_parts(rd.buffer as *const u8,
-                                                                       rd.length as usize)));
-
-                rd.status = 0;
+        let count = (self.read(RDLEN) / 16) as usize;
+
+        loop {
+            let rd = &mut *receive_ring.offset(self.rx_i as isize);
+            if ptr::read_volatile(&rd.status) & RD_DD != RD_DD {
+                break;
+            }
+
+            // Buffers are large enough for any frame, so a frame without EOP is bad, like one
+            // with an error
+            if rd.status & RD_EOP == RD_EOP && rd.error == 0 {
+                inbound.push_back(Vec::from(slice::from_raw_parts(rd.buffer as *const u8,
+                                                                  rd.length as usize)));
             }
+            rd.status = 0;
+
+            // The device fills descriptors up to the one before the tail, so making this one the
+            // tail gives back the one before it
+            self.write(RDT, self.rx_i as u32);
+            self.rx_i = (self.rx_i + 1) % count;
         }
     }
 
-    pub unsafe fn send_outbound(&mut self) {
-        while let Some(bytes) = self.outbound.pop_front() {
-            let transmit_ring = self.read(TDBAL) as *mut Td;
-            let length = self.read(TDLEN);
-
-            loop {
-                let head = self.read(TDH);
-                let mut tail = self.read(TDT);
-                let old_tail = tail;
-
-                tail += 1;
-                if tail >= length / 16 {
-                    tail = 0;
-                }
-
-                if tail != head {
-                    if bytes.len() < 16384 {
-                        let td = &mut *transmit_ring.offset(old_tail as isize);
-
-                        debug::d("Send ");
-                        debug::dh(old_tail as usize);
-                        debug::d(" ");
-                        debug::dh(td.status as usize);
-                        debug::d(" ");
-                        debug::dh(td.buffer as usize);
-                        debug::d(" ");
-                        debug::dh(bytes.len() & 0x3FFF);
-                        debug::dl();
-
-                        ::memcpy(td.buffer as *mut u8, bytes.as_ptr(), bytes.len());
-                        td.length = (bytes.len() & 0x3FFF) as u16;
-                        td.cso = 0;
-                        td.command = TD_CMD_EOP | TD_CMD_IFCS | TD_CMD_RS;
-                        td.status = 0;
-                        td.css = 0;
-                        td.special = 0;
-
-                        self.write(TDT, tail);
-                    } else {
-                        // TODO: More than one TD
-                        debug::dl();
-                        debug::d("Intel 8254x: Frame too long for transmit: ");
-                        debug::dd(bytes.len());
-                        debug::dl();
-                    }
-
-                    break;
-                }
-            }
+    /// Send a frame, waiting until the device is done with the next descriptor
+    pub unsafe fn send(&mut self, bytes: &[u8]) -> Result<usize> {
+        if bytes.len() >= 16384 {
+            return Err(Error::new(EMSGSIZE));
         }
+
+        let transmit_ring = self.read(TDBAL) as *mut Td;
+        let count = (self.read(TDLEN) / 16) as usize;
+
+        let td = &mut *transmit_ring.offset(self.tx_i as isize);
+        // Descriptors that were never used have no command, the others report when they are done
+        while td.command != 0 && ptr::read_volatile(&td.status) & TD_DD != TD_DD {}
+
+        ::memcpy(td.buffer as *mut u8, bytes.as_ptr(), bytes.len());
+        td.length = (bytes.len() & 0x3FFF) as u16;
+        td.cso = 0;
+        td.command = TD_CMD_EOP | TD_CMD_IFCS | TD_CMD_RS;
+        td.status = 0;
+        td.css = 0;
+        td.special = 0;
+
+        self.tx_i = (self.tx_i + 1) % count;
+        self.write(TDT, self.tx_i as u32);
+
+        Ok(bytes.len())
     }
 
     pub unsafe fn read(&self, register: u32) -> u32 {
@@ -346,7 +278,7 @@ impl Intel8254x {
         debug::d(" MAC: ");
         let mac_low = self.read(RAL0);
         let mac_high = self.read(RAH0);
-        MAC_ADDR = MacAddr {
+        self.mac = MacAddr {
             bytes: [mac_low as u8,
                     (mac_low >> 8) as u8,
                     (mac_low >> 16) as u8,
@@ -354,7 +286,7 @@ impl Intel8254x {
                     mac_high as u8,
                     (mac_high >> 8) as u8],
         };
-        debug::d(&MAC_ADDR.to_string());
+        debug::d(&self.mac.to_string());
 
         //
         // MTA => 0;
@@ -3,10 +3,20 @@ use common::slice::GetSlice;
 use collections::slice;
 use collections::vec::Vec;
 
-use core::mem;
+use core::{cmp, mem};
 
 use network::common::*;
 
+/// The protocol of the data of a packet, in `proto`
+pub const IP_PROTO_ICMP: u8 = 1;
+pub const IP_PROTO_TCP: u8 = 6;
+pub const IP_PROTO_UDP: u8 = 17;
+
+/// More fragments follow, in `flags_fragment`
+pub const IP_MORE_FRAGMENTS: u16 = 0x2000;
+/// The offset of a fragment, in units of 8 bytes
+pub const IP_FRAGMENT_OFFSET: u16 = 0x1FFF;
+
 #[derive(Copy, Clone)]
 #[repr(packed)]
 pub struct Ipv4Header {
@@ -28,6 +38,66 @@ pub struct Ipv4 {
     pub data: Vec<u8>,
 }
 
+impl Ipv4 {
+    /// A packet of `data` without options, with its header checksum
+    pub fn new(src: Ipv4Addr, dst: Ipv4Addr, proto: u8, id: u16, data: Vec<u8>) -> Ipv4 {
+        let mut ip = Ipv4 {
+            header: Ipv4Header {
+                ver_hlen: 0x40 | (mem::size_of::<Ipv4Header>() / 4 & 0xF) as u8,
+                services: 0,
+                len: n16::new((mem::size_of::<Ipv4Header>() + data.len()) as u16),
+                id: n16::new(id),
+                flags_fragment: n16::new(0),
+                ttl: 64,
+                proto: proto,
+                checksum: Checksum { data: 0 },
+                src: src,
+                dst: dst,
+            },
+            options: Vec::new(),
+            data: data,
+        };
+
+        unsafe {
+            let header_ptr: *const Ipv4Header = &ip.header;
+            ip.header.checksum.data = Checksum::compile(Checksum::sum(header_ptr as usize, mem::size_of::<Ipv4Header>()));
+        }
+
+        ip
+    }
+
+    /// Check the version, header length, length and header checksum, and that the packet is not a
+    /// fragment, which are not reassembled. A header length below the fixed header, or past the
+    /// end of the packet, is rejected
+    pub fn valid(&self) -> bool {
+        let header_ptr: *const Ipv4Header = &self.header;
+        let header_sum = unsafe {
+            Checksum::sum(header_ptr as usize, mem::size_of::<Ipv4Header>()) +
+            Checksum::sum(self.options.as_ptr() as usize, self.options.len())
+        };
+
+        self.header.ver_hlen >> 4 == 4 &&
+        ((self.header.ver_hlen & 0xF) << 2) as usize == mem::size_of::<Ipv4Header>() + self.options.len() &&
+        unsafe { Checksum::compile(header_sum) } == 0 &&
+        self.header.len.get() as usize >= mem::size_of::<Ipv4Header>() + self.options.len() &&
+        self.header.flags_fragment.get() & (IP_MORE_FRAGMENTS | IP_FRAGMENT_OFFSET) == 0
+    }
+
+    /// The data without the padding that short frames are given, using the length in the header
+    pub fn payload(&self) -> &[u8] {
+        let len = self.header.len.get() as usize - mem::size_of::<Ipv4Header>() - self.options.len();
+        &self.data[.. cmp::min(len, self.data.len())]
+    }
+}
+
+/// The pseudo header that TCP and UDP checksums cover, for a segment of `len` bytes
+pub fn pseudo_header(src: Ipv4Addr, dst: Ipv4Addr, proto: u8, len: usize) -> [u8; 12] {
+    let len = n16::new(len as u16);
+    [src.bytes[0], src.bytes[1], src.bytes[2], src.bytes[3],
+     dst.bytes[0], dst.bytes[1], dst.bytes[2], dst.bytes[3],
+     0, proto, len.bytes[0], len.bytes[1]]
+}
+
 impl FromBytes for Ipv4 {
     fn from_bytes(bytes: Vec<u8>) -> Option<Self> {
         if bytes.len() >= mem::size_of::<Ipv4Header>() {
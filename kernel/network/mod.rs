@@ -1,7 +1,102 @@
+use alloc::boxed::Box;
+
+use collections::string::ToString;
+
+use core::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
+
+use drivers::pci::common::deviceid::*;
+use drivers::pci::common::vendorid::*;
+use drivers::pci::config::PciConfig;
+use drivers::registry::{self, PciId, Subsystem};
+
+use env::Environment;
+
+use self::common::MAC_ADDR;
+use self::device::NetworkDevice;
+use self::intel8254x::Intel8254x;
+use self::loopback::Loopback;
+use self::rtl8139::Rtl8139;
+use self::scheme::NetworkDeviceScheme;
+use self::schemes::dns::DnsScheme;
+use self::schemes::netcfg::NetcfgScheme;
+use self::schemes::ping::PingScheme;
+use self::schemes::tcp::TcpScheme;
+use self::schemes::udp::UdpScheme;
+use self::stack::Stack;
+
+pub mod arp;
 pub mod common;
+pub mod device;
+pub mod dhcp;
+pub mod dns;
 pub mod ethernet;
+pub mod icmp;
 pub mod intel8254x;
 pub mod ipv4;
 pub mod ipv6;
+pub mod loopback;
+pub mod netcfg;
+pub mod resolver;
 pub mod rtl8139;
 pub mod scheme;
+pub mod schemes;
+pub mod stack;
+pub mod tcp;
+pub mod udp;
+
+/// The network stack and its drivers, enabled by the `network` feature
+pub const SUBSYSTEM: Subsystem = Subsystem {
+    name: "network",
+    init: init,
+    pci_device: pci_device,
+};
+
+/// Register `lo:`, start the protocol layers over it and the first network device, and register
+/// `dns:`, `netcfg:`, `ping:`, `tcp:` and `udp:`
+unsafe fn init(env: &mut Environment) {
+    register_loopback(env);
+
+    let stack = Stack::new();
+    Stack::start(stack.clone());
+    env.schemes.push(DnsScheme::new(stack.clone()));
+    env.schemes.push(NetcfgScheme::new(stack.clone()));
+    env.schemes.push(PingScheme::new(stack.clone()));
+    env.schemes.push(TcpScheme::new(stack.clone()));
+    env.schemes.push(UdpScheme::new(stack));
+}
+
+/// The number of network devices registered
+static DEVICES: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Register a network device interrupting on `irq`, as `network:` for the first device and
+/// `network1:`, `network2:` and so on for the next
+///
+/// The address of the first device is the one used by the protocol layers.
+pub fn register(env: &Environment, device: Box<NetworkDevice>, irq: u8) {
+    let number = DEVICES.fetch_add(1, Ordering::SeqCst);
+    let name = if number == 0 {
+        unsafe { MAC_ADDR = device.mac() };
+        "network".to_string()
+    } else {
+        format!("network{}", number)
+    };
+
+    debugln!("{}: {} {}", name, device.name(), device.mac().to_string());
+    env.add_driver(NetworkDeviceScheme::new(name, device, Some(irq)), &[irq]);
+}
+
+/// Register the loopback device as `lo:`
+pub fn register_loopback(env: &Environment) {
+    env.add_driver(NetworkDeviceScheme::new("lo".to_string(), Loopback::new(), None), &[]);
+}
+
+/// Start a driver for a network card
+unsafe fn pci_device(env: &mut Environment, mut pci: PciConfig, id: &PciId) -> bool {
+    let irq = pci.read(0x3C) as u8 & 0xF;
+    match (id.vendor, id.device) {
+        (REALTEK, RTL8139) => register(env, Rtl8139::new(pci), irq),
+        (INTEL, GBE_82540EM) => register(env, Intel8254x::new(pci), irq),
+        _ => return false,
+    }
+    true
+}
@@ -0,0 +1,346 @@
+//! The DHCP client, which configures the addresses of the interface
+//!
+//! It runs in `knettimer`, starting when the stack does, and again when `dhcp` is written to
+//! `netcfg:`. The fallback addresses are kept until a server leases others, and are restored when
+//! the lease expires.
+
+use alloc::arc::Arc;
+
+use collections::Vec;
+
+use common::random;
+use common::time::Duration;
+
+use network::common::*;
+use network::dhcp::*;
+use network::ipv4::IP_PROTO_UDP;
+use network::schemes::udp::UdpSocket;
+use network::stack::{Config, Stack};
+use network::udp::Udp;
+
+use sync::Intex;
+
+/// The times a message is sent before the client gives up
+const DHCP_TRIES: usize = 4;
+/// The ticks before the first message is resent, doubled for each one after
+const DHCP_RETRY_TICKS: usize = 4;
+/// The ticks between requests to extend a lease
+const DHCP_RENEW_TICKS: usize = 40;
+
+/// The options the client asks servers for
+const DHCP_PARAMETERS: [u8; 6] = [DHCP_OPT_SUBNET_MASK, DHCP_OPT_ROUTER, DHCP_OPT_DNS,
+                                  DHCP_OPT_LEASE_TIME, DHCP_OPT_RENEWAL_TIME, DHCP_OPT_REBINDING_TIME];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DhcpState {
+    /// About to discover a server
+    Init,
+    /// Waiting for offers
+    Selecting,
+    /// Waiting for the server to acknowledge an offer
+    Requesting,
+    /// Configured with a lease
+    Bound,
+    /// Asking the server of the lease to extend it
+    Renewing,
+    /// Asking any server to extend the lease
+    Rebinding,
+    /// No server answered, the fallback addresses are used
+    Failed,
+}
+
+impl DhcpState {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            DhcpState::Init => "init",
+            DhcpState::Selecting => "selecting",
+            DhcpState::Requesting => "requesting",
+            DhcpState::Bound => "bound",
+            DhcpState::Renewing => "renewing",
+            DhcpState::Rebinding => "rebinding",
+            DhcpState::Failed => "failed",
+        }
+    }
+}
+
+/// The addresses a server gave, and for how long
+#[derive(Copy, Clone)]
+pub struct Lease {
+    pub server: Ipv4Addr,
+    pub config: Config,
+    /// The length of the lease, in seconds
+    pub secs: u32,
+    /// The seconds after which the lease is renewed, and rebound
+    pub renew: u32,
+    pub rebind: u32,
+    /// The monotonic time the lease was given
+    pub obtained: Duration,
+}
+
+impl Lease {
+    /// The seconds since the lease was given
+    pub fn elapsed(&self) -> u32 {
+        let elapsed = Duration::monotonic() - self.obtained;
+        if elapsed.secs > 0 {
+            elapsed.secs as u32
+        } else {
+            0
+        }
+    }
+}
+
+struct Client {
+    state: DhcpState,
+    /// The socket of port 68, bound by the first tick
+    socket: Option<Arc<UdpSocket>>,
+    /// The transaction ID, which replies carry back
+    xid: u32,
+    /// The ticks until the current message is resent
+    timer: usize,
+    tries: usize,
+    /// The address offered, and the server that offered it
+    offer: Option<(Ipv4Addr, Ipv4Addr)>,
+    lease: Option<Lease>,
+}
+
+impl Client {
+    /// Send a message of `message_type` to `dst` from `ciaddr`, with `options`
+    fn send(&self, stack: &Stack, message_type: u8, ciaddr: Ipv4Addr, dst: Ipv4Addr, options: &[u8]) {
+        let mut all = Vec::new();
+        all.push(DHCP_OPT_PARAMETERS);
+        all.push(DHCP_PARAMETERS.len() as u8);
+        all.push_all(&DHCP_PARAMETERS);
+        all.push_all(options);
+
+        let dhcp = Dhcp::request(message_type, self.xid, stack.mac(), ciaddr, &all);
+        let udp = Udp::new(ciaddr, DHCP_CLIENT_PORT, dst, DHCP_SERVER_PORT, dhcp.to_bytes());
+        let _ = stack.send_from(ciaddr, dst, IP_PROTO_UDP, udp.to_bytes());
+    }
+
+    /// Send the message of the current state
+    fn transmit(&self, stack: &Stack) {
+        let none = Ipv4Addr::from_u32(0);
+        match self.state {
+            DhcpState::Selecting => self.send(stack, DHCP_DISCOVER, none, LIMITED_BROADCAST_ADDR, &[]),
+            DhcpState::Requesting => if let Some((ip, server)) = self.offer {
+                let mut options = vec![DHCP_OPT_REQUESTED_IP, 4];
+                options.push_all(&ip.bytes);
+                options.push(DHCP_OPT_SERVER_ID);
+                options.push(4);
+                options.push_all(&server.bytes);
+                self.send(stack, DHCP_REQUEST, none, LIMITED_BROADCAST_ADDR, &options);
+            },
+            DhcpState::Renewing => if let Some(lease) = self.lease {
+                self.send(stack, DHCP_REQUEST, lease.config.ip, lease.server, &[]);
+            },
+            DhcpState::Rebinding => if let Some(lease) = self.lease {
+                self.send(stack, DHCP_REQUEST, lease.config.ip, LIMITED_BROADCAST_ADDR, &[]);
+            },
+            _ => (),
+        }
+    }
+
+    /// Send the message of the current state again, giving up after `DHCP_TRIES`, when a lease
+    /// that is still valid is kept
+    fn retry(&mut self, stack: &Stack) {
+        if self.tries >= DHCP_TRIES {
+            debugln!("netcfg: no DHCP server answered");
+            self.state = if self.lease.is_some() {
+                DhcpState::Bound
+            } else {
+                DhcpState::Failed
+            };
+            return;
+        }
+
+        self.timer = DHCP_RETRY_TICKS << self.tries;
+        self.tries += 1;
+        self.transmit(stack);
+    }
+
+    /// Handle a reply from a server
+    fn receive(&mut self, stack: &Stack, dhcp: Dhcp) {
+        if dhcp.header.op != BOOTP_REPLY || dhcp.header.xid.get() != self.xid ||
+           dhcp.header.chaddr[.. 6] != stack.mac().bytes {
+            return;
+        }
+
+        match (self.state, dhcp.message_type()) {
+            (DhcpState::Selecting, Some(DHCP_OFFER)) => {
+                if let Some(server) = dhcp.option_addr(DHCP_OPT_SERVER_ID) {
+                    self.offer = Some((dhcp.header.yiaddr, server));
+                    self.state = DhcpState::Requesting;
+                    self.tries = 0;
+                    self.retry(stack);
+                }
+            },
+            (DhcpState::Requesting, Some(DHCP_ACK)) |
+            (DhcpState::Renewing, Some(DHCP_ACK)) |
+            (DhcpState::Rebinding, Some(DHCP_ACK)) => {
+                let server = dhcp.option_addr(DHCP_OPT_SERVER_ID)
+                                 .or(self.offer.map(|offer| offer.1))
+                                 .or(self.lease.map(|lease| lease.server))
+                                 .unwrap_or(Ipv4Addr::from_u32(0));
+                let secs = dhcp.option_u32(DHCP_OPT_LEASE_TIME).unwrap_or(3600);
+                let lease = Lease {
+                    server: server,
+                    config: Config {
+                        ip: dhcp.header.yiaddr,
+                        netmask: dhcp.option_addr(DHCP_OPT_SUBNET_MASK).unwrap_or(NETMASK_ADDR),
+                        gateway: dhcp.option_addr(DHCP_OPT_ROUTER).unwrap_or(Ipv4Addr::from_u32(0)),
+                        dns: dhcp.option_addr(DHCP_OPT_DNS),
+                    },
+                    secs: secs,
+                    renew: dhcp.option_u32(DHCP_OPT_RENEWAL_TIME).unwrap_or(secs / 2),
+                    rebind: dhcp.option_u32(DHCP_OPT_REBINDING_TIME).unwrap_or(secs / 8 * 7),
+                    obtained: Duration::monotonic(),
+                };
+
+                if self.state == DhcpState::Requesting {
+                    debugln!("netcfg: leased {} from {} for {} seconds",
+                             lease.config.ip.to_string(), server.to_string(), secs);
+                }
+                stack.configure(lease.config);
+                self.lease = Some(lease);
+                self.state = DhcpState::Bound;
+            },
+            (DhcpState::Requesting, Some(DHCP_NAK)) |
+            (DhcpState::Renewing, Some(DHCP_NAK)) |
+            (DhcpState::Rebinding, Some(DHCP_NAK)) => {
+                debugln!("netcfg: lease refused");
+                self.expire(stack);
+            },
+            _ => (),
+        }
+    }
+
+    /// Drop the lease and start over, with the fallback addresses
+    fn expire(&mut self, stack: &Stack) {
+        if self.lease.take().is_some() {
+            stack.configure(Config::fallback());
+        }
+        self.state = DhcpState::Init;
+    }
+
+    /// Count down the timers of the current state
+    fn tick(&mut self, stack: &Stack) {
+        match self.state {
+            DhcpState::Init => {
+                self.xid = random::rand() as u32;
+                self.offer = None;
+                self.state = DhcpState::Selecting;
+                self.tries = 0;
+                self.retry(stack);
+            },
+            DhcpState::Selecting | DhcpState::Requesting => {
+                if self.timer > 1 {
+                    self.timer -= 1;
+                } else {
+                    self.retry(stack);
+                }
+            },
+            DhcpState::Bound | DhcpState::Renewing | DhcpState::Rebinding => {
+                let lease = match self.lease {
+                    Some(lease) => lease,
+                    None => {
+                        self.state = DhcpState::Init;
+                        return;
+                    }
+                };
+
+                let elapsed = lease.elapsed();
+                if elapsed >= lease.secs {
+                    debugln!("netcfg: lease expired");
+                    self.expire(stack);
+                    return;
+                }
+
+                let state = if elapsed >= lease.rebind {
+                    DhcpState::Rebinding
+                } else if elapsed >= lease.renew {
+                    DhcpState::Renewing
+                } else {
+                    DhcpState::Bound
+                };
+                if state != self.state {
+                    self.state = state;
+                    self.timer = 0;
+                }
+
+                if self.state != DhcpState::Bound {
+                    if self.timer > 0 {
+                        self.timer -= 1;
+                    } else {
+                        self.timer = DHCP_RENEW_TICKS;
+                        self.transmit(stack);
+                    }
+                }
+            },
+            DhcpState::Failed => (),
+        }
+    }
+}
+
+/// The DHCP client of the stack
+pub struct Netcfg {
+    client: Intex<Client>,
+}
+
+impl Netcfg {
+    pub fn new() -> Netcfg {
+        Netcfg {
+            client: Intex::new(Client {
+                state: DhcpState::Init,
+                socket: None,
+                xid: 0,
+                timer: 0,
+                tries: 0,
+                offer: None,
+                lease: None,
+            }),
+        }
+    }
+
+    /// The state of the client, and the lease if there is one
+    pub fn status(&self) -> (DhcpState, Option<Lease>) {
+        let client = self.client.lock();
+        (client.state, client.lease)
+    }
+
+    /// Discover a server again, keeping the current lease until another is given
+    pub fn restart(&self) {
+        let mut client = self.client.lock();
+        client.state = DhcpState::Init;
+    }
+
+    /// Handle the replies received, and resend what was not answered
+    pub fn tick(&self, stack: &Stack) {
+        let mut client = self.client.lock();
+
+        if client.socket.is_none() {
+            if client.state == DhcpState::Failed {
+                return;
+            }
+            match stack.udp.bind(DHCP_CLIENT_PORT, None) {
+                Ok(socket) => client.socket = Some(socket),
+                Err(err) => {
+                    debugln!("netcfg: failed to bind port {}: {}", DHCP_CLIENT_PORT, err);
+                    client.state = DhcpState::Failed;
+                    return;
+                }
+            }
+        }
+
+        if let Some(socket) = client.socket.clone() {
+            while let Some((_, port, data)) = socket.try_recv() {
+                if port == DHCP_SERVER_PORT {
+                    if let Some(dhcp) = Dhcp::from_bytes(data) {
+                        client.receive(stack, dhcp);
+                    }
+                }
+            }
+        }
+
+        client.tick(stack);
+    }
+}
@@ -0,0 +1,138 @@
+//! Resolving names with the DNS server of the configuration
+//!
+//! Answers are cached for as long as their TTL allows, so that opening sockets to the same host
+//! does not query the server each time.
+
+use collections::{BTreeMap, String, Vec};
+
+use common::random;
+use common::time::Duration;
+
+use core::cmp;
+
+use network::common::*;
+use network::dns::{self, DnsAnswer, DNS_PORT};
+use network::ipv4::IP_PROTO_UDP;
+use network::stack::{self, Stack};
+use network::udp::Udp;
+
+use sync::Intex;
+
+use system::error::{Error, Result, EDESTADDRREQ, EHOSTUNREACH, EINVAL, ENOENT, ETIMEDOUT};
+
+/// The queries sent before a name fails to resolve
+const DNS_TRIES: usize = 3;
+/// How long a response to a query is waited for
+const DNS_TIMEOUT_SECS: i64 = 2;
+/// How often the socket is checked for a response
+const DNS_POLL_NANOS: i32 = 50000000;
+
+/// The names cached, the answers that expire first are dropped for new ones
+const DNS_CACHE_MAX: usize = 64;
+/// The longest an answer is cached, whatever its TTL
+const DNS_TTL_MAX: u32 = 86400;
+
+/// An address and when it expires
+struct Cached {
+    addr: Ipv4Addr,
+    expires: Duration,
+}
+
+pub struct Resolver {
+    /// Addresses by lowercase name
+    cache: Intex<BTreeMap<String, Cached>>,
+}
+
+impl Resolver {
+    pub fn new() -> Resolver {
+        Resolver {
+            cache: Intex::new(BTreeMap::new()),
+        }
+    }
+
+    /// The address of `host`, which is either an address or a name
+    pub fn resolve(&self, stack: &Stack, host: &str) -> Result<Ipv4Addr> {
+        if let Some(addr) = Ipv4Addr::parse(host) {
+            return Ok(addr);
+        }
+
+        let host = host.trim_right_matches('.');
+        if ! dns::valid_name(host) {
+            return Err(Error::new(EINVAL));
+        }
+        let name: String = host.chars().map(|c| if c >= 'A' && c <= 'Z' {
+            (c as u8 + b'a' - b'A') as char
+        } else {
+            c
+        }).collect();
+
+        if let Some(cached) = self.cache.lock().get(&name) {
+            if cached.expires > Duration::monotonic() {
+                return Ok(cached.addr);
+            }
+        }
+
+        let (addr, ttl) = try!(self.query(stack, &name));
+        self.insert(name, addr, ttl);
+        Ok(addr)
+    }
+
+    /// Ask the server for the first address of `name`, and how long it may be cached
+    fn query(&self, stack: &Stack, name: &str) -> Result<(Ipv4Addr, u32)> {
+        let server = try!(stack.config().dns.ok_or(Error::new(EDESTADDRREQ)));
+        let socket = try!(stack.udp.bind(0, Some((server, DNS_PORT))));
+
+        let id = random::rand() as u16;
+        let query = dns::query(id, name);
+        for _ in 0..DNS_TRIES {
+            let udp = Udp::new(stack.source(server), socket.port(), server, DNS_PORT, query.clone());
+            try!(stack.send(server, IP_PROTO_UDP, udp.to_bytes()));
+
+            let deadline = Duration::monotonic() + Duration::new(DNS_TIMEOUT_SECS, 0);
+            while Duration::monotonic() < deadline {
+                while let Some((_, _, data)) = socket.try_recv() {
+                    match dns::parse_response(&data, id) {
+                        Some(DnsAnswer::Addresses(addresses)) => return Ok(addresses[0]),
+                        Some(DnsAnswer::NotFound) => return Err(Error::new(ENOENT)),
+                        Some(DnsAnswer::Failed) => return Err(Error::new(EHOSTUNREACH)),
+                        None => (),
+                    }
+                }
+
+                stack::sleep(Duration::new(0, DNS_POLL_NANOS));
+            }
+        }
+
+        Err(Error::new(ETIMEDOUT))
+    }
+
+    /// Cache `addr` for `name` for `ttl` seconds
+    fn insert(&self, name: String, addr: Ipv4Addr, ttl: u32) {
+        if ttl == 0 {
+            return;
+        }
+
+        let now = Duration::monotonic();
+        let mut cache = self.cache.lock();
+        if cache.len() >= DNS_CACHE_MAX {
+            let expired: Vec<String> = cache.iter().filter(|&(_, cached)| cached.expires <= now)
+                                            .map(|(name, _)| name.clone()).collect();
+            for name in expired.iter() {
+                cache.remove(name);
+            }
+
+            if cache.len() >= DNS_CACHE_MAX {
+                let oldest = cache.iter().min_by_key(|&(_, cached)| (cached.expires.secs, cached.expires.nanos))
+                                  .map(|(name, _)| name.clone());
+                if let Some(oldest) = oldest {
+                    cache.remove(&oldest);
+                }
+            }
+        }
+
+        cache.insert(name, Cached {
+            addr: addr,
+            expires: now + Duration::new(cmp::min(ttl, DNS_TTL_MAX) as i64, 0),
+        });
+    }
+}
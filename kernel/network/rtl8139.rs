@@ -3,7 +3,7 @@ use alloc::boxed::Box;
 use arch::memory;
 
 use collections::slice;
-use collections::string::ToString;
+use collections::string::{String, ToString};
 use collections::vec::Vec;
 use collections::vec_deque::VecDeque;
 
@@ -15,13 +15,9 @@ use drivers::pci::config::PciConfig;
 use drivers::io::{Io, Pio};
 
 use network::common::*;
-use network::scheme::*;
+use network::device::NetworkDevice;
 
-use fs::{KScheme, Resource, Url};
-
-use system::error::Result;
-
-use sync::Intex;
+use system::error::{Error, Result, EIO, EMSGSIZE};
 
 const RTL8139_TSR_OWN: u32 = 1 << 13;
 
@@ -49,6 +45,11 @@ const RTL8139_RCR_AB: u32 = 1 << 3;
 const RTL8139_RCR_AM: u32 = 1 << 2;
 const RTL8139_RCR_APM: u32 = 1 << 1;
 
+const RTL8139_MSR_LINKB: u8 = 1 << 2;
+
+/// The frame was received without errors, in the header of a frame in the receive ring
+const RTL8139_RX_ROK: u16 = 1 << 0;
+
 #[repr(packed)]
 struct Txd {
     pub address_port: Pio<u32>,
@@ -67,6 +68,7 @@ pub struct Rtl8139Port {
     pub tcr: Pio<u32>,
     pub rcr: Pio<u32>,
     pub config1: Pio<u8>,
+    pub msr: Pio<u8>,
 }
 
 impl Rtl8139Port {
@@ -87,6 +89,7 @@ impl Rtl8139Port {
             tcr: Pio::<u32>::new(base + 0x40),
             rcr: Pio::<u32>::new(base + 0x44),
             config1: Pio::<u8>::new(base + 0x52),
+            msr: Pio::<u8>::new(base + 0x58),
         };
     }
 }
@@ -96,9 +99,7 @@ pub struct Rtl8139 {
     base: usize,
     memory_mapped: bool,
     irq: u8,
-    resources: Intex<Vec<*mut NetworkResource>>,
-    inbound: VecDeque<Vec<u8>>,
-    outbound: VecDeque<Vec<u8>>,
+    mac: MacAddr,
     txds: Vec<Txd>,
     txd_i: usize,
     port: Rtl8139Port,
@@ -120,9 +121,7 @@ impl Rtl8139 {
             base: base & 0xFFFFFFF0,
             memory_mapped: base & 1 == 0,
             irq: irq,
-            resources: Intex::new(Vec::new()),
-            inbound: VecDeque::new(),
-            outbound: VecDeque::new(),
+            mac: MacAddr { bytes: [0; 6] },
             txds: Vec::new(),
             txd_i: 0,
             port: Rtl8139Port::new((base & 0xFFFFFFF0) as u16),
@@ -153,7 +152,7 @@ impl Rtl8139 {
         while self.port.cr.read() & RTL8139_CR_RST != 0 {}
 
         debug::d(" MAC: ");
-        MAC_ADDR = MacAddr {
+        self.mac = MacAddr {
             bytes: [self.port.idr[0].read(),
                     self.port.idr[1].read(),
                     self.port.idr[2].read(),
@@ -161,7 +160,7 @@ impl Rtl8139 {
                     self.port.idr[4].read(),
                     self.port.idr[5].read()],
         };
-        debug::d(&MAC_ADDR.to_string());
+        debug::d(&self.mac.to_string());
 
         let receive_buffer = memory::alloc(10240);
         self.port.rbstart.write(receive_buffer as u32);
@@ -194,27 +193,26 @@ impl Rtl8139 {
         debug::dl();
     }
 
-    unsafe fn receive_inbound(&mut self) {
+    /// Move the frames between the read pointer and the write pointer of the receive ring
+    unsafe fn receive_inbound(&mut self, inbound: &mut VecDeque<Vec<u8>>) {
         let receive_buffer = self.port.rbstart.read() as usize;
-        let mut capr = (self.port.capr.read() + 16) as usize;
+        let mut capr = (self.port.capr.read().wrapping_add(16)) as usize;
         let cbr = self.port.cbr.read() as usize;
 
         while capr != cbr {
             let frame_addr = receive_buffer + capr + 4;
-            let frame_status = ptr::read((receive_buffer + capr) as *const u16) as usize;
+            let frame_status = ptr::read((receive_buffer + capr) as *const u16);
             let frame_len = ptr::read((receive_buffer + capr + 2) as *const u16) as usize;
 
-            debug::d("Recv ");
-            debug::dh(capr as usize);
-            debug::d(" ");
-            debug::dh(frame_status);
-            debug::d(" ");
-            debug::dh(frame_addr);
-            debug::d(" ");
-            debug::dh(frame_len);
-            debug::dl();
+            // The header of a bad frame cannot be trusted to find the next one, so the frames
+            // received so far are dropped
+            if frame_status & RTL8139_RX_ROK != RTL8139_RX_ROK || frame_len < 4 || frame_len > 1518 + 4 {
+                debugln!("RTL8139: bad frame, status {:X} length {}", frame_status, frame_len);
+                self.port.capr.write((cbr as u16).wrapping_sub(16));
+                break;
+            }
 
-            self.inbound.push_back(Vec::from(slice::from_raw_parts(frame_addr as *const u8, frame_len - 4)));
+            inbound.push_back(Vec::from(slice::from_raw_parts(frame_addr as *const u8, frame_len - 4)));
 
             capr = capr + frame_len + 4;
             capr = (capr + 3) & (0xFFFFFFFF - 3);
@@ -226,116 +224,53 @@ impl Rtl8139 {
         }
     }
 
-    unsafe fn send_outbound(&mut self) {
-        while let Some(bytes) = self.outbound.pop_front() {
-            if let Some(ref mut txd) = self.txds.get_mut(self.txd_i) {
-                if bytes.len() < 4096 {
-                    while !txd.status_port.readf(RTL8139_TSR_OWN) {}
-
-                    debug::d("Send ");
-                    debug::dh(self.txd_i as usize);
-                    debug::d(" ");
-                    debug::dh(txd.status_port.read() as usize);
-                    debug::d(" ");
-                    debug::dh(txd.buffer);
-                    debug::d(" ");
-                    debug::dh(bytes.len() & 0xFFF);
-                    debug::dl();
-
-                    ::memcpy(txd.buffer as *mut u8, bytes.as_ptr(), bytes.len());
-
-                    txd.address_port.write(txd.buffer as u32);
-                    txd.status_port.write(bytes.len() as u32 & 0xFFF);
-
-                    self.txd_i = (self.txd_i + 1) % 4;
-                } else {
-                    debug::dl();
-                    debug::d("RTL8139: Frame too long for transmit: ");
-                    debug::dd(bytes.len());
-                    debug::dl();
-                }
+    unsafe fn send(&mut self, bytes: &[u8]) -> Result<usize> {
+        if let Some(ref mut txd) = self.txds.get_mut(self.txd_i) {
+            if bytes.len() < 4096 {
+                while !txd.status_port.readf(RTL8139_TSR_OWN) {}
+
+                ::memcpy(txd.buffer as *mut u8, bytes.as_ptr(), bytes.len());
+
+                txd.address_port.write(txd.buffer as u32);
+                txd.status_port.write(bytes.len() as u32 & 0xFFF);
+
+                self.txd_i = (self.txd_i + 1) % 4;
+
+                Ok(bytes.len())
             } else {
-                debug::d("RTL8139: TXD Overflow!\n");
-                self.txd_i = 0;
+                Err(Error::new(EMSGSIZE))
             }
+        } else {
+            debug::d("RTL8139: TXD Overflow!\n");
+            self.txd_i = 0;
+            Err(Error::new(EIO))
         }
     }
 }
 
-impl KScheme for Rtl8139 {
-    fn scheme(&self) -> &str {
-        "network"
+impl NetworkDevice for Rtl8139 {
+    fn name(&self) -> String {
+        "RTL8139".to_string()
     }
 
-    fn open(&mut self, _: Url, _: usize) -> Result<Box<Resource>> {
-        Ok(NetworkResource::new(self))
+    fn mac(&self) -> MacAddr {
+        self.mac
     }
 
-    fn on_irq(&mut self, irq: u8) {
-        if irq == self.irq {
-            let isr = self.port.isr.read();
-            self.port.isr.write(isr);
-
-            // dh(isr as usize);
-            // dl();
-
-            self.sync();
-        }
+    fn link_up(&self) -> bool {
+        self.port.msr.read() & RTL8139_MSR_LINKB == 0
     }
-}
 
-impl NetworkScheme for Rtl8139 {
-    fn add(&mut self, resource: *mut NetworkResource) {
-        self.resources.lock().push(resource);
+    fn transmit(&mut self, frame: &[u8]) -> Result<usize> {
+        unsafe { self.send(frame) }
     }
 
-    fn remove(&mut self, resource: *mut NetworkResource) {
-        let mut resources = self.resources.lock();
-
-        let mut i = 0;
-        while i < resources.len() {
-            let mut remove = false;
-
-            match resources.get(i) {
-                Some(ptr) => if *ptr == resource {
-                    remove = true;
-                } else {
-                    i += 1;
-                },
-                None => break,
-            }
-
-            if remove {
-                resources.remove(i);
-            }
-        }
+    fn receive(&mut self, inbound: &mut VecDeque<Vec<u8>>) {
+        unsafe { self.receive_inbound(inbound) };
     }
 
-    fn sync(&mut self) {
-        unsafe {
-            {
-                let resources = self.resources.lock();
-
-                for resource in resources.iter() {
-                    while let Some(bytes) = (**resource).outbound.lock().pop_front() {
-                        self.outbound.push_back(bytes);
-                    }
-                }
-            }
-
-            self.send_outbound();
-
-            self.receive_inbound();
-
-            {
-                let resources = self.resources.lock();
-
-                while let Some(bytes) = self.inbound.pop_front() {
-                    for resource in resources.iter() {
-                        (**resource).inbound.lock().push_back(bytes.clone());
-                    }
-                }
-            }
-        }
+    fn interrupt(&mut self) {
+        let isr = self.port.isr.read();
+        self.port.isr.write(isr);
     }
 }
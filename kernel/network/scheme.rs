@@ -1,17 +1,20 @@
 use alloc::boxed::Box;
 
-use arch::context::context_switch;
+use arch::context::Context;
 
+use collections::string::String;
 use collections::vec::Vec;
 use collections::vec_deque::VecDeque;
 
 use core::ops::DerefMut;
 
-use fs::Resource;
+use fs::{KScheme, Resource, Url, VecResource};
 
-use system::error::Result;
+use network::device::NetworkDevice;
 
-use sync::Intex;
+use system::error::{Error, Result, ENOENT};
+
+use sync::{Intex, WaitQueue};
 
 pub trait NetworkScheme {
     fn add(&mut self, resource: *mut NetworkResource);
@@ -22,7 +25,8 @@ pub trait NetworkScheme {
 pub struct NetworkResource {
     pub nic: *mut NetworkScheme,
     pub ptr: *mut NetworkResource,
-    pub inbound: Intex<VecDeque<Vec<u8>>>,
+    /// Frames received, moved here by the thread of the device
+    pub inbound: WaitQueue<Vec<u8>>,
     pub outbound: Intex<VecDeque<Vec<u8>>>,
 }
 
@@ -31,7 +35,7 @@ impl NetworkResource {
         let mut ret = box NetworkResource {
             nic: nic,
             ptr: 0 as *mut NetworkResource,
-            inbound: Intex::new(VecDeque::new()),
+            inbound: WaitQueue::new(),
             outbound: Intex::new(VecDeque::new()),
         };
 
@@ -50,9 +54,10 @@ impl Resource for NetworkResource {
         let mut ret = box NetworkResource {
             nic: self.nic,
             ptr: 0 as *mut NetworkResource,
-            inbound: Intex::new(self.inbound.lock().clone()),
+            inbound: WaitQueue::new(),
             outbound: Intex::new(self.outbound.lock().clone()),
         };
+        *ret.inbound.inner.lock() = self.inbound.inner.lock().clone();
 
         unsafe {
             ret.ptr = ret.deref_mut();
@@ -76,26 +81,14 @@ impl Resource for NetworkResource {
     }
 
     fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
-        loop {
-            unsafe {
-                {
-                    (*self.nic).sync();
-
-                    let option = (*self.ptr).inbound.lock().pop_front();
-
-                    if let Some(bytes) = option {
-                        let mut i = 0;
-                        while i < bytes.len() && i < buf.len() {
-                            buf[i] = bytes[i];
-                            i += 1;
-                        }
-                        return Ok(bytes.len());
-                    }
-                }
-
-                context_switch();
-            }
+        let bytes = unsafe { (*self.ptr).inbound.receive() };
+
+        let mut i = 0;
+        while i < bytes.len() && i < buf.len() {
+            buf[i] = bytes[i];
+            i += 1;
         }
+        Ok(bytes.len())
     }
 
     fn write(&mut self, buf: &[u8]) -> Result<usize> {
@@ -123,3 +116,109 @@ impl Drop for NetworkResource {
         }
     }
 }
+
+/// The `network:` scheme of a `NetworkDevice`, sending and receiving raw frames
+///
+/// Each resource opened on `network:` receives every frame, and `network:info` describes the
+/// device. Frames are moved by a kernel thread for each device, which the IRQ handler wakes, and
+/// after each write.
+pub struct NetworkDeviceScheme {
+    name: String,
+    device: Box<NetworkDevice>,
+    /// The IRQ of the device, `None` for a device without one like the loopback device
+    irq: Option<u8>,
+    resources: Intex<Vec<*mut NetworkResource>>,
+    /// Wakes the thread of the device, with room for one wakeup so that sending one from the
+    /// IRQ handler does not allocate
+    wake: WaitQueue<()>,
+}
+
+impl NetworkDeviceScheme {
+    /// Create the scheme, and start its thread, named after it
+    ///
+    /// The scheme must not be dropped, as the thread runs as long as the kernel does.
+    pub fn new(name: String, device: Box<NetworkDevice>, irq: Option<u8>) -> Box<Self> {
+        let mut scheme = box NetworkDeviceScheme {
+            name: name,
+            device: device,
+            irq: irq,
+            resources: Intex::new(Vec::new()),
+            wake: WaitQueue::with_capacity(1),
+        };
+
+        let scheme_ptr = scheme.deref_mut() as *mut NetworkDeviceScheme as usize;
+        Context::kspawn(&format!("k{}", scheme.name), move || {
+            let scheme = unsafe { &mut *(scheme_ptr as *mut NetworkDeviceScheme) };
+            loop {
+                scheme.wake.receive();
+                scheme.sync();
+            }
+        });
+
+        scheme
+    }
+}
+
+impl KScheme for NetworkDeviceScheme {
+    fn scheme(&self) -> &str {
+        &self.name
+    }
+
+    fn open(&mut self, url: Url, _: usize) -> Result<Box<Resource>> {
+        match url.reference().trim_matches('/') {
+            "" => Ok(NetworkResource::new(self)),
+            "info" => {
+                let info = format!("name={}\nmac={}\nmtu={}\nlink={}\n",
+                                   self.device.name(),
+                                   self.device.mac().to_string(),
+                                   self.device.mtu(),
+                                   if self.device.link_up() { "up" } else { "down" });
+                Ok(box VecResource::new(format!("{}:info", self.name), info.into_bytes()))
+            },
+            _ => Err(Error::new(ENOENT)),
+        }
+    }
+
+    fn on_irq(&mut self, irq: u8) {
+        if Some(irq) == self.irq {
+            self.device.interrupt();
+
+            // Move frames in the thread, as receiving allocates them. If a wakeup is already
+            // waiting, the thread will see these frames too
+            self.wake.try_send(());
+        }
+    }
+}
+
+impl NetworkScheme for NetworkDeviceScheme {
+    fn add(&mut self, resource: *mut NetworkResource) {
+        self.resources.lock().push(resource);
+    }
+
+    fn remove(&mut self, resource: *mut NetworkResource) {
+        self.resources.lock().retain(|&ptr| ptr != resource);
+    }
+
+    fn sync(&mut self) {
+        let mut outbound = VecDeque::new();
+        for resource in self.resources.lock().iter() {
+            unsafe { outbound.append(&mut (**resource).outbound.lock()) };
+        }
+
+        for frame in outbound.iter() {
+            if let Err(err) = self.device.transmit(frame) {
+                debugln!("{}: failed to transmit frame of {} bytes: {}", self.device.name(), frame.len(), err);
+            }
+        }
+
+        let mut inbound = VecDeque::new();
+        self.device.receive(&mut inbound);
+
+        let resources = self.resources.lock();
+        for frame in inbound.iter() {
+            for resource in resources.iter() {
+                unsafe { (**resource).inbound.send(frame.clone()) };
+            }
+        }
+    }
+}
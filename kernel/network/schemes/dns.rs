@@ -0,0 +1,35 @@
+use alloc::arc::Arc;
+use alloc::boxed::Box;
+
+use fs::{KScheme, Resource, Url, VecResource};
+
+use network::stack::Stack;
+
+use system::error::Result;
+
+/// The `dns:` scheme
+///
+/// Reading `dns:example.com` returns the address of example.com, followed by a newline.
+pub struct DnsScheme {
+    stack: Arc<Stack>,
+}
+
+impl DnsScheme {
+    pub fn new(stack: Arc<Stack>) -> Box<Self> {
+        box DnsScheme {
+            stack: stack,
+        }
+    }
+}
+
+impl KScheme for DnsScheme {
+    fn scheme(&self) -> &str {
+        "dns"
+    }
+
+    fn open(&mut self, url: Url, _flags: usize) -> Result<Box<Resource>> {
+        let host = url.reference().trim_matches('/');
+        let addr = try!(self.stack.resolve(host));
+        Ok(box VecResource::new(format!("dns:{}", host), format!("{}\n", addr.to_string()).into_bytes()))
+    }
+}
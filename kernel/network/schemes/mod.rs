@@ -0,0 +1,67 @@
+//! The `ping:`, `tcp:` and `udp:` schemes, the sockets of the network stack, `dns:`, its resolver,
+//! and `netcfg:`, its configuration
+
+use core::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
+
+use common::to_num::ToNum;
+
+use network::common::Ipv4Addr;
+use network::stack::Stack;
+
+use system::error::{Error, Result, EADDRINUSE, EINVAL};
+
+pub mod dns;
+pub mod netcfg;
+pub mod ping;
+pub mod tcp;
+pub mod udp;
+
+/// The first port given to sockets that do not choose one
+const EPHEMERAL_START: u16 = 49152;
+
+/// Where the search for a free ephemeral port starts next, so ports are not reused at once
+static EPHEMERAL_NEXT: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// The endpoint in the reference of a socket URL
+pub enum Endpoint {
+    /// `1.2.3.4:80` or `example.com:80`, a port of another host
+    Remote(Ipv4Addr, u16),
+    /// `/80`, a port of this host
+    Local(u16),
+}
+
+/// Parse `host:port` or `/port`, where the port is not 0, resolving the host if it is a name
+pub fn parse_endpoint(stack: &Stack, reference: &str) -> Result<Endpoint> {
+    fn parse_port(string: &str) -> Result<u16> {
+        if string.is_empty() || string.len() > 5 || ! string.bytes().all(|b| b >= b'0' && b <= b'9') {
+            return Err(Error::new(EINVAL));
+        }
+
+        match string.to_num() {
+            port @ 1 ... 65535 => Ok(port as u16),
+            _ => Err(Error::new(EINVAL)),
+        }
+    }
+
+    if reference.starts_with('/') {
+        Ok(Endpoint::Local(try!(parse_port(&reference[1..]))))
+    } else {
+        let mut parts = reference.splitn(2, ':');
+        let host = parts.next().unwrap_or("");
+        let port = try!(parse_port(parts.next().unwrap_or("")));
+        Ok(Endpoint::Remote(try!(stack.resolve(host)), port))
+    }
+}
+
+/// A port from 49152 up that `used` says is free
+pub fn ephemeral_port<F: Fn(u16) -> bool>(used: F) -> Result<u16> {
+    let count = 65536 - EPHEMERAL_START as usize;
+    let start = EPHEMERAL_NEXT.fetch_add(1, Ordering::SeqCst);
+    for i in 0..count {
+        let port = EPHEMERAL_START + ((start + i) % count) as u16;
+        if ! used(port) {
+            return Ok(port);
+        }
+    }
+    Err(Error::new(EADDRINUSE))
+}
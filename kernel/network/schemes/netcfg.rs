@@ -0,0 +1,130 @@
+use alloc::arc::Arc;
+use alloc::boxed::Box;
+
+use collections::String;
+
+use core::{cmp, str};
+
+use fs::{KScheme, Resource, Url};
+
+use network::common::Ipv4Addr;
+use network::stack::Stack;
+
+use system::error::{Error, Result, EACCES, EINVAL};
+
+/// The configuration of the interface
+///
+/// Reading returns the addresses and the state of the DHCP client, one `key=value` per line.
+/// Writing `dhcp` discovers a server again, and writing `dns=1.2.3.4` sets the name server.
+pub struct NetcfgResource {
+    stack: Arc<Stack>,
+    seek: usize,
+}
+
+impl NetcfgResource {
+    fn status(&self) -> String {
+        let config = self.stack.config();
+        let (state, lease) = self.stack.netcfg.status();
+
+        let mut status = format!("mac={}\nip={}\nnetmask={}\ngateway={}\ndns={}\ndhcp={}\n",
+                                 self.stack.mac().to_string(),
+                                 config.ip.to_string(),
+                                 config.netmask.to_string(),
+                                 config.gateway.to_string(),
+                                 config.dns.map_or(String::new(), |dns| dns.to_string()),
+                                 state.name());
+        if let Some(lease) = lease {
+            status.push_str(&format!("server={}\nlease={}\nremaining={}\n",
+                                     lease.server.to_string(),
+                                     lease.secs,
+                                     lease.secs.saturating_sub(lease.elapsed())));
+        }
+        status
+    }
+}
+
+impl Resource for NetcfgResource {
+    fn dup(&self) -> Result<Box<Resource>> {
+        Ok(box NetcfgResource {
+            stack: self.stack.clone(),
+            seek: self.seek,
+        })
+    }
+
+    fn path(&self, buf: &mut [u8]) -> Result<usize> {
+        let path = b"netcfg:";
+
+        for (b, p) in buf.iter_mut().zip(path.iter()) {
+            *b = *p;
+        }
+
+        Ok(cmp::min(buf.len(), path.len()))
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let status = self.status();
+        let data = status.as_bytes();
+
+        let mut i = 0;
+        while i < buf.len() && self.seek < data.len() {
+            buf[i] = data[self.seek];
+            i += 1;
+            self.seek += 1;
+        }
+
+        Ok(i)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        {
+            let contexts = ::env().contexts.read();
+            let current = try!(contexts.current());
+            if current.euid != 0 {
+                return Err(Error::new(EACCES));
+            }
+        }
+
+        let text = try!(str::from_utf8(buf).or(Err(Error::new(EINVAL)))).trim();
+        if text == "dhcp" {
+            self.stack.netcfg.restart();
+        } else if text.starts_with("dns=") {
+            let mut config = self.stack.config();
+            config.dns = Some(try!(Ipv4Addr::parse(&text[4..]).ok_or(Error::new(EINVAL))));
+            self.stack.configure(config);
+        } else {
+            return Err(Error::new(EINVAL));
+        }
+
+        Ok(buf.len())
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// The network configuration scheme, reconfiguring is limited to root
+pub struct NetcfgScheme {
+    stack: Arc<Stack>,
+}
+
+impl NetcfgScheme {
+    pub fn new(stack: Arc<Stack>) -> Box<Self> {
+        box NetcfgScheme {
+            stack: stack,
+        }
+    }
+}
+
+impl KScheme for NetcfgScheme {
+    fn scheme(&self) -> &str {
+        "netcfg"
+    }
+
+    fn open(&mut self, _: Url, _: usize) -> Result<Box<Resource>> {
+        Ok(box NetcfgResource {
+            stack: self.stack.clone(),
+            seek: 0,
+        })
+    }
+}
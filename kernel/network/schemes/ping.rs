@@ -0,0 +1,202 @@
+use alloc::arc::{Arc, Weak};
+use alloc::boxed::Box;
+
+use collections::{BTreeMap, Vec};
+use collections::vec_deque::VecDeque;
+
+use common::time::Duration;
+
+use core::cmp;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use fs::{KScheme, Resource, Url};
+
+use network::common::*;
+use network::icmp::{Icmp, ICMP_ECHO_REQUEST};
+use network::ipv4::IP_PROTO_ICMP;
+use network::stack::Stack;
+
+use sync::{Intex, WaitCondition};
+
+use system::error::{Error, Result, EMSGSIZE, ETIMEDOUT};
+use system::syscall::{POLLIN, POLLOUT};
+
+use super::ephemeral_port;
+
+/// The replies a socket holds before more are dropped
+const PING_QUEUE_MAX: usize = 16;
+
+/// The largest echo data that fits in a frame
+const PING_DATA_MAX: usize = 1500 - 20 - 8;
+
+/// How long a read waits for a reply
+const PING_TIMEOUT_SECS: i64 = 1;
+
+/// Echo requests to one host, and the replies to them
+pub struct PingSocket {
+    ip: Ipv4Addr,
+    /// The identifier of the requests, which replies carry back
+    id: u16,
+    /// The sequence number of the next request
+    sequence: AtomicUsize,
+    /// The data of replies, not read yet
+    replies: Intex<VecDeque<Vec<u8>>>,
+    /// Notified when a reply is received
+    readable: WaitCondition,
+}
+
+/// The ping sockets by identifier
+pub struct PingTable {
+    sockets: Intex<BTreeMap<u16, Weak<PingSocket>>>,
+}
+
+impl PingTable {
+    pub fn new() -> PingTable {
+        PingTable {
+            sockets: Intex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Add a socket for `ip`, with an identifier no other socket has, chosen like a port
+    fn bind(&self, ip: Ipv4Addr) -> Result<Arc<PingSocket>> {
+        let mut sockets = self.sockets.lock();
+        let id = try!(ephemeral_port(|id| sockets.get(&id).map_or(false, |socket| socket.upgrade().is_some())));
+
+        let socket = Arc::new(PingSocket {
+            ip: ip,
+            id: id,
+            sequence: AtomicUsize::new(0),
+            replies: Intex::new(VecDeque::new()),
+            readable: WaitCondition::new(),
+        });
+        sockets.insert(id, Arc::downgrade(&socket));
+        Ok(socket)
+    }
+
+    /// Queue an echo reply from `src` for the socket that sent the request
+    pub fn receive(&self, src: Ipv4Addr, icmp: Icmp) {
+        let id = icmp.header.id.get();
+        let socket = match self.sockets.lock().get(&id).and_then(|socket| socket.upgrade()) {
+            Some(socket) => socket,
+            None => return,
+        };
+        if socket.ip != src {
+            return;
+        }
+
+        {
+            let mut replies = socket.replies.lock();
+            if replies.len() >= PING_QUEUE_MAX {
+                return;
+            }
+            replies.push_back(icmp.data);
+        }
+        unsafe { socket.readable.notify(); }
+    }
+
+    /// Wake the readers, so they notice that they timed out
+    pub fn tick(&self) {
+        let sockets: Vec<Arc<PingSocket>> = self.sockets.lock().values().filter_map(|socket| socket.upgrade()).collect();
+        for socket in sockets.iter() {
+            unsafe { socket.readable.notify(); }
+        }
+    }
+}
+
+/// Echo requests to a host, each write sends one and each read returns the data of a reply
+pub struct PingResource {
+    stack: Arc<Stack>,
+    socket: Arc<PingSocket>,
+}
+
+impl Resource for PingResource {
+    fn dup(&self) -> Result<Box<Resource>> {
+        Ok(box PingResource {
+            stack: self.stack.clone(),
+            socket: self.socket.clone(),
+        })
+    }
+
+    fn path(&self, buf: &mut [u8]) -> Result<usize> {
+        let path = format!("ping:{}", self.socket.ip.to_string());
+
+        let path = path.as_bytes();
+        for (b, p) in buf.iter_mut().zip(path.iter()) {
+            *b = *p;
+        }
+        Ok(cmp::min(buf.len(), path.len()))
+    }
+
+    /// Read the data of the next reply, failing with `ETIMEDOUT` if none arrives within a
+    /// second. A round trip is timed by writing the time a request is sent as its data
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let deadline = Duration::monotonic() + Duration::new(PING_TIMEOUT_SECS, 0);
+        loop {
+            if let Some(data) = self.socket.replies.lock().pop_front() {
+                let count = cmp::min(buf.len(), data.len());
+                buf[.. count].copy_from_slice(&data[.. count]);
+                return Ok(count);
+            }
+
+            if Duration::monotonic() >= deadline {
+                return Err(Error::new(ETIMEDOUT));
+            }
+
+            unsafe { self.socket.readable.wait(); }
+        }
+    }
+
+    /// Send an echo request of `buf`
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        if buf.len() > PING_DATA_MAX {
+            return Err(Error::new(EMSGSIZE));
+        }
+
+        let sequence = self.socket.sequence.fetch_add(1, Ordering::SeqCst) as u16;
+        let icmp = Icmp::echo(ICMP_ECHO_REQUEST, self.socket.id, sequence, buf.to_vec());
+        try!(self.stack.send(self.socket.ip, IP_PROTO_ICMP, icmp.to_bytes()));
+        Ok(buf.len())
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn poll(&self) -> Result<usize> {
+        if self.socket.replies.lock().is_empty() {
+            Ok(POLLOUT)
+        } else {
+            Ok(POLLIN | POLLOUT)
+        }
+    }
+}
+
+/// The `ping:` scheme
+///
+/// `ping:1.2.3.4` sends echo requests to 1.2.3.4, and `ping:example.com` to the address of
+/// example.com.
+pub struct PingScheme {
+    stack: Arc<Stack>,
+}
+
+impl PingScheme {
+    pub fn new(stack: Arc<Stack>) -> Box<Self> {
+        box PingScheme {
+            stack: stack,
+        }
+    }
+}
+
+impl KScheme for PingScheme {
+    fn scheme(&self) -> &str {
+        "ping"
+    }
+
+    fn open(&mut self, url: Url, _flags: usize) -> Result<Box<Resource>> {
+        let ip = try!(self.stack.resolve(url.reference()));
+        Ok(box PingResource {
+            stack: self.stack.clone(),
+            socket: try!(self.stack.ping.bind(ip)),
+        })
+    }
+}
@@ -0,0 +1,855 @@
+use alloc::arc::Arc;
+use alloc::boxed::Box;
+
+use collections::{BTreeMap, Vec};
+use collections::vec_deque::VecDeque;
+
+use common::random;
+
+use core::cmp;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use fs::{KScheme, Resource, Url};
+
+use network::common::*;
+use network::ipv4::IP_PROTO_TCP;
+use network::stack::Stack;
+use network::tcp::{Tcp, TCP_ACK, TCP_FIN, TCP_PSH, TCP_RST, TCP_SYN};
+
+use sync::{Intex, WaitCondition};
+
+use system::error::{Error, Result, ECONNREFUSED, ECONNRESET, EPIPE, ETIMEDOUT};
+use system::syscall::{POLLIN, POLLOUT};
+
+use super::{ephemeral_port, parse_endpoint, Endpoint};
+
+/// The most data sent in one segment, what fits in a frame
+const TCP_MSS: usize = 1500 - 20 - 20;
+
+/// The data a connection buffers in each direction, which is also the largest window
+const TCP_BUFFER: usize = 65535;
+
+/// The ticks before the first retransmission, doubled for each one after
+const TCP_RTO_TICKS: usize = 4;
+/// The retransmissions before a connection times out
+const TCP_RETRIES: usize = 8;
+
+/// The ticks a closed connection lingers in `TimeWait`, to acknowledge a resent FIN
+const TCP_TIME_WAIT_TICKS: usize = 8;
+/// The ticks a closed connection waits for the FIN of the other end in `FinWait2`
+const TCP_FIN_WAIT_TICKS: usize = 240;
+
+/// The connections of a port waiting to be accepted, SYNs beyond this are refused
+const TCP_BACKLOG_MAX: usize = 16;
+/// The ticks a listener outlives its last `open`, so a server that accepts one connection at
+/// a time does not refuse the next
+const TCP_LISTEN_TICKS: usize = 20;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum TcpState {
+    SynSent,
+    SynReceived,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+    TimeWait,
+    Closed,
+}
+
+/// Is sequence number `a` before `b`, allowing for wrapping
+fn seq_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+/// Is sequence number `a` before or at `b`, allowing for wrapping
+fn seq_le(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) <= 0
+}
+
+/// The state of a connection
+struct Tcb {
+    state: TcpState,
+    local_ip: Ipv4Addr,
+    local_port: u16,
+    remote_ip: Ipv4Addr,
+    remote_port: u16,
+    /// The first sequence number not acknowledged, that of the first byte of `unacked` once
+    /// the SYN is
+    snd_una: u32,
+    /// The next sequence number to send
+    snd_nxt: u32,
+    /// The window of the other end
+    snd_wnd: u32,
+    /// The next sequence number expected
+    rcv_nxt: u32,
+    /// Data written and not acknowledged, some of it not sent yet
+    unacked: VecDeque<u8>,
+    /// Data received and not read
+    received: VecDeque<u8>,
+    /// The other end has no more data
+    fin_received: bool,
+    /// The last handle was closed, a FIN follows the data
+    closing: bool,
+    fin_sent: bool,
+    /// The ticks until the unacknowledged data is resent, 0 if there is none
+    retransmit: usize,
+    backoff: usize,
+    retries: usize,
+    /// The ticks until a connection in `TimeWait` or `FinWait2` is closed
+    linger: usize,
+    /// The reason the connection failed
+    error: Option<isize>,
+    /// The port of the listener that accepts this connection, until it is established
+    listener: Option<u16>,
+}
+
+impl Tcb {
+    fn new(state: TcpState, local_ip: Ipv4Addr, local_port: u16, remote_ip: Ipv4Addr, remote_port: u16) -> Tcb {
+        let iss = random::rand() as u32;
+        Tcb {
+            state: state,
+            local_ip: local_ip,
+            local_port: local_port,
+            remote_ip: remote_ip,
+            remote_port: remote_port,
+            snd_una: iss,
+            snd_nxt: iss.wrapping_add(1),
+            snd_wnd: 0,
+            rcv_nxt: 0,
+            unacked: VecDeque::new(),
+            received: VecDeque::new(),
+            fin_received: false,
+            closing: false,
+            fin_sent: false,
+            retransmit: TCP_RTO_TICKS,
+            backoff: 0,
+            retries: 0,
+            linger: 0,
+            error: None,
+            listener: None,
+        }
+    }
+
+    /// The window advertised, the space left in `received`
+    fn window(&self) -> u16 {
+        (TCP_BUFFER - self.received.len()) as u16
+    }
+
+    fn segment(&self, sequence: u32, flags: u16, data: Vec<u8>) -> Tcp {
+        Tcp::new(self.local_ip, self.local_port, self.remote_ip, self.remote_port,
+                 sequence, self.rcv_nxt, flags, self.window(), data)
+    }
+
+    /// The SYN that opens or accepts the connection
+    fn syn(&self) -> Tcp {
+        if self.state == TcpState::SynReceived {
+            self.segment(self.snd_una, TCP_SYN | TCP_ACK, Vec::new())
+        } else {
+            self.segment(self.snd_una, TCP_SYN, Vec::new())
+        }
+    }
+
+    fn ack(&self) -> Tcp {
+        self.segment(self.snd_nxt, TCP_ACK, Vec::new())
+    }
+
+    /// Send the data the window of the other end allows, and the FIN after the last of it once
+    /// the connection is closing
+    ///
+    /// One byte is sent into a closed window, as the acknowledgment of it reopens the window.
+    fn output(&mut self, segments: &mut Vec<Tcp>) {
+        match self.state {
+            TcpState::Established | TcpState::CloseWait |
+            TcpState::FinWait1 | TcpState::Closing | TcpState::LastAck => (),
+            _ => return,
+        }
+
+        let end = self.snd_una.wrapping_add(self.unacked.len() as u32);
+        let limit = self.snd_una.wrapping_add(cmp::max(self.snd_wnd, 1));
+        while seq_lt(self.snd_nxt, end) && seq_lt(self.snd_nxt, limit) {
+            let offset = self.snd_nxt.wrapping_sub(self.snd_una) as usize;
+            let count = cmp::min(TCP_MSS, cmp::min(end.wrapping_sub(self.snd_nxt),
+                                                   limit.wrapping_sub(self.snd_nxt)) as usize);
+            let data = self.unacked.iter().skip(offset).take(count).cloned().collect();
+            segments.push(self.segment(self.snd_nxt, TCP_ACK | TCP_PSH, data));
+            self.snd_nxt = self.snd_nxt.wrapping_add(count as u32);
+        }
+
+        if self.closing && ! self.fin_sent && self.snd_nxt == end {
+            segments.push(self.segment(self.snd_nxt, TCP_ACK | TCP_FIN, Vec::new()));
+            self.snd_nxt = self.snd_nxt.wrapping_add(1);
+            self.fin_sent = true;
+        }
+
+        if self.retransmit == 0 && self.snd_nxt != self.snd_una {
+            self.retransmit = TCP_RTO_TICKS << self.backoff;
+        }
+    }
+
+    /// Resend from the first sequence number that was not acknowledged
+    fn resend(&mut self, segments: &mut Vec<Tcp>) {
+        match self.state {
+            TcpState::SynSent | TcpState::SynReceived => segments.push(self.syn()),
+            _ => {
+                self.snd_nxt = self.snd_una;
+                self.fin_sent = false;
+                self.output(segments);
+            }
+        }
+    }
+
+    /// Fail the connection with `err`
+    fn fail(&mut self, err: isize) {
+        self.error = Some(err);
+        self.state = TcpState::Closed;
+        self.retransmit = 0;
+    }
+
+    /// Handle a segment of this connection
+    fn input(&mut self, tcp: &Tcp, segments: &mut Vec<Tcp>) {
+        let flags = tcp.flags();
+        let sequence = tcp.header.sequence.get();
+        let ack_num = tcp.header.ack_num.get();
+
+        match self.state {
+            TcpState::Closed => return,
+            TcpState::SynSent => {
+                if flags & TCP_ACK == TCP_ACK && ack_num != self.snd_nxt {
+                    if flags & TCP_RST != TCP_RST {
+                        segments.push(self.segment(ack_num, TCP_RST, Vec::new()));
+                    }
+                    return;
+                }
+
+                if flags & TCP_RST == TCP_RST {
+                    if flags & TCP_ACK == TCP_ACK {
+                        self.fail(ECONNREFUSED);
+                    }
+                    return;
+                }
+
+                if flags & TCP_SYN == TCP_SYN {
+                    self.rcv_nxt = sequence.wrapping_add(1);
+                    self.snd_wnd = tcp.header.window_size.get() as u32;
+                    if flags & TCP_ACK == TCP_ACK {
+                        self.snd_una = ack_num;
+                        self.state = TcpState::Established;
+                        self.retransmit = 0;
+                        self.backoff = 0;
+                        self.retries = 0;
+                        segments.push(self.ack());
+                    } else {
+                        // Both ends opened at once
+                        self.state = TcpState::SynReceived;
+                        segments.push(self.syn());
+                    }
+                }
+                return;
+            },
+            _ => (),
+        }
+
+        let window = cmp::max(self.window() as u32, 1);
+        let in_window = seq_le(self.rcv_nxt, sequence) && seq_lt(sequence, self.rcv_nxt.wrapping_add(window));
+
+        if flags & TCP_RST == TCP_RST {
+            if in_window {
+                if self.state == TcpState::SynReceived {
+                    self.state = TcpState::Closed;
+                    self.retransmit = 0;
+                } else {
+                    self.fail(ECONNRESET);
+                }
+            }
+            return;
+        }
+
+        // A SYN resent because our answer was lost
+        if flags & TCP_SYN == TCP_SYN {
+            if self.state == TcpState::SynReceived {
+                segments.push(self.syn());
+            } else {
+                segments.push(self.ack());
+            }
+            return;
+        }
+
+        if flags & TCP_ACK != TCP_ACK {
+            return;
+        }
+
+        if self.state == TcpState::SynReceived {
+            if ack_num != self.snd_nxt {
+                segments.push(self.segment(ack_num, TCP_RST, Vec::new()));
+                return;
+            }
+            self.snd_una = ack_num;
+            self.state = TcpState::Established;
+            self.retransmit = 0;
+            self.backoff = 0;
+            self.retries = 0;
+        }
+
+        if seq_lt(self.snd_una, ack_num) && seq_le(ack_num, self.snd_nxt) {
+            let acked = cmp::min(ack_num.wrapping_sub(self.snd_una) as usize, self.unacked.len());
+            for _ in 0..acked {
+                self.unacked.pop_front();
+            }
+            self.snd_una = ack_num;
+            self.backoff = 0;
+            self.retries = 0;
+            self.retransmit = if self.snd_una != self.snd_nxt {
+                TCP_RTO_TICKS
+            } else {
+                0
+            };
+        }
+        if seq_le(self.snd_una, ack_num) && seq_le(ack_num, self.snd_nxt) {
+            self.snd_wnd = tcp.header.window_size.get() as u32;
+        }
+
+        if self.fin_sent && ack_num == self.snd_nxt {
+            match self.state {
+                TcpState::FinWait1 => {
+                    self.state = TcpState::FinWait2;
+                    self.linger = TCP_FIN_WAIT_TICKS;
+                },
+                TcpState::Closing => {
+                    self.state = TcpState::TimeWait;
+                    self.linger = TCP_TIME_WAIT_TICKS;
+                },
+                TcpState::LastAck => {
+                    self.state = TcpState::Closed;
+                },
+                _ => (),
+            }
+        }
+
+        // Data before `rcv_nxt` was received already, and data after it is dropped until the
+        // data between arrives
+        let end = sequence.wrapping_add(tcp.data.len() as u32);
+        match self.state {
+            TcpState::Established | TcpState::FinWait1 | TcpState::FinWait2 => {
+                if seq_le(sequence, self.rcv_nxt) && seq_lt(self.rcv_nxt, end) {
+                    let start = self.rcv_nxt.wrapping_sub(sequence) as usize;
+                    let count = cmp::min(tcp.data.len() - start, TCP_BUFFER - self.received.len());
+                    self.received.extend(tcp.data[start .. start + count].iter().cloned());
+                    self.rcv_nxt = self.rcv_nxt.wrapping_add(count as u32);
+                }
+
+                if flags & TCP_FIN == TCP_FIN && end == self.rcv_nxt {
+                    self.rcv_nxt = self.rcv_nxt.wrapping_add(1);
+                    self.fin_received = true;
+                    self.state = match self.state {
+                        TcpState::Established => TcpState::CloseWait,
+                        TcpState::FinWait1 => TcpState::Closing,
+                        _ => {
+                            self.linger = TCP_TIME_WAIT_TICKS;
+                            TcpState::TimeWait
+                        },
+                    };
+                }
+            },
+            _ => (),
+        }
+
+        if tcp.sequence_len() > 0 {
+            segments.push(self.ack());
+        }
+
+        self.output(segments);
+    }
+
+    /// Count down the timers
+    fn tick(&mut self, segments: &mut Vec<Tcp>) {
+        match self.state {
+            TcpState::TimeWait | TcpState::FinWait2 => {
+                if self.linger > 1 {
+                    self.linger -= 1;
+                } else {
+                    self.state = TcpState::Closed;
+                }
+                return;
+            },
+            _ => (),
+        }
+
+        if self.retransmit > 0 {
+            self.retransmit -= 1;
+            if self.retransmit == 0 {
+                self.retries += 1;
+                if self.retries > TCP_RETRIES {
+                    self.fail(ETIMEDOUT);
+                } else {
+                    self.backoff = cmp::min(self.backoff + 1, 6);
+                    self.resend(segments);
+                    self.retransmit = TCP_RTO_TICKS << self.backoff;
+                }
+            }
+        }
+    }
+
+    /// Start closing the connection, once its last handle is closed
+    fn close(&mut self, segments: &mut Vec<Tcp>) {
+        self.received.clear();
+        match self.state {
+            TcpState::SynSent | TcpState::SynReceived => {
+                self.state = TcpState::Closed;
+                self.retransmit = 0;
+            },
+            TcpState::Established => {
+                self.closing = true;
+                self.state = TcpState::FinWait1;
+                self.output(segments);
+            },
+            TcpState::CloseWait => {
+                self.closing = true;
+                self.state = TcpState::LastAck;
+                self.output(segments);
+            },
+            _ => (),
+        }
+    }
+}
+
+/// A connection, shared by its handles, its listener and the table
+pub struct TcpSocket {
+    tcb: Intex<Tcb>,
+    /// Notified when data, an acknowledgment or a change of state arrives
+    condition: WaitCondition,
+    /// The open handles, the connection is closed when the last one is
+    handles: AtomicUsize,
+}
+
+impl TcpSocket {
+    fn new(tcb: Tcb) -> Arc<TcpSocket> {
+        Arc::new(TcpSocket {
+            tcb: Intex::new(tcb),
+            condition: WaitCondition::new(),
+            handles: AtomicUsize::new(0),
+        })
+    }
+}
+
+/// A port that `tcp:/port` is waiting for connections on
+struct TcpListener {
+    /// Connections that are established and not accepted yet
+    backlog: Intex<VecDeque<Arc<TcpSocket>>>,
+    /// Notified when a connection is added to the backlog
+    condition: WaitCondition,
+    /// The contexts waiting for a connection
+    waiting: AtomicUsize,
+    /// The ticks since nobody waited for a connection
+    idle: AtomicUsize,
+}
+
+/// Write `segments` to `dst`, returning the first error
+fn transmit(stack: &Stack, dst: Ipv4Addr, segments: Vec<Tcp>) -> Result<()> {
+    let mut result = Ok(());
+    for tcp in segments.iter() {
+        let sent = stack.send(dst, IP_PROTO_TCP, tcp.to_bytes());
+        if result.is_ok() {
+            result = sent;
+        }
+    }
+    result
+}
+
+/// The TCP connections and listeners
+pub struct TcpTable {
+    sockets: Intex<Vec<Arc<TcpSocket>>>,
+    listeners: Intex<BTreeMap<u16, Arc<TcpListener>>>,
+}
+
+impl TcpTable {
+    pub fn new() -> TcpTable {
+        TcpTable {
+            sockets: Intex::new(Vec::new()),
+            listeners: Intex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Is `port` used by a connection or a listener
+    pub fn in_use(&self, port: u16) -> bool {
+        self.listeners.lock().contains_key(&port) ||
+        self.sockets.lock().iter().any(|socket| socket.tcb.lock().local_port == port)
+    }
+
+    /// The connection between port `dst` of this host and port `src` of `src_ip`
+    fn find(&self, src_ip: Ipv4Addr, src: u16, dst: u16) -> Option<Arc<TcpSocket>> {
+        self.sockets.lock().iter().find(|socket| {
+            let tcb = socket.tcb.lock();
+            tcb.local_port == dst && tcb.remote_ip == src_ip && tcb.remote_port == src
+        }).cloned()
+    }
+
+    /// Handle a segment from `src` to `dst`
+    pub fn receive(&self, stack: &Stack, src: Ipv4Addr, dst: Ipv4Addr, data: Vec<u8>) {
+        let tcp = match Tcp::from_bytes(data) {
+            Some(tcp) => tcp,
+            None => return,
+        };
+        if ! tcp.valid(src, dst) {
+            return;
+        }
+
+        let src_port = tcp.header.src.get();
+        let dst_port = tcp.header.dst.get();
+
+        let socket = match self.find(src, src_port, dst_port) {
+            Some(socket) => socket,
+            None => {
+                // A reset is never answered with a reset
+                if tcp.flags() & TCP_RST == TCP_RST {
+                    return;
+                }
+                if tcp.flags() & (TCP_SYN | TCP_ACK | TCP_RST) == TCP_SYN {
+                    if self.listen_syn(stack, src, dst, &tcp) {
+                        return;
+                    }
+                }
+                let _ = transmit(stack, src, vec![reset(src, dst, &tcp)]);
+                return;
+            }
+        };
+
+        let mut segments = Vec::new();
+        let accept = {
+            let mut tcb = socket.tcb.lock();
+            tcb.input(&tcp, &mut segments);
+            match tcb.state {
+                TcpState::SynReceived | TcpState::Closed => None,
+                _ => tcb.listener.take(),
+            }
+        };
+        let _ = transmit(stack, src, segments);
+
+        if let Some(port) = accept {
+            let listener = self.listeners.lock().get(&port).cloned();
+            match listener {
+                Some(listener) => {
+                    listener.backlog.lock().push_back(socket.clone());
+                    unsafe { listener.condition.notify(); }
+                },
+                None => {
+                    let mut segments = Vec::new();
+                    socket.tcb.lock().close(&mut segments);
+                    let _ = transmit(stack, src, segments);
+                }
+            }
+        }
+
+        unsafe { socket.condition.notify(); }
+    }
+
+    /// Answer a SYN to a port with a listener, returning false if there is none or its backlog
+    /// is full
+    fn listen_syn(&self, stack: &Stack, src: Ipv4Addr, dst: Ipv4Addr, tcp: &Tcp) -> bool {
+        let port = tcp.header.dst.get();
+        let listener = match self.listeners.lock().get(&port) {
+            Some(listener) => listener.clone(),
+            None => return false,
+        };
+
+        let mut sockets = self.sockets.lock();
+        let pending = sockets.iter().filter(|socket| socket.tcb.lock().listener == Some(port)).count();
+        if pending + listener.backlog.lock().len() >= TCP_BACKLOG_MAX {
+            return false;
+        }
+
+        let mut tcb = Tcb::new(TcpState::SynReceived, dst, port, src, tcp.header.src.get());
+        tcb.rcv_nxt = tcp.header.sequence.get().wrapping_add(1);
+        tcb.snd_wnd = tcp.header.window_size.get() as u32;
+        tcb.listener = Some(port);
+        let syn = tcb.syn();
+
+        sockets.push(TcpSocket::new(tcb));
+        drop(sockets);
+
+        let _ = transmit(stack, src, vec![syn]);
+        true
+    }
+
+    /// Resend what was not acknowledged, and drop closed connections and idle listeners
+    ///
+    /// Every waiting context is woken, so one that missed a notification between checking its
+    /// connection and blocking waits no longer than a tick.
+    pub fn tick(&self, stack: &Stack) {
+        let sockets = self.sockets.lock().clone();
+        for socket in sockets.iter() {
+            let mut segments = Vec::new();
+            let dst = {
+                let mut tcb = socket.tcb.lock();
+                tcb.tick(&mut segments);
+                tcb.remote_ip
+            };
+            let _ = transmit(stack, dst, segments);
+            unsafe { socket.condition.notify(); }
+        }
+        self.sockets.lock().retain(|socket| socket.tcb.lock().state != TcpState::Closed);
+
+        let mut listeners = self.listeners.lock();
+        let mut idle = Vec::new();
+        for (&port, listener) in listeners.iter() {
+            if listener.waiting.load(Ordering::SeqCst) == 0 && listener.backlog.lock().is_empty() {
+                if listener.idle.fetch_add(1, Ordering::SeqCst) >= TCP_LISTEN_TICKS {
+                    idle.push(port);
+                }
+            } else {
+                listener.idle.store(0, Ordering::SeqCst);
+            }
+            unsafe { listener.condition.notify(); }
+        }
+        for port in idle.iter() {
+            listeners.remove(port);
+        }
+    }
+}
+
+/// The reset answering a segment that belongs to no connection
+fn reset(src: Ipv4Addr, dst: Ipv4Addr, tcp: &Tcp) -> Tcp {
+    let src_port = tcp.header.src.get();
+    let dst_port = tcp.header.dst.get();
+    if tcp.flags() & TCP_ACK == TCP_ACK {
+        Tcp::new(dst, dst_port, src, src_port, tcp.header.ack_num.get(), 0, TCP_RST, 0, Vec::new())
+    } else {
+        let ack_num = tcp.header.sequence.get().wrapping_add(tcp.sequence_len());
+        Tcp::new(dst, dst_port, src, src_port, 0, ack_num, TCP_RST | TCP_ACK, 0, Vec::new())
+    }
+}
+
+/// A TCP connection, reading and writing its stream
+pub struct TcpResource {
+    stack: Arc<Stack>,
+    socket: Arc<TcpSocket>,
+}
+
+impl TcpResource {
+    fn new(stack: Arc<Stack>, socket: Arc<TcpSocket>) -> TcpResource {
+        socket.handles.fetch_add(1, Ordering::SeqCst);
+        TcpResource {
+            stack: stack,
+            socket: socket,
+        }
+    }
+}
+
+impl Resource for TcpResource {
+    fn dup(&self) -> Result<Box<Resource>> {
+        Ok(box TcpResource::new(self.stack.clone(), self.socket.clone()))
+    }
+
+    fn path(&self, buf: &mut [u8]) -> Result<usize> {
+        let path = {
+            let tcb = self.socket.tcb.lock();
+            format!("tcp:{}:{}/{}", tcb.remote_ip.to_string(), tcb.remote_port, tcb.local_port)
+        };
+
+        let path = path.as_bytes();
+        for (b, p) in buf.iter_mut().zip(path.iter()) {
+            *b = *p;
+        }
+        Ok(cmp::min(buf.len(), path.len()))
+    }
+
+    /// Read the data received, blocking until there is some. Returns 0 once the other end has
+    /// sent all of its data
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        loop {
+            let (result, update) = {
+                let mut tcb = self.socket.tcb.lock();
+                if ! tcb.received.is_empty() {
+                    let closed = (tcb.window() as usize) < TCP_MSS;
+
+                    let mut count = 0;
+                    while count < buf.len() {
+                        match tcb.received.pop_front() {
+                            Some(b) => buf[count] = b,
+                            None => break,
+                        }
+                        count += 1;
+                    }
+
+                    // Tell the other end that the window it was waiting on has opened
+                    let update = if closed && tcb.window() as usize >= TCP_MSS {
+                        Some((tcb.remote_ip, tcb.ack()))
+                    } else {
+                        None
+                    };
+                    (Some(Ok(count)), update)
+                } else if let Some(err) = tcb.error {
+                    (Some(Err(Error::new(err))), None)
+                } else if tcb.fin_received || tcb.state == TcpState::Closed {
+                    (Some(Ok(0)), None)
+                } else {
+                    (None, None)
+                }
+            };
+
+            if let Some((dst, ack)) = update {
+                let _ = transmit(&self.stack, dst, vec![ack]);
+            }
+
+            match result {
+                Some(result) => return result,
+                None => unsafe { self.socket.condition.wait() },
+            }
+        }
+    }
+
+    /// Queue `buf` to be sent, blocking while the send buffer is full
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let mut written = 0;
+        while written < buf.len() {
+            let sent = {
+                let mut tcb = self.socket.tcb.lock();
+                if let Some(err) = tcb.error {
+                    return if written > 0 { Ok(written) } else { Err(Error::new(err)) };
+                }
+                match tcb.state {
+                    TcpState::Established | TcpState::CloseWait => (),
+                    _ => return Err(Error::new(EPIPE)),
+                }
+
+                let count = cmp::min(buf.len() - written, TCP_BUFFER - tcb.unacked.len());
+                if count > 0 {
+                    tcb.unacked.extend(buf[written .. written + count].iter().cloned());
+                    written += count;
+
+                    let mut segments = Vec::new();
+                    tcb.output(&mut segments);
+                    Some((tcb.remote_ip, segments))
+                } else {
+                    None
+                }
+            };
+
+            match sent {
+                Some((dst, segments)) => try!(transmit(&self.stack, dst, segments)),
+                None => unsafe { self.socket.condition.wait() },
+            }
+        }
+        Ok(written)
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn poll(&self) -> Result<usize> {
+        let tcb = self.socket.tcb.lock();
+
+        let mut events = 0;
+        if ! tcb.received.is_empty() || tcb.fin_received || tcb.state == TcpState::Closed {
+            events |= POLLIN;
+        }
+        match tcb.state {
+            TcpState::Established | TcpState::CloseWait => if tcb.unacked.len() < TCP_BUFFER {
+                events |= POLLOUT;
+            },
+            _ => (),
+        }
+        Ok(events)
+    }
+}
+
+impl Drop for TcpResource {
+    fn drop(&mut self) {
+        if self.socket.handles.fetch_sub(1, Ordering::SeqCst) == 1 {
+            let mut segments = Vec::new();
+            let dst = {
+                let mut tcb = self.socket.tcb.lock();
+                tcb.close(&mut segments);
+                tcb.remote_ip
+            };
+            let _ = transmit(&self.stack, dst, segments);
+        }
+    }
+}
+
+/// The `tcp:` scheme
+///
+/// `tcp:1.2.3.4:80` connects to port 80 of 1.2.3.4, from a free local port, and
+/// `tcp:example.com:80` to that of the address of example.com. `tcp:/80` waits for
+/// a connection to port 80, and each `open` of it accepts one.
+pub struct TcpScheme {
+    stack: Arc<Stack>,
+}
+
+impl TcpScheme {
+    pub fn new(stack: Arc<Stack>) -> Box<Self> {
+        box TcpScheme {
+            stack: stack,
+        }
+    }
+
+    /// Open a connection to `port` of `ip`, blocking until it is established or refused
+    fn connect(&self, ip: Ipv4Addr, port: u16) -> Result<Arc<TcpSocket>> {
+        let socket = {
+            let table = &self.stack.tcp;
+            let local_port = try!(ephemeral_port(|port| table.in_use(port)));
+            TcpSocket::new(Tcb::new(TcpState::SynSent, self.stack.source(ip), local_port, ip, port))
+        };
+        self.stack.tcp.sockets.lock().push(socket.clone());
+
+        let syn = socket.tcb.lock().syn();
+        if let Err(err) = transmit(&self.stack, ip, vec![syn]) {
+            socket.tcb.lock().fail(err.errno);
+            return Err(err);
+        }
+
+        loop {
+            {
+                let tcb = socket.tcb.lock();
+                match tcb.state {
+                    TcpState::SynSent | TcpState::SynReceived => (),
+                    TcpState::Closed => return Err(Error::new(tcb.error.unwrap_or(ECONNREFUSED))),
+                    _ => return Ok(socket.clone()),
+                }
+            }
+
+            unsafe { socket.condition.wait(); }
+        }
+    }
+
+    /// Accept a connection to `port`, blocking until there is one
+    fn accept(&self, port: u16) -> Result<Arc<TcpSocket>> {
+        let listener = self.stack.tcp.listeners.lock().entry(port).or_insert_with(|| {
+            Arc::new(TcpListener {
+                backlog: Intex::new(VecDeque::new()),
+                condition: WaitCondition::new(),
+                waiting: AtomicUsize::new(0),
+                idle: AtomicUsize::new(0),
+            })
+        }).clone();
+
+        listener.waiting.fetch_add(1, Ordering::SeqCst);
+        loop {
+            let socket = listener.backlog.lock().pop_front();
+            if let Some(socket) = socket {
+                listener.waiting.fetch_sub(1, Ordering::SeqCst);
+                return Ok(socket);
+            }
+
+            unsafe { listener.condition.wait(); }
+        }
+    }
+}
+
+impl KScheme for TcpScheme {
+    fn scheme(&self) -> &str {
+        "tcp"
+    }
+
+    fn open(&mut self, url: Url, _flags: usize) -> Result<Box<Resource>> {
+        let socket = match try!(parse_endpoint(&self.stack, url.reference())) {
+            Endpoint::Remote(ip, port) => try!(self.connect(ip, port)),
+            Endpoint::Local(port) => try!(self.accept(port)),
+        };
+
+        Ok(box TcpResource::new(self.stack.clone(), socket))
+    }
+}
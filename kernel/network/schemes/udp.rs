@@ -0,0 +1,230 @@
+use alloc::arc::{Arc, Weak};
+use alloc::boxed::Box;
+
+use collections::{BTreeMap, Vec};
+use collections::vec_deque::VecDeque;
+
+use core::cmp;
+
+use fs::{KScheme, Resource, Url};
+
+use network::common::*;
+use network::ipv4::IP_PROTO_UDP;
+use network::stack::Stack;
+use network::udp::Udp;
+
+use sync::{Intex, WaitCondition};
+
+use system::error::{Error, Result, EADDRINUSE, EDESTADDRREQ, EMSGSIZE};
+use system::syscall::{POLLIN, POLLOUT};
+
+use super::{ephemeral_port, parse_endpoint, Endpoint};
+
+/// The datagrams a socket holds before more are dropped
+const UDP_QUEUE_MAX: usize = 64;
+
+/// The largest datagram that fits in a frame, as packets are not fragmented
+const UDP_DATA_MAX: usize = 1500 - 20 - 8;
+
+/// A UDP port, and the datagrams received on it
+pub struct UdpSocket {
+    port: u16,
+    /// The only host and port datagrams are received from and sent to, if the socket is
+    /// connected
+    remote: Option<(Ipv4Addr, u16)>,
+    /// Datagrams by source, not read yet
+    datagrams: Intex<VecDeque<(Ipv4Addr, u16, Vec<u8>)>>,
+    /// The source of the last datagram read, which a socket that is not connected answers
+    last: Intex<Option<(Ipv4Addr, u16)>>,
+    /// Notified when a datagram is received
+    readable: WaitCondition,
+}
+
+impl UdpSocket {
+    /// The local port
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Take the next datagram, without blocking, for sockets used by the kernel itself
+    pub fn try_recv(&self) -> Option<(Ipv4Addr, u16, Vec<u8>)> {
+        self.datagrams.lock().pop_front()
+    }
+}
+
+/// The UDP sockets by port
+pub struct UdpTable {
+    sockets: Intex<BTreeMap<u16, Weak<UdpSocket>>>,
+}
+
+impl UdpTable {
+    pub fn new() -> UdpTable {
+        UdpTable {
+            sockets: Intex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Is `port` used by a socket
+    pub fn in_use(&self, port: u16) -> bool {
+        self.sockets.lock().get(&port).map_or(false, |socket| socket.upgrade().is_some())
+    }
+
+    /// Add a socket on `port`, or on a free port if it is 0
+    pub fn bind(&self, port: u16, remote: Option<(Ipv4Addr, u16)>) -> Result<Arc<UdpSocket>> {
+        let mut sockets = self.sockets.lock();
+        let port = if port == 0 {
+            try!(ephemeral_port(|port| sockets.get(&port).map_or(false, |socket| socket.upgrade().is_some())))
+        } else if sockets.get(&port).map_or(false, |socket| socket.upgrade().is_some()) {
+            return Err(Error::new(EADDRINUSE));
+        } else {
+            port
+        };
+
+        let socket = Arc::new(UdpSocket {
+            port: port,
+            remote: remote,
+            datagrams: Intex::new(VecDeque::new()),
+            last: Intex::new(None),
+            readable: WaitCondition::new(),
+        });
+        sockets.insert(port, Arc::downgrade(&socket));
+        Ok(socket)
+    }
+
+    /// Queue a datagram for the socket of its port
+    pub fn receive(&self, src: Ipv4Addr, dst: Ipv4Addr, data: Vec<u8>) {
+        if let Some(udp) = Udp::from_bytes(data) {
+            if ! udp.valid(src, dst) {
+                return;
+            }
+
+            let port = udp.header.dst.get();
+            let socket = match self.sockets.lock().get(&port).and_then(|socket| socket.upgrade()) {
+                Some(socket) => socket,
+                None => return,
+            };
+
+            let src_port = udp.header.src.get();
+            if let Some((ip, port)) = socket.remote {
+                if ip != src || port != src_port {
+                    return;
+                }
+            }
+
+            {
+                let mut datagrams = socket.datagrams.lock();
+                if datagrams.len() >= UDP_QUEUE_MAX {
+                    return;
+                }
+                datagrams.push_back((src, src_port, udp.payload().to_vec()));
+            }
+            unsafe { socket.readable.notify(); }
+        }
+    }
+}
+
+/// A UDP socket, sending each write as a datagram and reading one datagram at a time
+pub struct UdpResource {
+    stack: Arc<Stack>,
+    socket: Arc<UdpSocket>,
+}
+
+impl Resource for UdpResource {
+    fn dup(&self) -> Result<Box<Resource>> {
+        Ok(box UdpResource {
+            stack: self.stack.clone(),
+            socket: self.socket.clone(),
+        })
+    }
+
+    fn path(&self, buf: &mut [u8]) -> Result<usize> {
+        let path = match self.socket.remote {
+            Some((ip, port)) => format!("udp:{}:{}/{}", ip.to_string(), port, self.socket.port),
+            None => format!("udp:/{}", self.socket.port),
+        };
+
+        let path = path.as_bytes();
+        for (b, p) in buf.iter_mut().zip(path.iter()) {
+            *b = *p;
+        }
+        Ok(cmp::min(buf.len(), path.len()))
+    }
+
+    /// Read the next datagram, the rest of it is dropped if it does not fit
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        loop {
+            if let Some((ip, port, data)) = self.socket.datagrams.lock().pop_front() {
+                *self.socket.last.lock() = Some((ip, port));
+
+                let count = cmp::min(buf.len(), data.len());
+                buf[.. count].copy_from_slice(&data[.. count]);
+                return Ok(count);
+            }
+
+            unsafe { self.socket.readable.wait(); }
+        }
+    }
+
+    /// Send a datagram to the connected host, or to the source of the last datagram read
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        if buf.len() > UDP_DATA_MAX {
+            return Err(Error::new(EMSGSIZE));
+        }
+
+        let (ip, port) = match self.socket.remote.or(*self.socket.last.lock()) {
+            Some(remote) => remote,
+            None => return Err(Error::new(EDESTADDRREQ)),
+        };
+
+        let udp = Udp::new(self.stack.source(ip), self.socket.port, ip, port, buf.to_vec());
+        try!(self.stack.send(ip, IP_PROTO_UDP, udp.to_bytes()));
+        Ok(buf.len())
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn poll(&self) -> Result<usize> {
+        if self.socket.datagrams.lock().is_empty() {
+            Ok(POLLOUT)
+        } else {
+            Ok(POLLIN | POLLOUT)
+        }
+    }
+}
+
+/// The `udp:` scheme
+///
+/// `udp:1.2.3.4:53` sends to and receives from port 53 of 1.2.3.4, from a free local port.
+/// `udp:/53` receives from any host on port 53, and writes answer the last datagram read.
+pub struct UdpScheme {
+    stack: Arc<Stack>,
+}
+
+impl UdpScheme {
+    pub fn new(stack: Arc<Stack>) -> Box<Self> {
+        box UdpScheme {
+            stack: stack,
+        }
+    }
+}
+
+impl KScheme for UdpScheme {
+    fn scheme(&self) -> &str {
+        "udp"
+    }
+
+    fn open(&mut self, url: Url, _flags: usize) -> Result<Box<Resource>> {
+        let socket = match try!(parse_endpoint(&self.stack, url.reference())) {
+            Endpoint::Remote(ip, port) => try!(self.stack.udp.bind(0, Some((ip, port)))),
+            Endpoint::Local(port) => try!(self.stack.udp.bind(port, None)),
+        };
+
+        Ok(box UdpResource {
+            stack: self.stack.clone(),
+            socket: socket,
+        })
+    }
+}
+
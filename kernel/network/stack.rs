@@ -0,0 +1,490 @@
+//! The protocol layers, over the first network device and the loopback device
+//!
+//! `knetd` reads every frame of `network:` and hands it to ARP and IPv4, which pass segments and
+//! datagrams to the sockets of `tcp:` and `udp:`, and echo replies to those of `ping:`. Echo
+//! requests are answered by the stack itself. Packets are sent by the context that produces
+//! them, through the same resource. Packets to this host, and to 127.0.0.0/8, are written to
+//! `lo:` instead, which `knetlo` reads them back from, so sockets work without a network device.
+//! A packet to a host whose hardware address is not known waits for the answer to an ARP request,
+//! and `knettimer` resends requests, as well as the TCP segments that were not acknowledged. It
+//! also runs the DHCP client that configures the addresses.
+
+use alloc::arc::Arc;
+
+use arch::context::{context_switch, Context};
+
+use collections::{BTreeMap, Vec};
+
+use common::random;
+use common::time::Duration;
+
+use core::cmp;
+
+use fs::{Resource, Url};
+
+use network::arp::{Arp, ARP_REPLY, ARP_REQUEST};
+use network::common::*;
+use network::ethernet::{EthernetII, EthernetIIHeader};
+use network::icmp::{Icmp, ICMP_ECHO_REPLY, ICMP_ECHO_REQUEST};
+use network::ipv4::{Ipv4, IP_PROTO_ICMP, IP_PROTO_TCP, IP_PROTO_UDP};
+use network::schemes::ping::PingTable;
+use network::schemes::tcp::TcpTable;
+use network::netcfg::Netcfg;
+use network::resolver::Resolver;
+use network::schemes::udp::UdpTable;
+
+use sync::Intex;
+
+use system::error::{Error, Result, ENETDOWN};
+
+/// The ethertype of ARP messages
+pub const ETHERTYPE_ARP: u16 = 0x806;
+/// The ethertype of IPv4 packets
+pub const ETHERTYPE_IPV4: u16 = 0x800;
+
+/// The time between runs of `knettimer`
+pub const TICK_NANOS: i32 = 250000000;
+
+/// The ARP requests sent for a host before the packets waiting for it are dropped
+const ARP_TRIES: usize = 4;
+/// The packets that wait for the hardware address of a host, more are dropped
+const ARP_PENDING_MAX: usize = 16;
+
+/// The addresses of the interface
+#[derive(Copy, Clone)]
+pub struct Config {
+    pub ip: Ipv4Addr,
+    pub netmask: Ipv4Addr,
+    pub gateway: Ipv4Addr,
+    /// The server that names are resolved with
+    pub dns: Option<Ipv4Addr>,
+}
+
+impl Config {
+    /// The addresses used until DHCP configures others, those of `tap_redox`
+    pub fn fallback() -> Config {
+        Config {
+            ip: IP_ADDR,
+            netmask: NETMASK_ADDR,
+            gateway: GATEWAY_ADDR,
+            dns: None,
+        }
+    }
+}
+
+/// Packets waiting for the hardware address of a host
+struct ArpPending {
+    /// IPv4 packets, with their headers
+    packets: Vec<Vec<u8>>,
+    /// Requests sent so far
+    tries: usize,
+}
+
+/// The addresses and tables of the stack
+struct Interface {
+    /// The resource of `network:` read by `knetd`, which frames are written to, `None` until it
+    /// is opened
+    link: Option<*mut Resource>,
+    /// The resource of `lo:` read by `knetlo`
+    loopback: Option<*mut Resource>,
+    mac: MacAddr,
+    ip: Ipv4Addr,
+    netmask: Ipv4Addr,
+    gateway: Ipv4Addr,
+    dns: Option<Ipv4Addr>,
+    /// Hardware addresses by IPv4 address
+    arp: BTreeMap<Ipv4Addr, MacAddr>,
+    /// Packets by the address of the next hop that they wait for
+    pending: BTreeMap<Ipv4Addr, ArpPending>,
+    /// The identification of the next IPv4 packet
+    next_id: u16,
+}
+
+impl Interface {
+    /// The host that a packet to `dst` is sent to, `dst` itself if it is on the local network
+    fn next_hop(&self, dst: Ipv4Addr) -> Ipv4Addr {
+        if dst.to_u32() & self.netmask.to_u32() == self.ip.to_u32() & self.netmask.to_u32() {
+            dst
+        } else {
+            self.gateway
+        }
+    }
+
+    /// Is `dst` this host, so packets to it are sent through `lo:`
+    fn is_local(&self, dst: Ipv4Addr) -> bool {
+        dst.is_loopback() || dst == self.ip
+    }
+
+    /// Is `addr` a broadcast address of the local network
+    fn is_broadcast(&self, addr: Ipv4Addr) -> bool {
+        addr == LIMITED_BROADCAST_ADDR || addr.to_u32() == self.ip.to_u32() | ! self.netmask.to_u32()
+    }
+
+    /// Write a frame of `data` to `dst`
+    fn transmit(&self, dst: MacAddr, ethertype: u16, data: Vec<u8>) -> Result<()> {
+        let link = try!(self.link.ok_or(Error::new(ENETDOWN)));
+        let frame = EthernetII {
+            header: EthernetIIHeader {
+                dst: dst,
+                src: self.mac,
+                ethertype: n16::new(ethertype),
+            },
+            data: data,
+        };
+        // The resource locks its own queues, so it is written while `knetd` blocks reading it
+        unsafe { (*link).write(&frame.to_bytes()) }.and(Ok(()))
+    }
+
+    /// Write a packet to `lo:`, which receives it back
+    fn loop_back(&self, packet: Vec<u8>) -> Result<()> {
+        let link = try!(self.loopback.ok_or(Error::new(ENETDOWN)));
+        let frame = EthernetII {
+            header: EthernetIIHeader {
+                dst: MacAddr { bytes: [0; 6] },
+                src: MacAddr { bytes: [0; 6] },
+                ethertype: n16::new(ETHERTYPE_IPV4),
+            },
+            data: packet,
+        };
+        unsafe { (*link).write(&frame.to_bytes()) }.and(Ok(()))
+    }
+
+    /// Ask every host for the hardware address of `ip`
+    fn request(&self, ip: Ipv4Addr) -> Result<()> {
+        let arp = Arp::new(ARP_REQUEST, self.mac, self.ip, BROADCAST_MAC_ADDR, ip);
+        self.transmit(BROADCAST_MAC_ADDR, ETHERTYPE_ARP, arp.to_bytes())
+    }
+}
+
+/// The network stack, shared by `knetd`, `knettimer` and the schemes
+pub struct Stack {
+    interface: Intex<Interface>,
+    pub netcfg: Netcfg,
+    resolver: Resolver,
+    pub ping: PingTable,
+    pub tcp: TcpTable,
+    pub udp: UdpTable,
+}
+
+impl Stack {
+    pub fn new() -> Arc<Stack> {
+        let config = Config::fallback();
+        Arc::new(Stack {
+            interface: Intex::new(Interface {
+                link: None,
+                loopback: None,
+                mac: unsafe { MAC_ADDR },
+                ip: config.ip,
+                netmask: config.netmask,
+                gateway: config.gateway,
+                dns: config.dns,
+                arp: BTreeMap::new(),
+                pending: BTreeMap::new(),
+                next_id: random::rand() as u16,
+            }),
+            netcfg: Netcfg::new(),
+            resolver: Resolver::new(),
+            ping: PingTable::new(),
+            tcp: TcpTable::new(),
+            udp: UdpTable::new(),
+        })
+    }
+
+    /// Start `knetd`, `knetlo` and `knettimer`
+    ///
+    /// `knetd` exits if there is no network device, and sending to other hosts then fails with
+    /// `ENETDOWN`.
+    pub fn start(stack: Arc<Stack>) {
+        let receiver = stack.clone();
+        Context::kspawn("knetd", move || receiver.run_link("network:", false));
+
+        let receiver = stack.clone();
+        Context::kspawn("knetlo", move || receiver.run_link("lo:", true));
+
+        Context::kspawn("knettimer", move || {
+            loop {
+                sleep(Duration::new(0, TICK_NANOS));
+                stack.tick();
+                stack.netcfg.tick(&stack);
+                stack.ping.tick();
+                stack.tcp.tick(&stack);
+            }
+        });
+    }
+
+    /// Open the device at `path` and hand the frames read from it to the protocols, until reading
+    /// fails
+    fn run_link(&self, path: &str, loopback: bool) {
+        let mut link = match Url::from_str(path).and_then(|url| url.open()) {
+            Ok(link) => link,
+            Err(err) => {
+                debugln!("knetd: failed to open {} {}", path, err);
+                return;
+            },
+        };
+
+        {
+            let mut interface = self.interface.lock();
+            if loopback {
+                interface.loopback = Some(&mut *link as *mut Resource);
+            } else {
+                interface.link = Some(&mut *link as *mut Resource);
+                interface.mac = unsafe { MAC_ADDR };
+            }
+        }
+
+        let mut buf = [0; 16384];
+        loop {
+            match link.read(&mut buf) {
+                Ok(count) => {
+                    let frame = buf[.. cmp::min(count, buf.len())].to_vec();
+                    if loopback {
+                        self.receive_loopback(frame);
+                    } else {
+                        self.receive(frame);
+                    }
+                },
+                Err(err) => {
+                    debugln!("knetd: failed to read {} {}", path, err);
+                    break;
+                },
+            }
+        }
+
+        let mut interface = self.interface.lock();
+        if loopback {
+            interface.loopback = None;
+        } else {
+            interface.link = None;
+        }
+    }
+
+    /// The address of this host
+    pub fn ip(&self) -> Ipv4Addr {
+        self.interface.lock().ip
+    }
+
+    /// The address of `host`, an address or a name that is resolved with DNS
+    pub fn resolve(&self, host: &str) -> Result<Ipv4Addr> {
+        self.resolver.resolve(self, host)
+    }
+
+    /// The hardware address of the device
+    pub fn mac(&self) -> MacAddr {
+        self.interface.lock().mac
+    }
+
+    /// The addresses of the interface
+    pub fn config(&self) -> Config {
+        let interface = self.interface.lock();
+        Config {
+            ip: interface.ip,
+            netmask: interface.netmask,
+            gateway: interface.gateway,
+            dns: interface.dns,
+        }
+    }
+
+    /// Change the addresses of the interface
+    pub fn configure(&self, config: Config) {
+        let mut interface = self.interface.lock();
+        interface.ip = config.ip;
+        interface.netmask = config.netmask;
+        interface.gateway = config.gateway;
+        interface.dns = config.dns;
+    }
+
+    /// Send an IPv4 packet of `data` to `dst`, which waits for an ARP reply if the hardware
+    /// address of the next hop is not known yet. Packets are not fragmented
+    pub fn send(&self, dst: Ipv4Addr, proto: u8, data: Vec<u8>) -> Result<()> {
+        let src = self.source(dst);
+        self.send_from(src, dst, proto, data)
+    }
+
+    /// The address that packets to `dst` are sent from, `dst` itself for 127.0.0.0/8
+    pub fn source(&self, dst: Ipv4Addr) -> Ipv4Addr {
+        if dst.is_loopback() {
+            dst
+        } else {
+            self.ip()
+        }
+    }
+
+    /// Send a packet from `src` rather than the address of the interface, for DHCP, which sends
+    /// from 0.0.0.0 until it has an address
+    pub fn send_from(&self, src: Ipv4Addr, dst: Ipv4Addr, proto: u8, data: Vec<u8>) -> Result<()> {
+        let mut interface = self.interface.lock();
+        interface.next_id = interface.next_id.wrapping_add(1);
+        let packet = Ipv4::new(src, dst, proto, interface.next_id, data).to_bytes();
+
+        if interface.is_local(dst) {
+            return interface.loop_back(packet);
+        }
+        if interface.link.is_none() {
+            return Err(Error::new(ENETDOWN));
+        }
+
+        if interface.is_broadcast(dst) {
+            return interface.transmit(BROADCAST_MAC_ADDR, ETHERTYPE_IPV4, packet);
+        }
+
+        let hop = interface.next_hop(dst);
+        if let Some(&mac) = interface.arp.get(&hop) {
+            return interface.transmit(mac, ETHERTYPE_IPV4, packet);
+        }
+
+        let request = {
+            let pending = interface.pending.entry(hop).or_insert(ArpPending {
+                packets: Vec::new(),
+                tries: 0,
+            });
+            if pending.packets.len() < ARP_PENDING_MAX {
+                pending.packets.push(packet);
+            }
+            if pending.tries == 0 {
+                pending.tries = 1;
+                true
+            } else {
+                false
+            }
+        };
+        if request {
+            try!(interface.request(hop));
+        }
+        Ok(())
+    }
+
+    /// Handle a frame read from the device
+    fn receive(&self, frame: Vec<u8>) {
+        if let Some(frame) = EthernetII::from_bytes(frame) {
+            let mac = self.interface.lock().mac;
+            if ! frame.header.dst.equals(mac) && ! frame.header.dst.equals(BROADCAST_MAC_ADDR) {
+                return;
+            }
+
+            match frame.header.ethertype.get() {
+                ETHERTYPE_ARP => self.receive_arp(frame.data),
+                ETHERTYPE_IPV4 => self.receive_ipv4(frame.data, false),
+                _ => (),
+            }
+        }
+    }
+
+    /// Learn the address of a host that asks for ours, or answers our request, and send it the
+    /// packets that waited for it
+    fn receive_arp(&self, data: Vec<u8>) {
+        if let Some(arp) = Arp::from_bytes(data) {
+            let header = arp.header;
+            if header.htype.get() != 1 || header.ptype.get() != ETHERTYPE_IPV4 {
+                return;
+            }
+
+            let mut interface = self.interface.lock();
+            let known = interface.arp.contains_key(&header.src_ip);
+            if header.dst_ip != interface.ip && ! known {
+                return;
+            }
+            interface.arp.insert(header.src_ip, header.src_mac);
+
+            if header.oper.get() == ARP_REQUEST && header.dst_ip == interface.ip {
+                let reply = Arp::new(ARP_REPLY, interface.mac, interface.ip, header.src_mac, header.src_ip);
+                let _ = interface.transmit(header.src_mac, ETHERTYPE_ARP, reply.to_bytes());
+            }
+
+            if let Some(pending) = interface.pending.remove(&header.src_ip) {
+                for packet in pending.packets {
+                    let _ = interface.transmit(header.src_mac, ETHERTYPE_IPV4, packet);
+                }
+            }
+        }
+    }
+
+    /// Handle a frame written to `lo:`, which only carries packets of this host
+    fn receive_loopback(&self, frame: Vec<u8>) {
+        if let Some(frame) = EthernetII::from_bytes(frame) {
+            if frame.header.ethertype.get() == ETHERTYPE_IPV4 {
+                self.receive_ipv4(frame.data, true);
+            }
+        }
+    }
+
+    /// Pass a packet for this host to its protocol, every packet of `lo:` is, while those of the
+    /// network must be to the address of this host or a broadcast one
+    fn receive_ipv4(&self, data: Vec<u8>, loopback: bool) {
+        if let Some(ip) = Ipv4::from_bytes(data) {
+            if ! ip.valid() {
+                return;
+            }
+
+            if ! loopback {
+                let interface = self.interface.lock();
+                if ip.header.dst != interface.ip && ! interface.is_broadcast(ip.header.dst) {
+                    return;
+                }
+            }
+
+            let payload = ip.payload().to_vec();
+            match ip.header.proto {
+                IP_PROTO_ICMP => self.receive_icmp(ip.header.src, ip.header.dst, payload),
+                IP_PROTO_TCP => self.tcp.receive(self, ip.header.src, ip.header.dst, payload),
+                IP_PROTO_UDP => self.udp.receive(ip.header.src, ip.header.dst, payload),
+                _ => (),
+            }
+        }
+    }
+
+    /// Answer an echo request to this host, and pass echo replies to `ping:`
+    fn receive_icmp(&self, src: Ipv4Addr, dst: Ipv4Addr, data: Vec<u8>) {
+        if let Some(icmp) = Icmp::from_bytes(data) {
+            if ! icmp.valid() {
+                return;
+            }
+
+            match icmp.header._type {
+                ICMP_ECHO_REQUEST => if ! self.interface.lock().is_broadcast(dst) {
+                    let reply = Icmp::echo(ICMP_ECHO_REPLY, icmp.header.id.get(), icmp.header.sequence.get(), icmp.data);
+                    let _ = self.send(src, IP_PROTO_ICMP, reply.to_bytes());
+                },
+                ICMP_ECHO_REPLY => self.ping.receive(src, icmp),
+                _ => (),
+            }
+        }
+    }
+
+    /// Resend ARP requests that were not answered, dropping the packets waiting for hosts that
+    /// did not answer any
+    fn tick(&self) {
+        let mut interface = self.interface.lock();
+
+        let mut retry = Vec::new();
+        let mut expired = Vec::new();
+        for (&ip, pending) in interface.pending.iter_mut() {
+            if pending.tries >= ARP_TRIES {
+                expired.push(ip);
+            } else {
+                pending.tries += 1;
+                retry.push(ip);
+            }
+        }
+
+        for ip in expired.iter() {
+            interface.pending.remove(ip);
+        }
+        for &ip in retry.iter() {
+            let _ = interface.request(ip);
+        }
+    }
+}
+
+/// Block the current context for `duration`
+pub fn sleep(duration: Duration) {
+    {
+        let mut contexts = ::env().contexts.write();
+        if let Ok(mut current) = contexts.current_mut() {
+            current.blocked = true;
+            current.wake = Some(Duration::monotonic() + duration);
+        }
+    }
+
+    unsafe { context_switch(); }
+}
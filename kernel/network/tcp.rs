@@ -0,0 +1,125 @@
+use common::slice::GetSlice;
+
+use collections::slice;
+use collections::vec::Vec;
+
+use core::mem;
+
+use network::common::*;
+use network::ipv4::{pseudo_header, IP_PROTO_TCP};
+
+/// The sender has no more data
+pub const TCP_FIN: u16 = 1;
+/// Synchronize sequence numbers, to open a connection
+pub const TCP_SYN: u16 = 1 << 1;
+/// Reset the connection
+pub const TCP_RST: u16 = 1 << 2;
+/// Push the data to the reader
+pub const TCP_PSH: u16 = 1 << 3;
+/// The acknowledgment number is valid
+pub const TCP_ACK: u16 = 1 << 4;
+
+/// The flag bits of `flags`, the top four bits are the header length in 32-bit words
+pub const TCP_FLAGS: u16 = 0x1FF;
+
+#[derive(Copy, Clone)]
+#[repr(packed)]
+pub struct TcpHeader {
+    pub src: n16,
+    pub dst: n16,
+    pub sequence: n32,
+    pub ack_num: n32,
+    pub flags: n16,
+    pub window_size: n16,
+    pub checksum: Checksum,
+    pub urgent_pointer: n16,
+}
+
+pub struct Tcp {
+    pub header: TcpHeader,
+    pub options: Vec<u8>,
+    pub data: Vec<u8>,
+}
+
+impl Tcp {
+    /// A segment without options from port `src` of `src_ip` to port `dst` of `dst_ip`, with
+    /// its checksum
+    pub fn new(src_ip: Ipv4Addr, src: u16, dst_ip: Ipv4Addr, dst: u16, sequence: u32, ack_num: u32, flags: u16,
+               window_size: u16, data: Vec<u8>) -> Tcp {
+        let mut tcp = Tcp {
+            header: TcpHeader {
+                src: n16::new(src),
+                dst: n16::new(dst),
+                sequence: n32::new(sequence),
+                ack_num: n32::new(ack_num),
+                flags: n16::new(((mem::size_of::<TcpHeader>() / 4) << 12) as u16 | flags & TCP_FLAGS),
+                window_size: n16::new(window_size),
+                checksum: Checksum { data: 0 },
+                urgent_pointer: n16::new(0),
+            },
+            options: Vec::new(),
+            data: data,
+        };
+
+        let bytes = tcp.to_bytes();
+        tcp.header.checksum.data = Checksum::of(&[&pseudo_header(src_ip, dst_ip, IP_PROTO_TCP, bytes.len()), &bytes]);
+        tcp
+    }
+
+    /// Check the checksum of a segment from `src_ip` to `dst_ip`
+    pub fn valid(&self, src_ip: Ipv4Addr, dst_ip: Ipv4Addr) -> bool {
+        let bytes = self.to_bytes();
+        Checksum::of(&[&pseudo_header(src_ip, dst_ip, IP_PROTO_TCP, bytes.len()), &bytes]) == 0
+    }
+
+    /// The flag bits
+    pub fn flags(&self) -> u16 {
+        self.header.flags.get() & TCP_FLAGS
+    }
+
+    /// The length in sequence numbers, which a SYN and a FIN take one of each
+    pub fn sequence_len(&self) -> u32 {
+        let mut len = self.data.len() as u32;
+        if self.flags() & TCP_SYN == TCP_SYN {
+            len += 1;
+        }
+        if self.flags() & TCP_FIN == TCP_FIN {
+            len += 1;
+        }
+        len
+    }
+}
+
+impl FromBytes for Tcp {
+    fn from_bytes(bytes: Vec<u8>) -> Option<Self> {
+        if bytes.len() >= mem::size_of::<TcpHeader>() {
+            unsafe {
+                let header = *(bytes.as_ptr() as *const TcpHeader);
+                let header_len = ((header.flags.get() >> 12) * 4) as usize;
+                if header_len < mem::size_of::<TcpHeader>() || header_len > bytes.len() {
+                    return None;
+                }
+
+                return Some(Tcp {
+                    header: header,
+                    options: bytes.get_slice(mem::size_of::<TcpHeader>() .. header_len).to_vec(),
+                    data: bytes.get_slice(header_len ..).to_vec(),
+                });
+            }
+        }
+        None
+    }
+}
+
+impl ToBytes for Tcp {
+    fn to_bytes(&self) -> Vec<u8> {
+        unsafe {
+            let header_ptr: *const TcpHeader = &self.header;
+            let mut ret = Vec::from(slice::from_raw_parts(header_ptr as *const u8,
+                                                          mem::size_of::<TcpHeader>()));
+            ret.push_all(&self.options);
+            ret.push_all(&self.data);
+            ret
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use common::slice::GetSlice;
+
+use collections::slice;
+use collections::vec::Vec;
+
+use core::{cmp, mem};
+
+use network::common::*;
+use network::ipv4::{pseudo_header, IP_PROTO_UDP};
+
+#[derive(Copy, Clone)]
+#[repr(packed)]
+pub struct UdpHeader {
+    pub src: n16,
+    pub dst: n16,
+    pub len: n16,
+    pub checksum: Checksum,
+}
+
+pub struct Udp {
+    pub header: UdpHeader,
+    pub data: Vec<u8>,
+}
+
+impl Udp {
+    /// A datagram of `data` from port `src` of `src_ip` to port `dst` of `dst_ip`, with its
+    /// checksum
+    pub fn new(src_ip: Ipv4Addr, src: u16, dst_ip: Ipv4Addr, dst: u16, data: Vec<u8>) -> Udp {
+        let len = mem::size_of::<UdpHeader>() + data.len();
+        let mut udp = Udp {
+            header: UdpHeader {
+                src: n16::new(src),
+                dst: n16::new(dst),
+                len: n16::new(len as u16),
+                checksum: Checksum { data: 0 },
+            },
+            data: data,
+        };
+
+        let checksum = Checksum::of(&[&pseudo_header(src_ip, dst_ip, IP_PROTO_UDP, len), &udp.to_bytes()]);
+        // 0 means that there is no checksum
+        udp.header.checksum.data = if checksum == 0 {
+            0xFFFF
+        } else {
+            checksum
+        };
+        udp
+    }
+
+    /// Check the checksum of a datagram from `src_ip` to `dst_ip`, if it has one
+    pub fn valid(&self, src_ip: Ipv4Addr, dst_ip: Ipv4Addr) -> bool {
+        let len = self.header.len.get() as usize;
+        len >= mem::size_of::<UdpHeader>() && len <= mem::size_of::<UdpHeader>() + self.data.len() &&
+        (self.header.checksum.data == 0 ||
+         Checksum::of(&[&pseudo_header(src_ip, dst_ip, IP_PROTO_UDP, len), &self.to_bytes()[.. len]]) == 0)
+    }
+
+    /// The data, without what follows the length in the header
+    pub fn payload(&self) -> &[u8] {
+        let len = (self.header.len.get() as usize).saturating_sub(mem::size_of::<UdpHeader>());
+        &self.data[.. cmp::min(len, self.data.len())]
+    }
+}
+
+impl FromBytes for Udp {
+    fn from_bytes(bytes: Vec<u8>) -> Option<Self> {
+        if bytes.len() >= mem::size_of::<UdpHeader>() {
+            unsafe {
+                return Some(Udp {
+                    header: *(bytes.as_ptr() as *const UdpHeader),
+                    data: bytes.get_slice(mem::size_of::<UdpHeader>() ..).to_vec(),
+                });
+            }
+        }
+        None
+    }
+}
+
+impl ToBytes for Udp {
+    fn to_bytes(&self) -> Vec<u8> {
+        unsafe {
+            let header_ptr: *const UdpHeader = &self.header;
+            let mut ret = Vec::from(slice::from_raw_parts(header_ptr as *const u8,
+                                                          mem::size_of::<UdpHeader>()));
+            ret.push_all(&self.data);
+            ret
+        }
+    }
+}
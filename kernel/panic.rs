@@ -1,12 +1,22 @@
 use core::{fmt, result};
+use core::sync::atomic::Ordering;
 
-use common::debug;
+use arch::cpu;
+use arch::gdb::{self, GdbStop};
+use arch::pstore;
+use arch::regs::Regs;
+
+use common::{backtrace, cmdline, debug};
+
+use env::log::LogLevel;
+
+use schemes::test;
 
 struct DebugStream;
 
 impl fmt::Write for DebugStream {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        debug::d(s);
+        debug::log(LogLevel::Critical, s);
 
         result::Result::Ok(())
     }
@@ -14,17 +24,47 @@ impl fmt::Write for DebugStream {
 
 #[lang="panic_fmt"]
 pub extern "C" fn panic_fmt(args: fmt::Arguments, file: &'static str, line: u32) -> ! {
-    debug::d(file);
+    debug::log(LogLevel::Critical, file);
     debug::d(":");
     debug::dd(line as usize);
     debug::d(": ");
     let _ = fmt::write(&mut DebugStream, args);
     debug::dl();
 
+    backtrace::trace();
+
+    unsafe { pstore::save() };
+
+    if cmdline::config().test {
+        debug::d("TEST: panicked\n");
+        test::fail_boot();
+    }
+
     unsafe {
+        cpu::interrupts_disable();
+
+        let mut regs = Regs::default();
+        regs.ip = panic_fmt as usize;
+        #[cfg(target_arch = "x86")]
+        asm!("mov $0, esp
+            mov $1, ebp
+            mov $2, cs"
+            : "=r"(regs.sp), "=r"(regs.bp), "=r"(regs.cs) : : : "intel", "volatile");
+        #[cfg(target_arch = "x86_64")]
+        asm!("mov $0, rsp
+            mov $1, rbp
+            mov $2, cs"
+            : "=r"(regs.sp), "=r"(regs.bp), "=r"(regs.cs) : : : "intel", "volatile");
+
+        // COM1 is also the console, so it is only polled for a debugger that is attached, or
+        // expected with `gdb` on the command line
+        if gdb::GDB_ACTIVE.load(Ordering::SeqCst) || cmdline::config().gdb {
+            debug::d("Waiting for GDB on COM1\n");
+            gdb::enter(&mut regs, GdbStop::Panic);
+        }
+
         loop {
-            asm!("sti");
-            asm!("hlt");
+            cpu::halt();
         }
     }
 }
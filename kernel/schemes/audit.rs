@@ -0,0 +1,29 @@
+use alloc::boxed::Box;
+
+use collections::string::ToString;
+
+use fs::{KScheme, Resource, Url, VecResource};
+
+use system::error::{Error, Result, EACCES};
+
+/// The audit scheme, only readable by root
+pub struct AuditScheme;
+
+impl KScheme for AuditScheme {
+    fn scheme(&self) -> &str {
+        "audit"
+    }
+
+    fn open(&mut self, _: Url, _: usize) -> Result<Box<Resource>> {
+        {
+            let contexts = ::env().contexts.read();
+            let current = try!(contexts.current());
+            if current.euid != 0 {
+                return Err(Error::new(EACCES));
+            }
+        }
+
+        let string = ::env().audit.lock().to_string();
+        Ok(box VecResource::new("audit:".to_string(), string.into_bytes()))
+    }
+}
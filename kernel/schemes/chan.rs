@@ -0,0 +1,327 @@
+use alloc::arc::Arc;
+use alloc::boxed::Box;
+
+use collections::{BTreeMap, String, Vec};
+use collections::string::ToString;
+use collections::vec_deque::VecDeque;
+
+use core::cmp;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use fs::{Creds, DirResource, KScheme, Resource, Url};
+use fs::access::open_access;
+
+use sync::{Intex, WaitCondition};
+
+use system::error::{Error, Result, EBADF, EEXIST, EINTR, EMSGSIZE, ENOENT, ENOSPC, EPERM, EPIPE};
+use system::syscall::{Dirent, Stat, MODE_FIFO, O_CREAT, O_EXCL, O_RDWR, O_WRONLY, POLLERR, POLLHUP, POLLIN,
+                      POLLOUT};
+
+/// The number of messages a channel holds before senders block
+pub const CHAN_MESSAGES: usize = 64;
+
+/// The largest message, in bytes
+pub const CHAN_MESSAGE_SIZE: usize = 4096;
+
+/// The most bytes the messages of every channel hold together, as they are kept in kernel memory
+pub const CHAN_SIZE: usize = 4 * 1024 * 1024;
+
+/// A message queue, shared by the resources open on it
+///
+/// Each write sends one message, and each read receives one whole message, so that messages are
+/// never split or merged. Receivers block while it is empty and read end of file once every
+/// sender has closed. Senders block while it is full and fail with `EPIPE` once every receiver
+/// has closed.
+pub struct Chan {
+    name: String,
+    /// The context that made the channel, which alone may open it, or remove the name
+    owner: Creds,
+    /// The messages sent that have not been received, at most `CHAN_MESSAGES`
+    messages: Intex<VecDeque<Vec<u8>>>,
+    /// Notified when a message is sent, or the last sender closes
+    readable: WaitCondition,
+    /// Notified when a message is received, or the last receiver closes
+    writable: WaitCondition,
+    receivers: AtomicUsize,
+    senders: AtomicUsize,
+    /// Set when the last sender closes, and cleared when one opens
+    hangup: AtomicBool,
+    /// The bytes held by the messages of every channel, which this one counts in
+    used: Arc<AtomicUsize>,
+}
+
+impl Chan {
+    fn new(name: String, used: Arc<AtomicUsize>) -> Arc<Chan> {
+        Arc::new(Chan {
+            name: name,
+            owner: Creds::current(),
+            messages: Intex::new(VecDeque::new()),
+            readable: WaitCondition::new(),
+            writable: WaitCondition::new(),
+            receivers: AtomicUsize::new(0),
+            senders: AtomicUsize::new(0),
+            hangup: AtomicBool::new(false),
+            used: used,
+        })
+    }
+
+    fn stat(&self, stat: &mut Stat) -> Result<usize> {
+        stat.st_mode = MODE_FIFO | 0o600;
+        stat.st_size = self.messages.lock().len() as u64;
+        stat.st_uid = self.owner.uid as u32;
+        stat.st_gid = self.owner.gid as u32;
+        Ok(0)
+    }
+}
+
+impl Drop for Chan {
+    fn drop(&mut self) {
+        let len = self.messages.lock().iter().fold(0, |len, message| len + message.len());
+        self.used.fetch_sub(len, Ordering::SeqCst);
+    }
+}
+
+/// Fail with `EINTR` if the current context has a signal pending, before it blocks
+fn check_signals() -> Result<()> {
+    let contexts = ::env().contexts.read();
+    if let Ok(current) = contexts.current() {
+        if current.pending_signals() != 0 {
+            return Err(Error::new(EINTR));
+        }
+    }
+    Ok(())
+}
+
+/// A channel that is open, for receiving, sending, or both with `O_RDWR`
+pub struct ChanResource {
+    chan: Arc<Chan>,
+    receive: bool,
+    send: bool,
+}
+
+impl ChanResource {
+    pub fn new(chan: Arc<Chan>, receive: bool, send: bool) -> Self {
+        if receive {
+            chan.receivers.fetch_add(1, Ordering::SeqCst);
+        }
+        if send {
+            chan.senders.fetch_add(1, Ordering::SeqCst);
+            chan.hangup.store(false, Ordering::SeqCst);
+        }
+        ChanResource {
+            chan: chan,
+            receive: receive,
+            send: send,
+        }
+    }
+}
+
+impl Resource for ChanResource {
+    fn dup(&self) -> Result<Box<Resource>> {
+        Ok(box ChanResource::new(self.chan.clone(), self.receive, self.send))
+    }
+
+    fn path(&self, buf: &mut [u8]) -> Result<usize> {
+        let path_a = b"chan:";
+        let path_b = self.chan.name.as_bytes();
+        for (b, p) in buf.iter_mut().zip(path_a.iter().chain(path_b.iter())) {
+            *b = *p;
+        }
+
+        Ok(cmp::min(buf.len(), path_a.len() + path_b.len()))
+    }
+
+    /// Receive the next message, the bytes of it that do not fit in `buf` are dropped
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if ! self.receive {
+            return Err(Error::new(EBADF));
+        }
+
+        loop {
+            {
+                let mut messages = self.chan.messages.lock();
+                let message = messages.pop_front();
+                if let Some(message) = message {
+                    drop(messages);
+                    self.chan.used.fetch_sub(message.len(), Ordering::SeqCst);
+
+                    let count = cmp::min(buf.len(), message.len());
+                    buf[..count].copy_from_slice(&message[..count]);
+                    unsafe { self.chan.writable.notify(); }
+                    return Ok(count);
+                }
+
+                if self.chan.hangup.load(Ordering::SeqCst) {
+                    return Ok(0);
+                }
+            }
+
+            try!(check_signals());
+            unsafe { self.chan.readable.wait(); }
+        }
+    }
+
+    /// Send `buf` as one message, failing with `EMSGSIZE` if it is larger than
+    /// `CHAN_MESSAGE_SIZE`, and with `ENOSPC` if the channels would hold more than `CHAN_SIZE`.
+    /// An empty write sends nothing
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        if ! self.send {
+            return Err(Error::new(EBADF));
+        }
+        if buf.len() > CHAN_MESSAGE_SIZE {
+            return Err(Error::new(EMSGSIZE));
+        }
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        loop {
+            if self.chan.receivers.load(Ordering::SeqCst) == 0 {
+                return Err(Error::new(EPIPE));
+            }
+
+            {
+                let mut messages = self.chan.messages.lock();
+                if messages.len() < CHAN_MESSAGES {
+                    if self.chan.used.fetch_add(buf.len(), Ordering::SeqCst) + buf.len() > CHAN_SIZE {
+                        self.chan.used.fetch_sub(buf.len(), Ordering::SeqCst);
+                        return Err(Error::new(ENOSPC));
+                    }
+                    messages.push_back(buf.to_vec());
+                    drop(messages);
+
+                    unsafe { self.chan.readable.notify(); }
+                    return Ok(buf.len());
+                }
+            }
+
+            try!(check_signals());
+            unsafe { self.chan.writable.wait(); }
+        }
+    }
+
+    fn stat(&self, stat: &mut Stat) -> Result<usize> {
+        self.chan.stat(stat)
+    }
+
+    fn poll(&self) -> Result<usize> {
+        let mut events = 0;
+        let len = self.chan.messages.lock().len();
+        if self.receive {
+            if len > 0 {
+                events |= POLLIN;
+            }
+            if self.chan.hangup.load(Ordering::SeqCst) {
+                events |= POLLHUP;
+            }
+        }
+        if self.send {
+            if self.chan.receivers.load(Ordering::SeqCst) == 0 {
+                events |= POLLERR;
+            } else if len < CHAN_MESSAGES {
+                events |= POLLOUT;
+            }
+        }
+        Ok(events)
+    }
+}
+
+impl Drop for ChanResource {
+    fn drop(&mut self) {
+        if self.receive && self.chan.receivers.fetch_sub(1, Ordering::SeqCst) == 1 {
+            // Blocked senders fail with `EPIPE`
+            unsafe { self.chan.writable.notify(); }
+        }
+        if self.send && self.chan.senders.fetch_sub(1, Ordering::SeqCst) == 1 {
+            // Blocked receivers read end of file
+            self.chan.hangup.store(true, Ordering::SeqCst);
+            unsafe { self.chan.readable.notify(); }
+        }
+    }
+}
+
+/// Named message queues
+///
+/// Opening `chan:<name>` with `O_CREAT` creates a channel that unrelated processes can open by
+/// name, which stays until it is unlinked. A channel is opened for receiving, for sending with
+/// `O_WRONLY`, or for both with `O_RDWR`. Unlike a pipe, it keeps the boundaries of the messages
+/// written, see `Chan`. With `O_NONBLOCK`, receiving from an empty channel and sending to a full
+/// one fail with `EAGAIN`, and `poll` reports when they would not. Blocked receivers and senders
+/// fail with `EINTR` when a signal arrives. The messages of the channels hold at most `CHAN_SIZE`
+/// bytes together. Opening `chan:` lists the channels.
+pub struct ChanScheme {
+    chans: BTreeMap<String, Arc<Chan>>,
+    /// The bytes held by the messages of every channel
+    used: Arc<AtomicUsize>,
+}
+
+impl ChanScheme {
+    pub fn new() -> Box<Self> {
+        box ChanScheme {
+            chans: BTreeMap::new(),
+            used: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+}
+
+impl KScheme for ChanScheme {
+    fn scheme(&self) -> &str {
+        "chan"
+    }
+
+    fn open(&mut self, url: Url, flags: usize) -> Result<Box<Resource>> {
+        let name = url.reference().trim_matches('/');
+        if name.is_empty() {
+            let entries: Vec<Dirent> = self.chans.iter().map(|(name, chan)| {
+                Dirent::new(name, MODE_FIFO | 0o600, chan.messages.lock().len() as u64)
+            }).collect();
+            return Ok(box DirResource::new("chan:".to_string(), entries));
+        }
+
+        let chan = match self.chans.get(name) {
+            Some(chan) => {
+                if flags & (O_CREAT | O_EXCL) == O_CREAT | O_EXCL {
+                    return Err(Error::new(EEXIST));
+                }
+
+                let mut stat = Stat::default();
+                try!(chan.stat(&mut stat));
+                try!(Creds::current().check(&stat, open_access(flags)));
+                chan.clone()
+            },
+            None => if flags & O_CREAT == O_CREAT {
+                let chan = Chan::new(name.to_string(), self.used.clone());
+                self.chans.insert(name.to_string(), chan.clone());
+                chan
+            } else {
+                return Err(Error::new(ENOENT));
+            },
+        };
+
+        let receive = flags & O_WRONLY != O_WRONLY;
+        let send = flags & (O_WRONLY | O_RDWR) != 0;
+        Ok(box ChanResource::new(chan, receive, send))
+    }
+
+    fn stat(&mut self, url: Url, stat: &mut Stat) -> Result<()> {
+        let name = url.reference().trim_matches('/');
+        match self.chans.get(name) {
+            Some(chan) => chan.stat(stat).and(Ok(())),
+            None => Err(Error::new(ENOENT)),
+        }
+    }
+
+    /// Remove the name, resources that are open keep working. Like a sticky directory, only the
+    /// owner of a channel or root may remove it
+    fn unlink(&mut self, url: Url) -> Result<()> {
+        let name = url.reference().trim_matches('/');
+        {
+            let chan = try!(self.chans.get(name).ok_or(Error::new(ENOENT)));
+            let creds = Creds::current();
+            if creds.uid != 0 && creds.uid != chan.owner.uid {
+                return Err(Error::new(EPERM));
+            }
+        }
+        self.chans.remove(name).map(|_| ()).ok_or(Error::new(ENOENT))
+    }
+}
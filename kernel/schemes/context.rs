@@ -16,9 +16,11 @@ impl KScheme for ContextScheme {
     }
 
     fn open(&mut self, _: Url, _: usize) -> Result<Box<Resource>> {
-        let mut string = format!("{:<6}{:<6}{:<8}{:<8}{:<8}{:<6}{:<6}{}\n",
+        let mut string = format!("{:<6}{:<6}{:<5}{:<4}{:<8}{:<8}{:<8}{:<6}{:<6}{}\n",
                                  "PID",
                                  "PPID",
+                                 "PRI",
+                                 "CPU",
                                  "SWITCH",
                                  "TIME",
                                  "MEM",
@@ -26,7 +28,7 @@ impl KScheme for ContextScheme {
                                  "FLG",
                                  "NAME");
         {
-            let contexts = ::env().contexts.lock();
+            let contexts = ::env().contexts.read();
             for context in contexts.iter() {
                 let mut memory = 0;
                 if context.kernel_stack > 0 {
@@ -57,12 +59,18 @@ impl KScheme for ContextScheme {
                 } else {
                     flags_string.push('K');
                 }
+                if context.running {
+                    flags_string.push('R');
+                }
                 if context.blocked {
                     flags_string.push('B');
                 }
                 if context.exited {
                     flags_string.push('E');
                 }
+                if context.status.is_some() {
+                    flags_string.push('Z');
+                }
                 if context.vfork.is_some() {
                     flags_string.push('V');
                 }
@@ -70,9 +78,11 @@ impl KScheme for ContextScheme {
                     flags_string.push('S');
                 }
 
-                string.push_str(&format!("{:<6}{:<6}{:<8}{:<8}{:<8}{:<6}{:<6}{}\n",
+                string.push_str(&format!("{:<6}{:<6}{:<5}{:<4}{:<8}{:<8}{:<8}{:<6}{:<6}{}\n",
                                    context.pid,
                                    context.ppid,
+                                   context.priority,
+                                   context.cpu,
                                    context.switch,
                                    context.time,
                                    memory_string,
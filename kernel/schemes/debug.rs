@@ -4,24 +4,35 @@ use collections::string::String;
 
 use core::cmp;
 
+use env::vt::VT_COUNT;
+
 use fs::{KScheme, Resource, Url};
 
-use system::error::Result;
+use system::error::{Error, Result, ENOENT};
+use system::syscall::{POLLIN, POLLOUT};
 
 /// A debug resource
 pub struct DebugResource {
+    /// The virtual terminal
+    pub vt: usize,
     pub command: String,
 }
 
 impl Resource for DebugResource {
     fn dup(&self) -> Result<Box<Resource>> {
         Ok(box DebugResource {
+            vt: self.vt,
             command: self.command.clone(),
         })
     }
 
     fn path(&self, buf: &mut [u8]) -> Result <usize> {
-        let path = b"debug:";
+        let path_string = if self.vt == 0 {
+            String::from("debug:")
+        } else {
+            format!("debug:{}", self.vt)
+        };
+        let path = path_string.as_bytes();
 
         for (b, p) in buf.iter_mut().zip(path.iter()) {
             *b = *p;
@@ -32,7 +43,7 @@ impl Resource for DebugResource {
 
     fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
         if self.command.is_empty() {
-            self.command = ::env().console.lock().commands.receive();
+            self.command = try!(::env().vts.lock().consoles[self.vt].receive());
         }
 
         let mut i = 0;
@@ -45,15 +56,30 @@ impl Resource for DebugResource {
     }
 
     fn write(&mut self, buf: &[u8]) -> Result<usize> {
-        ::env().console.lock().write(buf);
+        ::env().vts.lock().consoles[self.vt].write(buf);
         Ok(buf.len())
     }
 
     fn sync(&mut self) -> Result<()> {
         Ok(())
     }
+
+    fn poll(&self) -> Result<usize> {
+        if ! self.command.is_empty() || ! ::env().vts.lock().consoles[self.vt].commands.inner.lock().is_empty() {
+            Ok(POLLIN | POLLOUT)
+        } else {
+            Ok(POLLOUT)
+        }
+    }
+
+    fn ioctl(&mut self, request: usize, arg: &mut [u8]) -> Result<usize> {
+        let mut vts = ::env().vts.lock();
+        let console = &mut vts.consoles[self.vt];
+        console.tty.ioctl(request, arg, &console.commands)
+    }
 }
 
+/// The console scheme, `debug:` is the first virtual terminal and `debug:<n>` selects another
 pub struct DebugScheme;
 
 impl DebugScheme {
@@ -67,8 +93,17 @@ impl KScheme for DebugScheme {
         "debug"
     }
 
-    fn open(&mut self, _: Url, _: usize) -> Result<Box<Resource>> {
+    fn open(&mut self, url: Url, _: usize) -> Result<Box<Resource>> {
+        let vt = match url.reference().trim_matches('/') {
+            "" => 0,
+            reference => try!(reference.parse::<usize>().or(Err(Error::new(ENOENT)))),
+        };
+        if vt >= VT_COUNT {
+            return Err(Error::new(ENOENT));
+        }
+
         Ok(box DebugResource {
+            vt: vt,
             command: String::new()
         })
     }
@@ -0,0 +1,185 @@
+use alloc::arc::Arc;
+use alloc::boxed::Box;
+
+use collections::string::{String, ToString};
+use collections::vec::Vec;
+
+use common::to_num::ToNum;
+
+use core::cmp;
+
+use disk::BlockDevice;
+use disk::partition::{Disk, PartitionDevice};
+
+use fs::{KScheme, Resource, ResourceSeek, Url, VecResource};
+
+use system::error::{Error, Result, EACCES, EINVAL, ENOENT};
+
+/// A disk or a partition, read and written at any offset
+///
+/// Transfers that do not cover whole blocks read the blocks around them first. They go to the
+/// disk directly, not through the cache of `file:`.
+pub struct DiskResource {
+    disk: Arc<Disk>,
+    /// The number of the partition, 0 for the whole disk
+    number: usize,
+    device: PartitionDevice,
+    seek: u64,
+}
+
+impl DiskResource {
+    /// The path of the resource, like `disk:0p1`
+    fn name(&self) -> String {
+        if self.number == 0 {
+            format!("disk:{}", self.disk.number)
+        } else {
+            format!("disk:{}p{}", self.disk.number, self.number)
+        }
+    }
+
+    /// The blocks covering `len` bytes at the seek position, and the offset of the seek position
+    /// in the first block
+    fn blocks(&self, len: usize) -> (u64, usize, usize) {
+        let block_size = self.device.block_size() as u64;
+        let offset = (self.seek % block_size) as usize;
+        let size = (offset + len + block_size as usize - 1) / block_size as usize * block_size as usize;
+        (self.seek / block_size, offset, size)
+    }
+}
+
+impl Resource for DiskResource {
+    fn dup(&self) -> Result<Box<Resource>> {
+        let device = try!(PartitionDevice::new(self.disk.clone(), self.number).ok_or(Error::new(ENOENT)));
+        Ok(box DiskResource {
+            disk: self.disk.clone(),
+            number: self.number,
+            device: device,
+            seek: self.seek,
+        })
+    }
+
+    fn path(&self, buf: &mut [u8]) -> Result<usize> {
+        let name = self.name();
+        let path = name.as_bytes();
+
+        for (b, p) in buf.iter_mut().zip(path.iter()) {
+            *b = *p;
+        }
+
+        Ok(cmp::min(buf.len(), path.len()))
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let (block, offset, size) = self.blocks(buf.len());
+        let mut data = vec![0; size];
+        let count = try!(self.device.read_blocks(block, &mut data));
+
+        let count = cmp::min(count.saturating_sub(offset), buf.len());
+        buf[..count].copy_from_slice(&data[offset..offset + count]);
+        self.seek += count as u64;
+        Ok(count)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let (block, offset, size) = self.blocks(buf.len());
+        let mut data = vec![0; size];
+        if offset != 0 || size != buf.len() {
+            try!(self.device.read_blocks(block, &mut data));
+        }
+        data[offset..offset + buf.len()].copy_from_slice(buf);
+        let count = try!(self.device.write_blocks(block, &data));
+
+        let count = cmp::min(count.saturating_sub(offset), buf.len());
+        self.seek += count as u64;
+        Ok(count)
+    }
+
+    fn seek(&mut self, pos: ResourceSeek) -> Result<usize> {
+        let size = self.device.blocks().map_or(0, |blocks| blocks * self.device.block_size() as u64);
+        let seek = match pos {
+            ResourceSeek::Start(offset) => offset as i64,
+            ResourceSeek::Current(offset) => self.seek as i64 + offset as i64,
+            ResourceSeek::End(offset) => size as i64 + offset as i64,
+        };
+        if seek < 0 {
+            return Err(Error::new(EINVAL));
+        }
+
+        self.seek = seek as u64;
+        Ok(self.seek as usize)
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        self.device.flush()
+    }
+}
+
+/// The disks, and the partitions found on them
+///
+/// `disk:` lists them, `disk:0` is the first disk and `disk:0p1` the first partition on it,
+/// numbered like the entries of its partition table. Only root may open them.
+pub struct DiskScheme {
+    disks: Vec<Arc<Disk>>,
+}
+
+impl DiskScheme {
+    pub fn new(disks: Vec<Arc<Disk>>) -> Box<Self> {
+        box DiskScheme {
+            disks: disks,
+        }
+    }
+}
+
+impl KScheme for DiskScheme {
+    fn scheme(&self) -> &str {
+        "disk"
+    }
+
+    fn open(&mut self, url: Url, _: usize) -> Result<Box<Resource>> {
+        {
+            let contexts = ::env().contexts.read();
+            let current = try!(contexts.current());
+            if current.euid != 0 {
+                return Err(Error::new(EACCES));
+            }
+        }
+
+        let reference = url.reference().trim_matches('/');
+        if reference.is_empty() {
+            let mut list = String::new();
+            for disk in self.disks.iter() {
+                list.push_str(&format!("{}\n", disk.number));
+                for partition in disk.partitions.iter() {
+                    list.push_str(&format!("{}p{}\n", disk.number, partition.number));
+                }
+            }
+            return Ok(box VecResource::new("disk:".to_string(), list.into_bytes()));
+        }
+
+        fn parse_number(string: &str) -> Result<usize> {
+            if string.is_empty() || string.len() > 9 || ! string.bytes().all(|b| b >= b'0' && b <= b'9') {
+                return Err(Error::new(ENOENT));
+            }
+            Ok(string.to_num())
+        }
+
+        let mut parts = reference.splitn(2, 'p');
+        let disk_number = try!(parse_number(parts.next().unwrap_or("")));
+        let number = match parts.next() {
+            Some(part) => match try!(parse_number(part)) {
+                0 => return Err(Error::new(ENOENT)),
+                number => number,
+            },
+            None => 0,
+        };
+
+        let disk = try!(self.disks.iter().find(|disk| disk.number == disk_number).ok_or(Error::new(ENOENT)));
+        let device = try!(PartitionDevice::new(disk.clone(), number).ok_or(Error::new(ENOENT)));
+        Ok(box DiskResource {
+            disk: disk.clone(),
+            number: number,
+            device: device,
+            seek: 0,
+        })
+    }
+}
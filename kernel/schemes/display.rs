@@ -1,38 +1,85 @@
 use alloc::arc::Arc;
 use alloc::boxed::Box;
 
-use collections::String;
+use collections::{String, Vec};
+use collections::string::ToString;
 
 use common::event::Event;
 
-use core::{cmp, ptr};
+use core::{cmp, ptr, str};
 use core::mem::size_of;
 
-use graphics::display::Display;
+use graphics::display::{Display, VBEMODEINFO};
 
-use fs::{KScheme, Resource, ResourceSeek, Url};
+use fs::{KScheme, Resource, ResourceSeek, Url, VecResource};
 
 use system::error::{Error, Result, EACCES, ENOENT, EINVAL};
 use system::graphics::fast_copy;
-
-pub struct DisplayScheme;
+use system::syscall::{POLLIN, POLLOUT};
 
 // Should there only be one display per session?
 /// A display resource
+///
+/// Reads return input events. Writes are pixels copied to the screen at the seek position, or,
+/// for the manager opened as `display:manager`, lines of `flush [<x> <y> <w> <h>]` that copy the
+/// backbuffer mapped with `fmap` to the screen, the whole of it without a rectangle. Syncing also
+/// flushes the whole backbuffer.
 pub struct DisplayResource {
     /// Path
     path: String,
     /// The display
     display: Arc<Box<Display>>,
+    /// Writes are flush commands
+    manager: bool,
     /// Seek
     seek: usize,
 }
 
+impl DisplayResource {
+    /// Copy a rectangle of the backbuffer to the screen
+    fn flush(&self, x: usize, y: usize, w: usize, h: usize) {
+        let display = &self.display;
+        let start_x = cmp::min(display.width, x);
+        let end_x = cmp::min(display.width, x.saturating_add(w));
+        let end_y = cmp::min(display.height, y.saturating_add(h));
+
+        for y in cmp::min(display.height, y)..end_y {
+            let offset = (y * display.width + start_x) as isize;
+            unsafe {
+                fast_copy(display.onscreen.offset(offset), display.offscreen.offset(offset), end_x - start_x);
+            }
+        }
+    }
+
+    fn command(&self, line: &str) -> Result<()> {
+        let args: Vec<&str> = line.split_whitespace().collect();
+        match args.get(0) {
+            Some(&"flush") => {
+                if args.len() == 1 {
+                    self.display.flip();
+                } else if args.len() == 5 {
+                    let mut rect = [0; 4];
+                    for (value, arg) in rect.iter_mut().zip(args.iter().skip(1)) {
+                        *value = try!(arg.parse::<usize>().or(Err(Error::new(EINVAL))));
+                    }
+                    self.flush(rect[0], rect[1], rect[2], rect[3]);
+                } else {
+                    return Err(Error::new(EINVAL));
+                }
+                Ok(())
+            },
+            None => Ok(()),
+            _ => Err(Error::new(EINVAL)),
+        }
+    }
+}
+
 impl Resource for DisplayResource {
     fn dup(&self) -> Result<Box<Resource>> {
         Ok(Box::new(DisplayResource {
             path: self.path.clone(),
             display: self.display.clone(),
+            manager: self.manager,
             seek: self.seek
         }))
     }
@@ -69,7 +116,23 @@ impl Resource for DisplayResource {
         }
     }
 
+    fn poll(&self) -> Result<usize> {
+        if ::env().events.inner.lock().is_empty() {
+            Ok(POLLOUT)
+        } else {
+            Ok(POLLIN | POLLOUT)
+        }
+    }
+
     fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        if self.manager {
+            let commands = try!(str::from_utf8(buf).or(Err(Error::new(EINVAL))));
+            for line in commands.lines() {
+                try!(self.command(line));
+            }
+            return Ok(buf.len());
+        }
+
         let size = cmp::max(0, cmp::min(self.display.size as isize - self.seek as isize, (buf.len()/4) as isize)) as usize;
 
         if size > 0 {
@@ -92,13 +155,41 @@ impl Resource for DisplayResource {
     }
 
     fn sync(&mut self) -> Result<()> {
+        self.display.flip();
         Ok(())
     }
+
+    fn map(&mut self) -> Result<(usize, usize)> {
+        Ok((self.display.offscreen as usize, self.display.size * 4))
+    }
+}
+
+impl Drop for DisplayResource {
+    fn drop(&mut self) {
+        // The scheme holds the other reference, so this was the last resource
+        if Arc::strong_count(&self.display) == 2 {
+            let mut vts = ::env().vts.lock();
+            let console = vts.active_mut();
+            console.draw = true;
+            console.redraw = true;
+        }
+    }
+}
+
+/// The display scheme
+///
+/// Opening `display:` or `display:manager` takes the screen from the console until the last
+/// resource is closed. `display:mode` reads `<width> <height> <bits per pixel>` without taking
+/// the screen. The backbuffer is kept for the next open, as it may still be mapped.
+pub struct DisplayScheme {
+    display: Option<Arc<Box<Display>>>,
 }
 
-impl Drop for DisplayScheme {
-    fn drop(&mut self){
-        ::env().console.lock().draw = true;
+impl DisplayScheme {
+    pub fn new() -> Box<Self> {
+        box DisplayScheme {
+            display: None,
+        }
     }
 }
 
@@ -107,21 +198,38 @@ impl KScheme for DisplayScheme {
         "display"
     }
 
-    fn open(&mut self, _: Url, _: usize) -> Result<Box<Resource>> {
-        if ::env().console.lock().draw {
-            if let Some(display) = Display::root() {
-                ::env().console.lock().draw = false;
-
-                Ok(box DisplayResource {
-                    path: format!("display:{}/{}", display.width, display.height),
-                    display: Arc::new(display),
-                    seek: 0,
-                })
-            } else {
-                Err(Error::new(ENOENT))
-            }
+    fn open(&mut self, url: Url, _: usize) -> Result<Box<Resource>> {
+        let manager = match url.reference().trim_matches('/') {
+            "manager" => true,
+            "mode" => {
+                return match unsafe { VBEMODEINFO } {
+                    Some(mode_info) => Ok(box VecResource::new("display:mode".to_string(),
+                                                               format!("{} {} 32\n", mode_info.xresolution as usize, mode_info.yresolution as usize).into_bytes())),
+                    None => Err(Error::new(ENOENT)),
+                };
+            },
+            _ => false,
+        };
+
+        if ! ::env().vts.lock().active().draw {
+            return Err(Error::new(EACCES));
+        }
+
+        if self.display.is_none() {
+            self.display = Display::root().map(|display| Arc::new(display));
+        }
+
+        if let Some(ref display) = self.display {
+            ::env().vts.lock().active_mut().draw = false;
+
+            Ok(box DisplayResource {
+                path: format!("display:{}/{}", display.width, display.height),
+                display: display.clone(),
+                manager: manager,
+                seek: 0,
+            })
         } else {
-            Err(Error::new(EACCES))
+            Err(Error::new(ENOENT))
         }
     }
 }
@@ -2,26 +2,24 @@ use common::slice::GetSlice;
 
 use alloc::boxed::Box;
 
-use arch::memory::Memory;
-
-use collections::slice;
 use collections::string::{String, ToString};
 use collections::vec::Vec;
 
 use common::debug;
 
-use core::cmp;
+use core::{cmp, mem};
 
-use disk::Disk;
-use disk::ide::Extent;
+use disk::BlockDevice;
+use disk::cache::BlockCache;
 
-use fs::redoxfs::{FileSystem, Node, NodeData};
+use fs::redoxfs::{FileSystem, Node};
 
-use fs::{KScheme, Resource, ResourceSeek, Url, VecResource};
+use fs::{Creds, DirResource, KScheme, Resource, ResourceSeek, Url};
+use fs::access::open_access;
 
-use syscall::{O_CREAT, O_TRUNC, MODE_DIR, MODE_FILE, Stat};
+use syscall::{Dirent, O_CREAT, O_TRUNC, MODE_DIR, MODE_FILE, MODE_READ, MODE_SYMLINK, MODE_WRITE, Stat};
 
-use system::error::{Error, Result, ENOENT, EIO};
+use system::error::{Error, Result, EEXIST, EINVAL, EIO, ELOOP, ENAMETOOLONG, ENOENT, ENOSPC};
 
 /// A file resource
 pub struct FileResource {
@@ -53,6 +51,20 @@ impl Resource for FileResource {
         Ok(cmp::min(buf.len(), path_a.len() + path_b.len()))
     }
 
+    /// The block of the node with the data, which hard links share
+    fn node_path(&self, buf: &mut [u8]) -> Result<usize> {
+        if self.node.block == 0 {
+            return self.path(buf);
+        }
+
+        let path = format!("file:#{}", self.node.block);
+        for (b, p) in buf.iter_mut().zip(path.bytes()) {
+            *b = p;
+        }
+
+        Ok(cmp::min(buf.len(), path.len()))
+    }
+
     fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
         let mut i = 0;
         while i < buf.len() && self.seek < self.vec.len() {
@@ -98,7 +110,7 @@ impl Resource for FileResource {
         Ok(self.seek)
     }
 
-    // TODO: Check to make sure proper amount of bytes written. See Disk::write
+    // TODO: Check to make sure proper amount of bytes written. See BlockDevice::write_blocks
     fn sync(&mut self) -> Result<()> {
         if self.dirty {
             let mut node_dirty = false;
@@ -150,7 +162,7 @@ impl Resource for FileResource {
                     }
 
                     unsafe {
-                        let _ = (*self.scheme).fs.disk.write(extent.block, &self.vec[pos .. pos + max_size]);
+                        let _ = (*self.scheme).fs.disk.write_blocks(extent.block, &self.vec[pos .. pos + max_size]);
                     }
 
                     self.vec.truncate(pos + size);
@@ -165,20 +177,23 @@ impl Resource for FileResource {
 
                 if self.node.block > 0 {
                     unsafe {
-                        if let Some(mut node_data) = Memory::<NodeData>::new(1) {
-                            node_data.write(0, self.node.data());
-
-                            let mut buffer = slice::from_raw_parts(node_data.address() as *mut u8, 512);
-                            let _ = (*self.scheme).fs.disk.write(self.node.block, &mut buffer);
-
-                            debug::d("Renode\n");
-
-                            for mut node in (*self.scheme).fs.nodes.iter_mut() {
-                                if node.block == self.node.block {
-                                    *node = self.node.clone();
-                                }
+                        // The header changed too, if an extent was taken from the free space
+                        try!((*self.scheme).fs.write_header());
+
+                        debug::d("Renode\n");
+
+                        // A file opened through a hard link has the name of the link, which the
+                        // node with the data does not take
+                        let mut stored = None;
+                        for mut node in (*self.scheme).fs.nodes.iter_mut() {
+                            if node.block == self.node.block {
+                                node.extents = self.node.extents;
+                                stored = Some(node.clone());
                             }
                         }
+                        if let Some(node) = stored {
+                            try!((*self.scheme).fs.write_node(&node));
+                        }
                     }
                 } else {
                     debug::d("Need to place Node block\n");
@@ -193,6 +208,8 @@ impl Resource for FileResource {
                 debug::dl();
                 return Err(Error::new(EIO));
             }
+
+            try!(unsafe { (*self.scheme).fs.commit() });
         }
         Ok(())
     }
@@ -214,18 +231,36 @@ impl Drop for FileResource {
     }
 }
 
+/// The permissions of every file. The file system does not keep owners or modes, so every file
+/// belongs to root, and any file may be executed
+const FILE_MODE: u16 = 0o755;
+/// The permissions of every directory, only root may create or remove files
+const DIR_MODE: u16 = 0o755;
+/// The permissions of a symbolic link, which are not used, as the target is checked
+const LINK_MODE: u16 = 0o777;
+
+/// The owner and mode of a directory, to check access to it
+fn dir_stat() -> Stat {
+    Stat {
+        st_mode: MODE_DIR | DIR_MODE,
+        ..Stat::default()
+    }
+}
+
 /// A file scheme (pci + fs)
 pub struct FileScheme {
     fs: FileSystem,
 }
 
 impl FileScheme {
-    /// Create a new file scheme from an array of Disks
-    pub fn new(mut disks: Vec<Box<Disk>>) -> Option<Box<Self>> {
+    /// Create a new file scheme from the first of `disks` with a file system
+    ///
+    /// Blocks are kept in a `BlockCache`, which sends the requests it cannot serve to the disk.
+    pub fn new(mut disks: Vec<Box<BlockDevice>>) -> Option<Box<Self>> {
         while ! disks.is_empty() {
             let disk = disks.remove(0);
             let name = disk.name();
-            match FileSystem::from_disk(disk) {
+            match FileSystem::from_disk(BlockCache::new(disk)) {
                 Ok(fs) => return Some(box FileScheme { fs: fs }),
                 Err(err) => debugln!("{}: {}", name, err)
             }
@@ -233,6 +268,102 @@ impl FileScheme {
 
         None
     }
+
+    /// The node with the data of `node`, another node if `node` is a hard link, with the name of
+    /// `node`, so that the file keeps the path it was opened with
+    fn data_node(&self, node: &Node) -> Result<Node> {
+        if node.link == 0 {
+            return Ok(node.clone());
+        }
+
+        match self.fs.nodes.iter().find(|data| data.block == node.link) {
+            Some(data) => {
+                let mut data = data.clone();
+                data.name = node.name.clone();
+                Ok(data)
+            },
+            None => Err(Error::new(EIO)),
+        }
+    }
+
+    /// The number of names of the data of `node`
+    fn links(&self, node: &Node) -> u32 {
+        let block = if node.link == 0 {
+            node.block
+        } else {
+            node.link
+        };
+        if block == 0 {
+            return 1;
+        }
+
+        1 + self.fs.nodes.iter().filter(|other| other.link == block).count() as u32
+    }
+
+    /// Read the data of `node`
+    fn read_node(&mut self, node: &Node) -> Vec<u8> {
+        let mut vec: Vec<u8> = Vec::new();
+        for extent in &node.extents {
+            if extent.block > 0 && extent.length > 0 {
+                let current_sectors = (extent.length as usize + 511) / 512;
+                let max_size = current_sectors * 512;
+
+                let size = cmp::min(extent.length as usize, max_size);
+
+                let pos = vec.len();
+
+                while vec.len() < pos + max_size {
+                    vec.push(0);
+                }
+
+                let _ = self.fs.disk.read_blocks(extent.block, &mut vec[pos..pos + max_size]);
+
+                vec.truncate(pos + size);
+            }
+        }
+        vec
+    }
+
+    /// Create a node called `path`, taking a block from the free space, if there is one
+    fn create_node(&mut self, path: &str, mode: u16, link: u64) -> Result<Node> {
+        let mut node = Node::create(0, path.to_string(), mode, link);
+
+        if self.fs.header.free_space.length >= 512 {
+            node.block = self.fs.header.free_space.block;
+            self.fs.header.free_space.block = self.fs.header.free_space.block + 1;
+            self.fs.header.free_space.length = self.fs.header.free_space.length -
+                                               512;
+
+            try!(self.fs.write_header());
+            try!(self.fs.write_node(&node));
+            try!(self.fs.commit());
+        }
+
+        self.fs.nodes.push(node.clone());
+
+        Ok(node)
+    }
+
+    /// Check that a new node may be called `path`, which must not exist, as a file or a directory
+    fn check_new(&self, path: &str) -> Result<()> {
+        if path.is_empty() || path.ends_with('/') {
+            return Err(Error::new(ENOENT));
+        }
+        // The name must fit in `NodeData`, with its terminator
+        if path.len() >= 256 {
+            return Err(Error::new(ENAMETOOLONG));
+        }
+
+        let dir = path.to_string() + "/";
+        if self.fs.nodes.iter().any(|node| node.name == path || node.name.starts_with(&dir)) {
+            return Err(Error::new(EEXIST));
+        }
+        if self.fs.header.free_space.length < 512 {
+            return Err(Error::new(ENOSPC));
+        }
+
+        Ok(())
+    }
 }
 
 impl KScheme for FileScheme {
@@ -250,67 +381,57 @@ impl KScheme for FileScheme {
         while path.starts_with('/') {
             path = &path[1..];
         }
+        let creds = Creds::current();
         if path.is_empty() || path.ends_with('/') {
-            let mut list = String::new();
+            try!(creds.check(&dir_stat(), MODE_READ));
+
+            let mut entries: Vec<Dirent> = Vec::new();
             let mut dirs: Vec<String> = Vec::new();
 
-            for file in self.fs.list(path).iter() {
-                let mut line = String::new();
+            for node in self.fs.nodes.iter().filter(|node| node.name.starts_with(path)) {
+                let file = node.name.get_slice(path.len()..);
                 match file.find('/') {
                     Some(index) => {
-                        let dirname = file.get_slice(..index + 1).to_string();
-                        let mut found = false;
-                        for dir in dirs.iter() {
-                            if dirname == *dir {
-                                found = true;
-                                break;
-                            }
-                        }
-                        if found {
-                            line.clear();
-                        } else {
-                            line = dirname.clone();
+                        let dirname = file.get_slice(..index).to_string();
+                        if ! dirs.contains(&dirname) {
+                            entries.push(Dirent::new(&dirname, MODE_DIR | DIR_MODE, 0));
                             dirs.push(dirname);
                         }
                     }
-                    None => line = file.clone(),
-                }
-                if !line.is_empty() {
-                    if !list.is_empty() {
-                        list = list + "\n" + &line;
-                    } else {
-                        list = line;
+                    None => {
+                        let size = match self.data_node(node) {
+                            Ok(data) => data.size(),
+                            Err(_) => 0,
+                        };
+                        if node.mode == MODE_SYMLINK {
+                            entries.push(Dirent::new(file, MODE_SYMLINK | LINK_MODE, size));
+                        } else {
+                            entries.push(Dirent::new(file, MODE_FILE | FILE_MODE, size));
+                        }
                     }
                 }
             }
 
-            if list.len() > 0 {
-                Ok(box VecResource::new(url.to_string(), list.into_bytes()))
+            if ! entries.is_empty() {
+                Ok(box DirResource::new(url.to_string(), entries))
             } else {
                 Err(Error::new(ENOENT))
             }
         } else {
             match self.fs.node(path) {
                 Some(node) => {
-                    let mut vec: Vec<u8> = Vec::new();
-                    for extent in &node.extents {
-                        if extent.block > 0 && extent.length > 0 {
-                            let current_sectors = (extent.length as usize + 511) / 512;
-                            let max_size = current_sectors * 512;
-
-                            let size = cmp::min(extent.length as usize, max_size);
+                    // Links were followed before the scheme was called, unless with `O_NOFOLLOW`
+                    if node.mode == MODE_SYMLINK {
+                        return Err(Error::new(ELOOP));
+                    }
 
-                            let pos = vec.len();
+                    try!(creds.check(&Stat {
+                        st_mode: MODE_FILE | FILE_MODE,
+                        ..Stat::default()
+                    }, open_access(flags)));
 
-                            while vec.len() < pos + max_size {
-                                vec.push(0);
-                            }
-
-                            let _ = self.fs.disk.read(extent.block, &mut vec[pos..pos + max_size]);
-
-                            vec.truncate(pos + size);
-                        }
-                    }
+                    let node = try!(self.data_node(&node));
+                    let vec = self.read_node(&node);
 
                     let mut resource = box FileResource {
                         scheme: self,
@@ -328,24 +449,9 @@ impl KScheme for FileScheme {
                 }
                 None => {
                     if flags & O_CREAT == O_CREAT {
-                        // TODO: Create file
-                        let mut node = Node {
-                            block: 0,
-                            name: path.to_string(),
-                            extents: [Extent {
-                                block: 0,
-                                length: 0,
-                            }; 16],
-                        };
+                        try!(creds.check(&dir_stat(), MODE_WRITE));
 
-                        if self.fs.header.free_space.length >= 512 {
-                            node.block = self.fs.header.free_space.block;
-                            self.fs.header.free_space.block = self.fs.header.free_space.block + 1;
-                            self.fs.header.free_space.length = self.fs.header.free_space.length -
-                                                               512;
-                        }
-
-                        self.fs.nodes.push(node.clone());
+                        let node = try!(self.create_node(path, MODE_FILE, 0));
 
                         Ok(box FileResource {
                             scheme: self,
@@ -402,7 +508,7 @@ impl KScheme for FileScheme {
             }
 
             if list.len() > 0 {
-                stat.st_mode = MODE_DIR;
+                stat.st_mode = MODE_DIR | DIR_MODE;
                 stat.st_size = list.len() as u64;
 
                 Ok(())
@@ -412,14 +518,13 @@ impl KScheme for FileScheme {
         } else {
             match self.fs.node(path) {
                 Some(node) => {
-                    stat.st_mode = MODE_FILE;
-                    stat.st_size = 0;
-
-                    for extent in &node.extents {
-                        if extent.block > 0 && extent.length > 0 {
-                            stat.st_size += extent.length;
-                        }
+                    if node.mode == MODE_SYMLINK {
+                        stat.st_mode = MODE_SYMLINK | LINK_MODE;
+                    } else {
+                        stat.st_mode = MODE_FILE | FILE_MODE;
                     }
+                    stat.st_size = try!(self.data_node(&node)).size();
+                    stat.st_nlink = self.links(&node);
 
                     Ok(())
                 }
@@ -428,30 +533,157 @@ impl KScheme for FileScheme {
         }
     }
 
-    fn unlink(&mut self, url: Url) -> Result<()> {
-        let mut ret = Err(Error::new(ENOENT));
+    /// Rename a file, or a directory by renaming the files in it. Fails with `EEXIST` if `new`
+    /// exists, rather than replacing it
+    fn rename(&mut self, old: Url, new: Url) -> Result<()> {
+        let mut old_path = old.reference();
+        while old_path.starts_with('/') {
+            old_path = &old_path[1..];
+        }
+        let mut new_path = new.reference();
+        while new_path.starts_with('/') {
+            new_path = &new_path[1..];
+        }
+        let old_file = old_path.trim_right_matches('/');
+        let new_file = new_path.trim_right_matches('/');
+        if old_file.is_empty() || new_file.is_empty() {
+            return Err(Error::new(ENOENT));
+        }
+        try!(Creds::current().check(&dir_stat(), MODE_WRITE));
+
+        let old_dir = old_file.to_string() + "/";
+        let new_dir = new_file.to_string() + "/";
+        if new_dir.starts_with(&old_dir) {
+            return Err(Error::new(EINVAL));
+        }
+        if self.fs.nodes.iter().any(|node| node.name == new_file || node.name.starts_with(&new_dir)) {
+            return Err(Error::new(EEXIST));
+        }
+
+        let mut renames = Vec::new();
+        for (i, node) in self.fs.nodes.iter().enumerate() {
+            let name = if node.name == old_file {
+                new_file.to_string()
+            } else if node.name.starts_with(&old_dir) {
+                new_dir.clone() + &node.name[old_dir.len()..]
+            } else {
+                continue;
+            };
+            // The name must fit in `NodeData`, with its terminator
+            if name.len() >= 256 {
+                return Err(Error::new(ENAMETOOLONG));
+            }
+            renames.push((i, name));
+        }
+        if renames.is_empty() {
+            return Err(Error::new(ENOENT));
+        }
+
+        for (i, name) in renames {
+            self.fs.nodes[i].name = name;
 
+            let node = self.fs.nodes[i].clone();
+            if node.block > 0 {
+                try!(self.fs.write_node(&node));
+            }
+        }
+
+        self.fs.commit()
+    }
+
+    /// Create `new` as a hard link to the file `old`, which may be a hard link itself
+    fn link(&mut self, old: Url, new: Url) -> Result<()> {
+        let mut old_path = old.reference();
+        while old_path.starts_with('/') {
+            old_path = &old_path[1..];
+        }
+        let mut new_path = new.reference();
+        while new_path.starts_with('/') {
+            new_path = &new_path[1..];
+        }
+        try!(Creds::current().check(&dir_stat(), MODE_WRITE));
+
+        let node = try!(self.fs.node(old_path).ok_or(Error::new(ENOENT)));
+        try!(self.check_new(new_path));
+
+        let block = if node.link == 0 {
+            node.block
+        } else {
+            node.link
+        };
+        if block == 0 {
+            return Err(Error::new(EIO));
+        }
+
+        self.create_node(new_path, node.mode, block).and(Ok(()))
+    }
+
+    /// Create a symbolic link, a node with the target as its data
+    fn symlink(&mut self, target: &str, url: Url) -> Result<()> {
         let mut path = url.reference();
         while path.starts_with('/') {
             path = &path[1..];
         }
+        try!(Creds::current().check(&dir_stat(), MODE_WRITE));
+        try!(self.check_new(path));
+
+        let node = try!(self.create_node(path, MODE_SYMLINK, 0));
+        let mut resource = FileResource {
+            scheme: self,
+            node: node,
+            vec: target.as_bytes().to_vec(),
+            seek: 0,
+            dirty: true,
+        };
+        resource.sync()
+    }
 
-        let mut i = 0;
-        while i < self.fs.nodes.len() {
-            let mut remove = false;
+    fn readlink(&mut self, url: Url, buf: &mut [u8]) -> Result<usize> {
+        let mut path = url.reference();
+        while path.starts_with('/') {
+            path = &path[1..];
+        }
 
-            if let Some(node) = self.fs.nodes.get(i) {
-                remove = node.name == path;
-            }
+        let node = match self.fs.node(path) {
+            Some(node) => try!(self.data_node(&node)),
+            None => return Err(Error::new(ENOENT)),
+        };
+        if node.mode != MODE_SYMLINK {
+            return Err(Error::new(EINVAL));
+        }
 
-            if remove {
-                self.fs.nodes.remove(i);
-                ret = Ok(());
-            } else {
-                i += 1;
+        let target = self.read_node(&node);
+        let count = cmp::min(buf.len(), target.len());
+        buf[..count].copy_from_slice(&target[..count]);
+        Ok(count)
+    }
+
+    /// Remove a name of a file. If it is the node with the data, the node takes the name of one
+    /// of its hard links instead, and the node of that link is removed
+    fn unlink(&mut self, url: Url) -> Result<()> {
+        try!(Creds::current().check(&dir_stat(), MODE_WRITE));
+
+        let mut path = url.reference();
+        while path.starts_with('/') {
+            path = &path[1..];
+        }
+
+        let i = try!(self.fs.nodes.iter().position(|node| node.name == path).ok_or(Error::new(ENOENT)));
+        let mut removed = self.fs.nodes.remove(i);
+
+        if removed.link == 0 && removed.block > 0 {
+            if let Some(j) = self.fs.nodes.iter().position(|node| node.link == removed.block) {
+                let mut other = self.fs.nodes.remove(j);
+                mem::swap(&mut removed.name, &mut other.name);
+                try!(self.fs.write_node(&removed));
+                self.fs.nodes.push(removed);
+                removed = other;
             }
         }
 
-        ret
+        if removed.block > 0 {
+            try!(self.fs.remove_node(removed.block));
+        }
+        self.fs.commit()
     }
-}
+}
\ No newline at end of file
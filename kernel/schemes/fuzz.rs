@@ -0,0 +1,105 @@
+use alloc::boxed::Box;
+
+use collections::string::ToString;
+use collections::vec::Vec;
+
+use core::cmp;
+
+use fs::{KScheme, Resource, Url};
+
+use syscall::strace;
+
+use system::error::{Error, Result, EACCES};
+use system::syscall::O_TRUNC;
+
+/// An open `fuzz:`, the context that opened it is fuzzing until it is closed
+///
+/// Each read that finds no pending data returns the tallies as `<syscall> <outcome> <count>` lines,
+/// the outcome being `ok` or the error number.
+pub struct FuzzResource {
+    pid: usize,
+    data: Vec<u8>,
+}
+
+impl Resource for FuzzResource {
+    fn path(&self, buf: &mut [u8]) -> Result<usize> {
+        let path = b"fuzz:";
+
+        for (b, p) in buf.iter_mut().zip(path.iter()) {
+            *b = *p;
+        }
+
+        Ok(cmp::min(buf.len(), path.len()))
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if self.data.is_empty() {
+            let fuzz = ::env().fuzz.lock();
+            let mut string = format!("refused {}\n", fuzz.refused);
+            for (&(number, outcome), count) in fuzz.coverage.iter() {
+                let name = match strace::name(number) {
+                    Some(name) => name.to_string(),
+                    None => format!("{}", number),
+                };
+                let outcome = if outcome == 0 {
+                    "ok".to_string()
+                } else {
+                    format!("{}", outcome)
+                };
+                string.push_str(&format!("{:<24}{:<8}{}\n", name, outcome, count));
+            }
+            self.data = string.into_bytes();
+        }
+
+        let count = cmp::min(buf.len(), self.data.len());
+        for (b, d) in buf.iter_mut().zip(self.data.drain(.. count)) {
+            *b = d;
+        }
+        Ok(count)
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for FuzzResource {
+    fn drop(&mut self) {
+        ::env().fuzz.lock().close(self.pid);
+    }
+}
+
+/// The fuzz scheme
+///
+/// Opening `fuzz:` is limited to root. While the context holds it open, its syscalls are tallied
+/// and those that would end it are refused, so that a fuzzer can call anything with arbitrary
+/// arguments. `O_TRUNC` clears the tallies.
+pub struct FuzzScheme;
+
+impl KScheme for FuzzScheme {
+    fn scheme(&self) -> &str {
+        "fuzz"
+    }
+
+    fn open(&mut self, _: Url, flags: usize) -> Result<Box<Resource>> {
+        let pid = {
+            let contexts = ::env().contexts.read();
+            let current = try!(contexts.current());
+            if current.euid != 0 {
+                return Err(Error::new(EACCES));
+            }
+            current.pid
+        };
+
+        let mut fuzz = ::env().fuzz.lock();
+        if flags & O_TRUNC == O_TRUNC {
+            fuzz.reset();
+        }
+        fuzz.open(pid);
+
+        Ok(box FuzzResource {
+            pid: pid,
+            data: Vec::new(),
+        })
+    }
+}
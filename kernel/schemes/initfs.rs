@@ -1,25 +1,123 @@
 use alloc::boxed::Box;
 
-use collections::{BTreeMap, String};
+use arch::multiboot;
 
-use fs::{KScheme, Resource, Url, VecResource};
+use collections::{BTreeMap, Vec};
 
-use system::error::{Error, Result, ENOENT};
+use core::str;
 
-#[path="../../build/initfs.gen"]
-pub mod gen;
+use fs::{DirResource, KScheme, Resource, Url, VecResource};
 
-/// A memory scheme
+use system::error::{Error, Result, ENOENT, EROFS};
+use system::syscall::{Dirent, Stat, MODE_DIR, MODE_FILE, O_CREAT, O_RDWR, O_TRUNC, O_WRONLY};
+
+/// The signature at the start of an initfs archive, see `initfs.asm`
+const INITFS_SIGNATURE: &'static [u8; 8] = b"RDXINITF";
+
+/// The permissions of the files, which are executable, as the archive does not keep modes
+const FILE_MODE: u16 = 0o555;
+/// The permissions of the directories
+const DIR_MODE: u16 = 0o555;
+
+/// Read a 32 bit little endian number at `offset` in `data`
+fn read_u32(data: &[u8], offset: usize) -> Option<usize> {
+    if offset + 4 <= data.len() {
+        Some((0..4).fold(0, |value, i| value | (data[offset + i] as usize) << (i * 8)))
+    } else {
+        None
+    }
+}
+
+/// Read the files of an initfs archive into `files`, returning false if `data` is not one
+///
+/// An archive that ends early keeps the files before the end.
+fn read_archive(data: &'static [u8], files: &mut BTreeMap<&'static str, &'static [u8]>) -> bool {
+    if data.len() < INITFS_SIGNATURE.len() || &data[..INITFS_SIGNATURE.len()] != INITFS_SIGNATURE {
+        return false;
+    }
+
+    let mut offset = INITFS_SIGNATURE.len();
+    while let (Some(name_len), Some(data_len)) = (read_u32(data, offset), read_u32(data, offset + 4)) {
+        if name_len == 0 {
+            break;
+        }
+
+        let name_start = offset + 8;
+        let data_start = name_start + name_len;
+        let data_end = data_start + data_len;
+        if data_end > data.len() {
+            debugln!("initfs: archive ends in a file");
+            break;
+        }
+
+        match str::from_utf8(&data[name_start..data_start]) {
+            Ok(name) => {
+                files.insert(name.trim_matches('/'), &data[data_start..data_end]);
+            },
+            Err(_) => debugln!("initfs: file name is not UTF-8"),
+        }
+
+        offset = data_end;
+    }
+
+    true
+}
+
+/// The files of the initfs archive, read only
+///
+/// The archive is loaded into memory by the boot loader, after the kernel by the legacy boot
+/// loader, or as a module by a Multiboot2 loader, so programs like the drivers of the root file
+/// system can run before any disk is read. Directories are not in the archive, they are the
+/// prefixes of the names of the files.
 pub struct InitFsScheme {
     pub files: BTreeMap<&'static str, &'static [u8]>
 }
 
 impl InitFsScheme {
     pub fn new() -> Box<InitFsScheme> {
+        let mut files = BTreeMap::new();
+        for module in multiboot::modules() {
+            // The memory of the modules is reserved, and never freed
+            if read_archive(unsafe { module.data() }, &mut files) {
+                debugln!("initfs: {} bytes in {}", module.end - module.start, module.name());
+            }
+        }
+
         Box::new(InitFsScheme {
-            files: gen::gen()
+            files: files
         })
     }
+
+    /// The entries of the directory `path`, which is empty for the root, or `None` if there is
+    /// no file in it
+    fn entries(&self, path: &str) -> Option<Vec<Dirent>> {
+        let mut entries: Vec<Dirent> = Vec::new();
+        for (&name, data) in self.files.iter() {
+            let rest = if path.is_empty() {
+                name
+            } else if name.starts_with(path) && name[path.len()..].starts_with('/') {
+                &name[path.len() + 1..]
+            } else {
+                continue;
+            };
+
+            match rest.find('/') {
+                Some(i) => {
+                    let dir = &rest[..i];
+                    if ! entries.last().map_or(false, |entry| entry.name() == dir.as_bytes()) {
+                        entries.push(Dirent::new(dir, MODE_DIR | DIR_MODE, 0));
+                    }
+                },
+                None => entries.push(Dirent::new(rest, MODE_FILE | FILE_MODE, data.len() as u64)),
+            }
+        }
+
+        if entries.is_empty() && ! path.is_empty() {
+            None
+        } else {
+            Some(entries)
+        }
+    }
 }
 
 impl KScheme for InitFsScheme {
@@ -27,25 +125,34 @@ impl KScheme for InitFsScheme {
         "initfs"
     }
 
-    fn open(&mut self, url: Url, _: usize) -> Result<Box<Resource>> {
+    fn open(&mut self, url: Url, flags: usize) -> Result<Box<Resource>> {
+        if flags & (O_WRONLY | O_RDWR | O_CREAT | O_TRUNC) != 0 {
+            return Err(Error::new(EROFS));
+        }
+
         let reference = url.reference().trim_matches('/');
-        if reference.is_empty() {
-            let mut list = String::new();
-
-            for file in self.files.iter() {
-                if ! list.is_empty() {
-                    list.push('\n');
-                }
-                list.push_str(file.0);
-            }
+        if let Some(data) = self.files.get(reference) {
+            return Ok(box VecResource::new(url.to_string(), data.to_vec()));
+        }
 
-            Ok(box VecResource::new(url.to_string(), list.into_bytes()))
-        }else {
-            if let Some(data) = self.files.get(reference) {
-                Ok(box VecResource::new(url.to_string(), data.to_vec()))
-            } else {
-                Err(Error::new(ENOENT))
-            }
+        match self.entries(reference) {
+            Some(entries) => Ok(box DirResource::new(url.to_string(), entries)),
+            None => Err(Error::new(ENOENT)),
         }
     }
+
+    fn stat(&mut self, url: Url, stat: &mut Stat) -> Result<()> {
+        let reference = url.reference().trim_matches('/');
+        if let Some(data) = self.files.get(reference) {
+            stat.st_mode = MODE_FILE | FILE_MODE;
+            stat.st_size = data.len() as u64;
+        } else if self.entries(reference).is_some() {
+            stat.st_mode = MODE_DIR | DIR_MODE;
+        } else {
+            return Err(Error::new(ENOENT));
+        }
+
+        stat.st_nlink = 1;
+        Ok(())
+    }
 }
@@ -1,5 +1,7 @@
 use alloc::boxed::Box;
 
+use arch::percpu;
+
 use collections::string::ToString;
 
 use fs::{KScheme, Resource, Url, VecResource};
@@ -36,7 +38,7 @@ impl KScheme for InterruptScheme {
         let mut string = format!("{:<6}{:<16}{}\n", "INT", "COUNT", "DESCRIPTION");
 
         {
-            let interrupts = ::env().interrupts.lock();
+            let interrupts = percpu::interrupts();
             for interrupt in 0..interrupts.len() {
                 let count = interrupts[interrupt];
 
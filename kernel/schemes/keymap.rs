@@ -0,0 +1,88 @@
+use alloc::boxed::Box;
+
+use core::{cmp, str};
+
+use drivers::kb_layouts::layouts::{Keymap, Layout};
+
+use fs::{KScheme, Resource, Url};
+
+use system::error::{Error, Result, EACCES, EINVAL};
+
+/// The keyboard layout
+///
+/// Reading returns the name of the layout. Writing the name of a builtin layout, `english`,
+/// `french` or `german`, selects it, and writing a keymap table loads it, see `Keymap` for the
+/// format. Each write must be a complete name or table.
+pub struct KeymapResource {
+    seek: usize,
+}
+
+impl Resource for KeymapResource {
+    fn dup(&self) -> Result<Box<Resource>> {
+        Ok(box KeymapResource {
+            seek: self.seek,
+        })
+    }
+
+    fn path(&self, buf: &mut [u8]) -> Result<usize> {
+        let path = b"keymap:";
+
+        for (b, p) in buf.iter_mut().zip(path.iter()) {
+            *b = *p;
+        }
+
+        Ok(cmp::min(buf.len(), path.len()))
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let name = format!("{}\n", ::env().layout.lock().name());
+        let data = name.as_bytes();
+
+        let mut i = 0;
+        while i < buf.len() && self.seek < data.len() {
+            buf[i] = data[self.seek];
+            i += 1;
+            self.seek += 1;
+        }
+
+        Ok(i)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        {
+            let contexts = ::env().contexts.read();
+            let current = try!(contexts.current());
+            if current.euid != 0 {
+                return Err(Error::new(EACCES));
+            }
+        }
+
+        let text = try!(str::from_utf8(buf).or(Err(Error::new(EINVAL))));
+        let layout = match Layout::from_name(text.trim()) {
+            Some(layout) => layout,
+            None => Layout::Custom(box try!(Keymap::parse(text))),
+        };
+        *::env().layout.lock() = layout;
+
+        Ok(buf.len())
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// The keyboard layout scheme, changing the layout is limited to root
+pub struct KeymapScheme;
+
+impl KScheme for KeymapScheme {
+    fn scheme(&self) -> &str {
+        "keymap"
+    }
+
+    fn open(&mut self, _: Url, _: usize) -> Result<Box<Resource>> {
+        Ok(box KeymapResource {
+            seek: 0,
+        })
+    }
+}
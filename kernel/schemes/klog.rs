@@ -0,0 +1,36 @@
+use alloc::boxed::Box;
+
+use collections::string::ToString;
+
+use fs::{KScheme, Resource, Url, VecResource};
+
+use system::error::{Error, Result, EACCES};
+use system::syscall::O_TRUNC;
+
+/// The kernel log scheme, opening with `O_TRUNC` clears the log and is limited to root
+pub struct KlogScheme;
+
+impl KScheme for KlogScheme {
+    fn scheme(&self) -> &str {
+        "klog"
+    }
+
+    fn open(&mut self, _: Url, flags: usize) -> Result<Box<Resource>> {
+        let clear = flags & O_TRUNC == O_TRUNC;
+        if clear {
+            let contexts = ::env().contexts.read();
+            let current = try!(contexts.current());
+            if current.euid != 0 {
+                return Err(Error::new(EACCES));
+            }
+        }
+
+        let mut log = ::env().log.lock();
+        let string = log.to_string();
+        if clear {
+            log.clear();
+        }
+
+        Ok(box VecResource::new("klog:".to_string(), string.into_bytes()))
+    }
+}
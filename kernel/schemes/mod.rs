@@ -1,18 +1,52 @@
+/// Audit scheme
+pub mod audit;
+/// Message queues
+pub mod chan;
 /// Context scheme
 pub mod context;
 /// Debug scheme
 pub mod debug;
+/// Disks and partitions
+pub mod disk;
 /// Display Scheme
 pub mod display;
 /// File scheme
 pub mod file;
+/// Syscall fuzzing
+pub mod fuzz;
 /// Init Filesystem
 pub mod initfs;
 /// Interrupt scheme
 pub mod interrupt;
+/// Keyboard layout scheme
+pub mod keymap;
+/// Kernel log scheme
+pub mod klog;
 /// Memory scheme
 pub mod memory;
+/// Performance counter scheme
+pub mod perf;
 /// Pipes
 pub mod pipe;
+/// Process information
+pub mod proc;
+/// Pseudo-terminals
+pub mod pty;
+/// Random number scheme
+pub mod rand;
+/// Shared memory objects
+pub mod shm;
+/// Syscall tracing
+#[cfg(feature = "trace")]
+pub mod strace;
+/// System information
+pub mod sys;
 /// Tests
 pub mod test;
+/// Timer scheme
+pub mod time;
+/// Temporary files in memory
+pub mod tmp;
+/// Tracepoint scheme
+#[cfg(feature = "trace")]
+pub mod trace;
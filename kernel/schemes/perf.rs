@@ -0,0 +1,134 @@
+use alloc::boxed::Box;
+
+use collections::string::{String, ToString};
+use collections::vec::Vec;
+
+use core::cmp;
+
+use drivers::pmu::{PerfEvent, PERF_EVENTS};
+
+use fs::{KScheme, Resource, Url, VecResource};
+
+use system::error::{Error, Result, EACCES, EBUSY, ENODEV, ENOENT, ESRCH};
+
+/// An open performance counter
+///
+/// Each read that finds no pending data returns the current count, followed by the samples taken
+/// since the last read, as `<tsc> <count>` lines.
+pub struct PerfResource {
+    id: usize,
+    path: String,
+    data: Vec<u8>,
+}
+
+impl Resource for PerfResource {
+    fn path(&self, buf: &mut [u8]) -> Result<usize> {
+        let path = self.path.as_bytes();
+
+        for (b, p) in buf.iter_mut().zip(path.iter()) {
+            *b = *p;
+        }
+
+        Ok(cmp::min(buf.len(), path.len()))
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if self.data.is_empty() {
+            let mut perf = ::env().perf.lock();
+            let mut string = format!("count {}\n", perf.value(self.id));
+            if let Some(counter) = perf.counters.get_mut(&self.id) {
+                for (tsc, value) in counter.samples.drain(..) {
+                    string.push_str(&format!("{} {}\n", tsc, value));
+                }
+            }
+            self.data = string.into_bytes();
+        }
+
+        let count = cmp::min(buf.len(), self.data.len());
+        for (b, d) in buf.iter_mut().zip(self.data.drain(.. count)) {
+            *b = d;
+        }
+        Ok(count)
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for PerfResource {
+    fn drop(&mut self) {
+        ::env().perf.lock().close(self.id);
+    }
+}
+
+/// The perf scheme
+///
+/// `perf:<event>` counts system-wide and is limited to root, `perf:<event>/<pid>` only counts while
+/// that context runs. `perf:` lists the events and counters.
+pub struct PerfScheme;
+
+impl KScheme for PerfScheme {
+    fn scheme(&self) -> &str {
+        "perf"
+    }
+
+    fn open(&mut self, url: Url, _: usize) -> Result<Box<Resource>> {
+        let reference = url.reference();
+
+        if reference.is_empty() {
+            let perf = ::env().perf.lock();
+            let mut string = String::new();
+            if let Some(ref pmu) = perf.pmu {
+                string.push_str(&format!("version {}\n", pmu.version));
+                string.push_str(&format!("general {} x {} bits\n", pmu.general, pmu.general_width));
+                string.push_str(&format!("fixed {} x {} bits\n", pmu.fixed, pmu.fixed_width));
+                string.push_str(&format!("used {}\n", pmu.used.len()));
+            } else {
+                string.push_str("no pmu\n");
+            }
+            for event in PERF_EVENTS.iter() {
+                string.push_str(event.name());
+                string.push('\n');
+            }
+            return Ok(box VecResource::new("perf:".to_string(), string.into_bytes()));
+        }
+
+        let mut parts = reference.splitn(2, '/');
+        let event = try!(parts.next().and_then(PerfEvent::from_name).ok_or(Error::new(ENOENT)));
+        let pid = match parts.next() {
+            Some(pid) => Some(try!(pid.parse::<usize>().or(Err(Error::new(ESRCH))))),
+            None => None,
+        };
+
+        let current_pid = {
+            let contexts = ::env().contexts.read();
+            let current = try!(contexts.current());
+            match pid {
+                Some(pid) => {
+                    let target = try!(contexts.iter().find(|context| context.pid == pid)
+                                              .ok_or(Error::new(ESRCH)));
+                    if current.euid != 0 && current.euid != target.uid {
+                        return Err(Error::new(EACCES));
+                    }
+                },
+                None => if current.euid != 0 {
+                    return Err(Error::new(EACCES));
+                },
+            }
+            current.pid
+        };
+
+        let mut perf = ::env().perf.lock();
+        if perf.pmu.is_none() {
+            return Err(Error::new(ENODEV));
+        }
+        let id = try!(perf.open(event, pid, current_pid).ok_or(Error::new(EBUSY)));
+
+        Ok(box PerfResource {
+            id: id,
+            path: format!("perf:{}", reference),
+            data: Vec::new(),
+        })
+    }
+}
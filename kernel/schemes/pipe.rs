@@ -1,109 +1,333 @@
-use alloc::arc::{Arc, Weak};
+use alloc::arc::Arc;
 use alloc::boxed::Box;
 
+use collections::{BTreeMap, String, Vec};
+use collections::string::ToString;
+use collections::vec_deque::VecDeque;
+
 use core::cmp;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use fs::{Creds, DirResource, KScheme, Resource, Url};
+use fs::access::open_access;
+
+use sync::{Intex, WaitCondition};
 
-use fs::Resource;
+use system::error::{Error, Result, EINVAL, ENOENT, ENXIO, EPERM, EPIPE};
+use system::syscall::{Dirent, Stat, MODE_FIFO, O_CREAT, O_NONBLOCK, O_RDWR, O_WRONLY, PIPE_BUF, POLLERR, POLLHUP,
+                      POLLIN, POLLOUT};
 
-use sync::WaitQueue;
+/// The number of bytes a pipe holds before writers block
+pub const PIPE_SIZE: usize = 65536;
 
-use system::error::{Error, Result, EPIPE};
+/// A pipe, shared by its ends
+///
+/// Readers block while it is empty and read end of file once every writer has closed. Writers
+/// block while it is full and fail with `EPIPE` once every reader has closed.
+pub struct Pipe {
+    /// The name in `pipe:`, empty for pipes made with `pipe2`
+    name: String,
+    /// The context that made the pipe, which alone may open it by name, or remove the name
+    owner: Creds,
+    /// The bytes written that have not been read, at most `PIPE_SIZE`
+    buffer: Intex<VecDeque<u8>>,
+    /// Notified when bytes are written, or the last writer closes
+    readable: WaitCondition,
+    /// Notified when bytes are read, or the last reader closes
+    writable: WaitCondition,
+    /// Notified when an end is opened, for `open` of a named pipe
+    opened: WaitCondition,
+    readers: AtomicUsize,
+    writers: AtomicUsize,
+}
+
+impl Pipe {
+    pub fn new(name: String) -> Arc<Pipe> {
+        Arc::new(Pipe {
+            name: name,
+            owner: Creds::current(),
+            buffer: Intex::new(VecDeque::new()),
+            readable: WaitCondition::new(),
+            writable: WaitCondition::new(),
+            opened: WaitCondition::new(),
+            readers: AtomicUsize::new(0),
+            writers: AtomicUsize::new(0),
+        })
+    }
+
+    fn path(&self, buf: &mut [u8]) -> Result<usize> {
+        let path_a = b"pipe:";
+        let path_b = self.name.as_bytes();
+        for (b, p) in buf.iter_mut().zip(path_a.iter().chain(path_b.iter())) {
+            *b = *p;
+        }
+
+        Ok(cmp::min(buf.len(), path_a.len() + path_b.len()))
+    }
+
+    fn stat(&self, stat: &mut Stat) -> Result<usize> {
+        stat.st_mode = MODE_FIFO | 0o600;
+        stat.st_size = self.buffer.lock().len() as u64;
+        stat.st_uid = self.owner.uid as u32;
+        stat.st_gid = self.owner.gid as u32;
+        Ok(0)
+    }
+
+    /// Block until `count` is not zero, for `open` of a named pipe
+    fn wait_open(&self, count: &AtomicUsize) {
+        while count.load(Ordering::SeqCst) == 0 {
+            unsafe { self.opened.wait(); }
+        }
+    }
+}
 
 /// Read side of a pipe
 pub struct PipeRead {
-    vec: Arc<WaitQueue<u8>>
+    pipe: Arc<Pipe>,
 }
 
 impl PipeRead {
-    pub fn new() -> Self {
+    pub fn new(pipe: Arc<Pipe>) -> Self {
+        pipe.readers.fetch_add(1, Ordering::SeqCst);
+        unsafe { pipe.opened.notify(); }
         PipeRead {
-            vec: Arc::new(WaitQueue::new())
+            pipe: pipe,
         }
     }
 }
 
 impl Resource for PipeRead {
     fn dup(&self) -> Result<Box<Resource>> {
-        Ok(box PipeRead {
-            vec: self.vec.clone(),
-        })
+        Ok(box PipeRead::new(self.pipe.clone()))
     }
 
     fn path(&self, buf: &mut [u8]) -> Result<usize> {
-        let path = b"pipe:r";
-
-        for (b, p) in buf.iter_mut().zip(path.iter()) {
-            *b = *p;
-        }
-
-        Ok(cmp::min(buf.len(), path.len()))
+        self.pipe.path(buf)
     }
 
     fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
-        if Arc::weak_count(&self.vec) == 0 && self.vec.inner.lock().is_empty() {
-            Ok(0)
-        } else {
-            if !buf.is_empty() {
-                buf[0] = self.vec.receive();
-            }
+        loop {
+            {
+                let mut buffer = self.pipe.buffer.lock();
+                if ! buffer.is_empty() || buf.is_empty() {
+                    let count = cmp::min(buf.len(), buffer.len());
+                    for (b, d) in buf.iter_mut().zip(buffer.drain(.. count)) {
+                        *b = d;
+                    }
+                    drop(buffer);
 
-            let mut i = 1;
+                    unsafe { self.pipe.writable.notify(); }
+                    return Ok(count);
+                }
 
-            while i < buf.len() {
-                match self.vec.inner.lock().pop_front() {
-                    Some(b) => {
-                        buf[i] = b;
-                        i += 1;
-                    },
-                    None => break
+                if self.pipe.writers.load(Ordering::SeqCst) == 0 {
+                    return Ok(0);
                 }
             }
 
-            Ok(i)
+            unsafe { self.pipe.readable.wait(); }
         }
     }
+
+    fn stat(&self, stat: &mut Stat) -> Result<usize> {
+        self.pipe.stat(stat)
+    }
+
+    fn poll(&self) -> Result<usize> {
+        let mut events = 0;
+        if ! self.pipe.buffer.lock().is_empty() {
+            events |= POLLIN;
+        }
+        if self.pipe.writers.load(Ordering::SeqCst) == 0 {
+            events |= POLLHUP;
+        }
+        Ok(events)
+    }
 }
 
-/// Read side of a pipe
+impl Drop for PipeRead {
+    fn drop(&mut self) {
+        if self.pipe.readers.fetch_sub(1, Ordering::SeqCst) == 1 {
+            // Blocked writers fail with `EPIPE`
+            unsafe { self.pipe.writable.notify(); }
+        }
+    }
+}
+
+/// Write side of a pipe
 pub struct PipeWrite {
-    vec: Weak<WaitQueue<u8>>,
+    pipe: Arc<Pipe>,
 }
 
 impl PipeWrite {
-    pub fn new(read: &PipeRead) -> Self {
+    pub fn new(pipe: Arc<Pipe>) -> Self {
+        pipe.writers.fetch_add(1, Ordering::SeqCst);
+        unsafe { pipe.opened.notify(); }
         PipeWrite {
-            vec: Arc::downgrade(&read.vec),
+            pipe: pipe,
         }
     }
 }
 
 impl Resource for PipeWrite {
     fn dup(&self) -> Result<Box<Resource>> {
-        Ok(box PipeWrite {
-            vec: self.vec.clone(),
-        })
+        Ok(box PipeWrite::new(self.pipe.clone()))
     }
 
     fn path(&self, buf: &mut [u8]) -> Result<usize> {
-        let path = b"pipe:w";
+        self.pipe.path(buf)
+    }
 
-        for (b, p) in buf.iter_mut().zip(path.iter()) {
-            *b = *p;
+    /// Writes of up to `PIPE_BUF` bytes wait for room for all of them, so that they are not
+    /// interleaved with other writers
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let atomic = buf.len() <= PIPE_BUF;
+        let mut i = 0;
+        while i < buf.len() {
+            if self.pipe.readers.load(Ordering::SeqCst) == 0 {
+                return if i > 0 {
+                    Ok(i)
+                } else {
+                    Err(Error::new(EPIPE))
+                };
+            }
+
+            let count = {
+                let mut buffer = self.pipe.buffer.lock();
+                let space = PIPE_SIZE - buffer.len();
+                let count = if atomic && space < buf.len() {
+                    0
+                } else {
+                    cmp::min(buf.len() - i, space)
+                };
+                buffer.extend(buf[i .. i + count].iter().cloned());
+                count
+            };
+
+            if count > 0 {
+                i += count;
+                unsafe { self.pipe.readable.notify(); }
+            } else {
+                unsafe { self.pipe.writable.wait(); }
+            }
         }
 
-        Ok(cmp::min(buf.len(), path.len()))
+        Ok(i)
     }
 
-    fn write(&mut self, buf: &[u8]) -> Result<usize> {
-        match self.vec.upgrade() {
-            Some(vec) => {
-                for &b in buf.iter() {
-                    vec.send(b);
-                }
+    fn stat(&self, stat: &mut Stat) -> Result<usize> {
+        self.pipe.stat(stat)
+    }
+
+    fn poll(&self) -> Result<usize> {
+        if self.pipe.readers.load(Ordering::SeqCst) == 0 {
+            Ok(POLLERR)
+        } else if PIPE_SIZE - self.pipe.buffer.lock().len() >= PIPE_BUF {
+            Ok(POLLOUT)
+        } else {
+            Ok(0)
+        }
+    }
+}
+
+impl Drop for PipeWrite {
+    fn drop(&mut self) {
+        if self.pipe.writers.fetch_sub(1, Ordering::SeqCst) == 1 {
+            // Blocked readers read end of file
+            unsafe { self.pipe.readable.notify(); }
+        }
+    }
+}
+
+/// Named pipes
+///
+/// Opening `pipe:<name>` with `O_CREAT` creates a pipe that unrelated processes can open by name,
+/// which stays until it is unlinked. A pipe is opened for reading, or for writing with `O_WRONLY`,
+/// and `open` blocks until the other end is open too. With `O_NONBLOCK`, opening for reading
+/// does not wait, and opening for writing fails with `ENXIO` if there is no reader. Opening
+/// `pipe:` lists the named pipes.
+pub struct PipeScheme {
+    pipes: BTreeMap<String, Arc<Pipe>>,
+}
+
+impl PipeScheme {
+    pub fn new() -> Box<Self> {
+        box PipeScheme {
+            pipes: BTreeMap::new(),
+        }
+    }
+}
 
-                Ok(buf.len())
+impl KScheme for PipeScheme {
+    fn scheme(&self) -> &str {
+        "pipe"
+    }
+
+    fn open(&mut self, url: Url, flags: usize) -> Result<Box<Resource>> {
+        let name = url.reference().trim_matches('/');
+        if name.is_empty() {
+            let entries: Vec<Dirent> = self.pipes.iter().map(|(name, pipe)| {
+                Dirent::new(name, MODE_FIFO | 0o600, pipe.buffer.lock().len() as u64)
+            }).collect();
+            return Ok(box DirResource::new("pipe:".to_string(), entries));
+        }
+
+        // Each resource is one end of the pipe
+        if flags & O_RDWR == O_RDWR {
+            return Err(Error::new(EINVAL));
+        }
+
+        let pipe = match self.pipes.get(name) {
+            Some(pipe) => {
+                let mut stat = Stat::default();
+                try!(pipe.stat(&mut stat));
+                try!(Creds::current().check(&stat, open_access(flags)));
+                pipe.clone()
             },
-            None => Err(Error::new(EPIPE))
+            None => if flags & O_CREAT == O_CREAT {
+                let pipe = Pipe::new(name.to_string());
+                self.pipes.insert(name.to_string(), pipe.clone());
+                pipe
+            } else {
+                return Err(Error::new(ENOENT));
+            },
+        };
+
+        if flags & O_WRONLY == O_WRONLY {
+            if flags & O_NONBLOCK == O_NONBLOCK && pipe.readers.load(Ordering::SeqCst) == 0 {
+                return Err(Error::new(ENXIO));
+            }
+            let write = PipeWrite::new(pipe.clone());
+            pipe.wait_open(&pipe.readers);
+            Ok(box write)
+        } else {
+            let read = PipeRead::new(pipe.clone());
+            if flags & O_NONBLOCK != O_NONBLOCK {
+                pipe.wait_open(&pipe.writers);
+            }
+            Ok(box read)
+        }
+    }
+
+    fn stat(&mut self, url: Url, stat: &mut Stat) -> Result<()> {
+        let name = url.reference().trim_matches('/');
+        match self.pipes.get(name) {
+            Some(pipe) => pipe.stat(stat).and(Ok(())),
+            None => Err(Error::new(ENOENT)),
+        }
+    }
+
+    /// Remove the name, ends that are open keep working. Like a sticky directory, only the
+    /// owner of a pipe or root may remove it
+    fn unlink(&mut self, url: Url) -> Result<()> {
+        let name = url.reference().trim_matches('/');
+        {
+            let pipe = try!(self.pipes.get(name).ok_or(Error::new(ENOENT)));
+            let creds = Creds::current();
+            if creds.uid != 0 && creds.uid != pipe.owner.uid {
+                return Err(Error::new(EPERM));
+            }
         }
+        self.pipes.remove(name).map(|_| ()).ok_or(Error::new(ENOENT))
     }
 }
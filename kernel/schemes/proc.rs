@@ -0,0 +1,63 @@
+use alloc::boxed::Box;
+
+use collections::string::ToString;
+use collections::vec::Vec;
+
+use fs::{KScheme, Resource, Url, VecResource};
+
+use system::error::{Error, Result, EACCES, ENOENT, ESRCH};
+
+/// The process scheme, with a directory of information for each context
+///
+/// `proc:<pid>/environ` is the environment of a context, with each variable terminated by a NUL.
+/// `proc:<pid>/fd` lists the open files of a context with the time they were opened and the URL
+/// they were opened with. Both can only be read by root or by a context of the same user.
+pub struct ProcScheme;
+
+impl KScheme for ProcScheme {
+    fn scheme(&self) -> &str {
+        "proc"
+    }
+
+    fn open(&mut self, url: Url, _: usize) -> Result<Box<Resource>> {
+        let reference = url.reference().trim_matches('/');
+        let mut parts = reference.splitn(2, '/');
+        let pid = try!(parts.next().unwrap_or("").parse::<usize>().or(Err(Error::new(ENOENT))));
+        let file = parts.next().unwrap_or("");
+
+        let contexts = ::env().contexts.read();
+        let euid = try!(contexts.current()).euid;
+        for context in contexts.iter() {
+            if context.pid == pid {
+                if euid != 0 && euid != context.uid {
+                    return Err(Error::new(EACCES));
+                }
+
+                return match file {
+                    "environ" => {
+                        let mut data = Vec::new();
+                        for var in context.env.iter() {
+                            data.extend_from_slice(var.as_bytes());
+                            data.push(0);
+                        }
+                        Ok(box VecResource::new(format!("proc:{}/environ", pid), data))
+                    },
+                    "fd" => {
+                        let mut string = format!("{:<6}{:<16}{}\n", "FD", "OPENED", "URL");
+                        for file in unsafe { (*context.files.get()).iter() } {
+                            string.push_str(&format!("{:<6}{:<16}{}\n",
+                                                     file.fd,
+                                                     format!("{}.{:03}", file.time.secs, file.time.nanos / 1000000),
+                                                     file.url));
+                        }
+                        Ok(box VecResource::new(format!("proc:{}/fd", pid), string.into_bytes()))
+                    },
+                    "" => Ok(box VecResource::new(format!("proc:{}/", pid), "environ\nfd\n".to_string().into_bytes())),
+                    _ => Err(Error::new(ENOENT)),
+                };
+            }
+        }
+
+        Err(Error::new(ESRCH))
+    }
+}
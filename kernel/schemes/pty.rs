@@ -0,0 +1,297 @@
+use alloc::arc::{Arc, Weak};
+use alloc::boxed::Box;
+
+use arch::intex::Intex;
+
+use collections::{BTreeMap, String, Vec};
+
+use core::cmp;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use env::tty::{self, LineDiscipline};
+
+use fs::{Creds, KScheme, Resource, Url};
+use fs::access::open_access;
+
+use sync::WaitQueue;
+
+use system::error::{Error, Result, ENOENT};
+use system::syscall::{Stat, POLLHUP, POLLIN, POLLOUT};
+
+/// A pseudo-terminal pair
+struct Pty {
+    id: usize,
+    /// The context that opened the master, which alone may open the slave, so that other users
+    /// cannot read or inject its input
+    owner: Creds,
+    /// The line discipline of the slave side
+    tty: Intex<LineDiscipline>,
+    /// Input for the slave, from the line discipline
+    input: WaitQueue<String>,
+    /// Output for the master, written by the slave or echoed by the line discipline
+    output: WaitQueue<u8>,
+    /// The number of open master resources
+    masters: AtomicUsize,
+    /// The number of open slave resources
+    slaves: AtomicUsize,
+    /// The last slave resource was closed
+    hangup: AtomicBool,
+}
+
+impl Pty {
+    fn path(&self, buf: &mut [u8]) -> Result<usize> {
+        let path_string = format!("pty:{}", self.id);
+        let path = path_string.as_bytes();
+
+        for (b, p) in buf.iter_mut().zip(path.iter()) {
+            *b = *p;
+        }
+
+        Ok(cmp::min(buf.len(), path.len()))
+    }
+
+    fn ioctl(&self, request: usize, arg: &mut [u8]) -> Result<usize> {
+        self.tty.lock().ioctl(request, arg, &self.input)
+    }
+
+    fn stat(&self, stat: &mut Stat) {
+        stat.st_mode = 0o600;
+        stat.st_uid = self.owner.uid as u32;
+        stat.st_gid = self.owner.gid as u32;
+    }
+}
+
+/// The master side of a pseudo-terminal, used by a terminal emulator
+///
+/// Writes are keyboard input for the slave, and reads return the output of the slave. The path of
+/// the master is the path of its slave.
+pub struct PtyMaster {
+    pty: Arc<Pty>,
+}
+
+impl PtyMaster {
+    fn new(pty: Arc<Pty>) -> Self {
+        pty.masters.fetch_add(1, Ordering::SeqCst);
+        PtyMaster {
+            pty: pty,
+        }
+    }
+}
+
+impl Resource for PtyMaster {
+    fn dup(&self) -> Result<Box<Resource>> {
+        Ok(box PtyMaster::new(self.pty.clone()))
+    }
+
+    fn path(&self, buf: &mut [u8]) -> Result<usize> {
+        self.pty.path(buf)
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let mut i = 0;
+
+        loop {
+            {
+                let mut output = self.pty.output.inner.lock();
+                while i < buf.len() {
+                    match output.pop_front() {
+                        Some(b) => {
+                            buf[i] = b;
+                            i += 1;
+                        },
+                        None => break,
+                    }
+                }
+            }
+
+            // End of file once the slave has been opened and closed again
+            if i > 0 || buf.is_empty() || self.pty.hangup.load(Ordering::SeqCst) {
+                return Ok(i);
+            }
+
+            unsafe { self.pty.output.condition.wait(); }
+        }
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        for &b in buf.iter() {
+            let echo = self.pty.tty.lock().input(b as char, &self.pty.input);
+            for &e in echo.iter() {
+                self.pty.output.send(e);
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn poll(&self) -> Result<usize> {
+        let mut events = POLLOUT;
+        if ! self.pty.output.inner.lock().is_empty() {
+            events |= POLLIN;
+        }
+        if self.pty.hangup.load(Ordering::SeqCst) {
+            events |= POLLHUP;
+        }
+        Ok(events)
+    }
+
+    fn ioctl(&mut self, request: usize, arg: &mut [u8]) -> Result<usize> {
+        self.pty.ioctl(request, arg)
+    }
+}
+
+impl Drop for PtyMaster {
+    fn drop(&mut self) {
+        if self.pty.masters.fetch_sub(1, Ordering::SeqCst) == 1 {
+            // Hang up, the slave reads end of file
+            self.pty.input.send(String::new());
+        }
+    }
+}
+
+/// The slave side of a pseudo-terminal, which behaves like the console for the programs using it
+pub struct PtySlave {
+    pty: Arc<Pty>,
+    command: String,
+}
+
+impl PtySlave {
+    fn new(pty: Arc<Pty>) -> Self {
+        pty.slaves.fetch_add(1, Ordering::SeqCst);
+        pty.hangup.store(false, Ordering::SeqCst);
+        PtySlave {
+            pty: pty,
+            command: String::new(),
+        }
+    }
+}
+
+impl Resource for PtySlave {
+    fn dup(&self) -> Result<Box<Resource>> {
+        Ok(box PtySlave::new(self.pty.clone()))
+    }
+
+    fn path(&self, buf: &mut [u8]) -> Result<usize> {
+        self.pty.path(buf)
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if self.command.is_empty() {
+            if self.pty.masters.load(Ordering::SeqCst) == 0 && self.pty.input.inner.lock().is_empty() {
+                return Ok(0);
+            }
+            self.command = try!(tty::receive(&self.pty.input));
+        }
+
+        let mut i = 0;
+        while i < buf.len() && ! self.command.is_empty() {
+            buf[i] = unsafe { self.command.as_mut_vec().remove(0) };
+            i += 1;
+        }
+
+        Ok(i)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        for &b in buf.iter() {
+            self.pty.output.send(b);
+        }
+        Ok(buf.len())
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn poll(&self) -> Result<usize> {
+        let mut events = POLLOUT;
+        if ! self.command.is_empty() || ! self.pty.input.inner.lock().is_empty() {
+            events |= POLLIN;
+        }
+        if self.pty.masters.load(Ordering::SeqCst) == 0 {
+            events |= POLLHUP;
+        }
+        Ok(events)
+    }
+
+    fn ioctl(&mut self, request: usize, arg: &mut [u8]) -> Result<usize> {
+        self.pty.ioctl(request, arg)
+    }
+}
+
+impl Drop for PtySlave {
+    fn drop(&mut self) {
+        if self.pty.slaves.fetch_sub(1, Ordering::SeqCst) == 1 {
+            // Wake the master, so that it reads end of file
+            self.pty.hangup.store(true, Ordering::SeqCst);
+            unsafe { self.pty.output.condition.notify(); }
+        }
+    }
+}
+
+/// The pseudo-terminal scheme
+///
+/// Opening `pty:` creates a new pair and returns the master, and opening `pty:<n>` returns a slave
+/// of pair `n`, as named by the path of the master. Only the owner of the master, or root, may
+/// open the slave.
+pub struct PtyScheme {
+    next_id: usize,
+    ptys: BTreeMap<usize, Weak<Pty>>,
+}
+
+impl PtyScheme {
+    pub fn new() -> Box<Self> {
+        box PtyScheme {
+            next_id: 0,
+            ptys: BTreeMap::new(),
+        }
+    }
+}
+
+impl KScheme for PtyScheme {
+    fn scheme(&self) -> &str {
+        "pty"
+    }
+
+    fn open(&mut self, url: Url, flags: usize) -> Result<Box<Resource>> {
+        let reference = url.reference().trim_matches('/');
+        if reference.is_empty() {
+            let id = self.next_id;
+            self.next_id += 1;
+
+            let pty = Arc::new(Pty {
+                id: id,
+                owner: Creds::current(),
+                tty: Intex::new(LineDiscipline::new()),
+                input: WaitQueue::new(),
+                output: WaitQueue::new(),
+                masters: AtomicUsize::new(0),
+                slaves: AtomicUsize::new(0),
+                hangup: AtomicBool::new(false),
+            });
+
+            // Forget the pairs that have been closed
+            let closed: Vec<usize> = self.ptys.iter()
+                                              .filter(|&(_, pty)| pty.upgrade().is_none())
+                                              .map(|(&id, _)| id)
+                                              .collect();
+            for id in closed.iter() {
+                self.ptys.remove(id);
+            }
+
+            self.ptys.insert(id, Arc::downgrade(&pty));
+            Ok(box PtyMaster::new(pty))
+        } else {
+            let id = try!(reference.parse::<usize>().or(Err(Error::new(ENOENT))));
+            let pty = try!(self.ptys.get(&id).and_then(|pty| pty.upgrade()).ok_or(Error::new(ENOENT)));
+
+            let mut stat = Stat::default();
+            pty.stat(&mut stat);
+            try!(Creds::current().check(&stat, open_access(flags)));
+            Ok(box PtySlave::new(pty))
+        }
+    }
+}
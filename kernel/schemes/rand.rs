@@ -0,0 +1,67 @@
+use alloc::boxed::Box;
+
+use core::cmp;
+
+use common::random;
+
+use fs::{Creds, KScheme, Resource, Url};
+
+use system::error::{Error, Result, EACCES};
+
+/// A random number resource
+pub struct RandResource;
+
+impl Resource for RandResource {
+    fn dup(&self) -> Result<Box<Resource>> {
+        Ok(box RandResource)
+    }
+
+    fn path(&self, buf: &mut [u8]) -> Result<usize> {
+        let path = b"rand:";
+
+        for (b, p) in buf.iter_mut().zip(path.iter()) {
+            *b = *p;
+        }
+
+        Ok(cmp::min(buf.len(), path.len()))
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        random::fill(buf);
+        Ok(buf.len())
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        if Creds::current().uid != 0 {
+            return Err(Error::new(EACCES));
+        }
+
+        for chunk in buf.chunks(8) {
+            let mut value = random::rdtsc();
+            for &b in chunk.iter() {
+                value = value << 8 | b as u64;
+            }
+            random::add_entropy(value);
+        }
+        random::reseed();
+        Ok(buf.len())
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// The rand scheme, reading returns bytes from the ChaCha20 generator and writing, which only root
+/// may do, mixes in entropy
+pub struct RandScheme;
+
+impl KScheme for RandScheme {
+    fn scheme(&self) -> &str {
+        "rand"
+    }
+
+    fn open(&mut self, _: Url, _: usize) -> Result<Box<Resource>> {
+        Ok(box RandResource)
+    }
+}
@@ -0,0 +1,270 @@
+use alloc::arc::{Arc, Weak};
+use alloc::boxed::Box;
+
+use arch::context::SharedMemory;
+
+use collections::{BTreeMap, String, Vec};
+use collections::string::ToString;
+
+use core::{cmp, slice};
+
+use fs::{Creds, DirResource, KScheme, Resource, ResourceSeek, Url};
+use fs::access::open_access;
+
+use sync::Intex;
+
+use system::error::{Error, Result, EBUSY, EEXIST, EINVAL, ENOENT, ENOMEM, EPERM};
+use system::syscall::{Dirent, Stat, MODE_FILE, O_CREAT, O_EXCL, O_TRUNC};
+
+/// The permissions of objects, which only their owner may open
+const OBJECT_MODE: u16 = 0o600;
+
+/// A shared memory object, which the resources open on it hold
+///
+/// Its pages are also held by their mappings, so they stay until the last resource is closed and
+/// the last mapping is removed.
+struct ShmObject {
+    name: String,
+    owner: Creds,
+    /// The pages, `None` while the size is zero
+    pages: Intex<Option<Arc<SharedMemory>>>,
+}
+
+impl ShmObject {
+    fn size(&self) -> usize {
+        self.pages.lock().as_ref().map_or(0, |pages| pages.size())
+    }
+
+    fn stat(&self, stat: &mut Stat) {
+        stat.st_mode = MODE_FILE | OBJECT_MODE;
+        stat.st_size = self.size() as u64;
+        stat.st_uid = self.owner.uid as u32;
+        stat.st_gid = self.owner.gid as u32;
+        stat.st_nlink = 1;
+    }
+
+    /// Set the size to `len`, with new pages that keep the bytes below it. Fails with `EBUSY`
+    /// while the pages are mapped, as the mappings would keep the old pages
+    fn resize(&self, len: usize) -> Result<()> {
+        let mut pages = self.pages.lock();
+        let resized = {
+            let old = match *pages {
+                Some(ref old) => {
+                    if Arc::strong_count(old) > 1 {
+                        return Err(Error::new(EBUSY));
+                    }
+                    unsafe { slice::from_raw_parts(old.physical_address() as *const u8, old.size()) }
+                },
+                None => &[][..],
+            };
+
+            if len == 0 {
+                None
+            } else {
+                let new = try!(SharedMemory::new(len).ok_or(Error::new(ENOMEM)));
+                let count = cmp::min(len, old.len());
+                unsafe { ::memcpy(new.physical_address() as *mut u8, old.as_ptr(), count) };
+                Some(Arc::new(new))
+            }
+        };
+        *pages = resized;
+        Ok(())
+    }
+}
+
+/// A shared memory object that is open
+pub struct ShmResource {
+    object: Arc<ShmObject>,
+    seek: usize,
+}
+
+impl Resource for ShmResource {
+    fn dup(&self) -> Result<Box<Resource>> {
+        Ok(box ShmResource {
+            object: self.object.clone(),
+            seek: self.seek,
+        })
+    }
+
+    fn path(&self, buf: &mut [u8]) -> Result<usize> {
+        let path_a = b"shm:";
+        let path_b = self.object.name.as_bytes();
+        for (b, p) in buf.iter_mut().zip(path_a.iter().chain(path_b.iter())) {
+            *b = *p;
+        }
+
+        Ok(cmp::min(buf.len(), path_a.len() + path_b.len()))
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let pages = self.object.pages.lock();
+        let size = pages.as_ref().map_or(0, |pages| pages.size());
+        if self.seek >= size {
+            return Ok(0);
+        }
+
+        let count = cmp::min(buf.len(), size - self.seek);
+        if let Some(ref pages) = *pages {
+            let data = unsafe { slice::from_raw_parts(pages.physical_address() as *const u8, size) };
+            buf[..count].copy_from_slice(&data[self.seek..self.seek + count]);
+        }
+        self.seek += count;
+        Ok(count)
+    }
+
+    /// Write at the seek position, which does not grow the object, `truncate` sets its size
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let pages = self.object.pages.lock();
+        let size = pages.as_ref().map_or(0, |pages| pages.size());
+        if self.seek >= size {
+            return Ok(0);
+        }
+
+        let count = cmp::min(buf.len(), size - self.seek);
+        if let Some(ref pages) = *pages {
+            let data = unsafe { slice::from_raw_parts_mut(pages.physical_address() as *mut u8, size) };
+            data[self.seek..self.seek + count].copy_from_slice(&buf[..count]);
+        }
+        self.seek += count;
+        Ok(count)
+    }
+
+    fn seek(&mut self, pos: ResourceSeek) -> Result<usize> {
+        let size = self.object.size();
+        let seek = match pos {
+            ResourceSeek::Start(offset) => offset as isize,
+            ResourceSeek::Current(offset) => self.seek as isize + offset,
+            ResourceSeek::End(offset) => size as isize + offset,
+        };
+        if seek < 0 {
+            return Err(Error::new(EINVAL));
+        }
+
+        self.seek = seek as usize;
+        Ok(self.seek)
+    }
+
+    fn stat(&self, stat: &mut Stat) -> Result<usize> {
+        self.object.stat(stat);
+        Ok(0)
+    }
+
+    /// The pages are the object, there is nothing to write back
+    fn sync(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn truncate(&mut self, len: usize) -> Result<()> {
+        self.object.resize(len)
+    }
+
+    fn map(&mut self) -> Result<(usize, usize)> {
+        match *self.object.pages.lock() {
+            Some(ref pages) => Ok((pages.physical_address(), pages.size())),
+            None => Err(Error::new(EINVAL)),
+        }
+    }
+
+    fn map_object(&mut self) -> Option<Arc<SharedMemory>> {
+        self.object.pages.lock().clone()
+    }
+}
+
+/// Shared memory objects
+///
+/// Opening `shm:<name>` with `O_CREAT` creates an empty object, which other processes can open by
+/// name while it is open. `truncate` sets its size, and `mmap` with `MAP_SHARED` or `fmap` map
+/// its pages, so that the processes share them without copying. The object is removed when the
+/// last resource on it is closed, or when it is unlinked. Opening `shm:` lists the objects.
+pub struct ShmScheme {
+    objects: BTreeMap<String, Weak<ShmObject>>,
+}
+
+impl ShmScheme {
+    pub fn new() -> Box<Self> {
+        box ShmScheme {
+            objects: BTreeMap::new(),
+        }
+    }
+
+    /// The object named `name`, forgetting it if its last resource was closed
+    fn get(&mut self, name: &str) -> Option<Arc<ShmObject>> {
+        let object = self.objects.get(name).and_then(|object| object.upgrade());
+        if object.is_none() {
+            self.objects.remove(name);
+        }
+        object
+    }
+}
+
+impl KScheme for ShmScheme {
+    fn scheme(&self) -> &str {
+        "shm"
+    }
+
+    fn open(&mut self, url: Url, flags: usize) -> Result<Box<Resource>> {
+        let name = url.reference().trim_matches('/');
+        if name.is_empty() {
+            let entries: Vec<Dirent> = self.objects.iter().filter_map(|(name, object)| {
+                object.upgrade().map(|object| Dirent::new(name, MODE_FILE | OBJECT_MODE, object.size() as u64))
+            }).collect();
+            return Ok(box DirResource::new("shm:".to_string(), entries));
+        }
+
+        let object = match self.get(name) {
+            Some(object) => {
+                if flags & (O_CREAT | O_EXCL) == O_CREAT | O_EXCL {
+                    return Err(Error::new(EEXIST));
+                }
+
+                let mut stat = Stat::default();
+                object.stat(&mut stat);
+                try!(Creds::current().check(&stat, open_access(flags)));
+                if flags & O_TRUNC == O_TRUNC {
+                    try!(object.resize(0));
+                }
+                object
+            },
+            None => if flags & O_CREAT == O_CREAT {
+                let object = Arc::new(ShmObject {
+                    name: name.to_string(),
+                    owner: Creds::current(),
+                    pages: Intex::new(None),
+                });
+                self.objects.insert(name.to_string(), Arc::downgrade(&object));
+                object
+            } else {
+                return Err(Error::new(ENOENT));
+            },
+        };
+
+        Ok(box ShmResource {
+            object: object,
+            seek: 0,
+        })
+    }
+
+    fn stat(&mut self, url: Url, stat: &mut Stat) -> Result<()> {
+        let name = url.reference().trim_matches('/');
+        match self.get(name) {
+            Some(object) => {
+                object.stat(stat);
+                Ok(())
+            },
+            None => Err(Error::new(ENOENT)),
+        }
+    }
+
+    /// Remove the name, resources that are open and mappings keep working. Only the owner of an
+    /// object or root may remove it
+    fn unlink(&mut self, url: Url) -> Result<()> {
+        let name = url.reference().trim_matches('/');
+        let object = try!(self.get(name).ok_or(Error::new(ENOENT)));
+        let creds = Creds::current();
+        if creds.uid != 0 && creds.uid != object.owner.uid {
+            return Err(Error::new(EPERM));
+        }
+        self.objects.remove(name);
+        Ok(())
+    }
+}
@@ -0,0 +1,155 @@
+use alloc::arc::Arc;
+use alloc::boxed::Box;
+
+use collections::string::String;
+use collections::vec::Vec;
+
+use core::cmp;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use fs::{KScheme, Resource, Url};
+
+use sync::WaitQueue;
+
+use system::error::{Error, Result, EACCES, EBUSY, ESRCH};
+use system::syscall::{POLLHUP, POLLIN};
+
+/// The number of decoded syscalls kept while the tracer does not read them, later ones are dropped
+const STRACE_CAPACITY: usize = 1024;
+
+/// The decoded syscalls of a traced context, waiting to be read by its tracer
+pub struct StraceQueue {
+    queue: WaitQueue<String>,
+    /// The lines dropped since the last one read, as the queue was full
+    dropped: AtomicUsize,
+}
+
+impl StraceQueue {
+    pub fn new() -> StraceQueue {
+        StraceQueue {
+            queue: WaitQueue::with_capacity(STRACE_CAPACITY),
+            dropped: AtomicUsize::new(0),
+        }
+    }
+
+    /// Queue a decoded syscall, dropping it if the tracer has fallen behind
+    pub fn send(&self, line: String) {
+        if ! self.queue.try_send(line) {
+            self.dropped.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    /// Tell the tracer that the context exited, which is never dropped
+    pub fn exit(&self) {
+        self.queue.send(String::new());
+    }
+}
+
+/// A syscall trace of a single context, reading returns one decoded syscall per line
+///
+/// A line saying how many were dropped is read before the first line after the queue was full.
+pub struct StraceResource {
+    pid: usize,
+    queue: Arc<StraceQueue>,
+    data: Vec<u8>,
+    exited: bool,
+}
+
+impl Resource for StraceResource {
+    fn path(&self, buf: &mut [u8]) -> Result<usize> {
+        let path_string = format!("strace:{}", self.pid);
+        let path = path_string.as_bytes();
+
+        for (b, p) in buf.iter_mut().zip(path.iter()) {
+            *b = *p;
+        }
+
+        Ok(cmp::min(buf.len(), path.len()))
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if self.data.is_empty() && ! self.exited {
+            let line = self.queue.queue.receive();
+
+            let dropped = self.queue.dropped.swap(0, Ordering::SeqCst);
+            if dropped > 0 {
+                self.data.extend_from_slice(format!("{} lines dropped\n", dropped).as_bytes());
+            }
+
+            if line.is_empty() {
+                self.exited = true;
+            } else {
+                self.data.extend_from_slice(line.as_bytes());
+            }
+        }
+
+        let count = cmp::min(buf.len(), self.data.len());
+        for (b, d) in buf.iter_mut().zip(self.data.drain(.. count)) {
+            *b = d;
+        }
+        Ok(count)
+    }
+
+    fn poll(&self) -> Result<usize> {
+        if ! self.data.is_empty() || ! self.queue.queue.inner.lock().is_empty() {
+            Ok(POLLIN)
+        } else if self.exited {
+            Ok(POLLHUP)
+        } else {
+            Ok(0)
+        }
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for StraceResource {
+    fn drop(&mut self) {
+        let mut contexts = ::env().contexts.write();
+        for context in contexts.iter_mut() {
+            if context.pid == self.pid {
+                context.tracer = None;
+            }
+        }
+    }
+}
+
+/// The strace scheme, root can attach to a context by opening `strace:<pid>`
+pub struct StraceScheme;
+
+impl KScheme for StraceScheme {
+    fn scheme(&self) -> &str {
+        "strace"
+    }
+
+    fn open(&mut self, url: Url, _: usize) -> Result<Box<Resource>> {
+        let pid = url.reference().parse::<usize>().unwrap_or(0);
+
+        let mut contexts = ::env().contexts.write();
+        if try!(contexts.current()).euid != 0 {
+            return Err(Error::new(EACCES));
+        }
+
+        for context in contexts.iter_mut() {
+            if context.pid == pid {
+                if context.tracer.is_some() {
+                    return Err(Error::new(EBUSY));
+                }
+
+                let queue = Arc::new(StraceQueue::new());
+                context.tracer = Some(queue.clone());
+
+                return Ok(box StraceResource {
+                    pid: pid,
+                    queue: queue,
+                    data: Vec::new(),
+                    exited: false,
+                });
+            }
+        }
+
+        Err(Error::new(ESRCH))
+    }
+}
@@ -0,0 +1,155 @@
+use alloc::boxed::Box;
+
+use collections::string::String;
+
+use core::sync::atomic::Ordering;
+
+use arch::context::{CONTEXT_MAX_FILES, OPEN_FILES, SYSTEM_MAX_FILES};
+#[cfg(debug)]
+use arch::lockdep;
+use arch::percpu;
+use arch::pstore;
+
+use env::UID_MAX_SCHEMES;
+use env::stats::Histogram;
+
+use fs::{KScheme, Resource, Url, VecResource};
+
+use syscall::strace;
+
+use system::error::{Error, Result, EACCES, ENOENT};
+use system::syscall::O_TRUNC;
+
+/// The system information scheme
+pub struct SysScheme;
+
+impl SysScheme {
+    /// Resource accounting: open files, and schemes per user
+    fn resources() -> String {
+        let mut string = format!("Open Files: {} / {}\nFiles Per Context: {}\nSchemes Per UID: {}\n",
+                                 OPEN_FILES.load(Ordering::SeqCst),
+                                 SYSTEM_MAX_FILES,
+                                 CONTEXT_MAX_FILES,
+                                 UID_MAX_SCHEMES);
+
+        string.push_str(&format!("\n{:<6}{}\n", "UID", "SCHEMES"));
+        for (uid, count) in ::env().scheme_counts.lock().iter() {
+            string.push_str(&format!("{:<6}{}\n", uid, count));
+        }
+
+        string.push_str(&format!("\n{:<6}{:<6}{}\n", "PID", "FDS", "NAME"));
+        for context in ::env().contexts.read().iter() {
+            string.push_str(&format!("{:<6}{:<6}{}\n",
+                                     context.pid,
+                                     unsafe { (*context.files.get()).len() },
+                                     context.name));
+        }
+
+        string
+    }
+
+    /// Context switches by processor, interrupts by vector and syscalls by number
+    fn stats() -> String {
+        let mut string = format!("{:<6}{:<16}{:<16}{}\n", "CPU", "SWITCHES", "VOLUNTARY", "PREEMPTED");
+        for percpu in percpu::all().iter() {
+            let stats = &percpu.stats;
+            string.push_str(&format!("{:<6}{:<16}{:<16}{}\n",
+                                     percpu.cpu_id,
+                                     stats.voluntary + stats.preempted,
+                                     stats.voluntary,
+                                     stats.preempted));
+        }
+
+        string.push_str(&format!("\n{:<6}{}\n", "INT", "COUNT"));
+        for (interrupt, count) in percpu::interrupts().iter().enumerate() {
+            if *count > 0 {
+                string.push_str(&format!("{:<6X}{}\n", interrupt, count));
+            }
+        }
+
+        string.push_str(&format!("\n{:<6}{:<16}{}\n", "CALL", "COUNT", "NAME"));
+        for syscall in ::env().stats.lock().syscalls.iter().filter(|syscall| syscall.count > 0) {
+            string.push_str(&format!("{:<6}{:<16}{}\n", syscall.number, syscall.count, strace::name(syscall.number).unwrap_or("?")));
+        }
+
+        {
+            let grants = ::env().grants.lock();
+            string.push_str(&format!("\n{:<10}{:<16}{:<16}{}\n", "GRANTS", "HITS", "MISSES", "PAGES"));
+            string.push_str(&format!("{:<10}{:<16}{:<16}{}\n",
+                                     grants.grants.len(),
+                                     grants.hits,
+                                     grants.misses,
+                                     grants.grants.iter().fold(0, |pages, grant| pages + grant.size / 4096)));
+        }
+
+        string
+    }
+
+    /// Syscall latency histograms in cycles, opening with `O_TRUNC` resets them
+    fn latency(flags: usize) -> Result<String> {
+        let reset = flags & O_TRUNC == O_TRUNC;
+        if reset {
+            let contexts = ::env().contexts.read();
+            let current = try!(contexts.current());
+            if current.euid != 0 {
+                return Err(Error::new(EACCES));
+            }
+        }
+
+        let mut string = String::new();
+        let mut stats = ::env().stats.lock();
+        for syscall in stats.syscalls.iter_mut().filter(|syscall| syscall.latency.count > 0) {
+            string.push_str(&format!("{} {}: count {} avg {}\n",
+                                     syscall.number,
+                                     strace::name(syscall.number).unwrap_or("?"),
+                                     syscall.latency.count,
+                                     syscall.latency.total / syscall.latency.count));
+            string.push_str(&syscall.latency.to_string());
+            if reset {
+                syscall.latency = Histogram::new();
+            }
+        }
+
+        Ok(string)
+    }
+
+    /// The kernel log saved when the previous boot panicked
+    fn lastcrash() -> Result<String> {
+        match pstore::last() {
+            Some(log) => Ok(String::from_utf8_lossy(log).into_owned()),
+            None => Err(Error::new(ENOENT)),
+        }
+    }
+}
+
+impl KScheme for SysScheme {
+    fn scheme(&self) -> &str {
+        "sys"
+    }
+
+    fn open(&mut self, url: Url, flags: usize) -> Result<Box<Resource>> {
+        let string = match url.reference().trim_matches('/') {
+            "" => {
+                let mut string = String::new();
+                if pstore::last().is_some() {
+                    string.push_str("lastcrash\n");
+                }
+                string.push_str("latency\n");
+                if cfg!(debug) {
+                    string.push_str("locks\n");
+                }
+                string.push_str("resources\nstats\n");
+                string
+            },
+            "lastcrash" => try!(SysScheme::lastcrash()),
+            "latency" => try!(SysScheme::latency(flags)),
+            #[cfg(debug)]
+            "locks" => lockdep::stats(),
+            "resources" => SysScheme::resources(),
+            "stats" => SysScheme::stats(),
+            _ => return Err(Error::new(ENOENT)),
+        };
+
+        Ok(box VecResource::new(url.to_string(), string.into_bytes()))
+    }
+}
@@ -0,0 +1,33 @@
+use fs::Creds;
+
+use system::syscall::{Stat, MODE_EXEC, MODE_FILE, MODE_READ, MODE_WRITE};
+
+/// A file of user 1000 in group 100, readable by the group and executable by nobody
+fn stat() -> Stat {
+    Stat {
+        st_mode: MODE_FILE | 0o640,
+        st_uid: 1000,
+        st_gid: 100,
+        ..Stat::default()
+    }
+}
+
+pub fn permits() -> bool {
+    let owner = Creds { uid: 1000, gid: 1000 };
+    let member = Creds { uid: 1001, gid: 100 };
+    let other = Creds { uid: 1002, gid: 1002 };
+    let root = Creds::root();
+
+    test!(owner.permits(&stat(), MODE_READ | MODE_WRITE));
+    test!(! owner.permits(&stat(), MODE_EXEC));
+    test!(member.permits(&stat(), MODE_READ));
+    test!(! member.permits(&stat(), MODE_WRITE));
+    test!(! other.permits(&stat(), MODE_READ));
+    test!(root.permits(&stat(), MODE_READ | MODE_WRITE));
+    test!(! root.permits(&stat(), MODE_EXEC));
+    succ!();
+}
+
+pub static TESTS: &'static [super::KernelTest] = &[
+    kernel_test!(permits, "Permission bits of the owner, group and others"),
+];
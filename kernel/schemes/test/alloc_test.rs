@@ -0,0 +1,59 @@
+use alloc::boxed::Box;
+
+use arch::memory;
+
+use core::ptr;
+
+pub fn alloc_unalloc() -> bool {
+    unsafe {
+        let address = memory::alloc(4096);
+        test!(address > 0);
+        test!(memory::alloc_size(address) >= 4096);
+        ptr::write_bytes(address as *mut u8, 0x55, 4096);
+        test!(*((address + 4095) as *const u8) == 0x55);
+        memory::unalloc(address);
+    }
+    succ!();
+}
+
+pub fn alloc_aligned() -> bool {
+    unsafe {
+        for &align in [4096, 16384, 65536].iter() {
+            let address = memory::alloc_aligned(100, align);
+            test!(address > 0);
+            test!(address % align == 0);
+            memory::unalloc(address);
+        }
+    }
+    succ!();
+}
+
+pub fn realloc() -> bool {
+    unsafe {
+        let address = memory::alloc(64);
+        test!(address > 0);
+        ptr::write_bytes(address as *mut u8, 0x33, 64);
+        let address = memory::realloc(address, 64 * 1024);
+        test!(address > 0);
+        test!(memory::alloc_size(address) >= 64 * 1024);
+        test!(*((address + 63) as *const u8) == 0x33);
+        memory::unalloc(address);
+    }
+    succ!();
+}
+
+pub fn boxed() -> bool {
+    let value = box [7u64; 512];
+    test!(value.iter().all(|&i| i == 7));
+    let raw = Box::into_raw(value);
+    test!(raw as usize % 8 == 0);
+    drop(unsafe { Box::from_raw(raw) });
+    succ!();
+}
+
+pub static TESTS: &'static [super::KernelTest] = &[
+    kernel_test!(alloc_unalloc, "Allocate and free a page"),
+    kernel_test!(alloc_aligned, "Aligned allocations"),
+    kernel_test!(realloc, "Reallocation keeps contents"),
+    kernel_test!(boxed, "Box allocation"),
+];
@@ -0,0 +1,76 @@
+use fs::KScheme;
+
+use schemes::chan::{ChanScheme, CHAN_MESSAGES, CHAN_MESSAGE_SIZE};
+
+use system::error::{EBADF, EMSGSIZE, ENOENT, EPIPE};
+use system::syscall::{O_CREAT, O_RDWR, O_WRONLY, POLLERR, POLLHUP, POLLIN, POLLOUT};
+
+use super::url;
+
+/// Messages are received whole, in the order they were sent
+pub fn boundaries() -> bool {
+    let mut scheme = ChanScheme::new();
+    let mut receiver = test_try!(scheme.open(url("chan:queue"), O_CREAT));
+    let mut sender = test_try!(scheme.open(url("chan:queue"), O_WRONLY));
+
+    test!(receiver.poll().ok() == Some(0));
+    test!(sender.write(b"first").ok() == Some(5));
+    test!(sender.write(b"second message").ok() == Some(14));
+    test!(receiver.poll().ok() == Some(POLLIN));
+
+    let mut buf = [0; 16];
+    test!(receiver.read(&mut buf).ok() == Some(5));
+    test!(&buf[..5] == b"first");
+    // The rest of a message that does not fit is dropped
+    test!(receiver.read(&mut buf[..6]).ok() == Some(6));
+    test!(&buf[..6] == b"second");
+
+    test!(receiver.write(b"reply").err().map(|err| err.errno) == Some(EBADF));
+    test!(sender.read(&mut buf).err().map(|err| err.errno) == Some(EBADF));
+    test!(sender.write(&[0; CHAN_MESSAGE_SIZE + 1]).err().map(|err| err.errno) == Some(EMSGSIZE));
+
+    // The receiver reads end of file once the last sender closes
+    drop(sender);
+    test!(receiver.poll().ok() == Some(POLLHUP));
+    test!(receiver.read(&mut buf).ok() == Some(0));
+    succ!();
+}
+
+/// A full channel is not ready for sending
+pub fn full() -> bool {
+    let mut scheme = ChanScheme::new();
+    let mut chan = test_try!(scheme.open(url("chan:queue"), O_CREAT | O_RDWR));
+
+    for _ in 0..CHAN_MESSAGES {
+        test!(chan.write(b"message").ok() == Some(7));
+    }
+    test!(chan.poll().ok() == Some(POLLIN));
+
+    let mut buf = [0; 8];
+    test!(chan.read(&mut buf).ok() == Some(7));
+    test!(chan.poll().ok() == Some(POLLIN | POLLOUT));
+
+    test!(scheme.unlink(url("chan:queue")).is_ok());
+    test!(scheme.open(url("chan:queue"), O_RDWR).err().map(|err| err.errno) == Some(ENOENT));
+    test!(chan.read(&mut buf).ok() == Some(7));
+    succ!();
+}
+
+/// Sending fails once the last receiver closes
+pub fn no_receivers() -> bool {
+    let mut scheme = ChanScheme::new();
+    let receiver = test_try!(scheme.open(url("chan:queue"), O_CREAT));
+    let mut sender = test_try!(scheme.open(url("chan:queue"), O_WRONLY));
+
+    test!(sender.poll().ok() == Some(POLLOUT));
+    drop(receiver);
+    test!(sender.poll().ok() == Some(POLLERR));
+    test!(sender.write(b"message").err().map(|err| err.errno) == Some(EPIPE));
+    succ!();
+}
+
+pub static TESTS: &'static [super::KernelTest] = &[
+    kernel_test!(boundaries, "chan: messages keep their boundaries"),
+    kernel_test!(full, "chan: a full channel is not ready for sending"),
+    kernel_test!(no_receivers, "chan: sending fails with no receivers"),
+];
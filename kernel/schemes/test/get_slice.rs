@@ -15,3 +15,7 @@ pub fn test() -> bool {
     //test!(array.get_slice(..) == &array);
     succ!();
 }
+
+pub static TESTS: &'static [super::KernelTest] = &[
+    kernel_test!(test, "GetSlice"),
+];
@@ -9,3 +9,8 @@ pub fn meta_test_woah() -> bool {
     test!(true);
     succ!();
 }
+
+pub static TESTS: &'static [super::KernelTest] = &[
+    kernel_test!(meta_test_woah, "Testing the testing (wut)"),
+    kernel_test!(!meta_test_woah_fail, "Testing the fail testing (wut)"),
+];
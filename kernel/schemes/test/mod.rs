@@ -1,7 +1,16 @@
+//! Kernel tests
+//!
+//! Each module lists its tests in a `TESTS` slice with `kernel_test!`, and is added to `SUITES`.
+//! Opening `test:` runs them all and returns the report. Booting with `test` on the command line,
+//! or a kernel built with `TEST=1`, runs them instead of init, writes the report to the debug
+//! console and exits QEMU through the `isa-debug-exit` device, as `make qemu_test` does.
+
 use alloc::boxed::Box;
 
 use collections::string::{String, ToString};
 
+use drivers::io::{Io, Pio};
+
 use fs::{KScheme, Resource, Url, VecResource};
 
 use system::error::Result;
@@ -29,9 +38,127 @@ macro_rules! fail {
     )
 }
 
-// Add your test here!
+/// Unwrap a `Result`, failing the test if it is an error
+#[macro_export]
+macro_rules! test_try {
+    ($result:expr) => (
+        match $result {
+            Ok(value) => value,
+            Err(_) => fail!(),
+        }
+    )
+}
+
+/// Register a test in a `TESTS` slice, with `!` for a test that is expected to fail
+#[macro_export]
+macro_rules! kernel_test {
+    (! $test:path, $description:expr) => (
+        $crate::schemes::test::KernelTest {
+            name: stringify!($test),
+            description: $description,
+            func: $test,
+            expect: false,
+        }
+    );
+    ($test:path, $description:expr) => (
+        $crate::schemes::test::KernelTest {
+            name: stringify!($test),
+            description: $description,
+            func: $test,
+            expect: true,
+        }
+    );
+}
+
+// Add your test module here, and its `TESTS` to `SUITES`!
+pub mod access;
+pub mod alloc_test;
+pub mod chan;
 pub mod get_slice;
 pub mod meta;
+pub mod packet;
+pub mod shm;
+pub mod tmp;
+pub mod vec;
+
+/// Parse a URL of a test, which is known to be valid
+pub fn url(string: &str) -> Url {
+    Url::from_str(string).unwrap()
+}
+
+/// A test, registered with `kernel_test!`
+pub struct KernelTest {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub func: fn() -> bool,
+    /// What `func` returns when the test passes
+    pub expect: bool,
+}
+
+/// Every test module, by name
+static SUITES: [(&'static str, &'static [KernelTest]); 9] = [
+    ("meta", meta::TESTS),
+    ("get_slice", get_slice::TESTS),
+    ("vec", vec::TESTS),
+    ("alloc", alloc_test::TESTS),
+    ("packet", packet::TESTS),
+    ("access", access::TESTS),
+    ("tmp", tmp::TESTS),
+    ("shm", shm::TESTS),
+    ("chan", chan::TESTS),
+];
+
+/// The I/O port of the QEMU `isa-debug-exit` device, which exits QEMU with `(value << 1) | 1`
+const QEMU_EXIT_PORT: u16 = 0xF4;
+
+/// Run every test, returning the report and the number of tests that failed
+pub fn run() -> (String, usize) {
+    let mut string = String::new();
+    let mut failed = 0;
+
+    for &(suite, tests) in SUITES.iter() {
+        for test in tests.iter() {
+            if (test.func)() == test.expect {
+                string.push_str("\x1B[32mSUCCESS: ");
+            } else {
+                string.push_str("\x1B[31mFAILURE: ");
+                failed += 1;
+            }
+            string.push_str(suite);
+            string.push_str("::");
+            string.push_str(test.name);
+            string.push_str(": ");
+            string.push_str(test.description);
+            string.push_str("\x1B[0m\n");
+        }
+    }
+
+    (string, failed)
+}
+
+/// Run every test at boot, report over the debug console and exit QEMU, with status 1 if they
+/// passed and 3 if any failed. On other machines the processor halts
+pub fn run_boot() -> ! {
+    let (report, failed) = run();
+    debug!("{}", report);
+    if failed == 0 {
+        debugln!("TEST: all passed");
+    } else {
+        debugln!("TEST: {} failed", failed);
+    }
+
+    Pio::<u32>::new(QEMU_EXIT_PORT).write(if failed == 0 { 0 } else { 1 });
+
+    loop {
+        unsafe { ::arch::cpu::halt() };
+    }
+}
+
+/// Exit QEMU with the status of a failed run, called by the panic handler while the tests run at
+/// boot, so that a test that panics fails the run instead of leaving QEMU halted
+pub fn fail_boot() {
+    Pio::<u32>::new(QEMU_EXIT_PORT).write(1);
+}
 
 pub struct TestScheme;
 
@@ -41,56 +168,7 @@ impl KScheme for TestScheme {
     }
 
     fn open(&mut self, _: Url, _: usize) -> Result<Box<Resource>> {
-        let mut string = String::new();
-
-        macro_rules! reg_test {
-            (! $test:path) => (
-                if !$test() {
-                    string.push_str("\x1B[32mSUCCESS: ");
-                } else {
-                    string.push_str("\x1B[31mFAILURE: ");
-                }
-                string.push_str(stringify!($test));
-                string.push_str("\x1B[0m\n");
-            );
-            (! $test:path, $($arg:tt)*) => (
-                if !$test() {
-                    string.push_str("\x1B[32mSUCCESS: ");
-                } else {
-                    string.push_str("\x1B[31mFAILURE: ");
-                }
-                string.push_str(stringify!($test));
-                string.push_str(": ");
-                string.push_str(&format!($($arg)*));
-                string.push_str("\x1B[0m\n");
-            );
-            ($test:path) => (
-                if $test() {
-                    string.push_str("\x1B[32mSUCCESS: ");
-                } else {
-                    string.push_str("\x1B[31mFAILURE: ");
-                }
-                string.push_str(stringify!($test));
-                string.push_str("\x1B[0m\n");
-            );
-            ($test:path, $($arg:tt)*) => (
-                if $test() {
-                    string.push_str("\x1B[32mSUCCESS: ");
-                } else {
-                    string.push_str("\x1B[31mFAILURE: ");
-                }
-                string.push_str(stringify!($test));
-                string.push_str(": ");
-                string.push_str(&format!($($arg)*));
-                string.push_str("\x1B[0m\n");
-            );
-        }
-
-        // Add your test here!
-        reg_test!(meta::meta_test_woah, "Testing the testing (wut)");
-        reg_test!(!meta::meta_test_woah_fail, "Testing the fail testing (wut)");
-        reg_test!(get_slice::test, "GetSlice");
-
+        let (string, _) = run();
         Ok(box VecResource::new("test:".to_string(), string.into_bytes()))
     }
 }
@@ -0,0 +1,110 @@
+use collections::vec::Vec;
+
+use core::{cmp, mem};
+
+use system::error::{Error, Result, EBADF, ENOENT, ENOSYS};
+use system::scheme::{Packet, Scheme};
+use system::syscall::{SYS_CLOSE, SYS_OPEN, SYS_READ, SYS_WRITE};
+
+/// A scheme that reads back what was written to its one file
+struct EchoScheme {
+    data: Vec<u8>,
+}
+
+impl Scheme for EchoScheme {
+    fn open(&mut self, path: &str, _flags: usize, _mode: usize) -> Result<usize> {
+        if path == "echo:" {
+            Ok(1)
+        } else {
+            Err(Error::new(ENOENT))
+        }
+    }
+
+    fn read(&mut self, id: usize, buf: &mut [u8]) -> Result<usize> {
+        if id != 1 {
+            return Err(Error::new(EBADF));
+        }
+        let count = cmp::min(buf.len(), self.data.len());
+        buf[..count].copy_from_slice(&self.data[..count]);
+        Ok(count)
+    }
+
+    fn write(&mut self, id: usize, buf: &[u8]) -> Result<usize> {
+        if id != 1 {
+            return Err(Error::new(EBADF));
+        }
+        self.data.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn close(&mut self, id: usize) -> Result<usize> {
+        if id == 1 {
+            Ok(0)
+        } else {
+            Err(Error::new(EBADF))
+        }
+    }
+}
+
+/// Send a request to `scheme` like the kernel does, returning the answer
+fn call(scheme: &mut EchoScheme, a: usize, b: usize, c: usize, d: usize) -> usize {
+    let mut packet = Packet {
+        id: 7,
+        uid: 1000,
+        gid: 100,
+        a: a,
+        b: b,
+        c: c,
+        d: d,
+    };
+
+    // The packet crosses to the server as bytes
+    let mut bytes = [0; 64];
+    bytes[..mem::size_of::<Packet>()].copy_from_slice(&packet);
+    packet.copy_from_slice(&bytes[..mem::size_of::<Packet>()]);
+
+    scheme.handle(&mut packet);
+
+    if packet.id == 7 && packet.uid == 1000 && packet.gid == 100 {
+        packet.a
+    } else {
+        Error::mux(Err(Error::new(EBADF)))
+    }
+}
+
+pub fn round_trip() -> bool {
+    let mut scheme = EchoScheme {
+        data: Vec::new(),
+    };
+
+    let path = b"echo:\0";
+    let id = call(&mut scheme, SYS_OPEN, path.as_ptr() as usize, 0, 0);
+    test!(id == 1);
+
+    let message = b"round trip";
+    test!(call(&mut scheme, SYS_WRITE, 1, message.as_ptr() as usize, message.len()) == message.len());
+
+    let mut buf = [0; 32];
+    test!(call(&mut scheme, SYS_READ, 1, buf.as_mut_ptr() as usize, buf.len()) == message.len());
+    test!(&buf[..message.len()] == message);
+
+    test!(call(&mut scheme, SYS_CLOSE, 1, 0, 0) == 0);
+    succ!();
+}
+
+pub fn errors() -> bool {
+    let mut scheme = EchoScheme {
+        data: Vec::new(),
+    };
+
+    let path = b"other:\0";
+    test!(call(&mut scheme, SYS_OPEN, path.as_ptr() as usize, 0, 0) == Error::mux(Err(Error::new(ENOENT))));
+    test!(call(&mut scheme, SYS_CLOSE, 2, 0, 0) == Error::mux(Err(Error::new(EBADF))));
+    test!(call(&mut scheme, 0xFFFF, 0, 0, 0) == Error::mux(Err(Error::new(ENOSYS))));
+    succ!();
+}
+
+pub static TESTS: &'static [super::KernelTest] = &[
+    kernel_test!(round_trip, "Scheme packet open, write, read and close"),
+    kernel_test!(errors, "Scheme packet errors"),
+];
@@ -0,0 +1,65 @@
+use fs::{KScheme, ResourceSeek};
+
+use schemes::shm::ShmScheme;
+
+use system::error::{EBUSY, EEXIST, ENOENT};
+use system::syscall::{Stat, O_CREAT, O_EXCL, O_RDWR};
+
+use super::url;
+
+pub fn truncate_write() -> bool {
+    let mut scheme = ShmScheme::new();
+    let mut object = test_try!(scheme.open(url("shm:object"), O_CREAT | O_RDWR));
+
+    // Writes do not grow the object
+    test!(object.write(b"data").ok() == Some(0));
+    test!(object.map_object().is_none());
+
+    test!(object.truncate(8192).is_ok());
+    test!(object.write(b"data").ok() == Some(4));
+
+    let mut other = test_try!(scheme.open(url("shm:object"), O_RDWR));
+    let mut buf = [0; 4];
+    test!(other.read(&mut buf).ok() == Some(4));
+    test!(&buf == b"data");
+
+    let mut stat = Stat::default();
+    test!(scheme.stat(url("shm:object"), &mut stat).is_ok());
+    test!(stat.st_size == 8192);
+
+    // The pages cannot be replaced while they are mapped
+    let pages = match object.map_object() {
+        Some(pages) => pages,
+        None => fail!(),
+    };
+    test!(pages.size() == 8192);
+    test!(object.truncate(4096).err().map(|err| err.errno) == Some(EBUSY));
+    drop(pages);
+    test!(object.truncate(4096).is_ok());
+    test!(other.seek(ResourceSeek::Start(0)).is_ok());
+    test!(other.read(&mut buf).ok() == Some(4));
+    test!(&buf == b"data");
+
+    test!(scheme.open(url("shm:object"), O_CREAT | O_EXCL | O_RDWR).err().map(|err| err.errno) == Some(EEXIST));
+    succ!();
+}
+
+/// The object is gone once its last resource is closed
+pub fn last_close() -> bool {
+    let mut scheme = ShmScheme::new();
+    let object = test_try!(scheme.open(url("shm:object"), O_CREAT | O_RDWR));
+    let other = test_try!(object.dup());
+
+    drop(object);
+    let mut stat = Stat::default();
+    test!(scheme.stat(url("shm:object"), &mut stat).is_ok());
+    drop(other);
+    test!(scheme.stat(url("shm:object"), &mut stat).err().map(|err| err.errno) == Some(ENOENT));
+    test!(scheme.open(url("shm:object"), O_RDWR).err().map(|err| err.errno) == Some(ENOENT));
+    succ!();
+}
+
+pub static TESTS: &'static [super::KernelTest] = &[
+    kernel_test!(truncate_write, "shm: objects are resized, written and read by other resources"),
+    kernel_test!(last_close, "shm: an object is removed when its last resource is closed"),
+];
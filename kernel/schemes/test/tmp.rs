@@ -0,0 +1,90 @@
+use fs::{KScheme, ResourceSeek};
+
+use schemes::tmp::TmpScheme;
+
+use system::error::{EEXIST, EISDIR, ENOENT, ENOTEMPTY};
+use system::syscall::{Dirent, Stat, MODE_DIR, MODE_FILE, MODE_TYPE, O_CREAT, O_EXCL, O_RDWR, O_TRUNC};
+
+use super::url;
+
+pub fn write_read() -> bool {
+    let mut scheme = TmpScheme::new();
+    let mut file = test_try!(scheme.open(url("tmp:/file"), O_CREAT | O_RDWR));
+    test!(file.write(b"hello world").ok() == Some(11));
+    test!(file.seek(ResourceSeek::Start(6)).ok() == Some(6));
+    test!(file.write(b"there").ok() == Some(5));
+
+    // Another resource on the same file sees the data
+    let mut other = test_try!(scheme.open(url("tmp:file"), O_RDWR));
+    let mut buf = [0; 16];
+    test!(other.read(&mut buf).ok() == Some(11));
+    test!(&buf[..11] == b"hello there");
+    test!(other.read(&mut buf).ok() == Some(0));
+
+    // Writing past the end leaves zeros in the gap
+    test!(other.seek(ResourceSeek::End(2)).ok() == Some(13));
+    test!(other.write(b"!").ok() == Some(1));
+    let mut stat = Stat::default();
+    test!(scheme.stat(url("tmp:file"), &mut stat).is_ok());
+    test!(stat.st_mode & MODE_TYPE == MODE_FILE);
+    test!(stat.st_size == 14);
+
+    test!(file.truncate(5).is_ok());
+    test!(file.seek(ResourceSeek::Start(0)).is_ok());
+    test!(file.read(&mut buf).ok() == Some(5));
+
+    test!(scheme.open(url("tmp:file"), O_CREAT | O_EXCL | O_RDWR).err().map(|err| err.errno) == Some(EEXIST));
+    test!(scheme.open(url("tmp:file"), O_TRUNC | O_RDWR).is_ok());
+    test!(scheme.stat(url("tmp:file"), &mut stat).is_ok());
+    test!(stat.st_size == 0);
+    succ!();
+}
+
+pub fn directories() -> bool {
+    let mut scheme = TmpScheme::new();
+    test!(scheme.mkdir(url("tmp:dir"), 0).is_ok());
+    test!(scheme.mkdir(url("tmp:dir"), 0).err().map(|err| err.errno) == Some(EEXIST));
+    test!(scheme.open(url("tmp:dir/a"), O_CREAT | O_RDWR).is_ok());
+    test!(scheme.open(url("tmp:dir/./sub/../b"), O_CREAT | O_RDWR).is_ok());
+    test!(scheme.open(url("tmp:missing/a"), O_CREAT | O_RDWR).err().map(|err| err.errno) == Some(ENOENT));
+    test!(scheme.open(url("tmp:dir"), O_RDWR).err().map(|err| err.errno) == Some(EISDIR));
+
+    let mut list = test_try!(scheme.open(url("tmp:dir/"), 0));
+    let mut entries = [Dirent::default(); 4];
+    test!(list.getdents(&mut entries).ok() == Some(2));
+    test!(entries[0].name() == b"a" && entries[1].name() == b"b");
+
+    let mut root = test_try!(scheme.open(url("tmp:"), 0));
+    test!(root.getdents(&mut entries).ok() == Some(1));
+    test!(entries[0].name() == b"dir" && entries[0].d_mode & MODE_TYPE == MODE_DIR);
+
+    test!(scheme.rmdir(url("tmp:dir")).err().map(|err| err.errno) == Some(ENOTEMPTY));
+    test!(scheme.unlink(url("tmp:dir")).err().map(|err| err.errno) == Some(EISDIR));
+    test!(scheme.rename(url("tmp:dir"), url("tmp:moved")).is_ok());
+    test!(scheme.unlink(url("tmp:moved/a")).is_ok());
+    test!(scheme.unlink(url("tmp:moved/b")).is_ok());
+    test!(scheme.unlink(url("tmp:moved/b")).err().map(|err| err.errno) == Some(ENOENT));
+    test!(scheme.rmdir(url("tmp:moved")).is_ok());
+    succ!();
+}
+
+/// An unlinked file stays readable until it is closed
+pub fn unlink_open() -> bool {
+    let mut scheme = TmpScheme::new();
+    let mut file = test_try!(scheme.open(url("tmp:file"), O_CREAT | O_RDWR));
+    test!(file.write(b"data").ok() == Some(4));
+    test!(scheme.unlink(url("tmp:file")).is_ok());
+    test!(scheme.open(url("tmp:file"), O_RDWR).is_err());
+
+    let mut buf = [0; 4];
+    test!(file.seek(ResourceSeek::Start(0)).is_ok());
+    test!(file.read(&mut buf).ok() == Some(4));
+    test!(&buf == b"data");
+    succ!();
+}
+
+pub static TESTS: &'static [super::KernelTest] = &[
+    kernel_test!(write_read, "tmp: files are written, seeked, read back and truncated"),
+    kernel_test!(directories, "tmp: directories are listed, renamed and removed"),
+    kernel_test!(unlink_open, "tmp: an unlinked file stays open"),
+];
@@ -0,0 +1,43 @@
+use collections::vec::Vec;
+
+pub fn push_pop() -> bool {
+    let mut vec = Vec::new();
+    for i in 0..1000 {
+        vec.push(i);
+    }
+    test!(vec.len() == 1000);
+    test!(vec[999] == 999);
+    for i in (0..1000).rev() {
+        test!(vec.pop() == Some(i));
+    }
+    test!(vec.pop().is_none());
+    succ!();
+}
+
+pub fn insert_remove() -> bool {
+    let mut vec = vec![1, 2, 4];
+    vec.insert(2, 3);
+    test!(vec == [1, 2, 3, 4]);
+    test!(vec.remove(0) == 1);
+    test!(vec == [2, 3, 4]);
+    vec.retain(|&i| i != 3);
+    test!(vec == [2, 4]);
+    succ!();
+}
+
+pub fn resize() -> bool {
+    let mut vec: Vec<u8> = Vec::with_capacity(16);
+    test!(vec.capacity() >= 16);
+    vec.resize(4096, 0xAA);
+    test!(vec.len() == 4096);
+    test!(vec.iter().all(|&b| b == 0xAA));
+    vec.truncate(1);
+    test!(vec == [0xAA]);
+    succ!();
+}
+
+pub static TESTS: &'static [super::KernelTest] = &[
+    kernel_test!(push_pop, "Vec push and pop"),
+    kernel_test!(insert_remove, "Vec insert, remove and retain"),
+    kernel_test!(resize, "Vec resize and truncate"),
+];
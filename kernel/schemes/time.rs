@@ -0,0 +1,205 @@
+use alloc::boxed::Box;
+
+use arch::context::context_switch;
+
+use common::time::{Duration, NANOS_PER_MILLI, NANOS_PER_SEC};
+
+use core::{cmp, str};
+
+use fs::{KScheme, Resource, Url};
+
+use system::error::{Error, Result, EINVAL, ENOENT};
+use system::syscall::{CLOCK_MONOTONIC, CLOCK_REALTIME, POLLIN};
+
+/// A timer
+///
+/// Writing `<clock> <once|periodic> <seconds> [nanoseconds]` arms the timer, where `<clock>` is
+/// `monotonic` or `realtime`, and writing `disarm` stops it. A read blocks until the timer expires
+/// and returns the number of expirations since the last read as a `u64`, it is ready to read once
+/// the timer has expired.
+pub struct TimerResource {
+    clock: usize,
+    /// The next expiry on `clock`
+    deadline: Option<Duration>,
+    /// The period, if the timer is periodic
+    interval: Option<Duration>,
+}
+
+impl TimerResource {
+    fn now(&self) -> Duration {
+        if self.clock == CLOCK_REALTIME {
+            Duration::realtime()
+        } else {
+            Duration::monotonic()
+        }
+    }
+
+    fn arm(&mut self, command: &str) -> Result<()> {
+        let mut args = command.split_whitespace();
+
+        let clock = match args.next() {
+            Some("disarm") => {
+                self.deadline = None;
+                self.interval = None;
+                return Ok(());
+            },
+            Some("monotonic") => CLOCK_MONOTONIC,
+            Some("realtime") => CLOCK_REALTIME,
+            _ => return Err(Error::new(EINVAL)),
+        };
+
+        let periodic = match args.next() {
+            Some("once") => false,
+            Some("periodic") => true,
+            _ => return Err(Error::new(EINVAL)),
+        };
+
+        let secs = try!(args.next().and_then(|arg| arg.parse::<i64>().ok()).ok_or(Error::new(EINVAL)));
+        let nanos = match args.next() {
+            Some(arg) => try!(arg.parse::<i32>().or(Err(Error::new(EINVAL)))),
+            None => 0,
+        };
+        if nanos < 0 || nanos >= NANOS_PER_SEC {
+            return Err(Error::new(EINVAL));
+        }
+        let duration = Duration::new(secs, nanos);
+
+        // Expiries are only checked on each tick, so a shorter period would just spin, and one
+        // that is not positive would never catch up with the clock
+        if periodic && duration < Duration::new(0, NANOS_PER_MILLI) {
+            return Err(Error::new(EINVAL));
+        }
+
+        self.clock = clock;
+        self.deadline = Some(self.now() + duration);
+        self.interval = if periodic {
+            Some(duration)
+        } else {
+            None
+        };
+
+        Ok(())
+    }
+}
+
+impl Resource for TimerResource {
+    fn dup(&self) -> Result<Box<Resource>> {
+        Ok(box TimerResource {
+            clock: self.clock,
+            deadline: self.deadline,
+            interval: self.interval,
+        })
+    }
+
+    fn path(&self, buf: &mut [u8]) -> Result<usize> {
+        let path = if self.clock == CLOCK_REALTIME {
+            "time:realtime".as_bytes()
+        } else {
+            "time:monotonic".as_bytes()
+        };
+
+        for (b, p) in buf.iter_mut().zip(path.iter()) {
+            *b = *p;
+        }
+
+        Ok(cmp::min(buf.len(), path.len()))
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if buf.len() < 8 {
+            return Err(Error::new(EINVAL));
+        }
+
+        let mut deadline = try!(self.deadline.ok_or(Error::new(EINVAL)));
+
+        loop {
+            let now = self.now();
+            if deadline <= now {
+                break;
+            }
+
+            {
+                let mut contexts = ::env().contexts.write();
+                let mut context = try!(contexts.current_mut());
+                context.blocked = true;
+                // Realtime deadlines are converted when sleeping, so that a clock change is noticed
+                context.wake = Some(Duration::monotonic() + (deadline - now));
+            }
+
+            unsafe { context_switch(); }
+        }
+
+        let now = self.now();
+        let mut count: u64 = 0;
+        match self.interval {
+            Some(interval) => {
+                while deadline <= now {
+                    deadline = deadline + interval;
+                    count += 1;
+                }
+                self.deadline = Some(deadline);
+            },
+            None => {
+                count = 1;
+                self.deadline = None;
+            }
+        }
+
+        for (i, b) in buf.iter_mut().take(8).enumerate() {
+            *b = (count >> (i * 8)) as u8;
+        }
+
+        Ok(8)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let command = try!(str::from_utf8(buf).or(Err(Error::new(EINVAL))));
+        try!(self.arm(command.trim()));
+        Ok(buf.len())
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn poll(&self) -> Result<usize> {
+        let deadline = match self.deadline {
+            Some(deadline) => deadline,
+            None => return Ok(0),
+        };
+
+        let now = self.now();
+        if deadline <= now {
+            Ok(POLLIN)
+        } else {
+            // The wheel wakes the polling contexts when it expires, realtime deadlines are
+            // converted here and checked again then, so a clock change is noticed
+            let monotonic = Duration::monotonic();
+            ::env().timers.lock().add_pollers(monotonic + (deadline - now), monotonic);
+            Ok(0)
+        }
+    }
+}
+
+/// The timer scheme, each open creates a new disarmed timer
+pub struct TimeScheme;
+
+impl KScheme for TimeScheme {
+    fn scheme(&self) -> &str {
+        "time"
+    }
+
+    fn open(&mut self, url: Url, _: usize) -> Result<Box<Resource>> {
+        let clock = match url.reference().trim_matches('/') {
+            "" | "monotonic" => CLOCK_MONOTONIC,
+            "realtime" => CLOCK_REALTIME,
+            _ => return Err(Error::new(ENOENT)),
+        };
+
+        Ok(box TimerResource {
+            clock: clock,
+            deadline: None,
+            interval: None,
+        })
+    }
+}
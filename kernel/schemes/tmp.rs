@@ -0,0 +1,502 @@
+use alloc::arc::Arc;
+use alloc::boxed::Box;
+
+use collections::{BTreeMap, String, Vec};
+use collections::string::ToString;
+
+use core::cmp;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use fs::{Creds, DirResource, KScheme, Resource, ResourceSeek, Url};
+use fs::access::open_access;
+
+use sync::Intex;
+
+use system::error::{Error, Result, EEXIST, EINVAL, EISDIR, ELOOP, ENOENT, ENOSPC, ENOTDIR, ENOTEMPTY,
+                    EPERM};
+use system::syscall::{Dirent, Stat, MODE_DIR, MODE_EXEC, MODE_FILE, MODE_READ, MODE_SYMLINK, MODE_WRITE,
+                      O_APPEND, O_CREAT, O_EXCL, O_RDWR, O_TRUNC, O_WRONLY};
+
+/// The most bytes the files of `tmp:` hold together, as they are kept in kernel memory
+pub const TMP_SIZE: usize = 16 * 1024 * 1024;
+
+/// The permissions of files
+const FILE_MODE: u16 = 0o644;
+/// The permissions of directories
+const DIR_MODE: u16 = 0o755;
+/// The permissions of the root, where anyone may create files, like `/tmp`
+const ROOT_MODE: u16 = 0o777;
+/// The permissions of symbolic links
+const LINK_MODE: u16 = 0o777;
+
+/// A file of `tmp:`, shared by its names and the resources open on it, so that its data stays
+/// until the last of them is gone
+struct TmpFile {
+    data: Intex<Vec<u8>>,
+    owner: Creds,
+    /// The names of the file, more than one with hard links
+    links: AtomicUsize,
+    /// The bytes held by every file of the scheme, which this one counts in
+    used: Arc<AtomicUsize>,
+}
+
+impl TmpFile {
+    fn stat(&self, stat: &mut Stat) {
+        stat.st_mode = MODE_FILE | FILE_MODE;
+        stat.st_size = self.data.lock().len() as u64;
+        stat.st_uid = self.owner.uid as u32;
+        stat.st_gid = self.owner.gid as u32;
+        stat.st_nlink = self.links.load(Ordering::SeqCst) as u32;
+    }
+
+    /// Resize `data` to `len`, failing with `ENOSPC` if the scheme would hold more than `TMP_SIZE`
+    fn resize(&self, data: &mut Vec<u8>, len: usize) -> Result<()> {
+        if len > data.len() {
+            let grow = len - data.len();
+            if self.used.fetch_add(grow, Ordering::SeqCst) + grow > TMP_SIZE {
+                self.used.fetch_sub(grow, Ordering::SeqCst);
+                return Err(Error::new(ENOSPC));
+            }
+            data.resize(len, 0);
+        } else {
+            self.used.fetch_sub(data.len() - len, Ordering::SeqCst);
+            data.truncate(len);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for TmpFile {
+    fn drop(&mut self) {
+        self.used.fetch_sub(self.data.lock().len(), Ordering::SeqCst);
+    }
+}
+
+/// A node of `tmp:`
+enum TmpNode {
+    File(Arc<TmpFile>),
+    /// A directory, and its owner
+    Dir(Creds),
+    /// A symbolic link, its target and its owner
+    Link(String, Creds),
+}
+
+impl TmpNode {
+    fn owner(&self) -> Creds {
+        match *self {
+            TmpNode::File(ref file) => file.owner,
+            TmpNode::Dir(owner) => owner,
+            TmpNode::Link(_, owner) => owner,
+        }
+    }
+
+    fn stat(&self, stat: &mut Stat) {
+        match *self {
+            TmpNode::File(ref file) => file.stat(stat),
+            TmpNode::Dir(owner) => {
+                stat.st_mode = MODE_DIR | DIR_MODE;
+                stat.st_uid = owner.uid as u32;
+                stat.st_gid = owner.gid as u32;
+                stat.st_nlink = 1;
+            },
+            TmpNode::Link(ref target, owner) => {
+                stat.st_mode = MODE_SYMLINK | LINK_MODE;
+                stat.st_size = target.len() as u64;
+                stat.st_uid = owner.uid as u32;
+                stat.st_gid = owner.gid as u32;
+                stat.st_nlink = 1;
+            },
+        }
+    }
+}
+
+/// A file of `tmp:` that is open
+pub struct TmpResource {
+    path: String,
+    file: Arc<TmpFile>,
+    seek: usize,
+    /// Opened with `O_APPEND`, so that every write goes to the end
+    append: bool,
+}
+
+impl Resource for TmpResource {
+    fn dup(&self) -> Result<Box<Resource>> {
+        Ok(box TmpResource {
+            path: self.path.clone(),
+            file: self.file.clone(),
+            seek: self.seek,
+            append: self.append,
+        })
+    }
+
+    fn path(&self, buf: &mut [u8]) -> Result<usize> {
+        let path_a = b"tmp:/";
+        let path_b = self.path.as_bytes();
+        for (b, p) in buf.iter_mut().zip(path_a.iter().chain(path_b.iter())) {
+            *b = *p;
+        }
+
+        Ok(cmp::min(buf.len(), path_a.len() + path_b.len()))
+    }
+
+    /// The names of a file with hard links share the address of its data
+    fn node_path(&self, buf: &mut [u8]) -> Result<usize> {
+        let path = format!("tmp:#{:X}", &*self.file as *const TmpFile as usize);
+        for (b, p) in buf.iter_mut().zip(path.as_bytes().iter()) {
+            *b = *p;
+        }
+
+        Ok(cmp::min(buf.len(), path.len()))
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let data = self.file.data.lock();
+        if self.seek >= data.len() {
+            return Ok(0);
+        }
+
+        let count = cmp::min(buf.len(), data.len() - self.seek);
+        buf[..count].copy_from_slice(&data[self.seek..self.seek + count]);
+        self.seek += count;
+        Ok(count)
+    }
+
+    /// Write at the seek position, filling a gap after the end with zeros
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let mut data = self.file.data.lock();
+        if self.append {
+            self.seek = data.len();
+        }
+
+        let end = self.seek + buf.len();
+        if end > data.len() {
+            try!(self.file.resize(&mut data, end));
+        }
+        data[self.seek..end].copy_from_slice(buf);
+        self.seek = end;
+        Ok(buf.len())
+    }
+
+    fn seek(&mut self, pos: ResourceSeek) -> Result<usize> {
+        let len = self.file.data.lock().len();
+        let seek = match pos {
+            ResourceSeek::Start(offset) => offset as isize,
+            ResourceSeek::Current(offset) => self.seek as isize + offset,
+            ResourceSeek::End(offset) => len as isize + offset,
+        };
+        if seek < 0 {
+            return Err(Error::new(EINVAL));
+        }
+
+        self.seek = seek as usize;
+        Ok(self.seek)
+    }
+
+    fn stat(&self, stat: &mut Stat) -> Result<usize> {
+        self.file.stat(stat);
+        Ok(0)
+    }
+
+    /// The data is only in memory, there is nothing to write back
+    fn sync(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn truncate(&mut self, len: usize) -> Result<()> {
+        let mut data = self.file.data.lock();
+        self.file.resize(&mut data, len)
+    }
+}
+
+/// Files and directories in kernel memory, lost at shutdown
+///
+/// Anyone may create files and directories in the root, and like a sticky directory, only the
+/// owner of one or root may remove it. In other directories, only their owner may create and
+/// remove them. The files hold at most `TMP_SIZE` bytes together, writes past it fail with
+/// `ENOSPC`. Directories are opened for listing, opening one for writing fails with `EISDIR`.
+pub struct TmpScheme {
+    /// The nodes by path, without a leading or trailing `/`. The root is not one of them
+    nodes: BTreeMap<String, TmpNode>,
+    used: Arc<AtomicUsize>,
+}
+
+impl TmpScheme {
+    pub fn new() -> Box<Self> {
+        box TmpScheme {
+            nodes: BTreeMap::new(),
+            used: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// The path of `url` in the scheme, without empty and `.` components, failing with `ENOENT`
+    /// if it leaves the root with `..`
+    fn path(url: Url) -> Result<String> {
+        let mut parts: Vec<&str> = Vec::new();
+        for part in url.reference().split('/') {
+            match part {
+                "" | "." => (),
+                ".." => if parts.pop().is_none() {
+                    return Err(Error::new(ENOENT));
+                },
+                _ => parts.push(part),
+            }
+        }
+
+        let mut path = String::new();
+        for part in parts.iter() {
+            if ! path.is_empty() {
+                path.push('/');
+            }
+            path.push_str(part);
+        }
+        Ok(path)
+    }
+
+    /// The directory `path` is in, the root is ""
+    fn parent(path: &str) -> &str {
+        path.rfind('/').map_or("", |i| &path[..i])
+    }
+
+    /// The nodes in the directory `path`, with their names
+    fn children<'a>(&'a self, path: &str) -> Vec<(&'a str, &'a TmpNode)> {
+        let prefix = if path.is_empty() {
+            String::new()
+        } else {
+            format!("{}/", path)
+        };
+
+        self.nodes.iter().filter_map(|(node_path, node)| {
+            if node_path.starts_with(&prefix) && ! node_path[prefix.len()..].contains('/') {
+                Some((&node_path[prefix.len()..], node))
+            } else {
+                None
+            }
+        }).collect()
+    }
+
+    /// Fail unless the directory `path` exists, and the current context has `access` to it
+    fn check_dir(&self, path: &str, access: u16) -> Result<()> {
+        let mut stat = Stat::default();
+        if path.is_empty() {
+            stat.st_mode = MODE_DIR | ROOT_MODE;
+        } else {
+            match self.nodes.get(path) {
+                Some(node @ &TmpNode::Dir(_)) => node.stat(&mut stat),
+                Some(_) => return Err(Error::new(ENOTDIR)),
+                None => return Err(Error::new(ENOENT)),
+            }
+        }
+        Creds::current().check(&stat, access)
+    }
+
+    /// Fail unless a node can be created at `path`
+    fn check_new(&self, path: &str) -> Result<()> {
+        if path.is_empty() || self.nodes.contains_key(path) {
+            return Err(Error::new(EEXIST));
+        }
+        self.check_dir(TmpScheme::parent(path), MODE_WRITE | MODE_EXEC)
+    }
+
+    /// Fail unless the node at `path`, owned by `owner`, can be removed by the current context
+    fn check_remove(&self, path: &str, owner: Creds) -> Result<()> {
+        let parent = TmpScheme::parent(path);
+        try!(self.check_dir(parent, MODE_WRITE | MODE_EXEC));
+
+        let creds = Creds::current();
+        if parent.is_empty() && creds.uid != 0 && creds.uid != owner.uid {
+            return Err(Error::new(EPERM));
+        }
+        Ok(())
+    }
+}
+
+impl KScheme for TmpScheme {
+    fn scheme(&self) -> &str {
+        "tmp"
+    }
+
+    fn open(&mut self, url: Url, flags: usize) -> Result<Box<Resource>> {
+        let path = try!(TmpScheme::path(url));
+
+        let dir = match self.nodes.get(&path) {
+            Some(&TmpNode::Dir(_)) => true,
+            Some(_) => false,
+            None => path.is_empty(),
+        };
+        if dir {
+            if flags & (O_WRONLY | O_RDWR) != 0 {
+                return Err(Error::new(EISDIR));
+            }
+            try!(self.check_dir(&path, MODE_READ));
+
+            let entries: Vec<Dirent> = self.children(&path).iter().map(|&(name, node)| {
+                let mut stat = Stat::default();
+                node.stat(&mut stat);
+                Dirent::new(name, stat.st_mode, stat.st_size)
+            }).collect();
+            return Ok(box DirResource::new(format!("tmp:/{}", path), entries));
+        }
+
+        let file = match self.nodes.get(&path) {
+            Some(&TmpNode::File(ref file)) => {
+                if flags & O_CREAT == O_CREAT && flags & O_EXCL == O_EXCL {
+                    return Err(Error::new(EEXIST));
+                }
+
+                let mut stat = Stat::default();
+                file.stat(&mut stat);
+                try!(Creds::current().check(&stat, open_access(flags)));
+
+                Some(file.clone())
+            },
+            // Only reached with `O_NOFOLLOW`, other links are followed before the scheme is
+            Some(_) => return Err(Error::new(ELOOP)),
+            None => None,
+        };
+
+        let file = match file {
+            Some(file) => {
+                if flags & O_TRUNC == O_TRUNC {
+                    let mut data = file.data.lock();
+                    try!(file.resize(&mut data, 0));
+                }
+                file
+            },
+            None => if flags & O_CREAT == O_CREAT {
+                try!(self.check_new(&path));
+
+                let file = Arc::new(TmpFile {
+                    data: Intex::new(Vec::new()),
+                    owner: Creds::current(),
+                    links: AtomicUsize::new(1),
+                    used: self.used.clone(),
+                });
+                self.nodes.insert(path.clone(), TmpNode::File(file.clone()));
+                file
+            } else {
+                return Err(Error::new(ENOENT));
+            },
+        };
+
+        Ok(box TmpResource {
+            path: path,
+            file: file,
+            seek: 0,
+            append: flags & O_APPEND == O_APPEND,
+        })
+    }
+
+    fn mkdir(&mut self, url: Url, _: usize) -> Result<()> {
+        let path = try!(TmpScheme::path(url));
+        try!(self.check_new(&path));
+
+        self.nodes.insert(path, TmpNode::Dir(Creds::current()));
+        Ok(())
+    }
+
+    fn rmdir(&mut self, url: Url) -> Result<()> {
+        let path = try!(TmpScheme::path(url));
+        match self.nodes.get(&path) {
+            Some(node @ &TmpNode::Dir(_)) => try!(self.check_remove(&path, node.owner())),
+            Some(_) => return Err(Error::new(ENOTDIR)),
+            None => return Err(Error::new(if path.is_empty() { EPERM } else { ENOENT })),
+        }
+        if ! self.children(&path).is_empty() {
+            return Err(Error::new(ENOTEMPTY));
+        }
+
+        self.nodes.remove(&path);
+        Ok(())
+    }
+
+    /// Fails with `EEXIST` if `new` exists, rather than replacing it. A directory is moved with
+    /// everything in it
+    fn rename(&mut self, old: Url, new: Url) -> Result<()> {
+        let old_path = try!(TmpScheme::path(old));
+        let new_path = try!(TmpScheme::path(new));
+
+        let owner = try!(self.nodes.get(&old_path).map(|node| node.owner()).ok_or(Error::new(ENOENT)));
+        try!(self.check_remove(&old_path, owner));
+        try!(self.check_new(&new_path));
+        if new_path.starts_with(&format!("{}/", old_path)) {
+            return Err(Error::new(EINVAL));
+        }
+
+        let prefix = format!("{}/", old_path);
+        let moved: Vec<String> = self.nodes.keys()
+                                           .filter(|path| **path == old_path || path.starts_with(&prefix))
+                                           .cloned()
+                                           .collect();
+        for path in moved.iter() {
+            if let Some(node) = self.nodes.remove(path) {
+                self.nodes.insert(format!("{}{}", new_path, &path[old_path.len()..]), node);
+            }
+        }
+        Ok(())
+    }
+
+    fn link(&mut self, old: Url, new: Url) -> Result<()> {
+        let old_path = try!(TmpScheme::path(old));
+        let new_path = try!(TmpScheme::path(new));
+
+        let file = match self.nodes.get(&old_path) {
+            Some(&TmpNode::File(ref file)) => file.clone(),
+            Some(_) => return Err(Error::new(EPERM)),
+            None => return Err(Error::new(ENOENT)),
+        };
+        try!(self.check_new(&new_path));
+
+        file.links.fetch_add(1, Ordering::SeqCst);
+        self.nodes.insert(new_path, TmpNode::File(file));
+        Ok(())
+    }
+
+    fn symlink(&mut self, target: &str, url: Url) -> Result<()> {
+        let path = try!(TmpScheme::path(url));
+        try!(self.check_new(&path));
+
+        self.nodes.insert(path, TmpNode::Link(target.to_string(), Creds::current()));
+        Ok(())
+    }
+
+    fn readlink(&mut self, url: Url, buf: &mut [u8]) -> Result<usize> {
+        let path = try!(TmpScheme::path(url));
+        match self.nodes.get(&path) {
+            Some(&TmpNode::Link(ref target, _)) => {
+                let count = cmp::min(buf.len(), target.len());
+                buf[..count].copy_from_slice(&target.as_bytes()[..count]);
+                Ok(count)
+            },
+            Some(_) => Err(Error::new(EINVAL)),
+            None => Err(Error::new(ENOENT)),
+        }
+    }
+
+    fn stat(&mut self, url: Url, stat: &mut Stat) -> Result<()> {
+        let path = try!(TmpScheme::path(url));
+        if path.is_empty() {
+            stat.st_mode = MODE_DIR | ROOT_MODE;
+            stat.st_nlink = 1;
+            return Ok(());
+        }
+
+        let node = try!(self.nodes.get(&path).ok_or(Error::new(ENOENT)));
+        node.stat(stat);
+        Ok(())
+    }
+
+    /// Remove a file or a link, a file that is open stays until it is closed
+    fn unlink(&mut self, url: Url) -> Result<()> {
+        let path = try!(TmpScheme::path(url));
+        match self.nodes.get(&path) {
+            Some(&TmpNode::Dir(_)) => return Err(Error::new(EISDIR)),
+            Some(node) => try!(self.check_remove(&path, node.owner())),
+            None => return Err(Error::new(if path.is_empty() { EISDIR } else { ENOENT })),
+        }
+
+        if let Some(TmpNode::File(file)) = self.nodes.remove(&path) {
+            file.links.fetch_sub(1, Ordering::SeqCst);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,168 @@
+use alloc::boxed::Box;
+
+use collections::string::String;
+use collections::vec::Vec;
+
+use common::trace::{self, TracePoint, TRACE_BUSY, TRACE_POINTS};
+
+use core::{cmp, str};
+use core::sync::atomic::Ordering;
+
+use drivers::registry::{self, Subsystem};
+
+use env::Environment;
+
+use fs::{KScheme, Resource, Url};
+
+use schemes::strace::StraceScheme;
+
+use system::error::{Error, Result, EACCES, EINVAL};
+
+/// A trace resource, streaming events recorded since it was opened
+///
+/// Writing `enable <point>` or `disable <point>` turns a tracepoint on or off, `all` selects every
+/// tracepoint.
+pub struct TraceResource {
+    /// The next sequence number to read, for each CPU
+    seqs: Vec<u64>,
+    /// Formatted events that did not fit in the last read
+    data: Vec<u8>,
+}
+
+impl TraceResource {
+    fn new() -> TraceResource {
+        let seqs = ::env().trace.lock().buffers.iter().map(|buffer| buffer.head).collect();
+
+        TraceResource {
+            seqs: seqs,
+            data: Vec::new(),
+        }
+    }
+
+    /// Format new events into the pending data
+    fn collect(&mut self) {
+        let mut string = String::new();
+
+        while TRACE_BUSY.compare_and_swap(false, true, Ordering::SeqCst) {}
+        {
+            let tracer = ::env().trace.lock();
+            // Processors started since the open are read from their first event
+            while self.seqs.len() < tracer.buffers.len() {
+                self.seqs.push(0);
+            }
+            for (cpu, buffer) in tracer.buffers.iter().enumerate() {
+                if let Some(seq) = self.seqs.get_mut(cpu) {
+                    if *seq < buffer.tail() {
+                        string.push_str(&format!("{} events lost on CPU {}\n", buffer.tail() - *seq, cpu));
+                        *seq = buffer.tail();
+                    }
+
+                    while let Some(event) = buffer.get(*seq) {
+                        string.push_str(&trace::format_event(cpu, event));
+                        *seq += 1;
+                    }
+                }
+            }
+        }
+        TRACE_BUSY.store(false, Ordering::SeqCst);
+
+        self.data.extend_from_slice(string.as_bytes());
+    }
+}
+
+impl Resource for TraceResource {
+    fn dup(&self) -> Result<Box<Resource>> {
+        Ok(box TraceResource {
+            seqs: self.seqs.clone(),
+            data: self.data.clone(),
+        })
+    }
+
+    fn path(&self, buf: &mut [u8]) -> Result<usize> {
+        let path = b"trace:";
+
+        for (b, p) in buf.iter_mut().zip(path.iter()) {
+            *b = *p;
+        }
+
+        Ok(cmp::min(buf.len(), path.len()))
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if self.data.is_empty() {
+            self.collect();
+        }
+
+        let count = cmp::min(buf.len(), self.data.len());
+        for (b, d) in buf.iter_mut().zip(self.data.drain(.. count)) {
+            *b = d;
+        }
+        Ok(count)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let command = try!(str::from_utf8(buf).or(Err(Error::new(EINVAL))));
+
+        for line in command.lines() {
+            let mut args = line.split_whitespace();
+            let enable = match args.next() {
+                Some("enable") => true,
+                Some("disable") => false,
+                Some(_) => return Err(Error::new(EINVAL)),
+                None => continue,
+            };
+
+            for arg in args {
+                if arg == "all" {
+                    for point in TRACE_POINTS.iter() {
+                        trace::set_enabled(*point, enable);
+                    }
+                } else if let Some(point) = TracePoint::from_name(arg) {
+                    trace::set_enabled(point, enable);
+                } else {
+                    return Err(Error::new(EINVAL));
+                }
+            }
+        }
+
+        Ok(buf.len())
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// The trace scheme, only usable by root
+pub struct TraceScheme;
+
+impl KScheme for TraceScheme {
+    fn scheme(&self) -> &str {
+        "trace"
+    }
+
+    fn open(&mut self, _: Url, _: usize) -> Result<Box<Resource>> {
+        {
+            let contexts = ::env().contexts.read();
+            let current = try!(contexts.current());
+            if current.euid != 0 {
+                return Err(Error::new(EACCES));
+            }
+        }
+
+        Ok(box TraceResource::new())
+    }
+}
+
+/// Debug tracing, the `trace:` and `strace:` schemes, enabled by the `trace` feature
+pub const SUBSYSTEM: Subsystem = Subsystem {
+    name: "trace",
+    init: init,
+    pci_device: registry::no_pci_device,
+};
+
+/// Register the tracing schemes
+unsafe fn init(env: &mut Environment) {
+    env.schemes.push(box StraceScheme);
+    env.schemes.push(box TraceScheme);
+}
@@ -1,8 +1,12 @@
-pub use arch::intex::Intex;
+pub use arch::intex::{Intex, IntexRw};
+pub use self::poll::PollWaiters;
+pub use self::rcu::Rcu;
 pub use self::wait_condition::WaitCondition;
 pub use self::wait_queue::WaitQueue;
 pub use self::wait_map::WaitMap;
 
+pub mod poll;
+pub mod rcu;
 pub mod wait_condition;
 pub mod wait_queue;
 pub mod wait_map;
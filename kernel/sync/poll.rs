@@ -0,0 +1,44 @@
+use arch::context::Context;
+
+use collections::Vec;
+
+use core::mem;
+use core::ops::DerefMut;
+
+use super::Intex;
+
+/// Contexts blocked in `poll`
+///
+/// A context polling many files cannot wait on the condition of each, so it is woken by every
+/// `WaitCondition::notify`, which is how a resource that was not ready becomes ready, and checks
+/// its files again.
+pub struct PollWaiters {
+    contexts: Intex<Vec<*mut Context>>,
+}
+
+impl PollWaiters {
+    pub fn new() -> PollWaiters {
+        PollWaiters {
+            contexts: Intex::new(Vec::new()),
+        }
+    }
+
+    /// Wake all polling contexts
+    pub unsafe fn notify(&self) {
+        let mut contexts = Vec::new();
+        mem::swap(self.contexts.lock().deref_mut(), &mut contexts);
+        for &context in contexts.iter() {
+            (*context).blocked = false;
+        }
+    }
+
+    /// Add `context`, which must block until woken or removed
+    pub fn add(&self, context: *mut Context) {
+        self.contexts.lock().push(context);
+    }
+
+    /// Remove `context`, if it was not woken
+    pub fn remove(&self, context: *mut Context) {
+        self.contexts.lock().retain(|&waiter| waiter != context);
+    }
+}
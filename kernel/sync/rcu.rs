@@ -0,0 +1,130 @@
+//! Read-copy-update
+//!
+//! An `Rcu` holds a value that is read often and changed rarely. Readers never lock: `read`
+//! counts the reader and returns the current value. Writers copy the value, change the copy and
+//! publish it in place of the old one, which is retired instead of freed, as readers may still
+//! use it. Retired values are freed once no reader is counted, by the writer or by the last
+//! reader to leave. A reader that entered after a value was replaced cannot see it, so none can
+//! be using a retired value at that point.
+//!
+//! Readers must not block while holding an `RcuGuard`, as that delays freeing retired values.
+
+use alloc::boxed::Box;
+
+use collections::vec::Vec;
+
+use core::cell::UnsafeCell;
+use core::ops::Deref;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+
+pub struct Rcu<T> {
+    /// The current value
+    current: AtomicPtr<T>,
+    /// The number of readers
+    readers: AtomicUsize,
+    /// Held while writing, and while retired values are freed
+    lock: AtomicBool,
+    /// Values replaced while there were readers
+    retired: UnsafeCell<Vec<*mut T>>,
+    /// The length of `retired`, read without the lock
+    retired_count: AtomicUsize,
+}
+
+unsafe impl<T: Send + Sync> Send for Rcu<T> { }
+
+unsafe impl<T: Send + Sync> Sync for Rcu<T> { }
+
+impl<T: Clone> Rcu<T> {
+    pub fn new(value: T) -> Rcu<T> {
+        Rcu {
+            current: AtomicPtr::new(Box::into_raw(box value)),
+            readers: AtomicUsize::new(0),
+            lock: AtomicBool::new(false),
+            retired: UnsafeCell::new(Vec::new()),
+            retired_count: AtomicUsize::new(0),
+        }
+    }
+
+    /// Read the current value
+    pub fn read(&self) -> RcuGuard<T> {
+        self.readers.fetch_add(1, Ordering::SeqCst);
+        RcuGuard {
+            rcu: self,
+            value: unsafe { &*self.current.load(Ordering::SeqCst) },
+        }
+    }
+
+    /// Change a copy of the value with `f` and publish it, returning what `f` returns
+    ///
+    /// Writers are serialized, `f` sees the value left by the previous writer.
+    pub fn update<R, F: FnOnce(&mut T) -> R>(&self, f: F) -> R {
+        while ! self.try_lock() {}
+
+        let old = self.current.load(Ordering::SeqCst);
+        let mut new = box unsafe { (*old).clone() };
+        let result = f(&mut new);
+        self.current.store(Box::into_raw(new), Ordering::SeqCst);
+
+        unsafe {
+            (*self.retired.get()).push(old);
+            self.retired_count.fetch_add(1, Ordering::SeqCst);
+            self.collect();
+        }
+
+        self.lock.store(false, Ordering::SeqCst);
+
+        result
+    }
+
+    fn try_lock(&self) -> bool {
+        ! self.lock.compare_and_swap(false, true, Ordering::SeqCst)
+    }
+
+    /// Free the retired values if there are no readers, with the lock held
+    unsafe fn collect(&self) {
+        if self.readers.load(Ordering::SeqCst) == 0 {
+            for value in (*self.retired.get()).drain(..) {
+                drop(Box::from_raw(value));
+            }
+            self.retired_count.store(0, Ordering::SeqCst);
+        }
+    }
+}
+
+impl<T> Drop for Rcu<T> {
+    fn drop(&mut self) {
+        unsafe {
+            drop(Box::from_raw(self.current.load(Ordering::SeqCst)));
+            for value in (*self.retired.get()).drain(..) {
+                drop(Box::from_raw(value));
+            }
+        }
+    }
+}
+
+/// A reader of an `Rcu`, returned by `read`
+pub struct RcuGuard<'a, T: Clone + 'a> {
+    rcu: &'a Rcu<T>,
+    value: &'a T,
+}
+
+impl<'a, T: Clone> Deref for RcuGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.value
+    }
+}
+
+impl<'a, T: Clone> Drop for RcuGuard<'a, T> {
+    fn drop(&mut self) {
+        // The last reader frees the retired values, unless a writer holds the lock, then a later
+        // reader or writer does
+        if self.rcu.readers.fetch_sub(1, Ordering::SeqCst) == 1
+            && self.rcu.retired_count.load(Ordering::SeqCst) > 0
+            && self.rcu.try_lock() {
+            unsafe { self.rcu.collect() };
+            self.rcu.lock.store(false, Ordering::SeqCst);
+        }
+    }
+}
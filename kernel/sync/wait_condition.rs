@@ -24,10 +24,15 @@ impl WaitCondition {
         for &context in contexts.iter() {
             (*context).blocked = false;
         }
+
+        // Whatever was waited for may be one of the files a context is polling
+        if let Some(ref env) = ::ENV_PTR {
+            env.pollers.notify();
+        }
     }
 
     pub unsafe fn wait(&self) {
-        if let Ok(mut context) = ::env().contexts.lock().current_mut() {
+        if let Ok(mut context) = ::env().contexts.write().current_mut() {
             let mut contexts = self.contexts.lock();
             contexts.push(context.deref_mut() as *mut Context);
             (*context).blocked = true;
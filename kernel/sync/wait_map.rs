@@ -1,32 +1,73 @@
+use arch::context::{context_switch, Context};
+
 use collections::BTreeMap;
+use collections::Vec;
+
+use core::ops::DerefMut;
 
 use super::Intex;
-use super::WaitCondition;
 
+/// A map of values that contexts can block on by key
+///
+/// A context waiting for a key is blocked until a value is sent for that key, and only the
+/// contexts waiting for it are woken, so that many contexts waiting on one map, like the callers
+/// of a scheme, do not all run each time one of them gets its value.
 pub struct WaitMap<K, V> {
     pub inner: Intex<BTreeMap<K, V>>,
-    pub condition: WaitCondition
+    /// The contexts waiting for each key
+    waiters: Intex<BTreeMap<K, Vec<*mut Context>>>,
 }
 
-impl<K, V> WaitMap<K, V> where K: Ord {
+impl<K, V> WaitMap<K, V> where K: Ord + Clone {
     pub fn new() -> WaitMap<K, V> {
         WaitMap {
             inner: Intex::new(BTreeMap::new()),
-            condition: WaitCondition::new()
+            waiters: Intex::new(BTreeMap::new()),
         }
     }
 
     pub fn send(&self, key: K, value: V) {
-        self.inner.lock().insert(key, value);
-        unsafe { self.condition.notify(); }
+        let waiters = {
+            let mut inner = self.inner.lock();
+            let waiters = self.waiters.lock().remove(&key);
+            inner.insert(key, value);
+            waiters
+        };
+
+        for &context in waiters.iter().flat_map(|waiters| waiters.iter()) {
+            unsafe { (*context).blocked = false; }
+        }
     }
 
+    /// Block until a value is sent for `key`, and remove it
     pub fn receive(&self, key: &K) -> V {
         loop {
-            if let Some(value) = self.inner.lock().remove(key) {
-                return value;
+            {
+                // The value is checked for and the context queued under the same lock as `send`
+                // inserts, so that a value sent in between is not missed
+                let mut inner = self.inner.lock();
+                if let Some(value) = inner.remove(key) {
+                    return value;
+                }
+
+                if let Ok(mut context) = ::env().contexts.write().current_mut() {
+                    self.waiters.lock().entry(key.clone()).or_insert_with(Vec::new)
+                                       .push(context.deref_mut() as *mut Context);
+                    context.blocked = true;
+                }
+            }
+
+            unsafe { context_switch(); }
+        }
+    }
+}
+
+impl<K, V> Drop for WaitMap<K, V> {
+    fn drop(&mut self) {
+        for waiters in self.waiters.lock().values() {
+            for &context in waiters.iter() {
+                unsafe { (*context).blocked = false; }
             }
-            unsafe { self.condition.wait(); }
         }
     }
 }
@@ -19,6 +19,14 @@ impl<T> WaitQueue<T> {
         }
     }
 
+    /// Create a queue with room for `capacity` values, see `try_send`
+    pub fn with_capacity(capacity: usize) -> WaitQueue<T> {
+        WaitQueue {
+            inner: Intex::new(VecDeque::with_capacity(capacity)),
+            condition: WaitCondition::new()
+        }
+    }
+
     pub fn receive(&self) -> T {
         loop {
             if let Some(value) = self.inner.lock().pop_front() {
@@ -46,4 +54,17 @@ impl<T> WaitQueue<T> {
         self.inner.lock().push_back(value);
         unsafe { self.condition.notify(); }
     }
+
+    /// Send `value` only if the queue has room for it without allocating, for IRQ handlers
+    pub fn try_send(&self, value: T) -> bool {
+        {
+            let mut inner = self.inner.lock();
+            if inner.len() >= inner.capacity() {
+                return false;
+            }
+            inner.push_back(value);
+        }
+        unsafe { self.condition.notify(); }
+        true
+    }
 }
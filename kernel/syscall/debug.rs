@@ -1,30 +1,29 @@
-use core::slice;
+use arch::serial;
 
-use drivers::io::{Io, Pio};
+use core::cmp;
 
 use system::error::Result;
 
-pub fn do_sys_debug(ptr: *const u8, len: usize) -> Result<usize> {
-    let bytes = unsafe { slice::from_raw_parts(ptr, len) };
-
-    if unsafe { ::ENV_PTR.is_some() } {
-        ::env().console.lock().write(bytes);
-    } else {
-        let serial_status = Pio::<u8>::new(0x3F8 + 5);
-        let mut serial_data = Pio::<u8>::new(0x3F8);
-
-        for byte in bytes.iter() {
-            while !serial_status.readf(0x20) {}
-            serial_data.write(*byte);
+use super::validate::copy_from_user;
 
-            if *byte == 8 {
-                while !serial_status.readf(0x20) {}
-                serial_data.write(0x20);
-
-                while !serial_status.readf(0x20) {}
-                serial_data.write(8);
+pub fn do_sys_debug(ptr: *const u8, len: usize) -> Result<usize> {
+    // Copied in pieces on the stack, as this is used before memory is set up
+    let mut chunk = [0; 256];
+    let mut offset = 0;
+    while offset < len {
+        let count = cmp::min(chunk.len(), len - offset);
+        let bytes = &mut chunk[..count];
+        try!(copy_from_user(bytes, (ptr as usize + offset) as *const u8));
+
+        if unsafe { ::ENV_PTR.is_some() } {
+            ::env().vts.lock().consoles[0].write(bytes);
+        } else {
+            for byte in bytes.iter() {
+                serial::write(*byte);
             }
         }
+
+        offset += count;
     }
 
     Ok(len)
@@ -1,53 +1,109 @@
 use alloc::arc::Arc;
 
-use arch::context::{CONTEXT_STACK_SIZE, CONTEXT_STACK_ADDR, context_switch, context_userspace, Context, ContextMemory};
-use arch::elf::Elf;
+use arch::context::{CONTEXT_STACK_SIZE, CONTEXT_STACK_ADDR, USER_START, context_switch, context_userspace, Context,
+                    ContextMemory};
+use arch::elf::{Elf, ElfRelocation};
 use arch::memory;
 use arch::regs::Regs;
 
 use collections::string::{String, ToString};
 use collections::vec::Vec;
 
+use common::random;
 use common::slice::GetSlice;
 
 use core::cell::UnsafeCell;
 use core::ops::DerefMut;
 use core::{mem, ptr, str};
 
-use fs::Url;
+use fs::{Resource, Url};
+
+use system::error::{Error, Result, ENOEXEC, ENOMEM};
+use system::syscall::{SigAction, FD_CLOEXEC, SIG_IGN};
+
+/// The lowest base of position independent executables
+const PIE_BASE: usize = USER_START;
+/// The number of pages that the base of position independent executables is randomized over
+const PIE_PAGES: usize = 0x10000;
+/// The lowest base of the dynamic linker, randomized like position independent executables
+const INTERP_BASE: usize = 0x60000000;
+
+/// Auxiliary vector entries, passed after the environment
+const AT_NULL: usize = 0;
+const AT_PHDR: usize = 3;
+const AT_PHENT: usize = 4;
+const AT_PHNUM: usize = 5;
+const AT_PAGESZ: usize = 6;
+const AT_BASE: usize = 7;
+const AT_ENTRY: usize = 9;
+
+/// The user code segment for 32-bit programs, which run in compatibility mode
+#[cfg(target_arch = "x86_64")]
+const USER_CODE32: usize = 0x38;
+
+/// The user code segment for an executable
+#[cfg(target_arch = "x86")]
+fn user_code(_compat: bool) -> usize {
+    0x18
+}
+
+/// The user code segment for an executable
+#[cfg(target_arch = "x86_64")]
+fn user_code(compat: bool) -> usize {
+    if compat {
+        USER_CODE32
+    } else {
+        0x18
+    }
+}
+
+/// Copy a string into the memory of a context, with a terminating NUL, returning its virtual address
+fn map_string(context: &mut Context, mut string: String) -> usize {
+    if ! string.ends_with('\0') {
+        string.push('\0');
+    }
+
+    let physical_address = string.as_ptr() as usize;
+    let virtual_address = context.next_mem();
+    let virtual_size = string.len();
 
-use system::error::{Error, Result, ENOEXEC};
+    mem::forget(string);
 
-pub fn execute_thread(context_ptr: *mut Context, entry: usize, mut args: Vec<String>) -> ! {
-    Context::spawn("kexec".to_string(), box move || {
+    unsafe {
+        (*context.memory.get()).push(ContextMemory {
+            physical_address: physical_address,
+            virtual_address: virtual_address,
+            virtual_size: virtual_size,
+            writeable: false,
+            allocated: true,
+            shared: None,
+            object: None,
+        });
+    }
+
+    virtual_address
+}
+
+pub fn execute_thread(context_ptr: *mut Context, entry: usize, compat: bool, mut args: Vec<String>, mut env: Vec<String>, auxv: Vec<(usize, usize)>) -> ! {
+    Context::kspawn_pinned("kexec", move || {
         let context = unsafe { &mut *context_ptr };
 
         let mut context_args: Vec<usize> = Vec::new();
-        context_args.push(0); // ENVP
+        // Pushed in reverse, so that each entry is its type followed by its value
+        context_args.push(0);
+        context_args.push(AT_NULL);
+        for &(_type, value) in auxv.iter().rev() {
+            context_args.push(value);
+            context_args.push(_type);
+        }
+        context_args.push(0); // ENVP NULL
+        while let Some(var) = env.pop() {
+            context_args.push(map_string(context, var));
+        }
         context_args.push(0); // ARGV NULL
         let mut argc = 0;
-        while let Some(mut arg) = args.pop() {
-            if ! arg.ends_with('\0') {
-                arg.push('\0');
-            }
-
-            let physical_address = arg.as_ptr() as usize;
-            let virtual_address = context.next_mem();
-            let virtual_size = arg.len();
-
-            mem::forget(arg);
-
-            unsafe {
-                (*context.memory.get()).push(ContextMemory {
-                    physical_address: physical_address,
-                    virtual_address: virtual_address,
-                    virtual_size: virtual_size,
-                    writeable: false,
-                    allocated: true,
-                });
-            }
-
-            context_args.push(virtual_address as usize);
+        while let Some(arg) = args.pop() {
+            context_args.push(map_string(context, arg));
             argc += 1;
         }
         context_args.push(argc);
@@ -64,7 +120,9 @@ pub fn execute_thread(context_ptr: *mut Context, entry: usize, mut args: Vec<Str
                         virtual_address: virtual_address,
                         virtual_size: virtual_size,
                         writeable: true,
-                        allocated: true
+                        allocated: true,
+                        shared: None,
+                        object: None,
                     });
                 }
             }
@@ -79,13 +137,21 @@ pub fn execute_thread(context_ptr: *mut Context, entry: usize, mut args: Vec<Str
             virtual_size: CONTEXT_STACK_SIZE,
             writeable: true,
             allocated: true,
+            shared: None,
+            object: None,
         });
 
         let user_sp = if let Some(ref stack) = context.stack {
             let mut sp = stack.physical_address + stack.virtual_size - 128;
             for arg in context_args.iter() {
-                sp -= mem::size_of::<usize>();
-                unsafe { ptr::write(sp as *mut usize, *arg) };
+                // 32-bit programs expect 32-bit words
+                if compat {
+                    sp -= mem::size_of::<u32>();
+                    unsafe { ptr::write(sp as *mut u32, *arg as u32) };
+                } else {
+                    sp -= mem::size_of::<usize>();
+                    unsafe { ptr::write(sp as *mut usize, *arg) };
+                }
             }
             sp - stack.physical_address + stack.virtual_address
         } else {
@@ -96,7 +162,7 @@ pub fn execute_thread(context_ptr: *mut Context, entry: usize, mut args: Vec<Str
             context.push(0x20 | 3);
             context.push(user_sp);
             context.push(1 << 9);
-            context.push(0x18 | 3);
+            context.push(user_code(compat) | 3);
             context.push(entry);
             context.push(context_userspace as usize);
         }
@@ -111,16 +177,89 @@ pub fn execute_thread(context_ptr: *mut Context, entry: usize, mut args: Vec<Str
     }
 }
 
-/// Execute an executable
-pub fn execute(mut args: Vec<String>) -> Result<usize> {
-    let contexts = ::env().contexts.lock();
-    let current = try!(contexts.current());
-
+/// Read a resource to the end
+pub fn read_all(resource: &mut Box<Resource>) -> Result<Vec<u8>> {
     let mut vec: Vec<u8> = Vec::new();
 
-    let path = current.canonicalize(args.get(0).map_or("", |p| &p));
+    'reading: loop {
+        let mut bytes = [0; 4096];
+        match resource.read(&mut bytes) {
+            Ok(0) => break 'reading,
+            Ok(count) => vec.push_all(bytes.get_slice(.. count)),
+            Err(err) => return Err(err)
+        }
+    }
+
+    Ok(vec)
+}
+
+/// Copy the loadable segments of an executable to new memory, moved up by `base`
+unsafe fn load(executable: &Elf, base: usize, memory: &mut Vec<ContextMemory>) -> Result<()> {
+    for segment in executable.load_segment().iter() {
+        let virtual_address = try!((segment.vaddr as usize).checked_add(base).ok_or(Error::new(ENOEXEC)));
+        let virtual_size = segment.mem_len as usize;
+
+        let offset = virtual_address % 4096;
+
+        let physical_address = memory::alloc(virtual_size + offset);
+
+        if physical_address > 0 {
+            // Copy progbits
+            ::memcpy((physical_address + offset) as *mut u8,
+                     (executable.data.as_ptr() as usize + segment.off as usize) as *const u8,
+                     segment.file_len as usize);
+            // Zero bss
+            if segment.mem_len > segment.file_len {
+                ::memset((physical_address + offset + segment.file_len as usize) as *mut u8,
+                        0,
+                        segment.mem_len as usize - segment.file_len as usize);
+            }
+
+            memory.push(ContextMemory {
+                physical_address: physical_address,
+                virtual_address: virtual_address - offset,
+                virtual_size: virtual_size + offset,
+                writeable: segment.flags & 2 == 2,
+                allocated: true,
+                shared: None,
+                object: None,
+            });
+        } else if virtual_size > 0 {
+            return Err(Error::new(ENOMEM));
+        }
+    }
+
+    Ok(())
+}
+
+/// Apply the relative relocations of an executable loaded at `base`
+unsafe fn relocate(relocations: &[ElfRelocation], base: usize, compat: bool, memory: &[ContextMemory]) -> Result<()> {
+    for relocation in relocations.iter() {
+        let virtual_address = try!(base.checked_add(relocation.vaddr).ok_or(Error::new(ENOEXEC)));
+        let size = if compat { mem::size_of::<u32>() } else { mem::size_of::<usize>() };
+        let physical_address = try!(memory.iter().find(|mem| {
+            virtual_address >= mem.virtual_address && virtual_address + size <= mem.virtual_address + mem.virtual_size
+        }).map(|mem| virtual_address - mem.virtual_address + mem.physical_address).ok_or(Error::new(ENOEXEC)));
+
+        if compat {
+            let addend = relocation.addend.unwrap_or(ptr::read(physical_address as *const u32) as usize);
+            ptr::write(physical_address as *mut u32, base.wrapping_add(addend) as u32);
+        } else {
+            let addend = relocation.addend.unwrap_or(ptr::read(physical_address as *const usize));
+            ptr::write(physical_address as *mut usize, base.wrapping_add(addend));
+        }
+    }
+
+    Ok(())
+}
+
+/// Execute an executable, replacing the environment of the current context with `env` if it is
+/// given
+pub fn execute(mut args: Vec<String>, env: Option<Vec<String>>) -> Result<usize> {
+    // The contexts are not kept locked while the executable is read, which may block
+    let path = try!(::env().contexts.read().current()).canonicalize(args.get(0).map_or("", |p| &p));
     let mut url = try!(Url::from_str(&path)).to_cow();
-    {
+    let vec = {
         let mut resource = if let Ok(resource) = url.as_url().open() {
             resource
         } else {
@@ -129,15 +268,8 @@ pub fn execute(mut args: Vec<String>) -> Result<usize> {
             try!(url.as_url().open())
         };
 
-        'reading: loop {
-            let mut bytes = [0; 4096];
-            match resource.read(&mut bytes) {
-                Ok(0) => break 'reading,
-                Ok(count) => vec.push_all(bytes.get_slice(.. count)),
-                Err(err) => return Err(err)
-            }
-        }
-    }
+        try!(read_all(&mut resource))
+    };
 
     if vec.starts_with(b"#!") {
         if let Some(mut arg) = args.get_mut(0) {
@@ -155,58 +287,116 @@ pub fn execute(mut args: Vec<String>) -> Result<usize> {
         if i == 0 {
             args.insert(i, "/bin/sh".to_string());
         }
-        execute(args)
+        execute(args, env)
     } else {
         match Elf::from(&vec) {
             Ok(executable) => {
-                let entry = unsafe { executable.entry() };
+                let compat = executable.compat();
+
+                // Dynamically linked executables are loaded with the dynamic linker they name, which
+                // relocates them and loads their libraries
+                let mut interpreter_data = Vec::new();
+                if let Some(path) = unsafe { executable.interpreter() } {
+                    let path = try!(::env().contexts.read().current()).canonicalize(path);
+                    let mut resource = try!(try!(Url::from_str(&path)).open());
+                    interpreter_data = try!(read_all(&mut resource));
+                }
+                let interpreter = if interpreter_data.is_empty() {
+                    None
+                } else {
+                    match Elf::from(&interpreter_data) {
+                        Ok(interpreter) => if interpreter.dynamic() && interpreter.compat() == compat && unsafe { interpreter.interpreter() }.is_none() {
+                            Some(interpreter)
+                        } else {
+                            debugln!("execute: invalid dynamic linker for '{:?}'", url);
+                            return Err(Error::new(ENOEXEC));
+                        },
+                        Err(msg) => {
+                            debugln!("execute: failed to load dynamic linker for '{:?}': {}", url, msg);
+                            return Err(Error::new(ENOEXEC));
+                        }
+                    }
+                };
+
+                // Position independent executables are loaded at a random base, and relocated here
+                // if there is no dynamic linker to do it
+                let (base, relocations) = if executable.dynamic() {
+                    let relocations = if interpreter.is_some() {
+                        Ok(Vec::new())
+                    } else {
+                        unsafe { executable.relocations() }
+                    };
+                    match relocations {
+                        Ok(relocations) => (PIE_BASE + (random::rand() % PIE_PAGES) * 4096, relocations),
+                        Err(msg) => {
+                            debugln!("execute: failed to relocate '{:?}': {}", url, msg);
+                            return Err(Error::new(ENOEXEC));
+                        }
+                    }
+                } else {
+                    (0, Vec::new())
+                };
+
                 let mut memory = Vec::new();
                 unsafe {
-                    for segment in executable.load_segment().iter() {
-                        let virtual_address = segment.vaddr as usize;
-                        let virtual_size = segment.mem_len as usize;
-
-                        let offset = virtual_address % 4096;
-
-                        let physical_address = memory::alloc(virtual_size + offset);
-
-                        if physical_address > 0 {
-                            // Copy progbits
-                            ::memcpy((physical_address + offset) as *mut u8,
-                                     (executable.data.as_ptr() as usize + segment.off as usize) as *const u8,
-                                     segment.file_len as usize);
-                            // Zero bss
-                            if segment.mem_len > segment.file_len {
-                                ::memset((physical_address + offset + segment.file_len as usize) as *mut u8,
-                                        0,
-                                        segment.mem_len as usize - segment.file_len as usize);
-                            }
+                    try!(load(&executable, base, &mut memory));
+                    try!(relocate(&relocations, base, compat, &memory));
+                }
 
-                            memory.push(ContextMemory {
-                                physical_address: physical_address,
-                                virtual_address: virtual_address - offset,
-                                virtual_size: virtual_size + offset,
-                                writeable: segment.flags & 2 == 2,
-                                allocated: true,
-                            });
-                        }
-                    }
+                let mut auxv = Vec::new();
+                if let Some((phdr, phent, phnum)) = unsafe { executable.program_headers() } {
+                    auxv.push((AT_PHDR, base + phdr));
+                    auxv.push((AT_PHENT, phent));
+                    auxv.push((AT_PHNUM, phnum));
                 }
+                auxv.push((AT_PAGESZ, 4096));
+                auxv.push((AT_ENTRY, unsafe { executable.entry() } + base));
+
+                let entry = if let Some(ref interpreter) = interpreter {
+                    let interpreter_base = INTERP_BASE + (random::rand() % PIE_PAGES) * 4096;
+                    unsafe { try!(load(interpreter, interpreter_base, &mut memory)) };
+                    auxv.push((AT_BASE, interpreter_base));
+                    unsafe { interpreter.entry() } + interpreter_base
+                } else {
+                    unsafe { executable.entry() } + base
+                };
 
                 if entry > 0 && ! memory.is_empty() {
-                    let mut contexts = ::env().contexts.lock();
-                    let mut context = try!(contexts.current_mut());
+                    let (context_ptr, env) = {
+                        let mut contexts = ::env().contexts.write();
+                        let mut context = try!(contexts.current_mut());
+
+                        //debugln!("{}: {}: execute {}", context.pid, context.name, url.string);
+
+                        context.name = url.as_url().to_string();
+                        // The program starts as the main thread of a process, without a thread pointer
+                        context.tgid = context.pid;
+                        context.tls = 0;
+                        context.cwd = Arc::new(UnsafeCell::new(unsafe { (*context.cwd.get()).clone() }));
+                        if let Some(env) = env {
+                            context.env = env;
+                        }
+                        let env = context.env.clone();
 
-                    //debugln!("{}: {}: execute {}", context.pid, context.name, url.string);
+                        // Close the files marked close-on-exec, the rest are inherited by the program
+                        unsafe { (*context.files.get()).retain(|file| file.fd_flags & FD_CLOEXEC != FD_CLOEXEC) };
+
+                        // Handlers are in the old program, ignored signals and the mask are kept
+                        for action in context.sigactions.iter_mut() {
+                            if action.sa_handler > SIG_IGN {
+                                *action = SigAction::default();
+                            }
+                        }
 
-                    context.name = url.as_url().to_string();
-                    context.cwd = Arc::new(UnsafeCell::new(unsafe { (*context.cwd.get()).clone() }));
+                        unsafe { context.unmap() };
+                        context.memory = Arc::new(UnsafeCell::new(memory));
+                        unsafe { context.map() };
 
-                    unsafe { context.unmap() };
-                    context.memory = Arc::new(UnsafeCell::new(memory));
-                    unsafe { context.map() };
+                        (context.deref_mut() as *mut Context, env)
+                    };
 
-                    execute_thread(context.deref_mut(), entry, args);
+                    // This does not return, so the contexts must be unlocked first
+                    execute_thread(context_ptr, entry, compat, args, env, auxv);
                 } else {
                     Err(Error::new(ENOEXEC))
                 }
@@ -1,205 +1,450 @@
-use arch::context::ContextFile;
+use alloc::arc::Arc;
 
-use core::slice;
+use arch::context::{context_switch, Context, ContextFile, FileResource};
 
-use fs::{ResourceSeek, Url};
+use collections::string::{String, ToString};
 
-use schemes::pipe::{PipeRead, PipeWrite};
+use common::time::Duration;
 
-use system::c_string_to_str;
+use core::{mem, slice};
+use core::ops::DerefMut;
 
-use syscall::{Stat, SEEK_CUR, SEEK_END, SEEK_SET};
+use fs::{FileLock, ResourceSeek, Url};
 
-use system::error::{Error, Result, EBADF, EFAULT, EINVAL};
+use schemes::pipe::{Pipe, PipeRead, PipeWrite};
+
+use sync::Intex;
+
+use syscall::{Dirent, PollFd, Stat, Termios, Winsize, FD_CLOEXEC, F_DUPFD, F_GETFD, F_GETFL, F_SETFD, F_SETFL,
+              LOCK_EX, LOCK_NB, LOCK_SH, LOCK_UN, O_CLOEXEC, O_EXLOCK, O_NONBLOCK, O_SHLOCK, O_WRONLY, POLLERR,
+              POLLHUP, POLLIN, POLLNVAL, POLLOUT, SEEK_CUR, SEEK_END, SEEK_SET, SIGPIPE, TCGETS, TCSETS, TIOCGPGRP,
+              TIOCGWINSZ, TIOCSPGRP, TIOCSWINSZ};
+
+use system::error::{Error, Result, EAGAIN, EBADF, EINTR, EINVAL, ENOENT, ENOTTY, EPIPE};
+
+use super::validate::{copy_to_user, user_read, user_slice, user_slice_mut, user_str, user_vec, user_write,
+                      validate_user_slice};
+
+/// A file of the current context, which a call uses with the contexts unlocked
+struct OpenFile {
+    /// The resource, held so that closing the file meanwhile does not free it
+    resource: Arc<FileResource>,
+    /// The flags of the file when it was found
+    flags: usize,
+    lock: Arc<Intex<Option<FileLock>>>,
+}
+
+/// The file `fd` of the current context, found with a read lock that is dropped before the file is
+/// used, as using it may block
+fn current_file(fd: usize) -> Result<OpenFile> {
+    let contexts = ::env().contexts.read();
+    let current = try!(contexts.current());
+    let file = try!(current.get_context_file_mut(fd));
+    Ok(OpenFile {
+        resource: file.resource.clone(),
+        flags: file.flags,
+        lock: file.lock.clone(),
+    })
+}
+
+/// The resource, URL, flags and lock of the file `fd` of the current context, to duplicate it,
+/// checking that one more file may be opened
+fn dup_source(fd: usize) -> Result<(Arc<FileResource>, String, usize, Arc<Intex<Option<FileLock>>>)> {
+    let contexts = ::env().contexts.read();
+    let current = try!(contexts.current());
+    try!(current.check_files(1));
+    let file = try!(current.get_context_file_mut(fd));
+    Ok((file.resource.clone(), file.url.clone(), file.flags, file.lock.clone()))
+}
+
+/// Canonicalize `path` in the working directory of the current context
+fn current_path(path: *const u8) -> Result<String> {
+    let contexts = ::env().contexts.read();
+    let current = try!(contexts.current());
+    Ok(current.canonicalize(&try!(user_str(path))))
+}
 
 pub fn do_sys_chdir(path: *const u8) -> Result<usize> {
-    let contexts = ::env().contexts.lock();
+    let contexts = ::env().contexts.read();
     let current = try!(contexts.current());
     unsafe {
-        *current.cwd.get() = current.canonicalize(c_string_to_str(path));
+        *current.cwd.get() = current.canonicalize(&try!(user_str(path)));
     }
     Ok(0)
 }
 
 pub fn do_sys_close(fd: usize) -> Result<usize> {
-    let contexts = ::env().contexts.lock();
-    let current = try!(contexts.current());
+    let file = {
+        let contexts = ::env().contexts.read();
+        let current = try!(contexts.current());
 
-    //debugln!("{}: {}: close {}", current.pid, current.name, fd);
+        //debugln!("{}: {}: close {}", current.pid, current.name, fd);
 
-    for i in 0..unsafe { (*current.files.get()).len() } {
-        let mut remove = false;
-        if let Some(file) = unsafe { (*current.files.get()).get(i) } {
-            if file.fd == fd {
-                remove = true;
-            }
+        let files = unsafe { &mut *current.files.get() };
+        match files.iter().position(|file| file.fd == fd) {
+            Some(i) => files.remove(i),
+            None => return Err(Error::new(EBADF)),
         }
+    };
 
-        if remove {
-            if i < unsafe { (*current.files.get()).len() } {
-                drop(unsafe { (*current.files.get()).remove(i) });
+    // Closing may wait for the scheme, so the file is dropped after the contexts are unlocked
+    drop(file);
+    Ok(0)
+}
 
-                return Ok(0);
-            }
+pub fn do_sys_dup(fd: usize) -> Result<usize> {
+    let (resource, url, flags, lock) = try!(dup_source(fd));
+    let new_resource = try!(resource.get().dup());
+
+    let contexts = ::env().contexts.read();
+    let current = try!(contexts.current());
+
+    //debugln!("{}: {}: dup {}", current.pid, current.name, fd);
+
+    let new_fd = try!(current.add_file(new_resource, url, flags));
+    try!(current.get_context_file_mut(new_fd)).lock = lock;
+    Ok(new_fd)
+}
+
+/// Duplicate a file, or get or change its flags
+pub fn do_sys_fcntl(fd: usize, cmd: usize, arg: usize) -> Result<usize> {
+    if cmd == F_DUPFD {
+        if arg as u64 >= try!(::env().contexts.read().current()).files_limit.rlim_cur {
+            return Err(Error::new(EINVAL));
+        }
+        let (resource, url, flags, lock) = try!(dup_source(fd));
+        let new_resource = try!(resource.get().dup());
+
+        let contexts = ::env().contexts.read();
+        let current = try!(contexts.current());
+        try!(current.check_files(1));
+        let new_fd = current.next_fd_from(arg);
+        let mut new_file = ContextFile::new(new_fd, new_resource, url, flags);
+        new_file.lock = lock;
+        unsafe {
+            (*current.files.get()).push(new_file);
         }
+        current.check_leak();
+        return Ok(new_fd);
     }
 
-    Err(Error::new(EBADF))
-}
+    if cmd == F_SETFL {
+        // The resource may call its scheme, which is not done with the contexts locked
+        let file = try!(current_file(fd));
+        let flags = file.flags & ! O_NONBLOCK | arg & O_NONBLOCK;
+        try!(file.resource.get().fcntl(F_SETFL, flags));
 
-pub fn do_sys_dup(fd: usize) -> Result<usize> {
-    let contexts = ::env().contexts.lock();
+        let contexts = ::env().contexts.read();
+        let current = try!(contexts.current());
+        try!(current.get_context_file_mut(fd)).flags = flags;
+        return Ok(0);
+    }
+
+    let contexts = ::env().contexts.read();
     let current = try!(contexts.current());
-    let resource = try!(current.get_file(fd));
-    let new_resource = try!(resource.dup());
-    let new_fd = current.next_fd();
+    let file = try!(current.get_context_file_mut(fd));
+    match cmd {
+        F_GETFD => Ok(file.fd_flags),
+        F_SETFD => {
+            file.fd_flags = arg & FD_CLOEXEC;
+            Ok(0)
+        },
+        F_GETFL => Ok(file.flags),
+        _ => Err(Error::new(EINVAL)),
+    }
+}
 
-    //debugln!("{}: {}: dup {} as {}", current.pid, current.name, fd, new_fd);
+/// Take or release the advisory lock of a file, shared by its duplicates
+///
+/// A file holds one lock, so taking a lock replaces the one it has, which is released first.
+pub fn do_sys_flock(fd: usize, operation: usize) -> Result<usize> {
+    let file = try!(current_file(fd));
+    let exclusive = match operation & ! LOCK_NB {
+        LOCK_SH => false,
+        LOCK_EX => true,
+        LOCK_UN => {
+            let lock = file.lock.lock().take();
+            drop(lock);
+            return Ok(0);
+        },
+        _ => return Err(Error::new(EINVAL)),
+    };
+
+    let lock = file.lock.lock().take();
+    drop(lock);
+
+    let new_lock = try!(lock_file(&file.resource, exclusive, operation & LOCK_NB != LOCK_NB));
+    *file.lock.lock() = Some(new_lock);
+    Ok(0)
+}
 
-    unsafe {
-        (*current.files.get()).push(ContextFile {
-            fd: new_fd,
-            resource: new_resource,
-        });
-    }
-    Ok(new_fd)
+/// Lock the node under `resource`, waiting for it if `block` is set
+fn lock_file(resource: &FileResource, exclusive: bool, block: bool) -> Result<FileLock> {
+    let mut buf = [0; 4096];
+    let count = try!(resource.get().node_path(&mut buf));
+    let node = String::from_utf8_lossy(&buf[..count]).into_owned();
+    ::env().locks.lock(node, exclusive, block)
 }
 
 pub fn do_sys_fpath(fd: usize, buf: *mut u8, count: usize) -> Result<usize> {
-    let contexts = ::env().contexts.lock();
-    let current = try!(contexts.current());
-    let resource = try!(current.get_file(fd));
-    resource.path(unsafe { slice::from_raw_parts_mut(buf, count) })
+    let file = try!(current_file(fd));
+    file.resource.get().path(try!(user_slice_mut(buf, count)))
 }
 
 pub fn do_sys_fstat(fd: usize, stat: *mut Stat) -> Result<usize> {
-    let contexts = ::env().contexts.lock();
-    let current = try!(contexts.current());
-    let resource = try!(current.get_file(fd));
-    if stat as usize > 0 {
-        resource.stat(unsafe { &mut *stat })
-    } else {
-        Err(Error::new(EFAULT))
-    }
+    let file = try!(current_file(fd));
+    let mut stat_buf = Stat::default();
+    let result = try!(file.resource.get().stat(&mut stat_buf));
+    try!(user_write(stat, &stat_buf));
+    Ok(result)
 }
 
 pub fn do_sys_fsync(fd: usize) -> Result<usize> {
-    let mut contexts = ::env().contexts.lock();
-    let mut current = try!(contexts.current_mut());
-    let mut resource = try!(current.get_file_mut(fd));
-    resource.sync().and(Ok(0))
+    let file = try!(current_file(fd));
+    file.resource.get().sync().and(Ok(0))
 }
 
 pub fn do_sys_ftruncate(fd: usize, len: usize) -> Result<usize> {
-    let mut contexts = ::env().contexts.lock();
-    let mut current = try!(contexts.current_mut());
-    let mut resource = try!(current.get_file_mut(fd));
-    resource.truncate(len).and(Ok(0))
+    let file = try!(current_file(fd));
+    file.resource.get().truncate(len).and(Ok(0))
 }
 
-//TODO: Link
+pub fn do_sys_getdents(fd: usize, buf: *mut Dirent, count: usize) -> Result<usize> {
+    let size = try!(count.checked_mul(mem::size_of::<Dirent>()).ok_or(Error::new(EINVAL)));
+    try!(validate_user_slice(buf as usize, size, true));
+
+    let file = try!(current_file(fd));
+    file.resource.get().getdents(unsafe { slice::from_raw_parts_mut(buf, count) })
+}
+
+pub fn do_sys_ioctl(fd: usize, request: usize, arg: *mut u8) -> Result<usize> {
+    let size = match request {
+        TCGETS | TCSETS => mem::size_of::<Termios>(),
+        TIOCGPGRP | TIOCSPGRP => mem::size_of::<usize>(),
+        TIOCGWINSZ | TIOCSWINSZ => mem::size_of::<Winsize>(),
+        _ => return Err(Error::new(ENOTTY)),
+    };
+
+    let file = try!(current_file(fd));
+    let mut buf = try!(user_vec(arg, size));
+    let result = try!(file.resource.get().ioctl(request, &mut buf));
+    try!(copy_to_user(arg, &buf));
+    Ok(result)
+}
+
+/// Create `new` as another name of the file `old`
+pub fn do_sys_link(old: *const u8, new: *const u8) -> Result<usize> {
+    let old_string = try!(current_path(old));
+    let new_string = try!(current_path(new));
+    ::env().link(try!(Url::from_str(&old_string)), try!(Url::from_str(&new_string))).and(Ok(0))
+}
 
 pub fn do_sys_lseek(fd: usize, offset: isize, whence: usize) -> Result<usize> {
-    let mut contexts = ::env().contexts.lock();
-    let mut current = try!(contexts.current_mut());
-    let mut resource = try!(current.get_file_mut(fd));
+    let file = try!(current_file(fd));
     match whence {
-        SEEK_SET => resource.seek(ResourceSeek::Start(offset as usize)),
-        SEEK_CUR => resource.seek(ResourceSeek::Current(offset)),
-        SEEK_END => resource.seek(ResourceSeek::End(offset)),
+        SEEK_SET => file.resource.get().seek(ResourceSeek::Start(offset as usize)),
+        SEEK_CUR => file.resource.get().seek(ResourceSeek::Current(offset)),
+        SEEK_END => file.resource.get().seek(ResourceSeek::End(offset)),
         _ => Err(Error::new(EINVAL)),
     }
 }
 
 pub fn do_sys_mkdir(path: *const u8, flags: usize) -> Result<usize> {
-    let contexts = ::env().contexts.lock();
-    let current = try!(contexts.current());
-    let path_string = current.canonicalize(c_string_to_str(path));
+    let path_string = try!(current_path(path));
     ::env().mkdir(try!(Url::from_str(&path_string)), flags).and(Ok(0))
 }
 
 pub fn do_sys_open(path: *const u8, flags: usize) -> Result<usize> {
-    let contexts = ::env().contexts.lock();
-    let current = try!(contexts.current());
-    let path = current.canonicalize(c_string_to_str(path));
+    let path = try!(current_path(path));
     let url = try!(Url::from_str(&path));
+    try!(try!(::env().contexts.read().current()).check_files(1));
     let resource = try!(::env().open(url, flags));
-    let fd = current.next_fd();
-
-    //debugln!("{}: {}: open {} as {}", current.pid, current.name, url.string, fd);
 
-    unsafe {
-        (*current.files.get()).push(ContextFile {
-            fd: fd,
-            resource: resource,
-        });
+    let (fd, file) = {
+        let contexts = ::env().contexts.read();
+        let current = try!(contexts.current());
+
+        //debugln!("{}: {}: open {}", current.pid, current.name, path);
+
+        let fd = try!(current.add_file(resource, path.clone(), flags));
+        let file = try!(current.get_context_file_mut(fd));
+        (fd, OpenFile {
+            resource: file.resource.clone(),
+            flags: file.flags,
+            lock: file.lock.clone(),
+        })
+    };
+
+    // The lock may be waited for, which is not done with the contexts locked
+    if flags & (O_SHLOCK | O_EXLOCK) != 0 {
+        match lock_file(&file.resource, flags & O_EXLOCK == O_EXLOCK, flags & O_NONBLOCK != O_NONBLOCK) {
+            Ok(lock) => *file.lock.lock() = Some(lock),
+            Err(err) => {
+                let _ = do_sys_close(fd);
+                return Err(err);
+            },
+        }
     }
     Ok(fd)
 }
 
-pub fn do_sys_pipe2(fds: *mut usize, _flags: usize) -> Result<usize> {
-    let contexts = ::env().contexts.lock();
-    let current = try!(contexts.current());
-    if fds as usize > 0 {
-        let read = box PipeRead::new();
-        let write = box PipeWrite::new(&read);
+pub fn do_sys_pipe2(fds: *mut [usize; 2], flags: usize) -> Result<usize> {
+    // Check before adding the files, which would be left open if writing the numbers failed
+    try!(validate_user_slice(fds as usize, mem::size_of::<[usize; 2]>(), true));
 
+    let mut new_fds = [0; 2];
+    {
+        let contexts = ::env().contexts.read();
+        let current = try!(contexts.current());
+        try!(current.check_files(2));
+
+        let pipe = Pipe::new(String::new());
+        let read = box PipeRead::new(pipe.clone());
+        let write = box PipeWrite::new(pipe);
+
+        let flags = flags & (O_NONBLOCK | O_CLOEXEC);
         unsafe {
-            *fds.offset(0) = current.next_fd();
-            (*current.files.get()).push(ContextFile {
-                fd: *fds.offset(0),
-                resource: read,
-            });
-
-            *fds.offset(1) = current.next_fd();
-            (*current.files.get()).push(ContextFile {
-                fd: *fds.offset(1),
-                resource: write,
-            });
+            new_fds[0] = current.next_fd();
+            (*current.files.get()).push(ContextFile::new(new_fds[0], read, "pipe:".to_string(), flags));
+
+            new_fds[1] = current.next_fd();
+            (*current.files.get()).push(ContextFile::new(new_fds[1], write, "pipe:".to_string(), O_WRONLY | flags));
         }
+        current.check_leak();
+    }
 
-        Ok(0)
+    try!(user_write(fds, &new_fds));
+    Ok(0)
+}
+
+/// Wait up to `timeout` milliseconds, or forever if it is negative, until one of the `count` files
+/// at `fds` is ready, returning the number that are
+pub fn do_sys_poll(fds: *mut PollFd, count: usize, timeout: isize) -> Result<usize> {
+    let size = try!(count.checked_mul(mem::size_of::<PollFd>()).ok_or(Error::new(EINVAL)));
+    try!(validate_user_slice(fds as usize, size, true));
+
+    let deadline = if timeout >= 0 {
+        Some(Duration::monotonic() + Duration::new((timeout / 1000) as i64, (timeout % 1000) as i32 * 1000000))
     } else {
-        Err(Error::new(EFAULT))
+        None
+    };
+
+    loop {
+        {
+            let mut contexts = ::env().contexts.write();
+            let mut current = try!(contexts.current_mut());
+            let context = current.deref_mut() as *mut Context;
+
+            // Woken by a timeout, or by a notify, which already removed it
+            ::env().pollers.remove(context);
+
+            let mut ready = 0;
+            for i in 0..count {
+                let ptr = unsafe { fds.offset(i as isize) };
+                let mut fd = try!(user_read(ptr));
+                fd.revents = match current.get_file(fd.fd) {
+                    Ok(resource) => resource.poll().unwrap_or(POLLERR) & (fd.events | POLLERR | POLLHUP),
+                    Err(_) => POLLNVAL,
+                };
+                if fd.revents != 0 {
+                    ready += 1;
+                }
+                try!(user_write(ptr, &fd));
+            }
+
+            if ready > 0 || deadline.map_or(false, |deadline| deadline <= Duration::monotonic()) {
+                return Ok(ready);
+            }
+
+            if current.pending_signals() != 0 {
+                return Err(Error::new(EINTR));
+            }
+
+            // Interrupts are disabled from the check until here, so a resource cannot become
+            // ready before this context is woken by it
+            ::env().pollers.add(context);
+            current.blocked = true;
+            current.wake = deadline;
+        }
+
+        unsafe { context_switch(); }
+    }
+}
+
+/// Fail with `EAGAIN` if `file` is non-blocking and neither `events` nor an end of file or error
+/// is ready, as using it would block
+fn check_nonblock(file: &OpenFile, events: usize) -> Result<()> {
+    if file.flags & O_NONBLOCK == O_NONBLOCK {
+        // An error is returned by the call itself
+        let ready = file.resource.get().poll().unwrap_or(POLLERR);
+        if ready & (events | POLLERR | POLLHUP) == 0 {
+            return Err(Error::new(EAGAIN));
+        }
     }
+    Ok(())
 }
 
 pub fn do_sys_read(fd: usize, buf: *mut u8, count: usize) -> Result<usize> {
-    let mut contexts = ::env().contexts.lock();
-    let mut current = try!(contexts.current_mut());
-    let mut resource = try!(current.get_file_mut(fd));
-    resource.read(unsafe { slice::from_raw_parts_mut(buf, count) })
+    let file = try!(current_file(fd));
+    try!(check_nonblock(&file, POLLIN));
+    file.resource.get().read(try!(user_slice_mut(buf, count)))
+}
+
+/// Read the target of the symbolic link at `path`, which is not terminated, into `buf`
+pub fn do_sys_readlink(path: *const u8, buf: *mut u8, count: usize) -> Result<usize> {
+    let path_string = try!(current_path(path));
+    ::env().readlink(try!(Url::from_str(&path_string)), try!(user_slice_mut(buf, count)))
+}
+
+pub fn do_sys_rename(old: *const u8, new: *const u8) -> Result<usize> {
+    let old_string = try!(current_path(old));
+    let new_string = try!(current_path(new));
+    ::env().rename(try!(Url::from_str(&old_string)), try!(Url::from_str(&new_string))).and(Ok(0))
 }
 
 pub fn do_sys_rmdir(path: *const u8) -> Result<usize> {
-    let contexts = ::env().contexts.lock();
-    let current = try!(contexts.current());
-    let path_string = current.canonicalize(c_string_to_str(path));
+    let path_string = try!(current_path(path));
     ::env().rmdir(try!(Url::from_str(&path_string))).and(Ok(0))
 }
 
 pub fn do_sys_stat(path: *const u8, stat: *mut Stat) -> Result<usize> {
-    let contexts = ::env().contexts.lock();
-    let current = try!(contexts.current());
-    let path = current.canonicalize(c_string_to_str(path));
+    let path = try!(current_path(path));
     let url = try!(Url::from_str(&path));
-    if stat as usize > 0 {
-        ::env().stat(url, unsafe { &mut *stat }).and(Ok(0))
-    } else {
-        Err(Error::new(EFAULT))
+    let mut stat_buf = Stat::default();
+    try!(::env().stat(url, &mut stat_buf));
+    try!(user_write(stat, &stat_buf));
+    Ok(0)
+}
+
+/// Create a symbolic link at `path` to `target`, which is stored as it is given, so that a relative
+/// target is relative to the directory of the link
+pub fn do_sys_symlink(target: *const u8, path: *const u8) -> Result<usize> {
+    let target = try!(user_str(target));
+    if target.is_empty() {
+        return Err(Error::new(ENOENT));
     }
+    let path_string = try!(current_path(path));
+    ::env().symlink(&target, try!(Url::from_str(&path_string))).and(Ok(0))
 }
 
 pub fn do_sys_unlink(path: *const u8) -> Result<usize> {
-    let contexts = ::env().contexts.lock();
-    let current = try!(contexts.current());
-    let path_string = current.canonicalize(c_string_to_str(path));
+    let path_string = try!(current_path(path));
     ::env().unlink(try!(Url::from_str(&path_string))).and(Ok(0))
 }
 
 pub fn do_sys_write(fd: usize, buf: *const u8, count: usize) -> Result<usize> {
-    let mut contexts = ::env().contexts.lock();
-    let mut current = try!(contexts.current_mut());
-    let mut resource = try!(current.get_file_mut(fd));
-    resource.write(unsafe { slice::from_raw_parts(buf, count) })
+    let result = {
+        let file = try!(current_file(fd));
+        try!(check_nonblock(&file, POLLOUT));
+        file.resource.get().write(try!(user_slice(buf, count)))
+    };
+
+    // Writing to a pipe with no readers terminates the writer unless it handles `SIGPIPE`
+    if let Err(ref err) = result {
+        if err.errno == EPIPE {
+            try!(::env().contexts.write().current_mut()).signal(SIGPIPE);
+        }
+    }
+
+    result
 }
@@ -1,14 +1,19 @@
-use arch::context::ContextMemory;
+use arch::context::{ContextMemory, CONTEXT_STACK_ADDR, USER_START};
 use arch::memory;
 
-use system::error::Result;
+use core::cmp;
+
+use system::error::{Error, Result, EACCES, EEXIST, EINVAL, ENOMEM};
+use system::syscall::{Mmap, MAP_ANONYMOUS, MAP_FIXED, MAP_PRIVATE, MAP_SHARED, O_RDWR, PROT_WRITE};
+
+use super::validate::user_read;
 
 //TODO: Refactor file to propogate results
 
 pub fn do_sys_brk(addr: usize) -> Result<usize> {
     let mut ret = 0;
 
-    let mut contexts = ::env().contexts.lock();
+    let mut contexts = ::env().contexts.write();
     if let Ok(mut current) = contexts.current_mut() {
         unsafe {
             current.unmap();
@@ -18,16 +23,22 @@ pub fn do_sys_brk(addr: usize) -> Result<usize> {
 
         // TODO: Make this smarter, currently it attempt to resize the entire data segment
         if let Some(mut mem) = unsafe { (*current.memory.get()).last_mut() } {
-            if mem.writeable && mem.allocated {
+            // Memory shared after fork is copied first, as resizing may move it
+            if mem.writeable && unsafe { mem.unshare() }.is_ok() && mem.allocated {
                 if addr >= mem.virtual_address {
                     let size = addr - mem.virtual_address;
+                    ::env().grants.lock().revoke(mem.physical_address, mem.virtual_size);
                     let physical_address = unsafe { memory::realloc(mem.physical_address, size) };
                     if physical_address > 0 {
                         mem.physical_address = physical_address;
                         mem.virtual_size = size;
                         ret = mem.virtual_address + mem.virtual_size;
-                    } else {
+                    } else if size == 0 {
+                        // Freed by realloc, so it must not be freed again when dropped
+                        mem.allocated = false;
                         mem.virtual_size = 0;
+                        ret = mem.virtual_address;
+                    } else {
                         debug!("BRK: Realloc failed {:X}, {}\n", mem.virtual_address, size);
                     }
                 }
@@ -52,7 +63,7 @@ pub fn do_sys_brk(addr: usize) -> Result<usize> {
 pub fn do_sys_alloc(size: usize) -> Result<usize> {
     let mut ret = 0;
 
-    let contexts = ::env().contexts.lock();
+    let contexts = ::env().contexts.read();
     if let Ok(current) = contexts.current() {
         let physical_address = unsafe { memory::alloc(size) };
         if physical_address > 0 {
@@ -64,6 +75,8 @@ pub fn do_sys_alloc(size: usize) -> Result<usize> {
                 virtual_size: size,
                 writeable: true,
                 allocated: true,
+                shared: None,
+                object: None,
             };
 
             //debugln!("{}: {}: allocate {:X}:{:X}", current.pid, current.name, mem.virtual_address, mem.virtual_address + mem.virtual_size);
@@ -81,20 +94,29 @@ pub fn do_sys_alloc(size: usize) -> Result<usize> {
 pub fn do_sys_realloc(ptr: usize, size: usize) -> Result<usize> {
     let mut ret = 0;
 
-    let mut contexts = ::env().contexts.lock();
+    let mut contexts = ::env().contexts.write();
     if let Ok(mut current) = contexts.current_mut() {
         if let Ok(mut mem) = current.get_mem_mut(ptr) {
             unsafe { mem.unmap(); }
 
             //debug!("{}: {}: reallocate {:X}:{:X}", current.pid, current.name, mem.virtual_address, mem.virtual_address + mem.virtual_size);
 
-            let physical_address = unsafe { memory::realloc(mem.physical_address, size) };
-            if physical_address > 0 {
-                mem.physical_address = physical_address;
-                mem.virtual_size = size;
-                ret = mem.virtual_address;
-            } else {
-                mem.virtual_size = 0;
+            // Memory shared after fork is copied first, as reallocating frees it. A memory
+            // object is not reallocated, as other mappings use it
+            if mem.object.is_none() && unsafe { mem.unshare() }.is_ok() {
+                if mem.allocated {
+                    ::env().grants.lock().revoke(mem.physical_address, mem.virtual_size);
+                }
+                let physical_address = unsafe { memory::realloc(mem.physical_address, size) };
+                if physical_address > 0 {
+                    mem.physical_address = physical_address;
+                    mem.virtual_size = size;
+                    ret = mem.virtual_address;
+                } else if size == 0 {
+                    // Freed by realloc, so it must not be freed again when dropped
+                    mem.allocated = false;
+                    mem.virtual_size = 0;
+                }
             }
 
             //debugln!(" to {:X}:{:X}", mem.virtual_address, mem.virtual_address + mem.virtual_size);
@@ -110,15 +132,20 @@ pub fn do_sys_realloc(ptr: usize, size: usize) -> Result<usize> {
 pub fn do_sys_realloc_inplace(ptr: usize, size: usize) -> Result<usize> {
     let mut ret = 0;
 
-    let mut contexts = ::env().contexts.lock();
+    let mut contexts = ::env().contexts.write();
     if let Ok(mut current) = contexts.current_mut() {
         if let Ok(mut mem) = current.get_mem_mut(ptr) {
             unsafe { mem.unmap(); }
 
             //debug!("{}: {}: reallocate {:X}:{:X}", current.pid, current.name, mem.virtual_address, mem.virtual_address + mem.virtual_size);
 
-            mem.virtual_size = unsafe { memory::realloc_inplace(mem.physical_address, size) };
-            ret = mem.virtual_size;
+            if mem.object.is_none() {
+                if mem.allocated {
+                    ::env().grants.lock().revoke(mem.physical_address, mem.virtual_size);
+                }
+                mem.virtual_size = unsafe { memory::realloc_inplace(mem.physical_address, size) };
+                ret = mem.virtual_size;
+            }
 
             //debugln!(" to {:X}:{:X}", mem.virtual_address, mem.virtual_address + mem.virtual_size);
 
@@ -130,8 +157,162 @@ pub fn do_sys_realloc_inplace(ptr: usize, size: usize) -> Result<usize> {
     Ok(ret)
 }
 
+pub fn do_sys_fmap(fd: usize) -> Result<usize> {
+    let mut contexts = ::env().contexts.write();
+    let mut current = try!(contexts.current_mut());
+    let (physical_address, size, object) = {
+        let resource = try!(current.get_file_mut(fd));
+        match resource.map_object() {
+            Some(object) => (object.physical_address(), object.size(), Some(object)),
+            None => {
+                let (physical_address, size) = try!(resource.map());
+                (physical_address, size, None)
+            },
+        }
+    };
+
+    let virtual_address = current.next_mem();
+    let mut mem = ContextMemory {
+        physical_address: physical_address,
+        virtual_address: virtual_address,
+        virtual_size: size,
+        writeable: true,
+        // The memory belongs to the resource, or to the object
+        allocated: false,
+        shared: None,
+        object: object,
+    };
+
+    unsafe {
+        mem.map();
+        (*current.memory.get()).push(mem);
+    }
+
+    Ok(virtual_address)
+}
+
+/// Map zeroed memory, the memory of a file, or a copy of it, into the current context
+///
+/// Without `MAP_FIXED` the address is chosen above every other mapping, and never below
+/// `USER_START`. Pages cannot be made unreadable or not executable, so only `PROT_WRITE` has an
+/// effect.
+pub fn do_sys_mmap(args: *const Mmap) -> Result<usize> {
+    let args = try!(user_read(args));
+    if args.size == 0 || args.offset % 4096 != 0 {
+        return Err(Error::new(EINVAL));
+    }
+    let size = try!(args.size.checked_add(4095).ok_or(Error::new(EINVAL))) / 4096 * 4096;
+    let writeable = args.prot & PROT_WRITE == PROT_WRITE;
+
+    let mut contexts = ::env().contexts.write();
+    let mut current = try!(contexts.current_mut());
+
+    let virtual_address = if args.flags & MAP_FIXED == MAP_FIXED {
+        let end = try!(args.addr.checked_add(size).ok_or(Error::new(EINVAL)));
+        // Mapping over the kernel would replace its pages for every context
+        if args.addr < USER_START || args.addr % 4096 != 0 || end > CONTEXT_STACK_ADDR {
+            return Err(Error::new(EINVAL));
+        }
+
+        let overlaps = |mem: &ContextMemory| {
+            let mem_end = mem.virtual_address + (mem.virtual_size + 4095) / 4096 * 4096;
+            mem.virtual_size > 0 && args.addr < mem_end && mem.virtual_address < end
+        };
+        if current.stack.as_ref().map_or(false, &overlaps)
+           || unsafe { (*current.memory.get()).iter() }.any(&overlaps) {
+            return Err(Error::new(EEXIST));
+        }
+
+        args.addr
+    } else {
+        cmp::max(current.next_mem(), USER_START)
+    };
+
+    let (physical_address, allocated, object) = if args.flags & MAP_ANONYMOUS == MAP_ANONYMOUS {
+        let physical_address = unsafe { memory::alloc(size) };
+        if physical_address == 0 {
+            return Err(Error::new(ENOMEM));
+        }
+        (physical_address, true, None)
+    } else {
+        let file = try!(current.get_context_file_mut(args.fd));
+        let object = file.resource.get().map_object();
+        let (file_address, file_size) = match object {
+            Some(ref object) => (object.physical_address(), object.size()),
+            None => try!(file.resource.get().map()),
+        };
+        if args.offset >= file_size {
+            return Err(Error::new(EINVAL));
+        }
+        let file_address = file_address + args.offset;
+        let file_size = file_size - args.offset;
+
+        if args.flags & MAP_SHARED == MAP_SHARED {
+            if writeable && file.flags & O_RDWR != O_RDWR {
+                return Err(Error::new(EACCES));
+            }
+            if size > (file_size + 4095) / 4096 * 4096 {
+                return Err(Error::new(EINVAL));
+            }
+            // The memory belongs to the resource, or to the object
+            (file_address, false, object)
+        } else if args.flags & MAP_PRIVATE == MAP_PRIVATE {
+            let physical_address = unsafe { memory::alloc(size) };
+            if physical_address == 0 {
+                return Err(Error::new(ENOMEM));
+            }
+            unsafe {
+                ::memcpy(physical_address as *mut u8, file_address as *const u8, cmp::min(size, file_size));
+            }
+            (physical_address, true, None)
+        } else {
+            return Err(Error::new(EINVAL));
+        }
+    };
+
+    let mut mem = ContextMemory {
+        physical_address: physical_address,
+        virtual_address: virtual_address,
+        virtual_size: size,
+        writeable: writeable,
+        allocated: allocated,
+        shared: None,
+        object: object,
+    };
+
+    //debugln!("{}: {}: mmap {:X}:{:X}", current.pid, current.name, mem.virtual_address, mem.virtual_address + mem.virtual_size);
+
+    unsafe {
+        mem.map();
+        (*current.memory.get()).push(mem);
+    }
+
+    Ok(virtual_address)
+}
+
+/// Unmap a whole mapping of the current context, which cannot be split
+pub fn do_sys_munmap(addr: usize, size: usize) -> Result<usize> {
+    let mut contexts = ::env().contexts.write();
+    let current = try!(contexts.current_mut());
+    let memory = unsafe { &mut *current.memory.get() };
+    match memory.iter().position(|mem| mem.virtual_address == addr && mem.virtual_size > 0) {
+        Some(i) => {
+            if (size + 4095) / 4096 != (memory[i].virtual_size + 4095) / 4096 {
+                return Err(Error::new(EINVAL));
+            }
+
+            //debugln!("{}: {}: munmap {:X}:{:X}", current.pid, current.name, addr, addr + size);
+
+            let mut mem = memory.remove(i);
+            unsafe { mem.unmap() };
+            Ok(0)
+        },
+        None => Err(Error::new(EINVAL)),
+    }
+}
+
 pub fn do_sys_unalloc(ptr: usize) -> Result<usize> {
-    let mut contexts = ::env().contexts.lock();
+    let mut contexts = ::env().contexts.write();
     if let Ok(mut current) = contexts.current_mut() {
         if let Ok(mut mem) = current.get_mem_mut(ptr) {
             unsafe { mem.unmap() };
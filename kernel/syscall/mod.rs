@@ -5,20 +5,57 @@ pub use self::debug::*;
 pub use self::file::*;
 pub use self::memory::*;
 pub use self::process::*;
+pub use self::signal::*;
 pub use self::time::*;
+pub use self::validate::*;
 
 use arch::regs::Regs;
 
+use common::random::rdtsc;
+use common::trace::TracePoint;
+
+use env::fuzz::FUZZ_REFUSED;
+
 pub mod debug;
 pub mod execute;
 pub mod file;
 pub mod memory;
 pub mod process;
+pub mod signal;
+pub mod strace;
 pub mod time;
+pub mod validate;
 
 pub fn syscall_handle(regs: &mut Regs) {
     //debugln!("{:X}: {} {:X} {:X} {:X}", regs.ip, regs.ax, regs.bx, regs.cx, regs.dx);
+    let number = regs.ax;
+    let start = rdtsc();
+    tracepoint!(TracePoint::SyscallEnter, number, regs.bx);
+    ::env().stats.lock().syscall(number);
+
+    let (pid, tracer) = match ::env().contexts.read().current() {
+        Ok(current) => (Some(current.pid), current.tracer.clone()),
+        Err(_) => (None, None),
+    };
+    let fuzzing = pid.map_or(false, |pid| ::env().fuzz.lock().fuzzing(pid));
+    let call = if let Some(ref tracer) = tracer {
+        let call = strace::decode_call(regs);
+        // These do not return on success
+        if number == SYS_EXIT || number == SYS_EXECVE {
+            tracer.send(format!("{} = ?\n", call));
+        }
+        Some(call)
+    } else {
+        None
+    };
+
     regs.ax = Error::mux(match regs.ax {
+        // A fuzzer must survive its own calls
+        _ if fuzzing && FUZZ_REFUSED.contains(&number) => {
+            ::env().fuzz.lock().refused += 1;
+            Err(Error::new(EPERM))
+        },
+
         SYS_DEBUG => do_sys_debug(regs.bx as *const u8, regs.cx),
 
         // Rust Memory
@@ -26,30 +63,68 @@ pub fn syscall_handle(regs: &mut Regs) {
         SYS_REALLOC => do_sys_realloc(regs.bx, regs.cx),
         SYS_REALLOC_INPLACE => do_sys_realloc_inplace(regs.bx, regs.cx),
         SYS_UNALLOC => do_sys_unalloc(regs.bx),
+        SYS_FMAP => do_sys_fmap(regs.bx),
+
+        // Redox Security
+        SYS_DROP_PRIV => do_sys_drop_priv(regs.bx),
+        SYS_ENTER_NAMESPACE => do_sys_enter_namespace(regs.bx as *const *const u8),
+
+        // Redox Time
+        SYS_ADJTIME => do_sys_adjtime(regs.bx as *const TimeSpec, regs.cx as *mut TimeSpec),
+
+        // Redox System
+        SYS_KEXEC => do_sys_kexec(regs.bx as *const u8, regs.cx as *const u8, regs.dx as *const u8),
 
         // Linux
+        SYS_ALARM => do_sys_alarm(regs.bx),
         SYS_BRK => do_sys_brk(regs.bx),
         SYS_CHDIR => do_sys_chdir(regs.bx as *const u8),
         SYS_CLONE => do_sys_clone(regs),
         SYS_CLOSE => do_sys_close(regs.bx),
         SYS_CLOCK_GETTIME => do_sys_clock_gettime(regs.bx, regs.cx as *mut TimeSpec),
         SYS_DUP => do_sys_dup(regs.bx),
-        SYS_EXECVE => do_sys_execve(regs.bx as *const u8, regs.cx as *const *const u8),
+        SYS_EXECVE => do_sys_execve(regs.bx as *const u8, regs.cx as *const *const u8, regs.dx as *const *const u8),
         SYS_EXIT => do_sys_exit(regs.bx),
+        SYS_FCNTL => do_sys_fcntl(regs.bx, regs.cx, regs.dx),
+        SYS_FLOCK => do_sys_flock(regs.bx, regs.cx),
         SYS_FPATH => do_sys_fpath(regs.bx, regs.cx as *mut u8, regs.dx),
         SYS_FSTAT => do_sys_fstat(regs.bx, regs.cx as *mut Stat),
         SYS_FSYNC => do_sys_fsync(regs.bx),
         SYS_FTRUNCATE => do_sys_ftruncate(regs.bx, regs.cx),
+        SYS_GETDENTS => do_sys_getdents(regs.bx, regs.cx as *mut Dirent, regs.dx),
+        SYS_GETEGID => do_sys_getegid(),
+        SYS_GETEUID => do_sys_geteuid(),
+        SYS_GETGID => do_sys_getgid(),
+        SYS_GETPGID => do_sys_getpgid(regs.bx),
         SYS_GETPID => do_sys_getpid(),
-        // TODO: link
+        SYS_GETRLIMIT => do_sys_getrlimit(regs.bx, regs.cx as *mut Rlimit),
+        SYS_GETUID => do_sys_getuid(),
+        SYS_IOCTL => do_sys_ioctl(regs.bx, regs.cx, regs.dx as *mut u8),
+        SYS_KILL => do_sys_kill(regs.bx as isize, regs.cx),
+        SYS_LINK => do_sys_link(regs.bx as *const u8, regs.cx as *const u8),
         SYS_LSEEK => do_sys_lseek(regs.bx, regs.cx as isize, regs.dx),
         SYS_MKDIR => do_sys_mkdir(regs.bx as *const u8, regs.cx),
+        SYS_MMAP => do_sys_mmap(regs.bx as *const Mmap),
+        SYS_MUNMAP => do_sys_munmap(regs.bx, regs.cx),
         SYS_NANOSLEEP => do_sys_nanosleep(regs.bx as *const TimeSpec, regs.cx as *mut TimeSpec),
         SYS_OPEN => do_sys_open(regs.bx as *const u8, regs.cx), //regs.cx as isize, regs.dx as isize),
-        SYS_PIPE2 => do_sys_pipe2(regs.bx as *mut usize, regs.cx),
+        SYS_PIPE2 => do_sys_pipe2(regs.bx as *mut [usize; 2], regs.cx),
+        SYS_POLL => do_sys_poll(regs.bx as *mut PollFd, regs.cx, regs.dx as isize),
         SYS_READ => do_sys_read(regs.bx, regs.cx as *mut u8, regs.dx),
+        SYS_READLINK => do_sys_readlink(regs.bx as *const u8, regs.cx as *mut u8, regs.dx),
+        SYS_RENAME => do_sys_rename(regs.bx as *const u8, regs.cx as *const u8),
         SYS_RMDIR => do_sys_rmdir(regs.bx as *const u8),
+        SYS_SETGID => do_sys_setgid(regs.bx),
+        SYS_SETITIMER => do_sys_setitimer(regs.bx, regs.cx as *const ITimerSpec, regs.dx as *mut ITimerSpec),
+        SYS_SETPGID => do_sys_setpgid(regs.bx, regs.cx),
+        SYS_SETPRIORITY => do_sys_setpriority(regs.bx, regs.cx, regs.dx as isize),
+        SYS_SETRLIMIT => do_sys_setrlimit(regs.bx, regs.cx as *const Rlimit),
+        SYS_SETUID => do_sys_setuid(regs.bx),
+        SYS_SIGACTION => do_sys_sigaction(regs.bx, regs.cx as *const SigAction, regs.dx as *mut SigAction),
+        SYS_SIGPROCMASK => do_sys_sigprocmask(regs.bx, regs.cx as *const usize, regs.dx as *mut usize),
+        SYS_SIGRETURN => do_sys_sigreturn(regs),
         SYS_STAT => do_sys_stat(regs.bx as *const u8, regs.cx as *mut Stat),
+        SYS_SYMLINK => do_sys_symlink(regs.bx as *const u8, regs.cx as *const u8),
         SYS_UNLINK => do_sys_unlink(regs.bx as *const u8),
         SYS_WAITPID => do_sys_waitpid(regs.bx as isize, regs.cx as *mut usize, regs.dx),
         SYS_WRITE => do_sys_write(regs.bx, regs.cx as *mut u8, regs.dx),
@@ -57,5 +132,16 @@ pub fn syscall_handle(regs: &mut Regs) {
 
         _ => Err(Error::new(ENOSYS)),
     });
+    tracepoint!(TracePoint::SyscallExit, number, regs.ax);
+    ::env().stats.lock().syscall_latency(number, rdtsc().wrapping_sub(start));
+    if fuzzing {
+        ::env().fuzz.lock().record(number, regs.ax);
+    }
+
+    if let (Some(tracer), Some(call)) = (tracer, call) {
+        tracer.send(format!("{} = {}\n", call, strace::decode_result(regs.ax)));
+    }
+
+    handle_signals(regs);
     //debugln!("={:X}", regs.ax);
 }
@@ -1,62 +1,199 @@
-use arch::context::{context_clone, context_switch};
+use arch::context::{context_clone, context_switch, priority_level, PRIORITY_MAX, PRIORITY_MIN};
+use arch::kexec::Kexec;
 use arch::regs::Regs;
 
-use collections::{BTreeMap, Vec};
-use collections::string::ToString;
+use collections::Vec;
+use collections::string::String;
 
-use core::{mem, ptr};
-use core::ops::DerefMut;
+use core::{cmp, u32};
 
-use system::{c_array_to_slice, c_string_to_str};
+use env::audit::AuditKind;
+use env::log::LogLevel;
 
-use system::error::{Error, Result, ECHILD};
+use fs::Url;
 
-use super::execute::execute;
+use system::error::{Error, Result, ECHILD, EINTR, EINVAL, ENOEXEC, EPERM, ESRCH};
+use system::syscall::{Rlimit, PRIO_PGRP, PRIO_PROCESS, PRIO_USER, PRIV_ALL, PRIV_SETUID, RLIMIT_CORE, RLIMIT_NOFILE, SIGCHLD,
+                      SIGKILL, SIG_IGN, WNOHANG};
+
+use super::execute::{execute, read_all};
+use super::validate::{user_read, user_str, user_str_array, user_write};
 
 pub fn do_sys_clone(regs: &Regs) -> Result<usize> {
     unsafe { context_clone(regs) }
 }
 
-pub fn do_sys_execve(path: *const u8, args: *const *const u8) -> Result<usize> {
+/// Permanently drop privileges from the current context
+pub fn do_sys_drop_priv(privs: usize) -> Result<usize> {
+    if privs & !PRIV_ALL != 0 {
+        return Err(Error::new(EINVAL));
+    }
+
+    let remaining = {
+        let mut contexts = ::env().contexts.write();
+        let mut current = try!(contexts.current_mut());
+        current.privs &= !privs;
+        current.privs
+    };
+
+    ::env().audit(AuditKind::PrivilegeChange, format!("dropped {:X}, remaining {:X}", privs, remaining));
+
+    Ok(remaining)
+}
+
+/// Restrict the current context to the schemes in `schemes`, a null terminated array of names
+///
+/// A namespace can only shrink, naming a scheme outside the current one fails with `EPERM`.
+pub fn do_sys_enter_namespace(schemes: *const *const u8) -> Result<usize> {
+    let schemes = try!(user_str_array(schemes));
+
+    {
+        let mut contexts = ::env().contexts.write();
+        let mut current = try!(contexts.current_mut());
+        if schemes.iter().any(|scheme| ! current.in_namespace(scheme)) {
+            return Err(Error::new(EPERM));
+        }
+        current.namespace = Some(schemes.clone());
+    }
+
+    ::env().audit(AuditKind::PrivilegeChange, format!("entered namespace {:?}", schemes));
+
+    Ok(0)
+}
+
+/// Execute a program. The environment is replaced with `envp`, or kept if it is null
+pub fn do_sys_execve(path: *const u8, args: *const *const u8, envp: *const *const u8) -> Result<usize> {
     let mut args_vec = Vec::new();
-    args_vec.push(c_string_to_str(path).to_string());
-    for arg in c_array_to_slice(args) {
-        args_vec.push(c_string_to_str(*arg).to_string());
+    args_vec.push(try!(user_str(path)));
+    for arg in try!(user_str_array(args)) {
+        args_vec.push(arg);
     }
 
-    execute(args_vec)
+    let env = if envp.is_null() {
+        None
+    } else {
+        Some(try!(user_str_array(envp)))
+    };
+
+    execute(args_vec, env)
+}
+
+/// Read the file at `path`, relative to the working directory of the current context
+fn read_file(path: &str) -> Result<Vec<u8>> {
+    let path = try!(::env().contexts.read().current()).canonicalize(path);
+    let mut resource = try!(try!(Url::from_str(&path)).open());
+    read_all(&mut resource)
+}
+
+/// Boot the kernel file at `kernel` in place of this one, with the optional initial ramdisk at
+/// `initrd` and command line `cmdline`
+pub fn do_sys_kexec(kernel: *const u8, initrd: *const u8, cmdline: *const u8) -> Result<usize> {
+    if try!(::env().contexts.read().current()).euid != 0 {
+        return Err(Error::new(EPERM));
+    }
+
+    let kernel = try!(user_str(kernel));
+    let image = try!(read_file(&kernel));
+    let initrd = if initrd.is_null() {
+        None
+    } else {
+        Some(try!(read_file(&try!(user_str(initrd)))))
+    };
+    let cmdline = if cmdline.is_null() {
+        String::new()
+    } else {
+        try!(user_str(cmdline))
+    };
+
+    match Kexec::new(image, initrd, cmdline.clone()) {
+        Ok(kexec) => {
+            klogln!(LogLevel::Info, "kexec: booting {} with '{}'", kernel, cmdline);
+            unsafe { kexec.boot() }
+        },
+        Err(msg) => {
+            klogln!(LogLevel::Warning, "kexec: failed to load {}: {}", kernel, msg);
+            Err(Error::new(ENOEXEC))
+        }
+    }
 }
 
 /// Exit context
 ///
+/// The memory and files are freed at once, but the context stays a zombie holding `status`
+/// until its parent collects it with `waitpid`. It is reaped immediately if it has no parent,
+/// or the parent ignores `SIGCHLD`. Its children are adopted by init.
+///
 /// Unsafe due to interrupt disabling and raw pointers
 pub fn do_sys_exit(status: usize) -> ! {
     {
-        let mut contexts = ::env().contexts.lock();
+        let mut contexts = ::env().contexts.write();
+        let init_pid = contexts.init_pid;
 
-        let mut statuses = BTreeMap::new();
-        let (pid, ppid) = {
+        let (pid, ppid, tgid) = {
             if let Ok(mut current) = contexts.current_mut() {
                 current.exited = true;
-                mem::swap(&mut statuses, &mut current.statuses.inner.lock().deref_mut());
-                (current.pid, current.ppid)
+                unsafe { current.release(); }
+                (current.pid, current.ppid, current.tgid)
             } else {
-                (0, 0)
+                (0, 0, 0)
             }
         };
 
+        // The children of init are orphaned for good, and reaped as they exit
+        let adopter = if pid == init_pid {
+            0
+        } else {
+            init_pid
+        };
+
+        let mut collected = false;
+        let mut adopted_zombie = false;
         for mut context in contexts.iter_mut() {
-            // Add exit status to parent
-            if context.pid == ppid {
-                context.statuses.send(pid, status);
-                for (pid, status) in statuses.iter() {
-                    context.statuses.send(*pid, *status);
+            if context.pid == ppid && ! context.exited {
+                // A thread is joined by waiting for it, without a signal to its parent
+                if tgid == pid {
+                    collected = context.sigactions[SIGCHLD].sa_handler != SIG_IGN;
+                    context.signal(SIGCHLD);
+                } else {
+                    collected = true;
+                }
+                if context.waiting {
+                    context.waiting = false;
+                    context.blocked = false;
+                }
+            }
+
+            // The other threads of the process end with its main thread
+            if tgid == pid && context.tgid == pid && context.pid != pid && ! context.exited {
+                context.signal(SIGKILL);
+            }
+
+            // Move children to init
+            if context.ppid == pid && context.pid != pid {
+                context.ppid = adopter;
+                if context.status.is_some() {
+                    if adopter == 0 {
+                        context.status = None;
+                    } else {
+                        adopted_zombie = true;
+                    }
                 }
             }
+        }
+
+        // Init may be waiting, and can now reap the zombies it adopted
+        if adopted_zombie {
+            if let Some(init) = contexts.iter_mut().find(|context| context.pid == adopter) {
+                if init.waiting {
+                    init.waiting = false;
+                    init.blocked = false;
+                }
+            }
+        }
 
-            // Move children to parent
-            if context.ppid == pid {
-                context.ppid = ppid;
+        if collected {
+            if let Ok(mut current) = contexts.current_mut() {
+                current.status = Some(status);
             }
         }
     }
@@ -68,29 +205,310 @@ pub fn do_sys_exit(status: usize) -> ! {
     }
 }
 
+pub fn do_sys_getrlimit(resource: usize, rlimit: *mut Rlimit) -> Result<usize> {
+    let limit = {
+        let contexts = ::env().contexts.read();
+        let current = try!(contexts.current());
+        match resource {
+            RLIMIT_CORE => current.core_limit,
+            RLIMIT_NOFILE => current.files_limit,
+            _ => return Err(Error::new(EINVAL)),
+        }
+    };
+
+    // Writing may copy memory shared after fork, which takes the contexts to write
+    try!(user_write(rlimit, &limit));
+    Ok(0)
+}
+
+/// Set a resource limit of the current context, the maximum can be lowered but not raised
+pub fn do_sys_setrlimit(resource: usize, rlimit: *const Rlimit) -> Result<usize> {
+    let rlimit = try!(user_read(rlimit));
+    if rlimit.rlim_cur > rlimit.rlim_max {
+        return Err(Error::new(EINVAL));
+    }
+
+    let mut contexts = ::env().contexts.write();
+    let mut current = try!(contexts.current_mut());
+    match resource {
+        RLIMIT_CORE => {
+            if rlimit.rlim_max > current.core_limit.rlim_max {
+                return Err(Error::new(EPERM));
+            }
+            current.core_limit = rlimit;
+        },
+        RLIMIT_NOFILE => {
+            if rlimit.rlim_max > current.files_limit.rlim_max {
+                return Err(Error::new(EPERM));
+            }
+            current.files_limit = rlimit;
+        },
+        _ => return Err(Error::new(EINVAL)),
+    }
+
+    Ok(0)
+}
+
+pub fn do_sys_getpgid(pid: usize) -> Result<usize> {
+    let contexts = ::env().contexts.read();
+    let current = try!(contexts.current());
+    if pid == 0 || pid == current.pid {
+        Ok(current.pgid)
+    } else {
+        let context = try!(contexts.iter().find(|context| context.pid == pid).ok_or(Error::new(ESRCH)));
+        Ok(context.pgid)
+    }
+}
+
 pub fn do_sys_getpid() -> Result<usize> {
-    let contexts = ::env().contexts.lock();
+    let contexts = ::env().contexts.read();
     let current = try!(contexts.current());
     Ok(current.pid)
 }
 
-//TODO: Finish implementation, add more functions to WaitMap so that matching any or using WNOHANG works
-pub fn do_sys_waitpid(pid: isize, status_ptr: *mut usize, _options: usize) -> Result<usize> {
-    let mut contexts = ::env().contexts.lock();
-    let current = try!(contexts.current_mut());
+pub fn do_sys_getuid() -> Result<usize> {
+    let contexts = ::env().contexts.read();
+    let current = try!(contexts.current());
+    Ok(current.uid)
+}
+
+pub fn do_sys_geteuid() -> Result<usize> {
+    let contexts = ::env().contexts.read();
+    let current = try!(contexts.current());
+    Ok(current.euid)
+}
+
+pub fn do_sys_getgid() -> Result<usize> {
+    let contexts = ::env().contexts.read();
+    let current = try!(contexts.current());
+    Ok(current.gid)
+}
+
+pub fn do_sys_getegid() -> Result<usize> {
+    let contexts = ::env().contexts.read();
+    let current = try!(contexts.current());
+    Ok(current.egid)
+}
+
+/// Set the user IDs of the current context
+///
+/// Root with `PRIV_SETUID` sets both the real and effective IDs, which gives up root for good if
+/// `uid` is not 0. Anyone else may only set the effective ID back to the real one. IDs are 32
+/// bits, as scheme packets carry them.
+pub fn do_sys_setuid(uid: usize) -> Result<usize> {
+    if uid > u32::MAX as usize {
+        return Err(Error::new(EINVAL));
+    }
+
+    {
+        let mut contexts = ::env().contexts.write();
+        let mut current = try!(contexts.current_mut());
+        if current.euid == 0 && current.has_priv(PRIV_SETUID) {
+            current.uid = uid;
+            current.euid = uid;
+        } else if uid == current.uid {
+            current.euid = uid;
+        } else {
+            return Err(Error::new(EPERM));
+        }
+    }
+
+    ::env().audit(AuditKind::PrivilegeChange, format!("set user ID {}", uid));
+
+    Ok(0)
+}
+
+/// Set the group IDs of the current context, with the same rules as `setuid`
+pub fn do_sys_setgid(gid: usize) -> Result<usize> {
+    if gid > u32::MAX as usize {
+        return Err(Error::new(EINVAL));
+    }
+
+    {
+        let mut contexts = ::env().contexts.write();
+        let mut current = try!(contexts.current_mut());
+        if current.euid == 0 && current.has_priv(PRIV_SETUID) {
+            current.gid = gid;
+            current.egid = gid;
+        } else if gid == current.gid {
+            current.egid = gid;
+        } else {
+            return Err(Error::new(EPERM));
+        }
+    }
+
+    ::env().audit(AuditKind::PrivilegeChange, format!("set group ID {}", gid));
+
+    Ok(0)
+}
+
+/// Move the current context or one of its children into a process group, a `pgid` of 0 uses
+/// the PID of the moved context
+///
+/// Without sessions, only contexts of the same user may be moved, into their own group or one
+/// whose contexts are of that user too, see `ContextManager::group_permitted`. Root may use any
+/// group.
+pub fn do_sys_setpgid(pid: usize, pgid: usize) -> Result<usize> {
+    let mut contexts = ::env().contexts.write();
+    let (current_pid, uid, euid) = {
+        let current = try!(contexts.current());
+        (current.pid, current.uid, current.euid)
+    };
+    let pid = if pid == 0 {
+        current_pid
+    } else {
+        pid
+    };
+    let pgid = if pgid == 0 {
+        pid
+    } else {
+        pgid
+    };
+
+    {
+        let context = try!(contexts.iter().find(|context| context.pid == pid).ok_or(Error::new(ESRCH)));
+        if context.pid != current_pid && context.ppid != current_pid {
+            return Err(Error::new(ESRCH));
+        }
+        if euid != 0 && uid != context.uid && euid != context.uid {
+            return Err(Error::new(EPERM));
+        }
+    }
+    if pgid != pid && ! contexts.group_permitted(pgid, uid, euid) {
+        return Err(Error::new(EPERM));
+    }
+
+    let context = try!(contexts.iter_mut().find(|context| context.pid == pid).ok_or(Error::new(ESRCH)));
+    context.pgid = pgid;
+
+    Ok(0)
+}
+
+/// Set the nice value of a context, a process group, or the contexts of a user, selected by
+/// `which` and `who` like `kill` selects its targets
+///
+/// The value is clamped to the valid range, and each context moves to the run level of its new
+/// priority. Only root may raise a priority, or change that of contexts of other users.
+pub fn do_sys_setpriority(which: usize, who: usize, priority: isize) -> Result<usize> {
+    let priority = cmp::max(PRIORITY_MIN, cmp::min(PRIORITY_MAX, priority));
+
+    let mut contexts = ::env().contexts.write();
+    let (pid, pgid, uid, euid) = {
+        let current = try!(contexts.current());
+        (current.pid, current.pgid, current.uid, current.euid)
+    };
+
+    let who = if who > 0 {
+        who
+    } else {
+        match which {
+            PRIO_PROCESS => pid,
+            PRIO_PGRP => pgid,
+            PRIO_USER => uid,
+            _ => return Err(Error::new(EINVAL)),
+        }
+    };
 
-    if pid > 0 {
-        let status = current.statuses.receive(&(pid as usize));
+    let mut found = false;
+    let mut permitted = true;
+    for context in contexts.iter_mut() {
+        let target = match which {
+            PRIO_PROCESS => context.pid == who,
+            PRIO_PGRP => context.pgid == who,
+            PRIO_USER => context.uid == who,
+            _ => return Err(Error::new(EINVAL)),
+        };
 
-        if status_ptr as usize > 0 {
-            unsafe {
-                ptr::write(status_ptr, status);
+        if target && ! context.exited {
+            found = true;
+            if euid == 0 || (euid == context.uid && priority >= context.priority) {
+                context.priority = priority;
+                context.level = priority_level(priority);
+            } else {
+                permitted = false;
             }
         }
+    }
 
-        Ok(pid as usize)
+    if ! found {
+        Err(Error::new(ESRCH))
+    } else if ! permitted {
+        Err(Error::new(EPERM))
     } else {
-        Err(Error::new(ECHILD))
+        Ok(0)
+    }
+}
+
+/// Wait for a child to exit, and reap it by collecting its exit status
+///
+/// A `pid` above 0 waits for that child, -1 for any child, 0 for any child in the process group
+/// of the caller, and below -1 for any child in the process group `-pid`. With `WNOHANG`, 0 is
+/// returned instead of blocking while none of them has exited.
+pub fn do_sys_waitpid(pid: isize, status_ptr: *mut usize, options: usize) -> Result<usize> {
+    if options & ! WNOHANG != 0 {
+        return Err(Error::new(EINVAL));
+    }
+
+    loop {
+        {
+            let mut contexts = ::env().contexts.write();
+            let (current_pid, pgid) = {
+                let mut current = try!(contexts.current_mut());
+                current.waiting = false;
+                (current.pid, current.pgid)
+            };
+
+            let mut running = false;
+            let mut zombie = None;
+            for context in contexts.iter_mut() {
+                let target = if pid > 0 {
+                    context.pid == pid as usize
+                } else if pid == 0 {
+                    context.pgid == pgid
+                } else if pid == -1 {
+                    true
+                } else {
+                    context.pgid == pid.wrapping_neg() as usize
+                };
+
+                if target && context.ppid == current_pid && context.pid != current_pid {
+                    if let Some(status) = context.status {
+                        zombie = Some((context.pid, status));
+                        break;
+                    } else if ! context.exited {
+                        running = true;
+                    }
+                }
+            }
+
+            if let Some((child_pid, status)) = zombie {
+                // The status is only taken once it is written, so that the child can still be
+                // waited for if the pointer is bad
+                if ! status_ptr.is_null() {
+                    try!(user_write(status_ptr, &status));
+                }
+                if let Some(context) = contexts.iter_mut().find(|context| context.pid == child_pid) {
+                    context.status = None;
+                }
+                return Ok(child_pid);
+            }
+
+            if ! running {
+                return Err(Error::new(ECHILD));
+            } else if options & WNOHANG == WNOHANG {
+                return Ok(0);
+            }
+
+            // Woken by the exit of a child, or by a signal
+            let mut current = try!(contexts.current_mut());
+            if current.pending_signals() != 0 {
+                return Err(Error::new(EINTR));
+            }
+            current.waiting = true;
+            current.blocked = true;
+        }
+
+        unsafe { context_switch(); }
     }
 }
 
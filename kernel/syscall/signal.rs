@@ -0,0 +1,292 @@
+use arch::coredump;
+use arch::fault;
+use arch::regs::Regs;
+
+use core::mem;
+
+use env::audit::AuditKind;
+
+use system::error::{Error, Result, EINVAL, EPERM, ESRCH};
+use system::syscall::{SigAction, NSIG, SA_NODEFER, SA_RESETHAND, SIGABRT, SIGBUS, SIGCHLD, SIGCONT, SIGFPE, SIGILL,
+                      SIGKILL, SIGQUIT, SIGSEGV, SIGSTOP, SIGTRAP, SIGTSTP, SIGWINCH, SIG_BLOCK, SIG_DFL, SIG_IGN,
+                      SIG_SETMASK, SIG_UNBLOCK, SYS_SIGRETURN};
+
+use super::process::do_sys_exit;
+use super::validate::{user_read, user_write};
+
+/// The signals that cannot be caught, blocked or ignored
+const UNCATCHABLE: usize = 1 << SIGKILL | 1 << SIGSTOP;
+
+/// The flags that a signal handler may change, the rest are restored from before it was called
+const USER_FLAGS: usize = 0x40DD5;
+
+/// Pushed on the user stack to call a signal handler, and read back by `sigreturn`
+#[derive(Copy, Clone)]
+#[repr(packed)]
+struct SignalFrame {
+    /// The return address of the handler, which is `code`
+    ret: usize,
+    /// The argument of the handler on x86, x86_64 passes it in a register
+    signal: usize,
+    /// The blocked signals to restore
+    mask: usize,
+    /// The registers to restore
+    regs: Regs,
+    /// `mov eax, SYS_SIGRETURN; int 0x80`, which works in either mode
+    code: [u8; 8],
+}
+
+/// Does the default action of a signal write a core file
+fn core_signal(signal: usize) -> bool {
+    match signal {
+        SIGQUIT | SIGILL | SIGTRAP | SIGABRT | SIGBUS | SIGFPE | SIGSEGV => true,
+        _ => false,
+    }
+}
+
+/// Is a signal ignored by default. Stopping is not supported, so the stop signals are as well
+fn default_ignored(signal: usize) -> bool {
+    match signal {
+        SIGCHLD | SIGCONT | SIGWINCH | SIGTSTP | SIGSTOP => true,
+        _ => false,
+    }
+}
+
+/// Is a signal discarded instead of being made pending, with the action `action`
+pub fn signal_ignored(signal: usize, action: &SigAction) -> bool {
+    action.sa_handler == SIG_IGN || (action.sa_handler == SIG_DFL && default_ignored(signal))
+}
+
+/// Terminate the current context with `128 + signal` as the exit status, after writing a core
+/// file if the signal calls for one
+pub fn signal_exit(signal: usize, regs: &Regs) -> ! {
+    if core_signal(signal) {
+        coredump::dump(regs, signal);
+    }
+    do_sys_exit(128 + signal);
+}
+
+/// Push a frame to call `handler` on the user stack, so that the context runs it when it returns
+/// to user mode
+fn signal_deliver(regs: &mut Regs, signal: usize, handler: usize, mask: usize) -> Result<()> {
+    let frame_size = mem::size_of::<SignalFrame>();
+    // Below the red zone, and aligned as after a call
+    let sp = try!(regs.sp.checked_sub(128 + frame_size).ok_or(Error::new(EINVAL)));
+    let sp = sp / 16 * 16 - mem::size_of::<usize>();
+
+    let frame = SignalFrame {
+        ret: sp + frame_size - 8,
+        signal: signal,
+        mask: mask,
+        regs: *regs,
+        code: [0xB8, SYS_SIGRETURN as u8, 0, 0, 0, 0xCD, 0x80, 0x90],
+    };
+    try!(user_write(sp as *mut SignalFrame, &frame));
+
+    regs.sp = sp;
+    regs.ip = handler;
+    regs.di = signal;
+    Ok(())
+}
+
+/// Act on the pending signals of the current context that are not blocked, `regs` are its user
+/// registers
+///
+/// A signal with a handler is delivered by pushing a frame that calls it, one at a time, and
+/// the context is killed with `SIGSEGV` if the frame does not fit. Otherwise the default action
+/// applies, which terminates the context unless the signal is ignored by default.
+pub fn handle_signals(regs: &mut Regs) {
+    loop {
+        let (signal, handler) = {
+            let mut contexts = ::env().contexts.write();
+            let mut current = match contexts.current_mut() {
+                Ok(current) => current,
+                Err(_) => return,
+            };
+
+            let pending = current.pending_signals();
+            if pending == 0 {
+                return;
+            }
+            let signal = pending.trailing_zeros() as usize;
+            let action = current.sigactions[signal];
+            // A handler can only be called on the way back to user mode
+            if action.sa_handler > SIG_IGN && ! fault::user_mode(regs) {
+                return;
+            }
+            current.signals &= ! (1 << signal);
+
+            if action.sa_handler > SIG_IGN {
+                if action.sa_flags & SA_RESETHAND == SA_RESETHAND {
+                    current.sigactions[signal] = SigAction::default();
+                }
+                let mask = current.sigmask;
+                current.sigmask |= action.sa_mask & ! UNCATCHABLE;
+                if action.sa_flags & SA_NODEFER != SA_NODEFER {
+                    current.sigmask |= 1 << signal;
+                }
+                (signal, Some((action.sa_handler, mask)))
+            } else if signal_ignored(signal, &action) {
+                continue;
+            } else {
+                (signal, None)
+            }
+        };
+
+        match handler {
+            Some((handler, mask)) => {
+                if signal_deliver(regs, signal, handler, mask).is_err() {
+                    signal_exit(SIGSEGV, regs);
+                }
+                return;
+            },
+            None => signal_exit(signal, regs),
+        }
+    }
+}
+
+/// Send a signal to the context `pid`, to the process group `-pid` if it is negative, or to the
+/// process group of the caller if it is 0
+///
+/// Only root may signal contexts of other users, the real or effective user ID of the caller must
+/// match the real user ID of the target. A signal of 0 checks that a context would receive it,
+/// without sending anything.
+pub fn do_sys_kill(pid: isize, signal: usize) -> Result<usize> {
+    if signal >= NSIG {
+        return Err(Error::new(EINVAL));
+    }
+
+    let (found, permitted) = {
+        let mut contexts = ::env().contexts.write();
+        let (uid, euid, pgid) = {
+            let current = try!(contexts.current());
+            (current.uid, current.euid, current.pgid)
+        };
+
+        let mut found = false;
+        let mut permitted = false;
+        for context in contexts.iter_mut() {
+            let target = if pid > 0 {
+                context.pid == pid as usize
+            } else if pid == 0 {
+                context.pgid == pgid
+            } else {
+                context.pgid == pid.wrapping_neg() as usize
+            };
+
+            if target && ! context.exited {
+                found = true;
+                if euid == 0 || uid == context.uid || euid == context.uid {
+                    permitted = true;
+                    if signal > 0 {
+                        context.signal(signal);
+                    }
+                }
+            }
+        }
+
+        (found, permitted)
+    };
+
+    if ! found {
+        Err(Error::new(ESRCH))
+    } else if ! permitted {
+        Err(Error::new(EPERM))
+    } else {
+        if signal > 0 {
+            // The audit log reads the current context, so the contexts are no longer locked
+            ::env().audit(AuditKind::Kill, format!("sent signal {} to {}", signal, pid));
+        }
+        Ok(0)
+    }
+}
+
+/// Get and set the action for a signal. `SIGKILL` and `SIGSTOP` cannot be changed, and setting
+/// an action that ignores a signal discards it if it is pending
+pub fn do_sys_sigaction(signal: usize, act: *const SigAction, oldact: *mut SigAction) -> Result<usize> {
+    if signal == 0 || signal >= NSIG {
+        return Err(Error::new(EINVAL));
+    }
+
+    let act = if act.is_null() {
+        None
+    } else {
+        if UNCATCHABLE & 1 << signal != 0 {
+            return Err(Error::new(EINVAL));
+        }
+        Some(try!(user_read(act)))
+    };
+
+    let old = {
+        let mut contexts = ::env().contexts.write();
+        let mut current = try!(contexts.current_mut());
+        let old = current.sigactions[signal];
+        if let Some(act) = act {
+            current.sigactions[signal] = act;
+            if signal_ignored(signal, &act) {
+                current.signals &= ! (1 << signal);
+            }
+        }
+        old
+    };
+
+    if ! oldact.is_null() {
+        try!(user_write(oldact, &old));
+    }
+
+    Ok(0)
+}
+
+/// Get and change the blocked signals, `SIGKILL` and `SIGSTOP` cannot be blocked
+pub fn do_sys_sigprocmask(how: usize, set: *const usize, oldset: *mut usize) -> Result<usize> {
+    let set = if set.is_null() {
+        None
+    } else {
+        Some(try!(user_read(set)))
+    };
+
+    let old = {
+        let mut contexts = ::env().contexts.write();
+        let mut current = try!(contexts.current_mut());
+        let old = current.sigmask;
+        if let Some(set) = set {
+            current.sigmask = match how {
+                SIG_BLOCK => old | set,
+                SIG_UNBLOCK => old & ! set,
+                SIG_SETMASK => set,
+                _ => return Err(Error::new(EINVAL)),
+            } & ! UNCATCHABLE;
+        }
+        old
+    };
+
+    if ! oldset.is_null() {
+        try!(user_write(oldset, &old));
+    }
+
+    Ok(0)
+}
+
+/// Return from a signal handler, restoring the registers and blocked signals from the frame
+/// pushed by `signal_deliver`. The segments and privileged flags are kept, as the frame is in
+/// user memory
+pub fn do_sys_sigreturn(regs: &mut Regs) -> Result<usize> {
+    // The handler returned to the code in the frame, popping its return address
+    let frame_ptr = try!(regs.sp.checked_sub(mem::size_of::<usize>()).ok_or(Error::new(EINVAL)));
+    let frame = try!(user_read(frame_ptr as *const SignalFrame));
+
+    {
+        let mut contexts = ::env().contexts.write();
+        let mut current = try!(contexts.current_mut());
+        current.sigmask = frame.mask & ! UNCATCHABLE;
+    }
+
+    let (cs, ss, flags) = (regs.cs, regs.ss, regs.flags);
+    *regs = frame.regs;
+    regs.cs = cs;
+    regs.ss = ss;
+    regs.flags = regs.flags & USER_FLAGS | flags & ! USER_FLAGS;
+
+    // The syscall return value is written to ax
+    Ok(regs.ax)
+}
@@ -0,0 +1,180 @@
+use arch::regs::Regs;
+
+use collections::string::String;
+
+use core::cmp;
+
+use system::error::Error;
+use system::syscall::*;
+
+use super::validate::{user_str, user_vec};
+
+/// The number of bytes of a string or buffer argument that are shown
+const STRACE_STR_MAX: usize = 64;
+
+/// How a syscall argument is decoded
+#[derive(Copy, Clone)]
+enum Arg {
+    /// Decimal integer
+    Int,
+    /// Hexadecimal integer, flags and pointers
+    Hex,
+    /// Null-terminated string
+    Str,
+    /// Buffer, with the length in the next argument
+    Buf,
+    /// No more arguments
+    End,
+}
+
+/// The name and arguments of a syscall
+fn signature(number: usize) -> Option<(&'static str, [Arg; 3])> {
+    use self::Arg::*;
+
+    Some(match number {
+        SYS_DEBUG => ("debug", [Buf, Int, End]),
+
+        SYS_ALLOC => ("alloc", [Int, End, End]),
+        SYS_REALLOC => ("realloc", [Hex, Int, End]),
+        SYS_REALLOC_INPLACE => ("realloc_inplace", [Hex, Int, End]),
+        SYS_UNALLOC => ("unalloc", [Hex, End, End]),
+        SYS_FMAP => ("fmap", [Int, End, End]),
+
+        SYS_DROP_PRIV => ("drop_priv", [Hex, End, End]),
+        SYS_ENTER_NAMESPACE => ("enter_namespace", [Hex, End, End]),
+
+        SYS_ADJTIME => ("adjtime", [Hex, Hex, End]),
+
+        SYS_KEXEC => ("kexec", [Str, Hex, Hex]),
+
+        SYS_ALARM => ("alarm", [Int, End, End]),
+        SYS_BRK => ("brk", [Hex, End, End]),
+        SYS_CHDIR => ("chdir", [Str, End, End]),
+        SYS_CLONE => ("clone", [Hex, Hex, Hex]),
+        SYS_CLOSE => ("close", [Int, End, End]),
+        SYS_CLOCK_GETTIME => ("clock_gettime", [Int, Hex, End]),
+        SYS_DUP => ("dup", [Int, End, End]),
+        SYS_EXECVE => ("execve", [Str, Hex, Hex]),
+        SYS_EXIT => ("exit", [Int, End, End]),
+        SYS_FCNTL => ("fcntl", [Int, Int, Hex]),
+        SYS_FLOCK => ("flock", [Int, Hex, End]),
+        SYS_FPATH => ("fpath", [Int, Hex, Int]),
+        SYS_FSTAT => ("fstat", [Int, Hex, End]),
+        SYS_FSYNC => ("fsync", [Int, End, End]),
+        SYS_FTRUNCATE => ("ftruncate", [Int, Int, End]),
+        SYS_GETDENTS => ("getdents", [Int, Hex, Int]),
+        SYS_GETEGID => ("getegid", [End, End, End]),
+        SYS_GETEUID => ("geteuid", [End, End, End]),
+        SYS_GETGID => ("getgid", [End, End, End]),
+        SYS_GETPGID => ("getpgid", [Int, End, End]),
+        SYS_GETPID => ("getpid", [End, End, End]),
+        SYS_GETRLIMIT => ("getrlimit", [Int, Hex, End]),
+        SYS_GETUID => ("getuid", [End, End, End]),
+        SYS_IOCTL => ("ioctl", [Int, Hex, Hex]),
+        SYS_KILL => ("kill", [Int, Int, End]),
+        SYS_LINK => ("link", [Str, Str, End]),
+        SYS_LSEEK => ("lseek", [Int, Int, Int]),
+        SYS_MKDIR => ("mkdir", [Str, Hex, End]),
+        SYS_MMAP => ("mmap", [Hex, End, End]),
+        SYS_MUNMAP => ("munmap", [Hex, Int, End]),
+        SYS_NANOSLEEP => ("nanosleep", [Hex, Hex, End]),
+        SYS_OPEN => ("open", [Str, Hex, End]),
+        SYS_PIPE2 => ("pipe2", [Hex, Hex, End]),
+        SYS_POLL => ("poll", [Hex, Int, Int]),
+        SYS_READ => ("read", [Int, Hex, Int]),
+        SYS_READLINK => ("readlink", [Str, Hex, Int]),
+        SYS_RENAME => ("rename", [Str, Str, End]),
+        SYS_RMDIR => ("rmdir", [Str, End, End]),
+        SYS_SETGID => ("setgid", [Int, End, End]),
+        SYS_SETITIMER => ("setitimer", [Int, Hex, Hex]),
+        SYS_SETPGID => ("setpgid", [Int, Int, End]),
+        SYS_SETPRIORITY => ("setpriority", [Int, Int, Int]),
+        SYS_SETRLIMIT => ("setrlimit", [Int, Hex, End]),
+        SYS_SETUID => ("setuid", [Int, End, End]),
+        SYS_SIGACTION => ("sigaction", [Int, Hex, Hex]),
+        SYS_SIGPROCMASK => ("sigprocmask", [Int, Hex, Hex]),
+        SYS_SIGRETURN => ("sigreturn", [End, End, End]),
+        SYS_STAT => ("stat", [Str, Hex, End]),
+        SYS_SYMLINK => ("symlink", [Str, Str, End]),
+        SYS_UNLINK => ("unlink", [Str, End, End]),
+        SYS_WAITPID => ("waitpid", [Int, Hex, Hex]),
+        SYS_WRITE => ("write", [Int, Buf, Int]),
+        SYS_YIELD => ("yield", [End, End, End]),
+
+        _ => return None,
+    })
+}
+
+/// The name of a syscall
+pub fn name(number: usize) -> Option<&'static str> {
+    signature(number).map(|(name, _)| name)
+}
+
+/// Quote and escape bytes, truncating long values
+fn quote(bytes: &[u8]) -> String {
+    let mut string = String::from("\"");
+    for &b in bytes[.. cmp::min(bytes.len(), STRACE_STR_MAX)].iter() {
+        match b {
+            b'"' => string.push_str("\\\""),
+            b'\\' => string.push_str("\\\\"),
+            b'\n' => string.push_str("\\n"),
+            b'\r' => string.push_str("\\r"),
+            b'\t' => string.push_str("\\t"),
+            0x20 ... 0x7E => string.push(b as char),
+            _ => string.push_str(&format!("\\x{:02X}", b)),
+        }
+    }
+    string.push('"');
+    if bytes.len() > STRACE_STR_MAX {
+        string.push_str("...");
+    }
+    string
+}
+
+/// Decode a syscall and its arguments from the registers at entry
+pub fn decode_call(regs: &Regs) -> String {
+    let args = [regs.bx, regs.cx, regs.dx];
+
+    if let Some((name, kinds)) = signature(regs.ax) {
+        let mut string = format!("{}(", name);
+        for (i, kind) in kinds.iter().enumerate() {
+            if let Arg::End = *kind {
+                break;
+            }
+
+            if i > 0 {
+                string.push_str(", ");
+            }
+
+            let arg = args[i];
+            match *kind {
+                Arg::Int => string.push_str(&format!("{}", arg as isize)),
+                Arg::Hex => string.push_str(&format!("{:#X}", arg)),
+                Arg::Str => match user_str(arg as *const u8) {
+                    Ok(value) => string.push_str(&quote(value.as_bytes())),
+                    Err(_) => string.push_str(&format!("{:#X}", arg)),
+                },
+                Arg::Buf => {
+                    let len = args.get(i + 1).map_or(0, |len| cmp::min(*len, STRACE_STR_MAX + 1));
+                    match user_vec(arg as *const u8, len) {
+                        Ok(value) => string.push_str(&quote(&value)),
+                        Err(_) => string.push_str(&format!("{:#X}", arg)),
+                    }
+                }
+                Arg::End => (),
+            }
+        }
+        string.push(')');
+        string
+    } else {
+        format!("syscall_{}({:#X}, {:#X}, {:#X})", regs.ax, args[0], args[1], args[2])
+    }
+}
+
+/// Decode a muxed syscall result
+pub fn decode_result(value: usize) -> String {
+    match Error::demux(value) {
+        Ok(value) => format!("{}", value),
+        Err(err) => format!("-1 {} ({})", err.errno, err.text()),
+    }
+}
@@ -1,60 +1,169 @@
-use arch::context::context_switch;
+use arch::context::{context_switch, ITimer};
 
-use common::time::Duration;
+use common::time::{Duration, NANOS_PER_SEC};
 
-use syscall::{CLOCK_MONOTONIC, CLOCK_REALTIME, TimeSpec};
+use env::clock::PIT_DURATION;
 
-use system::error::{Error, Result, EFAULT, EINVAL};
+use syscall::{CLOCK_MONOTONIC, CLOCK_REALTIME, ITIMER_REAL, ITimerSpec, TimeSpec};
 
-pub fn do_sys_clock_gettime(clock: usize, tp: *mut TimeSpec) -> Result<usize> {
-    if tp as usize > 0 {
-        match clock {
-            CLOCK_REALTIME => {
-                let clock_realtime = ::env().clock_realtime.lock();
-                unsafe {
-                    (*tp).tv_sec = clock_realtime.secs;
-                    (*tp).tv_nsec = clock_realtime.nanos;
-                }
-                Ok(0)
-            }
-            CLOCK_MONOTONIC => {
-                let clock_monotonic = ::env().clock_monotonic.lock();
-                unsafe {
-                    (*tp).tv_sec = clock_monotonic.secs;
-                    (*tp).tv_nsec = clock_monotonic.nanos;
-                }
-                Ok(0)
-            }
-            _ => Err(Error::new(EINVAL)),
+use system::error::{Error, Result, EINVAL, EPERM};
+
+use super::validate::{user_read, user_write};
+
+/// The time until the timer next expires, and its period
+fn itimer_remaining(itimer: Option<ITimer>) -> (Duration, Duration) {
+    match itimer {
+        Some(itimer) => {
+            let now = Duration::monotonic();
+            // Expired timers are only noticed on the next tick
+            let remaining = if itimer.deadline > now {
+                itimer.deadline - now
+            } else {
+                Duration::new(0, 0)
+            };
+            (remaining, itimer.interval.unwrap_or(Duration::new(0, 0)))
+        },
+        None => (Duration::new(0, 0), Duration::new(0, 0)),
+    }
+}
+
+/// Slew the realtime clock, limited to root
+pub fn do_sys_adjtime(delta: *const TimeSpec, old: *mut TimeSpec) -> Result<usize> {
+    let delta = try!(user_read(delta));
+
+    {
+        let contexts = ::env().contexts.read();
+        let current = try!(contexts.current());
+        if current.euid != 0 {
+            return Err(Error::new(EPERM));
         }
+    }
+
+    let nanos = delta.tv_sec * NANOS_PER_SEC as i64 + delta.tv_nsec as i64;
+    let remaining = ::env().clock.lock().adjust(nanos);
+
+    if old as usize > 0 {
+        try!(user_write(old, &TimeSpec {
+            tv_sec: remaining / NANOS_PER_SEC as i64,
+            tv_nsec: (remaining % NANOS_PER_SEC as i64) as i32,
+        }));
+    }
+
+    Ok(0)
+}
+
+pub fn do_sys_alarm(seconds: usize) -> Result<usize> {
+    let mut contexts = ::env().contexts.write();
+    let mut current = try!(contexts.current_mut());
+
+    let (remaining, _) = itimer_remaining(current.itimer);
+
+    current.itimer = if seconds > 0 {
+        let now = Duration::monotonic();
+        let deadline = now + Duration::new(seconds as i64, 0);
+        ::env().timers.lock().add(current.pid, deadline, now);
+        Some(ITimer {
+            deadline: deadline,
+            interval: None,
+        })
+    } else {
+        ::env().timers.lock().remove(current.pid);
+        None
+    };
+
+    // Round up, so that a pending alarm is never reported as zero
+    if remaining.nanos > 0 {
+        Ok(remaining.secs as usize + 1)
     } else {
-        Err(Error::new(EFAULT))
+        Ok(remaining.secs as usize)
     }
 }
 
+pub fn do_sys_clock_gettime(clock: usize, tp: *mut TimeSpec) -> Result<usize> {
+    let time = match clock {
+        CLOCK_REALTIME => Duration::realtime(),
+        CLOCK_MONOTONIC => Duration::monotonic(),
+        _ => return Err(Error::new(EINVAL)),
+    };
+    try!(user_write(tp, &TimeSpec {
+        tv_sec: time.secs,
+        tv_nsec: time.nanos,
+    }));
+    Ok(0)
+}
+
 pub fn do_sys_nanosleep(req: *const TimeSpec, rem: *mut TimeSpec) -> Result<usize> {
-    if req as usize > 0 {
-        let mut contexts = ::env().contexts.lock();
+    let req = try!(user_read(req));
+
+    {
+        let mut contexts = ::env().contexts.write();
         let mut context = try!(contexts.current_mut());
 
         context.blocked = true;
-        context.wake = Some(
-            Duration::monotonic() + Duration::new(unsafe { (*req).tv_sec }, unsafe { (*req).tv_nsec })
-        );
-
-        unsafe { context_switch(); }
-
-        if rem as usize > 0 {
-            unsafe {
-                (*rem).tv_sec = 0;
-            }
-            unsafe {
-                (*rem).tv_nsec = 0;
-            }
+        context.wake = Some(Duration::monotonic() + Duration::new(req.tv_sec, req.tv_nsec));
+    }
+
+    unsafe { context_switch(); }
+
+    if rem as usize > 0 {
+        try!(user_write(rem, &TimeSpec::default()));
+    }
+
+    Ok(0)
+}
+
+pub fn do_sys_setitimer(which: usize, new: *const ITimerSpec, old: *mut ITimerSpec) -> Result<usize> {
+    if which != ITIMER_REAL {
+        return Err(Error::new(EINVAL));
+    }
+
+    let new = try!(user_read(new));
+    for time in [new.it_value, new.it_interval].iter() {
+        if time.tv_sec < 0 || time.tv_nsec < 0 || time.tv_nsec >= NANOS_PER_SEC {
+            return Err(Error::new(EINVAL));
         }
+    }
 
-        Ok(0)
-    } else {
-        Err(Error::new(EFAULT))
+    let mut contexts = ::env().contexts.write();
+    let mut current = try!(contexts.current_mut());
+
+    if old as usize > 0 {
+        let (remaining, interval) = itimer_remaining(current.itimer);
+        try!(user_write(old, &ITimerSpec {
+            it_interval: TimeSpec {
+                tv_sec: interval.secs,
+                tv_nsec: interval.nanos,
+            },
+            it_value: TimeSpec {
+                tv_sec: remaining.secs,
+                tv_nsec: remaining.nanos,
+            },
+        }));
     }
+
+    let value = Duration::new(new.it_value.tv_sec, new.it_value.tv_nsec);
+    let interval = Duration::new(new.it_interval.tv_sec, new.it_interval.tv_nsec);
+    let zero = Duration::new(0, 0);
+
+    current.itimer = if value > zero {
+        let now = Duration::monotonic();
+        let deadline = now + value;
+        ::env().timers.lock().add(current.pid, deadline, now);
+        Some(ITimer {
+            deadline: deadline,
+            // Expiries are only checked on each tick, so a shorter period is rounded up to one
+            interval: if interval >= PIT_DURATION {
+                Some(interval)
+            } else if interval > zero {
+                Some(PIT_DURATION)
+            } else {
+                None
+            },
+        })
+    } else {
+        ::env().timers.lock().remove(current.pid);
+        None
+    };
+
+    Ok(0)
 }
@@ -0,0 +1,143 @@
+use alloc::boxed::Box;
+
+use arch::context::Context;
+use arch::usercopy;
+
+use collections::string::String;
+use collections::vec::Vec;
+
+use core::{mem, slice};
+
+use system::error::{Error, Result, EFAULT, EINVAL};
+
+/// Check that `len` bytes at `ptr` are mapped in the current context, and writeable if requested.
+///
+/// Kernel contexts, and the kernel before contexts are enabled, may pass kernel pointers. User
+/// contexts may only pass pointers that lie entirely in their own memory, which excludes the
+/// kernel range.
+pub fn validate_user_slice(ptr: usize, len: usize, writeable: bool) -> Result<()> {
+    if unsafe { ::ENV_PTR.is_none() } {
+        return Ok(());
+    }
+
+    // Validating a writeable range copies the memory shared after fork, which must not be done
+    // under a shared guard
+    if writeable {
+        let contexts = ::env().contexts.write();
+        validate_current(contexts.current(), ptr, len, writeable)
+    } else {
+        let contexts = ::env().contexts.read();
+        validate_current(contexts.current(), ptr, len, writeable)
+    }
+}
+
+/// Validate a range for the current context, if it is a user context
+fn validate_current(current: Result<&Box<Context>>, ptr: usize, len: usize, writeable: bool) -> Result<()> {
+    match current {
+        Ok(current) if current.stack.is_some() => current.validate(ptr, len, writeable),
+        _ => Ok(()),
+    }
+}
+
+/// Validate and convert a user buffer to a slice
+///
+/// Only for buffers of `read` and `write`, which user schemes map into the server instead of
+/// copying. Anything else is copied with `copy_from_user` or `user_read`.
+pub fn user_slice<'a>(ptr: *const u8, len: usize) -> Result<&'a [u8]> {
+    if len == 0 {
+        return Ok(&[]);
+    }
+    try!(validate_user_slice(ptr as usize, len, false));
+    Ok(unsafe { slice::from_raw_parts(ptr, len) })
+}
+
+/// Validate and convert a writeable user buffer to a mutable slice, see `user_slice`
+pub fn user_slice_mut<'a>(ptr: *mut u8, len: usize) -> Result<&'a mut [u8]> {
+    if len == 0 {
+        return Ok(&mut []);
+    }
+    try!(validate_user_slice(ptr as usize, len, true));
+    Ok(unsafe { slice::from_raw_parts_mut(ptr, len) })
+}
+
+/// Copy `dst.len()` bytes from the user buffer at `src`
+pub fn copy_from_user(dst: &mut [u8], src: *const u8) -> Result<()> {
+    try!(validate_user_slice(src as usize, dst.len(), false));
+    unsafe { usercopy::copy(dst.as_mut_ptr() as usize, src as usize, dst.len()) }
+}
+
+/// Copy `src` to the user buffer at `dst`
+pub fn copy_to_user(dst: *mut u8, src: &[u8]) -> Result<()> {
+    try!(validate_user_slice(dst as usize, src.len(), true));
+    unsafe { usercopy::copy(dst as usize, src.as_ptr() as usize, src.len()) }
+}
+
+/// Copy a user buffer into a new vector
+pub fn user_vec(ptr: *const u8, len: usize) -> Result<Vec<u8>> {
+    // Before allocating, so that a garbage length fails instead of exhausting memory
+    try!(validate_user_slice(ptr as usize, len, false));
+    let mut vec = vec![0; len];
+    try!(copy_from_user(&mut vec, ptr));
+    Ok(vec)
+}
+
+/// Copy a value from user memory
+pub fn user_read<T: Copy>(ptr: *const T) -> Result<T> {
+    let mut value: T = unsafe { mem::uninitialized() };
+    try!(copy_from_user(unsafe { slice::from_raw_parts_mut(&mut value as *mut T as *mut u8, mem::size_of::<T>()) },
+                        ptr as *const u8));
+    Ok(value)
+}
+
+/// Copy a value to user memory
+pub fn user_write<T>(ptr: *mut T, value: &T) -> Result<()> {
+    copy_to_user(ptr as *mut u8,
+                 unsafe { slice::from_raw_parts(value as *const T as *const u8, mem::size_of::<T>()) })
+}
+
+/// Copy a null-terminated user string
+///
+/// It is copied up to the end of a page at a time, as the page after the terminator may not be
+/// mapped. Strings that are not UTF-8 fail with `EINVAL`, as paths are sliced by character.
+pub fn user_str(ptr: *const u8) -> Result<String> {
+    if ptr as usize == 0 {
+        return Err(Error::new(EFAULT));
+    }
+
+    let mut bytes = Vec::new();
+    let mut address = ptr as usize;
+    let mut chunk = [0; 4096];
+    loop {
+        let len = 4096 - address % 4096;
+        try!(copy_from_user(&mut chunk[..len], address as *const u8));
+        match chunk[..len].iter().position(|&byte| byte == 0) {
+            Some(end) => {
+                bytes.extend_from_slice(&chunk[..end]);
+                break;
+            },
+            None => bytes.extend_from_slice(&chunk[..len]),
+        }
+        address += len;
+    }
+
+    String::from_utf8(bytes).or(Err(Error::new(EINVAL)))
+}
+
+/// Copy a null-terminated array of null-terminated user strings
+pub fn user_str_array(ptr: *const *const u8) -> Result<Vec<String>> {
+    let mut vec = Vec::new();
+
+    if ptr as usize > 0 {
+        let mut i = 0;
+        loop {
+            let arg = try!(user_read(unsafe { ptr.offset(i) }));
+            if arg as usize == 0 {
+                break;
+            }
+            vec.push(try!(user_str(arg)));
+            i += 1;
+        }
+    }
+
+    Ok(vec)
+}
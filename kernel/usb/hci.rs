@@ -1,8 +1,6 @@
 use arch::context::Context;
 use arch::memory;
 
-use collections::string::ToString;
-
 use common::event::MouseEvent;
 use common::time;
 
@@ -130,7 +128,7 @@ pub trait Hci {
 
                         if hid {
                             let this = self as *mut Hci;
-                            Context::spawn("kuhci_hid".to_string(), box move || {
+                            Context::kspawn("kuhci_hid", move || {
                                 if let Some(mode_info) = VBEMODEINFO {
                                     debugln!("Starting HID driver");
 
@@ -159,7 +157,7 @@ pub trait Hci {
                                                 right_button: buttons & 2 == 2,
                                             };
 
-                                            if ::env().console.lock().draw {
+                                            if ::env().vts.lock().active().draw {
                                                 //ignore mouse event
                                             } else {
                                                 ::env().events.send(mouse_event.to_event());
@@ -1,6 +1,19 @@
+use drivers::pci::common::class::*;
+use drivers::pci::common::programming_interface::*;
+use drivers::pci::common::subclass::*;
+use drivers::pci::config::PciConfig;
+use drivers::registry::{self, PciId, Subsystem};
+
+use env::Environment;
+
 pub use self::hci::Hci;
 pub use self::setup::Setup;
 
+use self::ehci::Ehci;
+use self::ohci::Ohci;
+use self::uhci::Uhci;
+use self::xhci::Xhci;
+
 pub mod desc;
 pub mod ehci;
 pub mod hci;
@@ -23,3 +36,23 @@ pub enum Pipe {
     Isochronous,
     Bulk
 }
+
+/// The USB host controllers, enabled by the `usb` feature
+pub const SUBSYSTEM: Subsystem = Subsystem {
+    name: "usb",
+    init: registry::no_init,
+    pci_device: pci_device,
+};
+
+/// Start a driver for a USB host controller
+unsafe fn pci_device(env: &mut Environment, mut pci: PciConfig, id: &PciId) -> bool {
+    let irq = pci.read(0x3C) as u8 & 0xF;
+    match (id.class, id.subclass, id.interface) {
+        (SERIAL_BUS, USB, UHCI) => env.add_driver(Uhci::new(pci), &[irq]),
+        (SERIAL_BUS, USB, OHCI) => env.add_driver(Ohci::new(pci), &[irq]),
+        (SERIAL_BUS, USB, EHCI) => env.add_driver(Ehci::new(pci), &[irq]),
+        (SERIAL_BUS, USB, XHCI) => env.add_driver(Xhci::new(pci), &[irq]),
+        _ => return false,
+    }
+    true
+}
@@ -16,6 +16,7 @@ use system::syscall::sys_chdir;
 use io::{Error, Result};
 
 static mut _args: *mut Vec<&'static str> = 0 as *mut Vec<&'static str>;
+static mut _vars: *mut Vec<&'static str> = 0 as *mut Vec<&'static str>;
 
 pub struct Args {
     i: usize
@@ -65,6 +66,11 @@ pub unsafe fn args_destroy() {
     }
 }
 
+/// Initialize the environment, as `KEY=VALUE` strings
+pub unsafe fn vars_init(vars: Vec<&'static str>) {
+    _vars = Box::into_raw(box vars);
+}
+
 /// Private function to get the path from a custom location
 /// If the custom directory cannot be found, None will be returned
 fn get_path_from(location : &str) -> Result<PathBuf> {
@@ -122,7 +128,15 @@ pub fn set_current_dir<P: AsRef<Path>>(path: P) -> Result<()> {
     }
 }
 
-// TODO: Fully implement `env::var()`
-pub fn var(_key: &str) -> Result<String> {
-    Ok("This is code filler".to_owned())
+/// The value of an environment variable, as passed to the program by exec
+pub fn var(key: &str) -> Result<String> {
+    if unsafe { _vars as usize } > 0 {
+        for var in unsafe { (*_vars).iter() } {
+            let mut parts = var.splitn(2, '=');
+            if parts.next() == Some(key) {
+                return Ok(parts.next().unwrap_or("").to_owned());
+            }
+        }
+    }
+    Err(Error::new_sys(ENOENT))
 }
@@ -1,6 +1,6 @@
 use core::ops::Deref;
 use core_collections::borrow::ToOwned;
-use io::{Read, Error, Result, Write, Seek, SeekFrom};
+use io::{Read, Error, ErrorKind, Result, Write, Seek, SeekFrom};
 use os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
 use mem;
 use path::{PathBuf, Path};
@@ -9,9 +9,10 @@ use string::String;
 use sys_common::AsInner;
 use vec::Vec;
 
-use system::syscall::{sys_open, sys_dup, sys_close, sys_fpath, sys_ftruncate, sys_read,
-              sys_write, sys_lseek, sys_fsync, sys_mkdir, sys_rmdir, sys_stat, sys_unlink};
-use system::syscall::{O_RDWR, O_RDONLY, O_WRONLY, O_APPEND, O_CREAT, O_TRUNC, MODE_DIR, MODE_FILE, SEEK_SET, SEEK_CUR, SEEK_END, Stat};
+use system::syscall::{sys_open, sys_dup, sys_close, sys_fpath, sys_ftruncate, sys_getdents, sys_read,
+              sys_write, sys_lseek, sys_fsync, sys_mkdir, sys_rmdir, sys_stat, sys_unlink, sys_link,
+              sys_symlink, sys_readlink};
+use system::syscall::{O_RDWR, O_RDONLY, O_WRONLY, O_APPEND, O_CREAT, O_TRUNC, MODE_DIR, MODE_FILE, MODE_TYPE, SEEK_SET, SEEK_CUR, SEEK_END, Dirent, Stat};
 
 /// A Unix-style file
 pub struct File {
@@ -267,11 +268,29 @@ impl DirEntry {
 
 pub struct ReadDir {
     file: File,
+    /// Cleared if the directory does not support `getdents`, it is then read as a list of names
+    dirents: bool,
 }
 
 impl Iterator for ReadDir {
     type Item = Result<DirEntry>;
     fn next(&mut self) -> Option<Result<DirEntry>> {
+        if self.dirents {
+            let mut dirents = [Dirent::default(); 1];
+            match sys_getdents(self.file.fd, &mut dirents) {
+                Ok(0) => return None,
+                Ok(_) => {
+                    let dir = dirents[0].d_mode & MODE_TYPE == MODE_DIR;
+                    return Some(Ok(DirEntry {
+                        path: String::from_utf8_lossy(dirents[0].name()).into_owned(),
+                        dir: dir,
+                        file: !dir,
+                    }));
+                },
+                Err(_) => self.dirents = false,
+            }
+        }
+
         let mut path = String::new();
         let mut buf: [u8; 1] = [0; 1];
         loop {
@@ -319,7 +338,10 @@ pub fn canonicalize<P: AsRef<Path>>(path: P) -> Result<PathBuf> {
 pub fn metadata<P: AsRef<Path>>(path: P) -> Result<Metadata> {
     let mut stat = Stat {
         st_mode: 0,
-        st_size: 0
+        st_size: 0,
+        st_uid: 0,
+        st_gid: 0,
+        st_nlink: 0
     };
     let path_str = path.as_ref().as_os_str().as_inner();
     let mut path_c = path_str.to_owned();
@@ -354,11 +376,48 @@ pub fn read_dir<P: AsRef<Path>>(path: P) -> Result<ReadDir> {
     };
 
     match file_result {
-        Ok(file) => Ok(ReadDir { file: file }),
+        Ok(file) => Ok(ReadDir {
+            file: file,
+            dirents: true,
+        }),
         Err(err) => Err(err),
     }
 }
 
+/// Create `dst` as another name of the file `src`
+pub fn hard_link<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q) -> Result<()> {
+    let mut src_c = src.as_ref().as_os_str().as_inner().to_owned();
+    src_c.push_str("\0");
+    let mut dst_c = dst.as_ref().as_os_str().as_inner().to_owned();
+    dst_c.push_str("\0");
+    unsafe {
+        sys_link(src_c.as_ptr(), dst_c.as_ptr()).and(Ok(())).map_err(|x| Error::from_sys(x))
+    }
+}
+
+/// Create a symbolic link at `dst` to `src`
+pub fn soft_link<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q) -> Result<()> {
+    let mut src_c = src.as_ref().as_os_str().as_inner().to_owned();
+    src_c.push_str("\0");
+    let mut dst_c = dst.as_ref().as_os_str().as_inner().to_owned();
+    dst_c.push_str("\0");
+    unsafe {
+        sys_symlink(src_c.as_ptr(), dst_c.as_ptr()).and(Ok(())).map_err(|x| Error::from_sys(x))
+    }
+}
+
+/// Read the target of a symbolic link
+pub fn read_link<P: AsRef<Path>>(path: P) -> Result<PathBuf> {
+    let mut path_c = path.as_ref().as_os_str().as_inner().to_owned();
+    path_c.push_str("\0");
+    let mut buf = [0; 4096];
+    let count = try!(unsafe { sys_readlink(path_c.as_ptr(), &mut buf) }.map_err(|x| Error::from_sys(x)));
+    match str::from_utf8(&buf[..count]) {
+        Ok(target) => Ok(PathBuf::from(target.to_owned())),
+        Err(_) => Err(Error::new(ErrorKind::InvalidData, "link target is not valid UTF-8")),
+    }
+}
+
 pub fn remove_dir(path: &str) -> Result<()> {
     let path_c = path.to_owned() + "\0";
     unsafe {
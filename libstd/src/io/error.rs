@@ -16,7 +16,9 @@ use marker::{Send, Sync};
 use option::Option::{self, None};
 use result;
 
-use system::error::Error as SysError;
+use system::error::{strerror, Error as SysError};
+use system::error::{EACCES, EADDRINUSE, EADDRNOTAVAIL, EAGAIN, ECONNABORTED, ECONNREFUSED, ECONNRESET, EEXIST, EINTR,
+                    EINVAL, ENOENT, ENOTCONN, EPERM, EPIPE, ETIMEDOUT};
 
 /// A specialized [`Result`](../result/enum.Result.html) type for I/O
 /// operations.
@@ -97,11 +99,33 @@ impl Error {
     /// Get the error kind of this IO error.
     pub fn kind(&self) -> ErrorKind {
         match &self.repr {
-            &Repr::Os(_) => ErrorKind::Other,
+            &Repr::Os(code) => decode_error_kind(code),
             &Repr::Custom(ref c) => c.kind,
         }
     }
 }
+
+/// The error kind of an error number
+fn decode_error_kind(errno: isize) -> ErrorKind {
+    match errno {
+        ENOENT => ErrorKind::NotFound,
+        EACCES | EPERM => ErrorKind::PermissionDenied,
+        ECONNREFUSED => ErrorKind::ConnectionRefused,
+        ECONNRESET => ErrorKind::ConnectionReset,
+        ECONNABORTED => ErrorKind::ConnectionAborted,
+        ENOTCONN => ErrorKind::NotConnected,
+        EADDRINUSE => ErrorKind::AddrInUse,
+        EADDRNOTAVAIL => ErrorKind::AddrNotAvailable,
+        EPIPE => ErrorKind::BrokenPipe,
+        EEXIST => ErrorKind::AlreadyExists,
+        EAGAIN => ErrorKind::WouldBlock,
+        EINVAL => ErrorKind::InvalidInput,
+        ETIMEDOUT => ErrorKind::TimedOut,
+        EINTR => ErrorKind::Interrupted,
+        _ => ErrorKind::Other,
+    }
+}
+
 #[derive(Debug)]
 enum Repr {
     Os(isize),
@@ -194,8 +218,7 @@ impl fmt::Display for Error {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         match self.repr {
             Repr::Os(code) => {
-                let detail = self.description();
-                write!(fmt, "{} (os error {})", detail, code)
+                write!(fmt, "{} (os error {})", strerror(code), code)
             }
             Repr::Custom(ref c) => c.error.fmt(fmt),
         }
@@ -203,31 +226,9 @@ impl fmt::Display for Error {
 }
 
 impl error::Error for Error {
-    #[allow(deprecated)] // remove with UnexpectedEOF
     fn description(&self) -> &str {
         match self.repr {
-            Repr::Os(..) => match self.kind() {
-                ErrorKind::NotFound => "entity not found",
-                ErrorKind::PermissionDenied => "permission denied",
-                ErrorKind::ConnectionRefused => "connection refused",
-                ErrorKind::ConnectionReset => "connection reset",
-                ErrorKind::ConnectionAborted => "connection aborted",
-                ErrorKind::NotConnected => "not connected",
-                ErrorKind::AddrInUse => "address in use",
-                ErrorKind::AddrNotAvailable => "address not available",
-                ErrorKind::BrokenPipe => "broken pipe",
-                ErrorKind::AlreadyExists => "entity already exists",
-                ErrorKind::WouldBlock => "operation would block",
-                ErrorKind::InvalidInput => "invalid input parameter",
-                ErrorKind::InvalidData => "invalid data",
-                ErrorKind::TimedOut => "timed out",
-                ErrorKind::WriteZero => "write zero",
-                ErrorKind::Interrupted => "operation interrupted",
-                ErrorKind::Other => "other os error",
-                ErrorKind::UnexpectedEOF => "unexpected end of file",
-                ErrorKind::UnexpectedEof => "unexpected end of file",
-                ErrorKind::__Nonexhaustive => unreachable!()
-            },
+            Repr::Os(code) => strerror(code),
             Repr::Custom(ref c) => c.error.description(),
         }
     }
@@ -212,10 +212,10 @@ impl Command {
                 _ => ()
             }
 
-            unsafe { sys_execve(path_c.as_ptr(), args_c.as_ptr()) }.map_err(|x| Error::from_sys(x))
+            unsafe { sys_execve(path_c.as_ptr(), args_c.as_ptr(), 0 as *const *const u8) }.map_err(|x| Error::from_sys(x))
         });
 
-        match unsafe { sys_clone(CLONE_VM | CLONE_VFORK) } {
+        match unsafe { sys_clone(CLONE_VM | CLONE_VFORK, 0, 0) } {
             Ok(0) => {
                 let error = child_code();
 
@@ -1,6 +1,6 @@
 use core::{fmt, mem, ptr, slice, str};
 use panic::panic_impl;
-use env::{args_init, args_destroy};
+use env::{args_init, args_destroy, vars_init};
 use system::syscall::sys_exit;
 use vec::Vec;
 
@@ -14,6 +14,19 @@ pub fn begin_unwind_fmt(fmt: fmt::Arguments, file_line: &(&'static str, u32)) ->
     panic_impl(fmt, file, line)
 }
 
+/// Read a NUL terminated string passed by the kernel, of at most 4096 bytes
+unsafe fn c_str(ptr: *const u8) -> &'static str {
+    let mut len = 0;
+    for j in 0..4096 {
+        len = j;
+        if ptr::read(ptr.offset(j)) == 0 {
+            break;
+        }
+    }
+    let utf8: &'static [u8] = slice::from_raw_parts(ptr, len as usize);
+    str::from_utf8_unchecked(utf8)
+}
+
 #[no_mangle]
 #[inline(never)]
 pub unsafe extern "C" fn _start_stack(stack: *const usize) {
@@ -21,7 +34,22 @@ pub unsafe extern "C" fn _start_stack(stack: *const usize) {
         fn main(argc: usize, argv: *const *const u8) -> usize;
     }
 
-    sys_exit(main(*stack, stack.offset(1) as *const *const u8)).unwrap();
+    // The environment follows the arguments and their terminating null pointer
+    let argc = *stack;
+    let envp = stack.offset(argc as isize + 2) as *const *const u8;
+    let mut vars: Vec<&'static str> = Vec::new();
+    let mut i = 0;
+    loop {
+        let var = ptr::read(envp.offset(i));
+        if var as usize == 0 {
+            break;
+        }
+        vars.push(c_str(var));
+        i += 1;
+    }
+    vars_init(vars);
+
+    sys_exit(main(argc, stack.offset(1) as *const *const u8)).unwrap();
 }
 
 #[lang = "start"]
@@ -31,15 +59,7 @@ fn lang_start(main: *const u8, argc: usize, argv: *const *const u8) -> usize {
         for i in 0..argc as isize {
             let arg = ptr::read(argv.offset(i));
             if arg as usize > 0 {
-                let mut len = 0;
-                for j in 0..4096 {
-                    len = j;
-                    if ptr::read(arg.offset(j)) == 0 {
-                        break;
-                    }
-                }
-                let utf8: &'static [u8] = slice::from_raw_parts(arg, len as usize);
-                args.push(str::from_utf8_unchecked(utf8));
+                args.push(c_str(arg));
             }
         }
 
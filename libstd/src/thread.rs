@@ -3,7 +3,7 @@ use alloc::boxed::Box;
 use core::mem;
 
 use system::syscall::{sys_clone, sys_exit, sys_yield, sys_nanosleep, sys_waitpid, CLONE_VM, CLONE_FS, CLONE_FILES,
-              TimeSpec};
+              CLONE_THREAD, TimeSpec};
 
 use time::Duration;
 
@@ -77,7 +77,7 @@ pub fn spawn<F, T>(f: F) -> JoinHandle<T>
     //This must only be used by the child
     let boxed_f = Box::new(f);
 
-    match unsafe { sys_clone(CLONE_VM | CLONE_FS | CLONE_FILES).unwrap() } {
+    match unsafe { sys_clone(CLONE_VM | CLONE_FS | CLONE_FILES | CLONE_THREAD, 0, 0).unwrap() } {
         0 => {
             unsafe { *result_ptr = Some(boxed_f()) };
             loop {